@@ -0,0 +1,134 @@
+use std::fmt;
+use std::net::IpAddr;
+
+// An address prefix such as 192.0.2.0/24 or 2001:db8::/32. IPv4 prefixes
+// don't match IPv6 addresses; callers canonicalise IPv4-mapped ones first.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    // `addr/prefix`, or a bare address for a single host.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("bad address {:?}", addr))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("bad prefix length {:?}", prefix))?,
+            None => max,
+        };
+        Ok(Network { addr, prefix })
+    }
+
+    pub(crate) fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, bits) = match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = bits - self.prefix as u32;
+        network >> shift == ip >> shift
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+// A list of networks; `any` and `none` stand for everything and nothing.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Acl(Vec<Network>);
+
+impl Acl {
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "any" => Acl::parse("0.0.0.0/0,::/0"),
+            "none" => Ok(Acl(Vec::new())),
+            _ => text
+                .split(',')
+                .map(Network::parse)
+                .collect::<Result<_, _>>()
+                .map(Acl),
+        }
+    }
+
+    // Loopback and private address space.
+    pub(crate) fn local() -> Self {
+        Acl::parse("127.0.0.0/8,10.0.0.0/8,172.16.0.0/12,192.168.0.0/16,::1,fc00::/7,fe80::/10")
+            .unwrap()
+    }
+
+    pub(crate) fn allows(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_network() {
+        let network = Network::parse("192.168.0.0/16").unwrap();
+        assert!(network.contains(ip("192.168.77.1")));
+        assert!(!network.contains(ip("192.169.0.1")));
+        assert!(!network.contains(ip("::ffff:192.168.0.1")));
+
+        let network = Network::parse("2001:db8::/32").unwrap();
+        assert!(network.contains(ip("2001:db8:1::53")));
+        assert!(!network.contains(ip("2001:db9::53")));
+
+        let host = Network::parse("192.0.2.1").unwrap();
+        assert_eq!(host.to_string(), "192.0.2.1/32");
+        assert!(host.contains(ip("192.0.2.1")));
+        assert!(!host.contains(ip("192.0.2.2")));
+
+        for bad in [
+            "192.0.2.0/33",
+            "2001:db8::/129",
+            "example.com",
+            "10.0.0.0/x",
+        ] {
+            assert!(Network::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_acl() {
+        let local = Acl::local();
+        assert!(local.allows(ip("127.0.0.1")));
+        assert!(local.allows(ip("172.20.1.1")));
+        assert!(local.allows(ip("::1")));
+        assert!(!local.allows(ip("8.8.8.8")));
+        assert!(!local.allows(ip("2001:db8::1")));
+
+        assert!(Acl::parse("any").unwrap().allows(ip("2001:db8::1")));
+        assert!(!Acl::parse("none").unwrap().allows(ip("127.0.0.1")));
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const LABEL: &str = "_acme-challenge";
+// Short, so a retried validation isn't answered from a stale cache.
+const TTL: i32 = 60;
+// Hooks that never clean up still don't leave records behind for long.
+const LIFETIME: Duration = Duration::from_secs(3600);
+const MAX_CHALLENGES: usize = 1000;
+
+// DNS-01 challenge records (RFC 8555 section 8.4) that ACME clients put in
+// through the admin API, served alongside the zone they fall in.
+#[derive(Default)]
+pub(crate) struct Challenges {
+    records: Mutex<Vec<Challenge>>,
+}
+
+struct Challenge {
+    name: Name,
+    value: String,
+    expires: Instant,
+}
+
+impl Challenges {
+    pub(crate) fn present(&self, name: &Name, value: &str) -> Result<(), String> {
+        let first = name.as_str().split('.').next().unwrap_or_default();
+        if !first.eq_ignore_ascii_case(LABEL) {
+            return Err(format!("{} is not an {} name", name, LABEL));
+        }
+        if value.is_empty() || value.len() > 255 {
+            return Err("the token must be 1 to 255 characters".to_string());
+        }
+        let mut records = self.records.lock().unwrap();
+        let now = Instant::now();
+        records.retain(|challenge| challenge.expires > now);
+        let (expires, full) = (now + LIFETIME, records.len() >= MAX_CHALLENGES);
+        let existing = records
+            .iter_mut()
+            .find(|challenge| challenge.name.eq_ignore_case(name) && challenge.value == value);
+        match existing {
+            Some(challenge) => challenge.expires = expires,
+            None if full => return Err("too many challenges".into()),
+            None => records.push(Challenge {
+                name: name.clone(),
+                value: value.to_string(),
+                expires,
+            }),
+        }
+        Ok(())
+    }
+
+    // Whether there was such a challenge to remove.
+    pub(crate) fn cleanup(&self, name: &Name, value: &str) -> bool {
+        let mut records = self.records.lock().unwrap();
+        let before = records.len();
+        records
+            .retain(|challenge| !(challenge.name.eq_ignore_case(name) && challenge.value == value));
+        records.len() < before
+    }
+
+    // Adds the challenges at the question's name to a response the zone
+    // already filled in: the name exists while it has any.
+    pub(crate) fn answer(&self, question: &DnsQuestion, response: &mut DnsPacket) {
+        let now = Instant::now();
+        let records = self.records.lock().unwrap();
+        let mut at_name = records
+            .iter()
+            .filter(|challenge| challenge.expires > now)
+            .filter(|challenge| challenge.name.eq_ignore_case(&question.qname))
+            .peekable();
+        if at_name.peek().is_none() {
+            return;
+        }
+        if response.header.rcode == ResponseCode::NxDomain {
+            response.header.rcode = ResponseCode::NoError;
+        }
+        if !matches!(question.qtype, DnsType::Txt | DnsType::Any) {
+            return;
+        }
+        response.answers.extend(at_name.map(|challenge| {
+            DnsAnswer::new(
+                question.qname.clone(),
+                DnsType::Txt,
+                DnsClass::In,
+                TTL,
+                RData::Txt(vec![challenge.value.as_bytes().to_vec()]),
+            )
+        }));
+        // The SOA of a negative answer no longer applies.
+        response.authorities.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ask(challenges: &Challenges, qtype: DnsType) -> DnsPacket {
+        let question = DnsQuestion {
+            qname: "_ACME-challenge.www.example.com".into(),
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        response.header.rcode = ResponseCode::NxDomain;
+        challenges.answer(&question, &mut response);
+        response
+    }
+
+    #[test]
+    fn test_challenges() {
+        let challenges = Challenges::default();
+        let name = Name::from("_acme-challenge.www.example.com");
+        assert!(challenges
+            .present(&"www.example.com".into(), "token")
+            .is_err());
+        assert!(challenges.present(&name, "").is_err());
+        challenges.present(&name, "first").unwrap();
+        challenges.present(&name, "second").unwrap();
+        challenges.present(&name, "second").unwrap();
+
+        let response = ask(&challenges, DnsType::Txt);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        let values: Vec<&RData> = response.answers.iter().map(|r| &r.rdata).collect();
+        assert_eq!(
+            values,
+            vec![
+                &RData::Txt(vec![b"first".to_vec()]),
+                &RData::Txt(vec![b"second".to_vec()])
+            ]
+        );
+        assert_eq!(response.answers[0].ttl, 60);
+        let response = ask(&challenges, DnsType::A);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+
+        assert!(challenges.cleanup(&name, "first"));
+        assert!(!challenges.cleanup(&name, "first"));
+        assert!(challenges.cleanup(&name, "second"));
+        let response = ask(&challenges, DnsType::Txt);
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+    }
+}
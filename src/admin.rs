@@ -0,0 +1,639 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::Config;
+use crate::crypto;
+use crate::forward;
+use crate::handler::State;
+use crate::json::Json;
+use crate::question::DnsQuestion;
+use crate::recursor::Recursor;
+use crate::stats::Stats;
+use crate::trace;
+use crate::zone::resolve_name;
+
+// For the whole request, however slowly it arrives.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY: usize = 4096;
+// The request line and each header line.
+const MAX_LINE: u64 = 8192;
+// Requests served at once; more are closed unanswered, so probes still get
+// a thread while slow clients or traces hold the others.
+const MAX_CONNECTIONS: usize = 32;
+// Per upstream group, so a dead upstream still answers well within the
+// usual 5-10s probe timeout of orchestrators.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+// Polls /api/stats and draws it; no external assets, so it works offline.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+// What the admin endpoints report on, updated by the server as it runs.
+#[derive(Default)]
+pub(crate) struct Health {
+    // Sockets bound, and how many of their threads have since stopped.
+    listeners: AtomicUsize,
+    stopped: AtomicUsize,
+    // Why the last zone reload failed, until one succeeds.
+    zone_error: Mutex<Option<String>>,
+}
+
+impl Health {
+    pub(crate) fn listening(&self, count: usize) {
+        self.listeners.store(count, Ordering::SeqCst);
+    }
+
+    pub(crate) fn listener_stopped(&self) {
+        self.stopped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn zones_loaded(&self, error: Option<String>) {
+        *self.zone_error.lock().unwrap() = error;
+    }
+}
+
+// Reads from the stream until the deadline, however the time is spread
+// over the reads.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+// A line of at most MAX_LINE bytes.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+// One line of a health report.
+type Check = (String, Result<String, String>);
+// An HTTP status line and plain text body.
+type Response = (&'static str, String);
+
+// `--admin-listen addr`: plain HTTP for orchestrators.
+//   GET /healthz   200 while every listener is serving, else 503
+//   GET /readyz    200 when also the zones loaded, the cache is warm and
+//                  every upstream group answers a probe, else 503
+// The body lists each check, e.g. `upstream wan: unreachable (timed out)`.
+// With `--acme-token`, DNS-01 hooks for certbot or lego can also
+//   POST /acme/present   {"fqdn": "_acme-challenge.www.example.com.",
+//                         "token": "<digest>"} to serve a TXT record
+//   POST /acme/cleanup   the same, to take it out again
+// sending `Authorization: Bearer <token>`. For people there is
+//   GET /            a dashboard of the figures below, refreshed live
+//   GET /api/stats   queries, cache hits, blocks, top names and clients and
+//                    the latest queries, as JSON
+//   GET /api/trace?name=<name>[&type=<type>]
+//                    every query of a resolution from the roots, as JSON,
+//                    like `dns-server trace`
+pub(crate) struct Admin {
+    config: Arc<Config>,
+    state: Arc<State>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Admin {
+    pub(crate) fn new(config: Arc<Config>, state: Arc<State>, stats: Arc<Mutex<Stats>>) -> Self {
+        Admin {
+            config,
+            state,
+            stats,
+        }
+    }
+
+    // Binds the address and serves it on its own thread.
+    pub(crate) fn serve(self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = self.state.sockets.tcp("admin", addr)?;
+        let bound = listener.local_addr()?;
+        let admin = Arc::new(self);
+        let serving = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(_) if serving.load(Ordering::SeqCst) >= MAX_CONNECTIONS => {
+                        eprintln!("Admin connection dropped: too many at once");
+                    }
+                    Ok(stream) => {
+                        serving.fetch_add(1, Ordering::SeqCst);
+                        let (admin, serving) = (Arc::clone(&admin), Arc::clone(&serving));
+                        thread::spawn(move || {
+                            if let Err(e) = admin.handle(stream) {
+                                eprintln!("Admin connection failed: {}", e);
+                            }
+                            serving.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                    Err(e) => eprintln!("Admin listener error: {}", e),
+                }
+            }
+        });
+        Ok(bound)
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(Deadline {
+            stream: &stream,
+            deadline: Instant::now() + REQUEST_TIMEOUT,
+        });
+        let mut request = String::new();
+        read_line(&mut reader, &mut request)?;
+        let (mut length, mut authorization) = (0, None);
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => length = value.trim().parse().unwrap_or(0),
+                    "authorization" => authorization = Some(value.trim().to_string()),
+                    _ => {}
+                }
+            }
+        }
+        let mut request_body = vec![0; length.min(MAX_BODY)];
+        reader.read_exact(&mut request_body)?;
+
+        let authorization = authorization.as_deref();
+        let request: Vec<&str> = request.split(' ').collect();
+        let (method, target) = match request[..] {
+            [method, target, _] => (method, target),
+            _ => ("", ""),
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (status, body) = match (method, path) {
+            ("GET", "/") => ("200 OK", DASHBOARD.to_string()),
+            ("GET", "/api/stats") => ("200 OK", self.stats().to_string()),
+            ("GET", "/api/trace") => self.trace(query),
+            ("GET", "/healthz") => report(self.liveness()),
+            ("GET", "/readyz") => report(self.readiness()),
+            ("POST", "/acme/present") => self.acme(authorization, &request_body, true),
+            ("POST", "/acme/cleanup") => self.acme(authorization, &request_body, false),
+            ("GET" | "POST", _) => not_found(),
+            _ => ("405 Method Not Allowed", "only GET and POST\n".to_string()),
+        };
+        let content_type = match path {
+            "/" => "text/html; charset=utf-8",
+            "/api/stats" | "/api/trace" => "application/json",
+            _ => "text/plain",
+        };
+        write!(
+            &stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    // `?name=example.com&type=AAAA`: resolves from the roots as the
+    // recursor does, whether or not recursion is on, and returns every
+    // query made on the way.
+    fn trace(&self, query: &str) -> Response {
+        let (mut name, mut qtype) = (None, DnsType::A);
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("name", value)) if !value.is_empty() => name = Some(Name::from(value)),
+                Some(("type", value)) => match value.parse() {
+                    Ok(parsed) => qtype = parsed,
+                    Err(_) => return ("400 Bad Request", format!("unknown type {:?}\n", value)),
+                },
+                _ => {}
+            }
+        }
+        let Some(qname) = name else {
+            return ("400 Bad Request", "name required\n".to_string());
+        };
+        let recursor = Recursor::new(
+            self.config.recursion_limits,
+            self.config.max_udp_size,
+            &self.state.roots.get(),
+            Arc::clone(&self.state.reputation),
+        );
+        let question = DnsQuestion {
+            qname,
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let deadline = Instant::now() + trace::TRACE_BUDGET;
+        let trace = trace::trace_json(recursor, &question, deadline);
+        ("200 OK", trace.to_string())
+    }
+
+    fn stats(&self) -> Json {
+        let (snapshot, recent) = {
+            let stats = self.stats.lock().unwrap();
+            (stats.snapshot(), stats.recent())
+        };
+        let (hits, misses) = self.state.cache.lookups();
+        let number = |n: u64| Json::Number(n as f64);
+        let pairs = |pairs: Vec<(String, u64)>| {
+            let pairs = pairs
+                .into_iter()
+                .map(|(key, n)| Json::Array(vec![key.as_str().into(), number(n)]));
+            Json::Array(pairs.collect())
+        };
+        let clients = snapshot
+            .top_clients
+            .iter()
+            .map(|(client, n)| (client.to_string(), *n))
+            .collect();
+        let recent = recent.iter().rev().map(|query| {
+            let time = query.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            Json::Object(vec![
+                (
+                    "time".into(),
+                    Json::Number(time.as_millis() as f64 / 1000.0),
+                ),
+                ("client".into(), query.client.to_string().as_str().into()),
+                ("name".into(), query.name.as_str().into()),
+                ("type".into(), query.qtype.as_str().into()),
+                ("rcode".into(), query.rcode.to_string().as_str().into()),
+            ])
+        });
+        Json::Object(vec![
+            ("uptime".into(), number(snapshot.uptime.as_secs())),
+            ("queries".into(), number(snapshot.queries)),
+            ("nxdomain".into(), number(snapshot.nxdomain)),
+            ("cache_hits".into(), number(hits)),
+            ("cache_misses".into(), number(misses)),
+            (
+                "cache_entries".into(),
+                number(self.state.cache.len() as u64),
+            ),
+            ("blocked".into(), pairs(self.state.blocked.counts())),
+            ("top_names".into(), pairs(snapshot.top_names)),
+            ("top_clients".into(), pairs(clients)),
+            ("recent".into(), Json::Array(recent.collect())),
+        ])
+    }
+
+    // Puts in or takes out a challenge given as `{"fqdn": .., "token": ..}`,
+    // which must fall in a loaded zone.
+    fn acme(&self, authorization: Option<&str>, body: &[u8], present: bool) -> Response {
+        let Some(expected) = &self.config.acme_token else {
+            return not_found();
+        };
+        let bearer = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        // Digests compare in the same time however much of the token matches.
+        if bearer.map(|token| crypto::sha256(token.as_bytes()))
+            != Some(crypto::sha256(expected.as_bytes()))
+        {
+            return (
+                "401 Unauthorized",
+                "bad or missing bearer token\n".to_string(),
+            );
+        }
+        let bad_request = |message: String| ("400 Bad Request", format!("{}\n", message));
+        let request = match std::str::from_utf8(body)
+            .map_err(|e| e.to_string())
+            .and_then(Json::parse)
+        {
+            Ok(request) => request,
+            Err(e) => return bad_request(format!("bad JSON: {}", e)),
+        };
+        let field = |key| request.get(key).and_then(Json::as_str);
+        let (Some(fqdn), Some(token)) = (field("fqdn"), field("token")) else {
+            return bad_request("fqdn and token are required".to_string());
+        };
+        let name = match resolve_name(fqdn, Some(&Name::from(""))) {
+            Ok(name) => name,
+            Err(e) => return bad_request(e),
+        };
+        if self.state.zones().find(&name).is_none() {
+            return bad_request(format!("{} is not in a loaded zone", name));
+        }
+        let challenges = &self.state.challenges;
+        match present {
+            true => match challenges.present(&name, token) {
+                Ok(()) => ("200 OK", "ok\n".to_string()),
+                Err(e) => bad_request(e),
+            },
+            false if challenges.cleanup(&name, token) => ("200 OK", "ok\n".to_string()),
+            false => ("404 Not Found", "no such challenge\n".to_string()),
+        }
+    }
+
+    fn liveness(&self) -> Vec<Check> {
+        let health = &self.state.health;
+        let listeners = health.listeners.load(Ordering::SeqCst);
+        let stopped = health.stopped.load(Ordering::SeqCst);
+        let status = match (listeners, stopped) {
+            (0, _) => Err("not bound yet".to_string()),
+            (_, 0) => Ok(format!("{} serving", listeners)),
+            _ => Err(format!("{} of {} stopped", stopped, listeners)),
+        };
+        vec![("listeners".to_string(), status)]
+    }
+
+    fn readiness(&self) -> Vec<Check> {
+        let mut checks = self.liveness();
+        let zones = match &*self.state.health.zone_error.lock().unwrap() {
+            Some(error) => Err(format!("reload failed: {}", error)),
+            None => Ok(format!("{} loaded", self.state.zones().len())),
+        };
+        checks.push(("zones".to_string(), zones));
+        let warm = &self.state.warm;
+        if warm.warming() {
+            let done = warm.done.load(Ordering::SeqCst);
+            let total = warm.total.load(Ordering::SeqCst);
+            checks.push((
+                "cache".to_string(),
+                Err(format!("warming ({}/{})", done, total)),
+            ));
+        }
+
+        let probe = DnsQuestion {
+            qname: ".".into(),
+            qtype: DnsType::Ns,
+            qclass: DnsClass::In,
+        };
+        for group in &self.config.upstreams {
+            let deadline = Instant::now() + PROBE_TIMEOUT;
+            let udp_size = self.config.max_udp_size;
+            let state = &self.state;
+            let status = match forward::forward(&probe, group, udp_size, deadline, state) {
+                Ok(_) => Ok("ok".to_string()),
+                Err(e) => Err(format!("unreachable ({})", e)),
+            };
+            checks.push((format!("upstream {}", group.name), status));
+        }
+        checks
+    }
+}
+
+fn not_found() -> Response {
+    ("404 Not Found", "not found\n".to_string())
+}
+
+fn report(checks: Vec<Check>) -> Response {
+    let mut healthy = true;
+    let mut body = String::new();
+    for (name, status) in checks {
+        match status {
+            Ok(status) => body.push_str(&format!("{}: {}\n", name, status)),
+            Err(status) => {
+                healthy = false;
+                body.push_str(&format!("{}: {}\n", name, status));
+            }
+        }
+    }
+    match healthy {
+        true => ("200 OK", body),
+        false => ("503 Service Unavailable", body),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authority::Zones;
+    use crate::forward::UpstreamGroup;
+    use crate::header::ResponseCode;
+    use std::io::Read;
+    use std::net::UdpSocket;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_health_endpoints() {
+        // Bound but never answering, so the probe times out.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spec = format!("wan={}", silent.local_addr().unwrap());
+        let config = Config {
+            upstreams: vec![UpstreamGroup::parse(&spec).unwrap()],
+            ..Config::default()
+        };
+        let state = Arc::new(State::new(Zones::default()));
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let admin = Admin::new(Arc::new(config), Arc::clone(&state), stats);
+        let addr = admin.serve("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let response = get(addr, "/healthz");
+        assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+        assert!(response.ends_with("listeners: not bound yet\n"));
+
+        state.health.listening(2);
+        let response = get(addr, "/healthz");
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+
+        state.health.zones_loaded(Some("line 3: bad TTL".into()));
+        let response = get(addr, "/readyz");
+        assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+        assert!(response.contains("zones: reload failed: line 3: bad TTL\n"));
+        assert!(response.contains("upstream wan: unreachable"));
+
+        state.health.listener_stopped();
+        let response = get(addr, "/healthz");
+        assert!(response.ends_with("listeners: 1 of 2 stopped\n"));
+        assert!(get(addr, "/metrics").starts_with("HTTP/1.1 404 "));
+    }
+
+    #[test]
+    fn test_slow_clients() {
+        let state = Arc::new(State::new(Zones::default()));
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let config = Config {
+            acme_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let admin = Admin::new(Arc::new(config), Arc::clone(&state), stats);
+        let addr = admin.serve("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        // One client that never finishes its request doesn't hold up probes.
+        let mut idle = TcpStream::connect(addr).unwrap();
+        write!(idle, "GET /healthz HTTP/1.1\r\n").unwrap();
+        let start = Instant::now();
+        assert!(get(addr, "/healthz").starts_with("HTTP/1.1 "));
+        assert!(start.elapsed() < REQUEST_TIMEOUT);
+
+        // A header line without end is cut off rather than read forever.
+        let mut long = TcpStream::connect(addr).unwrap();
+        write!(long, "GET /healthz HTTP/1.1\r\nX-Long: ").unwrap();
+        let padding = vec![b'a'; MAX_LINE as usize];
+        let _ = long.write_all(&padding);
+        let mut response = String::new();
+        let _ = long.read_to_string(&mut response);
+        assert_eq!(response, "");
+
+        // A one-letter header line doesn't end the headers, so the token
+        // after it still counts.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST /acme/cleanup HTTP/1.1\nX\nAuthorization: Bearer s3cret\n\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+    }
+
+    fn post(addr: SocketAddr, path: &str, token: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            token,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_acme() {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-acme-{}.zone", std::process::id()));
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\n",
+        )
+        .unwrap();
+        let config = Config {
+            zones: vec![(None, path.clone())],
+            acme_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let state = Arc::new(State::new(Zones::load(&config).unwrap()));
+        std::fs::remove_file(&path).unwrap();
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let admin = Admin::new(Arc::new(config), Arc::clone(&state), stats);
+        let addr = admin.serve("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let challenge = r#"{"fqdn": "_acme-challenge.www.example.com.", "token": "abc"}"#;
+        let response = post(addr, "/acme/present", "wrong", challenge);
+        assert!(response.starts_with("HTTP/1.1 401 "), "{}", response);
+        let response = post(addr, "/acme/present", "s3cret", challenge);
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+
+        let question = DnsQuestion {
+            qname: "_acme-challenge.www.example.com".into(),
+            qtype: DnsType::Txt,
+            qclass: DnsClass::In,
+        };
+        let mut response = crate::packet::DnsPacket::query(1, question.clone());
+        state.challenges.answer(&question, &mut response);
+        assert_eq!(response.answers.len(), 1);
+
+        let elsewhere = r#"{"fqdn": "_acme-challenge.example.org", "token": "abc"}"#;
+        let response = post(addr, "/acme/present", "s3cret", elsewhere);
+        assert!(
+            response.ends_with("is not in a loaded zone\n"),
+            "{}",
+            response
+        );
+        let response = post(addr, "/acme/present", "s3cret", "{}");
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+
+        let response = post(addr, "/acme/cleanup", "s3cret", challenge);
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        let response = post(addr, "/acme/cleanup", "s3cret", challenge);
+        assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+    }
+
+    #[test]
+    fn test_trace() {
+        let state = Arc::new(State::default());
+        // A root that never answers: the trace shows the query that failed.
+        state.roots.set(vec!["192.0.2.1".parse().unwrap()]);
+        let stats = Arc::new(Mutex::new(Stats::new()));
+        let admin = Admin::new(Arc::new(Config::default()), state, stats);
+        let addr = admin.serve("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        assert!(get(addr, "/api/trace").starts_with("HTTP/1.1 400 "));
+        assert!(get(addr, "/api/trace?name=example.com&type=BOGUS").starts_with("HTTP/1.1 400 "));
+
+        let response = get(addr, "/api/trace?name=example.com&type=AAAA");
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.contains("Content-Type: application/json"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let json = Json::parse(body).unwrap();
+        assert_eq!(json.get("type").and_then(Json::as_str), Some("AAAA"));
+        assert!(json.get("error").is_some(), "{}", body);
+        let step = &json.get("steps").unwrap().items()[0];
+        assert_eq!(step.get("zone").and_then(Json::as_str), Some("."));
+        assert_eq!(
+            step.get("server").and_then(Json::as_str),
+            Some("192.0.2.1:53")
+        );
+        assert!(step.get("error").is_some(), "{}", body);
+    }
+
+    #[test]
+    fn test_dashboard() {
+        let state = Arc::new(State::default());
+        let mut stats = Stats::new();
+        let client = "192.0.2.7".parse().unwrap();
+        let ms = Duration::from_millis(1);
+        stats.record("ads.example", "A", client, ResponseCode::NxDomain, ms);
+        stats.record(
+            "<b>www.example.com",
+            "AAAA",
+            client,
+            ResponseCode::NoError,
+            ms,
+        );
+        state.blocked.count("ads");
+        let stats = Arc::new(Mutex::new(stats));
+        let admin = Admin::new(Arc::new(Config::default()), Arc::clone(&state), stats);
+        let addr = admin.serve("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let response = get(addr, "/");
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("/api/stats"));
+
+        let response = get(addr, "/api/stats");
+        assert!(response.contains("Content-Type: application/json"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let json = Json::parse(body).unwrap();
+        assert_eq!(json.get("queries").and_then(Json::as_u64), Some(2));
+        assert_eq!(json.get("cache_misses").and_then(Json::as_u64), Some(0));
+        let blocked = json.get("blocked").unwrap().items();
+        assert_eq!(blocked[0].items()[0].as_str(), Some("ads"));
+        let top_clients = json.get("top_clients").unwrap().items();
+        assert_eq!(top_clients[0].items()[0].as_str(), Some("192.0.2.7"));
+        // Newest first.
+        let recent = json.get("recent").unwrap().items();
+        assert_eq!(
+            recent[0].get("name").and_then(Json::as_str),
+            Some("<b>www.example.com")
+        );
+        assert_eq!(
+            recent[1].get("rcode").and_then(Json::as_str),
+            Some("NXDOMAIN")
+        );
+    }
+}
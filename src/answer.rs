@@ -1,19 +1,76 @@
-use crate::common::{DnsClass, DnsType, Name};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+use crate::common::{backpatch_length, Compressor, DnsClass, DnsType, Name};
+use crate::error::ParseError;
+use crate::template::Template;
+
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) struct DnsAnswer {
     pub(crate) name: Name,
     pub(crate) qtype: DnsType,
     pub(crate) qclass: DnsClass,
     pub(crate) ttl: i32,
-    rdlength: u16,
-    rdata: RData,
+    pub(crate) rdata: RData,
 }
 
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) enum RData {
     A([u8; 4]),
+    Aaaa([u8; 16]),
+    Ns(Name),
+    Cname(Name),
+    Ptr(Name),
+    Alias(Name),
+    Mx {
+        preference: u16,
+        exchange: Name,
+    },
+    Soa {
+        mname: Name,
+        rname: Name,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    Txt(Vec<Vec<u8>>),
+    // WEIGHTED and FAILOVER: the target's weight or priority, and the
+    // port its health checks connect to.
+    Pool {
+        rank: u16,
+        port: u16,
+        target: Name,
+    },
+    // Which TEMPLATE answer to make up.
+    Template(Template),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: Name,
+    },
+    Unknown(Vec<u8>),
 }
 
 impl DnsAnswer {
+    // An IN A or AAAA record, whichever the address calls for.
+    pub(crate) fn address(name: Name, ttl: i32, addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => {
+                DnsAnswer::new(name, DnsType::A, DnsClass::In, ttl, RData::A(addr.octets()))
+            }
+            IpAddr::V6(addr) => DnsAnswer::new(
+                name,
+                DnsType::Aaaa,
+                DnsClass::In,
+                ttl,
+                RData::Aaaa(addr.octets()),
+            ),
+        }
+    }
+
     pub(crate) fn new(
         name: Name,
         qtype: DnsType,
@@ -21,45 +78,495 @@ impl DnsAnswer {
         ttl: i32,
         rdata: RData,
     ) -> Self {
-        let rdlength = match &rdata {
-            RData::A(_) => 4,
-        };
-
         DnsAnswer {
             name,
             qtype,
             qclass,
             ttl,
-            rdlength,
             rdata,
         }
     }
 
+    pub(crate) fn parse(message: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (name, offset) = Name::parse(message, offset)?;
+        let fixed = message
+            .get(offset..offset + 10)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let qtype = DnsType::from(u16::from_be_bytes([fixed[0], fixed[1]]));
+        let qclass = DnsClass::try_from(u16::from_be_bytes([fixed[2], fixed[3]]))?;
+        let ttl = i32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]);
+
+        let start = offset + 10;
+        let end = start + rdlength as usize;
+        if end > message.len() {
+            return Err(ParseError::UnexpectedEof);
+        }
+        // Empty RDATA is how dynamic updates say "any value" (RFC 2136).
+        let rdata = match (rdlength, qclass) {
+            (0, DnsClass::Any | DnsClass::None) => RData::Unknown(Vec::new()),
+            _ => RData::parse(qtype, message, start, end)?,
+        };
+
+        let answer = DnsAnswer {
+            name,
+            qtype,
+            qclass,
+            ttl,
+            rdata,
+        };
+        Ok((answer, end))
+    }
+
     #[allow(dead_code)]
     pub(crate) fn len(&self) -> usize {
-        self.name.len() + 10 + self.rdlength as usize
+        self.name.len() + 10 + self.rdata.len()
     }
 
-    pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.name.to_bytes());
-        bytes.push((self.qtype as u16 >> 8) as u8);
-        bytes.push(self.qtype as u8);
+    #[allow(dead_code)]
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        self.write_compressed(bytes, &mut Compressor::off());
+    }
+
+    // Names in the RDATA of the types RFC 1035 defines may be compressed
+    // too; any other type's may not (RFC 3597 section 4).
+    pub(crate) fn write_compressed<'a>(&'a self, bytes: &mut Vec<u8>, names: &mut Compressor<'a>) {
+        self.name.write_compressed(bytes, names);
+        bytes.extend_from_slice(&self.qtype.code().to_be_bytes());
         bytes.push((self.qclass as u16 >> 8) as u8);
         bytes.push(self.qclass as u8);
         bytes.push((self.ttl >> 24) as u8);
         bytes.push((self.ttl >> 16) as u8);
         bytes.push((self.ttl >> 8) as u8);
         bytes.push(self.ttl as u8);
-        bytes.push((self.rdlength >> 8) as u8);
-        bytes.push(self.rdlength as u8);
+        // RDLENGTH, filled in once the RDATA is written: names in it may
+        // not be written as they are stored.
+        let rdlength = bytes.len();
+        bytes.extend_from_slice(&[0, 0]);
 
         match &self.rdata {
             RData::A(ip) => {
                 bytes.extend_from_slice(ip);
             }
+            RData::Aaaa(ip) => {
+                bytes.extend_from_slice(ip);
+            }
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => {
+                name.write_compressed(bytes, names);
+            }
+            // Not a type compression is allowed for (RFC 3597 section 4).
+            RData::Alias(name) => {
+                name.write(bytes);
+            }
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                bytes.extend_from_slice(&preference.to_be_bytes());
+                exchange.write_compressed(bytes, names);
+            }
+            RData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                mname.write_compressed(bytes, names);
+                rname.write_compressed(bytes, names);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    bytes.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                bytes.extend_from_slice(&priority.to_be_bytes());
+                bytes.extend_from_slice(&weight.to_be_bytes());
+                bytes.extend_from_slice(&port.to_be_bytes());
+                target.write(bytes);
+            }
+            RData::Pool { rank, port, target } => {
+                bytes.extend_from_slice(&rank.to_be_bytes());
+                bytes.extend_from_slice(&port.to_be_bytes());
+                target.write(bytes);
+            }
+            RData::Template(template) => {
+                let name = template.name();
+                bytes.push(name.len() as u8);
+                bytes.extend_from_slice(name.as_bytes());
+            }
+            RData::Txt(strings) => {
+                for string in strings {
+                    bytes.push(string.len() as u8);
+                    bytes.extend_from_slice(string);
+                }
+            }
+            RData::Unknown(data) => {
+                bytes.extend_from_slice(data);
+            }
+        }
+        backpatch_length(bytes, rdlength);
+    }
+}
+
+impl RData {
+    // The uncompressed wire length.
+    #[allow(dead_code)]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            RData::A(_) => 4,
+            RData::Aaaa(_) => 16,
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) | RData::Alias(name) => {
+                name.len()
+            }
+            RData::Mx { exchange, .. } => 2 + exchange.len(),
+            RData::Soa { mname, rname, .. } => mname.len() + rname.len() + 20,
+            RData::Srv { target, .. } => 6 + target.len(),
+            RData::Pool { target, .. } => 4 + target.len(),
+            RData::Template(template) => 1 + template.name().len(),
+            RData::Txt(strings) => strings.iter().map(|s| s.len() + 1).sum(),
+            RData::Unknown(bytes) => bytes.len(),
+        }
+    }
+
+    // Names inside RDATA may be compressed against the whole message, so
+    // this takes the message and the bounds of the RDATA within it.
+    fn parse(qtype: DnsType, message: &[u8], start: usize, end: usize) -> Result<Self, ParseError> {
+        let rdata = &message[start..end];
+        let invalid_length = || ParseError::InvalidLength(rdata.len() as u16);
+        let name_at = |offset: usize| -> Result<Name, ParseError> {
+            let (name, next) = Name::parse(&message[..end], offset)?;
+            if next != end {
+                return Err(invalid_length());
+            }
+            Ok(name)
+        };
+
+        match qtype {
+            DnsType::A => Ok(RData::A(rdata.try_into().map_err(|_| invalid_length())?)),
+            DnsType::Aaaa => Ok(RData::Aaaa(rdata.try_into().map_err(|_| invalid_length())?)),
+            DnsType::Ns => Ok(RData::Ns(name_at(start)?)),
+            DnsType::Cname => Ok(RData::Cname(name_at(start)?)),
+            DnsType::Ptr => Ok(RData::Ptr(name_at(start)?)),
+            DnsType::Alias => Ok(RData::Alias(name_at(start)?)),
+            DnsType::Mx => {
+                let fixed = rdata.get(..2).ok_or_else(invalid_length)?;
+                Ok(RData::Mx {
+                    preference: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    exchange: name_at(start + 2)?,
+                })
+            }
+            DnsType::Soa => {
+                let (mname, offset) = Name::parse(&message[..end], start)?;
+                let (rname, offset) = Name::parse(&message[..end], offset)?;
+                let fixed = message.get(offset..end).ok_or_else(invalid_length)?;
+                if fixed.len() != 20 {
+                    return Err(invalid_length());
+                }
+                let field = |i: usize| {
+                    u32::from_be_bytes([fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]])
+                };
+                Ok(RData::Soa {
+                    mname,
+                    rname,
+                    serial: field(0),
+                    refresh: field(4),
+                    retry: field(8),
+                    expire: field(12),
+                    minimum: field(16),
+                })
+            }
+            DnsType::Srv => {
+                let fixed = rdata.get(..6).ok_or_else(invalid_length)?;
+                Ok(RData::Srv {
+                    priority: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    weight: u16::from_be_bytes([fixed[2], fixed[3]]),
+                    port: u16::from_be_bytes([fixed[4], fixed[5]]),
+                    target: name_at(start + 6)?,
+                })
+            }
+            DnsType::Weighted | DnsType::Failover => {
+                let fixed = rdata.get(..4).ok_or_else(invalid_length)?;
+                Ok(RData::Pool {
+                    rank: u16::from_be_bytes([fixed[0], fixed[1]]),
+                    port: u16::from_be_bytes([fixed[2], fixed[3]]),
+                    target: name_at(start + 4)?,
+                })
+            }
+            DnsType::Template => {
+                let (&len, name) = rdata.split_first().ok_or_else(invalid_length)?;
+                let name = std::str::from_utf8(name)
+                    .ok()
+                    .filter(|name| name.len() == len as usize)
+                    .and_then(|name| name.parse().ok())
+                    .ok_or_else(invalid_length)?;
+                Ok(RData::Template(name))
+            }
+            DnsType::Txt => {
+                let mut strings = Vec::new();
+                let mut rest = rdata;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let string = tail.get(..len as usize).ok_or(ParseError::UnexpectedEof)?;
+                    strings.push(string.to_vec());
+                    rest = &tail[len as usize..];
+                }
+                Ok(RData::Txt(strings))
+            }
+            _ => Ok(RData::Unknown(rdata.to_vec())),
+        }
+    }
+}
+
+// Zone file presentation format, e.g. `example.com. 60 IN A 192.0.2.1`.
+impl fmt::Display for DnsAnswer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.\t{}\t{}\t{}\t{}",
+            self.name, self.ttl, self.qclass, self.qtype, self.rdata
+        )
+    }
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", Ipv4Addr::from(*ip)),
+            RData::Aaaa(ip) => write!(f, "{}", Ipv6Addr::from(*ip)),
+            RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) | RData::Alias(name) => {
+                write!(f, "{}.", name)
+            }
+            RData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}.", preference, exchange),
+            RData::Soa {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{}. {}. {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            RData::Txt(strings) => {
+                for (i, string) in strings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write_character_string(f, string)?;
+                }
+                Ok(())
+            }
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}.", priority, weight, port, target),
+            RData::Pool { rank, port, target } => write!(f, "{} {} {}.", rank, port, target),
+            RData::Template(template) => f.write_str(template.name()),
+            // RFC 3597 generic encoding.
+            RData::Unknown(data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    f.write_str(" ")?;
+                }
+                for byte in data {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
         }
+    }
+}
 
+fn write_character_string(f: &mut fmt::Formatter<'_>, string: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            0x20..=0x7e => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_answer_round_trip() {
+        let answers = vec![
+            DnsAnswer::new(
+                "example.com".into(),
+                DnsType::A,
+                DnsClass::In,
+                300,
+                RData::A([93, 184, 216, 34]),
+            ),
+            DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Txt,
+                DnsClass::In,
+                60,
+                RData::Txt(vec![b"v=spf1 -all".to_vec(), b"".to_vec()]),
+            ),
+            DnsAnswer::new(
+                "www.example.com".into(),
+                DnsType::Cname,
+                DnsClass::In,
+                60,
+                RData::Cname("example.com".into()),
+            ),
+            DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Mx,
+                DnsClass::In,
+                60,
+                RData::Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".into(),
+                },
+            ),
+            DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Soa,
+                DnsClass::In,
+                3600,
+                RData::Soa {
+                    mname: "ns1.example.com".into(),
+                    rname: "hostmaster.example.com".into(),
+                    serial: 2024010101,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 300,
+                },
+            ),
+            DnsAnswer::new(
+                "_sip._udp.example.com".into(),
+                DnsType::Srv,
+                DnsClass::In,
+                60,
+                RData::Srv {
+                    priority: 10,
+                    weight: 5,
+                    port: 5060,
+                    target: "sip.example.com".into(),
+                },
+            ),
+            DnsAnswer::new(
+                "whoami.example.com".into(),
+                DnsType::Template,
+                DnsClass::In,
+                0,
+                RData::Template(Template::Address),
+            ),
+            DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Aaaa,
+                DnsClass::In,
+                60,
+                RData::Aaaa([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            ),
+        ];
+        for answer in answers {
+            let mut bytes = Vec::new();
+            answer.write(&mut bytes);
+            let (parsed, end) = DnsAnswer::parse(&bytes, 0).unwrap();
+            assert_eq!(end, bytes.len());
+            assert_eq!(parsed.name.as_str(), answer.name.as_str());
+            assert_eq!(parsed.ttl, answer.ttl);
+            assert_eq!(parsed.rdata, answer.rdata);
+        }
+    }
+
+    #[test]
+    fn test_unknown_types() {
+        // A DNAME (type 39) ahead of an A, as an upstream would send them.
+        let mut bytes =
+            b"\x07example\x03com\x00\x00\x27\x00\x01\x00\x00\x00\x3c\x00\x05\x03net\x00".to_vec();
+        let dname = bytes.len();
         bytes
+            .extend_from_slice(b"\xc0\x00\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\xc0\x00\x02\x01");
+        let (answer, end) = DnsAnswer::parse(&bytes, 0).unwrap();
+        assert_eq!(end, dname);
+        assert_eq!(answer.qtype, DnsType::Unknown(39));
+        assert_eq!(answer.rdata, RData::Unknown(b"\x03net\x00".to_vec()));
+        assert_eq!(
+            answer.to_string(),
+            "example.com.\t60\tIN\tTYPE39\t\\# 5 036e657400"
+        );
+        let (address, _) = DnsAnswer::parse(&bytes, dname).unwrap();
+        assert_eq!(address.rdata, RData::A([192, 0, 2, 1]));
+
+        // Written back as it came.
+        let mut written = Vec::new();
+        answer.write(&mut written);
+        assert_eq!(written, bytes[..dname]);
+    }
+
+    #[test]
+    fn test_answer_parse_bad_rdata() {
+        // An A record claiming 3 bytes of rdata.
+        let bytes = b"\x00\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x03\x01\x02\x03";
+        assert!(matches!(
+            DnsAnswer::parse(bytes, 0),
+            Err(ParseError::InvalidLength(3))
+        ));
+        // rdlength running past the end of the message.
+        let bytes = b"\x00\x00\x01\x00\x01\x00\x00\x00\x3c\x00\x04\x01\x02";
+        assert!(matches!(
+            DnsAnswer::parse(bytes, 0),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_rdlength_after_compressed_rdata() {
+        // A CNAME whose target ends in a pointer to the owner name: six
+        // bytes of RDATA on the wire, seventeen once written out in full.
+        let message = b"\x07example\x03com\x00\
+            \xc0\x00\x00\x05\x00\x01\x00\x00\x00\x3c\x00\x06\x03www\xc0\x00";
+        let (answer, end) = DnsAnswer::parse(message, 13).unwrap();
+        assert_eq!(end, message.len());
+        assert_eq!(answer.rdata, RData::Cname("www.example.com".into()));
+
+        let mut bytes = Vec::new();
+        answer.write(&mut bytes);
+        assert_eq!(bytes.len(), answer.len());
+        assert_eq!(bytes[21..23], [0, 17]);
+        assert_eq!(DnsAnswer::parse(&bytes, 0).unwrap(), (answer, bytes.len()));
+    }
+
+    #[test]
+    fn test_presentation_format() {
+        let answer = DnsAnswer::new(
+            "example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([192, 0, 2, 1]),
+        );
+        assert_eq!(answer.to_string(), "example.com.\t300\tIN\tA\t192.0.2.1");
+        assert_eq!(
+            RData::Txt(vec![b"say \"hi\"".to_vec(), vec![0x07]]).to_string(),
+            "\"say \\\"hi\\\"\" \"\\007\""
+        );
+        assert_eq!(RData::Unknown(vec![0xab, 0x01]).to_string(), "\\# 2 ab01");
+        assert_eq!(RData::Cname("".into()).to_string(), ".");
     }
 }
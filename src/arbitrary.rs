@@ -0,0 +1,256 @@
+// Random messages for property tests: anything generated here must survive
+// a trip through the wire format unchanged. New record types and encodings
+// get a generator alongside their codec.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::edns::{self, Edns, EdnsOption};
+use crate::header::{DnsHeader, OpCode, PacketType, ResponseCode};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const LABEL_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_*";
+
+// Types whose RDATA is kept as opaque bytes.
+const OPAQUE_TYPES: [DnsType; 8] = [
+    DnsType::Null,
+    DnsType::Hinfo,
+    DnsType::Ds,
+    DnsType::Dnskey,
+    DnsType::Rrsig,
+    DnsType::Tlsa,
+    DnsType::Https,
+    DnsType::Caa,
+];
+
+const QUESTION_TYPES: [DnsType; 8] = [
+    DnsType::A,
+    DnsType::Aaaa,
+    DnsType::Mx,
+    DnsType::Txt,
+    DnsType::Soa,
+    DnsType::Axfr,
+    DnsType::Ixfr,
+    DnsType::Any,
+];
+
+fn bytes(rng: &mut impl Rng, max: usize) -> Vec<u8> {
+    let len = rng.gen_range(0..=max);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+// Up to the 255-byte wire limit, mostly short.
+pub(crate) fn name(rng: &mut impl Rng) -> Name {
+    let mut labels = Vec::new();
+    let mut wire_len = 1;
+    for _ in 0..rng.gen_range(0..5) {
+        let len = match rng.gen_bool(0.1) {
+            true => rng.gen_range(1..=63),
+            false => rng.gen_range(1..=10),
+        };
+        if wire_len + len + 1 > 255 {
+            break;
+        }
+        wire_len += len + 1;
+        let label: String = (0..len)
+            .map(|_| *LABEL_CHARS.choose(rng).unwrap() as char)
+            .collect();
+        labels.push(label);
+    }
+    Name::from(labels.join(".").as_str())
+}
+
+pub(crate) fn header(rng: &mut impl Rng) -> DnsHeader {
+    DnsHeader {
+        id: rng.gen(),
+        qr: *[PacketType::Query, PacketType::Response]
+            .choose(rng)
+            .unwrap(),
+        opcode: OpCode::from(rng.gen_range(0..16)),
+        aa: rng.gen(),
+        tc: rng.gen(),
+        rd: rng.gen(),
+        ra: rng.gen(),
+        z: rng.gen_range(0..8),
+        rcode: ResponseCode::try_from(rng.gen_range(0..=10)).unwrap(),
+        qdcount: 0,
+        ancount: 0,
+        nscount: 0,
+        arcount: 0,
+    }
+}
+
+pub(crate) fn question(rng: &mut impl Rng) -> DnsQuestion {
+    DnsQuestion {
+        qname: name(rng),
+        qtype: *QUESTION_TYPES.choose(rng).unwrap(),
+        qclass: *[DnsClass::In, DnsClass::Ch, DnsClass::Any]
+            .choose(rng)
+            .unwrap(),
+    }
+}
+
+pub(crate) fn record(rng: &mut impl Rng) -> DnsAnswer {
+    let (qtype, rdata) = match rng.gen_range(0..10) {
+        0 => (DnsType::A, RData::A(rng.gen())),
+        1 => (DnsType::Aaaa, RData::Aaaa(rng.gen())),
+        2 => (DnsType::Ns, RData::Ns(name(rng))),
+        3 => (DnsType::Cname, RData::Cname(name(rng))),
+        4 => (DnsType::Ptr, RData::Ptr(name(rng))),
+        5 => (
+            DnsType::Mx,
+            RData::Mx {
+                preference: rng.gen(),
+                exchange: name(rng),
+            },
+        ),
+        6 => (
+            DnsType::Soa,
+            RData::Soa {
+                mname: name(rng),
+                rname: name(rng),
+                serial: rng.gen(),
+                refresh: rng.gen(),
+                retry: rng.gen(),
+                expire: rng.gen(),
+                minimum: rng.gen(),
+            },
+        ),
+        7 => (
+            DnsType::Txt,
+            RData::Txt((0..rng.gen_range(0..4)).map(|_| bytes(rng, 255)).collect()),
+        ),
+        8 => (
+            DnsType::Srv,
+            RData::Srv {
+                priority: rng.gen(),
+                weight: rng.gen(),
+                port: rng.gen(),
+                target: name(rng),
+            },
+        ),
+        _ => (
+            *OPAQUE_TYPES.choose(rng).unwrap(),
+            RData::Unknown(bytes(rng, 64)),
+        ),
+    };
+    // Empty RDATA in the NONE and ANY classes means "any value" to dynamic
+    // updates and doesn't parse back as the record type.
+    let qclass = match rdata {
+        RData::Txt(_) | RData::Unknown(_) => DnsClass::In,
+        _ => *[DnsClass::In, DnsClass::Ch, DnsClass::None, DnsClass::Any]
+            .choose(rng)
+            .unwrap(),
+    };
+    DnsAnswer::new(name(rng), qtype, qclass, rng.gen(), rdata)
+}
+
+pub(crate) fn edns(rng: &mut impl Rng) -> Edns {
+    let mut edns = Edns::new(rng.gen());
+    edns.extended_rcode = rng.gen();
+    edns.version = rng.gen();
+    edns.dnssec_ok = rng.gen();
+    for _ in 0..rng.gen_range(0..4) {
+        edns.options.push(match rng.gen_range(0..5) {
+            0 => EdnsOption::Nsid(bytes(rng, 16)),
+            1 => EdnsOption::TcpKeepalive(rng.gen()),
+            3 => EdnsOption::Expire(rng.gen()),
+            2 => {
+                let text = bytes(rng, 32).into_iter().map(|b| (b & 0x7f) as char);
+                EdnsOption::ExtendedError(rng.gen(), text.collect())
+            }
+            _ => {
+                let code = rng.gen();
+                if edns::known(code) {
+                    continue;
+                }
+                EdnsOption::Unknown(code, bytes(rng, 16))
+            }
+        });
+    }
+    edns
+}
+
+pub(crate) fn packet(rng: &mut impl Rng) -> DnsPacket {
+    let mut packet = DnsPacket {
+        header: header(rng),
+        questions: (0..rng.gen_range(0..3)).map(|_| question(rng)).collect(),
+        answers: (0..rng.gen_range(0..6)).map(|_| record(rng)).collect(),
+        authorities: (0..rng.gen_range(0..3)).map(|_| record(rng)).collect(),
+        additionals: (0..rng.gen_range(0..3)).map(|_| record(rng)).collect(),
+        edns: rng.gen_bool(0.5).then(|| edns(rng)),
+    };
+    // As `to_bytes` writes them.
+    packet.header.qdcount = packet.questions.len() as u16;
+    packet.header.ancount = packet.answers.len() as u16;
+    packet.header.nscount = packet.authorities.len() as u16;
+    packet.header.arcount = (packet.additionals.len() + packet.edns.is_some() as usize) as u16;
+    packet
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CASES: usize = 2_000;
+
+    #[test]
+    fn test_name_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CASES {
+            let name = name(&mut rng);
+            let bytes = name.to_bytes();
+            assert_eq!(bytes.len(), name.len());
+            assert_eq!(Name::try_from(bytes.as_slice()), Ok(name));
+        }
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CASES {
+            let header = header(&mut rng);
+            let mut bytes = Vec::new();
+            header.write(&mut bytes);
+            assert_eq!(DnsHeader::try_from(bytes.as_slice()), Ok(header));
+        }
+    }
+
+    #[test]
+    fn test_question_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CASES {
+            let question = question(&mut rng);
+            let mut bytes = Vec::new();
+            question.write(&mut bytes);
+            assert_eq!(DnsQuestion::parse(&bytes, 0), Ok((question, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CASES {
+            let record = record(&mut rng);
+            let mut bytes = Vec::new();
+            record.write(&mut bytes);
+            assert_eq!(bytes.len(), record.len());
+            assert_eq!(DnsAnswer::parse(&bytes, 0), Ok((record, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn test_packet_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..CASES {
+            let packet = packet(&mut rng);
+            let bytes = packet.to_bytes();
+            let parsed = DnsPacket::try_from(&bytes).unwrap();
+            assert_eq!(parsed, packet);
+            assert_eq!(parsed.to_bytes(), bytes);
+        }
+    }
+}
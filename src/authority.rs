@@ -0,0 +1,546 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsType, Name};
+use crate::config::Config;
+use crate::dnssec;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::serial;
+use crate::zone::{self, ZoneFile};
+use crate::zonecheck::{self, Severity};
+
+const MAX_CNAME_HOPS: usize = 8;
+
+// A zone served authoritatively, loaded from a master file. Records are
+// kept in canonical order (RFC 4034 section 6.1), so a name's records are
+// found by binary search and the names below it follow them.
+pub(crate) struct Zone {
+    pub(crate) apex: Name,
+    records: Vec<DnsAnswer>,
+    // The `--zone` file it came from and the origin given for it.
+    file: Option<(PathBuf, Option<Name>)>,
+}
+
+// Records a zone reload took out and put in.
+pub(crate) struct Changes<'a> {
+    pub(crate) removed: Vec<&'a DnsAnswer>,
+    pub(crate) added: Vec<&'a DnsAnswer>,
+}
+
+// Every loaded zone; a name is answered from the most specific one.
+#[derive(Default)]
+pub(crate) struct Zones(Vec<Arc<Zone>>);
+
+impl Zone {
+    pub(crate) fn load(path: &Path, origin: Option<&Name>) -> Result<Zone, String> {
+        let file = ZoneFile::read(path, origin.cloned())?;
+        let mut zone = Zone::from_file(file).map_err(|e| format!("{}: {}", path.display(), e))?;
+        zone.file = Some((path.to_path_buf(), origin.cloned()));
+        Ok(zone)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn parse(text: &str, origin: Option<&Name>) -> Result<Zone, String> {
+        Zone::from_file(ZoneFile::parse(text, origin.cloned()))
+    }
+
+    // Zones with errors check-zone would report are refused.
+    pub(crate) fn from_file(file: ZoneFile) -> Result<Zone, String> {
+        if let Some(error) = file.errors.first() {
+            return Err(error.to_string());
+        }
+        let apex = zonecheck::find_apex(&file).ok_or("cannot determine the zone origin")?;
+        let findings = zonecheck::check(&file, &apex);
+        if let Some(error) = findings.iter().find(|f| f.severity == Severity::Error) {
+            return Err(match error.line {
+                Some(line) => format!("line {}: {}", line, error.message),
+                None => error.message.clone(),
+            });
+        }
+        let records = file.entries.into_iter().map(|e| e.record).collect();
+        Ok(Zone::from_records(apex, records))
+    }
+
+    // A zone as transferred from its primary, or read from a file.
+    pub(crate) fn from_records(apex: Name, mut records: Vec<DnsAnswer>) -> Zone {
+        records.sort_by(|a, b| dnssec::canonical_cmp(&a.name, &b.name));
+        Zone {
+            apex,
+            records,
+            file: None,
+        }
+    }
+
+    // The zone with `records` in place of its own, from the same file.
+    pub(crate) fn updated(&self, records: Vec<DnsAnswer>) -> Zone {
+        Zone {
+            file: self.file.clone(),
+            ..Zone::from_records(self.apex.clone(), records)
+        }
+    }
+
+    // Writes the changes since `old` to the zone's file, editing it as
+    // little as `zone::edit` can so that it stays as its author wrote it.
+    // The edit is read back before it replaces the file, and refused if it
+    // doesn't give this zone.
+    pub(crate) fn save(&self, old: &Zone) -> Result<(), String> {
+        let Some((path, origin)) = &self.file else {
+            return Ok(());
+        };
+        let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+        let path = fs::canonicalize(path).map_err(error)?;
+        let text = fs::read_to_string(&path).map_err(error)?;
+        let changes = old.diff(self);
+        let text = zone::edit(&text, origin.clone(), &changes.removed, &changes.added)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let edited = ZoneFile::parse_as(&text, path.clone(), origin.clone());
+        let edited = Zone::from_file(edited).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let changes = edited.diff(self);
+        if !changes.removed.is_empty() || !changes.added.is_empty() {
+            return Err(format!("{}: can't be edited to match", path.display()));
+        }
+        // Renamed into place, so a crash leaves the old file or the new.
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, text).map_err(error)?;
+        fs::rename(&temporary, &path).map_err(error)
+    }
+
+    // Fills in the response for a name inside the zone: an answer, a
+    // referral to a delegated child, NODATA or NXDOMAIN.
+    pub(crate) fn answer(&self, question: &DnsQuestion, response: &mut DnsPacket) {
+        let qname = &question.qname;
+        if let Some(cut) = self.delegation(qname, question.qtype) {
+            response.header.aa = false;
+            response.authorities = self.records_at(&cut, DnsType::Ns);
+            response.additionals = response
+                .authorities
+                .iter()
+                .filter_map(|ns| match &ns.rdata {
+                    RData::Ns(target) => Some(target),
+                    _ => None,
+                })
+                .flat_map(|target| {
+                    let mut glue = self.records_at(target, DnsType::A);
+                    glue.extend(self.records_at(target, DnsType::Aaaa));
+                    glue
+                })
+                .collect();
+            return;
+        }
+
+        response.header.aa = true;
+        let mut name = qname.clone();
+        for _ in 0..MAX_CNAME_HOPS {
+            let (at_name, below) = self.at(&name);
+            if at_name.is_empty() {
+                // A name with nothing at it but names below it exists.
+                if response.answers.is_empty() {
+                    let exists = below.first().is_some_and(|r| r.name.is_subdomain_of(&name));
+                    if !exists {
+                        response.header.rcode = ResponseCode::NxDomain;
+                    }
+                }
+                break;
+            }
+
+            let matching: Vec<DnsAnswer> = at_name
+                .iter()
+                .filter(|r| r.qtype == question.qtype || question.qtype == DnsType::Any)
+                .cloned()
+                .collect();
+            if !matching.is_empty() {
+                response.answers.extend(matching);
+                return;
+            }
+            let Some(cname) = at_name.iter().find(|r| r.qtype == DnsType::Cname) else {
+                break;
+            };
+            response.answers.push(cname.clone());
+            match &cname.rdata {
+                RData::Cname(target) if target.is_subdomain_of(&self.apex) => name = target.clone(),
+                _ => return,
+            }
+        }
+
+        if response.answers.is_empty() || response.header.rcode == ResponseCode::NxDomain {
+            response.authorities.extend(self.negative_soa());
+        }
+    }
+
+    // The topmost zone cut between the apex and the name. A DS query for
+    // the cut itself is answered by the parent.
+    fn delegation(&self, qname: &Name, qtype: DnsType) -> Option<Name> {
+        let labels: Vec<&str> = match qname.as_str() {
+            "" => Vec::new(),
+            qname => qname.split('.').collect(),
+        };
+        let depth = match self.apex.as_str() {
+            "" => 0,
+            apex => apex.split('.').count(),
+        };
+        (depth + 1..=labels.len())
+            .map(|n| Name::from(labels[labels.len() - n..].join(".").as_str()))
+            .filter(|cut| !(qtype == DnsType::Ds && cut.eq_ignore_case(qname)))
+            .find(|cut| self.at(cut).0.iter().any(|r| r.qtype == DnsType::Ns))
+    }
+
+    // The records at the name, and every record after them.
+    fn at(&self, name: &Name) -> (&[DnsAnswer], &[DnsAnswer]) {
+        let start = self
+            .records
+            .partition_point(|r| dnssec::canonical_cmp(&r.name, name) == Ordering::Less);
+        let rest = &self.records[start..];
+        rest.split_at(rest.partition_point(|r| r.name.eq_ignore_case(name)))
+    }
+
+    pub(crate) fn records_at(&self, name: &Name, qtype: DnsType) -> Vec<DnsAnswer> {
+        self.at(name)
+            .0
+            .iter()
+            .filter(|record| record.qtype == qtype)
+            .cloned()
+            .collect()
+    }
+
+    // Whether the name owns any records.
+    pub(crate) fn in_use(&self, name: &Name) -> bool {
+        !self.at(name).0.is_empty()
+    }
+
+    pub(crate) fn soa(&self) -> Option<DnsAnswer> {
+        self.records_at(&self.apex, DnsType::Soa).pop()
+    }
+
+    // The ALIAS at the name, which the zones plugin flattens.
+    pub(crate) fn alias(&self, name: &Name) -> Option<DnsAnswer> {
+        self.records_at(name, DnsType::Alias).pop()
+    }
+
+    // The TEMPLATE at the name, which the zones plugin makes answers from.
+    pub(crate) fn template(&self, name: &Name) -> Option<DnsAnswer> {
+        self.records_at(name, DnsType::Template).pop()
+    }
+
+    // The WEIGHTED or FAILOVER records at the name, which the zones plugin
+    // picks a target from.
+    pub(crate) fn pool(&self, name: &Name) -> Vec<DnsAnswer> {
+        let (records, _) = self.at(name);
+        let pooled =
+            |record: &&DnsAnswer| matches!(record.qtype, DnsType::Weighted | DnsType::Failover);
+        records.iter().filter(pooled).cloned().collect()
+    }
+
+    // Every record, in canonical order.
+    pub(crate) fn records(&self) -> &[DnsAnswer] {
+        &self.records
+    }
+
+    // The SOA's EXPIRE field.
+    pub(crate) fn expire(&self) -> Option<u32> {
+        match self.soa()?.rdata {
+            RData::Soa { expire, .. } => Some(expire),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn serial(&self) -> Option<u32> {
+        match self.soa()?.rdata {
+            RData::Soa { serial, .. } => Some(serial),
+            _ => None,
+        }
+    }
+
+    // The SOA for negative answers, with the TTL lowered to its minimum
+    // field (RFC 2308 section 3).
+    fn negative_soa(&self) -> Option<DnsAnswer> {
+        let mut soa = self.records_at(&self.apex, DnsType::Soa).pop()?;
+        if let RData::Soa { minimum, .. } = soa.rdata {
+            soa.ttl = soa.ttl.min(minimum.min(i32::MAX as u32) as i32);
+        }
+        Some(soa)
+    }
+    // The records to remove from this zone and add to it to make `new`, as
+    // an incremental transfer would carry them.
+    pub(crate) fn diff<'a>(&'a self, new: &'a Zone) -> Changes<'a> {
+        let missing = |from: &'a Zone, to: &Zone| -> Vec<&'a DnsAnswer> {
+            from.records
+                .iter()
+                .filter(|record| !to.at(&record.name).0.contains(record))
+                .collect()
+        };
+        Changes {
+            removed: missing(self, new),
+            added: missing(new, self),
+        }
+    }
+}
+
+// Cuts an answer to ANY down to one RRset and the signatures covering
+// it, as RFC 8482 section 4.1 allows, for servers that would rather not
+// send every record a name has to whoever asks.
+pub(crate) fn minimal_any(response: &mut DnsPacket) {
+    let Some(kept) = response
+        .answers
+        .iter()
+        .map(|record| record.qtype)
+        .find(|&qtype| qtype != DnsType::Rrsig)
+    else {
+        return;
+    };
+    response.answers.retain(|record| match &record.rdata {
+        RData::Unknown(rdata) if record.qtype == DnsType::Rrsig => {
+            dnssec::Rrsig::parse(rdata).is_some_and(|rrsig| rrsig.type_covered == kept.code())
+        }
+        _ => record.qtype == kept,
+    });
+}
+
+impl Zones {
+    // Every `--zone`, each on its own thread, failing on the first that
+    // doesn't load.
+    pub(crate) fn load(config: &Config) -> Result<Self, String> {
+        thread::scope(|scope| {
+            let loading: Vec<_> = config
+                .zones
+                .iter()
+                .map(|(origin, path)| scope.spawn(move || Zone::load(path, origin.as_ref())))
+                .collect();
+            loading
+                .into_iter()
+                .map(|handle| handle.join().unwrap().map(Arc::new))
+                .collect::<Result<_, _>>()
+                .map(Zones)
+        })
+    }
+
+    pub(crate) fn find(&self, qname: &Name) -> Option<&Zone> {
+        self.iter()
+            .filter(|zone| qname.is_subdomain_of(&zone.apex))
+            .max_by_key(|zone| zone.apex.len())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Zone> {
+        self.0.iter().map(|zone| &**zone)
+    }
+
+    // The same zones with `zone` in place of the one at its apex.
+    pub(crate) fn replace(&self, zone: Zone) -> Zones {
+        let mut zones = self.0.clone();
+        match zones
+            .iter_mut()
+            .find(|old| old.apex.eq_ignore_case(&zone.apex))
+        {
+            Some(old) => *old = Arc::new(zone),
+            None => zones.push(Arc::new(zone)),
+        }
+        Zones(zones)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // One key=value line per count of records added and removed in each
+    // zone on the way to `new`, as in `ctl stats`. Zones that come or go
+    // count all their records. A zone that changed without its serial
+    // going up, which secondaries won't notice, gets flagged.
+    pub(crate) fn changes(&self, new: &Zones) -> String {
+        let none = Zone::from_records(Name::from(""), Vec::new());
+        let mut apexes: Vec<&Name> = self.0.iter().chain(&new.0).map(|zone| &zone.apex).collect();
+        apexes.sort_by(|a, b| dnssec::canonical_cmp(a, b));
+        apexes.dedup_by(|a, b| a.eq_ignore_case(b));
+        let mut report = String::new();
+        for apex in apexes {
+            let old = self.iter().find(|zone| zone.apex.eq_ignore_case(apex));
+            let new = new.iter().find(|zone| zone.apex.eq_ignore_case(apex));
+            let changes = old.unwrap_or(&none).diff(new.unwrap_or(&none));
+            let apex = match apex.as_str() {
+                "" => ".",
+                apex => apex,
+            };
+            writeln!(report, "zone.{}.added={}", apex, changes.added.len()).unwrap();
+            writeln!(report, "zone.{}.removed={}", apex, changes.removed.len()).unwrap();
+            let serials = old
+                .zip(new)
+                .and_then(|(old, new)| old.serial().zip(new.serial()));
+            if let Some((old, new)) = serials {
+                let changed = !changes.added.is_empty() || !changes.removed.is_empty();
+                if changed && !serial::is_newer(new, old) {
+                    writeln!(report, "zone.{}.serial_not_increased=1", apex).unwrap();
+                }
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::DnsClass;
+
+    const ZONE: &str = "$ORIGIN example.com.\n\
+                        $TTL 3600\n\
+                        @ SOA ns1 hostmaster 2024010101 7200 900 1209600 300\n\
+                        @ NS ns1\n\
+                        ns1 A 192.0.2.1\n\
+                        www A 192.0.2.80\n\
+                        www AAAA 2001:db8::80\n\
+                        alias CNAME www\n\
+                        a.b.deep TXT \"deep\"\n\
+                        sub NS ns.sub\n\
+                        ns.sub A 192.0.2.53\n";
+
+    fn ask(name: &str, qtype: DnsType) -> DnsPacket {
+        let zone = Zone::parse(ZONE, None).unwrap();
+        let question = DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        zone.answer(&question, &mut response);
+        response
+    }
+
+    fn types(records: &[DnsAnswer]) -> Vec<DnsType> {
+        records.iter().map(|r| r.qtype).collect()
+    }
+
+    #[test]
+    fn test_answers() {
+        let response = ask("WWW.example.com", DnsType::A);
+        assert!(response.header.aa);
+        assert_eq!(Zone::parse(ZONE, None).unwrap().expire(), Some(1209600));
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+
+        let response = ask("alias.example.com", DnsType::Aaaa);
+        assert_eq!(
+            types(&response.answers),
+            vec![DnsType::Cname, DnsType::Aaaa]
+        );
+
+        let mut response = ask("www.example.com", DnsType::Any);
+        assert_eq!(types(&response.answers), vec![DnsType::A, DnsType::Aaaa]);
+        minimal_any(&mut response);
+        assert_eq!(types(&response.answers), vec![DnsType::A]);
+    }
+
+    #[test]
+    fn test_negative_answers() {
+        let response = ask("www.example.com", DnsType::Mx);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+        assert_eq!(types(&response.authorities), vec![DnsType::Soa]);
+        assert_eq!(response.authorities[0].ttl, 300);
+
+        // An empty non-terminal exists.
+        let response = ask("b.deep.example.com", DnsType::A);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+
+        let response = ask("missing.example.com", DnsType::A);
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+        assert_eq!(types(&response.authorities), vec![DnsType::Soa]);
+    }
+
+    #[test]
+    fn test_referral() {
+        let response = ask("host.sub.example.com", DnsType::A);
+        assert!(!response.header.aa);
+        assert!(response.answers.is_empty());
+        assert_eq!(types(&response.authorities), vec![DnsType::Ns]);
+        assert_eq!(response.additionals[0].rdata, RData::A([192, 0, 2, 53]));
+    }
+
+    #[test]
+    fn test_lookup() {
+        // Records for a name needn't be together in the file.
+        let zone = Zone::parse(
+            "$ORIGIN .\n\
+             $TTL 60\n\
+             @ SOA a.root hostmaster 1 2h 15m 2w 5m\n\
+             @ NS a.root\n\
+             WWW.test A 192.0.2.1\n\
+             test NS ns.test\n\
+             a.root A 192.0.2.53\n\
+             ns.test A 192.0.2.2\n\
+             www.test AAAA 2001:db8::1\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(zone.records_at(&"www.test".into(), DnsType::Aaaa).len(), 1);
+        let ask = |name: &str, qtype| {
+            let question = DnsQuestion {
+                qname: name.into(),
+                qtype,
+                qclass: DnsClass::In,
+            };
+            let mut response = DnsPacket::query(1, question.clone());
+            zone.answer(&question, &mut response);
+            response
+        };
+        let response = ask("www.Test", DnsType::A);
+        assert!(!response.header.aa);
+        assert_eq!(types(&response.authorities), vec![DnsType::Ns]);
+        assert_eq!(response.additionals[0].rdata, RData::A([192, 0, 2, 2]));
+        // The parent answers for DS at the cut.
+        let response = ask("TEST", DnsType::Ds);
+        assert!(response.header.aa);
+        assert_eq!(types(&response.authorities), vec![DnsType::Soa]);
+        let response = ask("", DnsType::Ns);
+        assert!(response.header.aa);
+        assert_eq!(types(&response.answers), vec![DnsType::Ns]);
+    }
+
+    #[test]
+    fn test_unknown_types() {
+        let text = format!("{}dname TYPE39 \\# 5 036e657400\n", ZONE);
+        let zone = Zone::parse(&text, None).unwrap();
+        let ask = |qtype| {
+            let question = DnsQuestion {
+                qname: "dname.example.com".into(),
+                qtype,
+                qclass: DnsClass::In,
+            };
+            let mut response = DnsPacket::query(1, question.clone());
+            zone.answer(&question, &mut response);
+            response
+        };
+        let response = ask(DnsType::Unknown(39));
+        assert_eq!(
+            response.answers[0].rdata,
+            RData::Unknown(b"\x03net\x00".to_vec())
+        );
+        let response = ask(DnsType::Unknown(40));
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(Zone::parse("$ORIGIN example.com.\n$TTL 60\nwww A 192.0.2.1\n", None).is_err());
+        assert!(Zone::parse("www A bogus\n", Some(&"example.com".into())).is_err());
+
+        let zones = Zones(vec![
+            Arc::new(Zone::parse(ZONE, None).unwrap()),
+            Arc::new(Zone::parse(&ZONE.replace("example.com.", "sub.example.com."), None).unwrap()),
+        ]);
+        assert_eq!(
+            zones
+                .find(&"x.sub.example.com".into())
+                .unwrap()
+                .apex
+                .as_str(),
+            "sub.example.com"
+        );
+        assert!(zones.find(&"example.org".into()).is_none());
+    }
+}
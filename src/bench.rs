@@ -0,0 +1,510 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::hint;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::authority::Zone;
+use crate::common::{DnsClass, DnsType};
+use crate::config::{parse_number, Config};
+use crate::edns::Edns;
+use crate::error::ConfigError;
+use crate::forward::UpstreamGroup;
+use crate::handler::{self, State};
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::stats::percentile;
+use crate::zone::ZoneFile;
+
+const USAGE: &str = "usage: dns-server bench --queries <file> [--target <addr:port>] [--qps <n>] [--duration <secs>] [--timeout <secs>]
+       dns-server bench --micro [--iterations <n>]";
+
+#[derive(PartialEq, Debug)]
+struct Options {
+    target: SocketAddr,
+    qps: u64, // 0 sends as fast as the socket allows
+    queries: PathBuf,
+    duration: Duration,
+    timeout: Duration,
+}
+
+#[derive(Default)]
+struct Report {
+    sent: u64,
+    completed: u64,
+    rcodes: BTreeMap<u8, u64>,
+    run_time: Duration,
+    latencies: Vec<Duration>,
+}
+
+// `dns-server bench`, a small dnsperf: replays a list of queries at a fixed
+// rate for a while and reports loss and latency percentiles.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    if args.first().map(String::as_str) == Some("--micro") {
+        let iterations = match &args[1..] {
+            [] => Ok(100_000),
+            [flag, value] if flag == "--iterations" => parse_number(flag, value),
+            _ => Err(ConfigError::UnknownFlag(args[1].clone())),
+        };
+        return match iterations
+            .map_err(|e| e.to_string())
+            .and_then(|n| micro(n as u32))
+        {
+            Ok(report) => {
+                print!("{}", report);
+                0
+            }
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                2
+            }
+        };
+    }
+    let options = match Options::from_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let questions = match fs::read_to_string(&options.queries)
+        .map_err(|e| e.to_string())
+        .and_then(|text| read_queries(&text))
+    {
+        Ok(questions) => questions,
+        Err(e) => {
+            eprintln!("{}: {}", options.queries.display(), e);
+            return 2;
+        }
+    };
+
+    println!(
+        "Sending {} queries/s to {} for {}s",
+        options.qps,
+        options.target,
+        options.duration.as_secs()
+    );
+    match run(&options, &questions) {
+        Ok(report) => {
+            print!("{}", report);
+            0
+        }
+        Err(e) => {
+            eprintln!("bench failed: {}", e);
+            1
+        }
+    }
+}
+
+impl Options {
+    fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut target = SocketAddr::from(([127, 0, 0, 1], 2053));
+        let mut qps = 1000;
+        let mut queries = None;
+        let mut duration = Duration::from_secs(10);
+        let mut timeout = Duration::from_secs(1);
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| ConfigError::MissingValue(flag.clone()))?;
+            match flag.as_str() {
+                "--target" => {
+                    target = value
+                        .parse()
+                        .map_err(|_| ConfigError::InvalidValue(flag.clone(), value))?
+                }
+                "--qps" => qps = parse_number(&flag, &value)?,
+                "--queries" => queries = Some(PathBuf::from(value)),
+                "--duration" => duration = Duration::from_secs(parse_number(&flag, &value)?),
+                "--timeout" => timeout = Duration::from_secs(parse_number(&flag, &value)?),
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+
+        Ok(Options {
+            target,
+            qps,
+            queries: queries.ok_or_else(|| ConfigError::MissingValue("--queries".to_string()))?,
+            duration,
+            timeout,
+        })
+    }
+}
+
+// dnsperf's input format: one `name [type]` per line, `;` starts a comment.
+fn read_queries(text: &str) -> Result<Vec<DnsQuestion>, String> {
+    let mut questions = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        let qtype = match fields.next() {
+            Some(qtype) => qtype
+                .parse()
+                .map_err(|_| format!("line {}: unknown type {}", i + 1, qtype))?,
+            None => DnsType::A,
+        };
+        questions.push(DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        });
+    }
+    if questions.is_empty() {
+        return Err("no queries".to_string());
+    }
+    Ok(questions)
+}
+
+// `dns-server bench --micro`: times each step of answering from the cache
+// and from a 50,000-record zone in-process, so changes to the hot path can
+// be measured without a load generator. Sockets, queues and logging are
+// left to `bench --queries` against a running server.
+fn micro(iterations: u32) -> Result<String, String> {
+    let question = DnsQuestion {
+        qname: "www.example.com".into(),
+        qtype: DnsType::A,
+        qclass: DnsClass::In,
+    };
+    let mut query = DnsPacket::query(1, question.clone());
+    query.edns = Some(Edns::new(1232));
+    let mut response = query.clone();
+    response.header.flip_qr();
+    for last in 1..=4 {
+        response.answers.push(DnsAnswer::new(
+            question.qname.clone(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([192, 0, 2, last]),
+        ));
+    }
+    let response_bytes = response.to_bytes();
+
+    // Forwarding makes the cache plugin answer; the upstream is never asked.
+    let config = Config {
+        upstreams: vec![UpstreamGroup::parse("bench=192.0.2.53").unwrap()],
+        forward_zones: vec![(".".into(), "bench".to_string())],
+        ..Config::default()
+    };
+    let state = State::default();
+    state.cache.insert(&question, &response);
+    let client = IpAddr::from([127, 0, 0, 1]);
+    let query_bytes = query.to_bytes();
+
+    let mut report = String::new();
+    let mut time = |name: &str, step: &mut dyn FnMut() -> bool| -> Result<(), String> {
+        let start = Instant::now();
+        for _ in 0..iterations.max(1) {
+            if !step() {
+                return Err(format!("{} failed", name));
+            }
+        }
+        let per_op = start.elapsed() / iterations.max(1);
+        report.push_str(&format!("  {:<20} {:>9} ns/op\n", name, per_op.as_nanos()));
+        Ok(())
+    };
+    time("parse", &mut || {
+        DnsPacket::try_from(hint::black_box(response_bytes.as_slice())).is_ok()
+    })?;
+    time("serialise", &mut || {
+        !hint::black_box(&response).to_bytes().is_empty()
+    })?;
+    time("serialise in full", &mut || {
+        !hint::black_box(&response).encode(false).0.is_empty()
+    })?;
+    time("cache lookup", &mut || {
+        state.cache.get(hint::black_box(&question)).is_some()
+    })?;
+    time("handle cache hit", &mut || {
+        let query = DnsPacket::try_from(query_bytes.as_slice()).unwrap();
+        let response = handler::handle(query, client, None, &config, &state);
+        !response.answers.is_empty()
+    })?;
+    let zone = ZoneFile::parse(
+        "$ORIGIN example.com.\n\
+         $TTL 300\n\
+         @ SOA ns1 hostmaster 1 2h 15m 2w 5m\n\
+         @ NS ns1\n\
+         @ MX 10 mail\n\
+         ns1 A 192.0.2.1\n\
+         mail A 192.0.2.25\n\
+         $GENERATE 0-49999 host-$ AAAA 2001:db8::${0,4,x}\n",
+        None,
+    );
+    let zone = Zone::from_file(zone)?;
+    let host = DnsQuestion {
+        qname: "host-31337.example.com".into(),
+        qtype: DnsType::Aaaa,
+        qclass: DnsClass::In,
+    };
+    time("zone lookup", &mut || {
+        let mut response = DnsPacket::query(1, host.clone());
+        zone.answer(hint::black_box(&host), &mut response);
+        !response.answers.is_empty()
+    })?;
+
+    // What name compression saves on a few typical responses.
+    let apex = DnsQuestion {
+        qname: "example.com".into(),
+        qtype: DnsType::Any,
+        qclass: DnsClass::In,
+    };
+    let mut apex_any = DnsPacket::query(1, apex.clone());
+    zone.answer(&apex, &mut apex_any);
+    report.push_str("compression:\n");
+    for (name, packet) in [("4 A records", &response), ("apex ANY", &apex_any)] {
+        let (bytes, saved) = packet.encode(true);
+        let full = bytes.len() + saved;
+        report.push_str(&format!(
+            "  {:<20} {:>5} bytes, {:>5} in full, {:>4.1}% saved\n",
+            name,
+            bytes.len(),
+            full,
+            saved as f64 * 100.0 / full as f64
+        ));
+    }
+    Ok(format!("{} iterations:\n{}", iterations.max(1), report))
+}
+
+fn run(options: &Options, questions: &[DnsQuestion]) -> io::Result<Report> {
+    let socket = UdpSocket::bind(match options.target {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    socket.connect(options.target)?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+    // Send time of every query still waiting for a response, keyed by ID.
+    let in_flight: Arc<Mutex<HashMap<u16, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+    let done = Arc::new(Mutex::new(false));
+
+    let receiver = {
+        let socket = socket.try_clone()?;
+        let in_flight = Arc::clone(&in_flight);
+        let done = Arc::clone(&done);
+        thread::spawn(move || receive(socket, in_flight, done))
+    };
+
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+    while start.elapsed() < options.duration {
+        if options.qps > 0 {
+            let due = start + Duration::from_secs_f64(sent as f64 / options.qps as f64);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+        let question = questions[sent as usize % questions.len()].clone();
+        let id = sent as u16;
+        let query = DnsPacket::query(id, question).to_bytes();
+        // An ID still in flight after 65536 queries is overwritten, and so lost.
+        in_flight.lock().unwrap().insert(id, Instant::now());
+        socket.send(&query)?;
+        sent += 1;
+    }
+
+    let deadline = Instant::now() + options.timeout;
+    while Instant::now() < deadline && !in_flight.lock().unwrap().is_empty() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    *done.lock().unwrap() = true;
+
+    let mut report = receiver.join().unwrap();
+    report.sent = sent;
+    report.run_time = start.elapsed();
+    report.latencies.sort();
+    Ok(report)
+}
+
+fn receive(
+    socket: UdpSocket,
+    in_flight: Arc<Mutex<HashMap<u16, Instant>>>,
+    done: Arc<Mutex<bool>>,
+) -> Report {
+    let mut report = Report::default();
+    let mut buf = [0; 4096];
+    while !*done.lock().unwrap() {
+        let Ok(len) = socket.recv(&mut buf) else {
+            continue;
+        };
+        if len < 12 {
+            continue;
+        }
+        let id = u16::from_be_bytes([buf[0], buf[1]]);
+        if let Some(sent) = in_flight.lock().unwrap().remove(&id) {
+            report.latencies.push(sent.elapsed());
+            report.completed += 1;
+            *report.rcodes.entry(buf[3] & 0x0F).or_default() += 1;
+        }
+    }
+    report
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ratio = |n: u64| match self.sent {
+            0 => 0.0,
+            sent => n as f64 * 100.0 / sent as f64,
+        };
+        let lost = self.sent - self.completed;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        writeln!(f, "Statistics:\n")?;
+        writeln!(f, "  Queries sent:         {}", self.sent)?;
+        writeln!(
+            f,
+            "  Queries completed:    {} ({:.2}%)",
+            self.completed,
+            ratio(self.completed)
+        )?;
+        writeln!(f, "  Queries lost:         {} ({:.2}%)", lost, ratio(lost))?;
+        let rcodes: Vec<String> = self
+            .rcodes
+            .iter()
+            .map(|(rcode, count)| {
+                let name = ResponseCode::try_from(*rcode)
+                    .map(|rcode| rcode.to_string())
+                    .unwrap_or_else(|_| format!("RCODE{}", rcode));
+                format!("{} {} ({:.2}%)", name, count, ratio(*count))
+            })
+            .collect();
+        writeln!(f, "  Response codes:       {}", rcodes.join(", "))?;
+        writeln!(
+            f,
+            "  Run time (s):         {:.3}",
+            self.run_time.as_secs_f64()
+        )?;
+        let qps = match self.run_time.as_secs_f64() {
+            secs if secs > 0.0 => self.completed as f64 / secs,
+            _ => 0.0,
+        };
+        writeln!(f, "  Queries per second:   {:.1}", qps)?;
+
+        if self.latencies.is_empty() {
+            return Ok(());
+        }
+        let average = self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32;
+        writeln!(
+            f,
+            "  Latency (ms):         min {:.3}, avg {:.3}, p50 {:.3}, p90 {:.3}, p99 {:.3}, max {:.3}",
+            ms(self.latencies[0]),
+            ms(average),
+            ms(percentile(&self.latencies, 50.0)),
+            ms(percentile(&self.latencies, 90.0)),
+            ms(percentile(&self.latencies, 99.0)),
+            ms(*self.latencies.last().unwrap())
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_options() {
+        let options = Options::from_args(args(&[
+            "--target",
+            "[::1]:53",
+            "--qps",
+            "50000",
+            "--queries",
+            "names.txt",
+        ]))
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                target: "[::1]:53".parse().unwrap(),
+                qps: 50000,
+                queries: PathBuf::from("names.txt"),
+                duration: Duration::from_secs(10),
+                timeout: Duration::from_secs(1),
+            }
+        );
+        assert_eq!(
+            Options::from_args(args(&["--qps", "10"])),
+            Err(ConfigError::MissingValue("--queries".to_string()))
+        );
+        assert!(Options::from_args(args(&["--target", "localhost", "--queries", "q"])).is_err());
+    }
+
+    #[test]
+    fn test_micro() {
+        let report = micro(10).unwrap();
+        for step in [
+            "parse",
+            "serialise",
+            "serialise in full",
+            "cache lookup",
+            "handle cache hit",
+            "zone lookup",
+        ] {
+            assert!(report.contains(&format!("  {} ", step)), "{}", report);
+        }
+        assert!(report.contains("  apex ANY "), "{}", report);
+    }
+
+    #[test]
+    fn test_read_queries() {
+        let questions = read_queries("; names\nexample.com\n\nexample.org AAAA ; v6\n").unwrap();
+        assert_eq!(questions.len(), 2);
+        assert_eq!(questions[0].qtype, DnsType::A);
+        assert_eq!(questions[1].qname.as_str(), "example.org");
+        assert_eq!(questions[1].qtype, DnsType::Aaaa);
+        assert_eq!(
+            read_queries("example.com BOGUS").map(|q| q.len()),
+            Err("line 1: unknown type BOGUS".to_string())
+        );
+        assert!(read_queries("; nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_run_against_echo_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            // Drop every tenth query to exercise loss accounting.
+            for i in 0.. {
+                let Ok((len, peer)) = server.recv_from(&mut buf) else {
+                    return;
+                };
+                if i % 10 != 9 {
+                    buf[2] |= 0x80;
+                    let _ = server.send_to(&buf[..len], peer);
+                }
+            }
+        });
+
+        let options = Options {
+            target,
+            qps: 200,
+            queries: PathBuf::new(),
+            duration: Duration::from_millis(500),
+            timeout: Duration::from_millis(200),
+        };
+        let report = run(&options, &read_queries("example.com").unwrap()).unwrap();
+        assert!(report.sent >= 90);
+        assert_eq!(report.sent - report.completed, report.sent / 10);
+        assert_eq!(report.rcodes.get(&0), Some(&report.completed));
+        assert_eq!(report.latencies.len() as u64, report.completed);
+        assert!(report.to_string().contains("Response codes:       NOERROR"));
+    }
+}
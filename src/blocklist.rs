@@ -0,0 +1,610 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::Config;
+use crate::handler::State;
+use crate::header::ResponseCode;
+use crate::http::{self, Url};
+use crate::packet::DnsPacket;
+use crate::pattern::{self, PatternSet};
+use crate::question::DnsQuestion;
+use crate::zone;
+
+// Short, so a name taken off a list works again soon after.
+const TTL: i32 = 60;
+// How often a list from a URL is fetched again, unless it says `refresh=`,
+// and how soon after a failed fetch.
+const DEFAULT_REFRESH: Duration = Duration::from_secs(24 * 3600);
+const RETRY: Duration = Duration::from_secs(300);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A named list of blocked domains, each blocking itself and every name
+// below it. `--blocklist name=path` reads one domain per line, or hosts file
+// lines such as `0.0.0.0 ads.example`; `#` starts a comment. A domain with
+// a `*` is a wildcard (`*.ads.example` is every name below ads.example but
+// not itself) and a line between slashes a regex (`/^ad[0-9]+\./`), see
+// PatternSet. `--allowlist` reads the same, for names never to block.
+pub(crate) struct Blocklist {
+    pub(crate) name: String,
+    pub(crate) allow: bool,
+    // Overrides `--block-style` for this list.
+    pub(crate) style: Option<BlockStyle>,
+    // When the list is in force; always without one.
+    pub(crate) schedule: Option<String>,
+    // When the file was written or the URL last fetched with changes.
+    pub(crate) updated: Option<SystemTime>,
+    // The validators of a fetched list, sent with the next fetch.
+    etag: Option<String>,
+    last_modified: Option<String>,
+    domains: HashSet<String>,
+    patterns: PatternSet,
+}
+
+impl Blocklist {
+    pub(crate) fn load(name: &str, path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut list =
+            Blocklist::parse(name, &text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        list.updated = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        Ok(list)
+    }
+
+    pub(crate) fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut domains = HashSet::new();
+        let mut regexes = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            // `/regex/`, perhaps with a comment after.
+            if let Some((regex, _)) = line
+                .strip_prefix('/')
+                .and_then(|rest| rest.rsplit_once('/'))
+                .filter(|(regex, rest)| {
+                    !regex.is_empty() && matches!(rest.trim().chars().next(), None | Some('#'))
+                })
+            {
+                regexes.push(regex.to_string());
+                continue;
+            }
+            let line = line.split('#').next().unwrap_or_default();
+            let domain = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [domain] => domain,
+                [address, domain, ..] if address.parse::<std::net::IpAddr>().is_ok() => domain,
+                _ => continue,
+            };
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            // Hosts files map these to themselves; blocking them would only
+            // break things.
+            if matches!(domain.as_str(), "" | "localhost" | "localhost.localdomain") {
+                continue;
+            }
+            match domain.contains('*') {
+                true => regexes.push(pattern::wildcard(&domain)),
+                false => {
+                    domains.insert(domain);
+                }
+            }
+        }
+        Ok(Blocklist {
+            name: name.to_string(),
+            allow: false,
+            style: None,
+            schedule: None,
+            updated: None,
+            etag: None,
+            last_modified: None,
+            domains,
+            patterns: PatternSet::new(&regexes)?,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.domains.len() + self.patterns.len()
+    }
+
+    // True when the name or one of its parents is listed, or a wildcard or
+    // regex matches it.
+    pub(crate) fn matches(&self, name: &Name) -> bool {
+        let name = name.as_str().to_ascii_lowercase();
+        let mut suffix = name.as_str();
+        loop {
+            if self.domains.contains(suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None => return self.patterns.is_match(&name),
+            }
+        }
+    }
+}
+
+// How a blocked name is answered, from `--block-style` or a list's
+// `style=`: some clients retry NXDOMAIN elsewhere or take REFUSED as a
+// broken server, and a block page needs an address to show.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub(crate) enum BlockStyle {
+    #[default]
+    NxDomain,
+    // 0.0.0.0 and ::.
+    Null,
+    Refused,
+    // Addresses of a block page, `/` separated.
+    Address(Vec<IpAddr>),
+}
+
+impl BlockStyle {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        match spec.to_ascii_lowercase().as_str() {
+            "nxdomain" => Ok(BlockStyle::NxDomain),
+            "null" | "0.0.0.0" => Ok(BlockStyle::Null),
+            "refused" => Ok(BlockStyle::Refused),
+            _ => spec
+                .split('/')
+                .map(|addr| {
+                    addr.parse()
+                        .map_err(|_| format!("bad block style {:?}", spec))
+                })
+                .collect::<Result<_, _>>()
+                .map(BlockStyle::Address),
+        }
+    }
+
+    // Addresses answer their own family's questions, with NODATA for the
+    // rest.
+    pub(crate) fn answer(&self, question: &DnsQuestion, response: &mut DnsPacket) {
+        let addresses = match self {
+            BlockStyle::NxDomain => {
+                response.header.rcode = ResponseCode::NxDomain;
+                return;
+            }
+            BlockStyle::Refused => {
+                response.header.rcode = ResponseCode::Refused;
+                return;
+            }
+            BlockStyle::Null => vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ],
+            BlockStyle::Address(addresses) => addresses.clone(),
+        };
+        let answers = addresses.into_iter().filter_map(|addr| {
+            let (qtype, rdata) = match addr {
+                IpAddr::V4(ip) => (DnsType::A, RData::A(ip.octets())),
+                IpAddr::V6(ip) => (DnsType::Aaaa, RData::Aaaa(ip.octets())),
+            };
+            (question.qtype == qtype || question.qtype == DnsType::Any)
+                .then(|| DnsAnswer::new(question.qname.clone(), qtype, DnsClass::In, TTL, rdata))
+        });
+        response.answers.extend(answers);
+    }
+}
+
+// `name=path[,style=...]` or `name=http://...[,refresh=6h][,style=...]`
+// from `--blocklist`, or `--allowlist` without the style; either may add
+// `schedule=name` (see Schedule). There is no TLS: lists served over HTTPS
+// are fetched through a local proxy.
+#[derive(PartialEq, Debug)]
+pub(crate) struct BlocklistSpec {
+    pub(crate) name: String,
+    pub(crate) allow: bool,
+    pub(crate) source: Source,
+    pub(crate) style: Option<BlockStyle>,
+    pub(crate) schedule: Option<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) enum Source {
+    File(PathBuf),
+    Url { url: Url, refresh: Duration },
+}
+
+pub(crate) fn parse_spec(spec: &str, allow: bool) -> Result<BlocklistSpec, String> {
+    let mut parts = spec.split(',');
+    let (name, location) = match parts.next().unwrap_or_default().split_once('=') {
+        Some((name, location)) if !name.is_empty() && !location.is_empty() => (name, location),
+        _ => return Err(format!("expected name=path, got {:?}", spec)),
+    };
+    let mut source = if location.starts_with("http://") {
+        let url = Url::parse(location).map_err(|e| e.to_string())?;
+        Source::Url {
+            url,
+            refresh: DEFAULT_REFRESH,
+        }
+    } else if location.starts_with("https://") {
+        return Err(format!("no TLS for {}: fetch it through a proxy", location));
+    } else {
+        Source::File(PathBuf::from(location))
+    };
+    let (mut style, mut schedule) = (None, None);
+    for part in parts {
+        match (part.split_once('='), &mut source) {
+            (Some(("style", spec)), _) if !allow => style = Some(BlockStyle::parse(spec)?),
+            (Some(("schedule", name)), _) if !name.is_empty() => schedule = Some(name.to_string()),
+            (Some(("refresh", every)), Source::Url { refresh, .. }) => {
+                *refresh = zone::parse_ttl(every)
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .map(|seconds| Duration::from_secs(seconds.into()))
+                    .ok_or_else(|| format!("bad refresh {:?}", every))?
+            }
+            _ => return Err(format!("unknown blocklist option {:?}", part)),
+        }
+    }
+    Ok(BlocklistSpec {
+        name: name.to_string(),
+        allow,
+        source,
+        style,
+        schedule,
+    })
+}
+
+// Every configured list, failing on the first file that can't be read.
+// Lists from URLs are kept from `current`, or start empty until fetched.
+pub(crate) fn load_all(
+    config: &Config,
+    current: &[Arc<Blocklist>],
+) -> Result<Vec<Arc<Blocklist>>, String> {
+    config
+        .blocklists
+        .iter()
+        .map(|spec| {
+            let mut list = match &spec.source {
+                Source::File(path) => Blocklist::load(&spec.name, path)?,
+                Source::Url { .. } => match current.iter().find(|list| list.name == spec.name) {
+                    Some(list) => return Ok(Arc::clone(list)),
+                    None => Blocklist::parse(&spec.name, "")?,
+                },
+            };
+            list.allow = spec.allow;
+            list.style = spec.style.clone();
+            list.schedule = spec.schedule.clone();
+            Ok(Arc::new(list))
+        })
+        .collect()
+}
+
+// Fetches the lists from URLs now and then each refresh, swapping in a new
+// copy only when it has changed and looks whole.
+pub(crate) fn update(config: Arc<Config>, state: Arc<State>) {
+    if !config
+        .blocklists
+        .iter()
+        .any(|spec| matches!(spec.source, Source::Url { .. }))
+    {
+        return;
+    }
+    thread::spawn(move || {
+        let mut due: HashMap<String, Instant> = HashMap::new();
+        loop {
+            for spec in &config.blocklists {
+                let Source::Url { url, refresh } = &spec.source else {
+                    continue;
+                };
+                if matches!(due.get(&spec.name), Some(at) if *at > Instant::now()) {
+                    continue;
+                }
+                let next = match fetch(spec, url, &state) {
+                    Ok(Some(count)) => {
+                        println!("Blocklist {}: {} domains from {}", spec.name, count, url);
+                        *refresh
+                    }
+                    Ok(None) => *refresh,
+                    Err(e) => {
+                        eprintln!("Failed to update blocklist {}: {}", spec.name, e);
+                        RETRY.min(*refresh)
+                    }
+                };
+                due.insert(spec.name.clone(), Instant::now() + next);
+            }
+            let next = due.values().min().copied().unwrap_or_else(Instant::now);
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+        }
+    });
+}
+
+// Fetches one list, returning its size when it changed and None when the
+// server says it hasn't.
+fn fetch(spec: &BlocklistSpec, url: &Url, state: &State) -> Result<Option<usize>, String> {
+    let lists = state.blocklists();
+    let current = lists.iter().find(|list| list.name == spec.name);
+    let response = http::get_if_changed(
+        url,
+        current.and_then(|list| list.etag.as_deref()),
+        current.and_then(|list| list.last_modified.as_deref()),
+        HTTP_TIMEOUT,
+    )
+    .map_err(|e| e.to_string())?;
+    if response.status == 304 {
+        return Ok(None);
+    }
+
+    let text = std::str::from_utf8(&response.body).map_err(|_| "not UTF-8".to_string())?;
+    let mut list = Blocklist::parse(&spec.name, text)?;
+    // An error page or a cut-off download would unblock everything.
+    let previous = current.map_or(0, |list| list.len());
+    if list.len() == 0 {
+        return Err("no domains".to_string());
+    }
+    if list.len() < previous / 10 {
+        return Err(format!("{} domains, down from {}", list.len(), previous));
+    }
+    list.allow = spec.allow;
+    list.style = spec.style.clone();
+    list.schedule = spec.schedule.clone();
+    list.updated = Some(SystemTime::now());
+    list.etag = response.header("etag").map(str::to_string);
+    list.last_modified = response.header("last-modified").map(str::to_string);
+    let count = list.len();
+
+    let mut lists = state.blocklists.write().unwrap();
+    let mut list = Some(Arc::new(list));
+    let swapped = lists
+        .iter()
+        .map(|other| match other.name == spec.name {
+            true => list.take().unwrap_or_else(|| Arc::clone(other)),
+            false => Arc::clone(other),
+        })
+        .collect();
+    *lists = Arc::new(swapped);
+    Ok(Some(count))
+}
+
+// The size of each list and when it last changed, as in `ctl stats`.
+pub(crate) fn report(lists: &[Arc<Blocklist>]) -> String {
+    lists
+        .iter()
+        .map(|list| {
+            let updated = list
+                .updated
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            format!(
+                "blocklist.{}.entries={}\nblocklist.{}.updated={}\n",
+                list.name,
+                list.len(),
+                list.name,
+                updated.as_secs()
+            )
+        })
+        .collect()
+}
+
+// Queries each list blocked since the start.
+#[derive(Default)]
+pub(crate) struct Blocked {
+    counts: Mutex<HashMap<String, u64>>,
+}
+
+impl Blocked {
+    pub(crate) fn count(&self, list: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.get_mut(list) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(list.to_string(), 1);
+            }
+        }
+    }
+
+    // By list name.
+    pub(crate) fn counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(list, count)| (list.clone(), *count))
+            .collect();
+        counts.sort();
+        counts
+    }
+
+    // One key=value line per list, as in `ctl stats`.
+    pub(crate) fn report(&self) -> String {
+        self.counts()
+            .iter()
+            .map(|(list, count)| format!("blocked.{}={}\n", list, count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_blocks() {
+        let list = Blocklist::parse(
+            "ads",
+            "# ad servers\nads.example\n0.0.0.0 Tracker.Example. # hosts style\n\
+             127.0.0.1 localhost\n\n",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(list.matches(&"ads.example".into()));
+        assert!(list.matches(&"x.y.ADS.example".into()));
+        assert!(list.matches(&"tracker.example".into()));
+        assert!(!list.matches(&"example".into()));
+        assert!(!list.matches(&"notads.example".into()));
+        assert!(!list.matches(&"localhost".into()));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let list = Blocklist::parse(
+            "ads",
+            "*.doubleclick.example\n0.0.0.0 ad*.cdn.example\n/^(ads?|track)[0-9]*\\./ # numbered\n",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 3);
+        assert!(list.matches(&"stats.doubleclick.example".into()));
+        assert!(!list.matches(&"doubleclick.example".into()));
+        assert!(list.matches(&"ad7.cdn.example".into()));
+        assert!(list.matches(&"Ads12.example".into()));
+        assert!(list.matches(&"track.example".into()));
+        assert!(!list.matches(&"www.ads.example".into()));
+        assert!(!list.matches(&"tracker.example".into()));
+
+        let error = Blocklist::parse("ads", "ads.example\n/(ads/\n")
+            .err()
+            .unwrap();
+        assert!(error.contains("(ads"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(
+            parse_spec("ads=/etc/dns/ads.txt", false).unwrap(),
+            BlocklistSpec {
+                name: "ads".to_string(),
+                allow: false,
+                source: Source::File(PathBuf::from("/etc/dns/ads.txt")),
+                style: None,
+                schedule: None,
+            }
+        );
+        assert_eq!(
+            parse_spec("adult=adult.txt,style=192.0.2.80/2001:db8::80", false)
+                .unwrap()
+                .style,
+            Some(BlockStyle::Address(vec![
+                "192.0.2.80".parse().unwrap(),
+                "2001:db8::80".parse().unwrap()
+            ]))
+        );
+        assert_eq!(
+            parse_spec("ads=http://127.0.0.1:3128/ads.txt,refresh=6h", false)
+                .unwrap()
+                .source,
+            Source::Url {
+                url: Url::parse("http://127.0.0.1:3128/ads.txt").unwrap(),
+                refresh: Duration::from_secs(6 * 3600),
+            }
+        );
+        for bad in [
+            "ads",
+            "=ads.txt",
+            "ads=ads.txt,style=sometimes",
+            "ads=ads.txt,ttl=5",
+            "ads=ads.txt,refresh=1h",
+            "ads=http://127.0.0.1/ads.txt,refresh=0",
+            "ads=https://example.com/ads.txt",
+        ] {
+            assert!(parse_spec(bad, false).is_err(), "{}", bad);
+        }
+        let list = parse_spec("ok=ok.txt", true).unwrap();
+        assert!(list.allow);
+        assert!(parse_spec("ok=ok.txt,style=null", true).is_err());
+    }
+
+    // Serves the body and ETag in `list`, or a 304 to a request that has
+    // that ETag already.
+    fn fake_server(list: Arc<Mutex<(&'static str, &'static str)>>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ads.txt", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut request = String::new();
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                let (body, etag) = *list.lock().unwrap();
+                if request.contains(&format!("If-None-Match: {}\r\n", etag)) {
+                    write!(&stream, "HTTP/1.1 304 Not Modified\r\n\r\n").unwrap();
+                    continue;
+                }
+                write!(
+                    &stream,
+                    "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n\r\n{}",
+                    etag,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        Url::parse(&url).unwrap()
+    }
+
+    #[test]
+    fn test_update() {
+        let served = Arc::new(Mutex::new(("ads.example\ntracker.example\n", "\"v1\"")));
+        let url = fake_server(Arc::clone(&served));
+        let config = Config {
+            blocklists: vec![parse_spec(&format!("ads={},style=refused", url), false).unwrap()],
+            ..Config::default()
+        };
+        let state = State::default();
+        let lists = load_all(&config, &[]).unwrap();
+        assert_eq!((lists[0].len(), lists[0].updated), (0, None));
+        *state.blocklists.write().unwrap() = Arc::new(lists);
+
+        let spec = &config.blocklists[0];
+        assert_eq!(fetch(spec, &url, &state), Ok(Some(2)));
+        let list = Arc::clone(&state.blocklists()[0]);
+        assert!(list.matches(&"ads.example".into()));
+        assert_eq!(list.style, Some(BlockStyle::Refused));
+        assert!(list.updated.is_some());
+        assert!(report(&state.blocklists()).starts_with("blocklist.ads.entries=2\n"));
+        // Unchanged, and kept across a reload.
+        assert_eq!(fetch(spec, &url, &state), Ok(None));
+        let lists = load_all(&config, &state.blocklists()).unwrap();
+        assert!(Arc::ptr_eq(&lists[0], &list));
+
+        // An empty list is taken for a broken download.
+        *served.lock().unwrap() = ("# moved\n", "\"v2\"");
+        assert!(fetch(spec, &url, &state).is_err());
+        assert!(Arc::ptr_eq(&state.blocklists()[0], &list));
+
+        *served.lock().unwrap() = ("ads.example\ntracker.example\nmore.example\n", "\"v3\"");
+        assert_eq!(fetch(spec, &url, &state), Ok(Some(3)));
+        assert!(state.blocklists()[0].matches(&"more.example".into()));
+    }
+
+    #[test]
+    fn test_block_style() {
+        let ask = |style: &str, qtype| {
+            let question = DnsQuestion {
+                qname: "ads.example".into(),
+                qtype,
+                qclass: DnsClass::In,
+            };
+            let mut response = DnsPacket::query(1, question.clone());
+            BlockStyle::parse(style)
+                .unwrap()
+                .answer(&question, &mut response);
+            response
+        };
+        let response = ask("NXDOMAIN", DnsType::A);
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+        assert!(response.answers.is_empty());
+        assert_eq!(
+            ask("refused", DnsType::A).header.rcode,
+            ResponseCode::Refused
+        );
+
+        let response = ask("0.0.0.0", DnsType::Aaaa);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert_eq!(response.answers[0].rdata, RData::Aaaa([0; 16]));
+        assert_eq!(ask("null", DnsType::Any).answers.len(), 2);
+
+        let response = ask("192.0.2.80", DnsType::A);
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+        assert_eq!(response.answers[0].ttl, TTL);
+        // NODATA for a family without an address.
+        let response = ask("192.0.2.80", DnsType::Aaaa);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+        assert!(BlockStyle::parse("192.0.2.80/nowhere").is_err());
+    }
+}
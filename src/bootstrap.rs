@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::common::Name;
+use crate::forward::UpstreamGroup;
+use crate::resolver::Resolver;
+
+// How long looked-up addresses are used before they are looked up again,
+// so that an upstream moving to new addresses is followed.
+const REFRESH: Duration = Duration::from_secs(300);
+// After a failed lookup the old addresses, if any, are kept this long
+// before trying again.
+const RETRY: Duration = Duration::from_secs(30);
+
+// Addresses of upstream servers given by name. They are looked up through
+// the group's bootstrap servers, never through this server, which may well
+// be forwarding to the very names being looked up.
+#[derive(Default)]
+pub(crate) struct Bootstrap {
+    hosts: Mutex<HashMap<String, Host>>,
+}
+
+struct Host {
+    addrs: Vec<IpAddr>,
+    refresh: Instant,
+}
+
+impl Bootstrap {
+    // The group's servers: its addresses, then those its names have now.
+    // A name that can't be looked up and never could adds nothing.
+    pub(crate) fn servers(&self, group: &UpstreamGroup, deadline: Instant) -> Vec<SocketAddr> {
+        let mut servers = group.servers.clone();
+        for (host, port) in &group.hosts {
+            let addrs = self.addrs(group, host, deadline);
+            servers.extend(
+                addrs
+                    .into_iter()
+                    .filter(|ip| !matches!(group.source, Some(s) if s.is_ipv4() != ip.is_ipv4()))
+                    .map(|ip| SocketAddr::new(ip, *port)),
+            );
+        }
+        servers
+    }
+
+    fn addrs(&self, group: &UpstreamGroup, host: &Name, deadline: Instant) -> Vec<IpAddr> {
+        let key = host.as_str().to_ascii_lowercase();
+        let now = Instant::now();
+        let stale = match self.hosts.lock().unwrap().get(&key) {
+            Some(known) if known.refresh > now => return known.addrs.clone(),
+            Some(known) => known.addrs.clone(),
+            None => Vec::new(),
+        };
+
+        // Not holding the lock: other groups' queries shouldn't wait on
+        // this lookup, and two racing lookups of a name are harmless.
+        let mut resolver = Resolver::new(group.bootstrap.clone()).with_deadline(deadline);
+        if let Some(source) = group.source {
+            resolver = resolver.with_source(source);
+        }
+        let known = match resolver.lookup_ip(host.as_str()) {
+            Ok(addrs) if !addrs.is_empty() => Host {
+                addrs,
+                refresh: now + REFRESH,
+            },
+            _ => Host {
+                addrs: stale,
+                refresh: now + RETRY,
+            },
+        };
+        let addrs = known.addrs.clone();
+        self.hosts.lock().unwrap().insert(key, known);
+        addrs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::common::{DnsClass, DnsType};
+    use crate::packet::DnsPacket;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_servers() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let bootstrap_addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut response = DnsPacket::try_from(&buf[..size]).unwrap();
+                response.header.flip_qr();
+                if response.questions[0].qtype == DnsType::A {
+                    response.add_answer(DnsAnswer::new(
+                        response.questions[0].qname.clone(),
+                        DnsType::A,
+                        DnsClass::In,
+                        300,
+                        RData::A([192, 0, 2, 53]),
+                    ));
+                }
+                socket.send_to(&response.to_bytes(), peer).unwrap();
+            }
+        });
+
+        let group = UpstreamGroup::parse(&format!(
+            "wan=10.0.0.53,dns.test:853,bootstrap={}",
+            bootstrap_addr
+        ))
+        .unwrap();
+        let bootstrap = Bootstrap::default();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let expected: Vec<SocketAddr> = vec![
+            "10.0.0.53:53".parse().unwrap(),
+            "192.0.2.53:853".parse().unwrap(),
+        ];
+        assert_eq!(bootstrap.servers(&group, deadline), expected);
+        assert_eq!(bootstrap.servers(&group, deadline), expected);
+        // One A and one AAAA query; the second call used what they found.
+        assert_eq!(queries.load(Ordering::SeqCst), 2);
+
+        // Once due for a refresh that fails, the old addresses stay.
+        let unreachable = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let group = UpstreamGroup {
+            bootstrap: vec![unreachable.local_addr().unwrap()],
+            ..group
+        };
+        bootstrap
+            .hosts
+            .lock()
+            .unwrap()
+            .get_mut("dns.test")
+            .unwrap()
+            .refresh = Instant::now();
+        let deadline = Instant::now() + Duration::from_millis(300);
+        assert_eq!(bootstrap.servers(&group, deadline), expected);
+    }
+}
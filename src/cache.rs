@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::clock::SharedClock;
+use crate::common::{DnsClass, DnsType};
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const MAX_ENTRIES: usize = 10_000;
+// A day, as in Unbound's cache-max-ttl.
+pub(crate) const DEFAULT_MAX_TTL: u32 = 86400;
+
+// Responses from upstreams and the recursor, kept for their TTL so that
+// repeated questions are answered without another lookup.
+#[derive(Default)]
+pub(crate) struct Cache {
+    entries: Mutex<HashMap<CacheKey, Entry>>,
+    // Lookups answered and not answered from the cache, since the start.
+    hits: AtomicU64,
+    misses: AtomicU64,
+    clock: SharedClock,
+}
+
+// Lowercased name, type and class.
+type CacheKey = (String, u16, u16);
+
+struct Entry {
+    rcode: ResponseCode,
+    answers: Vec<DnsAnswer>,
+    authorities: Vec<DnsAnswer>,
+    additionals: Vec<DnsAnswer>,
+    inserted: Instant,
+    expires: Instant,
+}
+
+impl Entry {
+    fn response(&self, question: DnsQuestion, now: Instant) -> DnsPacket {
+        let elapsed = now.duration_since(self.inserted).as_secs();
+        let elapsed = elapsed.min(i32::MAX as u64) as i32;
+        let remaining = |records: &[DnsAnswer]| {
+            let mut records = records.to_vec();
+            for record in &mut records {
+                record.ttl = record.ttl.saturating_sub(elapsed).max(0);
+            }
+            records
+        };
+        let mut response = DnsPacket::query(0, question);
+        response.header.rcode = self.rcode;
+        response.answers = remaining(&self.answers);
+        response.authorities = remaining(&self.authorities);
+        response.additionals = remaining(&self.additionals);
+        response
+    }
+}
+
+// `--min-ttl` and `--max-ttl`, applied to upstream records before they are
+// cached or sent, so clients and the cache agree on how long they live.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct TtlBounds {
+    pub(crate) min: u32,
+    pub(crate) max: u32,
+}
+
+impl Default for TtlBounds {
+    fn default() -> Self {
+        TtlBounds {
+            min: 0,
+            max: DEFAULT_MAX_TTL,
+        }
+    }
+}
+
+impl TtlBounds {
+    // Negative answers still live no longer than the SOA's minimum field
+    // (RFC 2308 section 5), whatever `--min-ttl` says.
+    pub(crate) fn apply(&self, response: &mut DnsPacket) {
+        let (min, max) = (self.min as i32, self.max as i32);
+        let sections = [
+            &mut response.answers,
+            &mut response.authorities,
+            &mut response.additionals,
+        ];
+        for record in sections.into_iter().flatten() {
+            record.ttl = record.ttl.clamp(min, max);
+        }
+        for record in &mut response.authorities {
+            if let RData::Soa { minimum, .. } = record.rdata {
+                record.ttl = record.ttl.min(minimum.min(i32::MAX as u32) as i32);
+            }
+        }
+    }
+}
+
+fn key(question: &DnsQuestion) -> CacheKey {
+    (
+        question.qname.as_str().to_ascii_lowercase(),
+        question.qtype.code(),
+        question.qclass as u16,
+    )
+}
+
+impl Cache {
+    #[cfg(test)]
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Cache {
+            clock,
+            ..Cache::default()
+        }
+    }
+
+    // A response with the cached sections, if the entry hasn't expired.
+    // TTLs count down from when the entry was inserted; since entries
+    // expire with their shortest TTL, none reaches zero before then.
+    pub(crate) fn get(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let now = self.clock.now();
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(&key(question))
+            .filter(|entry| entry.expires > now);
+        let counter = if entry.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Some(entry?.response(question.clone(), now))
+    }
+
+    // Positive answers live for their smallest TTL; NXDOMAIN and NODATA
+    // for the SOA's negative TTL (RFC 2308 section 5), and aren't cached
+    // without one. Failures aren't cached at all.
+    pub(crate) fn insert(&self, question: &DnsQuestion, response: &DnsPacket) {
+        let ttl = match response.header.rcode {
+            ResponseCode::NoError if !response.answers.is_empty() => {
+                response.answers.iter().map(|answer| answer.ttl).min()
+            }
+            ResponseCode::NoError | ResponseCode::NxDomain => negative_ttl(response),
+            _ => None,
+        };
+        let Some(ttl) = ttl.filter(|ttl| *ttl > 0) else {
+            return;
+        };
+
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(
+            key(question),
+            Entry {
+                rcode: response.header.rcode,
+                answers: response.answers.clone(),
+                authorities: response.authorities.clone(),
+                additionals: response.additionals.clone(),
+                inserted: now,
+                expires: now + Duration::from_secs(ttl as u64),
+            },
+        );
+    }
+
+    // Drops every entry and returns how many there were.
+    pub(crate) fn clear(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.clear();
+        count
+    }
+
+    // Every live entry as a response carrying its question, for handing
+    // the cache to the next process. Lookups aren't counted.
+    pub(crate) fn export(&self) -> Vec<DnsPacket> {
+        let now = self.clock.now();
+        let entries = self.entries.lock().unwrap();
+        let live = entries.iter().filter(|(_, entry)| entry.expires > now);
+        live.filter_map(|((name, qtype, qclass), entry)| {
+            let question = DnsQuestion {
+                qname: name.as_str().into(),
+                qtype: DnsType::from(*qtype),
+                qclass: DnsClass::try_from(*qclass).ok()?,
+            };
+            Some(entry.response(question, now))
+        })
+        .collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    // (hits, misses) since the start.
+    pub(crate) fn lookups(&self) -> (u64, u64) {
+        let hits = self.hits.load(Ordering::Relaxed);
+        (hits, self.misses.load(Ordering::Relaxed))
+    }
+
+    // One key=value line per counter, as in `ctl stats`.
+    pub(crate) fn report(&self) -> String {
+        let (hits, misses) = self.lookups();
+        format!(
+            "cache.hits={}\ncache.misses={}\ncache.entries={}\n",
+            hits,
+            misses,
+            self.len()
+        )
+    }
+}
+
+fn negative_ttl(response: &DnsPacket) -> Option<i32> {
+    response
+        .authorities
+        .iter()
+        .find_map(|record| match record.rdata {
+            RData::Soa { minimum, .. } if record.qtype == DnsType::Soa => {
+                Some(record.ttl.min(minimum.min(i32::MAX as u32) as i32))
+            }
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsQuestion {
+            qname: name.into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        }
+    }
+
+    fn response(rcode: ResponseCode, answers: Vec<DnsAnswer>, soa_ttl: Option<i32>) -> DnsPacket {
+        let mut response = DnsPacket::query(1, question("www.example.com"));
+        response.header.rcode = rcode;
+        response.answers = answers;
+        if let Some(ttl) = soa_ttl {
+            response.authorities.push(DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Soa,
+                DnsClass::In,
+                ttl,
+                RData::Soa {
+                    mname: "ns1.example.com".into(),
+                    rname: "hostmaster.example.com".into(),
+                    serial: 1,
+                    refresh: 7200,
+                    retry: 900,
+                    expire: 1209600,
+                    minimum: 60,
+                },
+            ));
+        }
+        response
+    }
+
+    fn a(ttl: i32) -> DnsAnswer {
+        DnsAnswer::new(
+            "www.example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            ttl,
+            RData::A([192, 0, 2, 80]),
+        )
+    }
+
+    #[test]
+    fn test_positive() {
+        let cache = Cache::default();
+        let q = question("www.example.com");
+        assert!(cache.get(&q).is_none());
+
+        cache.insert(
+            &q,
+            &response(ResponseCode::NoError, vec![a(300), a(30)], None),
+        );
+        let hit = cache.get(&question("WWW.Example.com")).unwrap();
+        assert_eq!(hit.answers.len(), 2);
+        assert_eq!(hit.header.rcode, ResponseCode::NoError);
+
+        // Zero TTLs and failures aren't kept.
+        let q = question("zero.example.com");
+        cache.insert(&q, &response(ResponseCode::NoError, vec![a(0)], None));
+        cache.insert(&q, &response(ResponseCode::ServFail, Vec::new(), None));
+        assert!(cache.get(&q).is_none());
+        assert_eq!(cache.lookups(), (1, 2));
+
+        assert_eq!(cache.clear(), 1);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_negative() {
+        let cache = Cache::default();
+        let q = question("missing.example.com");
+        cache.insert(&q, &response(ResponseCode::NxDomain, Vec::new(), None));
+        assert!(cache.get(&q).is_none());
+
+        cache.insert(
+            &q,
+            &response(ResponseCode::NxDomain, Vec::new(), Some(3600)),
+        );
+        let hit = cache.get(&q).unwrap();
+        assert_eq!(hit.header.rcode, ResponseCode::NxDomain);
+        assert_eq!(hit.authorities.len(), 1);
+    }
+
+    #[test]
+    fn test_ttl_bounds() {
+        let bounds = TtlBounds { min: 60, max: 3600 };
+        let mut positive = response(ResponseCode::NoError, vec![a(5), a(300), a(86400)], None);
+        bounds.apply(&mut positive);
+        let ttls: Vec<_> = positive.answers.iter().map(|r| r.ttl).collect();
+        assert_eq!(ttls, vec![60, 300, 3600]);
+
+        // The SOA of a negative answer is held to its minimum field, 60.
+        let bounds = TtlBounds {
+            min: 300,
+            max: 3600,
+        };
+        let mut negative = response(ResponseCode::NxDomain, Vec::new(), Some(7200));
+        bounds.apply(&mut negative);
+        assert_eq!(negative.authorities[0].ttl, 60);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let clock = ManualClock::new();
+        let cache = Cache::with_clock(clock.shared());
+        let q = question("www.example.com");
+        cache.insert(&q, &response(ResponseCode::NoError, vec![a(1)], None));
+        clock.advance(Duration::from_millis(999));
+        assert!(cache.get(&q).is_some());
+        clock.advance(Duration::from_millis(1));
+        assert!(cache.get(&q).is_none());
+
+        // A full cache makes room by dropping what has expired.
+        for i in 0..MAX_ENTRIES {
+            let q = question(&format!("{}.example.com", i));
+            cache.insert(&q, &response(ResponseCode::NoError, vec![a(60)], None));
+        }
+        cache.insert(&q, &response(ResponseCode::NoError, vec![a(60)], None));
+        assert!(cache.get(&q).is_none());
+        clock.advance(Duration::from_secs(60));
+        cache.insert(&q, &response(ResponseCode::NoError, vec![a(60)], None));
+        assert!(cache.get(&q).is_some());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_ttl_decrement() {
+        let clock = ManualClock::new();
+        let cache = Cache::with_clock(clock.shared());
+        let q = question("www.example.com");
+        cache.insert(
+            &q,
+            &response(ResponseCode::NoError, vec![a(300), a(30)], Some(3600)),
+        );
+        let ttls = |response: DnsPacket| -> Vec<i32> {
+            let records = response.answers.iter().chain(&response.authorities);
+            records.map(|record| record.ttl).collect()
+        };
+        assert_eq!(ttls(cache.get(&q).unwrap()), vec![300, 30, 3600]);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(ttls(cache.get(&q).unwrap()), vec![290, 20, 3590]);
+        // The last second before the entry expires still reports one.
+        clock.advance(Duration::from_secs(19));
+        assert_eq!(ttls(cache.get(&q).unwrap()), vec![271, 1, 3571]);
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(&q).is_none());
+
+        // Records with no TTL left aren't served from the cache, even
+        // alongside ones that have.
+        let q = question("zero.example.com");
+        cache.insert(
+            &q,
+            &response(ResponseCode::NoError, vec![a(300), a(0)], None),
+        );
+        assert!(cache.get(&q).is_none());
+    }
+
+    #[test]
+    fn test_negative_ttl_decrement() {
+        let clock = ManualClock::new();
+        let cache = Cache::with_clock(clock.shared());
+        let q = question("missing.example.com");
+        cache.insert(
+            &q,
+            &response(ResponseCode::NxDomain, Vec::new(), Some(3600)),
+        );
+        // Cached for the SOA minimum of 60, counting down from 3600.
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(cache.get(&q).unwrap().authorities[0].ttl, 3541);
+        clock.advance(Duration::from_secs(1));
+        assert!(cache.get(&q).is_none());
+    }
+}
@@ -0,0 +1,1175 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::authority::{self, Zone};
+use crate::clients::{self, ClientGroup, Device};
+use crate::common::{DnsClass, DnsType};
+use crate::config::Config;
+use crate::edns::{EdnsOption, EDE_BLOCKED, EDE_NO_REACHABLE_AUTHORITY, EDE_OTHER};
+use crate::error::ResolveError;
+use crate::forward;
+use crate::handler::State;
+use crate::header::ResponseCode;
+use crate::healthcheck;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::recursor::Recursor;
+use crate::rewrite;
+use crate::safesearch;
+use crate::schedule;
+use crate::secondary::Served;
+use crate::ttl;
+use crate::webhook;
+
+// The chain used unless `--plugins` names another.
+pub(crate) const DEFAULT_PLUGINS: &str =
+    "rewrite,block,safesearch,sinkhole,zones,consul,kubernetes,webhook,cache,forward,recursion";
+
+// One step of IN-class query processing, in the spirit of CoreDNS plugins.
+// A handler either answers by filling in `ctx.response`, or passes the
+// query on with `next.run(ctx)` and may look at the result afterwards.
+pub(crate) trait QueryHandler: Sync {
+    fn name(&self) -> &'static str;
+    fn handle(&self, ctx: &mut Context, next: Next);
+}
+
+// A query on its way through the chain.
+pub(crate) struct Context<'a> {
+    pub(crate) question: DnsQuestion,
+    pub(crate) client: IpAddr,
+    // The view of the listener the query arrived on.
+    pub(crate) view: Option<&'a str>,
+    // Who the query's EDNS options say asked, if anyone.
+    pub(crate) device: Device,
+    // The query asked for the zone's EXPIRE.
+    pub(crate) expire: bool,
+    pub(crate) config: &'a Config,
+    pub(crate) state: &'a State,
+    pub(crate) deadline: Instant,
+    pub(crate) response: DnsPacket,
+}
+
+// The handlers after the current one.
+pub(crate) struct Next<'a> {
+    chain: &'a [&'static dyn QueryHandler],
+}
+
+impl<'a> Context<'a> {
+    // Without RD, or for clients we don't recurse for, only local data is
+    // served.
+    pub(crate) fn recursive(&self) -> bool {
+        self.response.header.rd && self.response.header.ra
+    }
+
+    fn copy(&mut self, from: DnsPacket) {
+        self.response.header.rcode = from.header.rcode;
+        self.response.answers = from.answers;
+        self.response.authorities = from.authorities;
+        self.response.additionals = from.additionals;
+    }
+
+    // Takes an upstream response, with TTLs kept within `--min-ttl` and
+    // `--max-ttl`.
+    fn copy_upstream(&mut self, from: DnsPacket) {
+        self.copy(from);
+        self.config.ttl_bounds.apply(&mut self.response);
+    }
+
+    // The client's group, passing over those outside their schedule.
+    pub(crate) fn group(&self) -> Option<&'a ClientGroup> {
+        clients::resolve(
+            &self.config.client_groups,
+            self.client,
+            &self.device,
+            |group| {
+                schedule::active(
+                    group.schedule.as_deref(),
+                    self.config,
+                    &self.state.schedules,
+                )
+            },
+        )
+    }
+
+    // With `--log-only`, reports what enforcing a policy would have done to
+    // the query.
+    pub(crate) fn would(&self, action: &str) {
+        println!(
+            "log-only: {} {} {} would {}",
+            self.config.logged_client(self.client),
+            self.question.qname,
+            self.question.qtype,
+            action
+        );
+    }
+
+    fn fail(&mut self, e: ResolveError) {
+        self.response.header.rcode = ResponseCode::ServFail;
+        if let (ResolveError::Deadline | ResolveError::LimitExceeded(_), Some(edns)) =
+            (&e, &mut self.response.edns)
+        {
+            edns.options
+                .push(EdnsOption::ExtendedError(EDE_OTHER, e.to_string()));
+        }
+    }
+
+    // SERVFAIL saying that no server of the upstream group answered, and
+    // when they will be tried again.
+    fn unreachable(&mut self, group: &str, retry: Duration) {
+        self.response.header.rcode = ResponseCode::ServFail;
+        if let Some(edns) = &mut self.response.edns {
+            let seconds = retry.as_millis().div_ceil(1000);
+            let text = format!("upstream {} unreachable, retrying in {}s", group, seconds);
+            edns.options
+                .push(EdnsOption::ExtendedError(EDE_NO_REACHABLE_AUTHORITY, text));
+        }
+    }
+}
+
+impl<'a> Next<'a> {
+    // Queries nobody answered are refused.
+    pub(crate) fn run(self, ctx: &mut Context) {
+        match self.chain.split_first() {
+            Some((handler, chain)) => handler.handle(ctx, Next { chain }),
+            None => ctx.response.header.rcode = ResponseCode::Refused,
+        }
+    }
+}
+
+// Passes the query through every handler in order.
+pub(crate) fn run(chain: &[&'static dyn QueryHandler], ctx: &mut Context) {
+    Next { chain }.run(ctx)
+}
+
+const PLUGINS: [&dyn QueryHandler; 12] = [
+    &Rewrite,
+    &Block,
+    &SafeSearch,
+    &Sinkhole,
+    &Zones,
+    &Consul,
+    &Kubernetes,
+    &Webhook,
+    &Cache,
+    &Forward,
+    &Recursion,
+    &Log,
+];
+
+// `name[,name...]` from the plugins below.
+pub(crate) fn parse(spec: &str) -> Result<Vec<&'static dyn QueryHandler>, String> {
+    spec.split(',')
+        .map(|name| {
+            PLUGINS
+                .iter()
+                .copied()
+                .find(|plugin| plugin.name() == name)
+                .ok_or_else(|| format!("unknown plugin {:?}", name))
+        })
+        .collect()
+}
+
+// `--rewrite` rules: the query is renamed on the way down and the records
+// in the response adjusted on the way back, or with `--log-only` neither.
+struct Rewrite;
+
+impl QueryHandler for Rewrite {
+    fn name(&self) -> &'static str {
+        "rewrite"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let rules = &ctx.config.rewrites;
+        if rules.is_empty() {
+            return next.run(ctx);
+        }
+        if ctx.config.log_only {
+            return dry_run(ctx, next);
+        }
+        if ctx.question.qtype == DnsType::Aaaa && rules.strips_aaaa(&ctx.question.qname) {
+            return;
+        }
+
+        let original = ctx.question.qname.clone();
+        let renamed = rules.rename(&original);
+        if let Some((name, _, _)) = &renamed {
+            ctx.question.qname = name.clone();
+        }
+        next.run(ctx);
+        ctx.question.qname = original;
+
+        let response = &mut ctx.response;
+        for records in [
+            &mut response.answers,
+            &mut response.authorities,
+            &mut response.additionals,
+        ] {
+            if let Some((_, from, to)) = &renamed {
+                for record in records.iter_mut() {
+                    if let Some(name) = rewrite::replace_suffix(&record.name, to, from) {
+                        record.name = name;
+                    }
+                }
+            }
+            rules.apply(records);
+        }
+    }
+}
+
+// Lets the query through untouched, logging each rewrite the rules would
+// have made.
+fn dry_run(ctx: &mut Context, next: Next) {
+    let rules = &ctx.config.rewrites;
+    if ctx.question.qtype == DnsType::Aaaa && rules.strips_aaaa(&ctx.question.qname) {
+        ctx.would("get an empty answer (strip-aaaa)");
+    }
+    if let Some((name, _, _)) = rules.rename(&ctx.question.qname) {
+        ctx.would(&format!("be looked up as {}", name));
+    }
+    next.run(ctx);
+    let response = &ctx.response;
+    let changed = [
+        &response.answers,
+        &response.authorities,
+        &response.additionals,
+    ]
+    .into_iter()
+    .any(|records| {
+        let mut rewritten = records.clone();
+        rules.apply(&mut rewritten);
+        rewritten.len() != records.len()
+            || rewritten
+                .iter()
+                .zip(records)
+                .any(|(new, old)| new.ttl != old.ttl || new.rdata != old.rdata)
+    });
+    if changed {
+        ctx.would("get rewritten records");
+    }
+}
+
+// Names on the blocklists of the client's group, and on none of its
+// allowlists, get the list's block style, or `--block-style`, with an
+// Extended DNS Error saying which list blocked them. With `--log-only` the
+// block is logged and the query answered as usual.
+struct Block;
+
+impl QueryHandler for Block {
+    fn name(&self) -> &'static str {
+        "block"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let group = ctx.group();
+        let lists = ctx.state.blocklists();
+        let mut matching = lists.iter().filter(|list| {
+            group.into_iter().all(|group| group.uses(&list.name))
+                && schedule::active(list.schedule.as_deref(), ctx.config, &ctx.state.schedules)
+                && list.matches(&ctx.question.qname)
+        });
+        let blocked = match matching.clone().any(|list| list.allow) {
+            true => None,
+            false => matching.next(),
+        };
+        let Some(list) = blocked else {
+            return next.run(ctx);
+        };
+        if ctx.config.log_only {
+            ctx.would(&format!("be blocked by {}", list.name));
+            return next.run(ctx);
+        }
+        ctx.state.blocked.count(&list.name);
+        let style = list.style.as_ref().unwrap_or(&ctx.config.block_style);
+        style.answer(&ctx.question, &mut ctx.response);
+        if let Some(edns) = &mut ctx.response.edns {
+            let text = format!("blocked by {}", list.name);
+            edns.options
+                .push(EdnsOption::ExtendedError(EDE_BLOCKED, text));
+        }
+    }
+}
+
+// Search and video sites with SafeSearch forced for the client's group, or
+// with `--safesearch`, are answered with a CNAME to the site's enforcement
+// name, which the rest of the chain resolves.
+struct SafeSearch;
+
+impl QueryHandler for SafeSearch {
+    fn name(&self) -> &'static str {
+        "safesearch"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let sites = match ctx.group().and_then(|group| group.safesearch.as_ref()) {
+            Some(sites) => sites,
+            None => &ctx.config.safesearch,
+        };
+        let Some(target) = sites.target(&ctx.question.qname) else {
+            return next.run(ctx);
+        };
+        if ctx.config.log_only {
+            ctx.would(&format!("get a CNAME to {}", target));
+            return next.run(ctx);
+        }
+        let cname = DnsAnswer::new(
+            ctx.question.qname.clone(),
+            DnsType::Cname,
+            ctx.question.qclass,
+            safesearch::TTL,
+            RData::Cname(target.clone()),
+        );
+        if ctx.question.qtype != DnsType::Cname {
+            let original = std::mem::replace(&mut ctx.question.qname, target);
+            next.run(ctx);
+            ctx.question.qname = original;
+        }
+        ctx.response.answers.insert(0, cname);
+    }
+}
+
+// Every name but the exceptions, with `--sinkhole`.
+struct Sinkhole;
+
+impl QueryHandler for Sinkhole {
+    fn name(&self) -> &'static str {
+        "sinkhole"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        match &ctx.config.sinkhole {
+            Some(sinkhole) if sinkhole.covers(&ctx.question.qname) => {
+                sinkhole.answer(&ctx.question, &mut ctx.response)
+            }
+            _ => next.run(ctx),
+        }
+    }
+}
+
+// Authoritative answers from `--zone` files and `--secondary` zones, with
+// the ACME challenges put in through the admin API. ANY gets every RRset at
+// the name, or with `--minimal-any` just one. A secondary zone that has
+// expired gets its configured rcode instead of stale answers.
+struct Zones;
+
+impl QueryHandler for Zones {
+    fn name(&self) -> &'static str {
+        "zones"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let zones = ctx.state.zones();
+        let local = zones.find(&ctx.question.qname);
+        let secondary = ctx
+            .state
+            .secondaries
+            .find(&ctx.question.qname)
+            .filter(|(apex, _)| match local {
+                Some(zone) => zone.apex.len() < apex.len(),
+                None => true,
+            });
+        match (local, secondary) {
+            (_, Some((_, Served::Zone(zone, left)))) => answer_zone(ctx, &zone, Some(left)),
+            (_, Some((_, Served::Unavailable(rcode)))) => ctx.response.header.rcode = rcode,
+            // For secondaries asking the SOA (RFC 7314 section 3); ours
+            // never expire, so it is the SOA's own EXPIRE.
+            (Some(zone), None) => answer_zone(ctx, zone, zone.expire()),
+            (None, None) => next.run(ctx),
+        }
+    }
+}
+
+fn answer_zone(ctx: &mut Context, zone: &Zone, expire: Option<u32>) {
+    zone.answer(&ctx.question, &mut ctx.response);
+    let address = matches!(ctx.question.qtype, DnsType::A | DnsType::Aaaa);
+    if address && ctx.response.header.aa && ctx.response.answers.is_empty() {
+        if let Some(alias) = zone.alias(&ctx.question.qname) {
+            flatten(ctx, &alias);
+        }
+    }
+    if ctx.response.header.aa && ctx.response.answers.is_empty() {
+        if let Some(record) = zone.template(&ctx.question.qname) {
+            if let RData::Template(template) = record.rdata {
+                let (qname, qtype) = (&ctx.question.qname, ctx.question.qtype);
+                let answer = template.answer(qname, qtype, record.ttl, ctx.client);
+                if let Some(answer) = answer {
+                    ctx.response.answers.push(answer);
+                    ctx.response.authorities.clear();
+                }
+            }
+        }
+    }
+    // WEIGHTED and FAILOVER records answer as a CNAME to the target picked.
+    if ctx.response.header.aa && ctx.response.answers.is_empty() {
+        let pool = zone.pool(&ctx.question.qname);
+        if let Some(picked) = healthcheck::pick(&pool, &ctx.state.health_checks) {
+            if let RData::Pool { target, .. } = &picked.rdata {
+                let cname = RData::Cname(target.clone());
+                ctx.response.answers.push(DnsAnswer::new(
+                    ctx.question.qname.clone(),
+                    DnsType::Cname,
+                    DnsClass::In,
+                    picked.ttl,
+                    cname,
+                ));
+                ctx.response.authorities.clear();
+            }
+        }
+    }
+    ctx.state
+        .challenges
+        .answer(&ctx.question, &mut ctx.response);
+    if ctx.question.qtype == DnsType::Any && ctx.config.minimal_any {
+        authority::minimal_any(&mut ctx.response);
+    }
+    let expire = expire.filter(|_| {
+        ctx.expire
+            && ctx.question.qtype == DnsType::Soa
+            && ctx
+                .question
+                .qname
+                .as_str()
+                .eq_ignore_ascii_case(zone.apex.as_str())
+    });
+    if let (Some(expire), Some(edns)) = (expire, &mut ctx.response.edns) {
+        edns.options.push(EdnsOption::Expire(Some(expire)));
+    }
+}
+
+// Answers an address query at an ALIAS with the target's addresses under
+// the queried name, for zone apexes where a CNAME can't go. The target's
+// answers are cached like any other, and keep their TTLs capped at the
+// ALIAS's own.
+fn flatten(ctx: &mut Context, alias: &DnsAnswer) {
+    let RData::Alias(target) = &alias.rdata else {
+        return;
+    };
+    let question = DnsQuestion {
+        qname: target.clone(),
+        qtype: ctx.question.qtype,
+        qclass: DnsClass::In,
+    };
+    let resolved = match ctx.state.cache.get(&question) {
+        Some(cached) => Ok(cached),
+        None => resolve_alias(ctx, &question),
+    };
+    let response = match resolved {
+        Ok(response) => response,
+        Err(e) => return ctx.fail(e),
+    };
+    // A target that doesn't exist leaves the name without addresses.
+    if !matches!(
+        response.header.rcode,
+        ResponseCode::NoError | ResponseCode::NxDomain
+    ) {
+        ctx.response.header.rcode = ResponseCode::ServFail;
+        return;
+    }
+    let qname = &ctx.question.qname;
+    let answers: Vec<DnsAnswer> = response
+        .answers
+        .into_iter()
+        .filter(|record| record.qtype == question.qtype)
+        .map(|mut record| {
+            record.name = qname.clone();
+            record.ttl = record.ttl.min(alias.ttl);
+            record
+        })
+        .collect();
+    if !answers.is_empty() {
+        ctx.response.answers = answers;
+        ctx.response.authorities.clear();
+    }
+}
+
+// Looks an ALIAS target up in the local zones, or else as the forward or
+// recursion plugin would, whatever the client may ask for itself.
+fn resolve_alias(ctx: &Context, question: &DnsQuestion) -> Result<DnsPacket, ResolveError> {
+    if let Some(zone) = ctx.state.zones().find(&question.qname) {
+        let mut response = DnsPacket::query(0, question.clone());
+        zone.answer(question, &mut response);
+        return Ok(response);
+    }
+    let deadline = ctx.deadline;
+    let response = if let Some(group) = forward::select(ctx.config, &question.qname) {
+        let udp_size = ctx.config.max_udp_size;
+        let lookup = || forward::forward(question, group, udp_size, deadline, ctx.state);
+        ctx.state
+            .in_flight
+            .run(question, &group.name, deadline, lookup)?
+    } else if ctx.config.recursion {
+        let recursor = Recursor::new(
+            ctx.config.recursion_limits,
+            ctx.config.max_udp_size,
+            &ctx.state.roots.get(),
+            Arc::clone(&ctx.state.reputation),
+        );
+        let resolve = || recursor.resolve(question, deadline);
+        ctx.state.in_flight.run(question, "", deadline, resolve)?
+    } else {
+        return Err(ResolveError::NoServers);
+    };
+    ctx.state.cache.insert(question, &response);
+    Ok(response)
+}
+
+// Service discovery names from `--consul`.
+struct Consul;
+
+impl QueryHandler for Consul {
+    fn name(&self) -> &'static str {
+        "consul"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        match &ctx.config.consul {
+            Some(consul) if ctx.question.qname.is_subdomain_of(&consul.domain) => {
+                let catalog = &ctx.state.catalog;
+                catalog.answer(&ctx.question, &consul.domain, &mut ctx.response)
+            }
+            _ => next.run(ctx),
+        }
+    }
+}
+
+// Cluster service names from `--kubernetes`.
+struct Kubernetes;
+
+impl QueryHandler for Kubernetes {
+    fn name(&self) -> &'static str {
+        "kubernetes"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        match &ctx.config.kubernetes {
+            Some(kubernetes) if ctx.question.qname.is_subdomain_of(&kubernetes.domain) => {
+                let endpoints = &ctx.state.endpoints;
+                endpoints.answer(&ctx.question, &kubernetes.domain, &mut ctx.response)
+            }
+            _ => next.run(ctx),
+        }
+    }
+}
+
+// Records from the `--webhook` service. A service that can't be reached
+// fails the query rather than letting it fall through to upstreams.
+struct Webhook;
+
+impl QueryHandler for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let webhook = match &ctx.config.webhook {
+            Some(webhook) if ctx.question.qname.is_subdomain_of(&webhook.zone) => webhook,
+            _ => return next.run(ctx),
+        };
+        match webhook::lookup(webhook, &ctx.question, ctx.client) {
+            Ok(Some(reply)) => {
+                ctx.response.header.aa = true;
+                ctx.response.header.rcode = reply.rcode;
+                ctx.response.answers = reply.answers;
+            }
+            Ok(None) => next.run(ctx),
+            Err(e) => {
+                eprintln!("Webhook {} failed: {}", webhook.url, e);
+                ctx.response.header.rcode = ResponseCode::ServFail;
+            }
+        }
+    }
+}
+
+// Answers what later handlers looked up before, and remembers what they
+// look up now. With `--aggressive-nsec`, names that cached denial records
+// prove don't exist are answered too.
+struct Cache;
+
+impl QueryHandler for Cache {
+    fn name(&self) -> &'static str {
+        "cache"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        if !ctx.recursive() {
+            return next.run(ctx);
+        }
+        if let Some(cached) = ctx.state.cache.get(&ctx.question) {
+            ctx.copy(cached);
+            return ttl::jitter(ctx.config.ttl_jitter, &mut ctx.response);
+        }
+        let aggressive = ctx.config.aggressive_nsec;
+        if let Some(synthesized) = aggressive
+            .then(|| ctx.state.denials.synthesize(&ctx.question))
+            .flatten()
+        {
+            return ctx.copy(synthesized);
+        }
+        next.run(ctx);
+        ctx.state.cache.insert(&ctx.question, &ctx.response);
+        if aggressive {
+            ctx.state.denials.learn(&ctx.response);
+        }
+    }
+}
+
+// The upstream group of the most specific `--forward` zone.
+struct Forward;
+
+impl QueryHandler for Forward {
+    fn name(&self) -> &'static str {
+        "forward"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let group = forward::select(ctx.config, &ctx.question.qname);
+        let Some(group) = group.filter(|_| ctx.recursive()) else {
+            return next.run(ctx);
+        };
+        let (question, deadline) = (&ctx.question, ctx.deadline);
+        let udp_size = ctx.config.max_udp_size;
+        let state = ctx.state;
+        let lookup = || forward::forward(question, group, udp_size, deadline, state);
+        match ctx
+            .state
+            .in_flight
+            .run(question, &group.name, deadline, lookup)
+        {
+            Ok(response) => ctx.copy_upstream(response),
+            // Also when another client's query for the same found it down.
+            Err(e) => match ctx.state.outages.down(&group.name) {
+                Some(retry) => ctx.unreachable(&group.name, retry),
+                None => ctx.fail(e),
+            },
+        }
+    }
+}
+
+// Iterative resolution from the root, with `--recursion`.
+struct Recursion;
+
+impl QueryHandler for Recursion {
+    fn name(&self) -> &'static str {
+        "recursion"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        if !(ctx.config.recursion && ctx.recursive()) {
+            return next.run(ctx);
+        }
+        let recursor = Recursor::new(
+            ctx.config.recursion_limits,
+            ctx.config.max_udp_size,
+            &ctx.state.roots.get(),
+            Arc::clone(&ctx.state.reputation),
+        )
+        .with_dnssec_ok(ctx.config.aggressive_nsec);
+        let (question, deadline) = (&ctx.question, ctx.deadline);
+        let resolve = || recursor.resolve(question, deadline);
+        match ctx.state.in_flight.run(question, "", deadline, resolve) {
+            Ok(response) => ctx.copy_upstream(response),
+            Err(e) => ctx.fail(e),
+        }
+    }
+}
+
+// Prints each query with the outcome of the handlers after it.
+struct Log;
+
+impl QueryHandler for Log {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn handle(&self, ctx: &mut Context, next: Next) {
+        let start = Instant::now();
+        next.run(ctx);
+        println!(
+            "{} {} {} {} {} answers in {:?}",
+            ctx.config.logged_client(ctx.client),
+            ctx.question.qname,
+            ctx.question.qtype,
+            ctx.response.header.rcode,
+            ctx.response.answers.len(),
+            start.elapsed()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blocklist::{BlockStyle, Blocklist};
+    use crate::common::DnsClass;
+    use crate::edns::Edns;
+    use crate::safesearch::Sites;
+    use crate::testing::MockUpstream;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Answers every query with one A record and counts how often it ran.
+    struct Fixed(AtomicUsize);
+
+    impl QueryHandler for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        fn handle(&self, ctx: &mut Context, _: Next) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ctx.response.answers.push(DnsAnswer::new(
+                ctx.question.qname.clone(),
+                DnsType::A,
+                DnsClass::In,
+                300,
+                RData::A([192, 0, 2, 1]),
+            ));
+        }
+    }
+
+    // Answers with a CNAME to the name it was asked about.
+    struct Echo;
+
+    impl QueryHandler for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn handle(&self, ctx: &mut Context, _: Next) {
+            let name = ctx.question.qname.clone();
+            ctx.response.answers.push(DnsAnswer::new(
+                name.clone(),
+                DnsType::Cname,
+                DnsClass::In,
+                300,
+                RData::Cname(name),
+            ));
+        }
+    }
+
+    fn context<'a>(config: &'a Config, state: &'a State) -> Context<'a> {
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        response.header.flip_qr();
+        response.header.ra = true;
+        Context {
+            question,
+            client: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            view: None,
+            device: Device::default(),
+            expire: false,
+            config,
+            state,
+            deadline: Instant::now(),
+            response,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let names = |spec: &str| {
+            parse(spec)
+                .unwrap()
+                .iter()
+                .map(|plugin| plugin.name())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(DEFAULT_PLUGINS),
+            vec![
+                "rewrite",
+                "block",
+                "safesearch",
+                "sinkhole",
+                "zones",
+                "consul",
+                "kubernetes",
+                "webhook",
+                "cache",
+                "forward",
+                "recursion"
+            ]
+        );
+        assert_eq!(names("log,forward"), vec!["log", "forward"]);
+        assert!(parse("zones,blocklist").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_chain() {
+        static FIXED: Fixed = Fixed(AtomicUsize::new(0));
+        let (config, state) = (Config::default(), State::default());
+
+        // Nobody answers.
+        let mut ctx = context(&config, &state);
+        run(&parse("zones,forward").unwrap(), &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::Refused);
+
+        // The cache remembers what handlers after it answered.
+        let chain: Vec<&'static dyn QueryHandler> = vec![&Cache, &FIXED];
+        for _ in 0..3 {
+            let mut ctx = context(&config, &state);
+            run(&chain, &mut ctx);
+            assert_eq!(ctx.response.answers.len(), 1);
+        }
+        assert_eq!(FIXED.0.load(Ordering::SeqCst), 1);
+
+        // Without RD the cache steps aside.
+        let mut ctx = context(&config, &state);
+        ctx.response.header.rd = false;
+        run(&chain, &mut ctx);
+        assert_eq!(FIXED.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_forward_unreachable() {
+        let upstream = MockUpstream::silent();
+        let config = Config {
+            upstreams: vec![upstream.group("dead")],
+            forward_zones: vec![(".".into(), "dead".into())],
+            ..Config::default()
+        };
+        let state = State::default();
+        let chain: Vec<&'static dyn QueryHandler> = vec![&Forward];
+        for _ in 0..2 {
+            let mut ctx = context(&config, &state);
+            ctx.deadline = Instant::now() + Duration::from_millis(200);
+            ctx.response.edns = Some(Edns::new(1232));
+            run(&chain, &mut ctx);
+            assert_eq!(ctx.response.header.rcode, ResponseCode::ServFail);
+            assert_eq!(
+                ctx.response.edns.unwrap().options,
+                vec![EdnsOption::ExtendedError(
+                    EDE_NO_REACHABLE_AUTHORITY,
+                    "upstream dead unreachable, retrying in 2s".into()
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn test_alias() {
+        let target =
+            |qtype, rdata| DnsAnswer::new("lb.example.net".into(), qtype, DnsClass::In, 600, rdata);
+        let upstream = MockUpstream::serving(vec![target(DnsType::A, RData::A([192, 0, 2, 10]))]);
+        let path =
+            std::env::temp_dir().join(format!("dns-server-test-alias-{}.zone", std::process::id()));
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\n\
+             @ ALIAS lb.example.net.\nmail ALIAS ns1\n",
+        )
+        .unwrap();
+        let config = Config {
+            upstreams: vec![upstream.group("up")],
+            forward_zones: vec![(".".into(), "up".into())],
+            zones: vec![(None, path.clone())],
+            ..Config::default()
+        };
+        let state = State::new(authority::Zones::load(&config).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let query = |name: &str, qtype| {
+            let mut ctx = context(&config, &state);
+            ctx.question.qname = name.into();
+            ctx.question.qtype = qtype;
+            ctx.deadline = Instant::now() + Duration::from_secs(2);
+            run(&parse("zones").unwrap(), &mut ctx);
+            ctx.response
+        };
+
+        // Flattened at the apex with the ALIAS's TTL, and looked up once.
+        for _ in 0..2 {
+            let response = query("example.com", DnsType::A);
+            assert!(response.header.aa);
+            assert_eq!(
+                response.answers,
+                vec![DnsAnswer::new(
+                    "example.com".into(),
+                    DnsType::A,
+                    DnsClass::In,
+                    300,
+                    RData::A([192, 0, 2, 10])
+                )]
+            );
+        }
+        assert_eq!(upstream.queries().len(), 1);
+
+        // A target without the type, or another type asked, gets NODATA.
+        let response = query("example.com", DnsType::Aaaa);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].qtype, DnsType::Soa);
+        assert!(query("example.com", DnsType::Mx).answers.is_empty());
+        assert_eq!(upstream.queries().len(), 2);
+
+        // Targets in a local zone are answered from it.
+        let response = query("mail.example.com", DnsType::A);
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 1]));
+        assert_eq!(upstream.queries().len(), 2);
+    }
+
+    #[test]
+    fn test_failover_records() {
+        let path = std::env::temp_dir().join(format!(
+            "dns-server-test-failover-{}.zone",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\n\
+             www 60 FAILOVER 10 0 primary.example.net.\n\
+             www 60 FAILOVER 20 0 backup.example.net.\n",
+        )
+        .unwrap();
+        let config = Config {
+            zones: vec![(None, path.clone())],
+            ..Config::default()
+        };
+        let state = State::new(authority::Zones::load(&config).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        for qtype in [DnsType::A, DnsType::Mx] {
+            let mut ctx = context(&config, &state);
+            ctx.question.qtype = qtype;
+            run(&parse("zones").unwrap(), &mut ctx);
+            assert!(ctx.response.header.aa);
+            assert!(ctx.response.authorities.is_empty());
+            assert_eq!(
+                ctx.response.answers,
+                vec![DnsAnswer::new(
+                    "www.example.com".into(),
+                    DnsType::Cname,
+                    DnsClass::In,
+                    60,
+                    RData::Cname("primary.example.net".into())
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn test_templates() {
+        let path = std::env::temp_dir().join(format!(
+            "dns-server-test-templates-{}.zone",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\n\
+             whoami 0 TEMPLATE address\nmyip 0 TEMPLATE txt\n",
+        )
+        .unwrap();
+        let config = Config {
+            zones: vec![(None, path.clone())],
+            ..Config::default()
+        };
+        let state = State::new(authority::Zones::load(&config).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let query = |name: &str, qtype| {
+            let mut ctx = context(&config, &state);
+            ctx.question.qname = name.into();
+            ctx.question.qtype = qtype;
+            run(&parse("zones").unwrap(), &mut ctx);
+            ctx.response
+        };
+
+        let response = query("whoami.example.com", DnsType::A);
+        assert_eq!(
+            response.answers,
+            vec![DnsAnswer::new(
+                "whoami.example.com".into(),
+                DnsType::A,
+                DnsClass::In,
+                0,
+                RData::A([127, 0, 0, 1])
+            )]
+        );
+        let response = query("myip.example.com", DnsType::Txt);
+        assert_eq!(
+            response.answers[0].rdata,
+            RData::Txt(vec![b"127.0.0.1".to_vec()])
+        );
+        // Types the template has nothing for get NODATA.
+        let response = query("whoami.example.com", DnsType::Aaaa);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].qtype, DnsType::Soa);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut config = Config::default();
+        config.rewrites.add("name:example.com=example.net").unwrap();
+        config.rewrites.add("strip-aaaa:example.com").unwrap();
+        let state = State::default();
+        let chain: Vec<&'static dyn QueryHandler> = vec![&Rewrite, &Echo];
+
+        // Looked up as www.example.net, answered as www.example.com.
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.answers[0].name.as_str(), "www.example.com");
+        assert_eq!(
+            ctx.response.answers[0].rdata,
+            RData::Cname("www.example.net".into())
+        );
+
+        let mut ctx = context(&config, &state);
+        ctx.question.qtype = DnsType::Aaaa;
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NoError);
+        assert!(ctx.response.answers.is_empty());
+
+        // With --log-only the original name is looked up and answered.
+        config.log_only = true;
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(
+            ctx.response.answers[0].rdata,
+            RData::Cname("www.example.com".into())
+        );
+    }
+
+    #[test]
+    fn test_block() {
+        let mut config = Config::default();
+        let state = State::default();
+        *state.blocklists.write().unwrap() = Arc::new(vec![Arc::new(
+            Blocklist::parse("ads", "example.com").unwrap(),
+        )]);
+        let chain: Vec<&'static dyn QueryHandler> = vec![&Block, &Echo];
+
+        let mut ctx = context(&config, &state);
+        ctx.response.edns = Some(Edns::new(1232));
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NxDomain);
+        assert!(ctx.response.answers.is_empty());
+        assert_eq!(
+            ctx.response.edns.unwrap().options,
+            vec![EdnsOption::ExtendedError(
+                EDE_BLOCKED,
+                "blocked by ads".into()
+            )]
+        );
+        assert_eq!(state.blocked.counts(), vec![("ads".to_string(), 1)]);
+
+        // Logged but answered.
+        config.log_only = true;
+        let mut ctx = context(&config, &state);
+        ctx.response.edns = Some(Edns::new(1232));
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NoError);
+        assert_eq!(ctx.response.answers.len(), 1);
+        assert!(ctx.response.edns.unwrap().options.is_empty());
+        config.log_only = false;
+
+        // A group without the list is let through.
+        config.client_groups = vec![ClientGroup::parse("admin=127.0.0.1,blocklists=").unwrap()];
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.answers.len(), 1);
+        config.client_groups.clear();
+
+        // The list's style wins over the global one.
+        config.block_style = BlockStyle::Refused;
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::Refused);
+        let mut list = Blocklist::parse("ads", "example.com").unwrap();
+        list.style = Some(BlockStyle::Null);
+        *state.blocklists.write().unwrap() = Arc::new(vec![Arc::new(list)]);
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NoError);
+        assert_eq!(ctx.response.answers[0].rdata, RData::A([0; 4]));
+
+        // An allowlist wins over every blocklist.
+        let blocklist = Blocklist::parse("ads", "*.example.com").unwrap();
+        let mut allowlist = Blocklist::parse("ok", "/^www\\./").unwrap();
+        allowlist.allow = true;
+        *state.blocklists.write().unwrap() =
+            Arc::new(vec![Arc::new(blocklist), Arc::new(allowlist)]);
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NoError);
+        assert_eq!(ctx.response.answers[0].qtype, DnsType::Cname);
+
+        // Lists off their schedule are passed over.
+        let mut list = Blocklist::parse("ads", "example.com").unwrap();
+        list.schedule = Some("work".to_string());
+        *state.blocklists.write().unwrap() = Arc::new(vec![Arc::new(list)]);
+        state.schedules.set("work", Some(false));
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::NoError);
+        state.schedules.set("work", Some(true));
+        let mut ctx = context(&config, &state);
+        run(&chain, &mut ctx);
+        assert_eq!(ctx.response.header.rcode, ResponseCode::Refused);
+    }
+
+    #[test]
+    fn test_safesearch() {
+        static FIXED: Fixed = Fixed(AtomicUsize::new(0));
+        let mut config = Config {
+            safesearch: Sites::parse("google/youtube").unwrap(),
+            ..Config::default()
+        };
+        let state = State::default();
+        let chain: Vec<&'static dyn QueryHandler> = vec![&SafeSearch, &FIXED];
+        let query = |config: &Config, name: &str, qtype: DnsType| {
+            let mut ctx = context(config, &state);
+            ctx.question.qname = name.into();
+            ctx.question.qtype = qtype;
+            run(&chain, &mut ctx);
+            assert_eq!(ctx.question.qname.as_str(), name);
+            ctx.response
+                .answers
+                .iter()
+                .map(|record| (record.name.to_string(), record.rdata.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // The enforcement name is looked up further down the chain.
+        assert_eq!(
+            query(&config, "www.google.de", DnsType::A),
+            vec![
+                (
+                    "www.google.de".to_string(),
+                    RData::Cname("forcesafesearch.google.com".into())
+                ),
+                (
+                    "forcesafesearch.google.com".to_string(),
+                    RData::A([192, 0, 2, 1])
+                ),
+            ]
+        );
+        assert_eq!(
+            query(&config, "www.youtube.com", DnsType::Cname),
+            vec![(
+                "www.youtube.com".to_string(),
+                RData::Cname("restrict.youtube.com".into())
+            )]
+        );
+        assert_eq!(query(&config, "www.bing.com", DnsType::A).len(), 1);
+
+        // A group's setting wins over the global one.
+        config.client_groups = vec![ClientGroup::parse("adults=127.0.0.1,safesearch=off").unwrap()];
+        assert_eq!(query(&config, "www.google.de", DnsType::A).len(), 1);
+        config.client_groups = vec![ClientGroup::parse("kids=127.0.0.1,safesearch=bing").unwrap()];
+        assert_eq!(query(&config, "www.bing.com", DnsType::A).len(), 2);
+        assert_eq!(query(&config, "www.google.de", DnsType::A).len(), 1);
+
+        config.log_only = true;
+        assert_eq!(query(&config, "www.bing.com", DnsType::A).len(), 1);
+    }
+}
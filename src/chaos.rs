@@ -0,0 +1,90 @@
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType};
+use crate::config::ChaosConfig;
+use crate::header::ResponseCode;
+use crate::question::DnsQuestion;
+
+// Answers the conventional CH TXT diagnostic queries (RFC 4892).
+pub(crate) fn answer(
+    question: &DnsQuestion,
+    config: &ChaosConfig,
+) -> Result<DnsAnswer, ResponseCode> {
+    if config.hidden {
+        return Err(ResponseCode::Refused);
+    }
+    if question.qtype != DnsType::Txt {
+        return Err(ResponseCode::Refused);
+    }
+
+    let value = match question.qname.as_str().to_ascii_lowercase().as_str() {
+        "version.bind" | "version.server" => &config.version,
+        "hostname.bind" => &config.hostname,
+        "id.server" => &config.id,
+        _ => return Err(ResponseCode::Refused),
+    };
+    let value = value.as_ref().ok_or(ResponseCode::Refused)?;
+
+    Ok(DnsAnswer::new(
+        question.qname.as_str().into(),
+        DnsType::Txt,
+        DnsClass::Ch,
+        0,
+        RData::Txt(vec![value.as_bytes().to_vec()]),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn question(name: &str, qtype: DnsType) -> DnsQuestion {
+        DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::Ch,
+        }
+    }
+
+    fn config() -> ChaosConfig {
+        ChaosConfig {
+            version: Some("dns-server 1.0".to_string()),
+            hostname: Some("ns1.example.com".to_string()),
+            id: None,
+            hidden: false,
+        }
+    }
+
+    #[test]
+    fn test_configured_values() {
+        let config = config();
+        let answer = super::answer(&question("VERSION.BIND", DnsType::Txt), &config).unwrap();
+        assert_eq!(answer.qclass, DnsClass::Ch);
+        assert_eq!(answer.rdata, RData::Txt(vec![b"dns-server 1.0".to_vec()]));
+
+        let answer = super::answer(&question("hostname.bind", DnsType::Txt), &config).unwrap();
+        assert_eq!(answer.rdata, RData::Txt(vec![b"ns1.example.com".to_vec()]));
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut config = config();
+        let refused = Err(ResponseCode::Refused);
+        assert_eq!(
+            super::answer(&question("id.server", DnsType::Txt), &config).map(|a| a.rdata),
+            refused
+        );
+        assert_eq!(
+            super::answer(&question("version.bind", DnsType::A), &config).map(|a| a.rdata),
+            refused
+        );
+        assert_eq!(
+            super::answer(&question("example.com", DnsType::Txt), &config).map(|a| a.rdata),
+            refused
+        );
+        config.hidden = true;
+        assert_eq!(
+            super::answer(&question("version.bind", DnsType::Txt), &config).map(|a| a.rdata),
+            refused
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use std::net::IpAddr;
+
+use crate::acl::Network;
+use crate::edns::{Edns, EdnsOption};
+use crate::safesearch::Sites;
+
+// EDNS options some CPE add to forwarded queries to say which device asked:
+// the MAC address (dnsmasq --add-mac, also Nominum's) and a free-form
+// device ID (dnsmasq --add-cpe-id).
+const MAC_OPTION: u16 = 65001;
+const DEVICE_ID_OPTION: u16 = 65074;
+
+// What a query says about the device behind it.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub(crate) struct Device {
+    pub(crate) mac: Option<[u8; 6]>,
+    pub(crate) id: Option<Vec<u8>>,
+}
+
+impl Device {
+    pub(crate) fn from_edns(edns: Option<&Edns>) -> Self {
+        let mut device = Device::default();
+        for option in edns.map(|edns| edns.options.as_slice()).unwrap_or_default() {
+            match option {
+                EdnsOption::Unknown(MAC_OPTION, data) => device.mac = parse_mac(data),
+                EdnsOption::Unknown(DEVICE_ID_OPTION, data) if !data.is_empty() => {
+                    device.id = Some(data.clone())
+                }
+                _ => {}
+            }
+        }
+        device
+    }
+}
+
+// Six raw bytes, or the `aa:bb:cc:dd:ee:ff` text form dnsmasq can also send.
+fn parse_mac(data: &[u8]) -> Option<[u8; 6]> {
+    if let Ok(mac) = data.try_into() {
+        return Some(mac);
+    }
+    let text = std::str::from_utf8(data).ok()?;
+    let bytes = text
+        .split([':', '-'])
+        .map(|byte| {
+            u8::from_str_radix(byte, 16)
+                .ok()
+                .filter(|_| byte.len() == 2)
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    bytes.try_into().ok()
+}
+
+// A set of clients sharing a policy, from
+// `--client-group name=member[,member...][,blocklists=a/b][,schedule=name]
+// [,safesearch=sites]` where a member is an address or prefix,
+// `mac=aa:bb:cc:dd:ee:ff` or `device=id`. Without `blocklists=` the group
+// gets every list, allowlists included; `blocklists=` alone gets none.
+// Without `safesearch=` it gets `--safesearch`. Outside its schedule the
+// group is passed over, as if its clients weren't in it.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct ClientGroup {
+    pub(crate) name: String,
+    networks: Vec<Network>,
+    macs: Vec<[u8; 6]>,
+    ids: Vec<Vec<u8>>,
+    pub(crate) blocklists: Option<Vec<String>>,
+    pub(crate) schedule: Option<String>,
+    pub(crate) safesearch: Option<Sites>,
+}
+
+impl ClientGroup {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let (name, members) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| format!("expected name=members, got {:?}", spec))?;
+        let mut group = ClientGroup {
+            name: name.to_string(),
+            networks: Vec::new(),
+            macs: Vec::new(),
+            ids: Vec::new(),
+            blocklists: None,
+            schedule: None,
+            safesearch: None,
+        };
+        for member in members.split(',').filter(|member| !member.is_empty()) {
+            match member.split_once('=') {
+                Some(("mac", mac)) => group.macs.push(
+                    parse_mac(mac.as_bytes())
+                        .ok_or_else(|| format!("bad MAC address {:?}", mac))?,
+                ),
+                Some(("device", id)) if !id.is_empty() => group.ids.push(id.as_bytes().to_vec()),
+                Some(("blocklists", lists)) => {
+                    let lists = lists.split('/').filter(|list| !list.is_empty());
+                    group.blocklists = Some(lists.map(str::to_string).collect());
+                }
+                Some(("schedule", name)) if !name.is_empty() => {
+                    group.schedule = Some(name.to_string())
+                }
+                Some(("safesearch", sites)) => group.safesearch = Some(Sites::parse(sites)?),
+                Some(_) => return Err(format!("unknown client group option {:?}", member)),
+                None => group.networks.push(Network::parse(member)?),
+            }
+        }
+        Ok(group)
+    }
+
+    fn has_device(&self, device: &Device) -> bool {
+        device.mac.is_some_and(|mac| self.macs.contains(&mac))
+            || device.id.as_ref().is_some_and(|id| self.ids.contains(id))
+    }
+
+    // Whether the group uses the named blocklist.
+    pub(crate) fn uses(&self, list: &str) -> bool {
+        match &self.blocklists {
+            Some(lists) => lists.iter().any(|name| name == list),
+            None => true,
+        }
+    }
+}
+
+// The group a client belongs to. A device identified in the query is more
+// specific than its address, which may be shared behind the CPE's NAT; the
+// group named `default` takes everyone else. Groups `active` turns down
+// are skipped.
+pub(crate) fn resolve<'a>(
+    groups: &'a [ClientGroup],
+    client: IpAddr,
+    device: &Device,
+    active: impl Fn(&ClientGroup) -> bool,
+) -> Option<&'a ClientGroup> {
+    let groups: Vec<&ClientGroup> = groups.iter().filter(|group| active(group)).collect();
+    let group = groups
+        .iter()
+        .find(|group| group.has_device(device))
+        .or_else(|| {
+            groups
+                .iter()
+                .filter_map(|group| {
+                    let network = group.networks.iter().filter(|n| n.contains(client));
+                    Some((group, network.map(Network::prefix).max()?))
+                })
+                .max_by_key(|(_, prefix)| *prefix)
+                .map(|(group, _)| group)
+        })
+        .or_else(|| groups.iter().find(|group| group.name == "default"));
+    group.copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    fn device(options: Vec<EdnsOption>) -> Device {
+        let mut edns = Edns::new(1232);
+        edns.options = options;
+        Device::from_edns(Some(&edns))
+    }
+
+    #[test]
+    fn test_device() {
+        let mac = [0x02, 0x42, 0xac, 0x11, 0x00, 0x02];
+        let from_raw = device(vec![EdnsOption::Unknown(MAC_OPTION, mac.to_vec())]);
+        assert_eq!(from_raw.mac, Some(mac));
+        let from_text = device(vec![
+            EdnsOption::Unknown(MAC_OPTION, b"02:42:ac:11:00:02".to_vec()),
+            EdnsOption::Unknown(DEVICE_ID_OPTION, b"tablet".to_vec()),
+        ]);
+        assert_eq!(from_text.mac, Some(mac));
+        assert_eq!(from_text.id, Some(b"tablet".to_vec()));
+        assert_eq!(
+            device(vec![EdnsOption::Unknown(MAC_OPTION, b"junk".to_vec())]),
+            Device::default()
+        );
+        assert_eq!(Device::from_edns(None), Device::default());
+    }
+
+    #[test]
+    fn test_parse() {
+        let group =
+            ClientGroup::parse("kids=192.168.1.64/26,mac=02:42:ac:11:00:02,blocklists=ads/adult")
+                .unwrap();
+        assert!(group.uses("adult"));
+        assert!(!group.uses("gambling"));
+        assert!(ClientGroup::parse("adults=192.168.1.0/24")
+            .unwrap()
+            .uses("ads"));
+        assert!(!ClientGroup::parse("open=blocklists=").unwrap().uses("ads"));
+        assert_eq!(
+            ClientGroup::parse("kids=10.0.0.0/8,schedule=school")
+                .unwrap()
+                .schedule
+                .as_deref(),
+            Some("school")
+        );
+        assert!(
+            ClientGroup::parse("kids=10.0.0.0/8,safesearch=google/youtube")
+                .unwrap()
+                .safesearch
+                .is_some()
+        );
+        for bad in [
+            "kids",
+            "=10.0.0.1",
+            "kids=10.0.0.1/40",
+            "kids=mac=02:42",
+            "kids=user=alice",
+            "kids=10.0.0.1,safesearch=yahoo",
+        ] {
+            assert!(ClientGroup::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let groups: Vec<ClientGroup> = [
+            "adults=192.168.1.0/24",
+            "kids=192.168.1.64/26,device=tablet",
+            "default=blocklists=ads",
+        ]
+        .iter()
+        .map(|spec| ClientGroup::parse(spec).unwrap())
+        .collect();
+        let name = |client: &str, device: &Device| {
+            resolve(&groups, ip(client), device, |_| true).map(|group| group.name.as_str())
+        };
+        let none = Device::default();
+        assert_eq!(name("192.168.1.10", &none), Some("adults"));
+        assert_eq!(name("192.168.1.70", &none), Some("kids"));
+        assert_eq!(name("10.0.0.1", &none), Some("default"));
+        let tablet = Device {
+            mac: None,
+            id: Some(b"tablet".to_vec()),
+        };
+        assert_eq!(name("192.168.1.1", &tablet), Some("kids"));
+        assert_eq!(resolve(&groups[..2], ip("10.0.0.1"), &none, |_| true), None);
+
+        // Outside its schedule the kids' tablet falls back to adults.
+        let kids_off = |group: &ClientGroup| group.name != "kids";
+        let group = resolve(&groups, ip("192.168.1.70"), &tablet, kids_off);
+        assert_eq!(group.map(|group| group.name.as_str()), Some("adults"));
+    }
+}
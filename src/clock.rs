@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+#[cfg(test)]
+use std::sync::Mutex;
+#[cfg(test)]
+use std::time::Duration;
+
+// Where expiry and backoff timers read the time, so that tests can move it
+// on instead of sleeping.
+pub(crate) trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// The clock a cache or backoff table keeps: the system's unless a test
+// gives it another.
+#[derive(Clone)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(Arc::new(SystemClock))
+    }
+}
+
+impl SharedClock {
+    pub(crate) fn now(&self) -> Instant {
+        self.0.now()
+    }
+}
+
+// Stands still until moved on.
+#[cfg(test)]
+#[derive(Clone)]
+pub(crate) struct ManualClock(Arc<Mutex<Instant>>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub(crate) fn new() -> Self {
+        ManualClock(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub(crate) fn advance(&self, by: Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+
+    pub(crate) fn shared(&self) -> SharedClock {
+        SharedClock(Arc::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
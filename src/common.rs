@@ -1,109 +1,486 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
 use crate::error::ParseError;
 
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) struct Name(String);
 
 #[derive(PartialEq, Debug, Clone, Copy)]
-#[repr(u16)]
 pub(crate) enum DnsType {
-    A = 1,      // a host address
-    Ns = 2,     // an authoritative name server
-    Md = 3,     // a mail destination (Obsolete - use MX)
-    Mf = 4,     // a mail forwarder (Obsolete - use MX)
-    Cname = 5,  // the canonical name for an alias
-    Soa = 6,    // marks the start of a zone of authority
-    Mb = 7,     // a mailbox domain name (EXPERIMENTAL)
-    Mg = 8,     // a mail group member (EXPERIMENTAL)
-    Mr = 9,     // a mail rename domain name (EXPERIMENTAL)
-    Null = 10,  // a null RR (EXPERIMENTAL)
-    Wks = 11,   // a well known service description
-    Ptr = 12,   // a domain name pointer
-    Hinfo = 13, // host information
-    Minfo = 14, // mailbox or mail list information
-    Mx = 15,    // mail exchange
-    Txt = 16,   // text strings
+    A,            // a host address
+    Ns,           // an authoritative name server
+    Md,           // a mail destination (Obsolete - use MX)
+    Mf,           // a mail forwarder (Obsolete - use MX)
+    Cname,        // the canonical name for an alias
+    Soa,          // marks the start of a zone of authority
+    Mb,           // a mailbox domain name (EXPERIMENTAL)
+    Mg,           // a mail group member (EXPERIMENTAL)
+    Mr,           // a mail rename domain name (EXPERIMENTAL)
+    Null,         // a null RR (EXPERIMENTAL)
+    Wks,          // a well known service description
+    Ptr,          // a domain name pointer
+    Hinfo,        // host information
+    Minfo,        // mailbox or mail list information
+    Mx,           // mail exchange
+    Txt,          // text strings
+    Sig,          // transaction signature by public key (SIG(0), RFC 2931)
+    Key,          // public key for SIG(0) (RFC 2535, RFC 3445)
+    Aaaa,         // an IPv6 host address (RFC 3596)
+    Srv,          // service locator (RFC 2782)
+    Naptr,        // naming authority pointer (RFC 3403)
+    Opt,          // EDNS(0) pseudo-record (RFC 6891)
+    Ds,           // delegation signer (RFC 4034)
+    Sshfp,        // SSH key fingerprint (RFC 4255)
+    Rrsig,        // DNSSEC signature (RFC 4034)
+    Nsec,         // next secure record (RFC 4034)
+    Dnskey,       // DNSSEC public key (RFC 4034)
+    Nsec3,        // hashed next secure record (RFC 5155)
+    Nsec3param,   // NSEC3 parameters (RFC 5155)
+    Tlsa,         // TLS certificate association (RFC 6698)
+    Cds,          // child DS (RFC 7344)
+    Cdnskey,      // child DNSKEY (RFC 7344)
+    Svcb,         // service binding (RFC 9460)
+    Https,        // HTTPS service binding (RFC 9460)
+    Spf,          // sender policy framework (RFC 7208, obsolete)
+    Tkey,         // transaction key negotiation (RFC 2930)
+    Tsig,         // transaction signature (RFC 8945)
+    Ixfr,         // incremental zone transfer (QTYPE only, RFC 1995)
+    Axfr,         // full zone transfer (QTYPE only)
+    Any,          // a request for all records (QTYPE only)
+    Caa,          // certification authority authorization (RFC 8659)
+    Alias,        // apex alias flattened when served (private use, as PowerDNS)
+    Weighted,     // one of several targets by weight, served as a CNAME (private use)
+    Failover,     // the first healthy of several targets, served as a CNAME (private use)
+    Template,     // records made up per query, such as the client's address (private use)
+    Unknown(u16), // any other type, kept as its code (RFC 3597)
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 #[repr(u16)]
 pub(crate) enum DnsClass {
-    In = 1, // the Internet
-    Cs = 2, // the CSNET class (Obsolete - used only for examples in some obsolete RFCs)
-    Ch = 3, // the CHAOS class
-    Hs = 4, // Hesiod [Dyer 87]
+    In = 1,     // the Internet
+    Cs = 2,     // the CSNET class (Obsolete - used only for examples in some obsolete RFCs)
+    Ch = 3,     // the CHAOS class
+    Hs = 4,     // Hesiod [Dyer 87]
+    None = 254, // "none" in dynamic update prerequisites and deletions (RFC 2136)
+    Any = 255,  // any class (QCLASS only)
 }
 
-impl TryFrom<&[u8]> for Name {
-    type Error = ParseError;
+const MAX_NAME_LEN: usize = 255;
+const MAX_POINTERS: usize = 64; // more jumps than this can only be a compression loop
+const MAX_POINTER_OFFSET: usize = 0x3fff;
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut name = String::new();
-        let mut value = value;
+// Where each name suffix written to a message so far starts, so that later
+// names can point back at it (RFC 1035 section 4.1.4). Suffixes only match
+// as written, so every name keeps the case it had.
+#[derive(Default)]
+pub(crate) struct Compressor<'a> {
+    offsets: HashMap<&'a str, u16>,
+    off: bool,
+    // Bytes the pointers took the place of.
+    saved: usize,
+}
 
-        if value.is_empty() {
-            return Ok(Name(name));
+impl Compressor<'_> {
+    // Writes every name in full.
+    pub(crate) fn off() -> Self {
+        Compressor {
+            off: true,
+            ..Compressor::default()
         }
+    }
 
-        loop {
-            let len = value[0] as usize;
-            if len == 0 {
-                break;
-            }
+    pub(crate) fn saved(&self) -> usize {
+        self.saved
+    }
+}
 
-            if !name.is_empty() {
-                name.push('.');
-            }
+impl TryFrom<&[u8]> for Name {
+    type Error = ParseError;
 
-            name.push_str(&String::from_utf8_lossy(&value[1..=len]));
-            value = &value[len + 1..];
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Ok(Name(String::new()));
         }
-        Ok(Name(name))
+        Name::parse(value, 0).map(|(name, _)| name)
     }
 }
 
 impl From<&str> for Name {
     fn from(value: &str) -> Self {
-        Name(value.to_string())
+        Name(value.trim_end_matches('.').to_string())
     }
 }
 
 impl Name {
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn eq_ignore_case(&self, other: &Name) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+
+    // True when `self` is `parent` or lies below it.
+    pub(crate) fn is_subdomain_of(&self, parent: &Name) -> bool {
+        if parent.0.is_empty() || self.eq_ignore_case(parent) {
+            return true;
+        }
+        let (name, parent) = (self.0.as_bytes(), parent.0.as_bytes());
+        name.len() > parent.len()
+            && name[name.len() - parent.len()..].eq_ignore_ascii_case(parent)
+            && name[name.len() - parent.len() - 1] == b'.'
+    }
+
+    // Reads a possibly compressed name starting at `offset` and returns it
+    // together with the offset just past it.
+    pub(crate) fn parse(message: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let mut name = String::new();
+        let mut position = offset;
+        let mut end = None;
+        let mut pointers = 0;
+        let mut wire_len = 1;
+
+        loop {
+            let len = *message.get(position).ok_or(ParseError::UnexpectedEof)?;
+            match len & 0xC0 {
+                0x00 if len == 0 => break,
+                0x00 => {
+                    let start = position + 1;
+                    let label = message
+                        .get(start..start + len as usize)
+                        .ok_or(ParseError::UnexpectedEof)?;
+                    wire_len += label.len() + 1;
+                    if wire_len > MAX_NAME_LEN {
+                        return Err(ParseError::NameTooLong);
+                    }
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&String::from_utf8_lossy(label));
+                    position = start + label.len();
+                }
+                0xC0 => {
+                    let low = *message.get(position + 1).ok_or(ParseError::UnexpectedEof)?;
+                    end.get_or_insert(position + 2);
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err(ParseError::InvalidPointer);
+                    }
+                    position = (((len & 0x3F) as usize) << 8) | low as usize;
+                }
+                _ => return Err(ParseError::InvalidValue(len.into())),
+            }
+        }
+
+        Ok((Name(name), end.unwrap_or(position + 1)))
+    }
+
     pub(crate) fn len(&self) -> usize {
+        if self.0.is_empty() {
+            return 1;
+        }
         self.0.len() + 2
     }
 
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        for part in self.0.split('.') {
-            bytes.push(part.len() as u8);
-            bytes.extend_from_slice(part.as_bytes());
+        let mut bytes = Vec::with_capacity(self.len());
+        self.write(&mut bytes);
+        bytes
+    }
+
+    // Appends the uncompressed wire form.
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        if !self.0.is_empty() {
+            for part in self.0.split('.') {
+                bytes.push(part.len() as u8);
+                bytes.extend_from_slice(part.as_bytes());
+            }
+        }
+        bytes.push(0);
+    }
+
+    // Appends the wire form, ending in a pointer at the longest suffix
+    // written earlier in the message, and notes where the suffixes written
+    // out in full start.
+    pub(crate) fn write_compressed<'a>(&'a self, bytes: &mut Vec<u8>, names: &mut Compressor<'a>) {
+        if names.off {
+            return self.write(bytes);
+        }
+        let mut rest = self.0.as_str();
+        while !rest.is_empty() {
+            if let Some(&offset) = names.offsets.get(rest) {
+                bytes.extend_from_slice(&(0xc000 | offset).to_be_bytes());
+                // The suffix in full is its text, a length byte in place of
+                // the first label's dot and the root: two more than that.
+                names.saved += rest.len();
+                return;
+            }
+            if bytes.len() <= MAX_POINTER_OFFSET {
+                names.offsets.insert(rest, bytes.len() as u16);
+            }
+            let (label, tail) = rest.split_once('.').unwrap_or((rest, ""));
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+            rest = tail;
         }
         bytes.push(0);
-        bytes
     }
 }
 
-impl TryFrom<u16> for DnsType {
-    type Error = ParseError;
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+// Sets the 16-bit length at `at` to the number of bytes written after it.
+pub(crate) fn backpatch_length(bytes: &mut [u8], at: usize) {
+    let len = (bytes.len() - at - 2) as u16;
+    bytes[at..at + 2].copy_from_slice(&len.to_be_bytes());
+}
+
+impl DnsType {
+    // Whether a query of this type is answered by an address of that family.
+    pub(crate) fn wants_address(self, addr: IpAddr) -> bool {
+        match self {
+            DnsType::A => addr.is_ipv4(),
+            DnsType::Aaaa => addr.is_ipv6(),
+            DnsType::Any => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn code(self) -> u16 {
+        match self {
+            DnsType::A => 1,
+            DnsType::Ns => 2,
+            DnsType::Md => 3,
+            DnsType::Mf => 4,
+            DnsType::Cname => 5,
+            DnsType::Soa => 6,
+            DnsType::Mb => 7,
+            DnsType::Mg => 8,
+            DnsType::Mr => 9,
+            DnsType::Null => 10,
+            DnsType::Wks => 11,
+            DnsType::Ptr => 12,
+            DnsType::Hinfo => 13,
+            DnsType::Minfo => 14,
+            DnsType::Mx => 15,
+            DnsType::Txt => 16,
+            DnsType::Sig => 24,
+            DnsType::Key => 25,
+            DnsType::Aaaa => 28,
+            DnsType::Srv => 33,
+            DnsType::Naptr => 35,
+            DnsType::Opt => 41,
+            DnsType::Ds => 43,
+            DnsType::Sshfp => 44,
+            DnsType::Rrsig => 46,
+            DnsType::Nsec => 47,
+            DnsType::Dnskey => 48,
+            DnsType::Nsec3 => 50,
+            DnsType::Nsec3param => 51,
+            DnsType::Tlsa => 52,
+            DnsType::Cds => 59,
+            DnsType::Cdnskey => 60,
+            DnsType::Svcb => 64,
+            DnsType::Https => 65,
+            DnsType::Spf => 99,
+            DnsType::Tkey => 249,
+            DnsType::Tsig => 250,
+            DnsType::Ixfr => 251,
+            DnsType::Axfr => 252,
+            DnsType::Any => 255,
+            DnsType::Caa => 257,
+            DnsType::Alias => 65401,
+            DnsType::Weighted => 65402,
+            DnsType::Failover => 65403,
+            DnsType::Template => 65404,
+            DnsType::Unknown(code) => code,
+        }
+    }
+
+    // The RFC mnemonic, which types this server doesn't know lack.
+    fn mnemonic(self) -> Option<&'static str> {
+        let mnemonic = match self {
+            DnsType::A => "A",
+            DnsType::Ns => "NS",
+            DnsType::Md => "MD",
+            DnsType::Mf => "MF",
+            DnsType::Cname => "CNAME",
+            DnsType::Soa => "SOA",
+            DnsType::Mb => "MB",
+            DnsType::Mg => "MG",
+            DnsType::Mr => "MR",
+            DnsType::Null => "NULL",
+            DnsType::Wks => "WKS",
+            DnsType::Ptr => "PTR",
+            DnsType::Hinfo => "HINFO",
+            DnsType::Minfo => "MINFO",
+            DnsType::Mx => "MX",
+            DnsType::Txt => "TXT",
+            DnsType::Sig => "SIG",
+            DnsType::Key => "KEY",
+            DnsType::Aaaa => "AAAA",
+            DnsType::Srv => "SRV",
+            DnsType::Opt => "OPT",
+            DnsType::Tkey => "TKEY",
+            DnsType::Tsig => "TSIG",
+            DnsType::Any => "ANY",
+            DnsType::Naptr => "NAPTR",
+            DnsType::Ds => "DS",
+            DnsType::Sshfp => "SSHFP",
+            DnsType::Rrsig => "RRSIG",
+            DnsType::Nsec => "NSEC",
+            DnsType::Dnskey => "DNSKEY",
+            DnsType::Nsec3 => "NSEC3",
+            DnsType::Nsec3param => "NSEC3PARAM",
+            DnsType::Tlsa => "TLSA",
+            DnsType::Cds => "CDS",
+            DnsType::Cdnskey => "CDNSKEY",
+            DnsType::Svcb => "SVCB",
+            DnsType::Https => "HTTPS",
+            DnsType::Spf => "SPF",
+            DnsType::Ixfr => "IXFR",
+            DnsType::Axfr => "AXFR",
+            DnsType::Caa => "CAA",
+            DnsType::Alias => "ALIAS",
+            DnsType::Weighted => "WEIGHTED",
+            DnsType::Failover => "FAILOVER",
+            DnsType::Template => "TEMPLATE",
+            DnsType::Unknown(_) => return None,
+        };
+        Some(mnemonic)
+    }
+}
+
+// Types without a mnemonic are written as `TYPEnn` (RFC 3597 section 5).
+impl fmt::Display for DnsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mnemonic() {
+            Some(mnemonic) => f.write_str(mnemonic),
+            None => write!(f, "TYPE{}", self.code()),
+        }
+    }
+}
+
+// Accepts mnemonics in any case as well as the RFC 3597 `TYPEnn` form.
+impl FromStr for DnsType {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        if let Some(code) = upper
+            .strip_prefix("TYPE")
+            .and_then(|n| n.parse::<u16>().ok())
+        {
+            return Ok(DnsType::from(code));
+        }
+        (1..=DnsType::Caa.code())
+            .chain(
+                [
+                    DnsType::Alias,
+                    DnsType::Weighted,
+                    DnsType::Failover,
+                    DnsType::Template,
+                ]
+                .map(DnsType::code),
+            )
+            .map(DnsType::from)
+            .find(|qtype| qtype.mnemonic() == Some(upper.as_str()))
+            .ok_or(ParseError::InvalidMnemonic(s.to_string()))
+    }
+}
+
+impl DnsClass {
+    pub(crate) fn mnemonic(self) -> &'static str {
+        match self {
+            DnsClass::In => "IN",
+            DnsClass::Cs => "CS",
+            DnsClass::Ch => "CH",
+            DnsClass::Hs => "HS",
+            DnsClass::None => "NONE",
+            DnsClass::Any => "ANY",
+        }
+    }
+}
+
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+impl FromStr for DnsClass {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "IN" => Ok(DnsClass::In),
+            "CS" => Ok(DnsClass::Cs),
+            "CH" | "CHAOS" => Ok(DnsClass::Ch),
+            "HS" | "HESIOD" => Ok(DnsClass::Hs),
+            "NONE" => Ok(DnsClass::None),
+            "ANY" => Ok(DnsClass::Any),
+            _ => Err(ParseError::InvalidMnemonic(s.to_string())),
+        }
+    }
+}
+
+impl From<u16> for DnsType {
+    fn from(value: u16) -> Self {
         match value {
-            1 => Ok(DnsType::A),
-            2 => Ok(DnsType::Ns),
-            3 => Ok(DnsType::Md),
-            4 => Ok(DnsType::Mf),
-            5 => Ok(DnsType::Cname),
-            6 => Ok(DnsType::Soa),
-            7 => Ok(DnsType::Mb),
-            8 => Ok(DnsType::Mg),
-            9 => Ok(DnsType::Mr),
-            10 => Ok(DnsType::Null),
-            11 => Ok(DnsType::Wks),
-            12 => Ok(DnsType::Ptr),
-            13 => Ok(DnsType::Hinfo),
-            14 => Ok(DnsType::Minfo),
-            15 => Ok(DnsType::Mx),
-            16 => Ok(DnsType::Txt),
-            _ => Err(ParseError::InvalidValue(value as u8)),
+            1 => DnsType::A,
+            2 => DnsType::Ns,
+            3 => DnsType::Md,
+            4 => DnsType::Mf,
+            5 => DnsType::Cname,
+            6 => DnsType::Soa,
+            7 => DnsType::Mb,
+            8 => DnsType::Mg,
+            9 => DnsType::Mr,
+            10 => DnsType::Null,
+            11 => DnsType::Wks,
+            12 => DnsType::Ptr,
+            13 => DnsType::Hinfo,
+            14 => DnsType::Minfo,
+            15 => DnsType::Mx,
+            16 => DnsType::Txt,
+            24 => DnsType::Sig,
+            25 => DnsType::Key,
+            28 => DnsType::Aaaa,
+            33 => DnsType::Srv,
+            35 => DnsType::Naptr,
+            41 => DnsType::Opt,
+            43 => DnsType::Ds,
+            44 => DnsType::Sshfp,
+            46 => DnsType::Rrsig,
+            47 => DnsType::Nsec,
+            48 => DnsType::Dnskey,
+            50 => DnsType::Nsec3,
+            51 => DnsType::Nsec3param,
+            52 => DnsType::Tlsa,
+            59 => DnsType::Cds,
+            60 => DnsType::Cdnskey,
+            64 => DnsType::Svcb,
+            65 => DnsType::Https,
+            99 => DnsType::Spf,
+            249 => DnsType::Tkey,
+            250 => DnsType::Tsig,
+            251 => DnsType::Ixfr,
+            252 => DnsType::Axfr,
+            255 => DnsType::Any,
+            257 => DnsType::Caa,
+            65401 => DnsType::Alias,
+            65402 => DnsType::Weighted,
+            65403 => DnsType::Failover,
+            65404 => DnsType::Template,
+            code => DnsType::Unknown(code),
         }
     }
 }
@@ -117,14 +494,17 @@ impl TryFrom<u16> for DnsClass {
             2 => Ok(DnsClass::Cs),
             3 => Ok(DnsClass::Ch),
             4 => Ok(DnsClass::Hs),
-            _ => Err(ParseError::InvalidValue(value as u8)),
+            254 => Ok(DnsClass::None),
+            255 => Ok(DnsClass::Any),
+            _ => Err(ParseError::InvalidValue(value)),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Name;
+    use super::{DnsClass, DnsType, Name};
+    use crate::error::ParseError;
     use std::convert::TryFrom;
 
     #[test]
@@ -146,4 +526,73 @@ mod test {
             assert_eq!(name.0, expected);
         }
     }
+
+    #[test]
+    fn test_name_parse_compressed() {
+        // "example.com" at offset 0, then "www" followed by a pointer to it.
+        let message = b"\x07example\x03com\x00\x03www\xc0\x00";
+        let (name, end) = Name::parse(message, 0).unwrap();
+        assert_eq!((name.0.as_str(), end), ("example.com", 13));
+        let (name, end) = Name::parse(message, 13).unwrap();
+        assert_eq!((name.0.as_str(), end), ("www.example.com", 19));
+    }
+
+    #[test]
+    fn test_name_parse_malformed() {
+        assert_eq!(
+            Name::parse(b"\x07exam", 0).map(|(n, _)| n.0),
+            Err(ParseError::UnexpectedEof)
+        );
+        assert_eq!(
+            Name::parse(b"\xc0\x00", 0).map(|(n, _)| n.0),
+            Err(ParseError::InvalidPointer)
+        );
+        assert_eq!(
+            Name::parse(b"\x40abc", 0).map(|(n, _)| n.0),
+            Err(ParseError::InvalidValue(0x40))
+        );
+        let long = b"\x3faaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".repeat(5);
+        assert_eq!(
+            Name::parse(&long, 0).map(|(n, _)| n.0),
+            Err(ParseError::NameTooLong)
+        );
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        assert_eq!("aaaa".parse(), Ok(DnsType::Aaaa));
+        assert_eq!("TYPE16".parse(), Ok(DnsType::Txt));
+        assert_eq!(DnsType::Srv.to_string(), "SRV");
+        assert_eq!("type65280".parse(), Ok(DnsType::Unknown(65280)));
+        assert_eq!(DnsType::from(39).to_string(), "TYPE39");
+        assert_eq!(DnsType::from(28), DnsType::Aaaa);
+        assert_eq!(DnsType::Unknown(256).code(), 256);
+        assert_eq!("chaos".parse(), Ok(DnsClass::Ch));
+        assert_eq!(
+            "BOGUS".parse::<DnsType>(),
+            Err(ParseError::InvalidMnemonic("BOGUS".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_is_subdomain_of() {
+        let zone = Name::from("example.com");
+        assert!(Name::from("example.com").is_subdomain_of(&zone));
+        assert!(Name::from("WWW.Example.COM").is_subdomain_of(&zone));
+        assert!(Name::from("a.b.example.com").is_subdomain_of(&zone));
+        assert!(!Name::from("badexample.com").is_subdomain_of(&zone));
+        assert!(!Name::from("com").is_subdomain_of(&zone));
+        assert!(Name::from("com").is_subdomain_of(&Name::from(".")));
+    }
+
+    #[test]
+    fn test_root_name() {
+        let root = Name::from(".");
+        assert_eq!(root.to_bytes(), vec![0]);
+        assert_eq!(root.len(), 1);
+        assert_eq!(
+            Name::from("example.com.").to_bytes(),
+            b"\x07example\x03com\x00"
+        );
+    }
 }
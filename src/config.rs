@@ -0,0 +1,1154 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::acl::Acl;
+use crate::blocklist::{self, BlockStyle, BlocklistSpec};
+use crate::cache::TtlBounds;
+use crate::chain::{self, QueryHandler, DEFAULT_PLUGINS};
+use crate::clients::ClientGroup;
+use crate::common::Name;
+use crate::connections::TcpLimits;
+use crate::consul::ConsulConfig;
+use crate::error::ConfigError;
+use crate::fault::Faults;
+use crate::forward::UpstreamGroup;
+use crate::handoff::HandoffConfig;
+use crate::kubernetes::KubernetesConfig;
+use crate::policy::Policies;
+use crate::privacy::Anonymizer;
+use crate::querylog::QueryLogConfig;
+use crate::queue::{Overflow, QueueLimits};
+use crate::recursor::Limits;
+use crate::rewrite::Rewriter;
+use crate::safesearch::Sites;
+use crate::schedule::Schedule;
+use crate::secondary::SecondaryConfig;
+use crate::sinkhole::SinkholeConfig;
+use crate::transfer::Transfers;
+use crate::ttl;
+use crate::update::Updates;
+use crate::warm::WarmConfig;
+use crate::webhook::WebhookConfig;
+
+pub(crate) const DEFAULT_CONTROL_SOCKET: &str = "/tmp/dns-server.sock";
+pub(crate) const DEFAULT_LISTEN: &str = "127.0.0.1:2053";
+// Fits the common 1280-byte IPv6 MTU, avoiding fragmentation (DNS Flag Day
+// 2020).
+pub(crate) const DEFAULT_MAX_UDP_SIZE: u16 = 1232;
+// Stays under the 5s most stub resolvers wait before retrying.
+pub(crate) const DEFAULT_QUERY_BUDGET: Duration = Duration::from_secs(3);
+
+pub(crate) struct Config {
+    pub(crate) listeners: Vec<Listener>,
+    pub(crate) upstreams: Vec<UpstreamGroup>,
+    pub(crate) forward_zones: Vec<(Name, String)>,
+    // Zones served authoritatively: an optional origin and the master file.
+    pub(crate) zones: Vec<(Option<Name>, PathBuf)>,
+    // ANY queries to the zones get one RRset rather than all of them.
+    pub(crate) minimal_any: bool,
+    pub(crate) query_budget: Duration,
+    pub(crate) recursion: bool,
+    pub(crate) recursion_limits: Limits,
+    pub(crate) allow_recursion: Acl,
+    // A named.root file to start recursion from instead of the built-in one.
+    pub(crate) root_hints: Option<PathBuf>,
+    // Where the root trust anchors are kept across key rollovers.
+    pub(crate) trust_anchor_file: Option<PathBuf>,
+    // Answer from cached NSEC and NSEC3 ranges (RFC 8198).
+    pub(crate) aggressive_nsec: bool,
+    pub(crate) stats_interval: Option<Duration>,
+    pub(crate) control_socket: Option<PathBuf>,
+    pub(crate) admin_listen: Option<SocketAddr>,
+    // Where SIGUSR1 diagnostics dumps are appended; the log without one.
+    pub(crate) diagnostics_file: Option<PathBuf>,
+    // The bearer token the admin API's ACME endpoints require; they are
+    // off without one.
+    pub(crate) acme_token: Option<String>,
+    pub(crate) chaos: ChaosConfig,
+    pub(crate) nsid: Option<Vec<u8>>,
+    pub(crate) multi_question: MultiQuestion,
+    // The EDNS buffer size advertised to clients and upstreams, and the
+    // largest UDP response sent whatever size the client advertises.
+    pub(crate) max_udp_size: u16,
+    // By zone, the root for every name (see ttl::parse).
+    pub(crate) forced_ttls: Vec<(Name, u32)>,
+    // Percent by which TTLs served from the cache may be shortened at random.
+    pub(crate) ttl_jitter: u32,
+    // Off with `--no-compression`, for clients that mishandle pointers.
+    pub(crate) compression: bool,
+    pub(crate) tcp: TcpLimits,
+    pub(crate) queue: QueueLimits,
+    // IN-class queries pass through these in order.
+    pub(crate) plugins: Vec<&'static dyn QueryHandler>,
+    pub(crate) rewrites: Rewriter,
+    pub(crate) ttl_bounds: TtlBounds,
+    pub(crate) sinkhole: Option<SinkholeConfig>,
+    pub(crate) consul: Option<ConsulConfig>,
+    pub(crate) kubernetes: Option<KubernetesConfig>,
+    pub(crate) webhook: Option<WebhookConfig>,
+    pub(crate) warm: Option<WarmConfig>,
+    pub(crate) handoff: Option<HandoffConfig>,
+    pub(crate) secondaries: Vec<SecondaryConfig>,
+    pub(crate) query_log: Option<QueryLogConfig>,
+    // How clients appear in logs and metrics.
+    pub(crate) anonymize: Option<Anonymizer>,
+    pub(crate) policies: Policies,
+    pub(crate) transfers: Transfers,
+    pub(crate) updates: Updates,
+    // Deliberate misbehaviour, for testing clients.
+    pub(crate) faults: Faults,
+    // Blocklist names and files, loaded into State.
+    pub(crate) blocklists: Vec<BlocklistSpec>,
+    // How blocked names are answered, unless their list says otherwise.
+    pub(crate) block_style: BlockStyle,
+    pub(crate) client_groups: Vec<ClientGroup>,
+    // Sites whose SafeSearch is forced, unless a client group says otherwise.
+    pub(crate) safesearch: Sites,
+    // By name, for the lists and groups that give one.
+    pub(crate) schedules: Vec<Schedule>,
+    // Blocklists, rewrites and policies only log what they would have done.
+    pub(crate) log_only: bool,
+    // The flags as given, for diagnostics dumps.
+    pub(crate) args: Vec<String>,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Protocol {
+    Udp,
+    Tcp,
+}
+
+// One `--listen` address. Interfaces are selected by their address; `[::]`
+// is dual-stack where the OS allows it, so IPv4 clients show up as
+// IPv4-mapped addresses on it. Queries arriving on a listener with a view
+// are answered from that view.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Listener {
+    pub(crate) addr: SocketAddr,
+    pub(crate) protocols: Vec<Protocol>,
+    pub(crate) view: Option<String>,
+}
+
+// What to do with a query carrying more than one question. No resolver in
+// the wild accepts them (RFC 9619), so by default they get FORMERR.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum MultiQuestion {
+    FormErr,
+    First,
+}
+
+impl MultiQuestion {
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "formerr" => Ok(MultiQuestion::FormErr),
+            "first" => Ok(MultiQuestion::First),
+            _ => Err(format!("expected formerr or first, got {:?}", text)),
+        }
+    }
+}
+
+// Values served for CH TXT version.bind / hostname.bind / id.server. A name
+// without a value, or every name when hidden, is answered with REFUSED.
+pub(crate) struct ChaosConfig {
+    pub(crate) version: Option<String>,
+    pub(crate) hostname: Option<String>,
+    pub(crate) id: Option<String>,
+    pub(crate) hidden: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listeners: vec![Listener::parse(DEFAULT_LISTEN).unwrap()],
+            upstreams: Vec::new(),
+            forward_zones: Vec::new(),
+            zones: Vec::new(),
+            minimal_any: false,
+            query_budget: DEFAULT_QUERY_BUDGET,
+            recursion: false,
+            recursion_limits: Limits::default(),
+            allow_recursion: Acl::local(),
+            root_hints: None,
+            trust_anchor_file: None,
+            aggressive_nsec: false,
+            stats_interval: None,
+            control_socket: Some(PathBuf::from(DEFAULT_CONTROL_SOCKET)),
+            admin_listen: None,
+            diagnostics_file: None,
+            acme_token: None,
+            chaos: ChaosConfig::default(),
+            nsid: None,
+            multi_question: MultiQuestion::FormErr,
+            max_udp_size: DEFAULT_MAX_UDP_SIZE,
+            compression: true,
+            forced_ttls: Vec::new(),
+            ttl_jitter: 0,
+            tcp: TcpLimits::default(),
+            queue: QueueLimits::default(),
+            plugins: chain::parse(DEFAULT_PLUGINS).unwrap(),
+            rewrites: Rewriter::default(),
+            ttl_bounds: TtlBounds::default(),
+            sinkhole: None,
+            consul: None,
+            kubernetes: None,
+            webhook: None,
+            warm: None,
+            handoff: None,
+            secondaries: Vec::new(),
+            query_log: None,
+            anonymize: None,
+            policies: Policies::default(),
+            transfers: Transfers::default(),
+            updates: Updates::default(),
+            faults: Faults::default(),
+            blocklists: Vec::new(),
+            block_style: BlockStyle::NxDomain,
+            client_groups: Vec::new(),
+            safesearch: Sites::default(),
+            schedules: Vec::new(),
+            log_only: false,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            version: Some(format!("dns-server {}", env!("CARGO_PKG_VERSION"))),
+            hostname: None,
+            id: None,
+            hidden: false,
+        }
+    }
+}
+
+impl Listener {
+    // `addr:port[,udp|,tcp][,view=name]`, serving both protocols unless one
+    // is named.
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let addr = parts.next().unwrap_or_default();
+        let mut listener = Listener {
+            addr: addr
+                .parse()
+                .map_err(|_| format!("bad address {:?}, expected addr:port", addr))?,
+            protocols: Vec::new(),
+            view: None,
+        };
+        for part in parts {
+            let protocol = match part {
+                "udp" => Protocol::Udp,
+                "tcp" => Protocol::Tcp,
+                _ => match part.strip_prefix("view=") {
+                    Some(view) if !view.is_empty() => {
+                        listener.view = Some(view.to_string());
+                        continue;
+                    }
+                    _ => return Err(format!("unknown listener option {:?}", part)),
+                },
+            };
+            if !listener.protocols.contains(&protocol) {
+                listener.protocols.push(protocol);
+            }
+        }
+        if listener.protocols.is_empty() {
+            listener.protocols = vec![Protocol::Udp, Protocol::Tcp];
+        }
+        Ok(listener)
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Udp => write!(f, "udp"),
+            Protocol::Tcp => write!(f, "tcp"),
+        }
+    }
+}
+
+impl Config {
+    // The client as logs and metrics show it.
+    pub(crate) fn logged_client(&self, client: IpAddr) -> IpAddr {
+        match &self.anonymize {
+            Some(anonymizer) => anonymizer.apply(client),
+            None => client,
+        }
+    }
+
+    pub(crate) fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = Config {
+            args: args.clone(),
+            ..Config::default()
+        };
+        let mut listeners = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::MissingValue(flag.clone()))
+            };
+            match flag.as_str() {
+                "--listen" => {
+                    let listener = Listener::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    listeners.push(listener);
+                }
+                "--upstream" => {
+                    let group = UpstreamGroup::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config
+                        .upstreams
+                        .retain(|upstream| upstream.name != group.name);
+                    config.upstreams.push(group);
+                }
+                "--forward" => {
+                    let spec = value()?;
+                    let Some((zone, group)) = spec.split_once('=') else {
+                        return Err(ConfigError::InvalidValue(flag, spec));
+                    };
+                    config
+                        .forward_zones
+                        .push((Name::from(zone), group.to_string()));
+                }
+                "--zone" => {
+                    let spec = value()?;
+                    let zone = match spec.split_once('=') {
+                        Some((origin, path)) => (Some(Name::from(origin)), path),
+                        None => (None, spec.as_str()),
+                    };
+                    if zone.1.is_empty() {
+                        return Err(ConfigError::InvalidValue(flag, spec));
+                    }
+                    config.zones.push((zone.0, PathBuf::from(zone.1)));
+                }
+                "--secondary" => {
+                    let secondary = SecondaryConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.secondaries.push(secondary);
+                }
+                "--query-budget" => {
+                    let millis = parse_number(&flag, &value()?)?;
+                    config.query_budget = Duration::from_millis(millis.max(1));
+                }
+                "--recursion" => config.recursion = true,
+                "--allow-recursion" => {
+                    config.allow_recursion = Acl::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--root-hints" => config.root_hints = Some(PathBuf::from(value()?)),
+                "--trust-anchor-file" => config.trust_anchor_file = Some(PathBuf::from(value()?)),
+                "--aggressive-nsec" => config.aggressive_nsec = true,
+                "--max-referrals" => {
+                    config.recursion_limits.referrals = parse_number(&flag, &value()?)? as usize
+                }
+                "--max-cname-depth" => {
+                    config.recursion_limits.cname_depth = parse_number(&flag, &value()?)? as usize
+                }
+                "--max-upstream-queries" => {
+                    config.recursion_limits.queries = parse_number(&flag, &value()?)? as usize
+                }
+                "--tcp-idle-timeout" => {
+                    let secs = parse_number(&flag, &value()?)?;
+                    config.tcp.idle = Duration::from_secs(secs.max(1));
+                }
+                "--tcp-lifetime" => {
+                    let secs = parse_number(&flag, &value()?)?;
+                    config.tcp.lifetime = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--tcp-max-connections" => {
+                    config.tcp.max_connections = parse_number(&flag, &value()?)?.max(1) as usize
+                }
+                "--workers" => {
+                    config.queue.workers = parse_number(&flag, &value()?)?.max(1) as usize
+                }
+                "--queue-limit" => {
+                    config.queue.size = parse_number(&flag, &value()?)?.max(1) as usize
+                }
+                "--queue-drop" => {
+                    config.queue.overflow = Overflow::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--stats-interval" => {
+                    let secs = parse_number(&flag, &value()?)?;
+                    config.stats_interval = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--control-socket" => {
+                    let path = value()?;
+                    config.control_socket = (!path.is_empty()).then(|| PathBuf::from(path));
+                }
+                "--diagnostics-file" => {
+                    let path = value()?;
+                    config.diagnostics_file = (!path.is_empty()).then(|| PathBuf::from(path));
+                }
+                "--admin-listen" => {
+                    let text = value()?;
+                    let addr = text.parse().map_err(|_| {
+                        ConfigError::InvalidValue(flag.clone(), format!("bad address {:?}", text))
+                    })?;
+                    config.admin_listen = Some(addr);
+                }
+                "--acme-token" => {
+                    let token = value()?;
+                    if token.is_empty() {
+                        return Err(ConfigError::InvalidValue(flag, "empty token".into()));
+                    }
+                    config.acme_token = Some(token);
+                }
+                "--chaos-version" => config.chaos.version = Some(value()?),
+                "--chaos-hostname" => config.chaos.hostname = Some(value()?),
+                "--chaos-id" => config.chaos.id = Some(value()?),
+                "--hide-chaos" => config.chaos.hidden = true,
+                "--nsid" => config.nsid = Some(value()?.into_bytes()),
+                "--max-udp-size" => {
+                    let text = value()?;
+                    config.max_udp_size = parse_number(&flag, &text)?
+                        .try_into()
+                        .ok()
+                        .filter(|size| *size >= 512)
+                        .ok_or_else(|| {
+                            ConfigError::InvalidValue(
+                                flag.clone(),
+                                format!("{} is not in 512..=65535", text),
+                            )
+                        })?;
+                }
+                "--plugins" => {
+                    config.plugins = chain::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--rewrite" => config
+                    .rewrites
+                    .add(&value()?)
+                    .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?,
+                "--policy" => config
+                    .policies
+                    .add(&value()?)
+                    .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?,
+                "--fault" => config
+                    .faults
+                    .add(&value()?)
+                    .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?,
+                "--transfer" => config
+                    .transfers
+                    .add(&value()?)
+                    .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?,
+                "--allow-update" => config
+                    .updates
+                    .add(&value()?)
+                    .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?,
+                "--blocklist" => {
+                    let list = blocklist::parse_spec(&value()?, false)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.blocklists.retain(|other| other.name != list.name);
+                    config.blocklists.push(list);
+                }
+                "--allowlist" => {
+                    let list = blocklist::parse_spec(&value()?, true)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.blocklists.retain(|other| other.name != list.name);
+                    config.blocklists.push(list);
+                }
+                "--schedule" => {
+                    let schedule = Schedule::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.schedules.retain(|other| other.name != schedule.name);
+                    config.schedules.push(schedule);
+                }
+                "--block-style" => {
+                    config.block_style = BlockStyle::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--client-group" => {
+                    let group = ClientGroup::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.client_groups.push(group);
+                }
+                "--safesearch" => {
+                    config.safesearch = Sites::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--log-only" => config.log_only = true,
+                "--minimal-any" => config.minimal_any = true,
+                "--no-compression" => config.compression = false,
+                "--min-ttl" => config.ttl_bounds.min = parse_ttl(&flag, &value()?)?,
+                "--max-ttl" => config.ttl_bounds.max = parse_ttl(&flag, &value()?)?,
+                "--force-ttl" => {
+                    let forced = ttl::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.forced_ttls.push(forced);
+                }
+                "--ttl-jitter" => {
+                    config.ttl_jitter = ttl::parse_jitter(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                "--sinkhole" => {
+                    let sinkhole = SinkholeConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.sinkhole = Some(sinkhole);
+                }
+                "--consul" => {
+                    let consul = ConsulConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.consul = Some(consul);
+                }
+                "--kubernetes" => {
+                    let kubernetes = KubernetesConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.kubernetes = Some(kubernetes);
+                }
+                "--webhook" => {
+                    let webhook = WebhookConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.webhook = Some(webhook);
+                }
+                "--warm" => {
+                    let warm = WarmConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.warm = Some(warm);
+                }
+                "--handoff" => {
+                    let handoff = HandoffConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.handoff = Some(handoff);
+                }
+                "--query-log" => {
+                    let query_log = QueryLogConfig::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.query_log = Some(query_log);
+                }
+                "--anonymize-clients" => {
+                    let anonymizer = Anonymizer::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?;
+                    config.anonymize = Some(anonymizer);
+                }
+                "--multi-question" => {
+                    config.multi_question = MultiQuestion::parse(&value()?)
+                        .map_err(|e| ConfigError::InvalidValue(flag.clone(), e))?
+                }
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+        if !listeners.is_empty() {
+            config.listeners = listeners;
+        }
+        if config.ttl_bounds.min > config.ttl_bounds.max {
+            return Err(ConfigError::InvalidValue(
+                "--min-ttl".into(),
+                format!(
+                    "{} is above --max-ttl {}",
+                    config.ttl_bounds.min, config.ttl_bounds.max
+                ),
+            ));
+        }
+        for group in &config.client_groups {
+            for list in group.blocklists.iter().flatten() {
+                if !config.blocklists.iter().any(|spec| spec.name == *list) {
+                    return Err(ConfigError::InvalidValue(
+                        "--client-group".into(),
+                        format!("{}: no such blocklist {}", group.name, list),
+                    ));
+                }
+            }
+        }
+        let named = config
+            .blocklists
+            .iter()
+            .map(|list| ("--blocklist", &list.name, &list.schedule))
+            .chain(
+                config
+                    .client_groups
+                    .iter()
+                    .map(|group| ("--client-group", &group.name, &group.schedule)),
+            );
+        for (flag, name, schedule) in named {
+            if let Some(schedule) = schedule {
+                if !config.schedules.iter().any(|other| other.name == *schedule) {
+                    return Err(ConfigError::InvalidValue(
+                        flag.into(),
+                        format!("{}: no such schedule {}", name, schedule),
+                    ));
+                }
+            }
+        }
+        for (zone, group) in &config.forward_zones {
+            if !config
+                .upstreams
+                .iter()
+                .any(|upstream| &upstream.name == group)
+            {
+                return Err(ConfigError::InvalidValue(
+                    "--forward".into(),
+                    format!("{}={}: no such upstream group", zone, group),
+                ));
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Flags that take no value.
+const SWITCHES: [&str; 6] = [
+    "--recursion",
+    "--aggressive-nsec",
+    "--hide-chaos",
+    "--log-only",
+    "--minimal-any",
+    "--no-compression",
+];
+
+// Flags that can be given more than once, each adding to the others.
+const REPEATABLE: [&str; 15] = [
+    "--listen",
+    "--upstream",
+    "--forward",
+    "--zone",
+    "--secondary",
+    "--rewrite",
+    "--policy",
+    "--fault",
+    "--transfer",
+    "--allow-update",
+    "--blocklist",
+    "--allowlist",
+    "--schedule",
+    "--client-group",
+    "--force-ttl",
+];
+
+// Every flag can also be set from the environment, so that containers run
+// without mounting anything: `DNS_SERVER_MAX_TTL=300` is `--max-ttl 300`.
+// Values are split on whitespace into one flag each, for the flags that can
+// be repeated, as in `DNS_SERVER_LISTEN="0.0.0.0:53 [::]:53"`. Flags without
+// a value are given by `1`, `true` or `yes`, and left out by `0`, `false`,
+// `no` or nothing. The variables come in name order, before the command
+// line, so flags given there win or add to them. Variables that aren't
+// UTF-8 are skipped, unless they are ours.
+const ENV_PREFIX: &str = "DNS_SERVER_";
+
+pub(crate) fn env_args(
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Result<Vec<String>, ConfigError> {
+    let mut ours = Vec::new();
+    for (name, value) in vars {
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        let Some(flag) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let flag = format!("--{}", flag.to_ascii_lowercase().replace('_', "-"));
+        let value = value.into_string().map_err(|value| {
+            ConfigError::InvalidValue(name.to_string(), value.to_string_lossy().into_owned())
+        })?;
+        ours.push((name.to_string(), flag, value));
+    }
+    ours.sort();
+    let mut args = Vec::new();
+    for (name, flag, value) in ours {
+        if SWITCHES.contains(&flag.as_str()) {
+            match value.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" => args.push(flag),
+                "" | "0" | "false" | "no" => {}
+                _ => return Err(ConfigError::InvalidValue(name, value)),
+            }
+            continue;
+        }
+        if REPEATABLE.contains(&flag.as_str()) {
+            for value in value.split_whitespace() {
+                args.extend([flag.clone(), value.to_string()]);
+            }
+        } else {
+            args.extend([flag, value.trim().to_string()]);
+        }
+    }
+    Ok(args)
+}
+
+pub(crate) fn parse_number(flag: &str, value: &str) -> Result<u64, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidValue(flag.to_string(), value.to_string()))
+}
+
+// TTLs are at most 2^31 - 1 (RFC 2181 section 8).
+fn parse_ttl(flag: &str, value: &str) -> Result<u32, ConfigError> {
+    parse_number(flag, value)?
+        .try_into()
+        .ok()
+        .filter(|ttl| *ttl <= i32::MAX as u32)
+        .ok_or_else(|| ConfigError::InvalidValue(flag.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::ffi::OsStringExt;
+
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_stats_interval() {
+        let config = Config::from_args(args(&["--stats-interval", "30"])).unwrap();
+        assert_eq!(config.stats_interval, Some(Duration::from_secs(30)));
+        let config = Config::from_args(args(&["--stats-interval", "0"])).unwrap();
+        assert_eq!(config.stats_interval, None);
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(
+            config.control_socket,
+            Some(PathBuf::from(DEFAULT_CONTROL_SOCKET))
+        );
+        let config = Config::from_args(args(&["--control-socket", "/run/dns.sock"])).unwrap();
+        assert_eq!(config.control_socket, Some(PathBuf::from("/run/dns.sock")));
+        let config = Config::from_args(args(&["--control-socket", ""])).unwrap();
+        assert_eq!(config.control_socket, None);
+    }
+
+    #[test]
+    fn test_admin_listen() {
+        assert_eq!(Config::from_args(args(&[])).unwrap().admin_listen, None);
+        let config = Config::from_args(args(&["--admin-listen", "127.0.0.1:8053"])).unwrap();
+        assert_eq!(config.admin_listen, Some("127.0.0.1:8053".parse().unwrap()));
+        assert!(Config::from_args(args(&["--admin-listen", "localhost"])).is_err());
+        let config = Config::from_args(args(&["--acme-token", "s3cret"])).unwrap();
+        assert_eq!(config.acme_token.as_deref(), Some("s3cret"));
+        assert!(Config::from_args(args(&["--acme-token", ""])).is_err());
+    }
+
+    #[test]
+    fn test_listen() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.listeners.len(), 1);
+        assert_eq!(config.listeners[0].addr, DEFAULT_LISTEN.parse().unwrap());
+        assert_eq!(
+            config.listeners[0].protocols,
+            vec![Protocol::Udp, Protocol::Tcp]
+        );
+
+        let config = Config::from_args(args(&[
+            "--listen",
+            "[::]:53,udp",
+            "--listen",
+            "10.0.0.1:5353,tcp,view=internal",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.listeners,
+            vec![
+                Listener {
+                    addr: "[::]:53".parse().unwrap(),
+                    protocols: vec![Protocol::Udp],
+                    view: None,
+                },
+                Listener {
+                    addr: "10.0.0.1:5353".parse().unwrap(),
+                    protocols: vec![Protocol::Tcp],
+                    view: Some("internal".into()),
+                },
+            ]
+        );
+
+        for bad in [
+            "localhost:53",
+            "127.0.0.1",
+            "127.0.0.1:53,sctp",
+            "[::]:53,view=",
+        ] {
+            assert!(matches!(
+                Config::from_args(args(&["--listen", bad])),
+                Err(ConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_forward() {
+        let config = Config::from_args(args(&[
+            "--upstream",
+            "corp=10.0.0.53,source=10.8.0.2",
+            "--forward",
+            "corp.example.=corp",
+        ]))
+        .unwrap();
+        assert_eq!(config.upstreams[0].name, "corp");
+        assert_eq!(
+            config.forward_zones,
+            vec![(Name::from("corp.example"), "corp".to_string())]
+        );
+
+        assert!(matches!(
+            Config::from_args(args(&["--forward", ".=wan"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--upstream", "wan"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_zone() {
+        let config = Config::from_args(args(&[
+            "--zone",
+            "example.com=/etc/zones/example.com",
+            "--zone",
+            "db.local",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.zones,
+            vec![
+                (
+                    Some(Name::from("example.com")),
+                    PathBuf::from("/etc/zones/example.com")
+                ),
+                (None, PathBuf::from("db.local")),
+            ]
+        );
+        assert!(matches!(
+            Config::from_args(args(&["--zone", "example.com="])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_multi_question() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.multi_question, MultiQuestion::FormErr);
+        let config = Config::from_args(args(&["--multi-question", "first"])).unwrap();
+        assert_eq!(config.multi_question, MultiQuestion::First);
+        assert!(matches!(
+            Config::from_args(args(&["--multi-question", "all"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_max_udp_size() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.max_udp_size, DEFAULT_MAX_UDP_SIZE);
+        let config = Config::from_args(args(&["--max-udp-size", "4096"])).unwrap();
+        assert_eq!(config.max_udp_size, 4096);
+        for bad in ["511", "65536", "big"] {
+            assert!(matches!(
+                Config::from_args(args(&["--max-udp-size", bad])),
+                Err(ConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_tcp() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.tcp, TcpLimits::default());
+        let config = Config::from_args(args(&[
+            "--tcp-idle-timeout",
+            "30",
+            "--tcp-lifetime",
+            "0",
+            "--tcp-max-connections",
+            "1000",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.tcp,
+            TcpLimits {
+                idle: Duration::from_secs(30),
+                lifetime: None,
+                max_connections: 1000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_queue() {
+        let config = Config::from_args(args(&[
+            "--workers",
+            "0",
+            "--queue-limit",
+            "64",
+            "--queue-drop",
+            "oldest",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.queue,
+            QueueLimits {
+                workers: 1,
+                size: 64,
+                overflow: Overflow::Oldest,
+            }
+        );
+        assert!(matches!(
+            Config::from_args(args(&["--queue-drop", "random"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_plugins() {
+        let names = |config: Config| {
+            config
+                .plugins
+                .iter()
+                .map(|plugin| plugin.name())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(Config::default()).len(), 11);
+        let config = Config::from_args(args(&["--plugins", "log,zones"])).unwrap();
+        assert_eq!(names(config), vec!["log", "zones"]);
+        assert!(matches!(
+            Config::from_args(args(&["--plugins", "zones,nope"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let config = Config::from_args(args(&[
+            "--rewrite",
+            "name:docker=docker.internal",
+            "--rewrite",
+            "strip-aaaa:example.com",
+        ]))
+        .unwrap();
+        assert!(config.rewrites.rename(&"web.docker".into()).is_some());
+        assert!(matches!(
+            Config::from_args(args(&["--rewrite", "name:docker"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+        assert!(!config.log_only);
+        let config = Config::from_args(args(&["--log-only"])).unwrap();
+        assert!(config.log_only);
+    }
+
+    #[test]
+    fn test_block_style() {
+        let config = Config::from_args(args(&[
+            "--blocklist",
+            "ads=ads.txt",
+            "--blocklist",
+            "adult=adult.txt,style=192.0.2.80",
+            "--block-style",
+            "null",
+            "--allowlist",
+            "ok=ok.txt",
+        ]))
+        .unwrap();
+        assert!(config.blocklists[2].allow);
+        assert_eq!(config.block_style, BlockStyle::Null);
+        assert_eq!(config.blocklists[0].style, None);
+        assert!(matches!(
+            config.blocklists[1].style,
+            Some(BlockStyle::Address(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--block-style", "drop"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let config = Config::from_args(args(&[
+            "--schedule",
+            "work=mon-fri@09:00-17:00,tz=UTC",
+            "--blocklist",
+            "social=social.txt,schedule=work",
+            "--client-group",
+            "kids=10.0.0.0/8,schedule=work",
+        ]))
+        .unwrap();
+        assert_eq!(config.schedules[0].name, "work");
+        assert_eq!(config.blocklists[0].schedule.as_deref(), Some("work"));
+        for bad in [
+            &["--schedule", "work=9-5"][..],
+            &["--blocklist", "social=social.txt,schedule=work"],
+            &["--client-group", "kids=10.0.0.0/8,schedule=work"],
+        ] {
+            assert!(matches!(
+                Config::from_args(args(bad)),
+                Err(ConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_ttl_bounds() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.ttl_bounds, TtlBounds::default());
+        let config = Config::from_args(args(&["--min-ttl", "60", "--max-ttl", "3600"])).unwrap();
+        assert_eq!(config.ttl_bounds, TtlBounds { min: 60, max: 3600 });
+        for bad in [
+            &["--min-ttl", "600", "--max-ttl", "60"][..],
+            &["--max-ttl", "4294967295"],
+            &["--min-ttl", "-1"],
+        ] {
+            assert!(matches!(
+                Config::from_args(args(bad)),
+                Err(ConfigError::InvalidValue(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_query_budget() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.query_budget, DEFAULT_QUERY_BUDGET);
+        let config = Config::from_args(args(&["--query-budget", "1500"])).unwrap();
+        assert_eq!(config.query_budget, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_query_log() {
+        assert_eq!(Config::from_args(args(&[])).unwrap().query_log, None);
+        let config = Config::from_args(args(&["--query-log", "queries.jsonl,keep=2"])).unwrap();
+        assert_eq!(config.query_log.unwrap().keep, 2);
+        assert!(matches!(
+            Config::from_args(args(&["--query-log", "queries.jsonl,rotate"])),
+            Err(ConfigError::InvalidValue(..))
+        ));
+    }
+
+    #[test]
+    fn test_anonymize_clients() {
+        let client = "192.0.2.77".parse().unwrap();
+        let config = Config::from_args(args(&[])).unwrap();
+        assert_eq!(config.logged_client(client), client);
+        let config = Config::from_args(args(&["--anonymize-clients", "truncate"])).unwrap();
+        assert_eq!(
+            config.logged_client(client),
+            "192.0.2.0".parse::<IpAddr>().unwrap()
+        );
+        assert!(Config::from_args(args(&["--anonymize-clients", "drop"])).is_err());
+    }
+
+    #[test]
+    fn test_diagnostics_file() {
+        let config = Config::from_args(args(&["--diagnostics-file", "/tmp/dump"])).unwrap();
+        assert_eq!(config.diagnostics_file, Some(PathBuf::from("/tmp/dump")));
+        assert_eq!(config.args, args(&["--diagnostics-file", "/tmp/dump"]));
+        assert_eq!(Config::default().diagnostics_file, None);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let config = Config::from_args(args(&[])).unwrap();
+        assert!(!config.recursion);
+        assert_eq!(config.root_hints, None);
+        assert!(!config.aggressive_nsec);
+        let config = Config::from_args(args(&[
+            "--recursion",
+            "--max-cname-depth",
+            "4",
+            "--root-hints",
+            "/etc/named.root",
+            "--trust-anchor-file",
+            "/var/lib/dns/root.key",
+            "--aggressive-nsec",
+        ]))
+        .unwrap();
+        assert!(config.recursion);
+        assert!(config.aggressive_nsec);
+        assert_eq!(config.allow_recursion, Acl::local());
+        assert_eq!(config.root_hints, Some(PathBuf::from("/etc/named.root")));
+        assert_eq!(
+            config.trust_anchor_file,
+            Some(PathBuf::from("/var/lib/dns/root.key"))
+        );
+        assert_eq!(
+            config.recursion_limits,
+            Limits {
+                cname_depth: 4,
+                ..Limits::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_chaos() {
+        let config = Config::from_args(args(&["--chaos-hostname", "ns1", "--hide-chaos"])).unwrap();
+        assert_eq!(config.chaos.hostname.as_deref(), Some("ns1"));
+        assert_eq!(config.chaos.id, None);
+        assert!(config.chaos.hidden);
+    }
+
+    #[test]
+    fn test_env_args() {
+        let vars = [
+            ("DNS_SERVER_LISTEN", "0.0.0.0:5353\n[::]:5353,udp"),
+            ("DNS_SERVER_RECURSION", "true"),
+            ("DNS_SERVER_LOG_ONLY", "0"),
+            ("DNS_SERVER_MAX_TTL", " 300 "),
+            ("DNS_SERVER_CHAOS_VERSION", "my server 1.0"),
+            ("HOME", "/root"),
+        ];
+        let vars = vars.map(|(name, value)| (name.into(), value.into()));
+        let env = env_args(vars).unwrap();
+        assert_eq!(
+            env,
+            args(&[
+                "--chaos-version",
+                "my server 1.0",
+                "--listen",
+                "0.0.0.0:5353",
+                "--listen",
+                "[::]:5353,udp",
+                "--max-ttl",
+                "300",
+                "--recursion"
+            ])
+        );
+
+        // The command line comes after.
+        let config = Config::from_args(env.into_iter().chain(args(&["--max-ttl", "60"]))).unwrap();
+        assert_eq!(config.listeners.len(), 2);
+        assert!(config.recursion && !config.log_only);
+        assert_eq!(config.ttl_bounds.max, 60);
+        assert_eq!(config.chaos.version.as_deref(), Some("my server 1.0"));
+
+        let vars = [("DNS_SERVER_RECURSION".into(), "on".into())];
+        assert!(matches!(env_args(vars), Err(ConfigError::InvalidValue(..))));
+        // Other variables needn't be UTF-8, ours must.
+        let bad = || OsString::from_vec(vec![0xff]);
+        assert_eq!(
+            env_args([(bad(), bad()), ("HOME".into(), bad())]).unwrap(),
+            args(&[])
+        );
+        let vars = [("DNS_SERVER_NSID".into(), bad())];
+        assert!(matches!(env_args(vars), Err(ConfigError::InvalidValue(..))));
+        // Every switch is one.
+        for switch in SWITCHES {
+            assert!(Config::from_args(args(&[switch])).is_ok(), "{}", switch);
+        }
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(matches!(
+            Config::from_args(args(&["--stats-interval"])),
+            Err(ConfigError::MissingValue(_))
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--stats-interval", "soon"])),
+            Err(ConfigError::InvalidValue(_, _))
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--bogus"])),
+            Err(ConfigError::UnknownFlag(_))
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How long client TCP connections may stay open, and how many at once.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct TcpLimits {
+    pub(crate) idle: Duration,
+    // From accept, however busy the connection is; None for no limit.
+    pub(crate) lifetime: Option<Duration>,
+    pub(crate) max_connections: usize,
+}
+
+impl Default for TcpLimits {
+    fn default() -> Self {
+        TcpLimits {
+            idle: Duration::from_secs(10),
+            lifetime: Some(Duration::from_secs(120)),
+            max_connections: 150,
+        }
+    }
+}
+
+impl TcpLimits {
+    // The `edns-tcp-keepalive` timeout (RFC 7828), in units of 100ms.
+    pub(crate) fn keepalive(&self) -> u16 {
+        (self.idle.as_millis() / 100).min(u16::MAX as u128) as u16
+    }
+}
+
+// Open client TCP connections. A connection over the limit makes room by
+// closing the one that has gone longest without a query.
+#[derive(Default)]
+pub(crate) struct Connections {
+    open: Mutex<HashMap<u64, (TcpStream, Instant)>>,
+    next_id: AtomicU64,
+}
+
+// A registered connection, dropped from the registry along with this.
+pub(crate) struct Connection<'a> {
+    id: u64,
+    connections: &'a Connections,
+}
+
+impl Connections {
+    pub(crate) fn open(&self, stream: &TcpStream, max: usize) -> io::Result<Connection<'_>> {
+        let stream = stream.try_clone()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut open = self.open.lock().unwrap();
+        while open.len() >= max.max(1) {
+            let Some(&oldest) = open
+                .iter()
+                .min_by_key(|(_, (_, active))| *active)
+                .map(|(id, _)| id)
+            else {
+                break;
+            };
+            if let Some((stream, _)) = open.remove(&oldest) {
+                // The connection's own thread sees EOF and finishes up.
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+        open.insert(id, (stream, Instant::now()));
+        Ok(Connection {
+            id,
+            connections: self,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+}
+
+impl Connection<'_> {
+    // Marks the connection as just used.
+    pub(crate) fn active(&self) {
+        if let Some((_, active)) = self.connections.open.lock().unwrap().get_mut(&self.id) {
+            *active = Instant::now();
+        }
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.connections.open.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connections = Connections::default();
+        let (mut first_client, first) = pair(&listener);
+        let (mut second_client, second) = pair(&listener);
+        let first = connections.open(&first, 2).unwrap();
+        let second = connections.open(&second, 2).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        first.active();
+
+        let (_, third) = pair(&listener);
+        let third = connections.open(&third, 2).unwrap();
+        assert_eq!(connections.len(), 2);
+        // The second connection was closed, so its client reads EOF.
+        assert_eq!(second_client.read(&mut [0; 1]).unwrap(), 0);
+        first_client
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!(first_client.read(&mut [0; 1]).is_err());
+
+        drop((first, second, third));
+        assert_eq!(connections.len(), 0);
+    }
+
+    #[test]
+    fn test_keepalive() {
+        assert_eq!(TcpLimits::default().keepalive(), 100);
+    }
+}
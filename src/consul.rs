@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::error::HttpError;
+use crate::header::ResponseCode;
+use crate::http::{self, Url};
+use crate::json::Json;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const DEFAULT_DOMAIN: &str = "consul";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// Catalog answers go stale as soon as the next poll, so like Consul's own
+// DNS interface they aren't cached downstream.
+const TTL: i32 = 0;
+
+// `--consul url[,domain=name][,interval=secs]`: answer
+// `<service>.service.<domain>` and `<node>.node.<domain>` from the healthy
+// instances in a Consul catalog, polled every interval.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct ConsulConfig {
+    pub(crate) url: Url,
+    pub(crate) domain: Name,
+    pub(crate) interval: Duration,
+}
+
+impl ConsulConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let url = Url::parse(parts.next().unwrap_or_default()).map_err(|e| e.to_string())?;
+        let mut config = ConsulConfig {
+            url,
+            domain: DEFAULT_DOMAIN.into(),
+            interval: DEFAULT_INTERVAL,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("domain", domain)) if !domain.is_empty() => config.domain = domain.into(),
+                Some(("interval", secs)) => {
+                    let secs: u64 = secs
+                        .parse()
+                        .ok()
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| format!("bad interval {:?}", secs))?;
+                    config.interval = Duration::from_secs(secs);
+                }
+                _ => return Err(format!("unknown consul option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Instance {
+    node: String,
+    addr: IpAddr,
+    port: u16,
+}
+
+// The last catalog fetched, keyed by lowercased service and node names.
+#[derive(Default)]
+struct Snapshot {
+    services: HashMap<String, Vec<Instance>>,
+    nodes: HashMap<String, IpAddr>,
+}
+
+#[derive(Default)]
+pub(crate) struct Catalog {
+    snapshot: RwLock<Arc<Snapshot>>,
+}
+
+impl Catalog {
+    // Fetches every service's passing instances and swaps them in, keeping
+    // the previous catalog if any request fails.
+    pub(crate) fn refresh(&self, config: &ConsulConfig) -> Result<usize, HttpError> {
+        let services = http::get_json(&config.url.join("/v1/catalog/services"), HTTP_TIMEOUT)?;
+        let Json::Object(services) = services else {
+            return Err(HttpError::Malformed("service list is not an object"));
+        };
+        let mut snapshot = Snapshot::default();
+        for (service, _) in &services {
+            if !service
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+            {
+                continue;
+            }
+            let path = format!("/v1/health/service/{}?passing", service);
+            let entries = http::get_json(&config.url.join(&path), HTTP_TIMEOUT)?;
+            let instances: Vec<Instance> = entries.items().iter().filter_map(instance).collect();
+            for instance in &instances {
+                snapshot
+                    .nodes
+                    .insert(instance.node.to_ascii_lowercase(), instance.addr);
+            }
+            snapshot
+                .services
+                .insert(service.to_ascii_lowercase(), instances);
+        }
+        let count = snapshot.services.len();
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+        Ok(count)
+    }
+
+    // Answers a question under the Consul domain: A/AAAA and SRV for
+    // `<service>.service` (also `_<service>._tcp.service`), A/AAAA for
+    // `<node>.node`.
+    pub(crate) fn answer(&self, question: &DnsQuestion, domain: &Name, response: &mut DnsPacket) {
+        response.header.aa = true;
+        let snapshot = Arc::clone(&self.snapshot.read().unwrap());
+        let qname = question.qname.as_str();
+        let relative = qname[..qname.len() - domain.as_str().len()].trim_end_matches('.');
+        let labels: Vec<String> = relative
+            .split('.')
+            .map(|label| label.trim_start_matches('_').to_ascii_lowercase())
+            .collect();
+        let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+
+        match labels[..] {
+            [service, "service"] | [service, "tcp", "service"] => {
+                let Some(instances) = snapshot.services.get(service) else {
+                    response.header.rcode = ResponseCode::NxDomain;
+                    return;
+                };
+                for instance in instances {
+                    let node = node_name(&instance.node, domain);
+                    match question.qtype {
+                        DnsType::Srv => {
+                            response.answers.push(DnsAnswer::new(
+                                question.qname.clone(),
+                                DnsType::Srv,
+                                DnsClass::In,
+                                TTL,
+                                RData::Srv {
+                                    priority: 1,
+                                    weight: 1,
+                                    port: instance.port,
+                                    target: node.clone(),
+                                },
+                            ));
+                            response
+                                .additionals
+                                .push(DnsAnswer::address(node, TTL, instance.addr));
+                        }
+                        qtype if qtype.wants_address(instance.addr) => {
+                            let name = question.qname.clone();
+                            response
+                                .answers
+                                .push(DnsAnswer::address(name, TTL, instance.addr))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            [node, "node"] => match snapshot.nodes.get(node) {
+                Some(&addr) if question.qtype.wants_address(addr) => {
+                    let name = question.qname.clone();
+                    response.answers.push(DnsAnswer::address(name, TTL, addr))
+                }
+                Some(_) => {}
+                None => response.header.rcode = ResponseCode::NxDomain,
+            },
+            _ => response.header.rcode = ResponseCode::NxDomain,
+        }
+    }
+}
+
+// Refreshes the catalog every interval for as long as the server runs.
+pub(crate) fn poll(config: ConsulConfig, catalog: Arc<Catalog>) {
+    thread::spawn(move || loop {
+        if let Err(e) = catalog.refresh(&config) {
+            eprintln!(
+                "Failed to refresh Consul catalog from {}: {}",
+                config.url, e
+            );
+        }
+        thread::sleep(config.interval);
+    });
+}
+
+// One entry of /v1/health/service/<name>. The service address falls back
+// to the node's when the service doesn't register its own.
+fn instance(entry: &Json) -> Option<Instance> {
+    let node = entry.get("Node")?;
+    let service = entry.get("Service")?;
+    let addr = service
+        .get("Address")
+        .and_then(Json::as_str)
+        .filter(|addr| !addr.is_empty())
+        .or_else(|| node.get("Address").and_then(Json::as_str))?;
+    Some(Instance {
+        node: node.get("Node")?.as_str()?.to_string(),
+        addr: addr.parse().ok()?,
+        port: service.get("Port")?.as_u64()?.try_into().ok()?,
+    })
+}
+
+fn node_name(node: &str, domain: &Name) -> Name {
+    Name::from(format!("{}.node.{}", node, domain).as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // Serves a catalog with one service, `web`, on two nodes.
+    fn fake_consul() -> ConsulConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let body = if request.starts_with("GET /v1/catalog/services ") {
+                    r#"{"web": ["v1"], "bad name": []}"#
+                } else if request.starts_with("GET /v1/health/service/web?passing ") {
+                    r#"[{"Node": {"Node": "n1", "Address": "10.0.0.1"},
+                         "Service": {"Service": "web", "Address": "", "Port": 8080}},
+                        {"Node": {"Node": "n2", "Address": "10.0.0.2"},
+                         "Service": {"Service": "web", "Address": "2001:db8::2", "Port": 8081}}]"#
+                } else {
+                    "null"
+                };
+                write!(
+                    &stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        ConsulConfig::parse(&url).unwrap()
+    }
+
+    fn ask(catalog: &Catalog, name: &str, qtype: DnsType) -> DnsPacket {
+        let question = DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        catalog.answer(&question, &DEFAULT_DOMAIN.into(), &mut response);
+        response
+    }
+
+    #[test]
+    fn test_parse() {
+        let config =
+            ConsulConfig::parse("http://127.0.0.1:8500,domain=dc1.example,interval=30").unwrap();
+        assert_eq!(config.domain.as_str(), "dc1.example");
+        assert_eq!(config.interval, Duration::from_secs(30));
+        for bad in [
+            "127.0.0.1:8500",
+            "http://127.0.0.1:8500,interval=0",
+            "http://127.0.0.1:8500,dc=dc1",
+        ] {
+            assert!(ConsulConfig::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_catalog() {
+        let catalog = Catalog::default();
+        assert_eq!(catalog.refresh(&fake_consul()).unwrap(), 1);
+
+        let response = ask(&catalog, "WEB.service.consul", DnsType::A);
+        assert!(response.header.aa);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, RData::A([10, 0, 0, 1]));
+
+        let response = ask(&catalog, "_web._tcp.service.consul", DnsType::Srv);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(
+            response.answers[1].rdata,
+            RData::Srv {
+                priority: 1,
+                weight: 1,
+                port: 8081,
+                target: "n2.node.consul".into()
+            }
+        );
+        assert_eq!(response.additionals[1].qtype, DnsType::Aaaa);
+
+        let response = ask(&catalog, "n2.node.consul", DnsType::Aaaa);
+        assert_eq!(response.answers.len(), 1);
+
+        let response = ask(&catalog, "web.service.consul", DnsType::Mx);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(response.answers.is_empty());
+
+        for missing in [
+            "db.service.consul",
+            "n9.node.consul",
+            "consul",
+            "x.y.consul",
+        ] {
+            let response = ask(&catalog, missing, DnsType::A);
+            assert_eq!(response.header.rcode, ResponseCode::NxDomain, "{}", missing);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::authority::Zones;
+use crate::blocklist;
+use crate::common::Name;
+use crate::config::{Config, DEFAULT_CONTROL_SOCKET};
+use crate::handler::State;
+use crate::schedule;
+use crate::stats::{Stats, StatsSnapshot};
+use crate::ttl;
+
+// How long a client has to send its command, and how long it may be.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_COMMAND: u64 = 4096;
+
+// Commands understood on the control channel, one per connection:
+//   status          uptime, query count, cache size and TCP connections
+//   stats           dump statistics and reset the counters
+//   stats_noreset   dump statistics without resetting
+// Statistics include the reputation of the nameservers the recursor asked,
+// the UDP queues' counters, the freshness of secondary zones and the
+// zone journal with the transfers served from it, which aren't reset.
+//   flush           drop cached answers
+//   reload          re-read zones and blocklists
+//   schedules       whether each schedule is in force, and if forced
+//   schedule <name> on|off|auto
+//                   force a schedule on or off until set back to auto
+//   ttl <zone> <seconds>|off|auto
+//                   force the TTL of answers in a zone (`.` for all), or
+//                   stop forcing it, until set back to auto
+pub(crate) struct Control {
+    started: Instant,
+    config: Arc<Config>,
+    state: Arc<State>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Control {
+    pub(crate) fn new(config: Arc<Config>, state: Arc<State>, stats: Arc<Mutex<Stats>>) -> Self {
+        Control {
+            started: Instant::now(),
+            config,
+            state,
+            stats,
+        }
+    }
+
+    pub(crate) fn execute(&self, command: &str) -> String {
+        match command.trim() {
+            "status" => {
+                let queries = self.stats.lock().unwrap().snapshot().queries;
+                format!(
+                    "ok\nversion: {}\nuptime: {} seconds\nqueries: {}\ncache: {} entries\n\
+                     tcp connections: {}\n",
+                    env!("CARGO_PKG_VERSION"),
+                    self.started.elapsed().as_secs(),
+                    queries,
+                    self.state.cache.len(),
+                    self.state.connections.len()
+                )
+            }
+            "stats" => {
+                let mut stats = self.stats.lock().unwrap();
+                let snapshot = stats.snapshot();
+                stats.reset();
+                drop(stats);
+                self.stats_report(snapshot, true)
+            }
+            "stats_noreset" => {
+                let snapshot = self.stats.lock().unwrap().snapshot();
+                self.stats_report(snapshot, false)
+            }
+            "flush" => format!("ok\nflushed: {}\n", self.state.cache.clear()),
+            // Flags only change on restart; zone files and blocklists are
+            // re-read, and one that fails to load leaves the old set serving.
+            // The reply counts the records each zone gained and lost.
+            "reload" => {
+                // An update in progress would put back the zones it started from.
+                let _changing = self.state.changing_zones.lock().unwrap();
+                match Zones::load(&self.config) {
+                    Ok(zones) => {
+                        match blocklist::load_all(&self.config, &self.state.blocklists()) {
+                            Ok(lists) => {
+                                let counts = (zones.len(), lists.len());
+                                let old = self.state.zones();
+                                let changes = old.changes(&zones);
+                                self.state.journal.record_all(&old, &zones);
+                                *self.state.zones.write().unwrap() = Arc::new(zones);
+                                *self.state.blocklists.write().unwrap() = Arc::new(lists);
+                                self.state.health.zones_loaded(None);
+                                format!(
+                                    "ok\nzones: {}\nblocklists: {}\n{}",
+                                    counts.0, counts.1, changes
+                                )
+                            }
+                            Err(e) => format!("error: {}\n", e),
+                        }
+                    }
+                    Err(e) => {
+                        self.state.health.zones_loaded(Some(e.to_string()));
+                        format!("error: {}\n", e)
+                    }
+                }
+            }
+            "schedules" => format!(
+                "ok\n{}",
+                schedule::report(&self.config, &self.state.schedules)
+            ),
+            command if command.starts_with("schedule ") => self.schedule(command),
+            command if command.starts_with("ttl ") => self.ttl(command),
+            "" => "error: empty command\n".to_string(),
+            other => format!("error: unknown command: {}\n", other),
+        }
+    }
+
+    // `schedule <name> on|off|auto`.
+    fn schedule(&self, command: &str) -> String {
+        let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+        let (name, active) = match args[..] {
+            [name, "on"] => (name, Some(true)),
+            [name, "off"] => (name, Some(false)),
+            [name, "auto"] => (name, None),
+            _ => return "error: usage: schedule <name> on|off|auto\n".to_string(),
+        };
+        if !self
+            .config
+            .schedules
+            .iter()
+            .any(|schedule| schedule.name == name)
+        {
+            return format!("error: no such schedule: {}\n", name);
+        }
+        self.state.schedules.set(name, active);
+        "ok\n".to_string()
+    }
+
+    // `ttl <zone> <seconds>|off|auto`.
+    fn ttl(&self, command: &str) -> String {
+        let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+        let [zone, setting] = args[..] else {
+            return "error: usage: ttl <zone> <seconds>|off|auto\n".to_string();
+        };
+        let (zone, ttls) = (Name::from(zone), &self.state.ttls);
+        match setting {
+            "off" => ttls.set(&zone, None),
+            "auto" => ttls.clear(&zone),
+            seconds => match ttl::parse_ttl(seconds) {
+                Ok(seconds) => ttls.set(&zone, Some(seconds)),
+                Err(e) => return format!("error: {}\n", e),
+            },
+        }
+        "ok\n".to_string()
+    }
+
+    // The query counters, then every other report; `reset` says whether
+    // the counters that are reset with them were.
+    fn stats_report(&self, snapshot: StatsSnapshot, reset: bool) -> String {
+        format!(
+            "ok\n{}{}{}{}{}{}{}{}{}{}{}{}",
+            snapshot,
+            self.state.cache.report(),
+            self.state.blocked.report(),
+            blocklist::report(&self.state.blocklists()),
+            self.config.policies.report(reset),
+            self.config.faults.report(reset),
+            self.state.reputation.report(),
+            self.state.pool.report(),
+            self.state.queue.report(),
+            self.state.secondaries.report(),
+            self.state.journal.report(),
+            self.state.health_checks.report()
+        )
+    }
+
+    pub(crate) fn serve(self, path: &Path) -> io::Result<()> {
+        // A socket left behind by a previous run would make bind fail.
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let control = Arc::new(self);
+        // Each connection on its own thread, so a client that never sends
+        // its command doesn't hold up the next one.
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let control = Arc::clone(&control);
+                        thread::spawn(move || {
+                            if let Err(e) = control.handle(stream) {
+                                eprintln!("Control connection failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Control socket error: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn handle(&self, stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+        let mut command = String::new();
+        BufReader::new(&stream)
+            .take(MAX_COMMAND)
+            .read_line(&mut command)?;
+        let response = self.execute(&command);
+        (&stream).write_all(response.as_bytes())
+    }
+}
+
+// `dns-server ctl [--control-socket <path>] <command>`
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let (path, command) = match args.as_slice() {
+        [flag, path, command @ ..] if flag == "--control-socket" => {
+            (PathBuf::from(path), command.join(" "))
+        }
+        command => (PathBuf::from(DEFAULT_CONTROL_SOCKET), command.join(" ")),
+    };
+    if command.is_empty() {
+        eprintln!(
+            "usage: dns-server ctl [--control-socket <path>] \
+             <status|stats|stats_noreset|flush|reload|schedules|schedule <name> on|off|auto|\
+             ttl <zone> <seconds>|off|auto>"
+        );
+        return 2;
+    }
+
+    match send(&path, &command) {
+        Ok(response) => {
+            print!("{}", response);
+            if response.starts_with("ok") {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to connect to {}: {}", path.display(), e);
+            1
+        }
+    }
+}
+
+pub(crate) fn send(path: &Path, command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::ResponseCode;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    fn control() -> Control {
+        let mut stats = Stats::new();
+        stats.record(
+            "example.com",
+            "A",
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ResponseCode::NoError,
+            Duration::from_millis(1),
+        );
+        Control::new(
+            Arc::new(Config::default()),
+            Arc::new(State::default()),
+            Arc::new(Mutex::new(stats)),
+        )
+    }
+
+    #[test]
+    fn test_execute() {
+        let control = control();
+        assert!(control.execute("status\n").contains("queries: 1\n"));
+        assert!(control
+            .execute("stats_noreset")
+            .contains("total.num.queries=1\n"));
+        assert!(control.execute("stats").contains("total.num.queries=1\n"));
+        assert!(control.execute("stats").contains("total.num.queries=0\n"));
+        assert_eq!(
+            control.execute("frobnicate"),
+            "error: unknown command: frobnicate\n"
+        );
+    }
+
+    #[test]
+    fn test_schedule() {
+        let config = Config::from_args(
+            ["--schedule", "work=mon-fri@09:00-17:00"]
+                .iter()
+                .map(|arg| arg.to_string()),
+        )
+        .unwrap();
+        let control = Control::new(
+            Arc::new(config),
+            Arc::new(State::default()),
+            Arc::new(Mutex::new(Stats::new())),
+        );
+        assert_eq!(control.execute("schedule work on"), "ok\n");
+        assert_eq!(
+            control.execute("schedules"),
+            "ok\nschedule.work.active=1\nschedule.work.mode=on\n"
+        );
+        assert_eq!(control.execute("schedule work auto"), "ok\n");
+        assert!(control.execute("schedules").ends_with("mode=auto\n"));
+        assert!(control.execute("schedule play on").starts_with("error: "));
+        assert!(control.execute("schedule work").starts_with("error: "));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let control = control();
+        let forced = |name: &str| ttl::forced(&name.into(), &control.config, &control.state.ttls);
+        assert_eq!(control.execute("ttl example.com 5"), "ok\n");
+        assert_eq!(forced("www.example.com"), Some(5));
+        assert_eq!(control.execute("ttl . off"), "ok\n");
+        assert_eq!(forced("example.org"), None);
+        assert_eq!(control.execute("ttl example.com auto"), "ok\n");
+        assert_eq!(forced("www.example.com"), None);
+        assert!(control
+            .execute("ttl example.com soon")
+            .starts_with("error: "));
+        assert!(control.execute("ttl 5").starts_with("error: "));
+    }
+
+    #[test]
+    fn test_flush_and_reload() {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-test-{}.zone", std::process::id()));
+        let zone = "$ORIGIN example.com.\n$TTL 300\n\
+                    @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+                    @ NS ns1\nns1 A 192.0.2.1\n";
+        fs::write(&path, zone).unwrap();
+        let config = Config {
+            zones: vec![(None, path.clone())],
+            ..Config::default()
+        };
+        let state = Arc::new(State::default());
+        let control = Control::new(
+            Arc::new(config),
+            Arc::clone(&state),
+            Arc::new(Mutex::new(Stats::new())),
+        );
+
+        assert_eq!(control.execute("flush"), "ok\nflushed: 0\n");
+        assert_eq!(
+            control.execute("reload"),
+            "ok\nzones: 1\nblocklists: 0\nzone.example.com.added=3\nzone.example.com.removed=0\n"
+        );
+        assert_eq!(state.zones().len(), 1);
+
+        fs::write(
+            &path,
+            zone.replace("192.0.2.1", "192.0.2.2") + "www A 192.0.2.80\n",
+        )
+        .unwrap();
+        assert!(control.execute("reload").ends_with(
+            "zone.example.com.added=2\nzone.example.com.removed=1\n\
+             zone.example.com.serial_not_increased=1\n"
+        ));
+        fs::write(&path, zone.replace(" 1 7200", " 2 7200")).unwrap();
+        assert!(control
+            .execute("reload")
+            .ends_with("zone.example.com.added=2\nzone.example.com.removed=3\n"));
+
+        // A broken zone keeps the loaded ones serving.
+        fs::write(&path, "www A 192.0.2.1\n").unwrap();
+        assert!(control.execute("reload").starts_with("error: "));
+        assert_eq!(state.zones().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_socket_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-test-{}.sock", std::process::id()));
+        control().serve(&path).unwrap();
+        // A client that connects and says nothing doesn't hold up others.
+        let _silent = UnixStream::connect(&path).unwrap();
+        let response = send(&path, "stats_noreset").unwrap();
+        assert!(response.starts_with("ok\n"));
+        assert!(response.contains("top.name.1=example.com 1\n"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,570 @@
+// Just enough cryptography for TSIG and DNSSEC, written out by hand to avoid
+// pulling in a crypto crate: MD5 (RFC 1321), SHA-1 and SHA-256 (FIPS 180-4),
+// HMAC (RFC 2104), base64 and base32hex (RFC 4648), RSA signatures (RFC
+// 8017) and the modular exponentiation of Diffie-Hellman.
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// The DER DigestInfo header that goes before a SHA-256 hash in an RSA
+// signature (RFC 8017 section 9.2).
+const SHA256_DIGEST_INFO: [u8; 19] = [
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32HEX: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+// The message, a 1 bit, zeros and its length in bits, filling whole blocks.
+fn pad(data: &[u8]) -> Vec<u8> {
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    padded
+}
+
+// For NSEC3 hashes only (RFC 5155 section 5), where collisions don't help
+// an attacker.
+pub(crate) fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    for block in pad(data).chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+// For TKEY's Diffie-Hellman keying (RFC 2930 section 4.1), which fixes it.
+pub(crate) fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [[u32; 4]; 4] = [
+        [7, 12, 17, 22],
+        [5, 9, 14, 20],
+        [4, 11, 16, 23],
+        [6, 10, 15, 21],
+    ];
+    let mut padded = pad(data);
+    let len = padded.len();
+    padded[len - 8..].reverse();
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in padded.chunks(64) {
+        let m: Vec<u32> = block
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => (b & c | !b & d, i),
+                1 => (d & b | !d & c, (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), 7 * i % 16),
+            };
+            // The constants are the integer parts of 2^32 * |sin(i + 1)|.
+            let k = (((i + 1) as f64).sin().abs() * 4294967296.0) as u32;
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(k)
+                .wrapping_add(m[g])
+                .rotate_left(SHIFTS[i / 16][i % 4]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 16];
+    for (chunk, word) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    for block in pad(data).chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (chunk, word) in digest.chunks_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha256(&inner));
+    sha256(&outer)
+}
+
+// Ignores whitespace so that keys split across lines decode as one.
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let chunks = text.chunks_exact(4);
+    if !chunks.remainder().is_empty() {
+        return None;
+    }
+    let count = chunks.len();
+    let mut out = Vec::with_capacity(count * 3);
+    for (i, chunk) in chunks.enumerate() {
+        let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        if padding > 2 || (padding > 0 && i + 1 < count) {
+            return None;
+        }
+        let mut n = 0u32;
+        for b in &chunk[..4 - padding] {
+            let value = BASE64.iter().position(|c| c == b)? as u32;
+            n = n << 6 | value;
+        }
+        n <<= 6 * padding as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut n = 0u32;
+        for (i, b) in chunk.iter().enumerate() {
+            n |= (*b as u32) << (16 - 8 * i);
+        }
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+// Lowercase and unpadded, as NSEC3 owner names hold hashes (RFC 5155
+// section 3.3).
+#[allow(dead_code)]
+pub(crate) fn base32hex_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut bits, mut count) = (0u32, 0);
+    for b in bytes {
+        bits = bits << 8 | *b as u32;
+        count += 8;
+        while count >= 5 {
+            count -= 5;
+            out.push(BASE32HEX[(bits >> count & 0x1f) as usize] as char);
+        }
+    }
+    if count > 0 {
+        out.push(BASE32HEX[(bits << (5 - count) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+// Either case, unpadded; leftover bits must be zero.
+pub(crate) fn base32hex_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32HEX
+            .iter()
+            .position(|b| *b == c.to_ascii_lowercase())? as u32;
+        bits = (bits << 5 | value) & 0xffff;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    (count < 5 && bits & ((1 << count) - 1) == 0).then_some(out)
+}
+
+// RSASSA-PKCS1-v1_5 with SHA-256 (RFC 8017 section 8.2.2), the scheme of
+// DNSSEC algorithm 8. The exponent and modulus are big-endian.
+pub(crate) fn rsa_sha256_verify(
+    exponent: &[u8],
+    modulus: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    let modulus = strip_zeros(modulus);
+    let signature = strip_zeros(signature);
+    if modulus.is_empty() || signature.len() > modulus.len() {
+        return false;
+    }
+    let len = modulus.len().div_ceil(4);
+    let n = limbs(modulus, len);
+    let s = limbs(signature, len);
+    if !less(&s, &n) {
+        return false;
+    }
+    let m = pow_mod(&s, exponent, &n);
+    to_bytes(&m, modulus.len()) == encode(message, modulus.len())
+}
+
+// The signature of `message` with the private exponent, for SIG(0) and
+// tests.
+pub(crate) fn rsa_sha256_sign(private_exponent: &[u8], modulus: &[u8], message: &[u8]) -> Vec<u8> {
+    let modulus = strip_zeros(modulus);
+    let len = modulus.len().div_ceil(4);
+    let em = limbs(&encode(message, modulus.len()), len);
+    to_bytes(
+        &pow_mod(&em, private_exponent, &limbs(modulus, len)),
+        modulus.len(),
+    )
+}
+
+// base^exponent mod modulus, all big-endian, for Diffie-Hellman; the
+// result is as long as the modulus. The base must be below the modulus.
+pub(crate) fn mod_exp(base: &[u8], exponent: &[u8], modulus: &[u8]) -> Vec<u8> {
+    let modulus = strip_zeros(modulus);
+    let len = modulus.len().div_ceil(4);
+    let r = pow_mod(
+        &limbs(strip_zeros(base), len),
+        exponent,
+        &limbs(modulus, len),
+    );
+    to_bytes(&r, modulus.len())
+}
+
+// EMSA-PKCS1-v1_5: 00 01 FF.. 00, then the hash with its DigestInfo.
+fn encode(message: &[u8], len: usize) -> Vec<u8> {
+    let mut em = vec![0, 1];
+    let padding = len.saturating_sub(3 + SHA256_DIGEST_INFO.len() + 32);
+    em.resize(2 + padding, 0xff);
+    em.push(0);
+    em.extend_from_slice(&SHA256_DIGEST_INFO);
+    em.extend_from_slice(&sha256(message));
+    em
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+// Big numbers below are little-endian 32-bit limbs, as many as the modulus
+// has. Nothing here is constant time: fine for checking public signatures,
+// and for signing on a machine nobody else gets to time.
+fn limbs(bytes: &[u8], len: usize) -> Vec<u32> {
+    let mut limbs = vec![0; len];
+    for (i, b) in bytes.iter().rev().enumerate() {
+        limbs[i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    limbs
+}
+
+fn to_bytes(limbs: &[u32], len: usize) -> Vec<u8> {
+    let bytes: Vec<u8> = limbs.iter().rev().flat_map(|l| l.to_be_bytes()).collect();
+    bytes[bytes.len() - len..].to_vec()
+}
+
+fn less(a: &[u32], b: &[u32]) -> bool {
+    a.iter().rev().lt(b.iter().rev())
+}
+
+fn add(a: &mut [u32], b: &[u32]) -> bool {
+    let mut carry = 0;
+    for (x, y) in a.iter_mut().zip(b) {
+        let sum = *x as u64 + *y as u64 + carry;
+        *x = sum as u32;
+        carry = sum >> 32;
+    }
+    carry != 0
+}
+
+// Wraps around when b > a, which is what reduction after an overflow needs.
+fn sub(a: &mut [u32], b: &[u32]) {
+    let mut borrow = 0;
+    for (x, y) in a.iter_mut().zip(b) {
+        let (diff, under1) = x.overflowing_sub(*y);
+        let (diff, under2) = diff.overflowing_sub(borrow);
+        *x = diff;
+        borrow = (under1 || under2) as u32;
+    }
+}
+
+fn double(a: &mut [u32]) -> bool {
+    let mut carry = 0;
+    for x in a.iter_mut() {
+        let next = *x >> 31;
+        *x = *x << 1 | carry;
+        carry = next;
+    }
+    carry != 0
+}
+
+// a * b mod n by doubling and adding, a bit of b at a time; a, b < n.
+fn mul_mod(a: &[u32], b: &[u32], n: &[u32]) -> Vec<u32> {
+    let mut r = vec![0; n.len()];
+    for bit in (0..b.len() * 32).rev() {
+        if double(&mut r) || !less(&r, n) {
+            sub(&mut r, n);
+        }
+        if b[bit / 32] >> (bit % 32) & 1 == 1 && (add(&mut r, a) || !less(&r, n)) {
+            sub(&mut r, n);
+        }
+    }
+    r
+}
+
+fn pow_mod(base: &[u32], exponent: &[u8], n: &[u32]) -> Vec<u32> {
+    let mut r = vec![0; n.len()];
+    r[0] = 1;
+    for byte in exponent {
+        for bit in (0..8).rev() {
+            r = mul_mod(&r, &r, n);
+            if byte >> bit & 1 == 1 {
+                r = mul_mod(&r, base, n);
+            }
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_base32hex() {
+        // RFC 4648 section 10, unpadded.
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "co"),
+            ("fo", "cpng"),
+            ("foo", "cpnmu"),
+            ("foob", "cpnmuog"),
+            ("fooba", "cpnmuoj1"),
+            ("foobar", "cpnmuoj1e8"),
+        ] {
+            assert_eq!(base32hex_encode(plain.as_bytes()), encoded);
+            assert_eq!(base32hex_decode(encoded), Some(plain.as_bytes().to_vec()));
+        }
+        assert_eq!(base32hex_decode("CPNMUOJ1"), Some(b"fooba".to_vec()));
+        assert_eq!(base32hex_decode("cp"), None);
+        assert_eq!(base32hex_decode("cw"), None);
+    }
+
+    #[test]
+    fn test_md5() {
+        // RFC 1321 appendix A.5.
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(&md5(
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            )),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn test_mod_exp() {
+        assert_eq!(mod_exp(&[4], &[13], &[1, 241]), vec![1, 189]);
+        // Diffie-Hellman in a small group: both sides agree.
+        let (p, g) = ([0xff, 0xff, 0xff, 0xfb], [5]);
+        let (a, b) = ([0x12, 0x34], [0x56, 0x78, 0x9a]);
+        let (ya, yb) = (mod_exp(&g, &a, &p), mod_exp(&g, &b, &p));
+        assert_eq!(mod_exp(&yb, &a, &p), mod_exp(&ya, &b, &p));
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test cases 2 and 6.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_base64() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64_decode(encoded), Some(plain.as_bytes().to_vec()));
+        }
+        assert_eq!(base64_decode("Zm9v\n YmFy"), Some(b"foobar".to_vec()));
+        assert_eq!(base64_decode("Zm9"), None);
+        assert_eq!(base64_decode("Zg==Zg=="), None);
+        assert_eq!(base64_decode("Z!=="), None);
+        for plain in ["", "f", "fo", "foo", "foob", "fooba", "foobar"] {
+            let encoded = base64_encode(plain.as_bytes());
+            assert_eq!(base64_decode(&encoded), Some(plain.as_bytes().to_vec()));
+        }
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+    }
+
+    fn unhex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rsa_sha256_verify() {
+        // A 1024-bit key and its signature of "abc", from OpenSSL.
+        let modulus = unhex(
+            "e1c4f0483b7d07fb6621b917f850e7d0c5ce166cf1af6aad5499d1ec4772fd55\
+             a38f1f4ce41cd46e31d52f28f30abd924205a694e60dee8afe0ebab156d214d0\
+             2ac55da39f8e22750fb4fdd23c66f95d0be2a2c13184202deda93bd4aa96f398\
+             6de22275a12ce3be152bdf28645e8d97abb8f3a560f01da0fb41f7d9ef14b715",
+        );
+        let signature = unhex(
+            "3ca256b8432a2042233ba4984ffebbd30e567996fa7a25188c32cb1f96d8f88a\
+             43c75265e6f6391511be958752566aa64a5175b666022e82e56ab886a2f41333\
+             a1d1871a9a408dd21ccd347fd93ea93deb466ec9220cc60501b3febdde3f8d2f\
+             a652222ee0b4900377defbace2643c0a9a64f0b401152f08bd9580039cfd9a00",
+        );
+        let exponent = [1, 0, 1];
+        assert!(rsa_sha256_verify(&exponent, &modulus, b"abc", &signature));
+        assert!(!rsa_sha256_verify(&exponent, &modulus, b"abd", &signature));
+        let mut forged = signature.clone();
+        forged[64] ^= 1;
+        assert!(!rsa_sha256_verify(&exponent, &modulus, b"abc", &forged));
+        assert!(!rsa_sha256_verify(&exponent, &modulus, b"abc", &modulus));
+    }
+}
@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::clock::SharedClock;
+use crate::common::{DnsType, Name};
+use crate::dnssec::{self, Nsec, Nsec3, Rrsig, NSEC3_SHA1};
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const MAX_ZONES: usize = 1_000;
+const MAX_RECORDS_PER_ZONE: usize = 1_000;
+// Validators treat zones with more NSEC3 iterations as unsigned anyway
+// (RFC 9276 section 3.2); hashing that often per query isn't worth it.
+const MAX_ITERATIONS: u16 = 150;
+
+// NSEC and NSEC3 records from negative answers, kept by zone so that other
+// names they prove don't exist are answered without asking (RFC 8198).
+//
+// RFC 8198 allows this only for validated records, and there is no
+// validator yet, which is why `--aggressive-nsec` is off by default and
+// warns when turned on: one forged record denies a whole range of names
+// rather than one. Short of validation, only signed records from a zone
+// that encloses the name asked about are kept.
+#[derive(Default)]
+pub(crate) struct Denials {
+    // By lowercased zone name.
+    zones: Mutex<HashMap<String, Zone>>,
+    clock: SharedClock,
+}
+
+struct Zone {
+    soa: DnsAnswer,
+    soa_expires: Instant,
+    denials: Vec<Denial>,
+}
+
+struct Denial {
+    record: DnsAnswer,
+    signatures: Vec<DnsAnswer>,
+    proof: Proof,
+    expires: Instant,
+}
+
+enum Proof {
+    Nsec(Nsec),
+    // With the hash the owner name holds.
+    Nsec3(Vec<u8>, Nsec3),
+}
+
+impl Denial {
+    fn types(&self) -> &[u16] {
+        match &self.proof {
+            Proof::Nsec(nsec) => &nsec.types,
+            Proof::Nsec3(_, nsec3) => &nsec3.types,
+        }
+    }
+
+    // Whether the owner is a delegation, whose NSEC speaks for the parent
+    // zone only: names below it are the child's.
+    fn delegation(&self) -> bool {
+        let types = self.types();
+        types.contains(&(DnsType::Ns.code())) && !types.contains(&(DnsType::Soa.code()))
+    }
+
+    // Proves the name exists without data of the type.
+    fn nodata(&self, qtype: DnsType) -> bool {
+        let types = self.types();
+        !types.contains(&(qtype.code())) && !types.contains(&(DnsType::Cname.code()))
+    }
+}
+
+impl Denials {
+    #[cfg(test)]
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Denials {
+            clock,
+            ..Denials::default()
+        }
+    }
+
+    // Keeps the denial records of an NXDOMAIN or NODATA response, for as
+    // long as both they and the zone's negative TTL allow.
+    pub(crate) fn learn(&self, response: &DnsPacket) {
+        let negative = match response.header.rcode {
+            ResponseCode::NxDomain => true,
+            ResponseCode::NoError => response.answers.is_empty(),
+            _ => false,
+        };
+        let soa = response
+            .authorities
+            .iter()
+            .find_map(|record| match record.rdata {
+                RData::Soa { minimum, .. } if negative && record.qtype == DnsType::Soa => {
+                    Some((record, record.ttl.min(minimum.min(i32::MAX as u32) as i32)))
+                }
+                _ => None,
+            });
+        let Some((soa, ttl)) = soa.filter(|(_, ttl)| *ttl > 0) else {
+            return;
+        };
+        let zone = &soa.name;
+        // A SOA from outside the question's zone would let one answer speak
+        // for names it was never asked about.
+        let asked = response.questions.first();
+        if !asked.is_some_and(|question| question.qname.is_subdomain_of(zone)) {
+            return;
+        }
+        let now = self.clock.now();
+        let mut denials = Vec::new();
+        for record in &response.authorities {
+            if !record.name.is_subdomain_of(zone) {
+                continue;
+            }
+            let RData::Unknown(rdata) = &record.rdata else {
+                continue;
+            };
+            let proof = match record.qtype {
+                DnsType::Nsec => Nsec::parse(rdata).map(Proof::Nsec),
+                DnsType::Nsec3 => Nsec3::parse(rdata)
+                    .filter(|nsec3| {
+                        nsec3.algorithm == NSEC3_SHA1 && nsec3.iterations <= MAX_ITERATIONS
+                    })
+                    .zip(nsec3_owner(zone, &record.name))
+                    .map(|(nsec3, hash)| Proof::Nsec3(hash, nsec3)),
+                _ => None,
+            };
+            let Some(proof) = proof else {
+                continue;
+            };
+            let signatures = response
+                .authorities
+                .iter()
+                .filter(|rrsig| {
+                    rrsig.qtype == DnsType::Rrsig
+                        && rrsig.name.eq_ignore_case(&record.name)
+                        && matches!(&rrsig.rdata, RData::Unknown(rdata)
+                            if Rrsig::parse(rdata)
+                                .is_some_and(|rrsig| rrsig.type_covered == record.qtype.code()))
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            if signatures.is_empty() {
+                continue;
+            }
+            denials.push(Denial {
+                record: record.clone(),
+                signatures,
+                proof,
+                expires: now + Duration::from_secs(record.ttl.min(ttl).max(0) as u64),
+            });
+        }
+        if denials.is_empty() {
+            return;
+        }
+
+        let mut zones = self.zones.lock().unwrap();
+        let key = zone.as_str().to_ascii_lowercase();
+        if zones.len() >= MAX_ZONES && !zones.contains_key(&key) {
+            zones.retain(|_, zone| zone.soa_expires > now);
+            if zones.len() >= MAX_ZONES {
+                return;
+            }
+        }
+        let entry = zones.entry(key).or_insert_with(|| Zone {
+            soa: soa.clone(),
+            soa_expires: now,
+            denials: Vec::new(),
+        });
+        entry.soa = soa.clone();
+        entry.soa_expires = now + Duration::from_secs(ttl as u64);
+        entry.denials.retain(|denial| denial.expires > now);
+        for denial in denials {
+            entry.denials.retain(|old| {
+                !(old.record.qtype == denial.record.qtype
+                    && old.record.name.eq_ignore_case(&denial.record.name))
+            });
+            if entry.denials.len() < MAX_RECORDS_PER_ZONE {
+                entry.denials.push(denial);
+            }
+        }
+    }
+
+    // An NXDOMAIN or NODATA response for the question, when the records
+    // kept for its zone prove it.
+    pub(crate) fn synthesize(&self, question: &DnsQuestion) -> Option<DnsPacket> {
+        let now = self.clock.now();
+        let mut zones = self.zones.lock().unwrap();
+        let key = zones
+            .keys()
+            .filter(|zone| question.qname.is_subdomain_of(&Name::from(zone.as_str())))
+            .max_by_key(|zone| zone.len())?
+            .clone();
+        let zone = zones.get_mut(&key)?;
+        if zone.soa_expires <= now {
+            zones.remove(&key);
+            return None;
+        }
+        zone.denials.retain(|denial| denial.expires > now);
+
+        let (rcode, used) = nsec_proof(&zone.denials, question)
+            .or_else(|| nsec3_proof(&zone.soa.name, &zone.denials, question))?;
+        let remaining = |expires: Instant| expires.duration_since(now).as_secs() as i32;
+        let mut response = DnsPacket::query(0, question.clone());
+        response.header.rcode = rcode;
+        let mut soa = zone.soa.clone();
+        soa.ttl = soa.ttl.min(remaining(zone.soa_expires));
+        response.authorities.push(soa);
+        for denial in used {
+            let ttl = remaining(denial.expires);
+            for record in std::iter::once(&denial.record).chain(&denial.signatures) {
+                let mut record = record.clone();
+                record.ttl = record.ttl.min(ttl);
+                response.authorities.push(record);
+            }
+        }
+        Some(response)
+    }
+}
+
+// The hash in an NSEC3 owner name, which must be directly below the zone.
+fn nsec3_owner(zone: &Name, owner: &Name) -> Option<Vec<u8>> {
+    let parent = parent(owner)?;
+    parent
+        .eq_ignore_case(zone)
+        .then(|| dnssec::nsec3_owner_hash(owner))
+        .flatten()
+}
+
+fn parent(name: &Name) -> Option<Name> {
+    match name.as_str() {
+        "" => None,
+        name => Some(Name::from(
+            name.split_once('.').map_or("", |(_, rest)| rest),
+        )),
+    }
+}
+
+// The deepest name both are at or below.
+fn common_ancestor(a: &Name, b: &Name) -> Name {
+    let labels = |name: &Name| -> Vec<String> {
+        name.as_str()
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .rev()
+            .map(|label| label.to_ascii_lowercase())
+            .collect()
+    };
+    let (a, b) = (labels(a), labels(b));
+    let mut common: Vec<&str> = a
+        .iter()
+        .zip(&b)
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.as_str())
+        .collect();
+    common.reverse();
+    Name::from(common.join(".").as_str())
+}
+
+fn wildcard(name: &Name) -> Name {
+    match name.as_str() {
+        "" => Name::from("*"),
+        name => Name::from(format!("*.{}", name).as_str()),
+    }
+}
+
+// RFC 4035 section 5.4: an NSEC at the name without the type, or one
+// covering the name and one covering the wildcard that could have matched.
+fn nsec_proof<'a>(
+    denials: &'a [Denial],
+    question: &DnsQuestion,
+) -> Option<(ResponseCode, Vec<&'a Denial>)> {
+    let qname = &question.qname;
+    let nsecs: Vec<(&Denial, &Nsec)> = denials
+        .iter()
+        .filter_map(|denial| match &denial.proof {
+            Proof::Nsec(nsec) => Some((denial, nsec)),
+            _ => None,
+        })
+        .collect();
+    if let Some((denial, _)) = nsecs
+        .iter()
+        .find(|(denial, _)| denial.record.name.eq_ignore_case(qname))
+    {
+        return denial
+            .nodata(question.qtype)
+            .then(|| (ResponseCode::NoError, vec![*denial]));
+    }
+
+    let (covering, nsec) = nsecs
+        .iter()
+        .find(|(denial, nsec)| nsec.covers(&denial.record.name, qname))?;
+    let owner = &covering.record.name;
+    // Names below the next one mean the name is an empty non-terminal,
+    // and names below a delegation are the child zone's.
+    if nsec.next.is_subdomain_of(qname) || (qname.is_subdomain_of(owner) && covering.delegation()) {
+        return None;
+    }
+    let encloser = [
+        common_ancestor(qname, owner),
+        common_ancestor(qname, &nsec.next),
+    ]
+    .into_iter()
+    .max_by_key(|name| name.len())?;
+    let wildcard = wildcard(&encloser);
+    let (no_wildcard, _) = nsecs
+        .iter()
+        .find(|(denial, nsec)| nsec.covers(&denial.record.name, &wildcard))?;
+
+    let mut used = vec![*covering];
+    if !std::ptr::eq(*no_wildcard, *covering) {
+        used.push(*no_wildcard);
+    }
+    Some((ResponseCode::NxDomain, used))
+}
+
+// RFC 5155 section 8: the same over hashes, with the closest encloser
+// proven by an NSEC3 at it and one covering the next closer name. Opt-out
+// ranges may hide unsigned delegations, so they prove nothing.
+fn nsec3_proof<'a>(
+    zone: &Name,
+    denials: &'a [Denial],
+    question: &DnsQuestion,
+) -> Option<(ResponseCode, Vec<&'a Denial>)> {
+    let nsec3s: Vec<(&Denial, &[u8], &Nsec3)> = denials
+        .iter()
+        .filter_map(|denial| match &denial.proof {
+            Proof::Nsec3(hash, nsec3) => Some((denial, hash.as_slice(), nsec3)),
+            _ => None,
+        })
+        .collect();
+    let (_, _, params) = nsec3s.first()?;
+    let nsec3s: Vec<_> = nsec3s
+        .iter()
+        .filter(|(_, _, nsec3)| nsec3.salt == params.salt && nsec3.iterations == params.iterations)
+        .collect();
+    let hash = |name: &Name| dnssec::nsec3_hash(name, &params.salt, params.iterations);
+    let matching = |name: &Name| {
+        let hash = hash(name);
+        nsec3s
+            .iter()
+            .find(|(_, owner, _)| *owner == hash.as_slice())
+            .map(|(denial, _, _)| *denial)
+    };
+    let covering = |name: &Name| {
+        let hash = hash(name);
+        nsec3s
+            .iter()
+            .find(|(_, owner, nsec3)| nsec3.covers(owner, &hash))
+            .map(|(denial, _, nsec3)| (*denial, *nsec3))
+    };
+
+    let qname = &question.qname;
+    if let Some(denial) = matching(qname) {
+        return (denial.nodata(question.qtype) && !denial.delegation())
+            .then(|| (ResponseCode::NoError, vec![denial]));
+    }
+
+    let mut next_closer = qname.clone();
+    let (encloser, closest) = loop {
+        let candidate = parent(&next_closer)?;
+        if !candidate.is_subdomain_of(zone) {
+            return None;
+        }
+        if let Some(denial) = matching(&candidate) {
+            break (candidate, denial);
+        }
+        next_closer = candidate;
+    };
+    if closest.delegation() {
+        return None;
+    }
+    let (no_next_closer, nsec3) = covering(&next_closer)?;
+    if nsec3.opt_out() {
+        return None;
+    }
+    let (no_wildcard, _) = covering(&wildcard(&encloser))?;
+
+    let mut used = vec![closest];
+    for denial in [no_next_closer, no_wildcard] {
+        if !used.iter().any(|other| std::ptr::eq(*other, denial)) {
+            used.push(denial);
+        }
+    }
+    Some((ResponseCode::NxDomain, used))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::common::DnsClass;
+    use crate::crypto;
+
+    fn question(name: &str, qtype: DnsType) -> DnsQuestion {
+        DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        }
+    }
+
+    fn soa(zone: &str) -> DnsAnswer {
+        DnsAnswer::new(
+            zone.into(),
+            DnsType::Soa,
+            DnsClass::In,
+            3600,
+            RData::Soa {
+                mname: "ns1.example.com".into(),
+                rname: "hostmaster.example.com".into(),
+                serial: 1,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        )
+    }
+
+    fn nsec(owner: &str, next: &str, types: &[DnsType]) -> DnsAnswer {
+        let nsec = Nsec {
+            next: next.into(),
+            types: types.iter().map(|t| t.code()).collect(),
+        };
+        DnsAnswer::new(
+            owner.into(),
+            DnsType::Nsec,
+            DnsClass::In,
+            3600,
+            RData::Unknown(nsec.to_bytes()),
+        )
+    }
+
+    // With an RRSIG, never checked, for each NSEC and NSEC3 record.
+    fn negative(qname: &str, rcode: ResponseCode, records: Vec<DnsAnswer>) -> DnsPacket {
+        let mut response = DnsPacket::query(1, question(qname, DnsType::A));
+        response.header.rcode = rcode;
+        for record in records {
+            if matches!(record.qtype, DnsType::Nsec | DnsType::Nsec3) {
+                let rrsig = Rrsig {
+                    type_covered: record.qtype.code(),
+                    algorithm: dnssec::RSASHA256,
+                    labels: 2,
+                    original_ttl: 3600,
+                    expiration: u32::MAX,
+                    inception: 0,
+                    key_tag: 1,
+                    signer: "example.com".into(),
+                    signature: vec![0; 64],
+                };
+                response.authorities.push(DnsAnswer::new(
+                    record.name.clone(),
+                    DnsType::Rrsig,
+                    DnsClass::In,
+                    record.ttl,
+                    RData::Unknown(rrsig.to_bytes()),
+                ));
+            }
+            response.authorities.push(record);
+        }
+        response
+    }
+
+    // The records other than signatures.
+    fn names(response: &DnsPacket) -> Vec<String> {
+        response
+            .authorities
+            .iter()
+            .filter(|record| record.qtype != DnsType::Rrsig)
+            .map(|record| format!("{} {}", record.name, record.qtype))
+            .collect()
+    }
+
+    #[test]
+    fn test_nsec() {
+        let denials = Denials::default();
+        // The zone holds example.com, a, b.sub (an empty non-terminal at
+        // sub), del (delegated) and m.
+        denials.learn(&negative(
+            "c.example.com",
+            ResponseCode::NxDomain,
+            vec![
+                soa("example.com"),
+                nsec("example.com", "a.example.com", &[DnsType::Soa, DnsType::Ns]),
+                nsec("a.example.com", "del.example.com", &[DnsType::A]),
+                nsec("del.example.com", "m.example.com", &[DnsType::Ns]),
+                nsec("m.example.com", "b.sub.example.com", &[DnsType::Txt]),
+                nsec("b.sub.example.com", "example.com", &[DnsType::A]),
+            ],
+        ));
+
+        let response = denials
+            .synthesize(&question("aa.example.com", DnsType::A))
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+        assert_eq!(
+            names(&response),
+            vec!["example.com SOA", "a.example.com NSEC", "example.com NSEC"]
+        );
+        assert!(response.authorities.iter().all(|r| r.ttl <= 300));
+        let signatures = response
+            .authorities
+            .iter()
+            .filter(|r| r.qtype == DnsType::Rrsig);
+        assert_eq!(signatures.count(), 2);
+
+        let response = denials
+            .synthesize(&question("x.m.example.com", DnsType::A))
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+
+        let response = denials
+            .synthesize(&question("M.example.com", DnsType::A))
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert_eq!(
+            names(&response),
+            vec!["example.com SOA", "m.example.com NSEC"]
+        );
+        assert!(denials
+            .synthesize(&question("m.example.com", DnsType::Txt))
+            .is_none());
+
+        // An empty non-terminal, a delegated name and another zone.
+        assert!(denials
+            .synthesize(&question("sub.example.com", DnsType::A))
+            .is_none());
+        assert!(denials
+            .synthesize(&question("www.del.example.com", DnsType::A))
+            .is_none());
+        assert!(denials
+            .synthesize(&question("www.example.net", DnsType::A))
+            .is_none());
+    }
+
+    #[test]
+    fn test_wildcard_blocks_nxdomain() {
+        let denials = Denials::default();
+        denials.learn(&negative(
+            "b.example.com",
+            ResponseCode::NoError,
+            vec![
+                soa("example.com"),
+                nsec("*.example.com", "c.example.com", &[DnsType::A]),
+                nsec("c.example.com", "example.com", &[DnsType::A]),
+            ],
+        ));
+        // *.example.com exists, so d.example.com may too.
+        assert!(denials
+            .synthesize(&question("d.example.com", DnsType::A))
+            .is_none());
+    }
+
+    #[test]
+    fn test_not_learnt() {
+        let denials = Denials::default();
+        // Without the SOA there's no negative TTL.
+        denials.learn(&negative(
+            "b.example.com",
+            ResponseCode::NxDomain,
+            vec![nsec("a.example.com", "c.example.com", &[DnsType::A])],
+        ));
+        // Nor from a positive answer.
+        let mut response = negative(
+            "b.example.com",
+            ResponseCode::NoError,
+            vec![
+                soa("example.com"),
+                nsec("a.example.com", "c.example.com", &[DnsType::A]),
+            ],
+        );
+        response.answers.push(soa("b.example.com"));
+        denials.learn(&response);
+        // Nor unsigned records.
+        let mut response = negative(
+            "b.example.com",
+            ResponseCode::NxDomain,
+            vec![
+                soa("example.com"),
+                nsec("a.example.com", "c.example.com", &[DnsType::A]),
+            ],
+        );
+        response
+            .authorities
+            .retain(|record| record.qtype != DnsType::Rrsig);
+        denials.learn(&response);
+        assert!(denials
+            .synthesize(&question("b.example.com", DnsType::A))
+            .is_none());
+
+        // Nor records for a zone the question isn't in.
+        denials.learn(&negative(
+            "b.example.com",
+            ResponseCode::NxDomain,
+            vec![
+                soa("org"),
+                nsec("org", "a.org", &[DnsType::Soa]),
+                nsec("a.org", "c.org", &[DnsType::A]),
+            ],
+        ));
+        assert!(denials.synthesize(&question("b.org", DnsType::A)).is_none());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let clock = ManualClock::new();
+        let denials = Denials::with_clock(clock.shared());
+        denials.learn(&negative(
+            "b.example.com",
+            ResponseCode::NxDomain,
+            vec![
+                soa("example.com"),
+                nsec("a.example.com", "c.example.com", &[DnsType::A]),
+                nsec("example.com", "a.example.com", &[DnsType::Soa]),
+            ],
+        ));
+        let q = question("b.example.com", DnsType::A);
+        let ttls = |response: DnsPacket| -> Vec<i32> {
+            response.authorities.iter().map(|r| r.ttl).collect()
+        };
+        assert_eq!(ttls(denials.synthesize(&q).unwrap()), vec![300; 5]);
+
+        // Counting down from the negative TTL, then gone.
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(ttls(denials.synthesize(&q).unwrap()), vec![180; 5]);
+        clock.advance(Duration::from_secs(180));
+        assert!(denials.synthesize(&q).is_none());
+    }
+
+    fn nsec3(zone: &str, owner: &str, next: &str, flags: u8, types: &[DnsType]) -> DnsAnswer {
+        let salt = vec![0xab];
+        let hash = |name: &str| dnssec::nsec3_hash(&name.into(), &salt, 1);
+        let nsec3 = Nsec3 {
+            algorithm: NSEC3_SHA1,
+            flags,
+            iterations: 1,
+            salt: salt.clone(),
+            next_hashed: hash(next),
+            types: types.iter().map(|t| t.code()).collect(),
+        };
+        let owner = format!("{}.{}", crypto::base32hex_encode(&hash(owner)), zone);
+        DnsAnswer::new(
+            owner.as_str().into(),
+            DnsType::Nsec3,
+            DnsClass::In,
+            3600,
+            RData::Unknown(nsec3.to_bytes()),
+        )
+    }
+
+    // The names of the zone, in hash order, with the name after each.
+    fn hashed_chain(names: &[&'static str]) -> Vec<(&'static str, &'static str)> {
+        let mut names = names.to_vec();
+        names.sort_by_key(|name| dnssec::nsec3_hash(&(*name).into(), &[0xab], 1));
+        (0..names.len())
+            .map(|i| (names[i], names[(i + 1) % names.len()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_nsec3() {
+        let zone = "example.com";
+        let chain = hashed_chain(&["example.com", "a.example.com", "*.a.example.com"]);
+        let mut records = vec![soa(zone)];
+        for (owner, next) in &chain {
+            let types: &[DnsType] = match *owner {
+                "example.com" => &[DnsType::Soa, DnsType::Ns],
+                _ => &[DnsType::A],
+            };
+            records.push(nsec3(zone, owner, next, 0, types));
+        }
+        let denials = Denials::default();
+        denials.learn(&negative("x.example.com", ResponseCode::NxDomain, records));
+
+        let response = denials
+            .synthesize(&question("nope.example.com", DnsType::A))
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+        assert!((2..=4).contains(&names(&response).len()));
+
+        let response = denials
+            .synthesize(&question("a.example.com", DnsType::Mx))
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert!(denials
+            .synthesize(&question("a.example.com", DnsType::A))
+            .is_none());
+        // The wildcard below a.example.com could match.
+        assert!(denials
+            .synthesize(&question("b.a.example.com", DnsType::A))
+            .is_none());
+    }
+
+    #[test]
+    fn test_nsec3_opt_out() {
+        let zone = "example.com";
+        let chain = hashed_chain(&["example.com", "a.example.com"]);
+        let mut records = vec![soa(zone)];
+        for (owner, next) in &chain {
+            records.push(nsec3(
+                zone,
+                owner,
+                next,
+                dnssec::NSEC3_OPT_OUT,
+                &[DnsType::A],
+            ));
+        }
+        let denials = Denials::default();
+        denials.learn(&negative("x.example.com", ResponseCode::NxDomain, records));
+        assert!(denials
+            .synthesize(&question("nope.example.com", DnsType::A))
+            .is_none());
+    }
+}
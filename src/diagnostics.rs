@@ -0,0 +1,269 @@
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::blocklist;
+use crate::config::Config;
+use crate::handler::State;
+use crate::stats::Stats;
+
+// How often the dump thread looks for a signal. The handler itself only
+// sets a flag: almost nothing is safe to do inside one.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Flags whose values are secrets, left out of dumps.
+const SECRET_FLAGS: [&str; 2] = ["--acme-token", "--anonymize-clients"];
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// On SIGUSR1, writes a `report` to the diagnostics file, or the log without
+// one, so that a server that seems stuck can be looked into in place.
+pub(crate) fn on_sigusr1(config: Arc<Config>, state: Arc<State>, stats: Arc<Mutex<Stats>>) {
+    if let Err(e) = sys::install() {
+        eprintln!("Not dumping diagnostics on SIGUSR1: {}", e);
+        return;
+    }
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+        if !REQUESTED.swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        let report = report(&config, &state, &stats);
+        match &config.diagnostics_file {
+            Some(path) => {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(report.as_bytes()));
+                match written {
+                    Ok(()) => eprintln!("Diagnostics written to {}", path.display()),
+                    Err(e) => eprintln!("Failed to write {}: {}", path.display(), e),
+                }
+            }
+            None => eprint!("{}", report),
+        }
+    });
+}
+
+// Everything worth knowing about a running server, as key=value lines in
+// the layout of `ctl stats`, between begin and end markers.
+pub(crate) fn report(config: &Config, state: &State, stats: &Mutex<Stats>) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut report = format!(
+        "=== diagnostics begin\ntime={}\npid={}\nversion={}\n",
+        time.as_secs(),
+        std::process::id(),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    writeln!(report, "config.args={}", redact(&config.args).join(" ")).unwrap();
+    for listener in &config.listeners {
+        let protocols: Vec<String> = listener.protocols.iter().map(|p| p.to_string()).collect();
+        writeln!(
+            report,
+            "config.listen.{}={}",
+            listener.addr,
+            protocols.join(",")
+        )
+        .unwrap();
+    }
+    writeln!(report, "config.zones={}", state.zones().len()).unwrap();
+    writeln!(report, "config.blocklists={}", state.blocklists().len()).unwrap();
+
+    for group in &config.upstreams {
+        let servers: Vec<String> = group.servers.iter().map(|s| s.to_string()).collect();
+        let hosts: Vec<String> = group
+            .hosts
+            .iter()
+            .map(|(host, port)| format!("{}:{}", host.as_str(), port))
+            .collect();
+        let down = state.outages.down(&group.name).unwrap_or_default();
+        writeln!(
+            report,
+            "upstream.{}.servers={}",
+            group.name,
+            [servers, hosts].concat().join(",")
+        )
+        .unwrap();
+        writeln!(report, "upstream.{}.down={}", group.name, down.as_secs()).unwrap();
+    }
+    report.push_str(&state.pool.report());
+    report.push_str(&state.reputation.report());
+    report.push_str(&state.cache.report());
+    report.push_str(&state.blocked.report());
+    report.push_str(&blocklist::report(&state.blocklists()));
+
+    report.push_str(&state.queue.report());
+    writeln!(report, "tcp.connections={}", state.connections.len()).unwrap();
+    writeln!(report, "lookups.in_flight={}", state.in_flight.len()).unwrap();
+    // Threads and memory as the kernel sees them, where it says.
+    if let Ok(status) = fs::read_to_string("/proc/self/status") {
+        for (field, key) in [
+            ("Threads", "threads"),
+            ("VmRSS", "memory.rss"),
+            ("VmHWM", "memory.rss.peak"),
+            ("VmSize", "memory.virtual"),
+        ] {
+            if let Some(value) = proc_field(&status, field) {
+                writeln!(report, "{}={}", key, value).unwrap();
+            }
+        }
+    }
+
+    write!(report, "{}", stats.lock().unwrap().snapshot()).unwrap();
+    report.push_str("=== diagnostics end\n");
+    report
+}
+
+fn redact(args: &[String]) -> Vec<String> {
+    let mut redacted = args.to_vec();
+    for i in 1..redacted.len() {
+        if SECRET_FLAGS.contains(&args[i - 1].as_str()) {
+            redacted[i] = "<redacted>".to_string();
+        }
+    }
+    redacted
+}
+
+// A /proc status field's value, e.g. "1234 kB" for VmRSS.
+fn proc_field<'a>(status: &'a str, field: &str) -> Option<&'a str> {
+    status.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        (name == field).then(|| value.trim())
+    })
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_int;
+    use std::io;
+    use std::sync::atomic::Ordering;
+
+    #[cfg(any(target_os = "macos", target_os = "freebsd"))]
+    const SIGUSR1: c_int = 30;
+    #[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
+    const SIGUSR1: c_int = 10;
+    // SIG_ERR, as signal() returns it.
+    const ERROR: usize = usize::MAX;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn requested(_: c_int) {
+        super::REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        // SAFETY: the handler only stores to an atomic.
+        match unsafe { signal(SIGUSR1, requested) } {
+            ERROR => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn raise() {
+        extern "C" {
+            fn raise(signum: c_int) -> c_int;
+        }
+        // SAFETY: raising a signal whose handler is installed.
+        unsafe { raise(SIGUSR1) };
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::io;
+
+    pub(super) fn install() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "no signals on this platform",
+        ))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::header::ResponseCode;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Instant;
+
+    fn config(args: &[&str]) -> Config {
+        Config::from_args(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let config = config(&[
+            "--upstream",
+            "wan=192.0.2.1,dns.test,bootstrap=192.0.2.2",
+            "--acme-token",
+            "hunter2",
+        ]);
+        let stats = Mutex::new(Stats::new());
+        stats.lock().unwrap().record(
+            "example.com",
+            "A",
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            ResponseCode::NoError,
+            Duration::from_millis(1),
+        );
+        let report = report(&config, &State::default(), &stats);
+
+        assert!(report.starts_with("=== diagnostics begin\n"));
+        assert!(report.ends_with("=== diagnostics end\n"));
+        for line in [
+            "config.args=--upstream wan=192.0.2.1,dns.test,bootstrap=192.0.2.2 --acme-token <redacted>",
+            "config.listen.127.0.0.1:2053=udp,tcp",
+            "upstream.wan.servers=192.0.2.1:53,dns.test:53",
+            "upstream.wan.down=0",
+            "cache.entries=0",
+            "tcp.connections=0",
+            "lookups.in_flight=0",
+            "total.num.queries=1",
+        ] {
+            assert!(report.lines().any(|l| l == line), "{}\n{}", line, report);
+        }
+        assert!(!report.contains("hunter2"));
+        if cfg!(target_os = "linux") {
+            assert!(report.contains("\nmemory.rss="), "{}", report);
+            assert!(report.contains("\nthreads="), "{}", report);
+        }
+    }
+
+    #[test]
+    fn test_sigusr1() {
+        let path =
+            std::env::temp_dir().join(format!("dns-server-diagnostics-{}.txt", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = config(&["--diagnostics-file", path.to_str().unwrap()]);
+        on_sigusr1(
+            Arc::new(config),
+            Arc::new(State::default()),
+            Arc::new(Mutex::new(Stats::new())),
+        );
+        sys::raise();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let dump = loop {
+            let dump = fs::read_to_string(&path).unwrap_or_default();
+            if dump.ends_with("=== diagnostics end\n") || Instant::now() > deadline {
+                break dump;
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+        fs::remove_file(&path).unwrap();
+        assert!(dump.starts_with("=== diagnostics begin\n"), "{}", dump);
+        assert!(dump.contains(&format!("\npid={}\n", std::process::id())));
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+
+use crate::answer::DnsAnswer;
+use crate::common::{DnsClass, DnsType};
+use crate::edns::EdnsOption;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::resolver::Resolver;
+
+const USAGE: &str = "usage: dns-server query <name> [type] [class] [@server[:port]] [+tcp]";
+
+#[derive(PartialEq, Debug)]
+struct Request {
+    name: String,
+    qtype: DnsType,
+    qclass: DnsClass,
+    server: Option<SocketAddr>,
+    tcp: bool,
+}
+
+// `dns-server query <name> [type] [@server] [+tcp]`, printing the response
+// the way dig does. Exits 1 on SERVFAIL and 9 when no response arrives.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let request = match Request::parse(&args) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let server = match request.server {
+        Some(server) => server,
+        None => match Resolver::system_servers().first() {
+            Some(server) => *server,
+            None => {
+                eprintln!(";; no nameservers in /etc/resolv.conf, use @server");
+                return 2;
+            }
+        },
+    };
+    let resolver = Resolver::new(vec![server]).with_tcp(request.tcp);
+
+    let start = Instant::now();
+    let result = resolver.query(&request.name, request.qtype, request.qclass);
+    let elapsed = start.elapsed();
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            println!(";; communications error to {}: {}", server, e);
+            return 9;
+        }
+    };
+
+    println!(
+        "; <<>> dns-server {} <<>> {} {} {}",
+        env!("CARGO_PKG_VERSION"),
+        request.name,
+        request.qtype,
+        request.qclass
+    );
+    print!("{}", format_response(&response));
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(
+        ";; SERVER: {}#{}({}) ({})",
+        server.ip(),
+        server.port(),
+        server.ip(),
+        if request.tcp { "TCP" } else { "UDP" }
+    );
+    println!();
+
+    if response.header.rcode == ResponseCode::ServFail {
+        1
+    } else {
+        0
+    }
+}
+
+impl Request {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut name = None;
+        let mut qtype = None;
+        let mut qclass = None;
+        let mut server = None;
+        let mut tcp = false;
+
+        for arg in args {
+            if let Some(addr) = arg.strip_prefix('@') {
+                server = Some(parse_server(addr)?);
+            } else if let Some(option) = arg.strip_prefix('+') {
+                match option {
+                    "tcp" | "vc" => tcp = true,
+                    "notcp" => tcp = false,
+                    "https" | "tls" => {
+                        return Err(format!("+{} is not supported by this build", option))
+                    }
+                    _ => return Err(format!("unknown option: {}", arg)),
+                }
+            } else if name.is_none() {
+                name = Some(arg.clone());
+            } else if let (None, Ok(parsed)) = (qtype, arg.parse()) {
+                qtype = Some(parsed);
+            } else if let (None, Ok(parsed)) = (qclass, arg.parse()) {
+                qclass = Some(parsed);
+            } else {
+                return Err(format!("unexpected argument: {}", arg));
+            }
+        }
+
+        Ok(Request {
+            name: name.ok_or("missing name")?,
+            qtype: qtype.unwrap_or(DnsType::A),
+            qclass: qclass.unwrap_or(DnsClass::In),
+            server,
+            tcp,
+        })
+    }
+}
+
+pub(crate) fn parse_server(addr: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = addr.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, 53));
+    }
+    let with_port = if addr.contains(':') {
+        addr.to_string()
+    } else {
+        format!("{}:53", addr)
+    };
+    with_port
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("couldn't resolve server: {}", addr))
+}
+
+fn format_response(response: &DnsPacket) -> String {
+    let header = &response.header;
+    let mut out = String::new();
+
+    let mut flags = vec!["qr"];
+    for (set, flag) in [
+        (header.aa, "aa"),
+        (header.tc, "tc"),
+        (header.rd, "rd"),
+        (header.ra, "ra"),
+    ] {
+        if set {
+            flags.push(flag);
+        }
+    }
+    let _ = writeln!(
+        out,
+        ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+        header.opcode, header.rcode, header.id
+    );
+    let _ = writeln!(
+        out,
+        ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+        flags.join(" "),
+        response.questions.len(),
+        response.answers.len(),
+        response.authorities.len(),
+        response.additionals.len() + response.edns.is_some() as usize
+    );
+
+    if let Some(edns) = &response.edns {
+        let _ = writeln!(out, "\n;; OPT PSEUDOSECTION:");
+        let _ = writeln!(
+            out,
+            "; EDNS: version: {}, flags:{}; udp: {}",
+            edns.version,
+            if edns.dnssec_ok { " do" } else { "" },
+            edns.udp_payload_size
+        );
+        for option in &edns.options {
+            match option {
+                EdnsOption::Nsid(nsid) => {
+                    let hex: Vec<String> = nsid.iter().map(|b| format!("{:02x}", b)).collect();
+                    let _ = writeln!(
+                        out,
+                        "; NSID: {} (\"{}\")",
+                        hex.join(" "),
+                        String::from_utf8_lossy(nsid)
+                    );
+                }
+                EdnsOption::TcpKeepalive(Some(timeout)) => {
+                    let _ = writeln!(
+                        out,
+                        "; TCP KEEPALIVE: {}.{} secs",
+                        timeout / 10,
+                        timeout % 10
+                    );
+                }
+                EdnsOption::ExtendedError(info, text) => {
+                    let _ = writeln!(out, "; EDE: {}: ({})", info, text);
+                }
+                EdnsOption::Expire(Some(expire)) => {
+                    let _ = writeln!(out, "; EXPIRE: {}", expire);
+                }
+                EdnsOption::TcpKeepalive(None)
+                | EdnsOption::Expire(None)
+                | EdnsOption::Unknown(_, _) => {}
+            }
+        }
+    }
+
+    let _ = writeln!(out, "\n;; QUESTION SECTION:");
+    for question in &response.questions {
+        let _ = writeln!(
+            out,
+            ";{}.\t\t{}\t{}",
+            question.qname, question.qclass, question.qtype
+        );
+    }
+
+    for (title, records) in [
+        ("ANSWER", &response.answers),
+        ("AUTHORITY", &response.authorities),
+        ("ADDITIONAL", &response.additionals),
+    ] {
+        write_section(&mut out, title, records);
+    }
+    out.push('\n');
+    out
+}
+
+fn write_section(out: &mut String, title: &str, records: &[DnsAnswer]) {
+    if records.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "\n;; {} SECTION:", title);
+    for record in records {
+        let _ = writeln!(out, "{}", record);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::RData;
+    use crate::question::DnsQuestion;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request = Request::parse(&args(&["example.com", "aaaa", "@127.0.0.1:2053", "+tcp"]));
+        assert_eq!(
+            request,
+            Ok(Request {
+                name: "example.com".to_string(),
+                qtype: DnsType::Aaaa,
+                qclass: DnsClass::In,
+                server: Some("127.0.0.1:2053".parse().unwrap()),
+                tcp: true,
+            })
+        );
+
+        let request = Request::parse(&args(&["@::1", "version.bind", "TXT", "CH"])).unwrap();
+        assert_eq!(request.server, Some("[::1]:53".parse().unwrap()));
+        assert_eq!(request.qtype, DnsType::Txt);
+        assert_eq!(request.qclass, DnsClass::Ch);
+    }
+
+    #[test]
+    fn test_parse_request_errors() {
+        assert!(Request::parse(&args(&[])).is_err());
+        assert!(Request::parse(&args(&["example.com", "+https"])).is_err());
+        assert!(Request::parse(&args(&["example.com", "A", "IN", "extra"])).is_err());
+    }
+
+    #[test]
+    fn test_format_response() {
+        let mut response = DnsPacket::query(
+            0x1234,
+            DnsQuestion {
+                qname: "example.com".into(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            },
+        );
+        response.header.flip_qr();
+        response.add_answer(DnsAnswer::new(
+            "example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            60,
+            RData::A([192, 0, 2, 1]),
+        ));
+
+        let output = format_response(&response);
+        assert!(output.contains(";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4660\n"));
+        assert!(
+            output.contains(";; flags: qr rd; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 0\n")
+        );
+        assert!(output.contains(";example.com.\t\tIN\tA\n"));
+        assert!(output.contains(";; ANSWER SECTION:\nexample.com.\t60\tIN\tA\t192.0.2.1\n"));
+        assert!(!output.contains("AUTHORITY SECTION"));
+    }
+}
@@ -0,0 +1,726 @@
+use std::cmp::Ordering;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::crypto;
+
+// DNSKEY flags (RFC 4034 section 2.1.1, RFC 5011 section 3).
+pub(crate) const ZONE_KEY: u16 = 0x0100;
+pub(crate) const REVOKE: u16 = 0x0080;
+pub(crate) const SECURE_ENTRY_POINT: u16 = 0x0001;
+
+// The only algorithm signatures are checked for: what the root and most
+// TLDs sign with.
+pub(crate) const RSASHA256: u8 = 8;
+
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Dnskey {
+    pub(crate) flags: u16,
+    pub(crate) protocol: u8,
+    pub(crate) algorithm: u8,
+    pub(crate) public_key: Vec<u8>,
+}
+
+impl Dnskey {
+    pub(crate) fn parse(rdata: &[u8]) -> Option<Self> {
+        if rdata.len() < 4 {
+            return None;
+        }
+        Some(Dnskey {
+            flags: u16::from_be_bytes([rdata[0], rdata[1]]),
+            protocol: rdata[2],
+            algorithm: rdata[3],
+            public_key: rdata[4..].to_vec(),
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.flags.to_be_bytes().to_vec();
+        bytes.push(self.protocol);
+        bytes.push(self.algorithm);
+        bytes.extend_from_slice(&self.public_key);
+        bytes
+    }
+
+    // RFC 4034 appendix B.
+    pub(crate) fn key_tag(&self) -> u16 {
+        let mut sum: u32 = 0;
+        for (i, b) in self.to_bytes().iter().enumerate() {
+            sum += match i % 2 {
+                0 => (*b as u32) << 8,
+                _ => *b as u32,
+            };
+        }
+        sum += sum >> 16 & 0xffff;
+        sum as u16
+    }
+
+    pub(crate) fn is_revoked(&self) -> bool {
+        self.flags & REVOKE != 0
+    }
+
+    // Whether both are the same key, one perhaps revoked since.
+    pub(crate) fn same_key(&self, other: &Dnskey) -> bool {
+        self.flags & !REVOKE == other.flags & !REVOKE
+            && self.protocol == other.protocol
+            && self.algorithm == other.algorithm
+            && self.public_key == other.public_key
+    }
+
+    // The exponent and modulus of an RSA key (RFC 3110 section 2).
+    pub(crate) fn rsa(&self) -> Option<(&[u8], &[u8])> {
+        let key = &self.public_key;
+        let (len, start) = match *key.first()? {
+            0 => (u16::from_be_bytes([*key.get(1)?, *key.get(2)?]) as usize, 3),
+            len => (len as usize, 1),
+        };
+        let exponent = key.get(start..start + len)?;
+        let modulus = &key[start + len..];
+        (!modulus.is_empty()).then_some((exponent, modulus))
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Rrsig {
+    pub(crate) type_covered: u16,
+    pub(crate) algorithm: u8,
+    pub(crate) labels: u8,
+    pub(crate) original_ttl: u32,
+    pub(crate) expiration: u32,
+    pub(crate) inception: u32,
+    pub(crate) key_tag: u16,
+    pub(crate) signer: Name,
+    pub(crate) signature: Vec<u8>,
+}
+
+impl Rrsig {
+    pub(crate) fn parse(rdata: &[u8]) -> Option<Self> {
+        let fixed = rdata.get(..18)?;
+        let u32_at = |at: usize| u32::from_be_bytes(fixed[at..at + 4].try_into().unwrap());
+        // The signer's name is never compressed (RFC 4034 section 3.1.7).
+        let (signer, end) = Name::parse(rdata, 18).ok()?;
+        if end - 18 != signer.len() {
+            return None;
+        }
+        Some(Rrsig {
+            type_covered: u16::from_be_bytes([fixed[0], fixed[1]]),
+            algorithm: fixed[2],
+            labels: fixed[3],
+            original_ttl: u32_at(4),
+            expiration: u32_at(8),
+            inception: u32_at(12),
+            key_tag: u16::from_be_bytes([fixed[16], fixed[17]]),
+            signer,
+            signature: rdata[end..].to_vec(),
+        })
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.unsigned();
+        bytes.extend_from_slice(&self.signature);
+        bytes
+    }
+
+    // The RDATA without the signature, with the signer's name in canonical
+    // form, which is where the signed data starts.
+    fn unsigned(&self) -> Vec<u8> {
+        let mut bytes = self.type_covered.to_be_bytes().to_vec();
+        bytes.push(self.algorithm);
+        bytes.push(self.labels);
+        for field in [self.original_ttl, self.expiration, self.inception] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.key_tag.to_be_bytes());
+        bytes.extend_from_slice(&canonical(&self.signer));
+        bytes
+    }
+
+    // Whether `now`, in seconds since the epoch, falls within the validity
+    // period, compared in serial number arithmetic (RFC 4034 section 3.1.5).
+    pub(crate) fn current(&self, now: u64) -> bool {
+        let now = now as u32;
+        now.wrapping_sub(self.inception) as i32 >= 0
+            && self.expiration.wrapping_sub(now) as i32 >= 0
+    }
+
+    // Checks the signature over `rrset`, records of one name, type and
+    // class, with `key`. Validity dates are left to the caller.
+    pub(crate) fn verify(&self, key: &Dnskey, rrset: &[DnsAnswer]) -> bool {
+        let Some(first) = rrset.first() else {
+            return false;
+        };
+        if self.algorithm != key.algorithm
+            || self.key_tag != key.key_tag()
+            || key.flags & ZONE_KEY == 0
+            || self.type_covered != first.qtype.code()
+            || !first.name.is_subdomain_of(&self.signer)
+        {
+            return false;
+        }
+        let Some(data) = self.signed_data(rrset) else {
+            return false;
+        };
+        match (self.algorithm, key.rsa()) {
+            (RSASHA256, Some((exponent, modulus))) => {
+                crypto::rsa_sha256_verify(exponent, modulus, &data, &self.signature)
+            }
+            _ => false,
+        }
+    }
+
+    // RFC 4034 section 3.1.8.1: the RRSIG RDATA before the signature, then
+    // each record in canonical form and order, with the original TTL.
+    pub(crate) fn signed_data(&self, rrset: &[DnsAnswer]) -> Option<Vec<u8>> {
+        let first = rrset.first()?;
+        let owner = self.owner(&first.name)?;
+        let mut rdatas: Vec<Vec<u8>> = rrset
+            .iter()
+            .map(|record| canonical_rdata(&record.rdata))
+            .collect();
+        rdatas.sort();
+        rdatas.dedup();
+
+        let mut data = self.unsigned();
+        for rdata in rdatas {
+            data.extend_from_slice(&owner);
+            data.extend_from_slice(&(first.qtype.code()).to_be_bytes());
+            data.extend_from_slice(&(first.qclass as u16).to_be_bytes());
+            data.extend_from_slice(&self.original_ttl.to_be_bytes());
+            data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            data.extend_from_slice(&rdata);
+        }
+        Some(data)
+    }
+
+    // The owner name as signed: a wildcard expansion signs the wildcard.
+    fn owner(&self, name: &Name) -> Option<Vec<u8>> {
+        let labels: Vec<&str> = match name.as_str() {
+            "" => Vec::new(),
+            name => name.split('.').collect(),
+        };
+        let signed = self.labels as usize;
+        match signed.cmp(&labels.len()) {
+            Ordering::Greater => None,
+            Ordering::Equal => Some(canonical(name)),
+            Ordering::Less => {
+                let closest = labels[labels.len() - signed..].join(".");
+                let wildcard = match closest.is_empty() {
+                    true => "*".to_string(),
+                    false => format!("*.{}", closest),
+                };
+                Some(canonical(&Name::from(wildcard.as_str())))
+            }
+        }
+    }
+}
+
+// The next name in the zone after the owner, and the types at the owner
+// (RFC 4034 section 4).
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Nsec {
+    pub(crate) next: Name,
+    pub(crate) types: Vec<u16>,
+}
+
+impl Nsec {
+    pub(crate) fn parse(rdata: &[u8]) -> Option<Self> {
+        let (next, end) = Name::parse(rdata, 0).ok()?;
+        if end != next.len() {
+            return None;
+        }
+        Some(Nsec {
+            next,
+            types: parse_types(&rdata[end..])?,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = canonical(&self.next);
+        bytes.extend(write_types(&self.types));
+        bytes
+    }
+
+    // Whether `name` falls strictly between the owner and the next name,
+    // the last NSEC of a zone wrapping around to its apex.
+    pub(crate) fn covers(&self, owner: &Name, name: &Name) -> bool {
+        let after_owner = canonical_cmp(owner, name) == Ordering::Less;
+        let before_next = canonical_cmp(name, &self.next) == Ordering::Less;
+        match canonical_cmp(owner, &self.next) {
+            Ordering::Less => after_owner && before_next,
+            _ => after_owner || before_next,
+        }
+    }
+}
+
+pub(crate) const NSEC3_SHA1: u8 = 1;
+pub(crate) const NSEC3_OPT_OUT: u8 = 0x01;
+
+// Like NSEC, over hashed owner names (RFC 5155 section 3).
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Nsec3 {
+    pub(crate) algorithm: u8,
+    pub(crate) flags: u8,
+    pub(crate) iterations: u16,
+    pub(crate) salt: Vec<u8>,
+    pub(crate) next_hashed: Vec<u8>,
+    pub(crate) types: Vec<u16>,
+}
+
+impl Nsec3 {
+    pub(crate) fn parse(rdata: &[u8]) -> Option<Self> {
+        let salt_len = *rdata.get(4)? as usize;
+        let salt = rdata.get(5..5 + salt_len)?;
+        let at = 5 + salt_len;
+        let hash_len = *rdata.get(at)? as usize;
+        let next_hashed = rdata.get(at + 1..at + 1 + hash_len)?;
+        Some(Nsec3 {
+            algorithm: rdata[0],
+            flags: rdata[1],
+            iterations: u16::from_be_bytes([rdata[2], rdata[3]]),
+            salt: salt.to_vec(),
+            next_hashed: next_hashed.to_vec(),
+            types: parse_types(&rdata[at + 1 + hash_len..])?,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.algorithm, self.flags];
+        bytes.extend_from_slice(&self.iterations.to_be_bytes());
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes.push(self.next_hashed.len() as u8);
+        bytes.extend_from_slice(&self.next_hashed);
+        bytes.extend(write_types(&self.types));
+        bytes
+    }
+
+    pub(crate) fn opt_out(&self) -> bool {
+        self.flags & NSEC3_OPT_OUT != 0
+    }
+
+    // Whether `hash` falls strictly between the owner's hash and the next,
+    // wrapping around after the last.
+    pub(crate) fn covers(&self, owner: &[u8], hash: &[u8]) -> bool {
+        let next = self.next_hashed.as_slice();
+        match owner < next {
+            true => owner < hash && hash < next,
+            false => owner < hash || hash < next,
+        }
+    }
+}
+
+// The iterated, salted SHA-1 hash of the name (RFC 5155 section 5).
+pub(crate) fn nsec3_hash(name: &Name, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut data = canonical(name);
+    data.extend_from_slice(salt);
+    let mut hash = crypto::sha1(&data);
+    for _ in 0..iterations {
+        let mut data = hash.to_vec();
+        data.extend_from_slice(salt);
+        hash = crypto::sha1(&data);
+    }
+    hash.to_vec()
+}
+
+// The hash an NSEC3 record's owner name holds in its first label.
+pub(crate) fn nsec3_owner_hash(owner: &Name) -> Option<Vec<u8>> {
+    let label = owner.as_str().split('.').next()?;
+    crypto::base32hex_decode(label).filter(|hash| !hash.is_empty())
+}
+
+// RFC 4034 section 6.1: label by label from the root, each compared as
+// lowercased bytes.
+pub(crate) fn canonical_cmp(a: &Name, b: &Name) -> Ordering {
+    // Without allocating, as zones are searched with it.
+    fn labels(name: &Name) -> impl Iterator<Item = &str> {
+        name.as_str().rsplit('.').filter(|label| !label.is_empty())
+    }
+    fn lowercase(label: &str) -> impl Iterator<Item = u8> + '_ {
+        label.bytes().map(|byte| byte.to_ascii_lowercase())
+    }
+    let (mut a, mut b) = (labels(a), labels(b));
+    loop {
+        let (a, b) = match (a.next(), b.next()) {
+            (Some(a), Some(b)) => (a, b),
+            (a, b) => return a.is_some().cmp(&b.is_some()),
+        };
+        match lowercase(a).cmp(lowercase(b)) {
+            Ordering::Equal => continue,
+            other => return other,
+        }
+    }
+}
+
+// Type bitmaps (RFC 4034 section 4.1.2): windows of up to 32 bytes, a bit
+// per type.
+fn parse_types(mut bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut types = Vec::new();
+    while !bytes.is_empty() {
+        let window = *bytes.first()? as u16;
+        let len = *bytes.get(1)? as usize;
+        if len == 0 || len > 32 {
+            return None;
+        }
+        let bitmap = bytes.get(2..2 + len)?;
+        for (i, byte) in bitmap.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(window << 8 | (i * 8 + bit) as u16);
+                }
+            }
+        }
+        bytes = &bytes[2 + len..];
+    }
+    Some(types)
+}
+
+#[allow(dead_code)]
+fn write_types(types: &[u16]) -> Vec<u8> {
+    let mut types = types.to_vec();
+    types.sort();
+    types.dedup();
+    let mut bytes = Vec::new();
+    for window in 0..=255u16 {
+        let bits: Vec<u16> = types
+            .iter()
+            .filter(|t| *t >> 8 == window)
+            .map(|t| t & 0xff)
+            .collect();
+        let Some(last) = bits.last() else {
+            continue;
+        };
+        let mut bitmap = vec![0u8; *last as usize / 8 + 1];
+        for bit in bits {
+            bitmap[bit as usize / 8] |= 0x80 >> (bit % 8);
+        }
+        bytes.push(window as u8);
+        bytes.push(bitmap.len() as u8);
+        bytes.extend(bitmap);
+    }
+    bytes
+}
+
+// The keys in a DNSKEY RRset, skipping any that don't parse.
+pub(crate) fn dnskeys(records: &[DnsAnswer]) -> Vec<Dnskey> {
+    records
+        .iter()
+        .filter(|record| record.qtype == DnsType::Dnskey && record.qclass == DnsClass::In)
+        .filter_map(|record| match &record.rdata {
+            RData::Unknown(rdata) => Dnskey::parse(rdata),
+            _ => None,
+        })
+        .collect()
+}
+
+// The signatures covering `qtype` among `records`.
+pub(crate) fn rrsigs(records: &[DnsAnswer], qtype: DnsType) -> Vec<Rrsig> {
+    records
+        .iter()
+        .filter(|record| record.qtype == DnsType::Rrsig)
+        .filter_map(|record| match &record.rdata {
+            RData::Unknown(rdata) => Rrsig::parse(rdata),
+            _ => None,
+        })
+        .filter(|rrsig| rrsig.type_covered == qtype.code())
+        .collect()
+}
+
+// The uncompressed, lowercased wire form (RFC 4034 section 6.2).
+pub(crate) fn canonical(name: &Name) -> Vec<u8> {
+    name.to_bytes().to_ascii_lowercase()
+}
+
+// Names in RDATA are lowercased and written uncompressed, as RFC 4034
+// section 6.2 has for every type here with one.
+fn canonical_rdata(rdata: &RData) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match rdata {
+        RData::Ns(name) | RData::Cname(name) | RData::Ptr(name) => {
+            bytes.extend_from_slice(&canonical(name))
+        }
+        // Not types RFC 4034 lists, so left as they are.
+        RData::Alias(name) => bytes.extend_from_slice(&name.to_bytes()),
+        RData::Template(template) => {
+            bytes.push(template.name().len() as u8);
+            bytes.extend_from_slice(template.name().as_bytes());
+        }
+        RData::Pool { rank, port, target } => {
+            bytes.extend_from_slice(&rank.to_be_bytes());
+            bytes.extend_from_slice(&port.to_be_bytes());
+            bytes.extend_from_slice(&target.to_bytes());
+        }
+        RData::Mx {
+            preference,
+            exchange,
+        } => {
+            bytes.extend_from_slice(&preference.to_be_bytes());
+            bytes.extend_from_slice(&canonical(exchange));
+        }
+        RData::Soa {
+            mname,
+            rname,
+            serial,
+            refresh,
+            retry,
+            expire,
+            minimum,
+        } => {
+            bytes.extend_from_slice(&canonical(mname));
+            bytes.extend_from_slice(&canonical(rname));
+            for field in [serial, refresh, retry, expire, minimum] {
+                bytes.extend_from_slice(&field.to_be_bytes());
+            }
+        }
+        RData::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => {
+            for field in [priority, weight, port] {
+                bytes.extend_from_slice(&field.to_be_bytes());
+            }
+            bytes.extend_from_slice(&canonical(target));
+        }
+        RData::A(ip) => bytes.extend_from_slice(ip),
+        RData::Aaaa(ip) => bytes.extend_from_slice(ip),
+        RData::Txt(strings) => {
+            for string in strings {
+                bytes.push(string.len() as u8);
+                bytes.extend_from_slice(string);
+            }
+        }
+        RData::Unknown(data) => bytes.extend_from_slice(data),
+    }
+    bytes
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // A 512-bit RSA key pair, small so that signing in tests is quick.
+    pub(crate) const MODULUS: &str = "bd55b8c0187aa7a2aa8e55bda7264625b1885f4934d17db3e9edcb452b8306ba\
+                                      29cb9855b467d89f3652364223b9001790465699f3aa6a70dee542c855635ab3";
+    pub(crate) const PRIVATE_EXPONENT: &str =
+        "a1f4bdc0b42fd74db20f67107ff6a17284d65e8bc47e9ec4538a71a28d81db31\
+         d11aae21416cb170436e9a70091aef649441a536775de6579c4c0d00b2c5cfe1";
+    // Another, for rollovers.
+    pub(crate) const MODULUS_2: &str = "da9df41e10b22e7dc9ef449dce66498c0f5fec7c41076bd2784bc5ab17938646\
+                                        90e29b88e1b6a66dee53e2aa782f2102d7f41447cb9e24a73a6dbc86316b75b1";
+    pub(crate) const PRIVATE_EXPONENT_2: &str =
+        "34406c88831513679d4c42ea34daddd94c1371428188748ce993b2bb21f770b0\
+         1bc8d42d3d795046fe31c911d79f01f8227fe4934b121383a5d9f7695c375c01";
+
+    pub(crate) fn unhex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // A key signing key for the modulus, with exponent 65537.
+    pub(crate) fn ksk(modulus: &str) -> Dnskey {
+        let mut public_key = vec![3, 1, 0, 1];
+        public_key.extend(unhex(modulus));
+        Dnskey {
+            flags: ZONE_KEY | SECURE_ENTRY_POINT,
+            protocol: 3,
+            algorithm: RSASHA256,
+            public_key,
+        }
+    }
+
+    pub(crate) fn dnskey_record(owner: &str, key: &Dnskey) -> DnsAnswer {
+        DnsAnswer::new(
+            owner.into(),
+            DnsType::Dnskey,
+            DnsClass::In,
+            172800,
+            RData::Unknown(key.to_bytes()),
+        )
+    }
+
+    // An RRSIG record over `rrset` by `key`, valid from `inception` for
+    // thirty days.
+    pub(crate) fn sign(
+        rrset: &[DnsAnswer],
+        signer: &str,
+        key: &Dnskey,
+        private_exponent: &str,
+        inception: u32,
+    ) -> DnsAnswer {
+        let first = &rrset[0];
+        // Not counting a wildcard's asterisk.
+        let labels = first
+            .name
+            .as_str()
+            .split('.')
+            .filter(|label| !label.is_empty() && *label != "*")
+            .count() as u8;
+        let mut rrsig = Rrsig {
+            type_covered: first.qtype.code(),
+            algorithm: key.algorithm,
+            labels,
+            original_ttl: first.ttl as u32,
+            expiration: inception + 30 * 86400,
+            inception,
+            key_tag: key.key_tag(),
+            signer: signer.into(),
+            signature: Vec::new(),
+        };
+        let data = rrsig.signed_data(rrset).unwrap();
+        let (_, modulus) = key.rsa().unwrap();
+        rrsig.signature = crypto::rsa_sha256_sign(&unhex(private_exponent), modulus, &data);
+        DnsAnswer::new(
+            first.name.clone(),
+            DnsType::Rrsig,
+            DnsClass::In,
+            first.ttl,
+            RData::Unknown(rrsig.to_bytes()),
+        )
+    }
+
+    #[test]
+    fn test_key_tag() {
+        // The root KSK-2017 (RFC 4034 appendix B's algorithm, checked
+        // against the tag IANA publishes).
+        let key = Dnskey {
+            flags: 257,
+            protocol: 3,
+            algorithm: RSASHA256,
+            public_key: crypto::base64_decode(
+                "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN\
+                 7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8\
+                 efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLY\
+                 A4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+            )
+            .unwrap(),
+        };
+        assert_eq!(key.key_tag(), 20326);
+        assert_eq!(Dnskey::parse(&key.to_bytes()), Some(key));
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = ksk(MODULUS);
+        let other = ksk(MODULUS_2);
+        let rrset = vec![
+            dnskey_record("example.com", &key),
+            dnskey_record("Example.COM", &other),
+        ];
+        let record = sign(&rrset, "example.com", &key, PRIVATE_EXPONENT, 1_700_000_000);
+        let RData::Unknown(rdata) = &record.rdata else {
+            panic!("RRSIG as {:?}", record.rdata);
+        };
+        let rrsig = Rrsig::parse(rdata).unwrap();
+        assert_eq!(rrsig.to_bytes(), *rdata);
+        assert!(rrsig.verify(&key, &rrset));
+        // Order and TTLs don't matter to the signature.
+        let mut reordered = vec![rrset[1].clone(), rrset[0].clone()];
+        reordered[0].ttl = 60;
+        assert!(rrsig.verify(&key, &reordered));
+
+        assert!(!rrsig.verify(&other, &rrset));
+        assert!(!rrsig.verify(&key, &rrset[..1]));
+        let mut revoked = key.clone();
+        revoked.flags |= REVOKE;
+        assert!(!rrsig.verify(&revoked, &rrset));
+
+        assert!(rrsig.current(1_700_000_000));
+        assert!(rrsig.current(1_700_000_000 + 30 * 86400));
+        assert!(!rrsig.current(1_699_999_999));
+        assert!(!rrsig.current(1_700_000_001 + 30 * 86400));
+    }
+
+    #[test]
+    fn test_nsec() {
+        let nsec = Nsec {
+            next: "c.example.com".into(),
+            types: vec![1, 46, 47, 257],
+        };
+        assert_eq!(Nsec::parse(&nsec.to_bytes()), Some(nsec.clone()));
+        let owner = Name::from("a.example.com");
+        assert!(nsec.covers(&owner, &"b.example.com".into()));
+        assert!(nsec.covers(&owner, &"x.A.example.com".into()));
+        assert!(!nsec.covers(&owner, &"a.example.com".into()));
+        assert!(!nsec.covers(&owner, &"c.example.com".into()));
+        assert!(!nsec.covers(&owner, &"d.example.com".into()));
+
+        // The last in the zone, pointing back at the apex.
+        let last = Nsec {
+            next: "example.com".into(),
+            types: vec![1],
+        };
+        assert!(last.covers(&"z.example.com".into(), &"zz.example.com".into()));
+        assert!(!last.covers(&"z.example.com".into(), &"b.example.com".into()));
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // From RFC 4034 section 6.1, less the escaped labels.
+        let names = [
+            "example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "zABC.a.EXAMPLE",
+            "z.example",
+            "*.z.example",
+        ];
+        let mut sorted: Vec<Name> = names.iter().rev().map(|name| Name::from(*name)).collect();
+        sorted.sort_by(canonical_cmp);
+        let sorted: Vec<&str> = sorted.iter().map(Name::as_str).collect();
+        assert_eq!(sorted, names);
+    }
+
+    #[test]
+    fn test_nsec3() {
+        // RFC 5155 appendix A.
+        let hash = nsec3_hash(&"example".into(), &[0xaa, 0xbb, 0xcc, 0xdd], 12);
+        assert_eq!(
+            crypto::base32hex_encode(&hash),
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"
+        );
+        let owner = Name::from("0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM.example");
+        assert_eq!(nsec3_owner_hash(&owner), Some(hash.clone()));
+
+        let nsec3 = Nsec3 {
+            algorithm: NSEC3_SHA1,
+            flags: NSEC3_OPT_OUT,
+            iterations: 12,
+            salt: vec![0xaa, 0xbb, 0xcc, 0xdd],
+            next_hashed: vec![0x40; 20],
+            types: vec![2, 6, 46, 48, 51],
+        };
+        assert_eq!(Nsec3::parse(&nsec3.to_bytes()), Some(nsec3.clone()));
+        assert!(nsec3.opt_out());
+        assert!(nsec3.covers(&hash, &[0x10; 20]));
+        assert!(!nsec3.covers(&hash, &[0x50; 20]));
+        assert!(nsec3.covers(&[0x50; 20], &[0x60; 20]));
+        assert!(nsec3.covers(&[0x50; 20], &[0x00; 20]));
+    }
+
+    #[test]
+    fn test_wildcard_owner() {
+        let key = ksk(MODULUS);
+        let rrset = vec![DnsAnswer::new(
+            "*.example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([192, 0, 2, 1]),
+        )];
+        let record = sign(&rrset, "example.com", &key, PRIVATE_EXPONENT, 1_700_000_000);
+        let rrsig = rrsigs(&[record], DnsType::A).remove(0);
+        assert_eq!(rrsig.labels, 2);
+        let mut expanded = rrset.clone();
+        expanded[0].name = "www.Example.com".into();
+        assert!(rrsig.verify(&key, &expanded));
+        expanded[0].name = "www.example.org".into();
+        assert!(!rrsig.verify(&key, &expanded));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::common::{backpatch_length, DnsType};
+use crate::error::ParseError;
+
+pub(crate) const NSID: u16 = 3;
+pub(crate) const EXPIRE: u16 = 9;
+pub(crate) const TCP_KEEPALIVE: u16 = 11;
+pub(crate) const EXTENDED_ERROR: u16 = 15;
+
+// Extended DNS Error info codes (RFC 8914).
+pub(crate) const EDE_OTHER: u16 = 0;
+pub(crate) const EDE_BLOCKED: u16 = 15;
+pub(crate) const EDE_NO_REACHABLE_AUTHORITY: u16 = 22;
+
+// The OPT pseudo-record (RFC 6891). It lives in the additional section but
+// reuses the CLASS and TTL fields for its own purposes, so it is kept apart
+// from the regular records.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Edns {
+    pub(crate) udp_payload_size: u16,
+    pub(crate) extended_rcode: u8,
+    pub(crate) version: u8,
+    pub(crate) dnssec_ok: bool,
+    pub(crate) options: Vec<EdnsOption>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) enum EdnsOption {
+    Nsid(Vec<u8>), // name server identifier (RFC 5001), empty in queries
+    // edns-tcp-keepalive (RFC 7828): the idle timeout in units of 100ms,
+    // sent by servers only.
+    TcpKeepalive(Option<u16>),
+    // EXPIRE (RFC 7314): the seconds until a secondary should stop serving
+    // the zone, empty in queries.
+    Expire(Option<u32>),
+    ExtendedError(u16, String),
+    Unknown(u16, Vec<u8>),
+}
+
+impl Edns {
+    pub(crate) fn new(udp_payload_size: u16) -> Self {
+        Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+
+    // `offset` points just past the owner name, which must be the root.
+    pub(crate) fn parse(message: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let fixed = message
+            .get(offset..offset + 10)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let qtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        if qtype != DnsType::Opt.code() {
+            return Err(ParseError::InvalidValue(qtype));
+        }
+        let udp_payload_size = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let extended_rcode = fixed[4];
+        let version = fixed[5];
+        let dnssec_ok = fixed[6] & 0x80 != 0;
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]);
+
+        let start = offset + 10;
+        let end = start + rdlength as usize;
+        let mut rdata = message.get(start..end).ok_or(ParseError::UnexpectedEof)?;
+
+        let mut options = Vec::new();
+        while !rdata.is_empty() {
+            let header = rdata.get(..4).ok_or(ParseError::UnexpectedEof)?;
+            let code = u16::from_be_bytes([header[0], header[1]]);
+            let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let data = rdata.get(4..4 + len).ok_or(ParseError::UnexpectedEof)?;
+            options.push(EdnsOption::parse(code, data));
+            rdata = &rdata[4 + len..];
+        }
+
+        let edns = Edns {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok,
+            options,
+        };
+        Ok((edns, end))
+    }
+
+    pub(crate) fn nsid_requested(&self) -> bool {
+        self.options
+            .iter()
+            .any(|option| matches!(option, EdnsOption::Nsid(_)))
+    }
+
+    pub(crate) fn keepalive_requested(&self) -> bool {
+        self.options
+            .iter()
+            .any(|option| matches!(option, EdnsOption::TcpKeepalive(_)))
+    }
+
+    pub(crate) fn expire_requested(&self) -> bool {
+        self.options
+            .iter()
+            .any(|option| matches!(option, EdnsOption::Expire(_)))
+    }
+
+    // The EXPIRE a primary answered with.
+    pub(crate) fn expire(&self) -> Option<u32> {
+        self.options.iter().find_map(|option| match option {
+            EdnsOption::Expire(expire) => *expire,
+            _ => None,
+        })
+    }
+
+    // Appends the OPT record. Lengths are filled in once what they cover
+    // has been written.
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.push(0); // root owner name
+        bytes.extend_from_slice(&(DnsType::Opt.code()).to_be_bytes());
+        bytes.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        bytes.push(self.extended_rcode);
+        bytes.push(self.version);
+        bytes.push((self.dnssec_ok as u8) << 7);
+        bytes.push(0);
+        let rdata = bytes.len();
+        bytes.extend_from_slice(&[0, 0]);
+        for option in &self.options {
+            let (code, data) = option.encode();
+            bytes.extend_from_slice(&code.to_be_bytes());
+            bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&data);
+        }
+        backpatch_length(bytes, rdata);
+    }
+}
+
+// How the data of each known option code is read and written. Adding an
+// option takes a variant of EdnsOption and an entry here; the OPT record
+// itself is parsed and written the same way for all of them. Other codes,
+// and data a parser rejects, are kept as Unknown and written back
+// unchanged.
+struct OptionKind {
+    code: u16,
+    parse: fn(&[u8]) -> Option<EdnsOption>,
+    // The option's data, if it is of this kind.
+    write: fn(&EdnsOption) -> Option<Vec<u8>>,
+}
+
+const OPTIONS: [OptionKind; 4] = [
+    OptionKind {
+        code: NSID,
+        parse: |data| Some(EdnsOption::Nsid(data.to_vec())),
+        write: |option| match option {
+            EdnsOption::Nsid(value) => Some(value.clone()),
+            _ => None,
+        },
+    },
+    OptionKind {
+        code: EXPIRE,
+        parse: |data| match *data {
+            [] => Some(EdnsOption::Expire(None)),
+            [a, b, c, d] => Some(EdnsOption::Expire(Some(u32::from_be_bytes([a, b, c, d])))),
+            _ => None,
+        },
+        write: |option| match option {
+            EdnsOption::Expire(expire) => {
+                Some(expire.iter().flat_map(|e| e.to_be_bytes()).collect())
+            }
+            _ => None,
+        },
+    },
+    OptionKind {
+        code: TCP_KEEPALIVE,
+        parse: |data| match *data {
+            [] => Some(EdnsOption::TcpKeepalive(None)),
+            [high, low] => Some(EdnsOption::TcpKeepalive(Some(u16::from_be_bytes([
+                high, low,
+            ])))),
+            _ => None,
+        },
+        write: |option| match option {
+            EdnsOption::TcpKeepalive(timeout) => {
+                Some(timeout.iter().flat_map(|t| t.to_be_bytes()).collect())
+            }
+            _ => None,
+        },
+    },
+    OptionKind {
+        code: EXTENDED_ERROR,
+        parse: |data| {
+            let info = data.get(..2)?;
+            Some(EdnsOption::ExtendedError(
+                u16::from_be_bytes([info[0], info[1]]),
+                String::from_utf8_lossy(&data[2..]).into_owned(),
+            ))
+        },
+        write: |option| match option {
+            EdnsOption::ExtendedError(info, text) => {
+                Some([&info.to_be_bytes(), text.as_bytes()].concat())
+            }
+            _ => None,
+        },
+    },
+];
+
+// Whether options with the code are parsed into their own variant.
+#[cfg(test)]
+pub(crate) fn known(code: u16) -> bool {
+    OPTIONS.iter().any(|kind| kind.code == code)
+}
+
+impl EdnsOption {
+    pub(crate) fn parse(code: u16, data: &[u8]) -> Self {
+        OPTIONS
+            .iter()
+            .find(|kind| kind.code == code)
+            .and_then(|kind| (kind.parse)(data))
+            .unwrap_or_else(|| EdnsOption::Unknown(code, data.to_vec()))
+    }
+
+    // The option's code and data, as they go on the wire.
+    fn encode(&self) -> (u16, Vec<u8>) {
+        if let EdnsOption::Unknown(code, data) = self {
+            return (*code, data.clone());
+        }
+        OPTIONS
+            .iter()
+            .find_map(|kind| Some((kind.code, (kind.write)(self)?)))
+            .expect("every option variant has an entry in OPTIONS")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_query_opt() {
+        // dig +nsid: 1232 byte payload, DO clear, empty NSID option.
+        let bytes = [
+            0x00, // root
+            0x00, 0x29, // TYPE = OPT
+            0x04, 0xd0, // UDP payload size = 1232
+            0x00, 0x00, 0x00, 0x00, // extended RCODE, version, flags
+            0x00, 0x04, // RDLENGTH = 4
+            0x00, 0x03, 0x00, 0x00, // NSID, length 0
+        ];
+        let (edns, end) = Edns::parse(&bytes, 1).unwrap();
+        assert_eq!(end, bytes.len());
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert!(!edns.dnssec_ok);
+        assert!(edns.nsid_requested());
+        let mut written = Vec::new();
+        edns.write(&mut written);
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn test_round_trip_with_options() {
+        let edns = Edns {
+            udp_payload_size: 4096,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![
+                EdnsOption::Nsid(b"worker-1".to_vec()),
+                EdnsOption::TcpKeepalive(Some(100)),
+                EdnsOption::TcpKeepalive(None),
+                EdnsOption::Expire(Some(1209600)),
+                EdnsOption::Expire(None),
+                EdnsOption::ExtendedError(EDE_OTHER, "deadline exceeded".into()),
+                EdnsOption::Unknown(10, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            ],
+        };
+        let mut bytes = Vec::new();
+        edns.write(&mut bytes);
+        assert_eq!(Edns::parse(&bytes, 1).unwrap(), (edns, bytes.len()));
+    }
+
+    #[test]
+    fn test_unknown_options() {
+        // Unregistered codes, and registered ones with data their parser
+        // rejects, pass through unchanged.
+        for (code, data) in [
+            (65001, &[0x02, 0x42, 0xac, 0x11, 0x00, 0x02][..]),
+            (TCP_KEEPALIVE, &[0, 1, 2]),
+            (EXTENDED_ERROR, &[0]),
+            (EXPIRE, &[0, 0, 1]),
+        ] {
+            let option = EdnsOption::parse(code, data);
+            assert_eq!(option, EdnsOption::Unknown(code, data.to_vec()));
+            assert_eq!(option.encode(), (code, data.to_vec()));
+        }
+        assert!(known(NSID) && !known(65001));
+    }
+
+    #[test]
+    fn test_truncated_option() {
+        let bytes = [
+            0x00, 0x00, 0x29, 0x02, 0x00, 0, 0, 0, 0, 0x00, 0x04, 0x00, 0x03, 0x00, 0x08,
+        ];
+        assert_eq!(Edns::parse(&bytes, 1), Err(ParseError::UnexpectedEof));
+    }
+}
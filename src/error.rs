@@ -1,7 +1,99 @@
+use std::io;
+
 use thiserror::Error;
 
 #[derive(PartialEq, Debug, Error)]
-pub(crate) enum ParseError {
+pub enum ParseError {
     #[error("unparseable value: {0}")]
-    InvalidValue(u8),
+    InvalidValue(u16),
+    #[error("unexpected end of message")]
+    UnexpectedEof,
+    #[error("invalid compression pointer")]
+    InvalidPointer,
+    #[error("name exceeds 255 bytes")]
+    NameTooLong,
+    #[error("invalid rdata length: {0}")]
+    InvalidLength(u16),
+    #[error("unknown mnemonic: {0}")]
+    InvalidMnemonic(String),
+}
+
+#[derive(PartialEq, Debug, Error)]
+pub(crate) enum ConfigError {
+    #[error("unknown flag: {0}")]
+    UnknownFlag(String),
+    #[error("missing value for {0}")]
+    MissingValue(String),
+    #[error("invalid value for {0}: {1}")]
+    InvalidValue(String, String),
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveError {
+    #[error("no nameservers configured")]
+    NoServers,
+    #[error("timed out waiting for a response")]
+    Timeout,
+    #[error("resolution deadline exceeded")]
+    Deadline,
+    #[error("too many {0} while resolving")]
+    LimitExceeded(&'static str),
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed response: {0}")]
+    Parse(#[from] ParseError),
+    #[error("no such domain")]
+    NxDomain,
+    #[error("server failure")]
+    ServFail,
+    #[error("no upstream server reachable")]
+    Unreachable,
+    #[error("query refused with rcode {0}")]
+    Rcode(u8),
+    #[error("TSIG: {0}")]
+    Tsig(#[from] TsigError),
+    #[error("zone transfer failed: {0}")]
+    Transfer(String),
+}
+
+#[derive(PartialEq, Debug, Error)]
+pub enum TsigError {
+    #[error("message is not signed")]
+    Unsigned,
+    #[error("unknown key or algorithm")]
+    BadKey,
+    #[error("signature does not verify")]
+    BadSig,
+    #[error("signature time is outside the allowed window")]
+    BadTime,
+    #[error("error {0}")]
+    Other(u16),
+    #[error("malformed record: {0}")]
+    Parse(#[from] ParseError),
+}
+
+#[derive(PartialEq, Debug, Error)]
+pub(crate) enum PcapError {
+    #[error("not a pcap file (magic {0:#010x})")]
+    BadMagic(u32),
+    #[error("pcapng is not supported, convert with `editcap -F pcap`")]
+    Pcapng,
+    #[error("unsupported link type {0}")]
+    UnsupportedLinkType(u32),
+    #[error("file is truncated")]
+    Truncated,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum HttpError {
+    #[error("bad URL {0:?}, expected http://host[:port][/path]")]
+    BadUrl(String),
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("HTTP status {0}")]
+    Status(u16),
+    #[error("malformed response: {0}")]
+    Malformed(&'static str),
+    #[error("bad JSON: {0}")]
+    Json(String),
 }
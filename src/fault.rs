@@ -0,0 +1,203 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::common::Name;
+
+// What a `--fault` rule does to the responses it picks.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Action {
+    // Sends nothing, as if the response were lost.
+    Drop,
+    // Sends the response late.
+    Delay(Duration),
+    // Sends an empty response with TC set, over UDP only.
+    Truncate,
+    Servfail,
+}
+
+// One `--fault drop|tc|servfail|delay=<ms>[,rate=<percent>][,name=zone]`
+// rule, for testing how clients cope with a misbehaving server. It picks
+// the given share of responses, all by default, to names at or below the
+// zone, or to every name.
+struct Rule {
+    spec: String,
+    action: Action,
+    // Out of 100.
+    rate: f64,
+    zone: Option<Name>,
+    hits: AtomicU64,
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let first = parts.next().unwrap_or_default();
+        let action = match (first, first.split_once('=')) {
+            ("drop", _) => Action::Drop,
+            ("tc", _) => Action::Truncate,
+            ("servfail", _) => Action::Servfail,
+            (_, Some(("delay", ms))) => {
+                let ms = ms.strip_suffix("ms").unwrap_or(ms);
+                let ms = ms.parse().map_err(|_| format!("invalid delay {:?}", ms))?;
+                Action::Delay(Duration::from_millis(ms))
+            }
+            _ => {
+                return Err(format!(
+                    "expected drop, tc, servfail or delay=<ms> in {:?}",
+                    spec
+                ))
+            }
+        };
+        let (mut rate, mut zone) = (100.0, None);
+        for part in parts {
+            match part.split_once('=') {
+                Some(("rate", percent)) => {
+                    rate = percent
+                        .strip_suffix('%')
+                        .unwrap_or(percent)
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=100.0).contains(rate))
+                        .ok_or_else(|| format!("rate {:?} is not in 0..=100", percent))?;
+                }
+                Some(("name", name)) if !name.is_empty() => zone = Some(Name::from(name)),
+                _ => return Err(format!("unknown fault option {:?}", part)),
+            }
+        }
+        Ok(Rule {
+            spec: spec.to_string(),
+            action,
+            rate,
+            zone,
+            hits: AtomicU64::new(0),
+        })
+    }
+}
+
+// The faults drawn for one response.
+#[derive(PartialEq, Debug, Default)]
+pub(crate) struct Injected {
+    pub(crate) drop: bool,
+    pub(crate) delay: Duration,
+    pub(crate) truncate: bool,
+    pub(crate) servfail: bool,
+}
+
+// Every rule draws for each response on its own, so several faults can
+// strike the same response.
+#[derive(Default)]
+pub(crate) struct Faults {
+    rules: Vec<Rule>,
+}
+
+impl Faults {
+    pub(crate) fn add(&mut self, spec: &str) -> Result<(), String> {
+        self.rules.push(Rule::parse(spec)?);
+        Ok(())
+    }
+
+    pub(crate) fn draw(&self, qname: &Name) -> Injected {
+        let mut injected = Injected::default();
+        let mut rng = rand::thread_rng();
+        for rule in &self.rules {
+            let matches = match &rule.zone {
+                Some(zone) => qname.is_subdomain_of(zone),
+                None => true,
+            };
+            if !matches || rng.gen_range(0.0..100.0) >= rule.rate {
+                continue;
+            }
+            rule.hits.fetch_add(1, Ordering::Relaxed);
+            match rule.action {
+                Action::Drop => injected.drop = true,
+                Action::Delay(delay) => injected.delay += delay,
+                Action::Truncate => injected.truncate = true,
+                Action::Servfail => injected.servfail = true,
+            }
+        }
+        injected
+    }
+
+    // Hit counters in the `stats` layout, optionally starting over.
+    pub(crate) fn report(&self, reset: bool) -> String {
+        let mut report = String::new();
+        for rule in &self.rules {
+            let hits = match reset {
+                true => rule.hits.swap(0, Ordering::Relaxed),
+                false => rule.hits.load(Ordering::Relaxed),
+            };
+            writeln!(report, "fault.hits.{}={}", rule.spec, hits).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn faults(specs: &[&str]) -> Faults {
+        let mut faults = Faults::default();
+        for spec in specs {
+            faults.add(spec).unwrap();
+        }
+        faults
+    }
+
+    #[test]
+    fn test_parse() {
+        let rule = Rule::parse("delay=250ms,rate=12.5%,name=example.com").unwrap();
+        assert_eq!(rule.action, Action::Delay(Duration::from_millis(250)));
+        assert_eq!((rule.rate, rule.zone), (12.5, Some("example.com".into())));
+        assert_eq!(Rule::parse("tc").unwrap().action, Action::Truncate);
+        for bad in [
+            "",
+            "explode",
+            "dropped",
+            "delay=soon",
+            "drop,rate=150",
+            "drop,name=",
+            "servfail,client=10.0.0.1",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_draw() {
+        let faults = faults(&[
+            "servfail,name=broken.example",
+            "delay=100,name=example",
+            "delay=50",
+            "drop,rate=0",
+        ]);
+        assert_eq!(
+            faults.draw(&"www.broken.example".into()),
+            Injected {
+                servfail: true,
+                delay: Duration::from_millis(150),
+                ..Injected::default()
+            }
+        );
+        assert_eq!(
+            faults.draw(&"example.org".into()),
+            Injected {
+                delay: Duration::from_millis(50),
+                ..Injected::default()
+            }
+        );
+        assert_eq!(
+            faults.report(true),
+            "fault.hits.servfail,name=broken.example=1\n\
+             fault.hits.delay=100,name=example=1\n\
+             fault.hits.delay=50=2\n\
+             fault.hits.drop,rate=0=0\n"
+        );
+        assert!(faults
+            .report(false)
+            .starts_with("fault.hits.servfail,name=broken.example=0\n"));
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::clock::SharedClock;
+use crate::common::Name;
+use crate::config::Config;
+use crate::edns::Edns;
+use crate::error::ResolveError;
+use crate::handler::State;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::resolver::Resolver;
+use crate::sanitize;
+
+// A named set of upstream servers. With a source address, queries to them
+// leave from that address, which lets policy routing send e.g. corporate
+// zones over a VPN tunnel and everything else over the WAN.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct UpstreamGroup {
+    pub(crate) name: String,
+    pub(crate) servers: Vec<SocketAddr>,
+    pub(crate) source: Option<IpAddr>,
+    // Every query over TCP, on pooled connections, rather than UDP first.
+    pub(crate) tcp: bool,
+    // Servers given by name and port, looked up through `bootstrap`.
+    pub(crate) hosts: Vec<(Name, u16)>,
+    pub(crate) bootstrap: Vec<SocketAddr>,
+}
+
+impl UpstreamGroup {
+    // `name=addr[,addr...][,source=ip][,tcp][,bootstrap=addr...]`, where an
+    // address without a port uses 53. A server may also be a host name with
+    // an optional port, looked up through the bootstrap servers, or pinned
+    // to an address with `host[:port]@ip`.
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let (name, rest) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| format!("expected name=servers, got {:?}", spec))?;
+        let mut group = UpstreamGroup {
+            name: name.to_string(),
+            servers: Vec::new(),
+            source: None,
+            tcp: false,
+            hosts: Vec::new(),
+            bootstrap: Vec::new(),
+        };
+        for part in rest.split(',') {
+            if part == "tcp" {
+                group.tcp = true;
+            } else if let Some(server) = part.strip_prefix("bootstrap=") {
+                let server = parse_addr(server)
+                    .ok_or_else(|| format!("bad bootstrap address {:?}", server))?;
+                group.bootstrap.push(server);
+            } else if let Some(source) = part.strip_prefix("source=") {
+                let source = source
+                    .parse()
+                    .map_err(|_| format!("bad source address {:?}", source))?;
+                group.source = Some(source);
+            } else if let Some(server) = parse_addr(part) {
+                group.servers.push(server);
+            } else {
+                let (host, pinned) = match part.split_once('@') {
+                    Some((host, ip)) => (host, Some(ip)),
+                    None => (part, None),
+                };
+                let (host, port) =
+                    parse_host(host).ok_or_else(|| format!("bad upstream address {:?}", part))?;
+                match pinned {
+                    Some(ip) => {
+                        let ip: IpAddr = ip
+                            .parse()
+                            .map_err(|_| format!("bad pinned address {:?}", ip))?;
+                        group.servers.push(SocketAddr::new(ip, port));
+                    }
+                    None => group.hosts.push((host, port)),
+                }
+            }
+        }
+        if group.servers.is_empty() && group.hosts.is_empty() {
+            return Err(format!("upstream group {} has no servers", group.name));
+        }
+        if !group.hosts.is_empty() && group.bootstrap.is_empty() {
+            return Err(format!(
+                "upstream group {} names servers but has no bootstrap servers to look them up",
+                group.name
+            ));
+        }
+        if let Some(source) = group.source {
+            if group
+                .servers
+                .iter()
+                .chain(&group.bootstrap)
+                .any(|s| s.is_ipv4() != source.is_ipv4())
+            {
+                return Err(format!(
+                    "source {} and the servers of {} must be the same address family",
+                    source, group.name
+                ));
+            }
+        }
+        Ok(group)
+    }
+
+    fn resolver(&self, servers: Vec<SocketAddr>) -> Resolver {
+        let resolver = Resolver::new(servers).with_tcp(self.tcp);
+        match self.source {
+            Some(source) => resolver.with_source(source),
+            None => resolver,
+        }
+    }
+}
+
+// `ip` or `ip:port`, with port 53 by default.
+fn parse_addr(addr: &str) -> Option<SocketAddr> {
+    addr.parse()
+        .ok()
+        .or_else(|| Some(SocketAddr::new(addr.parse().ok()?, 53)))
+}
+
+// `host` or `host:port`: a name of letters, digits and hyphens in at least
+// two labels, so that a mistyped address isn't taken for one.
+fn parse_host(spec: &str) -> Option<(Name, u16)> {
+    let (host, port) = match spec.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (spec, 53),
+    };
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let labels: Vec<&str> = host.split('.').collect();
+    let valid = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.bytes().any(|b| b.is_ascii_alphabetic()));
+    valid.then(|| (Name::from(host), port))
+}
+
+// The upstream group for the most specific `--forward` zone containing the
+// name, if any.
+pub(crate) fn select<'a>(config: &'a Config, qname: &Name) -> Option<&'a UpstreamGroup> {
+    let (_, group) = config
+        .forward_zones
+        .iter()
+        .filter(|(zone, _)| qname.is_subdomain_of(zone))
+        .max_by_key(|(zone, _)| zone.len())?;
+    config
+        .upstreams
+        .iter()
+        .find(|upstream| &upstream.name == group)
+}
+
+// How long an upstream group is taken for down after none of its servers
+// answered: at first the shortest, doubling with each failure in a row.
+const DOWN_MIN: Duration = Duration::from_secs(2);
+const DOWN_MAX: Duration = Duration::from_secs(30);
+
+// Upstream groups none of whose servers answered lately. Queries for one
+// fail at once until its time is up, rather than each waiting out the
+// query budget; the first query after that tries the servers again.
+#[derive(Default)]
+pub(crate) struct Outages {
+    groups: Mutex<HashMap<String, Outage>>,
+    clock: SharedClock,
+}
+
+struct Outage {
+    until: Instant,
+    backoff: Duration,
+}
+
+impl Outages {
+    #[cfg(test)]
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Outages {
+            clock,
+            ..Outages::default()
+        }
+    }
+
+    // How much longer the group is down, if it is.
+    pub(crate) fn down(&self, group: &str) -> Option<Duration> {
+        let groups = self.groups.lock().unwrap();
+        let remaining = groups
+            .get(group)?
+            .until
+            .saturating_duration_since(self.clock.now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    fn failed(&self, group: &str) {
+        let mut groups = self.groups.lock().unwrap();
+        let backoff = match groups.get(group) {
+            Some(outage) => (outage.backoff * 2).min(DOWN_MAX),
+            None => DOWN_MIN,
+        };
+        let until = self.clock.now() + backoff;
+        groups.insert(group.to_string(), Outage { until, backoff });
+    }
+
+    fn answered(&self, group: &str) {
+        self.groups.lock().unwrap().remove(group);
+    }
+}
+
+// Lookups currently waiting on an upstream, keyed by question and group.
+// Clients asking the same thing meanwhile wait for that lookup's answer
+// instead of sending their own upstream query.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    lookups: Mutex<HashMap<LookupKey, Arc<Lookup>>>,
+}
+
+// Lowercased name, type, class and upstream group.
+type LookupKey = (String, u16, u16, String);
+
+#[derive(Default)]
+struct Lookup {
+    // None while in flight, then the response or None if the lookup failed.
+    response: Mutex<Option<Option<DnsPacket>>>,
+    done: Condvar,
+}
+
+impl InFlight {
+    // Lookups waiting on an upstream or the recursor now.
+    pub(crate) fn len(&self) -> usize {
+        self.lookups.lock().unwrap().len()
+    }
+
+    // Runs `lookup` unless the same question is already being looked up in
+    // `scope` (an upstream group, or the recursor), in which case this waits
+    // for that lookup's response until the deadline.
+    pub(crate) fn run(
+        &self,
+        question: &DnsQuestion,
+        scope: &str,
+        deadline: Instant,
+        lookup: impl FnOnce() -> Result<DnsPacket, ResolveError>,
+    ) -> Result<DnsPacket, ResolveError> {
+        let key = (
+            question.qname.as_str().to_ascii_lowercase(),
+            question.qtype.code(),
+            question.qclass as u16,
+            scope.to_string(),
+        );
+        let (pending, waiting) = {
+            let mut lookups = self.lookups.lock().unwrap();
+            match lookups.get(&key) {
+                Some(pending) => (Arc::clone(pending), true),
+                None => {
+                    let pending = Arc::new(Lookup::default());
+                    lookups.insert(key.clone(), Arc::clone(&pending));
+                    (pending, false)
+                }
+            }
+        };
+
+        if !waiting {
+            let result = lookup();
+            self.lookups.lock().unwrap().remove(&key);
+            *pending.response.lock().unwrap() = Some(result.as_ref().ok().cloned());
+            pending.done.notify_all();
+            return result;
+        }
+
+        let mut response = pending.response.lock().unwrap();
+        loop {
+            if let Some(response) = &*response {
+                return response.clone().ok_or(ResolveError::ServFail);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ResolveError::Deadline);
+            }
+            response = pending.done.wait_timeout(response, remaining).unwrap().0;
+        }
+    }
+}
+
+// Asks the group the question with a fresh ID and an OPT record advertising
+// `udp_size`, giving up at the deadline; the caller copies what it needs
+// from the response, which only holds records related to the question.
+// While the group is down this fails without asking.
+pub(crate) fn forward(
+    question: &DnsQuestion,
+    group: &UpstreamGroup,
+    udp_size: u16,
+    deadline: Instant,
+    state: &State,
+) -> Result<DnsPacket, ResolveError> {
+    if state.outages.down(&group.name).is_some() {
+        return Err(ResolveError::Unreachable);
+    }
+    let mut upstream = DnsPacket::query(rand::thread_rng().gen(), question.clone());
+    upstream.edns = Some(Edns::new(udp_size));
+    let servers = state.bootstrap.servers(group, deadline);
+    let result = group
+        .resolver(servers)
+        .with_deadline(deadline)
+        .with_pool(&state.pool)
+        .send(&upstream, None);
+    match &result {
+        Ok(_) => state.outages.answered(&group.name),
+        Err(
+            ResolveError::Timeout
+            | ResolveError::Deadline
+            | ResolveError::Io(_)
+            | ResolveError::NoServers,
+        ) => state.outages.failed(&group.name),
+        Err(_) => {}
+    }
+    let mut response = result?;
+    sanitize::scrub(question, &Name::from(""), &mut response);
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::clock::ManualClock;
+    use crate::common::{DnsClass, DnsType};
+    use crate::testing::{MockUpstream, Reply};
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn question() -> DnsQuestion {
+        DnsQuestion {
+            qname: "intranet.corp.example".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        }
+    }
+
+    fn answer(query: &[u8]) -> Vec<u8> {
+        let mut response = DnsPacket::try_from(query).unwrap();
+        response.header.flip_qr();
+        response.add_answer(DnsAnswer::new(
+            "intranet.corp.example".into(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([10, 1, 2, 3]),
+        ));
+        response.to_bytes()
+    }
+
+    fn group(spec: &str) -> UpstreamGroup {
+        UpstreamGroup::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            group("corp=10.0.0.53,10.0.1.53:5353,source=10.8.0.2"),
+            UpstreamGroup {
+                name: "corp".into(),
+                servers: vec![
+                    "10.0.0.53:53".parse().unwrap(),
+                    "10.0.1.53:5353".parse().unwrap()
+                ],
+                source: Some("10.8.0.2".parse().unwrap()),
+                tcp: false,
+                hosts: Vec::new(),
+                bootstrap: Vec::new(),
+            }
+        );
+        assert!(group("corp=10.0.0.53,tcp").tcp);
+        let named = group("doh=dns.example:853,dns.example@192.0.2.1,bootstrap=192.0.2.53");
+        assert_eq!(named.hosts, vec![(Name::from("dns.example"), 853)]);
+        assert_eq!(named.servers, vec!["192.0.2.1:53".parse().unwrap()]);
+        assert_eq!(named.bootstrap, vec!["192.0.2.53:53".parse().unwrap()]);
+        assert_eq!(
+            group("v6=2001:db8::53").servers,
+            vec!["[2001:db8::53]:53".parse().unwrap()]
+        );
+
+        for bad in [
+            "corp",
+            "=10.0.0.53",
+            "corp=",
+            "corp=dns.example",
+            "corp=dns.example@dns.example,bootstrap=10.0.0.53",
+            "corp=10.0.0.530,bootstrap=10.0.0.53",
+            "corp=10.0.0.53,bootstrap=dns.example",
+            "corp=source=10.8.0.2",
+            "corp=10.0.0.53,source=fe80::1",
+        ] {
+            assert!(UpstreamGroup::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let config = Config {
+            upstreams: vec![group("wan=192.0.2.53"), group("corp=10.0.0.53")],
+            forward_zones: vec![
+                (Name::from("."), "wan".into()),
+                (Name::from("corp.example"), "corp".into()),
+            ],
+            ..Config::default()
+        };
+        let name = |qname: &str| select(&config, &qname.into()).map(|g| g.name.as_str());
+        assert_eq!(name("www.corp.example"), Some("corp"));
+        assert_eq!(name("CORP.example"), Some("corp"));
+        assert_eq!(name("notcorp.example"), Some("wan"));
+        assert_eq!(select(&Config::default(), &"example.com".into()), None);
+    }
+
+    #[test]
+    fn test_forward_from_source() {
+        // Any 127/8 address is local on Linux; fall back where it isn't.
+        let source: IpAddr = match UdpSocket::bind("127.0.0.2:0") {
+            Ok(_) => "127.0.0.2".parse().unwrap(),
+            Err(_) => "127.0.0.1".parse().unwrap(),
+        };
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = upstream.local_addr().unwrap();
+        let (peers, seen) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, peer) = upstream.recv_from(&mut buf).unwrap();
+            peers.send(peer.ip()).unwrap();
+            upstream.send_to(&answer(&buf[..size]), peer).unwrap();
+        });
+
+        let group = group(&format!("corp={},source={}", addr, source));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let state = State::default();
+        let response = forward(&question(), &group, 1232, deadline, &state).unwrap();
+        assert_eq!(response.answers[0].rdata, RData::A([10, 1, 2, 3]));
+        assert_eq!(seen.recv().unwrap(), source);
+    }
+
+    #[test]
+    fn test_outage() {
+        let upstream = MockUpstream::silent();
+        let group = upstream.group("dead");
+        let state = State::default();
+        let deadline = Instant::now() + Duration::from_millis(200);
+        assert!(forward(&question(), &group, 1232, deadline, &state).is_err());
+        let down = state.outages.down("dead").unwrap();
+        assert!(down > Duration::from_secs(1) && down <= DOWN_MIN);
+
+        // Fails at once while down.
+        let (start, deadline) = (Instant::now(), Instant::now() + Duration::from_secs(5));
+        assert!(matches!(
+            forward(&question(), &group, 1232, deadline, &state),
+            Err(ResolveError::Unreachable)
+        ));
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_outage_backoff() {
+        let clock = ManualClock::new();
+        let outages = Outages::with_clock(clock.shared());
+        outages.failed("dead");
+        assert_eq!(outages.down("dead"), Some(DOWN_MIN));
+        clock.advance(DOWN_MIN);
+        assert_eq!(outages.down("dead"), None);
+
+        // Down longer after each failure in a row, until one answers.
+        outages.failed("dead");
+        assert_eq!(outages.down("dead"), Some(DOWN_MIN * 2));
+        for _ in 0..10 {
+            outages.failed("dead");
+        }
+        assert_eq!(outages.down("dead"), Some(DOWN_MAX));
+        outages.answered("dead");
+        assert_eq!(outages.down("dead"), None);
+    }
+
+    #[test]
+    fn test_in_flight_coalescing() {
+        let upstream = MockUpstream::start(|query, _| {
+            let reply = DnsPacket::try_from(answer(&query.to_bytes()).as_slice()).unwrap();
+            Reply::Delay(Duration::from_millis(200), Box::new(Reply::Send(reply)))
+        });
+        let group = Arc::new(upstream.group("slow"));
+
+        let in_flight = Arc::new(InFlight::default());
+        let clients: Vec<_> = (0..5)
+            .map(|_| {
+                let (in_flight, group) = (Arc::clone(&in_flight), Arc::clone(&group));
+                thread::spawn(move || {
+                    let (deadline, state) =
+                        (Instant::now() + Duration::from_secs(5), State::default());
+                    let lookup = || forward(&question(), &group, 1232, deadline, &state);
+                    in_flight
+                        .run(&question(), &group.name, deadline, lookup)
+                        .unwrap()
+                })
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap().answers.len(), 1);
+        }
+        assert_eq!(upstream.queries().len(), 1);
+        assert!(in_flight.lookups.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, which can only reach
+//! the public API. Not a stable interface.
+
+use crate::packet::DnsPacket;
+
+/// Parses arbitrary bytes as a DNS message. Must not panic, whatever the
+/// input: every query the server receives goes through this.
+pub fn parse(data: &[u8]) {
+    let _ = DnsPacket::try_from(data);
+}
+
+/// Parses arbitrary bytes and, if they make a message, serialises it and
+/// parses that again. What we write must parse, and serialise the same way
+/// a second time.
+pub fn round_trip(data: &[u8]) {
+    let Ok(packet) = DnsPacket::try_from(data) else {
+        return;
+    };
+    let bytes = packet.to_bytes();
+    let reparsed = match DnsPacket::try_from(bytes.as_slice()) {
+        Ok(reparsed) => reparsed,
+        Err(e) => panic!("serialised message doesn't parse: {}", e),
+    };
+    assert_eq!(reparsed.to_bytes(), bytes, "serialisation isn't stable");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::common::{DnsClass, DnsType};
+    use crate::edns::{Edns, EdnsOption};
+    use crate::question::DnsQuestion;
+    use rand::Rng;
+
+    // A response exercising most of the codec, as in fuzz/corpus.
+    fn seed() -> Vec<u8> {
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let mut packet = DnsPacket::query(1, question);
+        let record =
+            |name: &str, qtype, rdata| DnsAnswer::new(name.into(), qtype, DnsClass::In, 300, rdata);
+        packet.answers = vec![
+            record(
+                "www.example.com",
+                DnsType::Cname,
+                RData::Cname("web.example.com".into()),
+            ),
+            record("web.example.com", DnsType::A, RData::A([192, 0, 2, 80])),
+            record(
+                "example.com",
+                DnsType::Mx,
+                RData::Mx {
+                    preference: 10,
+                    exchange: "mail.example.com".into(),
+                },
+            ),
+            record(
+                "example.com",
+                DnsType::Txt,
+                RData::Txt(vec![b"v=spf1 -all".to_vec(), Vec::new()]),
+            ),
+        ];
+        packet.authorities = vec![record(
+            "example.com",
+            DnsType::Soa,
+            RData::Soa {
+                mname: "ns1.example.com".into(),
+                rname: "hostmaster.example.com".into(),
+                serial: 1,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        )];
+        let mut edns = Edns::new(1232);
+        edns.options = vec![
+            EdnsOption::Nsid(b"ns1".to_vec()),
+            EdnsOption::ExtendedError(0, "other".into()),
+        ];
+        packet.edns = Some(edns);
+        packet.to_bytes()
+    }
+
+    // A cheap stand-in for the fuzzer: random overwrites, pointer-like
+    // bytes, insertions and truncations of the seed.
+    #[test]
+    fn test_mutated_seed() {
+        let seed = seed();
+        let mut rng = rand::thread_rng();
+        for _ in 0..20_000 {
+            let mut data = seed.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let at = rng.gen_range(0..data.len());
+                match rng.gen_range(0..4) {
+                    0 => data[at] = rng.gen(),
+                    1 => data[at] = [0x00, 0x3f, 0x40, 0xc0, 0xff][rng.gen_range(0..5)],
+                    2 => data.insert(at, rng.gen()),
+                    _ => data.truncate(at.max(1)),
+                }
+            }
+            parse(&data);
+        }
+    }
+}
@@ -0,0 +1,472 @@
+// Just enough gzip (RFC 1952) for rotated query logs: compression finds
+// repeats within the 32KiB window and codes them with the fixed Huffman
+// tables, and decompression reads any DEFLATE stream (RFC 1951), so logs
+// recompressed with other tools still read back.
+
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// Candidates tried per position; more finds longer matches, slower.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+// Base lengths and extra bits of length codes 257..=285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// Base distances and extra bits of distance codes 0..=29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order code length code lengths come in, in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    // No name or time; 255 for an unknown OS.
+    let mut bits = BitWriter {
+        out: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255],
+        acc: 0,
+        count: 0,
+    };
+    // One final block with the fixed codes.
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW];
+    let mut i = 0;
+    while i < data.len() {
+        let (length, distance) = longest_match(data, i, &head, &prev);
+        let step = if length >= MIN_MATCH {
+            let code = LENGTH_BASE
+                .iter()
+                .rposition(|&b| b as usize <= length)
+                .unwrap();
+            bits.literal(257 + code as u16);
+            bits.write(
+                (length - LENGTH_BASE[code] as usize) as u32,
+                LENGTH_EXTRA[code],
+            );
+            let code = DISTANCE_BASE
+                .iter()
+                .rposition(|&b| b as usize <= distance)
+                .unwrap();
+            bits.code(code as u32, 5);
+            bits.write(
+                (distance - DISTANCE_BASE[code] as usize) as u32,
+                DISTANCE_EXTRA[code],
+            );
+            length
+        } else {
+            bits.literal(data[i] as u16);
+            1
+        };
+        for position in i..i + step {
+            if position + MIN_MATCH <= data.len() {
+                let hash = hash(&data[position..]);
+                prev[position % WINDOW] = head[hash];
+                head[hash] = position;
+            }
+        }
+        i += step;
+    }
+    bits.literal(256);
+    bits.flush();
+
+    let mut out = bits.out;
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+pub(crate) fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 18 || data[..3] != [0x1f, 0x8b, 8] {
+        return Err("not a gzip file".to_string());
+    }
+    let flags = data[3];
+    let mut position = 10;
+    let truncated = || "truncated gzip header".to_string();
+    if flags & 4 != 0 {
+        let extra = data.get(position..position + 2).ok_or_else(truncated)?;
+        position += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+    }
+    // The file name and comment end with a zero byte.
+    for flag in [8, 16] {
+        if flags & flag != 0 {
+            let rest = data.get(position..).ok_or_else(truncated)?;
+            position += rest.iter().position(|&b| b == 0).ok_or_else(truncated)? + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        position += 2;
+    }
+
+    let mut bits = BitReader {
+        data: data.get(position..).ok_or_else(truncated)?,
+        position: 0,
+        acc: 0,
+        count: 0,
+    };
+    let out = inflate(&mut bits)?;
+    let trailer = bits
+        .data
+        .get(bits.position..bits.position + 8)
+        .ok_or("truncated gzip trailer")?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    if crc != crc32(&out) || size != out.len() as u32 {
+        return Err("gzip checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+// The longest earlier repeat of the bytes at `i` within the window, as
+// (length, distance), following the hash chain a limited number of steps.
+fn longest_match(data: &[u8], i: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if i + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let limit = (data.len() - i).min(MAX_MATCH);
+    let (mut best, mut distance) = (0, 0);
+    let mut candidate = head[hash(&data[i..])];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || candidate >= i || i - candidate > WINDOW {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[i..i + limit])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best {
+            (best, distance) = (length, i - candidate);
+            if length == limit {
+                break;
+            }
+        }
+        let next = prev[candidate % WINDOW];
+        // An older position wrapped over by a newer one ends the chain.
+        if next != usize::MAX && next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+    (best, distance)
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    // `count` bits of `value`, least significant first.
+    fn write(&mut self, value: u32, count: u8) {
+        for bit in 0..count {
+            self.acc |= ((value >> bit) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.acc as u8);
+                (self.acc, self.count) = (0, 0);
+            }
+        }
+    }
+
+    // A Huffman code, which goes most significant bit first.
+    fn code(&mut self, code: u32, length: u8) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write(reversed, length);
+    }
+
+    // A literal/length symbol in the fixed code (RFC 1951 section 3.2.6).
+    fn literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+            (self.acc, self.count) = (0, 0);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    acc: u32,
+    count: u8,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u8) -> Result<u32, String> {
+        while self.count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or("truncated deflate stream")?;
+            self.acc |= (byte as u32) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = self.acc & ((1u64 << count) - 1) as u32;
+        self.acc = if count == 32 { 0 } else { self.acc >> count };
+        self.count -= count;
+        Ok(value)
+    }
+
+    // Drops the rest of the current byte.
+    fn align(&mut self) {
+        (self.acc, self.count) = (0, 0);
+    }
+}
+
+// A canonical Huffman code as symbols per code length and the symbols in
+// code order, decoded a bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code".to_string())
+    }
+}
+
+fn inflate(bits: &mut BitReader) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits
+                    .data
+                    .get(bits.position..bits.position + 4)
+                    .ok_or("truncated stored block")?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err("bad stored block length".to_string());
+                }
+                let start = bits.position + 4;
+                let block = bits
+                    .data
+                    .get(start..start + length as usize)
+                    .ok_or("truncated stored block")?;
+                out.extend_from_slice(block);
+                bits.position = start + length as usize;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                for (symbol, length) in lengths.iter_mut().enumerate() {
+                    *length = match symbol {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        _ => 8,
+                    };
+                }
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                codes(bits, &literals, &distances, &mut out)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(bits)?;
+                codes(bits, &literals, &distances, &mut out)?;
+            }
+            _ => return Err("bad block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn dynamic_tables(bits: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("bad dynamic block header".to_string());
+    }
+    let mut code_lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or("repeat with no length")?;
+                (previous, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.resize(lengths.len() + repeat as usize, length);
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("too many code lengths".to_string());
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+fn codes(
+    bits: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                let base = *LENGTH_BASE.get(code).ok_or("bad length code")?;
+                let length = base as usize + bits.bits(LENGTH_EXTRA[code])? as usize;
+                let code = distances.decode(bits)? as usize;
+                let base = *DISTANCE_BASE.get(code).ok_or("bad distance code")?;
+                let distance = base as usize + bits.bits(DISTANCE_EXTRA[code])? as usize;
+                if distance > out.len() {
+                    return Err("distance before start of output".to_string());
+                }
+                // Byte by byte, as a match may overlap what it copies.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let log: String = (0..2000)
+            .map(|i| {
+                format!(
+                    "{{\"client\":\"192.168.1.{}\",\"name\":\"host{}.example.com\"}}\n",
+                    i % 7,
+                    i % 50
+                )
+            })
+            .collect();
+        for data in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaa",
+            log.as_bytes(),
+        ] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert!(compress(log.as_bytes()).len() < log.len() / 5);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let mut corrupt = compress(log.as_bytes());
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 1;
+        assert!(decompress(&corrupt).is_err());
+        assert!(decompress(b"plain text, not gzip").is_err());
+    }
+
+    #[test]
+    fn test_decompress_other_encoders() {
+        // Python's gzip.compress(data, 9, mtime=0) chose a dynamic block
+        // for these random bases.
+        let data = b"gctaaagacaattacataacatacacgtcagcacgaaacttgttggcccagtgtgaatcgcttaagggttaagtaagtgtgatgcatacgcctttacttgctgtgtccaccccatcggac\n";
+        let gzip = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x1d, 0x8d, 0xc9, 0x0d,
+            0x00, 0x40, 0x08, 0x02, 0xff, 0xdb, 0x25, 0xe1, 0x41, 0x03, 0xf4, 0x9f, 0x1d, 0x4c,
+            0x3c, 0xd1, 0xd1, 0xb8, 0x92, 0x22, 0x4b, 0x2d, 0x91, 0x6e, 0xc1, 0x72, 0x6a, 0x65,
+            0x99, 0xb9, 0xdb, 0x60, 0xb1, 0xd1, 0x28, 0xd1, 0xea, 0xa0, 0x82, 0x26, 0x97, 0xce,
+            0x37, 0x69, 0xee, 0x00, 0xbb, 0xdd, 0x45, 0x30, 0x4f, 0x2f, 0xa8, 0xc7, 0x03, 0xf2,
+            0xee, 0x7d, 0xa4, 0xb8, 0x95, 0x24, 0x79, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(decompress(&gzip).unwrap(), data);
+
+        // A stored block.
+        let data = b"stored";
+        let mut stored = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255, 1, 6, 0, !6, !0];
+        stored.extend_from_slice(data);
+        stored.extend_from_slice(&crc32(data).to_le_bytes());
+        stored.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        assert_eq!(decompress(&stored).unwrap(), data);
+    }
+}
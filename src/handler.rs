@@ -0,0 +1,671 @@
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, SystemTime};
+
+use crate::acme::Challenges;
+use crate::admin::Health;
+use crate::authority::Zones;
+use crate::blocklist::{Blocked, Blocklist};
+use crate::bootstrap::Bootstrap;
+use crate::cache::Cache;
+use crate::chain::{self, Context};
+use crate::chaos;
+use crate::clients::Device;
+use crate::common::{DnsClass, DnsType};
+use crate::config::{Config, MultiQuestion};
+use crate::connections::Connections;
+use crate::consul::Catalog;
+use crate::denial::Denials;
+use crate::edns::{Edns, EdnsOption};
+use crate::forward::{InFlight, Outages};
+use crate::handoff::Sockets;
+use crate::header::{OpCode, ResponseCode};
+use crate::healthcheck::HealthChecks;
+use crate::hints::RootHints;
+use crate::journal::Journal;
+use crate::kubernetes::Endpoints;
+use crate::packet::DnsPacket;
+use crate::pool::Pool;
+use crate::querylog::{Entry, QueryLog};
+use crate::queue::QueueStats;
+use crate::reputation::Reputation;
+use crate::schedule::Overrides;
+use crate::secondary::Secondaries;
+use crate::tkey;
+use crate::ttl;
+use crate::warm::Progress;
+
+// What every client can take over UDP (RFC 1035 section 4.2.1).
+const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+// Runtime state shared by every listener thread.
+#[derive(Default)]
+pub(crate) struct State {
+    pub(crate) in_flight: InFlight,
+    pub(crate) cache: Cache,
+    pub(crate) denials: Denials,
+    // Swapped as a whole on reload; queries keep the zones they started with.
+    pub(crate) zones: RwLock<Arc<Zones>>,
+    // Held by whatever is replacing the zones, a reload or a dynamic update.
+    pub(crate) changing_zones: Mutex<()>,
+    // What each change to the zones took out and put in, for IXFR.
+    pub(crate) journal: Journal,
+    pub(crate) secondaries: Secondaries,
+    pub(crate) challenges: Challenges,
+    pub(crate) catalog: Arc<Catalog>,
+    pub(crate) endpoints: Arc<Endpoints>,
+    pub(crate) health: Health,
+    // Listening sockets, for handing over to the next process.
+    pub(crate) sockets: Sockets,
+    pub(crate) warm: Progress,
+    pub(crate) blocklists: RwLock<Arc<Vec<Arc<Blocklist>>>>,
+    pub(crate) blocked: Blocked,
+    // Schedules forced on or off by `ctl schedule`.
+    pub(crate) schedules: Overrides,
+    // TTLs forced or unforced by `ctl ttl`.
+    pub(crate) ttls: ttl::Overrides,
+    pub(crate) connections: Connections,
+    pub(crate) queue: QueueStats,
+    pub(crate) reputation: Arc<Reputation>,
+    // Connections to upstreams, for TCP queries.
+    pub(crate) pool: Arc<Pool>,
+    // Addresses of upstream servers given by name.
+    pub(crate) bootstrap: Bootstrap,
+    pub(crate) outages: Outages,
+    pub(crate) query_log: QueryLog,
+    pub(crate) roots: RootHints,
+    // TSIG keys negotiated with TKEY.
+    pub(crate) tkeys: tkey::Keys,
+    pub(crate) health_checks: HealthChecks,
+}
+
+impl State {
+    pub(crate) fn new(zones: Zones) -> Self {
+        State {
+            zones: RwLock::new(Arc::new(zones)),
+            ..State::default()
+        }
+    }
+
+    pub(crate) fn zones(&self) -> Arc<Zones> {
+        Arc::clone(&self.zones.read().unwrap())
+    }
+
+    pub(crate) fn blocklists(&self) -> Arc<Vec<Arc<Blocklist>>> {
+        Arc::clone(&self.blocklists.read().unwrap())
+    }
+}
+
+pub(crate) fn handle(
+    mut packet: DnsPacket,
+    client: IpAddr,
+    view: Option<&str>,
+    config: &Config,
+    state: &State,
+) -> DnsPacket {
+    let start = Instant::now();
+    let deadline = start + config.query_budget;
+    packet.header.flip_qr();
+    packet.header.ra = recursion_available(client, config);
+    packet.header.qdcount = packet.questions.len() as u16;
+    let device = Device::from_edns(packet.edns.as_ref());
+    let expire = packet.edns.as_ref().is_some_and(Edns::expire_requested);
+    packet.edns = packet
+        .edns
+        .take()
+        .map(|query| edns_response(&query, config));
+
+    // UPDATE is answered before it gets here.
+    match packet.header.opcode {
+        OpCode::Query => {}
+        OpCode::Notify => return notified(packet, client, state),
+        _ => {
+            packet.header.rcode = ResponseCode::NotImp;
+            return packet;
+        }
+    }
+    if packet.questions.is_empty() {
+        packet.header.rcode = ResponseCode::FormatError;
+        return packet;
+    }
+    if packet.questions.len() > 1 {
+        match config.multi_question {
+            MultiQuestion::FormErr => {
+                packet.header.rcode = ResponseCode::FormatError;
+                return packet;
+            }
+            MultiQuestion::First => {
+                packet.questions.truncate(1);
+                packet.header.qdcount = 1;
+            }
+        }
+    }
+    let question = &packet.questions[0];
+
+    match question.qclass {
+        DnsClass::In => {
+            let ctx = Context {
+                question: question.clone(),
+                client,
+                view,
+                device,
+                expire,
+                config,
+                state,
+                deadline,
+                response: packet,
+            };
+            let response = answer(ctx);
+            if let (Some(query_log), Some(question)) =
+                (&config.query_log, response.questions.first())
+            {
+                let entry = Entry {
+                    time: SystemTime::now(),
+                    client: config.logged_client(client),
+                    name: question.qname.to_string(),
+                    qtype: question.qtype.to_string(),
+                    rcode: response.header.rcode.to_string(),
+                    answers: response.answers.len(),
+                    duration: start.elapsed(),
+                };
+                state.query_log.record(query_log, &entry);
+            }
+            return response;
+        }
+        DnsClass::Ch => match chaos::answer(question, &config.chaos) {
+            Ok(answer) => {
+                packet.header.aa = true;
+                packet.add_answer(answer);
+            }
+            Err(rcode) => packet.header.rcode = rcode,
+        },
+        DnsClass::Cs | DnsClass::Hs | DnsClass::None | DnsClass::Any => {
+            packet.header.rcode = ResponseCode::NotImp;
+        }
+    }
+    packet
+}
+
+// Acknowledges a NOTIFY (RFC 1996) for a secondary zone from one of its
+// primaries, which has the zone checked straight away.
+fn notified(mut packet: DnsPacket, client: IpAddr, state: &State) -> DnsPacket {
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet.additionals.clear();
+    packet.header.rcode = match packet.questions.as_slice() {
+        [question] if question.qtype == DnsType::Soa => {
+            match state.secondaries.notify(&question.qname, client) {
+                true => ResponseCode::NoError,
+                false => ResponseCode::Refused,
+            }
+        }
+        _ => ResponseCode::FormatError,
+    };
+    packet.header.aa = packet.header.rcode == ResponseCode::NoError;
+    packet
+}
+
+// Meta query types are rejected, and types a `--policy` rule refuses unless
+// `--log-only`; everything else goes down the plugin chain, and gets any
+// TTL forced on its zone.
+fn answer(mut ctx: Context) -> DnsPacket {
+    if matches!(
+        ctx.question.qtype,
+        DnsType::Axfr | DnsType::Ixfr | DnsType::Tkey | DnsType::Tsig | DnsType::Opt
+    ) {
+        ctx.response.header.rcode = ResponseCode::NotImp;
+    } else if !ctx
+        .config
+        .policies
+        .refuses(&ctx.question.qname, ctx.question.qtype, ctx.view)
+    {
+        chain::run(&ctx.config.plugins, &mut ctx);
+    } else if ctx.config.log_only {
+        ctx.would("be refused by policy");
+        chain::run(&ctx.config.plugins, &mut ctx);
+    } else {
+        ctx.response.header.rcode = ResponseCode::Refused;
+    }
+    if let Some(forced) = ttl::forced(&ctx.question.qname, ctx.config, &ctx.state.ttls) {
+        ttl::apply(forced, &mut ctx.response);
+    }
+    ctx.response
+}
+
+// Recursion covers both forwarding and iterative resolution.
+fn recursion_available(client: IpAddr, config: &Config) -> bool {
+    (config.recursion || !config.forward_zones.is_empty()) && config.allow_recursion.allows(client)
+}
+
+// How large a UDP response may be, given the payload size the query's OPT
+// record advertised, if it had one. Sizes below 512 are treated as 512 (RFC
+// 6891 section 6.2.5).
+pub(crate) fn udp_limit(advertised: Option<u16>, config: &Config) -> usize {
+    let size = advertised.map_or(MIN_UDP_PAYLOAD_SIZE, |size| {
+        size.clamp(MIN_UDP_PAYLOAD_SIZE, config.max_udp_size)
+    });
+    size as usize
+}
+
+// Serialises a UDP response in at most `limit` bytes, returning it with
+// the bytes compression saved. Additional records go first, which needs no
+// TC bit (RFC 2181 section 9); if that isn't enough only the question and
+// OPT are sent, with TC set so the client retries over TCP.
+pub(crate) fn fit(mut packet: DnsPacket, limit: usize, compress: bool) -> (Vec<u8>, usize) {
+    let encoded = packet.encode(compress);
+    if encoded.0.len() <= limit {
+        return encoded;
+    }
+    packet.additionals.clear();
+    let encoded = packet.encode(compress);
+    if encoded.0.len() <= limit {
+        return encoded;
+    }
+    packet.header.tc = true;
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet.encode(compress)
+}
+
+// Every response to an EDNS query carries an OPT record of its own; only the
+// options we understand and were asked for are echoed back.
+fn edns_response(query: &Edns, config: &Config) -> Edns {
+    let mut edns = Edns::new(config.max_udp_size);
+    if let (true, Some(nsid)) = (query.nsid_requested(), &config.nsid) {
+        edns.options.push(EdnsOption::Nsid(nsid.clone()));
+    }
+    edns
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    use crate::answer::{DnsAnswer, RData};
+    use crate::config::DEFAULT_MAX_UDP_SIZE;
+    use crate::edns::EDE_NO_REACHABLE_AUTHORITY;
+    use crate::forward::UpstreamGroup;
+    use crate::secondary::SecondaryConfig;
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    fn query(qname: &[u8], qtype: u16, qclass: u16) -> DnsPacket {
+        let mut bytes = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(qname);
+        bytes.extend_from_slice(&qtype.to_be_bytes());
+        bytes.extend_from_slice(&qclass.to_be_bytes());
+        DnsPacket::try_from(bytes.as_slice()).unwrap()
+    }
+
+    fn edns_query(options: Vec<EdnsOption>) -> DnsPacket {
+        let mut packet = query(b"\x07example\x03com\x00", 1, 1);
+        let mut edns = Edns::new(1232);
+        edns.options = options;
+        packet.edns = Some(edns);
+        packet
+    }
+
+    #[test]
+    fn test_chaos_class_is_honored() {
+        let config = Config::default();
+        let response = handle(
+            query(b"\x07version\x04bind\x00", 16, 3),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].qclass, DnsClass::Ch);
+
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 3),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_unsupported_class() {
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 4),
+            CLIENT,
+            None,
+            &Config::default(),
+            &State::default(),
+        );
+        assert_eq!(response.header.rcode, ResponseCode::NotImp);
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn test_opcodes() {
+        let config = Config::default();
+        let state = State::default();
+        let mut packet = query(b"\x07example\x03com\x00", 1, 1);
+        for (opcode, rcode) in [
+            (OpCode::InverseQuery, ResponseCode::NotImp),
+            (OpCode::ServerStatus, ResponseCode::NotImp),
+            (OpCode::Unknown(6), ResponseCode::NotImp),
+            // A NOTIFY asks about the zone's SOA.
+            (OpCode::Notify, ResponseCode::FormatError),
+        ] {
+            packet.header.opcode = opcode;
+            let response = handle(packet.clone(), CLIENT, None, &config, &state);
+            assert_eq!(response.header.rcode, rcode, "{}", opcode);
+            assert!(response.answers.is_empty());
+        }
+
+        // Nothing to answer.
+        packet.header.opcode = OpCode::Query;
+        packet.questions.clear();
+        let response = handle(packet, CLIENT, None, &config, &state);
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+    }
+
+    #[test]
+    fn test_notify() {
+        let state = State::default();
+        let secondary = SecondaryConfig::parse("example.com=127.0.0.1:5300").unwrap();
+        let woken = state.secondaries.add(&secondary);
+        let notify = |qname: &[u8], client: IpAddr| {
+            let mut packet = query(qname, DnsType::Soa.code(), 1);
+            packet.header.opcode = OpCode::Notify;
+            handle(packet, client, None, &Config::default(), &state)
+        };
+
+        // Only from a primary, and only for its zone.
+        let response = notify(b"\x07example\x03com\x00", CLIENT);
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert_eq!(response.header.opcode, OpCode::Notify);
+        assert!(woken.try_recv().is_ok());
+        let other = IpAddr::from([192, 0, 2, 1]);
+        let response = notify(b"\x07example\x03com\x00", other);
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        let response = notify(b"\x07example\x03org\x00", CLIENT);
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        assert!(woken.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_multiple_questions() {
+        let mut bytes = vec![0x12, 0x34, 0x01, 0x00, 0x00, 0x02, 0, 0, 0, 0, 0, 0];
+        bytes.extend_from_slice(b"\x07version\x04bind\x00\x00\x10\x00\x03");
+        bytes.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        let packet = DnsPacket::try_from(bytes.as_slice()).unwrap();
+
+        let response = handle(
+            packet.clone(),
+            CLIENT,
+            None,
+            &Config::default(),
+            &State::default(),
+        );
+        assert_eq!(response.header.rcode, ResponseCode::FormatError);
+        assert!(response.answers.is_empty());
+
+        let config = Config {
+            multi_question: MultiQuestion::First,
+            ..Config::default()
+        };
+        let response = handle(packet, CLIENT, None, &config, &State::default());
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        assert_eq!(response.questions.len(), 1);
+        assert_eq!(response.answers[0].qclass, DnsClass::Ch);
+    }
+
+    #[test]
+    fn test_udp_limit() {
+        let config = Config::default();
+        assert_eq!(udp_limit(None, &config), 512);
+        assert_eq!(udp_limit(Some(0), &config), 512);
+        assert_eq!(udp_limit(Some(1000), &config), 1000);
+        assert_eq!(udp_limit(Some(4096), &config), 1232);
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut packet = query(b"\x07example\x03com\x00", 16, 1);
+        packet.header.flip_qr();
+        let txt = |name: &str, len: usize| {
+            DnsAnswer::new(
+                name.into(),
+                DnsType::Txt,
+                DnsClass::In,
+                300,
+                RData::Txt(vec![vec![b'x'; len]]),
+            )
+        };
+        packet.answers = vec![txt("example.com", 200)];
+        packet.additionals = vec![txt("extra.example.com", 250)];
+        packet.edns = Some(Edns::new(1232));
+
+        // Everything fits.
+        let (full, saved) = fit(packet.clone(), 1232, true);
+        // `example.com` twice.
+        assert_eq!(saved, 22);
+        assert_eq!(
+            DnsPacket::try_from(full.as_slice())
+                .unwrap()
+                .additionals
+                .len(),
+            1
+        );
+
+        // Dropping the additional section is enough.
+        let response = DnsPacket::try_from(fit(packet.clone(), 512, true).0.as_slice()).unwrap();
+        assert!(!response.header.tc);
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.additionals.is_empty());
+        assert!(response.edns.is_some());
+
+        // The answer itself is too large.
+        packet.answers = vec![txt("example.com", 250), txt("example.com", 250)];
+        let (bytes, _) = fit(packet, 512, true);
+        assert!(bytes.len() <= 512);
+        let response = DnsPacket::try_from(bytes.as_slice()).unwrap();
+        assert!(response.header.tc);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.questions.len(), 1);
+    }
+
+    #[test]
+    fn test_nsid() {
+        let config = Config {
+            nsid: Some(b"worker-1".to_vec()),
+            ..Config::default()
+        };
+        let response = handle(
+            edns_query(vec![EdnsOption::Nsid(Vec::new())]),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        let edns = response.edns.unwrap();
+        assert_eq!(edns.udp_payload_size, DEFAULT_MAX_UDP_SIZE);
+        assert_eq!(edns.options, vec![EdnsOption::Nsid(b"worker-1".to_vec())]);
+
+        // Not requested: OPT is still present, without NSID.
+        let response = handle(
+            edns_query(Vec::new()),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        assert_eq!(response.edns.unwrap().options, Vec::new());
+
+        // Requested but not configured.
+        let response = handle(
+            edns_query(vec![EdnsOption::Nsid(Vec::new())]),
+            CLIENT,
+            None,
+            &Config::default(),
+            &State::default(),
+        );
+        assert_eq!(response.edns.unwrap().options, Vec::new());
+
+        // No OPT in the query means none in the response.
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 1),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        assert!(response.edns.is_none());
+    }
+
+    #[test]
+    fn test_expire() {
+        let path = std::env::temp_dir().join(format!("dns-server-expire-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "$ORIGIN example.com.\n@ 3600 SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ 3600 NS ns1\nns1 3600 A 192.0.2.1\n",
+        )
+        .unwrap();
+        let config = Config {
+            zones: vec![(None, path.clone())],
+            ..Config::default()
+        };
+        let state = State::new(Zones::load(&config).unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let expire = |qname: &[u8], qtype: u16| {
+            let mut packet = query(qname, qtype, 1);
+            let mut edns = Edns::new(1232);
+            edns.options.push(EdnsOption::Expire(None));
+            packet.edns = Some(edns);
+            let response = handle(packet, CLIENT, None, &config, &state);
+            response.edns.unwrap().expire()
+        };
+        assert_eq!(expire(b"\x07Example\x03com\x00", 6), Some(1209600));
+        // Only SOA queries for the apex carry it.
+        assert_eq!(expire(b"\x07example\x03com\x00", 2), None);
+        assert_eq!(expire(b"\x03ns1\x07example\x03com\x00", 6), None);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = Config {
+            upstreams: vec![UpstreamGroup::parse(&format!(
+                "slow={}",
+                silent.local_addr().unwrap()
+            ))
+            .unwrap()],
+            forward_zones: vec![(".".into(), "slow".into())],
+            query_budget: Duration::from_millis(100),
+            ..Config::default()
+        };
+        let start = Instant::now();
+        let response = handle(
+            edns_query(Vec::new()),
+            CLIENT,
+            None,
+            &config,
+            &State::default(),
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(response.header.rcode, ResponseCode::ServFail);
+        assert!(matches!(
+            response.edns.unwrap().options[..],
+            [EdnsOption::ExtendedError(EDE_NO_REACHABLE_AUTHORITY, _)]
+        ));
+    }
+
+    #[test]
+    fn test_forced_ttl() {
+        let config = Config {
+            upstreams: vec![UpstreamGroup::parse("unused=192.0.2.53").unwrap()],
+            forward_zones: vec![(".".into(), "unused".into())],
+            forced_ttls: vec![ttl::parse("example.com=5").unwrap()],
+            ..Config::default()
+        };
+        let state = State::default();
+        let mut cached = query(b"\x07example\x03com\x00", 1, 1);
+        cached.answers.push(DnsAnswer::new(
+            "example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([192, 0, 2, 1]),
+        ));
+        let question = cached.questions[0].clone();
+        state.cache.insert(&question, &cached);
+
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 1),
+            CLIENT,
+            None,
+            &config,
+            &state,
+        );
+        assert_eq!(response.answers[0].ttl, 5);
+        // The cache keeps the real TTL.
+        assert!(state.cache.get(&question).unwrap().answers[0].ttl > 5);
+    }
+
+    #[test]
+    fn test_recursion_desired() {
+        // Forwarding to a silent upstream fails, so SERVFAIL shows that a
+        // query was forwarded and REFUSED that it wasn't.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = Config {
+            upstreams: vec![UpstreamGroup::parse(&format!(
+                "slow={}",
+                silent.local_addr().unwrap()
+            ))
+            .unwrap()],
+            forward_zones: vec![(".".into(), "slow".into())],
+            query_budget: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let state = State::default();
+
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 1),
+            CLIENT,
+            None,
+            &config,
+            &state,
+        );
+        assert!(response.header.ra);
+        assert_eq!(response.header.rcode, ResponseCode::ServFail);
+
+        let mut packet = query(b"\x07example\x03com\x00", 1, 1);
+        packet.header.rd = false;
+        let response = handle(packet, CLIENT, None, &config, &state);
+        assert!(response.header.ra);
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+
+        let outsider = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 1),
+            outsider,
+            None,
+            &config,
+            &state,
+        );
+        assert!(!response.header.ra);
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+
+        // Nothing to recurse with.
+        let response = handle(
+            query(b"\x07example\x03com\x00", 1, 1),
+            CLIENT,
+            None,
+            &Config::default(),
+            &state,
+        );
+        assert!(!response.header.ra);
+    }
+}
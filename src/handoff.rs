@@ -0,0 +1,443 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::handler::State;
+use crate::packet::DnsPacket;
+
+// Sockets passed in one handoff, at most.
+const MAX_SOCKETS: usize = 64;
+
+// `--handoff path[,cache=off][,drain=secs]`: binary upgrades without
+// dropping queries. A server started with it listens on the unix socket
+// `path`; a new one started with the same flags connects to it first and
+// is passed every listening socket, UDP, TCP and admin, along with the
+// cache unless `cache=off`. Once the new one is serving, the old one
+// keeps answering what it already received for `drain` seconds (5 by
+// default) and exits, while the new one takes over `path` for the next
+// upgrade. Sockets are matched by the address given to `--listen`, so
+// listeners that changed are bound afresh.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct HandoffConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) cache: bool,
+    pub(crate) drain: Duration,
+}
+
+impl HandoffConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let path = parts.next().filter(|path| !path.is_empty());
+        let mut config = HandoffConfig {
+            path: PathBuf::from(path.ok_or("expected a socket path")?),
+            cache: true,
+            drain: Duration::from_secs(5),
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("cache", "on")) => config.cache = true,
+                Some(("cache", "off")) => config.cache = false,
+                Some(("drain", secs)) => {
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("bad drain time {:?}", secs))?;
+                    config.drain = Duration::from_secs(secs);
+                }
+                _ => return Err(format!("unknown handoff option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// The listening sockets of this process by kind (`udp`, `tcp` or `admin`)
+// and configured address, to pass on in a handoff, and those passed to it
+// that no listener has taken yet.
+#[derive(Default)]
+pub(crate) struct Sockets {
+    serving: Mutex<Vec<(String, SocketAddr, OwnedFd)>>,
+    inherited: Mutex<Vec<(String, SocketAddr, OwnedFd)>>,
+}
+
+impl Sockets {
+    // The UDP socket for `addr`, inherited if there is one, else bound.
+    pub(crate) fn udp(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = match self.inherit("udp", addr) {
+            Some(fd) => UdpSocket::from(fd),
+            None => UdpSocket::bind(addr)?,
+        };
+        self.serve("udp", addr, socket.try_clone()?.into());
+        Ok(socket)
+    }
+
+    // As `udp`, for the DNS (`tcp`) or `admin` listener on `addr`.
+    pub(crate) fn tcp(&self, kind: &str, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = match self.inherit(kind, addr) {
+            Some(fd) => TcpListener::from(fd),
+            None => TcpListener::bind(addr)?,
+        };
+        self.serve(kind, addr, socket.try_clone()?.into());
+        Ok(socket)
+    }
+
+    fn inherit(&self, kind: &str, addr: SocketAddr) -> Option<OwnedFd> {
+        let mut inherited = self.inherited.lock().unwrap();
+        let index = inherited
+            .iter()
+            .position(|(other, other_addr, _)| other == kind && *other_addr == addr)?;
+        Some(inherited.remove(index).2)
+    }
+
+    fn serve(&self, kind: &str, addr: SocketAddr, fd: OwnedFd) {
+        self.serving.lock().unwrap().push((kind.into(), addr, fd));
+    }
+}
+
+// The connection to the process being upgraded, kept until this one
+// serves the sockets it passed.
+pub(crate) struct Handoff {
+    stream: UnixStream,
+}
+
+impl Handoff {
+    // Tells the old process it can stop.
+    pub(crate) fn ready(mut self) -> io::Result<()> {
+        self.stream.write_all(b"ready\n")
+    }
+}
+
+// Takes over from the process listening on the handoff socket, if one is:
+// its sockets wait in `state.sockets` for the listeners, and its cache is
+// loaded into ours.
+pub(crate) fn take_over(config: &HandoffConfig, state: &State) -> io::Result<Option<Handoff>> {
+    let stream = match UnixStream::connect(&config.path) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
+    };
+    receive(stream, state).map(Some)
+}
+
+fn receive(mut stream: UnixStream, state: &State) -> io::Result<Handoff> {
+    // The sockets come with the length of the list describing them.
+    let mut length = [0; 2];
+    let (size, fds) = sys::recv_fds(&stream, &mut length)?;
+    stream.read_exact(&mut length[size..])?;
+    let mut list = vec![0; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut list)?;
+    let list = String::from_utf8(list).map_err(|_| invalid("socket list isn't UTF-8"))?;
+    if list.lines().count() != fds.len() {
+        return Err(invalid("socket list doesn't match the sockets"));
+    }
+    let mut inherited = state.sockets.inherited.lock().unwrap();
+    for (line, fd) in list.lines().zip(fds) {
+        let (kind, addr) = line
+            .split_once(' ')
+            .and_then(|(kind, addr)| Some((kind, addr.parse().ok()?)))
+            .ok_or_else(|| invalid("bad socket list"))?;
+        inherited.push((kind.into(), addr, fd));
+    }
+    drop(inherited);
+
+    // Then cached responses, each with its length, up to an empty one.
+    loop {
+        stream.read_exact(&mut length)?;
+        let mut packet = vec![0; u16::from_be_bytes(length) as usize];
+        if packet.is_empty() {
+            break;
+        }
+        stream.read_exact(&mut packet)?;
+        let response = DnsPacket::try_from(&packet).map_err(|e| invalid(&e.to_string()))?;
+        if let Some(question) = response.questions.first() {
+            state.cache.insert(question, &response);
+        }
+    }
+    Ok(Handoff { stream })
+}
+
+// Listens on the handoff socket for the next process. After a handoff this
+// one keeps serving while it drains, then exits.
+pub(crate) fn serve(config: HandoffConfig, state: Arc<State>) -> io::Result<()> {
+    // Left behind by the previous run, or by the process handing off to us.
+    if config.path.exists() {
+        fs::remove_file(&config.path)?;
+    }
+    let listener = UnixListener::bind(&config.path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream.and_then(|stream| hand_off(stream, &config, &state)) {
+                Ok(()) => {
+                    println!(
+                        "Handed off to the new process, exiting in {}s",
+                        config.drain.as_secs()
+                    );
+                    thread::sleep(config.drain);
+                    process::exit(0);
+                }
+                Err(e) => eprintln!("Handoff failed, still serving: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+// Passes the sockets and the cache, and waits for the new process to be
+// serving them. Ok once it is.
+fn hand_off(mut stream: UnixStream, config: &HandoffConfig, state: &State) -> io::Result<()> {
+    let serving = state.sockets.serving.lock().unwrap();
+    let mut list = String::new();
+    for (kind, addr, _) in serving.iter() {
+        list.push_str(&format!("{} {}\n", kind, addr));
+    }
+    let fds: Vec<_> = serving.iter().map(|(_, _, fd)| fd).collect();
+    let length = u16::try_from(list.len())
+        .ok()
+        .filter(|_| fds.len() <= MAX_SOCKETS)
+        .ok_or_else(|| invalid("too many sockets"))?;
+    sys::send_fds(&stream, &length.to_be_bytes(), &fds)?;
+    drop(serving);
+    stream.write_all(list.as_bytes())?;
+
+    let cache = if config.cache {
+        state.cache.export()
+    } else {
+        Vec::new()
+    };
+    let mut buffer = Vec::new();
+    for response in cache {
+        let bytes = response.to_bytes();
+        if let Ok(length) = u16::try_from(bytes.len()) {
+            buffer.extend_from_slice(&length.to_be_bytes());
+            buffer.extend_from_slice(&bytes);
+        }
+    }
+    buffer.extend_from_slice(&[0, 0]);
+    stream.write_all(&buffer)?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    match reply.as_str() {
+        "ready\n" => Ok(()),
+        "" => Err(invalid("the new process went away")),
+        _ => Err(invalid(&format!("unexpected reply {:?}", reply.trim_end()))),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::c_int;
+    use std::io;
+    use std::mem::size_of;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::net::UnixStream;
+
+    use super::MAX_SOCKETS;
+    use crate::pktinfo::sys::{align, recvmsg, sendmsg, IoVec, MsgHdr, CMSG_HEADER};
+
+    const SOL_SOCKET: c_int = 1;
+    const SCM_RIGHTS: c_int = 1;
+    const MSG_CMSG_CLOEXEC: c_int = 0x40000000;
+
+    // Room for a control message carrying MAX_SOCKETS descriptors.
+    #[repr(C, align(8))]
+    struct Buffer([u8; CMSG_HEADER + MAX_SOCKETS * size_of::<c_int>()]);
+
+    // Sends `data` with copies of the descriptors (SCM_RIGHTS).
+    pub(super) fn send_fds(stream: &UnixStream, data: &[u8], fds: &[&OwnedFd]) -> io::Result<()> {
+        let mut control = Buffer([0; CMSG_HEADER + MAX_SOCKETS * size_of::<c_int>()]);
+        let len = CMSG_HEADER + fds.len() * size_of::<c_int>();
+        control.0[..size_of::<usize>()].copy_from_slice(&len.to_ne_bytes());
+        let rest = &mut control.0[size_of::<usize>()..];
+        rest[..4].copy_from_slice(&SOL_SOCKET.to_ne_bytes());
+        rest[4..8].copy_from_slice(&SCM_RIGHTS.to_ne_bytes());
+        for (fd, slot) in fds.iter().zip(control.0[CMSG_HEADER..].chunks_exact_mut(4)) {
+            slot.copy_from_slice(&fd.as_raw_fd().to_ne_bytes());
+        }
+        let mut iov = IoVec {
+            base: data.as_ptr() as *mut _,
+            len: data.len(),
+        };
+        let msg = MsgHdr {
+            name: std::ptr::null_mut(),
+            namelen: 0,
+            iov: &mut iov,
+            iovlen: 1,
+            control: control.0.as_mut_ptr().cast(),
+            controllen: if fds.is_empty() { 0 } else { align(len) },
+            flags: 0,
+        };
+        // SAFETY: iov and control point to buffers of the stated lengths
+        // that outlive the call, and the kernel only reads through them.
+        let size = unsafe { sendmsg(stream.as_raw_fd(), &msg, 0) };
+        match size {
+            _ if size as usize == data.len() => Ok(()),
+            0.. => Err(io::ErrorKind::WriteZero.into()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    // Receives into `buf`, along with any descriptors sent with it.
+    pub(super) fn recv_fds(
+        stream: &UnixStream,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, Vec<OwnedFd>)> {
+        let mut control = Buffer([0; CMSG_HEADER + MAX_SOCKETS * size_of::<c_int>()]);
+        let mut iov = IoVec {
+            base: buf.as_mut_ptr().cast(),
+            len: buf.len(),
+        };
+        let mut msg = MsgHdr {
+            name: std::ptr::null_mut(),
+            namelen: 0,
+            iov: &mut iov,
+            iovlen: 1,
+            control: control.0.as_mut_ptr().cast(),
+            controllen: control.0.len(),
+            flags: 0,
+        };
+        // SAFETY: as for sendmsg.
+        let size = unsafe { recvmsg(stream.as_raw_fd(), &mut msg, MSG_CMSG_CLOEXEC) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut fds = Vec::new();
+        let mut control = &control.0[..msg.controllen.min(control.0.len())];
+        while control.len() >= CMSG_HEADER {
+            let len = usize::from_ne_bytes(control[..size_of::<usize>()].try_into().unwrap());
+            let rest = &control[size_of::<usize>()..];
+            let level = c_int::from_ne_bytes(rest[..4].try_into().unwrap());
+            let kind = c_int::from_ne_bytes(rest[4..8].try_into().unwrap());
+            let Some(data) = control.get(CMSG_HEADER..len) else {
+                break;
+            };
+            if (level, kind) == (SOL_SOCKET, SCM_RIGHTS) {
+                for fd in data.chunks_exact(4) {
+                    let fd = c_int::from_ne_bytes(fd.try_into().unwrap());
+                    // SAFETY: the kernel just installed the descriptor in
+                    // this process, and nothing else refers to it.
+                    fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+            }
+            control = control.get(align(len)..).unwrap_or_default();
+        }
+        Ok((size as usize, fds))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+
+    pub(super) fn send_fds(_: &UnixStream, _: &[u8], _: &[&OwnedFd]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(super) fn recv_fds(_: &UnixStream, _: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>)> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::common::{DnsClass, DnsType};
+    use crate::question::DnsQuestion;
+
+    #[test]
+    fn test_parse() {
+        let config = HandoffConfig::parse("/run/dns.handoff").unwrap();
+        assert_eq!((config.cache, config.drain), (true, Duration::from_secs(5)));
+        let config = HandoffConfig::parse("/run/dns.handoff,cache=off,drain=0").unwrap();
+        assert_eq!((config.cache, config.drain), (false, Duration::ZERO));
+        for bad in [
+            "",
+            ",cache=off",
+            "/run/dns.handoff,cache=no",
+            "/run/x,drain=-1",
+        ] {
+            assert!(HandoffConfig::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_hand_off() {
+        let old = State::default();
+        let any: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let udp = old.sockets.udp(any).unwrap();
+        let tcp = old.sockets.tcp("tcp", any).unwrap();
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        response.answers.push(DnsAnswer::new(
+            "www.example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            300,
+            RData::A([192, 0, 2, 1]),
+        ));
+        old.cache.insert(&question, &response);
+
+        let new = State::default();
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let config = HandoffConfig::parse("unused").unwrap();
+        thread::scope(|scope| {
+            let old = scope.spawn(|| hand_off(ours, &config, &old));
+            receive(theirs, &new).unwrap().ready().unwrap();
+            old.join().unwrap().unwrap();
+        });
+
+        // The same sockets, under the configured address.
+        let inherited = new.sockets.udp(any).unwrap();
+        assert_eq!(inherited.local_addr().unwrap(), udp.local_addr().unwrap());
+        udp.send_to(b"ping", inherited.local_addr().unwrap())
+            .unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(inherited.recv(&mut buf).unwrap(), 4);
+        let inherited = new.sockets.tcp("tcp", any).unwrap();
+        assert_eq!(inherited.local_addr().unwrap(), tcp.local_addr().unwrap());
+        // Taken once; another listener on the address binds its own.
+        assert_ne!(
+            new.sockets.udp(any).unwrap().local_addr().unwrap(),
+            udp.local_addr().unwrap()
+        );
+
+        let cached = new.cache.get(&question).unwrap();
+        assert_eq!(cached.answers, response.answers);
+    }
+
+    #[test]
+    fn test_abandoned() {
+        let old = State::default();
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let config = HandoffConfig::parse("unused,cache=off").unwrap();
+        thread::scope(|scope| {
+            let old = scope.spawn(|| hand_off(ours, &config, &old));
+            drop(receive(theirs, &State::default()).unwrap());
+            assert!(old.join().unwrap().is_err());
+        });
+    }
+}
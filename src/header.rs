@@ -1,6 +1,8 @@
+use std::fmt;
+
 use crate::error::ParseError;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) struct DnsHeader {
     pub id: u16, // Packet Identifier (ID)	                16 bits	A random ID assigned to query packets. Response packets must reply with the same ID.
     pub qr: PacketType, // Query/Response Indicator (QR)    1 bit	1 for a response packet, 0 for a query packet.
@@ -25,11 +27,13 @@ pub(crate) enum PacketType {
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
-#[repr(u8)]
 pub(crate) enum OpCode {
-    Query = 0,
-    InverseQuery = 1,
-    ServerStatus = 2,
+    Query,
+    InverseQuery,
+    ServerStatus,
+    Notify,      // RFC 1996
+    Update,      // RFC 2136
+    Unknown(u8), // unassigned, answered with NOTIMP
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -39,6 +43,13 @@ pub(crate) enum ResponseCode {
     FormatError = 1,
     ServFail = 2,
     NxDomain = 3,
+    NotImp = 4,
+    Refused = 5,
+    YxDomain = 6, // name exists when it should not (RFC 2136)
+    YxRrset = 7,  // RRset exists when it should not
+    NxRrset = 8,  // RRset that should exist does not
+    NotAuth = 9,  // not authoritative for the zone, or TSIG failure (RFC 8945)
+    NotZone = 10, // name not within the zone
 }
 
 impl DnsHeader {
@@ -49,12 +60,11 @@ impl DnsHeader {
         };
     }
 
-    pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(12);
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
         bytes.extend(self.id.to_be_bytes());
 
         let flags = ((self.qr as u8) << 7)
-            | (((self.opcode as u8) & 0x0F) << 3)
+            | ((self.opcode.code() & 0x0F) << 3)
             | ((self.aa as u8) << 2)
             | ((self.tc as u8) << 1)
             | (self.rd as u8);
@@ -68,7 +78,50 @@ impl DnsHeader {
         bytes.extend_from_slice(&self.ancount.to_be_bytes());
         bytes.extend_from_slice(&self.nscount.to_be_bytes());
         bytes.extend_from_slice(&self.arcount.to_be_bytes());
-        bytes
+    }
+}
+
+impl OpCode {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            OpCode::Query => 0,
+            OpCode::InverseQuery => 1,
+            OpCode::ServerStatus => 2,
+            OpCode::Notify => 4,
+            OpCode::Update => 5,
+            OpCode::Unknown(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OpCode::Query => "QUERY",
+            OpCode::InverseQuery => "IQUERY",
+            OpCode::ServerStatus => "STATUS",
+            OpCode::Notify => "NOTIFY",
+            OpCode::Update => "UPDATE",
+            OpCode::Unknown(code) => return write!(f, "OPCODE{}", code),
+        })
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResponseCode::NoError => "NOERROR",
+            ResponseCode::FormatError => "FORMERR",
+            ResponseCode::ServFail => "SERVFAIL",
+            ResponseCode::NxDomain => "NXDOMAIN",
+            ResponseCode::NotImp => "NOTIMP",
+            ResponseCode::Refused => "REFUSED",
+            ResponseCode::YxDomain => "YXDOMAIN",
+            ResponseCode::YxRrset => "YXRRSET",
+            ResponseCode::NxRrset => "NXRRSET",
+            ResponseCode::NotAuth => "NOTAUTH",
+            ResponseCode::NotZone => "NOTZONE",
+        })
     }
 }
 
@@ -76,10 +129,13 @@ impl TryFrom<&[u8]> for DnsHeader {
     type Error = ParseError;
 
     fn try_from(bytes: &[u8]) -> Result<DnsHeader, Self::Error> {
+        if bytes.len() < 12 {
+            return Err(ParseError::UnexpectedEof);
+        }
         let id: u16 = (bytes[0] as u16) << 8 | bytes[1] as u16;
 
         let qr = PacketType::try_from(bytes[2] >> 7)?;
-        let opcode = OpCode::try_from((bytes[2] << 1) >> 4)?;
+        let opcode = OpCode::from((bytes[2] << 1) >> 4);
         let aa = ((bytes[2] << 5) >> 7) != 0;
         let tc = ((bytes[2] << 6) >> 7) != 0;
         let rd = ((bytes[2] << 7) >> 7) != 0;
@@ -117,20 +173,20 @@ impl TryFrom<u8> for PacketType {
         match byte {
             0 => Ok(PacketType::Query),
             1 => Ok(PacketType::Response),
-            _ => Err(ParseError::InvalidValue(byte)),
+            _ => Err(ParseError::InvalidValue(byte.into())),
         }
     }
 }
 
-impl TryFrom<u8> for OpCode {
-    type Error = ParseError;
-
-    fn try_from(byte: u8) -> Result<OpCode, Self::Error> {
+impl From<u8> for OpCode {
+    fn from(byte: u8) -> OpCode {
         match byte {
-            0 => Ok(OpCode::Query),
-            1 => Ok(OpCode::InverseQuery),
-            2 => Ok(OpCode::ServerStatus),
-            _ => Err(ParseError::InvalidValue(byte)),
+            0 => OpCode::Query,
+            1 => OpCode::InverseQuery,
+            2 => OpCode::ServerStatus,
+            4 => OpCode::Notify,
+            5 => OpCode::Update,
+            _ => OpCode::Unknown(byte),
         }
     }
 }
@@ -144,7 +200,14 @@ impl TryFrom<u8> for ResponseCode {
             1 => Ok(ResponseCode::FormatError),
             2 => Ok(ResponseCode::ServFail),
             3 => Ok(ResponseCode::NxDomain),
-            _ => Err(ParseError::InvalidValue(byte)),
+            4 => Ok(ResponseCode::NotImp),
+            5 => Ok(ResponseCode::Refused),
+            6 => Ok(ResponseCode::YxDomain),
+            7 => Ok(ResponseCode::YxRrset),
+            8 => Ok(ResponseCode::NxRrset),
+            9 => Ok(ResponseCode::NotAuth),
+            10 => Ok(ResponseCode::NotZone),
+            _ => Err(ParseError::InvalidValue(byte.into())),
         }
     }
 }
@@ -158,18 +221,25 @@ mod test {
         assert_eq!(PacketType::try_from(0), Ok(PacketType::Query));
         assert_eq!(PacketType::try_from(1), Ok(PacketType::Response));
         for i in 2..=7 {
-            assert_eq!(PacketType::try_from(i), Err(ParseError::InvalidValue(i)));
+            assert_eq!(
+                PacketType::try_from(i),
+                Err(ParseError::InvalidValue(i.into()))
+            );
         }
     }
 
     #[test]
     fn test_parse_opcode() {
-        assert_eq!(OpCode::try_from(0), Ok(OpCode::Query));
-        assert_eq!(OpCode::try_from(1), Ok(OpCode::InverseQuery));
-        assert_eq!(OpCode::try_from(2), Ok(OpCode::ServerStatus));
-        for i in 3..=7 {
-            assert_eq!(OpCode::try_from(i), Err(ParseError::InvalidValue(i)));
+        assert_eq!(OpCode::from(0), OpCode::Query);
+        assert_eq!(OpCode::from(1), OpCode::InverseQuery);
+        assert_eq!(OpCode::from(2), OpCode::ServerStatus);
+        assert_eq!(OpCode::from(3), OpCode::Unknown(3));
+        assert_eq!(OpCode::from(4), OpCode::Notify);
+        assert_eq!(OpCode::from(5), OpCode::Update);
+        for i in 0..=15 {
+            assert_eq!(OpCode::from(i).code(), i);
         }
+        assert_eq!(OpCode::Unknown(6).to_string(), "OPCODE6");
     }
 
     #[test]
@@ -178,8 +248,15 @@ mod test {
         assert_eq!(ResponseCode::try_from(1), Ok(ResponseCode::FormatError));
         assert_eq!(ResponseCode::try_from(2), Ok(ResponseCode::ServFail));
         assert_eq!(ResponseCode::try_from(3), Ok(ResponseCode::NxDomain));
-        for i in 4..=15 {
-            assert_eq!(ResponseCode::try_from(i), Err(ParseError::InvalidValue(i)));
+        assert_eq!(ResponseCode::try_from(4), Ok(ResponseCode::NotImp));
+        assert_eq!(ResponseCode::try_from(5), Ok(ResponseCode::Refused));
+        assert_eq!(ResponseCode::try_from(9), Ok(ResponseCode::NotAuth));
+        assert_eq!(ResponseCode::try_from(10), Ok(ResponseCode::NotZone));
+        for i in 11..=15 {
+            assert_eq!(
+                ResponseCode::try_from(i),
+                Err(ParseError::InvalidValue(i.into()))
+            );
         }
     }
 
@@ -327,7 +404,8 @@ mod test {
     fn assert_packet_equality(bytes: &[u8], expected: DnsHeader) {
         let actual = DnsHeader::try_from(bytes).unwrap();
         assert_eq!(actual, expected);
-        let serialised = actual.to_bytes();
+        let mut serialised = Vec::new();
+        actual.write(&mut serialised);
         assert_eq!(bytes, serialised.as_slice());
     }
 }
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsType, Name};
+use crate::handler::State;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Whether the targets WEIGHTED and FAILOVER records pick among are up, by
+// target and port. A target is up while a TCP connection to the port
+// succeeds; targets not checked yet, or whose records give port 0, count
+// as up.
+#[derive(Default)]
+pub(crate) struct HealthChecks {
+    targets: Mutex<HashMap<(String, u16), bool>>,
+}
+
+impl HealthChecks {
+    pub(crate) fn up(&self, target: &Name, port: u16) -> bool {
+        let key = (target.as_str().to_ascii_lowercase(), port);
+        self.targets
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    // The outcome of one round of checks, dropping the targets no record
+    // names any more.
+    fn checked(&self, results: HashMap<(String, u16), bool>) {
+        *self.targets.lock().unwrap() = results;
+    }
+
+    // One key=value line per target in the `stats` layout.
+    pub(crate) fn report(&self) -> String {
+        let targets = self.targets.lock().unwrap();
+        let mut targets: Vec<_> = targets.iter().collect();
+        targets.sort();
+        let mut report = String::new();
+        for ((target, port), up) in targets {
+            let status = if *up { "up" } else { "down" };
+            writeln!(report, "healthcheck.{}:{}={}", target, port, status).unwrap();
+        }
+        report
+    }
+}
+
+// Picks among the WEIGHTED or FAILOVER records at a name: the FAILOVER
+// target of the lowest priority that is up, or a WEIGHTED target that is up
+// at random by weight. When none is up the choice is made as if all were,
+// since an answer that may work beats none.
+pub(crate) fn pick<'a>(records: &'a [DnsAnswer], checks: &HealthChecks) -> Option<&'a DnsAnswer> {
+    let pool: Vec<&DnsAnswer> = records
+        .iter()
+        .filter(|record| matches!(record.rdata, RData::Pool { .. }))
+        .collect();
+    let up: Vec<&DnsAnswer> = pool
+        .iter()
+        .copied()
+        .filter(|record| match &record.rdata {
+            RData::Pool { port, target, .. } => checks.up(target, *port),
+            _ => false,
+        })
+        .collect();
+    let candidates = if up.is_empty() { pool } else { up };
+    let rank = |record: &DnsAnswer| match record.rdata {
+        RData::Pool { rank, .. } => rank as u32,
+        _ => 0,
+    };
+    if candidates.first()?.qtype == DnsType::Failover {
+        return candidates.into_iter().min_by_key(|record| rank(record));
+    }
+    let total: u32 = candidates.iter().map(|record| rank(record)).sum();
+    if total == 0 {
+        return candidates.first().copied();
+    }
+    let mut point = rand::thread_rng().gen_range(0..total);
+    candidates
+        .into_iter()
+        .find(|record| match point.checked_sub(rank(record)) {
+            Some(rest) => {
+                point = rest;
+                false
+            }
+            None => true,
+        })
+}
+
+// Checks every target the `--zone` files' WEIGHTED and FAILOVER records
+// name, each on a thread of its own so that slow ones don't hold up the
+// rest, and again every CHECK_INTERVAL.
+pub(crate) fn maintain(state: Arc<State>) {
+    thread::spawn(move || loop {
+        let mut targets: Vec<(String, u16)> = state
+            .zones()
+            .iter()
+            .flat_map(|zone| zone.records())
+            .filter_map(|record| match &record.rdata {
+                RData::Pool { port, target, .. } if *port != 0 => {
+                    Some((target.as_str().to_ascii_lowercase(), *port))
+                }
+                _ => None,
+            })
+            .collect();
+        targets.sort();
+        targets.dedup();
+        let results = thread::scope(|scope| {
+            let probes: Vec<_> = targets
+                .into_iter()
+                .map(|(target, port)| {
+                    scope.spawn(move || ((target.clone(), port), probe(&target, port)))
+                })
+                .collect();
+            probes
+                .into_iter()
+                .map(|probe| probe.join().unwrap())
+                .collect()
+        });
+        state.health_checks.checked(results);
+        thread::sleep(CHECK_INTERVAL);
+    });
+}
+
+// Whether any address of the target takes a TCP connection on the port.
+fn probe(target: &str, port: u16) -> bool {
+    let Ok(addrs) = (target, port).to_socket_addrs() else {
+        return false;
+    };
+    addrs
+        .into_iter()
+        .any(|addr| TcpStream::connect_timeout(&addr, CHECK_TIMEOUT).is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::DnsClass;
+    use std::net::TcpListener;
+
+    fn record(qtype: DnsType, rank: u16, port: u16, target: &str) -> DnsAnswer {
+        let rdata = RData::Pool {
+            rank,
+            port,
+            target: target.into(),
+        };
+        DnsAnswer::new("www.example.com".into(), qtype, DnsClass::In, 60, rdata)
+    }
+
+    fn target(record: Option<&DnsAnswer>) -> &str {
+        match &record.unwrap().rdata {
+            RData::Pool { target, .. } => target.as_str(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_failover() {
+        let records = [
+            record(DnsType::Failover, 20, 443, "backup.example.net"),
+            record(DnsType::Failover, 10, 443, "primary.example.net"),
+        ];
+        let checks = HealthChecks::default();
+        assert_eq!(target(pick(&records, &checks)), "primary.example.net");
+
+        let down = |target: &str| ((target.to_string(), 443), false);
+        checks.checked(HashMap::from([down("primary.example.net")]));
+        assert_eq!(target(pick(&records, &checks)), "backup.example.net");
+        assert_eq!(
+            checks.report(),
+            "healthcheck.primary.example.net:443=down\n"
+        );
+        // With every target down, as if none were.
+        checks.checked(HashMap::from([
+            down("primary.example.net"),
+            down("backup.example.net"),
+        ]));
+        assert_eq!(target(pick(&records, &checks)), "primary.example.net");
+        assert!(pick(&[], &checks).is_none());
+    }
+
+    #[test]
+    fn test_weighted() {
+        let records = [
+            record(DnsType::Weighted, 0, 0, "never.example.net"),
+            record(DnsType::Weighted, 1, 80, "a.example.net"),
+            record(DnsType::Weighted, 3, 80, "b.example.net"),
+        ];
+        let checks = HealthChecks::default();
+        let mut picked = HashMap::new();
+        for _ in 0..200 {
+            *picked.entry(target(pick(&records, &checks))).or_insert(0) += 1;
+        }
+        assert!(!picked.contains_key("never.example.net"));
+        assert!(picked["b.example.net"] > picked["a.example.net"]);
+
+        checks.checked(HashMap::from([(("b.example.net".to_string(), 80), false)]));
+        for _ in 0..20 {
+            assert_eq!(target(pick(&records, &checks)), "a.example.net");
+        }
+    }
+
+    #[test]
+    fn test_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(probe("127.0.0.1", port));
+        drop(listener);
+        assert!(!probe("127.0.0.1", port));
+        assert!(!probe("nonexistent.invalid", 80));
+    }
+}
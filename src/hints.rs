@@ -0,0 +1,203 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsType, Name};
+use crate::packet::DnsPacket;
+use crate::zone::ZoneFile;
+
+// The root zone servers as IANA publishes them in named.root, for when no
+// hints file is given.
+const BUILTIN: &str = "\
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+.                        3600000      NS    B.ROOT-SERVERS.NET.
+B.ROOT-SERVERS.NET.      3600000      A     170.247.170.2
+B.ROOT-SERVERS.NET.      3600000      AAAA  2801:1b8:10::b
+.                        3600000      NS    C.ROOT-SERVERS.NET.
+C.ROOT-SERVERS.NET.      3600000      A     192.33.4.12
+C.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2::c
+.                        3600000      NS    D.ROOT-SERVERS.NET.
+D.ROOT-SERVERS.NET.      3600000      A     199.7.91.13
+D.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2d::d
+.                        3600000      NS    E.ROOT-SERVERS.NET.
+E.ROOT-SERVERS.NET.      3600000      A     192.203.230.10
+E.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:a8::e
+.                        3600000      NS    F.ROOT-SERVERS.NET.
+F.ROOT-SERVERS.NET.      3600000      A     192.5.5.241
+F.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2f::f
+.                        3600000      NS    G.ROOT-SERVERS.NET.
+G.ROOT-SERVERS.NET.      3600000      A     192.112.36.4
+G.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:12::d0d
+.                        3600000      NS    H.ROOT-SERVERS.NET.
+H.ROOT-SERVERS.NET.      3600000      A     198.97.190.53
+H.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:1::53
+.                        3600000      NS    I.ROOT-SERVERS.NET.
+I.ROOT-SERVERS.NET.      3600000      A     192.36.148.17
+I.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fe::53
+.                        3600000      NS    J.ROOT-SERVERS.NET.
+J.ROOT-SERVERS.NET.      3600000      A     192.58.128.30
+J.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:c27::2:30
+.                        3600000      NS    K.ROOT-SERVERS.NET.
+K.ROOT-SERVERS.NET.      3600000      A     193.0.14.129
+K.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fd::1
+.                        3600000      NS    L.ROOT-SERVERS.NET.
+L.ROOT-SERVERS.NET.      3600000      A     199.7.83.42
+L.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:9f::42
+.                        3600000      NS    M.ROOT-SERVERS.NET.
+M.ROOT-SERVERS.NET.      3600000      A     202.12.27.33
+M.ROOT-SERVERS.NET.      3600000      AAAA  2001:dc3::35
+";
+
+// The addresses iterative resolution starts from: the hints until a
+// priming query replaces them with what the root servers say of themselves.
+pub(crate) struct RootHints {
+    servers: RwLock<Arc<Vec<IpAddr>>>,
+}
+
+impl Default for RootHints {
+    fn default() -> Self {
+        RootHints {
+            servers: RwLock::new(Arc::new(builtin())),
+        }
+    }
+}
+
+impl RootHints {
+    pub(crate) fn get(&self) -> Arc<Vec<IpAddr>> {
+        Arc::clone(&self.servers.read().unwrap())
+    }
+
+    pub(crate) fn set(&self, servers: Vec<IpAddr>) {
+        *self.servers.write().unwrap() = Arc::new(servers);
+    }
+}
+
+pub(crate) fn builtin() -> Vec<IpAddr> {
+    parse(BUILTIN).expect("built-in root hints")
+}
+
+pub(crate) fn load(path: &Path) -> Result<Vec<IpAddr>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// The addresses of the root's nameservers in a hints file, which is a
+// master file holding the root NS records and their A and AAAA records.
+fn parse(text: &str) -> Result<Vec<IpAddr>, String> {
+    let file = ZoneFile::parse(text, Some(Name::from(".")));
+    if let Some(error) = file.errors.first() {
+        return Err(format!("line {}: {}", error.line, error.message));
+    }
+    let records: Vec<_> = file.entries.into_iter().map(|entry| entry.record).collect();
+    let servers = addresses(&records, &records);
+    if servers.is_empty() {
+        return Err("no root nameserver addresses".to_string());
+    }
+    Ok(servers)
+}
+
+// The root server addresses in the response to a priming query (RFC 8109):
+// the root NS records in the answer, with their addresses from the
+// additional section. Empty if the response has neither.
+pub(crate) fn from_priming(response: &DnsPacket) -> Vec<IpAddr> {
+    addresses(&response.answers, &response.additionals)
+}
+
+// Addresses in `records` for the root's nameservers in `nameservers`, IPv4
+// first, since IPv6 may not be routable from here.
+fn addresses(nameservers: &[DnsAnswer], records: &[DnsAnswer]) -> Vec<IpAddr> {
+    let root = Name::from(".");
+    let names: Vec<&Name> = nameservers
+        .iter()
+        .filter(|record| record.qtype == DnsType::Ns && record.name.eq_ignore_case(&root))
+        .filter_map(|record| match &record.rdata {
+            RData::Ns(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    let mut servers: Vec<IpAddr> = Vec::new();
+    for record in records {
+        if !names.iter().any(|name| name.eq_ignore_case(&record.name)) {
+            continue;
+        }
+        let server = match record.rdata {
+            RData::A(ip) => IpAddr::from(Ipv4Addr::from(ip)),
+            RData::Aaaa(ip) => IpAddr::from(Ipv6Addr::from(ip)),
+            _ => continue,
+        };
+        if !servers.contains(&server) {
+            servers.push(server);
+        }
+    }
+    servers.sort_by_key(|addr| addr.is_ipv6());
+    servers
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::DnsClass;
+    use crate::question::DnsQuestion;
+
+    #[test]
+    fn test_builtin() {
+        let servers = builtin();
+        assert_eq!(servers.len(), 26);
+        assert_eq!(servers[0], IpAddr::from([198, 41, 0, 4]));
+        assert!(servers[..13].iter().all(IpAddr::is_ipv4));
+        assert!(servers.contains(&"2001:dc3::35".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse() {
+        let servers = parse(
+            "; formerly NS.INTERNIC.NET\n\
+             .  3600000  IN  NS  a.root.test.\n\
+             a.root.test.  3600000  A  192.0.2.1\n\
+             stray.test.  3600000  A  192.0.2.99\n",
+        )
+        .unwrap();
+        assert_eq!(servers, vec![IpAddr::from([192, 0, 2, 1])]);
+
+        assert!(parse(".  3600000  NS  a.root.test.\n").is_err());
+        assert!(parse("a.root.test.  3600000  A  192.0.2.300\n").is_err());
+    }
+
+    #[test]
+    fn test_from_priming() {
+        let mut response = DnsPacket::query(
+            1,
+            DnsQuestion {
+                qname: Name::from("."),
+                qtype: DnsType::Ns,
+                qclass: DnsClass::In,
+            },
+        );
+        let record =
+            |name: &str, qtype, rdata| DnsAnswer::new(name.into(), qtype, DnsClass::In, 300, rdata);
+        response.answers = vec![
+            record(".", DnsType::Ns, RData::Ns("a.root.test".into())),
+            record("com", DnsType::Ns, RData::Ns("b.root.test".into())),
+        ];
+        response.additionals = vec![
+            record("b.root.test", DnsType::A, RData::A([192, 0, 2, 2])),
+            record(
+                "A.ROOT.TEST",
+                DnsType::Aaaa,
+                RData::Aaaa([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+            ),
+            record("a.root.test", DnsType::A, RData::A([192, 0, 2, 1])),
+        ];
+        assert_eq!(
+            from_priming(&response),
+            vec![
+                IpAddr::from([192, 0, 2, 1]),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+}
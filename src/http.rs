@@ -0,0 +1,284 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use crate::error::HttpError;
+use crate::eyeballs;
+use crate::json::Json;
+
+// Responses larger than this are cut off and rejected.
+const MAX_RESPONSE: u64 = 16 << 20;
+const JSON: (&str, &str) = ("Accept", "application/json");
+
+// An `http://` URL. There is no TLS; HTTPS APIs are reached through a local
+// proxy or sidecar.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(text: &str) -> Result<Self, HttpError> {
+        let bad = || HttpError::BadUrl(text.to_string());
+        let rest = text.strip_prefix("http://").ok_or_else(bad)?;
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, "/"),
+        };
+        // `[v6]:port`, `[v6]`, `host:port` or `host`.
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host, port.parse().map_err(|_| bad())?),
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(bad());
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    // The same server with another path, which may carry a query string.
+    pub(crate) fn join(&self, path: &str) -> Url {
+        Url {
+            path: format!("{}{}", self.path.trim_end_matches('/'), path),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "http://[{}]:{}{}", self.host, self.port, self.path)
+        } else {
+            write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+        }
+    }
+}
+
+// A response's status, headers and body. Only 2xx and 304 responses are
+// returned; other statuses are errors.
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) fn get_json(url: &Url, timeout: Duration) -> Result<Json, HttpError> {
+    let response = request("GET", url, &[JSON], None, timeout)?;
+    parse_json(&response.body)
+}
+
+pub(crate) fn post_json(url: &Url, body: &Json, timeout: Duration) -> Result<Json, HttpError> {
+    let body = body.to_string();
+    let response = request("POST", url, &[JSON], Some(body.as_bytes()), timeout)?;
+    parse_json(&response.body)
+}
+
+// A GET sending the validators of the copy already held, so that an
+// unchanged resource comes back as a 304 with no body.
+pub(crate) fn get_if_changed(
+    url: &Url,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, HttpError> {
+    let mut headers = vec![("Accept", "*/*")];
+    if let Some(etag) = etag {
+        headers.push(("If-None-Match", etag));
+    }
+    if let Some(last_modified) = last_modified {
+        headers.push(("If-Modified-Since", last_modified));
+    }
+    request("GET", url, &headers, None, timeout)
+}
+
+fn parse_json(body: &[u8]) -> Result<Json, HttpError> {
+    let text = std::str::from_utf8(body).map_err(|_| HttpError::Malformed("body is not UTF-8"))?;
+    Json::parse(text).map_err(HttpError::Json)
+}
+
+// One HTTP/1.1 request on its own connection.
+fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<Response, HttpError> {
+    let addrs: Vec<_> = (url.host.as_str(), url.port).to_socket_addrs()?.collect();
+    if addrs.is_empty() {
+        return Err(HttpError::Malformed("host has no addresses"));
+    }
+    let mut stream = eyeballs::connect(&eyeballs::interleave(&addrs), timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let host = match url.host.contains(':') {
+        true => format!("[{}]", url.host),
+        false => url.host.clone(),
+    };
+    let mut message = format!(
+        "{} {} HTTP/1.1\r\nHost: {}:{}\r\nConnection: close\r\n",
+        method, url.path, host, url.port
+    );
+    for (name, value) in headers {
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    let mut message = message.into_bytes();
+    if let Some(body) = body {
+        message.extend_from_slice(
+            format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n",
+                body.len()
+            )
+            .as_bytes(),
+        );
+    }
+    message.extend_from_slice(b"\r\n");
+    message.extend_from_slice(body.unwrap_or_default());
+    stream.write_all(&message)?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE + 1).read_to_end(&mut response)?;
+    if response.len() as u64 > MAX_RESPONSE {
+        return Err(HttpError::Malformed("response too large"));
+    }
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> Result<Response, HttpError> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or(HttpError::Malformed("no end of headers"))?;
+    let head = std::str::from_utf8(&response[..end])
+        .map_err(|_| HttpError::Malformed("headers are not UTF-8"))?;
+    let body = &response[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or(HttpError::Malformed("bad status line"))?;
+    if !(200..300).contains(&status) && status != 304 {
+        return Err(HttpError::Status(status));
+    }
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    if status == 304 {
+        return Ok(response);
+    }
+    let chunked = matches!(response.header("transfer-encoding"), Some(coding) if coding.eq_ignore_ascii_case("chunked"));
+    let length = response
+        .header("content-length")
+        .and_then(|length| length.parse::<usize>().ok());
+    response.body = match (chunked, length) {
+        (true, _) => dechunk(body)?,
+        (false, Some(length)) => body
+            .get(..length)
+            .map(<[u8]>::to_vec)
+            .ok_or(HttpError::Malformed("body shorter than Content-Length"))?,
+        (false, None) => body.to_vec(),
+    };
+    Ok(response)
+}
+
+// Chunked transfer coding (RFC 9112 section 7.1), ignoring extensions and
+// trailers.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, HttpError> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or(HttpError::Malformed("bad chunked encoding"))?;
+        let size = std::str::from_utf8(&body[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or(HttpError::Malformed("bad chunk size"))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = body
+            .get(..size)
+            .ok_or(HttpError::Malformed("truncated chunk"))?;
+        decoded.extend_from_slice(chunk);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let url = Url::parse("http://127.0.0.1:8500").unwrap();
+        assert_eq!(url.to_string(), "http://127.0.0.1:8500/");
+        assert_eq!(
+            url.join("/v1/catalog/services").to_string(),
+            "http://127.0.0.1:8500/v1/catalog/services"
+        );
+        let url = Url::parse("http://[::1]/lookup").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 80));
+        assert_eq!(url.to_string(), "http://[::1]:80/lookup");
+        for bad in [
+            "https://example.com",
+            "http://",
+            "http://host:port/",
+            "host:80",
+        ] {
+            assert!(Url::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nETag: \"v1\"\r\n\r\n[]extra")
+                .unwrap();
+        assert_eq!(response.body, b"[]");
+        assert_eq!(response.header("etag"), Some("\"v1\""));
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n[1,2\r\n1;x=y\r\n]\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body, b"[1,2]");
+        let response = parse_response(b"HTTP/1.1 304 Not Modified\r\n\r\n").unwrap();
+        assert_eq!((response.status, response.body.len()), (304, 0));
+        assert!(matches!(
+            parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n"),
+            Err(HttpError::Status(404))
+        ));
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
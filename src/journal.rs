@@ -0,0 +1,216 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::authority::{Zone, Zones};
+use crate::common::{DnsType, Name};
+use crate::serial;
+
+// Versions kept per zone; a secondary further behind gets the whole zone.
+const VERSIONS: usize = 100;
+
+// One change to a zone, as IXFR sends it (RFC 1995 section 4): the SOA
+// before and after, and the other records taken out and put in.
+#[derive(Clone, Debug)]
+pub(crate) struct Delta {
+    pub(crate) from: DnsAnswer,
+    pub(crate) to: DnsAnswer,
+    pub(crate) removed: Vec<DnsAnswer>,
+    pub(crate) added: Vec<DnsAnswer>,
+}
+
+impl Delta {
+    fn serials(&self) -> (u32, u32) {
+        (soa_serial(&self.from), soa_serial(&self.to))
+    }
+}
+
+#[derive(Default)]
+struct History {
+    deltas: VecDeque<Delta>,
+    added: u64,
+    removed: u64,
+}
+
+// The recent changes to each served zone, from reloads and dynamic
+// updates, for answering IXFR with just what a secondary is missing.
+#[derive(Default)]
+pub(crate) struct Journal {
+    zones: Mutex<HashMap<String, History>>,
+    incremental: AtomicU64,
+    full: AtomicU64,
+}
+
+impl Journal {
+    // Notes the changes from `old` to `new`, both at the same apex. A
+    // change without a newer serial can't be told apart by a secondary, so
+    // the versions before it are forgotten.
+    pub(crate) fn record(&self, old: &Zone, new: &Zone) {
+        let changes = old.diff(new);
+        if changes.removed.is_empty() && changes.added.is_empty() {
+            return;
+        }
+        let others = |records: Vec<&DnsAnswer>| -> Vec<DnsAnswer> {
+            records
+                .into_iter()
+                .filter(|record| record.qtype != DnsType::Soa)
+                .cloned()
+                .collect()
+        };
+        let (removed, added) = (others(changes.removed), others(changes.added));
+        let mut zones = self.zones.lock().unwrap();
+        let history = zones.entry(key(&new.apex)).or_default();
+        history.added += added.len() as u64;
+        history.removed += removed.len() as u64;
+        let (Some(from), Some(to)) = (old.soa(), new.soa()) else {
+            history.deltas.clear();
+            return;
+        };
+        if !serial::is_newer(soa_serial(&to), soa_serial(&from)) {
+            history.deltas.clear();
+            return;
+        }
+        history.deltas.push_back(Delta {
+            from,
+            to,
+            removed,
+            added,
+        });
+        if history.deltas.len() > VERSIONS {
+            history.deltas.pop_front();
+        }
+    }
+
+    // Every zone at an apex in both, after a reload.
+    pub(crate) fn record_all(&self, old: &Zones, new: &Zones) {
+        for zone in new.iter() {
+            if let Some(previous) = old.iter().find(|old| old.apex.eq_ignore_case(&zone.apex)) {
+                self.record(previous, zone);
+            }
+        }
+    }
+
+    // The changes taking the zone from `serial` to `current`, in order, if
+    // the journal still reaches back that far.
+    pub(crate) fn since(&self, apex: &Name, serial: u32, current: u32) -> Option<Vec<Delta>> {
+        let zones = self.zones.lock().unwrap();
+        let deltas = &zones.get(&key(apex))?.deltas;
+        let start = deltas
+            .iter()
+            .position(|delta| delta.serials().0 == serial)?;
+        let mut at = serial;
+        let mut chain = Vec::new();
+        for delta in deltas.range(start..) {
+            let (from, to) = delta.serials();
+            if from != at {
+                return None;
+            }
+            at = to;
+            chain.push(delta.clone());
+        }
+        (at == current).then_some(chain)
+    }
+
+    // Counts a transfer served, incremental or the whole zone.
+    pub(crate) fn served(&self, incremental: bool) {
+        let counter = match incremental {
+            true => &self.incremental,
+            false => &self.full,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report(&self) -> String {
+        let mut report = String::new();
+        let zones = self.zones.lock().unwrap();
+        let mut apexes: Vec<&String> = zones.keys().collect();
+        apexes.sort();
+        for apex in apexes {
+            let history = &zones[apex];
+            let apex = match apex.as_str() {
+                "" => ".",
+                apex => apex,
+            };
+            for (key, value) in [
+                ("versions", history.deltas.len() as u64),
+                ("added", history.added),
+                ("removed", history.removed),
+            ] {
+                writeln!(report, "journal.{}.{}={}", apex, key, value).unwrap();
+            }
+        }
+        for (key, value) in [
+            ("incremental", self.incremental.load(Ordering::Relaxed)),
+            ("full", self.full.load(Ordering::Relaxed)),
+        ] {
+            writeln!(report, "transfers.{}={}", key, value).unwrap();
+        }
+        report
+    }
+}
+
+fn key(apex: &Name) -> String {
+    apex.as_str().to_ascii_lowercase()
+}
+
+fn soa_serial(soa: &DnsAnswer) -> u32 {
+    match soa.rdata {
+        RData::Soa { serial, .. } => serial,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ZONE: &str = "$ORIGIN example.com.\n$TTL 300\n\
+                        @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+                        @ NS ns1\nns1 A 192.0.2.1\n";
+
+    fn zone(serial: u32, address: &str) -> Zone {
+        let text = ZONE
+            .replace("hostmaster 1", &format!("hostmaster {}", serial))
+            .replace("192.0.2.1", address);
+        Zone::parse(&text, None).unwrap()
+    }
+
+    #[test]
+    fn test_journal() {
+        let journal = Journal::default();
+        let apex = Name::from("example.com");
+        let (one, two, three) = (
+            zone(1, "192.0.2.1"),
+            zone(2, "192.0.2.2"),
+            zone(3, "192.0.2.3"),
+        );
+        journal.record(&one, &two);
+        journal.record(&two, &three);
+
+        let deltas = journal.since(&apex, 1, 3).unwrap();
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].serials(), (1, 2));
+        assert_eq!(deltas[0].removed.len(), 1);
+        assert_eq!(deltas[0].added.len(), 1);
+        assert_eq!(journal.since(&apex, 2, 3).unwrap().len(), 1);
+        // Too old, or not where the zone is now.
+        assert!(journal.since(&apex, 0, 3).is_none());
+        assert!(journal.since(&apex, 1, 4).is_none());
+
+        // The same serial with different records breaks the chain.
+        journal.record(&three, &zone(3, "192.0.2.4"));
+        assert!(journal.since(&apex, 1, 3).is_none());
+
+        journal.served(true);
+        assert_eq!(
+            journal.report(),
+            "journal.example.com.versions=0\n\
+             journal.example.com.added=3\n\
+             journal.example.com.removed=3\n\
+             transfers.incremental=1\n\
+             transfers.full=0\n"
+        );
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::error::HttpError;
+use crate::header::ResponseCode;
+use crate::http::{self, Url};
+use crate::json::Json;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+const DEFAULT_DOMAIN: &str = "cluster.local";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const SLICES_PATH: &str = "/apis/discovery.k8s.io/v1/endpointslices";
+// What CoreDNS's kubernetes plugin uses.
+const TTL: i32 = 5;
+
+// `--kubernetes url[,domain=name][,interval=secs]`: answer
+// `<service>.<namespace>.svc.<domain>` from the EndpointSlices the API
+// server at `url` lists, polled every interval. The API is reached over
+// plain HTTP, e.g. through `kubectl proxy`.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct KubernetesConfig {
+    pub(crate) url: Url,
+    pub(crate) domain: Name,
+    pub(crate) interval: Duration,
+}
+
+impl KubernetesConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let url = Url::parse(parts.next().unwrap_or_default()).map_err(|e| e.to_string())?;
+        let mut config = KubernetesConfig {
+            url,
+            domain: DEFAULT_DOMAIN.into(),
+            interval: DEFAULT_INTERVAL,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("domain", domain)) if !domain.is_empty() => config.domain = domain.into(),
+                Some(("interval", secs)) => {
+                    let secs: u64 = secs
+                        .parse()
+                        .ok()
+                        .filter(|secs| *secs > 0)
+                        .ok_or_else(|| format!("bad interval {:?}", secs))?;
+                    config.interval = Duration::from_secs(secs);
+                }
+                _ => return Err(format!("unknown kubernetes option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Endpoint {
+    addr: IpAddr,
+    hostname: Option<String>,
+}
+
+impl Endpoint {
+    // The label naming this endpoint under its service: the hostname if
+    // it has one, else the address with dashes (10-0-0-1).
+    fn label(&self) -> String {
+        match &self.hostname {
+            Some(hostname) => hostname.to_ascii_lowercase(),
+            None => self.addr.to_string().replace(['.', ':'], "-"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Port {
+    name: String,
+    protocol: String,
+    port: u16,
+}
+
+// The ready endpoints and ports of every slice of one service.
+#[derive(Default)]
+struct Service {
+    endpoints: Vec<Endpoint>,
+    ports: Vec<Port>,
+}
+
+// Services keyed by lowercased (namespace, name).
+#[derive(Default)]
+pub(crate) struct Endpoints {
+    services: RwLock<Arc<HashMap<(String, String), Service>>>,
+}
+
+impl Endpoints {
+    // Lists every EndpointSlice and swaps in the result, keeping the
+    // previous set if the request fails.
+    pub(crate) fn refresh(&self, config: &KubernetesConfig) -> Result<usize, HttpError> {
+        let list = http::get_json(&config.url.join(SLICES_PATH), HTTP_TIMEOUT)?;
+        let Some(items) = list.get("items") else {
+            return Err(HttpError::Malformed("EndpointSlice list has no items"));
+        };
+        let mut services: HashMap<(String, String), Service> = HashMap::new();
+        for slice in items.items() {
+            let metadata = slice.get("metadata");
+            let namespace = metadata
+                .and_then(|m| m.get("namespace"))
+                .and_then(Json::as_str);
+            let name = metadata
+                .and_then(|m| m.get("labels"))
+                .and_then(|labels| labels.get("kubernetes.io/service-name"))
+                .and_then(Json::as_str);
+            let (Some(namespace), Some(name)) = (namespace, name) else {
+                continue;
+            };
+            let service = services
+                .entry((namespace.to_ascii_lowercase(), name.to_ascii_lowercase()))
+                .or_default();
+            for endpoint in slice.get("endpoints").map(Json::items).unwrap_or_default() {
+                // An unset condition counts as ready.
+                let ready = endpoint.get("conditions").and_then(|c| c.get("ready"));
+                if ready == Some(&Json::Bool(false)) {
+                    continue;
+                }
+                let hostname = endpoint.get("hostname").and_then(Json::as_str);
+                for addr in endpoint
+                    .get("addresses")
+                    .map(Json::items)
+                    .unwrap_or_default()
+                {
+                    if let Some(addr) = addr.as_str().and_then(|addr| addr.parse().ok()) {
+                        service.endpoints.push(Endpoint {
+                            addr,
+                            hostname: hostname.map(str::to_string),
+                        });
+                    }
+                }
+            }
+            for port in slice.get("ports").map(Json::items).unwrap_or_default() {
+                let Some(number) = port.get("port").and_then(Json::as_u64) else {
+                    continue;
+                };
+                let port = Port {
+                    name: port
+                        .get("name")
+                        .and_then(Json::as_str)
+                        .unwrap_or_default()
+                        .to_ascii_lowercase(),
+                    protocol: port
+                        .get("protocol")
+                        .and_then(Json::as_str)
+                        .unwrap_or("TCP")
+                        .to_ascii_lowercase(),
+                    port: number.try_into().unwrap_or_default(),
+                };
+                if !service.ports.contains(&port) {
+                    service.ports.push(port);
+                }
+            }
+        }
+        let count = services.len();
+        *self.services.write().unwrap() = Arc::new(services);
+        Ok(count)
+    }
+
+    // Answers a question under the cluster domain, as a headless service
+    // would be (the Kubernetes DNS specification):
+    //   <service>.<ns>.svc               A/AAAA of every ready endpoint, SRV
+    //                                    for every port
+    //   <endpoint>.<service>.<ns>.svc    A/AAAA of that endpoint
+    //   _<port>._<proto>.<service>.<ns>.svc  SRV for the named port
+    pub(crate) fn answer(&self, question: &DnsQuestion, domain: &Name, response: &mut DnsPacket) {
+        response.header.aa = true;
+        let services = Arc::clone(&self.services.read().unwrap());
+        let qname = question.qname.as_str().to_ascii_lowercase();
+        let relative = qname[..qname.len() - domain.as_str().len()].trim_end_matches('.');
+        let labels: Vec<&str> = relative.split('.').collect();
+
+        let (prefix, service_name, namespace) = match labels[..] {
+            [ref prefix @ .., service, namespace, "svc"] if prefix.len() <= 2 => {
+                (prefix, service, namespace)
+            }
+            _ => {
+                response.header.rcode = ResponseCode::NxDomain;
+                return;
+            }
+        };
+        let Some(service) = services.get(&(namespace.to_string(), service_name.to_string())) else {
+            response.header.rcode = ResponseCode::NxDomain;
+            return;
+        };
+        let service_domain = format!("{}.{}.svc.{}", service_name, namespace, domain);
+
+        match prefix {
+            [] => {
+                if question.qtype == DnsType::Srv {
+                    srv(question, service, &service.ports, &service_domain, response);
+                }
+                for endpoint in &service.endpoints {
+                    if question.qtype.wants_address(endpoint.addr) {
+                        let name = question.qname.clone();
+                        response
+                            .answers
+                            .push(DnsAnswer::address(name, TTL, endpoint.addr));
+                    }
+                }
+            }
+            [port, protocol] if port.starts_with('_') && protocol.starts_with('_') => {
+                let ports: Vec<Port> = service
+                    .ports
+                    .iter()
+                    .filter(|p| p.name == port[1..] && p.protocol == protocol[1..])
+                    .cloned()
+                    .collect();
+                if ports.is_empty() {
+                    response.header.rcode = ResponseCode::NxDomain;
+                } else if question.qtype == DnsType::Srv {
+                    srv(question, service, &ports, &service_domain, response);
+                }
+            }
+            [endpoint] => {
+                let matching: Vec<&Endpoint> = service
+                    .endpoints
+                    .iter()
+                    .filter(|e| e.label() == *endpoint)
+                    .collect();
+                if matching.is_empty() {
+                    response.header.rcode = ResponseCode::NxDomain;
+                }
+                for endpoint in matching {
+                    if question.qtype.wants_address(endpoint.addr) {
+                        let name = question.qname.clone();
+                        response
+                            .answers
+                            .push(DnsAnswer::address(name, TTL, endpoint.addr));
+                    }
+                }
+            }
+            _ => response.header.rcode = ResponseCode::NxDomain,
+        }
+    }
+}
+
+// One SRV record per port and endpoint, with the endpoint's address as glue.
+fn srv(
+    question: &DnsQuestion,
+    service: &Service,
+    ports: &[Port],
+    service_domain: &str,
+    response: &mut DnsPacket,
+) {
+    for port in ports {
+        for endpoint in &service.endpoints {
+            let target = Name::from(format!("{}.{}", endpoint.label(), service_domain).as_str());
+            response.answers.push(DnsAnswer::new(
+                question.qname.clone(),
+                DnsType::Srv,
+                DnsClass::In,
+                TTL,
+                RData::Srv {
+                    priority: 0,
+                    weight: 100,
+                    port: port.port,
+                    target: target.clone(),
+                },
+            ));
+            response
+                .additionals
+                .push(DnsAnswer::address(target, TTL, endpoint.addr));
+        }
+    }
+}
+
+// Refreshes the endpoints every interval for as long as the server runs.
+pub(crate) fn poll(config: KubernetesConfig, endpoints: Arc<Endpoints>) {
+    thread::spawn(move || loop {
+        if let Err(e) = endpoints.refresh(&config) {
+            eprintln!("Failed to list EndpointSlices from {}: {}", config.url, e);
+        }
+        thread::sleep(config.interval);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const SLICES: &str = r#"{"kind": "EndpointSliceList", "items": [
+        {"metadata": {"namespace": "default",
+                      "labels": {"kubernetes.io/service-name": "db"}},
+         "endpoints": [
+            {"addresses": ["10.1.0.5"], "hostname": "db-0",
+             "conditions": {"ready": true}},
+            {"addresses": ["10.1.0.6"], "conditions": {}},
+            {"addresses": ["10.1.0.7"], "conditions": {"ready": false}}],
+         "ports": [{"name": "postgres", "protocol": "TCP", "port": 5432}]},
+        {"metadata": {"namespace": "default"}, "endpoints": []}]}"#;
+
+    fn fake_api_server() -> KubernetesConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                let (status, body) = match request.starts_with(&format!("GET {} ", SLICES_PATH)) {
+                    true => ("200 OK", SLICES),
+                    false => ("404 Not Found", "{}"),
+                };
+                write!(
+                    &stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        KubernetesConfig::parse(&url).unwrap()
+    }
+
+    fn ask(endpoints: &Endpoints, name: &str, qtype: DnsType) -> DnsPacket {
+        let question = DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question.clone());
+        endpoints.answer(&question, &DEFAULT_DOMAIN.into(), &mut response);
+        response
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = KubernetesConfig::parse("http://127.0.0.1:8001,domain=lab.local").unwrap();
+        assert_eq!(config.domain.as_str(), "lab.local");
+        assert_eq!(config.interval, DEFAULT_INTERVAL);
+        assert!(KubernetesConfig::parse("https://10.96.0.1").is_err());
+        assert!(KubernetesConfig::parse("http://127.0.0.1:8001,ns=default").is_err());
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let endpoints = Endpoints::default();
+        assert_eq!(endpoints.refresh(&fake_api_server()).unwrap(), 1);
+
+        let response = ask(&endpoints, "db.default.svc.cluster.local", DnsType::A);
+        assert!(response.header.aa);
+        let addrs: Vec<_> = response.answers.iter().map(|a| a.rdata.clone()).collect();
+        assert_eq!(
+            addrs,
+            vec![RData::A([10, 1, 0, 5]), RData::A([10, 1, 0, 6])]
+        );
+
+        let response = ask(&endpoints, "db-0.db.default.svc.cluster.local", DnsType::A);
+        assert_eq!(response.answers.len(), 1);
+        let response = ask(
+            &endpoints,
+            "10-1-0-6.db.default.svc.cluster.local",
+            DnsType::A,
+        );
+        assert_eq!(response.answers.len(), 1);
+
+        let response = ask(
+            &endpoints,
+            "_postgres._tcp.db.default.svc.cluster.local",
+            DnsType::Srv,
+        );
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(
+            response.answers[0].rdata,
+            RData::Srv {
+                priority: 0,
+                weight: 100,
+                port: 5432,
+                target: "db-0.db.default.svc.cluster.local".into()
+            }
+        );
+        assert_eq!(response.additionals.len(), 2);
+
+        for missing in [
+            "web.default.svc.cluster.local",
+            "db-9.db.default.svc.cluster.local",
+            "_http._tcp.db.default.svc.cluster.local",
+            "default.svc.cluster.local",
+        ] {
+            let response = ask(&endpoints, missing, DnsType::A);
+            assert_eq!(response.header.rcode, ResponseCode::NxDomain, "{}", missing);
+        }
+
+        // The previous endpoints survive a failed refresh.
+        let broken = KubernetesConfig {
+            url: fake_api_server().url.join("/nothing"),
+            ..fake_api_server()
+        };
+        assert!(endpoints.refresh(&broken).is_err());
+        let response = ask(&endpoints, "db.default.svc.cluster.local", DnsType::A);
+        assert_eq!(response.answers.len(), 2);
+    }
+}
@@ -0,0 +1,100 @@
+mod acl;
+mod acme;
+mod admin;
+mod answer;
+#[cfg(test)]
+mod arbitrary;
+mod authority;
+mod bench;
+mod blocklist;
+mod bootstrap;
+mod cache;
+mod chain;
+mod chaos;
+mod clients;
+mod clock;
+mod common;
+mod config;
+mod connections;
+mod consul;
+mod control;
+mod crypto;
+mod denial;
+mod diagnostics;
+mod dig;
+mod dnssec;
+mod edns;
+mod error;
+mod eyeballs;
+mod fault;
+mod forward;
+pub mod fuzz;
+mod gzip;
+mod handler;
+mod handoff;
+mod header;
+mod healthcheck;
+mod hints;
+mod http;
+mod journal;
+mod json;
+mod kubernetes;
+mod packet;
+mod pattern;
+mod pcap;
+mod pktinfo;
+mod policy;
+mod pool;
+mod privacy;
+mod querylog;
+mod question;
+mod queue;
+mod recursor;
+mod replay;
+mod reputation;
+pub mod resolver;
+mod rewrite;
+mod safesearch;
+mod sanitize;
+mod schedule;
+mod secondary;
+pub mod serial;
+mod server;
+mod sig0;
+mod sinkhole;
+mod stats;
+mod template;
+#[cfg(test)]
+mod testing;
+mod timezone;
+mod tkey;
+mod trace;
+mod transfer;
+mod trust;
+mod tsig;
+mod ttl;
+mod update;
+mod warm;
+mod webhook;
+mod zone;
+mod zonecheck;
+
+pub use error::{ParseError, ResolveError, TsigError};
+pub use resolver::{Resolver, SrvRecord};
+
+// Entry point for the `dns-server` binary: dispatches subcommands and
+// otherwise runs the server. Returns the process exit code.
+pub fn run(args: Vec<String>) -> i32 {
+    match args.first().map(String::as_str) {
+        Some("axfr") => transfer::main(args[1..].to_vec()),
+        Some("bench") => bench::main(args[1..].to_vec()),
+        Some("check-zone") => zonecheck::main(args[1..].to_vec()),
+        Some("ctl") => control::main(args[1..].to_vec()),
+        Some("log") => querylog::main(args[1..].to_vec()),
+        Some("query") => dig::main(args[1..].to_vec()),
+        Some("replay") => replay::main(args[1..].to_vec()),
+        Some("trace") => trace::main(args[1..].to_vec()),
+        Some("update") => update::main(args[1..].to_vec()),
+        _ => server::main(args),
+    }
+}
@@ -1,34 +1,96 @@
-use crate::{answer::DnsAnswer, error::ParseError, header::DnsHeader, question::DnsQuestion};
+use crate::{
+    answer::DnsAnswer,
+    common::{Compressor, Name},
+    edns::Edns,
+    error::ParseError,
+    header::{DnsHeader, OpCode, PacketType, ResponseCode},
+    question::DnsQuestion,
+};
 
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) struct DnsPacket {
     pub(crate) header: DnsHeader,
     pub(crate) questions: Vec<DnsQuestion>,
     pub(crate) answers: Vec<DnsAnswer>,
+    pub(crate) authorities: Vec<DnsAnswer>,
+    pub(crate) additionals: Vec<DnsAnswer>,
+    pub(crate) edns: Option<Edns>,
 }
 
 impl DnsPacket {
+    pub(crate) fn query(id: u16, question: DnsQuestion) -> Self {
+        let header = DnsHeader {
+            id,
+            qr: PacketType::Query,
+            opcode: OpCode::Query,
+            aa: false,
+            tc: false,
+            rd: true,
+            ra: false,
+            z: 0,
+            rcode: ResponseCode::NoError,
+            qdcount: 1,
+            ancount: 0,
+            nscount: 0,
+            arcount: 0,
+        };
+        DnsPacket {
+            header,
+            questions: vec![question],
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+        }
+    }
+
     pub(crate) fn try_from(bytes: &[u8]) -> Result<Self, ParseError> {
         let header = DnsHeader::try_from(bytes)?;
         let mut offset = 12;
-        let mut bytes = bytes;
-        let mut questions = Vec::new();
 
+        let mut questions = Vec::new();
         for _ in 0..header.qdcount {
-            bytes = &bytes[offset..];
-            if bytes.is_empty() {
-                break;
-            }
-            let question = DnsQuestion::try_from(bytes)?;
-            offset += question.len();
+            let (question, next) = DnsQuestion::parse(bytes, offset)?;
             questions.push(question);
+            offset = next;
         }
 
-        let answers = Vec::new();
+        let mut answers = Vec::new();
+        for _ in 0..header.ancount {
+            let (answer, next) = DnsAnswer::parse(bytes, offset)?;
+            answers.push(answer);
+            offset = next;
+        }
+
+        let mut authorities = Vec::new();
+        for _ in 0..header.nscount {
+            let (authority, next) = DnsAnswer::parse(bytes, offset)?;
+            authorities.push(authority);
+            offset = next;
+        }
+
+        let mut additionals = Vec::new();
+        let mut edns = None;
+        for _ in 0..header.arcount {
+            let (name, after_name) = Name::parse(bytes, offset)?;
+            if name.as_str().is_empty() && bytes.get(after_name..after_name + 2) == Some(&[0, 41]) {
+                let (opt, next) = Edns::parse(bytes, after_name)?;
+                edns = Some(opt);
+                offset = next;
+            } else {
+                let (additional, next) = DnsAnswer::parse(bytes, offset)?;
+                additionals.push(additional);
+                offset = next;
+            }
+        }
 
         Ok(DnsPacket {
             header,
             questions,
             answers,
+            authorities,
+            additionals,
+            edns,
         })
     }
 
@@ -38,13 +100,147 @@ impl DnsPacket {
     }
 
     pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.header.to_bytes();
+        self.encode(true).0
+    }
+
+    // The message, and how many bytes compression saved. Without
+    // `compress` every name is written in full.
+    pub(crate) fn encode(&self, compress: bool) -> (Vec<u8>, usize) {
+        let mut header = self.header.clone();
+        header.qdcount = self.questions.len() as u16;
+        header.ancount = self.answers.len() as u16;
+        header.nscount = self.authorities.len() as u16;
+        header.arcount = (self.additionals.len() + self.edns.is_some() as usize) as u16;
+
+        // Everything is written into one buffer, sized for a typical UDP
+        // response so it rarely grows.
+        let mut bytes = Vec::with_capacity(512);
+        let mut names = match compress {
+            true => Compressor::default(),
+            false => Compressor::off(),
+        };
+        header.write(&mut bytes);
         for question in &self.questions {
-            bytes.extend_from_slice(&question.to_bytes());
+            question.write_compressed(&mut bytes, &mut names);
         }
-        for answer in &self.answers {
-            bytes.extend_from_slice(&answer.to_bytes());
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+        {
+            record.write_compressed(&mut bytes, &mut names);
         }
-        bytes
+        if let Some(edns) = &self.edns {
+            edns.write(&mut bytes);
+        }
+        (bytes, names.saved())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::RData;
+    use crate::common::{DnsClass, DnsType};
+    use crate::edns::EdnsOption;
+
+    #[test]
+    fn test_parse_query_with_opt() {
+        let bytes = [
+            0xab, 0xcd, 0x01, 0x20, // ID, RD, AD
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // QD=1, AR=1
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, //
+            0x00, 0x01, 0x00, 0x01, // A IN
+            0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, // OPT
+            0x00, 0x03, 0x00, 0x00, // NSID
+        ];
+        let packet = DnsPacket::try_from(&bytes).unwrap();
+        assert_eq!(packet.questions.len(), 1);
+        assert!(packet.additionals.is_empty());
+        let edns = packet.edns.as_ref().unwrap();
+        assert_eq!(edns.options, vec![EdnsOption::Nsid(Vec::new())]);
+        assert_eq!(packet.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_parse_compressed_response() {
+        let bytes = [
+            0x12, 0x34, 0x81, 0x80, // ID, response, RD, RA
+            0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // QD=1, AN=1
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00, //
+            0x00, 0x01, 0x00, 0x01, // A IN
+            0xc0, 0x0c, // pointer to the question name
+            0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, // A IN 3600
+            93, 184, 216, 34,
+        ];
+        let packet = DnsPacket::try_from(&bytes).unwrap();
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(packet.answers[0].name.as_str(), "example.com");
+        assert_eq!(packet.answers[0].rdata, RData::A([93, 184, 216, 34]));
+        assert_eq!(packet.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_write_compressed() {
+        let question = DnsQuestion {
+            qname: "example.com".into(),
+            qtype: DnsType::Any,
+            qclass: DnsClass::In,
+        };
+        let mut packet = DnsPacket::query(1, question);
+        let record =
+            |qtype, rdata| DnsAnswer::new("example.com".into(), qtype, DnsClass::In, 60, rdata);
+        packet.answers = vec![
+            record(DnsType::Ns, RData::Ns("ns1.example.com".into())),
+            record(
+                DnsType::Mx,
+                RData::Mx {
+                    preference: 10,
+                    exchange: "mail.Example.com".into(),
+                },
+            ),
+            record(
+                DnsType::Srv,
+                RData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: 53,
+                    target: "ns1.example.com".into(),
+                },
+            ),
+        ];
+        let bytes = packet.to_bytes();
+        // The NS target points at the question name, after its own label.
+        assert_eq!(bytes[41..47], [3, b'n', b's', b'1', 0xc0, 12]);
+        // Case is kept, so the MX exchange only shares `com`.
+        assert_eq!(bytes[61..76], *b"\x04mail\x07Example\xc0\x14");
+        // SRV targets are written in full.
+        assert!(bytes.ends_with(b"\x03ns1\x07example\x03com\x00"));
+        let (full, saved) = packet.encode(false);
+        assert_eq!(full.len(), bytes.len() + packet.encode(true).1);
+        assert_eq!(saved, 0);
+        assert_eq!(
+            DnsPacket::try_from(full.as_slice()).unwrap().answers,
+            packet.answers
+        );
+        assert_eq!(DnsPacket::try_from(bytes.as_slice()).unwrap(), {
+            packet.header.ancount = 3;
+            packet
+        });
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert_eq!(
+            DnsPacket::try_from(&[0x12, 0x34, 0x01]).map(|p| p.header),
+            Err(ParseError::UnexpectedEof)
+        );
+        // QDCOUNT claims a question that isn't there.
+        let bytes = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            DnsPacket::try_from(&bytes).map(|p| p.header),
+            Err(ParseError::UnexpectedEof)
+        );
     }
 }
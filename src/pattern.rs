@@ -0,0 +1,527 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+// Past this many DFA states the cache starts over, bounding its memory
+// whatever the names asked about.
+const MAX_STATES: usize = 10_000;
+// Counted repeats are copied out, so `x{1000}` makes a thousand copies.
+const MAX_REPEAT: u32 = 100;
+
+// Wildcard and regex rules compiled together into one automaton: an NFA
+// per rule sharing one accepting state, run as a DFA built lazily from the
+// names it sees. A name costs one table lookup per byte however many rules
+// there are.
+//
+// Regexes are matched against the lowercased name without its trailing dot,
+// anywhere in it unless anchored with `^` or `$`. They take literals, `.`,
+// classes (`[a-z]`, `[^.]`, `\d`, `\w`), groups, `|` and the `*`, `+`, `?`
+// and `{m,n}` repeats; there are no backreferences or lookarounds.
+pub(crate) struct PatternSet {
+    nfa: Vec<Inst>,
+    rules: usize,
+    // The NFA states every rule starts from, which make DFA state 0.
+    start: Vec<usize>,
+    dfa: Mutex<Dfa>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    const EMPTY: ByteSet = ByteSet([0; 4]);
+    const ANY: ByteSet = ByteSet([u64::MAX; 4]);
+
+    fn byte(b: u8) -> Self {
+        let mut set = ByteSet::EMPTY;
+        set.insert(b);
+        set
+    }
+
+    fn range(from: u8, to: u8) -> Self {
+        let mut set = ByteSet::EMPTY;
+        for b in from..=to {
+            set.insert(b);
+        }
+        set
+    }
+
+    fn insert(&mut self, b: u8) {
+        self.0[b as usize / 64] |= 1 << (b % 64);
+    }
+
+    fn contains(&self, b: u8) -> bool {
+        self.0[b as usize / 64] & (1 << (b % 64)) != 0
+    }
+
+    fn union(mut self, other: ByteSet) -> Self {
+        for (word, other) in self.0.iter_mut().zip(other.0) {
+            *word |= other;
+        }
+        self
+    }
+
+    fn negate(mut self) -> Self {
+        for word in &mut self.0 {
+            *word = !*word;
+        }
+        self
+    }
+
+    // Names are matched lowercased, so uppercase letters stand for
+    // lowercase ones.
+    fn fold_case(mut self) -> Self {
+        for b in b'A'..=b'Z' {
+            if self.contains(b) {
+                self.insert(b.to_ascii_lowercase());
+            }
+        }
+        self
+    }
+}
+
+enum Node {
+    Empty,
+    Class(ByteSet),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>),
+}
+
+enum Inst {
+    Class(ByteSet, usize),
+    Split(usize, usize),
+    Match,
+}
+
+#[derive(Default)]
+struct Dfa {
+    // NFA states (classes and the match) reachable, by DFA state.
+    states: Vec<Vec<usize>>,
+    ids: HashMap<Vec<usize>, usize>,
+    next: HashMap<(usize, u8), usize>,
+}
+
+impl PatternSet {
+    pub(crate) fn new<S: AsRef<str>>(regexes: &[S]) -> Result<Self, String> {
+        // The accepting state is first, so every rule can end there.
+        let mut nfa = vec![Inst::Match];
+        let mut starts = Vec::new();
+        for regex in regexes {
+            let regex = regex.as_ref();
+            let node = parse(regex).map_err(|e| format!("{:?}: {}", regex, e))?;
+            starts.push(compile(&node, 0, &mut nfa));
+        }
+        let mut set = PatternSet {
+            nfa,
+            rules: starts.len(),
+            start: Vec::new(),
+            dfa: Mutex::new(Dfa::default()),
+        };
+        set.start = set.closure(&starts);
+        Ok(set)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.rules
+    }
+
+    // True when any rule matches the (lowercased) text.
+    pub(crate) fn is_match(&self, text: &str) -> bool {
+        if self.rules == 0 {
+            return false;
+        }
+        let mut dfa = self.dfa.lock().unwrap();
+        if dfa.states.is_empty() || dfa.states.len() > MAX_STATES {
+            *dfa = Dfa::default();
+            dfa.id(self.start.clone());
+        }
+        let mut state = 0;
+        for b in text.bytes() {
+            let b = b.to_ascii_lowercase();
+            state = match dfa.next.get(&(state, b)) {
+                Some(&next) => next,
+                None => {
+                    let targets: Vec<usize> = dfa.states[state]
+                        .iter()
+                        .filter_map(|&nfa_state| match self.nfa[nfa_state] {
+                            Inst::Class(class, next) if class.contains(b) => Some(next),
+                            _ => None,
+                        })
+                        .collect();
+                    let next = dfa.id(self.closure(&targets));
+                    dfa.next.insert((state, b), next);
+                    next
+                }
+            };
+        }
+        dfa.states[state].first() == Some(&0)
+    }
+
+    // The states reachable from these without reading a byte.
+    fn closure(&self, states: &[usize]) -> Vec<usize> {
+        let mut stack = states.to_vec();
+        let mut seen = HashSet::new();
+        let mut set = Vec::new();
+        while let Some(state) = stack.pop() {
+            if !seen.insert(state) {
+                continue;
+            }
+            match self.nfa[state] {
+                Inst::Split(a, b) => stack.extend([b, a]),
+                _ => set.push(state),
+            }
+        }
+        set
+    }
+}
+
+impl Dfa {
+    fn id(&mut self, mut set: Vec<usize>) -> usize {
+        set.sort_unstable();
+        set.dedup();
+        if let Some(&id) = self.ids.get(&set) {
+            return id;
+        }
+        let id = self.states.len();
+        self.states.push(set.clone());
+        self.ids.insert(set, id);
+        id
+    }
+}
+
+// A `*` wildcard rule as a regex: `*.ads.example` matches every name below
+// ads.example, `ads*.example` every name from ads to .example.
+pub(crate) fn wildcard(rule: &str) -> String {
+    let mut regex = String::from("^");
+    for c in rule.trim_end_matches('.').chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => regex.push(c),
+            c => {
+                regex.push('\\');
+                regex.push(c);
+            }
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+// Thompson's construction, back to front: `next` is where the node goes
+// once matched, and the node's first state is returned.
+fn compile(node: &Node, next: usize, nfa: &mut Vec<Inst>) -> usize {
+    let push = |nfa: &mut Vec<Inst>, inst| {
+        nfa.push(inst);
+        nfa.len() - 1
+    };
+    match node {
+        Node::Empty => next,
+        Node::Class(class) => push(nfa, Inst::Class(*class, next)),
+        Node::Concat(nodes) => nodes
+            .iter()
+            .rev()
+            .fold(next, |next, node| compile(node, next, nfa)),
+        Node::Alternate(nodes) => {
+            let starts: Vec<usize> = nodes.iter().map(|node| compile(node, next, nfa)).collect();
+            let (last, rest) = starts.split_last().expect("alternation of nothing");
+            rest.iter()
+                .rev()
+                .fold(*last, |other, &start| push(nfa, Inst::Split(start, other)))
+        }
+        Node::Repeat(node, min, max) => {
+            let mut start = match max {
+                None => {
+                    // A loop back through a split patched once the body
+                    // exists.
+                    let split = push(nfa, Inst::Split(next, next));
+                    let body = compile(node, split, nfa);
+                    nfa[split] = Inst::Split(body, next);
+                    split
+                }
+                Some(max) => {
+                    let mut start = next;
+                    for _ in *min..*max {
+                        let body = compile(node, start, nfa);
+                        start = push(nfa, Inst::Split(body, next));
+                    }
+                    start
+                }
+            };
+            for _ in 0..*min {
+                start = compile(node, start, nfa);
+            }
+            start
+        }
+    }
+}
+
+// A whole regex, made to match anywhere unless anchored.
+fn parse(regex: &str) -> Result<Node, String> {
+    let (anchored_start, rest) = match regex.strip_prefix('^') {
+        Some(rest) => (true, rest),
+        None => (false, regex),
+    };
+    let (anchored_end, rest) = match rest.strip_suffix('$') {
+        Some(body) if !body.ends_with('\\') || body.ends_with("\\\\") => (true, body),
+        _ => (false, rest),
+    };
+    let mut parser = Parser {
+        bytes: rest.as_bytes(),
+        pos: 0,
+    };
+    let node = parser.alternation()?;
+    if parser.pos < parser.bytes.len() {
+        return Err(format!("unexpected {:?}", parser.bytes[parser.pos] as char));
+    }
+    let anything = || Node::Repeat(Box::new(Node::Class(ByteSet::ANY)), 0, None);
+    let mut nodes = Vec::new();
+    if !anchored_start {
+        nodes.push(anything());
+    }
+    nodes.push(node);
+    if !anchored_end {
+        nodes.push(anything());
+    }
+    Ok(Node::Concat(nodes))
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, String> {
+        let b = self.peek().ok_or("unexpected end")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        let found = self.peek() == Some(b);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concatenation()?];
+        while self.eat(b'|') {
+            branches.push(self.concatenation()?);
+        }
+        Ok(match branches.len() {
+            1 => branches.pop().unwrap(),
+            _ => Node::Alternate(branches),
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while !matches!(self.peek(), None | Some(b'|') | Some(b')')) {
+            let atom = self.atom()?;
+            nodes.push(self.repeat(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        Ok(match self.next()? {
+            b'(' => {
+                // Every group is non-capturing anyway.
+                if self.eat(b'?') && !self.eat(b':') {
+                    return Err("unsupported group".to_string());
+                }
+                let node = self.alternation()?;
+                if !self.eat(b')') {
+                    return Err("unclosed group".to_string());
+                }
+                node
+            }
+            b'.' => Node::Class(ByteSet::ANY),
+            b'[' => Node::Class(self.class()?),
+            b'\\' => Node::Class(self.escape()?),
+            b @ (b'*' | b'+' | b'?' | b'{') => {
+                return Err(format!("nothing to repeat before {:?}", b as char))
+            }
+            b'^' | b'$' => return Err("anchors only at the ends".to_string()),
+            b => Node::Class(ByteSet::byte(b).fold_case()),
+        })
+    }
+
+    fn repeat(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'*') => (0, None),
+                Some(b'+') => (1, None),
+                Some(b'?') => (0, Some(1)),
+                Some(b'{') => {
+                    self.pos += 1;
+                    self.counts()?
+                }
+                _ => return Ok(node),
+            };
+            // Past the operator or the closing `}`.
+            self.pos += 1;
+            // Lazy and greedy repeats match the same names.
+            self.eat(b'?');
+            node = Node::Repeat(Box::new(node), min, max);
+        }
+    }
+
+    // `m}`, `m,}` or `m,n}`, leaving the `}` to be skipped.
+    fn counts(&mut self) -> Result<(u32, Option<u32>), String> {
+        let number = |parser: &mut Parser| {
+            let start = parser.pos;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.pos += 1;
+            }
+            std::str::from_utf8(&parser.bytes[start..parser.pos])
+                .unwrap()
+                .parse::<u32>()
+                .ok()
+        };
+        let min = number(self).ok_or("bad repeat count")?;
+        let max = match self.eat(b',') {
+            true => number(self),
+            false => Some(min),
+        };
+        if self.peek() != Some(b'}') || matches!(max, Some(max) if max < min) {
+            return Err("bad repeat count".to_string());
+        }
+        if min > MAX_REPEAT || matches!(max, Some(max) if max > MAX_REPEAT) {
+            return Err(format!("repeat count over {}", MAX_REPEAT));
+        }
+        Ok((min, max))
+    }
+
+    // After the `[`.
+    fn class(&mut self) -> Result<ByteSet, String> {
+        let negated = self.eat(b'^');
+        let mut set = ByteSet::EMPTY;
+        let mut first = true;
+        loop {
+            let b = self.next().map_err(|_| "unclosed class".to_string())?;
+            let from = match b {
+                b']' if !first => break,
+                b'\\' => {
+                    let escaped = self.escape()?;
+                    set = set.union(escaped);
+                    first = false;
+                    continue;
+                }
+                b => b,
+            };
+            first = false;
+            if self.peek() == Some(b'-') && !matches!(self.bytes.get(self.pos + 1), Some(b']')) {
+                self.pos += 1;
+                let to = match self.next()? {
+                    b'\\' => self.next()?,
+                    to => to,
+                };
+                if to < from {
+                    return Err("bad class range".to_string());
+                }
+                set = set.union(ByteSet::range(from, to));
+            } else {
+                set.insert(from);
+            }
+        }
+        let set = set.fold_case();
+        Ok(if negated { set.negate() } else { set })
+    }
+
+    // After the `\`.
+    fn escape(&mut self) -> Result<ByteSet, String> {
+        Ok(match self.next()? {
+            b'd' => ByteSet::range(b'0', b'9'),
+            b'w' => ByteSet::range(b'a', b'z')
+                .union(ByteSet::range(b'0', b'9'))
+                .union(ByteSet::byte(b'_')),
+            b'D' => ByteSet::range(b'0', b'9').negate(),
+            b => ByteSet::byte(b).fold_case(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(regex: &str, text: &str) -> bool {
+        PatternSet::new(&[regex]).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn test_regex() {
+        for (regex, text) in [
+            ("ads", "ads.example"),
+            ("ads", "bads.example"),
+            ("^ad[0-9]+\\.", "ad123.example"),
+            ("^(www|cdn)\\.tracker\\.", "CDN.tracker.example"),
+            ("\\.(com|net)$", "x.example.net"),
+            ("^[^.]+\\.example$", "www.example"),
+            ("^a{2,3}\\.", "aaa.example"),
+            ("^x?y*z+$", "zz"),
+            ("^\\d\\w-$", "1_-"),
+            ("^(a*)*b$", "aaab"),
+            ("^.*$", ""),
+            ("^(?:ab)+$", "abab"),
+        ] {
+            assert!(matches(regex, text), "{} {}", regex, text);
+        }
+        for (regex, text) in [
+            ("^ads", "bads.example"),
+            ("ads$", "ads.example"),
+            ("^[^.]+\\.example$", "www.x.example"),
+            ("^a{2,3}\\.", "aaaa.example"),
+            ("^a{2,3}\\.", "a.example"),
+            ("^(www|cdn)\\.", "ww.example"),
+            ("^\\d+$", "12a"),
+        ] {
+            assert!(!matches(regex, text), "{} {}", regex, text);
+        }
+        for bad in [
+            "(ads", "ads)", "[ads", "*ads", "a{3,2}", "a{1000}", "a^b", "[z-a]", "(?=a)",
+        ] {
+            assert!(PatternSet::new(&[bad]).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert_eq!(wildcard("*.ads.example"), "^.*\\.ads\\.example$");
+        let set =
+            PatternSet::new(&[wildcard("*.ads.example"), wildcard("track*.example")]).unwrap();
+        assert!(set.is_match("www.ads.example"));
+        assert!(set.is_match("a.b.ads.example"));
+        assert!(!set.is_match("ads.example"));
+        assert!(!set.is_match("badads.example"));
+        assert!(set.is_match("tracker.example"));
+        assert!(set.is_match("track.example"));
+        assert!(!set.is_match("untrack.example"));
+    }
+
+    #[test]
+    fn test_many_rules() {
+        let rules: Vec<String> = (0..1000).map(|i| format!("^ad{}\\.", i)).collect();
+        let set = PatternSet::new(&rules).unwrap();
+        assert_eq!(set.len(), 1000);
+        assert!(set.is_match("ad999.example"));
+        assert!(set.is_match("ad0.example"));
+        assert!(!set.is_match("ad1000.example"));
+        assert!(!set.is_match("www.example"));
+        // The DFA is built once; the rest are lookups.
+        let states = set.dfa.lock().unwrap().states.len();
+        assert!(set.is_match("ad999.example"));
+        assert_eq!(set.dfa.lock().unwrap().states.len(), states);
+    }
+}
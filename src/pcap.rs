@@ -0,0 +1,328 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::error::PcapError;
+
+const DNS_PORT: u16 = 53;
+
+// Link-layer header types (https://www.tcpdump.org/linktypes.html).
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Transport {
+    Udp,
+    Tcp,
+}
+
+// A DNS message found in a capture. Messages over TCP are only found when a
+// segment holds whole length-prefixed messages; streams aren't reassembled.
+#[derive(PartialEq, Debug)]
+pub(crate) struct Capture {
+    pub(crate) packet: usize, // 1-based, as numbered by tcpdump and wireshark
+    pub(crate) time: Duration,
+    pub(crate) transport: Transport,
+    pub(crate) source: SocketAddr,
+    pub(crate) destination: SocketAddr,
+    pub(crate) message: Vec<u8>,
+}
+
+pub(crate) struct Summary {
+    pub(crate) captures: Vec<Capture>,
+    pub(crate) packets: usize,
+    pub(crate) skipped: usize,
+}
+
+// Reads a classic libpcap file and pulls out the DNS messages sent to or
+// from port 53 over IPv4 or IPv6.
+pub(crate) fn read(data: &[u8]) -> Result<Summary, PcapError> {
+    let header = data.get(..24).ok_or(PcapError::Truncated)?;
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let (big_endian, nanos) = match magic {
+        0xa1b2c3d4 => (false, false),
+        0xa1b23c4d => (false, true),
+        0xd4c3b2a1 => (true, false),
+        0x4d3cb2a1 => (true, true),
+        0x0a0d0d0a => return Err(PcapError::Pcapng),
+        _ => return Err(PcapError::BadMagic(magic)),
+    };
+    let u32_at = |bytes: &[u8], at: usize| {
+        let word = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+        if big_endian {
+            u32::from_be_bytes(word)
+        } else {
+            u32::from_le_bytes(word)
+        }
+    };
+    let linktype = u32_at(header, 20) & 0xFFFF;
+    if ![
+        LINKTYPE_NULL,
+        LINKTYPE_ETHERNET,
+        LINKTYPE_RAW,
+        LINKTYPE_LINUX_SLL,
+    ]
+    .contains(&linktype)
+    {
+        return Err(PcapError::UnsupportedLinkType(linktype));
+    }
+
+    let mut summary = Summary {
+        captures: Vec::new(),
+        packets: 0,
+        skipped: 0,
+    };
+    let mut offset = 24;
+    while offset < data.len() {
+        let record = data.get(offset..offset + 16).ok_or(PcapError::Truncated)?;
+        let seconds = u32_at(record, 0) as u64;
+        let fraction = u32_at(record, 4);
+        let length = u32_at(record, 8) as usize;
+        let frame = data
+            .get(offset + 16..offset + 16 + length)
+            .ok_or(PcapError::Truncated)?;
+        offset += 16 + length;
+        summary.packets += 1;
+
+        let time = Duration::from_secs(seconds)
+            + match nanos {
+                true => Duration::from_nanos(fraction as u64),
+                false => Duration::from_micros(fraction as u64),
+            };
+        let found = network_layer(linktype, frame)
+            .and_then(|(ethertype, packet)| transport_layer(ethertype, packet));
+        match found {
+            Some(found) => summary
+                .captures
+                .extend(found.into_captures(summary.packets, time)),
+            None => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+struct Segment<'a> {
+    transport: Transport,
+    source: SocketAddr,
+    destination: SocketAddr,
+    payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    fn into_captures(self, packet: usize, time: Duration) -> Vec<Capture> {
+        let capture = |message: &[u8]| Capture {
+            packet,
+            time,
+            transport: self.transport,
+            source: self.source,
+            destination: self.destination,
+            message: message.to_vec(),
+        };
+        match self.transport {
+            Transport::Udp => vec![capture(self.payload)],
+            Transport::Tcp => {
+                let mut captures = Vec::new();
+                let mut rest = self.payload;
+                while rest.len() >= 2 {
+                    let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+                    let Some(message) = rest.get(2..2 + len) else {
+                        break;
+                    };
+                    captures.push(capture(message));
+                    rest = &rest[2 + len..];
+                }
+                captures
+            }
+        }
+    }
+}
+
+// Strips the link-layer header, returning the ethertype and the IP packet.
+fn network_layer(linktype: u32, frame: &[u8]) -> Option<(u16, &[u8])> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut start = 14;
+            // Skip 802.1Q VLAN tags.
+            while ethertype == 0x8100 {
+                ethertype = u16::from_be_bytes([*frame.get(start + 2)?, *frame.get(start + 3)?]);
+                start += 4;
+            }
+            Some((ethertype, frame.get(start..)?))
+        }
+        LINKTYPE_LINUX_SLL => {
+            let ethertype = u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]);
+            Some((ethertype, frame.get(16..)?))
+        }
+        LINKTYPE_NULL | LINKTYPE_RAW => {
+            let packet = if linktype == LINKTYPE_NULL {
+                frame.get(4..)?
+            } else {
+                frame
+            };
+            match packet.first()? >> 4 {
+                4 => Some((0x0800, packet)),
+                6 => Some((0x86DD, packet)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn transport_layer(ethertype: u16, packet: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, protocol, payload) = match ethertype {
+        0x0800 => {
+            let header_len = ((packet.first()? & 0x0F) as usize) * 4;
+            let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+            // Fragments other than the first don't start with a UDP header.
+            let fragment_offset = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]) & 0x1FFF;
+            if fragment_offset != 0 {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(source)),
+                IpAddr::V4(Ipv4Addr::from(destination)),
+                *packet.get(9)?,
+                packet.get(header_len..total_len.min(packet.len()))?,
+            )
+        }
+        0x86DD => {
+            let payload_len = u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as usize;
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            // Extension headers are not followed.
+            (
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                *packet.get(6)?,
+                packet.get(40..(40 + payload_len).min(packet.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]);
+    let destination_port = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    if source_port != DNS_PORT && destination_port != DNS_PORT {
+        return None;
+    }
+    let (transport, data) = match protocol {
+        17 => (Transport::Udp, payload.get(8..)?),
+        6 => {
+            let data_offset = ((payload.get(12)? >> 4) as usize) * 4;
+            (Transport::Tcp, payload.get(data_offset..)?)
+        }
+        _ => return None,
+    };
+    if data.is_empty() {
+        return None;
+    }
+    Some(Segment {
+        transport,
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        payload: data,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // Builds a little-endian microsecond pcap of Ethernet frames.
+    pub(crate) fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&65535u32.to_le_bytes());
+        bytes.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (i, frame) in frames.iter().enumerate() {
+            bytes.extend_from_slice(&(1_700_000_000 + i as u32).to_le_bytes());
+            bytes.extend_from_slice(&500u32.to_le_bytes());
+            bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(frame);
+        }
+        bytes
+    }
+
+    pub(crate) fn udp_frame(source_port: u16, destination_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        let total = 20 + 8 + payload.len();
+        frame.extend_from_slice(&[0x45, 0, (total >> 8) as u8, total as u8, 0, 0, 0, 0, 64, 17]);
+        frame.extend_from_slice(&[0, 0, 192, 0, 2, 1, 192, 0, 2, 53]);
+        frame.extend_from_slice(&source_port.to_be_bytes());
+        frame.extend_from_slice(&destination_port.to_be_bytes());
+        frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn tcp_frame_v6(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x86, 0xDD]);
+        frame.extend_from_slice(&[0x60, 0, 0, 0]);
+        frame.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[6, 64]);
+        frame.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        frame.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        frame.extend_from_slice(&40000u16.to_be_bytes());
+        frame.extend_from_slice(&53u16.to_be_bytes());
+        frame.extend_from_slice(&[0; 8]);
+        frame.extend_from_slice(&[0x50, 0x18, 0, 0, 0, 0, 0, 0]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_read_udp_and_tcp() {
+        let query = [0x12, 0x34, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut tcp = Vec::new();
+        for _ in 0..2 {
+            tcp.extend_from_slice(&(query.len() as u16).to_be_bytes());
+            tcp.extend_from_slice(&query);
+        }
+        let data = pcap(&[
+            udp_frame(5353, 53, &query),
+            udp_frame(5353, 123, &query), // NTP, skipped
+            tcp_frame_v6(&tcp),
+        ]);
+
+        let summary = read(&data).unwrap();
+        assert_eq!(summary.packets, 3);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.captures.len(), 3);
+
+        let first = &summary.captures[0];
+        assert_eq!(first.packet, 1);
+        assert_eq!(first.transport, Transport::Udp);
+        assert_eq!(first.source, "192.0.2.1:5353".parse().unwrap());
+        assert_eq!(first.destination, "192.0.2.53:53".parse().unwrap());
+        assert_eq!(first.time, Duration::new(1_700_000_000, 500_000));
+        assert_eq!(first.message, query);
+
+        assert_eq!(summary.captures[2].packet, 3);
+        assert_eq!(summary.captures[2].transport, Transport::Tcp);
+        assert_eq!(summary.captures[2].destination, "[::1]:53".parse().unwrap());
+    }
+
+    #[test]
+    fn test_bad_files() {
+        assert_eq!(read(&[0; 10]).map(|_| ()), Err(PcapError::Truncated));
+        assert_eq!(
+            read(&[0x0a, 0x0d, 0x0d, 0x0a].repeat(6)).map(|_| ()),
+            Err(PcapError::Pcapng)
+        );
+        let mut truncated = pcap(&[udp_frame(5353, 53, &[0; 12])]);
+        truncated.pop();
+        assert_eq!(read(&truncated).map(|_| ()), Err(PcapError::Truncated));
+    }
+}
@@ -0,0 +1,324 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+// The local address a UDP query was sent to. A socket bound to a wildcard
+// address otherwise replies from whichever address the route back to the
+// client picks, and clients drop replies from an address they didn't ask.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) struct Destination {
+    pub(crate) addr: IpAddr,
+    ifindex: u32,
+}
+
+// Asks the kernel to report each datagram's destination (IP_PKTINFO,
+// IPV6_RECVPKTINFO).
+pub(crate) fn enable(socket: &UdpSocket) -> io::Result<()> {
+    sys::enable(socket)
+}
+
+// Like `recv_from`, along with the destination once `enable`d.
+pub(crate) fn recv(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+    sys::recv(socket, buf)
+}
+
+// Like `send_to`, from the given destination when there is one.
+pub(crate) fn send(
+    socket: &UdpSocket,
+    buf: &[u8],
+    to: SocketAddr,
+    from: Option<&Destination>,
+) -> io::Result<usize> {
+    match from {
+        Some(from) => sys::send(socket, buf, to, from),
+        None => socket.send_to(buf, to),
+    }
+}
+
+// Also used by `handoff` to pass sockets between processes.
+#[cfg(target_os = "linux")]
+pub(crate) mod sys {
+    use std::ffi::{c_int, c_void};
+    use std::io;
+    use std::mem::size_of;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+    use std::os::fd::AsRawFd;
+
+    use super::Destination;
+
+    const AF_INET: u16 = 2;
+    const AF_INET6: u16 = 10;
+    const IPPROTO_IP: c_int = 0;
+    const IP_PKTINFO: c_int = 8;
+    const IPPROTO_IPV6: c_int = 41;
+    const IPV6_RECVPKTINFO: c_int = 49;
+    const IPV6_PKTINFO: c_int = 50;
+
+    // struct cmsghdr: a size_t length, then level and type.
+    pub(crate) const CMSG_HEADER: usize = size_of::<usize>() + 2 * size_of::<c_int>();
+
+    #[repr(C)]
+    pub(crate) struct IoVec {
+        pub(crate) base: *mut c_void,
+        pub(crate) len: usize,
+    }
+
+    #[repr(C)]
+    pub(crate) struct MsgHdr {
+        pub(crate) name: *mut c_void,
+        pub(crate) namelen: u32,
+        pub(crate) iov: *mut IoVec,
+        pub(crate) iovlen: usize,
+        pub(crate) control: *mut c_void,
+        pub(crate) controllen: usize,
+        pub(crate) flags: c_int,
+    }
+
+    // Room for a sockaddr_in6, or one pktinfo control message.
+    #[repr(C, align(8))]
+    struct Buffer([u8; 64]);
+
+    extern "C" {
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        pub(crate) fn recvmsg(fd: c_int, msg: *mut MsgHdr, flags: c_int) -> isize;
+        pub(crate) fn sendmsg(fd: c_int, msg: *const MsgHdr, flags: c_int) -> isize;
+    }
+
+    pub(super) fn enable(socket: &UdpSocket) -> io::Result<()> {
+        let (level, name) = match socket.local_addr()? {
+            SocketAddr::V4(_) => (IPPROTO_IP, IP_PKTINFO),
+            SocketAddr::V6(_) => (IPPROTO_IPV6, IPV6_RECVPKTINFO),
+        };
+        let on: c_int = 1;
+        // SAFETY: the option value is a live c_int of the given size.
+        let result = unsafe {
+            setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                (&on as *const c_int).cast(),
+                size_of::<c_int>() as u32,
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub(super) fn recv(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+        let mut name = Buffer([0; 64]);
+        let mut control = Buffer([0; 64]);
+        let mut iov = IoVec {
+            base: buf.as_mut_ptr().cast(),
+            len: buf.len(),
+        };
+        let mut msg = MsgHdr {
+            name: name.0.as_mut_ptr().cast(),
+            namelen: name.0.len() as u32,
+            iov: &mut iov,
+            iovlen: 1,
+            control: control.0.as_mut_ptr().cast(),
+            controllen: control.0.len(),
+            flags: 0,
+        };
+        // SAFETY: every pointer in msg refers to a buffer of the stated
+        // length that outlives the call.
+        let size = unsafe { recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = &name.0[..(msg.namelen as usize).min(name.0.len())];
+        let source = parse_sockaddr(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown address family"))?;
+        let control = &control.0[..msg.controllen.min(control.0.len())];
+        Ok((size as usize, source, parse_pktinfo(control)))
+    }
+
+    pub(super) fn send(
+        socket: &UdpSocket,
+        buf: &[u8],
+        to: SocketAddr,
+        from: &Destination,
+    ) -> io::Result<usize> {
+        let mut name = Buffer([0; 64]);
+        let namelen = write_sockaddr(to, &mut name.0);
+        let (level, kind, data) = match from.addr {
+            // in_pktinfo: interface, source address, (ignored) destination.
+            IpAddr::V4(addr) => (
+                IPPROTO_IP,
+                IP_PKTINFO,
+                [[0; 4], addr.octets(), [0; 4]].concat(),
+            ),
+            // in6_pktinfo: source address, interface.
+            IpAddr::V6(addr) => (
+                IPPROTO_IPV6,
+                IPV6_PKTINFO,
+                [&addr.octets()[..], &from.ifindex.to_ne_bytes()].concat(),
+            ),
+        };
+        let mut control = Buffer([0; 64]);
+        let len = CMSG_HEADER + data.len();
+        control.0[..size_of::<usize>()].copy_from_slice(&len.to_ne_bytes());
+        let rest = &mut control.0[size_of::<usize>()..];
+        rest[..4].copy_from_slice(&level.to_ne_bytes());
+        rest[4..8].copy_from_slice(&kind.to_ne_bytes());
+        control.0[CMSG_HEADER..len].copy_from_slice(&data);
+        let mut iov = IoVec {
+            base: buf.as_ptr() as *mut c_void,
+            len: buf.len(),
+        };
+        let msg = MsgHdr {
+            name: name.0.as_mut_ptr().cast(),
+            namelen,
+            iov: &mut iov,
+            iovlen: 1,
+            control: control.0.as_mut_ptr().cast(),
+            controllen: CMSG_HEADER + align(data.len()),
+            flags: 0,
+        };
+        // SAFETY: as for recvmsg; the kernel only reads through iov here.
+        let size = unsafe { sendmsg(socket.as_raw_fd(), &msg, 0) };
+        match size {
+            0.. => Ok(size as usize),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub(crate) fn align(len: usize) -> usize {
+        (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+    }
+
+    fn parse_sockaddr(name: &[u8]) -> Option<SocketAddr> {
+        let family = u16::from_ne_bytes(name.get(..2)?.try_into().ok()?);
+        let port = u16::from_be_bytes(name.get(2..4)?.try_into().ok()?);
+        match family {
+            AF_INET => {
+                let addr: [u8; 4] = name.get(4..8)?.try_into().ok()?;
+                Some(SocketAddr::new(Ipv4Addr::from(addr).into(), port))
+            }
+            AF_INET6 => {
+                let flowinfo = u32::from_ne_bytes(name.get(4..8)?.try_into().ok()?);
+                let addr: [u8; 16] = name.get(8..24)?.try_into().ok()?;
+                let scope = u32::from_ne_bytes(name.get(24..28)?.try_into().ok()?);
+                let addr = SocketAddrV6::new(Ipv6Addr::from(addr), port, flowinfo, scope);
+                Some(addr.into())
+            }
+            _ => None,
+        }
+    }
+
+    fn write_sockaddr(addr: SocketAddr, name: &mut [u8]) -> u32 {
+        name[2..4].copy_from_slice(&addr.port().to_be_bytes());
+        match addr {
+            SocketAddr::V4(addr) => {
+                name[..2].copy_from_slice(&AF_INET.to_ne_bytes());
+                name[4..8].copy_from_slice(&addr.ip().octets());
+                16
+            }
+            SocketAddr::V6(addr) => {
+                name[..2].copy_from_slice(&AF_INET6.to_ne_bytes());
+                name[4..8].copy_from_slice(&addr.flowinfo().to_ne_bytes());
+                name[8..24].copy_from_slice(&addr.ip().octets());
+                name[24..28].copy_from_slice(&addr.scope_id().to_ne_bytes());
+                28
+            }
+        }
+    }
+
+    // The first pktinfo among the control messages.
+    fn parse_pktinfo(mut control: &[u8]) -> Option<Destination> {
+        while control.len() >= CMSG_HEADER {
+            let len = usize::from_ne_bytes(control[..size_of::<usize>()].try_into().ok()?);
+            let rest = &control[size_of::<usize>()..];
+            let level = c_int::from_ne_bytes(rest[..4].try_into().ok()?);
+            let kind = c_int::from_ne_bytes(rest[4..8].try_into().ok()?);
+            let data = control.get(CMSG_HEADER..len)?;
+            match (level, kind) {
+                (IPPROTO_IP, IP_PKTINFO) if data.len() >= 12 => {
+                    let ifindex = u32::from_ne_bytes(data[..4].try_into().ok()?);
+                    let addr: [u8; 4] = data[4..8].try_into().ok()?;
+                    let addr = Ipv4Addr::from(addr).into();
+                    return Some(Destination { addr, ifindex });
+                }
+                (IPPROTO_IPV6, IPV6_PKTINFO) if data.len() >= 20 => {
+                    let addr: [u8; 16] = data[..16].try_into().ok()?;
+                    let ifindex = u32::from_ne_bytes(data[16..20].try_into().ok()?);
+                    let addr = Ipv6Addr::from(addr).into();
+                    return Some(Destination { addr, ifindex });
+                }
+                _ => control = control.get(align(len)..)?,
+            }
+        }
+        None
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+
+    use super::Destination;
+
+    pub(super) fn enable(_: &UdpSocket) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(super) fn recv(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<Destination>)> {
+        let (size, source) = socket.recv_from(buf)?;
+        Ok((size, source, None))
+    }
+
+    pub(super) fn send(
+        socket: &UdpSocket,
+        buf: &[u8],
+        to: SocketAddr,
+        _: &Destination,
+    ) -> io::Result<usize> {
+        socket.send_to(buf, to)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reply_source() {
+        let server = UdpSocket::bind("0.0.0.0:0").unwrap();
+        enable(&server).unwrap();
+        let port = server.local_addr().unwrap().port();
+        // All of 127/8 is local, but the route back to 127.0.0.1 prefers
+        // 127.0.0.1 as its source.
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"query", ("127.0.0.2", port)).unwrap();
+
+        let mut buf = [0; 16];
+        let (size, source, destination) = recv(&server, &mut buf).unwrap();
+        assert_eq!(&buf[..size], b"query");
+        assert_eq!(source, client.local_addr().unwrap());
+        let destination = destination.unwrap();
+        assert_eq!(destination.addr, "127.0.0.2".parse::<IpAddr>().unwrap());
+
+        send(&server, b"reply", source, Some(&destination)).unwrap();
+        let (size, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..size], b"reply");
+        assert_eq!(from, SocketAddr::from(([127, 0, 0, 2], port)));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::common::{DnsType, Name};
+
+// One `--policy zone:allow=TYPE[/TYPE...][,view=name]` or
+// `zone:deny=...` rule. Allow rules refuse every other type at or below the
+// zone, deny rules refuse the listed ones. Without a view the rule applies
+// to every listener.
+struct Rule {
+    spec: String,
+    zone: Name,
+    allow: bool,
+    types: Vec<DnsType>,
+    view: Option<String>,
+    hits: AtomicU64,
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (zone, rest) = spec.split_once(':').ok_or_else(|| {
+            format!(
+                "expected zone:allow=types or zone:deny=types, got {:?}",
+                spec
+            )
+        })?;
+        let mut parts = rest.split(',');
+        let (allow, types) = match parts.next().and_then(|part| part.split_once('=')) {
+            Some(("allow", types)) => (true, types),
+            Some(("deny", types)) => (false, types),
+            _ => return Err(format!("expected allow= or deny= in {:?}", spec)),
+        };
+        let types = types
+            .split('/')
+            .map(|qtype| {
+                qtype
+                    .parse()
+                    .map_err(|_| format!("unknown type {:?}", qtype))
+            })
+            .collect::<Result<Vec<DnsType>, String>>()?;
+        let mut view = None;
+        for part in parts {
+            match part.strip_prefix("view=") {
+                Some(name) if !name.is_empty() => view = Some(name.to_string()),
+                _ => return Err(format!("unknown policy option {:?}", part)),
+            }
+        }
+        Ok(Rule {
+            spec: spec.to_string(),
+            zone: Name::from(zone),
+            allow,
+            types,
+            view,
+            hits: AtomicU64::new(0),
+        })
+    }
+
+    fn refuses(&self, qname: &Name, qtype: DnsType, view: Option<&str>) -> bool {
+        qname.is_subdomain_of(&self.zone)
+            && (self.view.is_none() || self.view.as_deref() == view)
+            && self.types.contains(&qtype) != self.allow
+    }
+}
+
+// Every rule must let a query through; the first that doesn't counts a hit.
+#[derive(Default)]
+pub(crate) struct Policies {
+    rules: Vec<Rule>,
+}
+
+impl Policies {
+    pub(crate) fn add(&mut self, spec: &str) -> Result<(), String> {
+        self.rules.push(Rule::parse(spec)?);
+        Ok(())
+    }
+
+    pub(crate) fn refuses(&self, qname: &Name, qtype: DnsType, view: Option<&str>) -> bool {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.refuses(qname, qtype, view))
+        {
+            Some(rule) => {
+                rule.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whether a rule keeps the record from the view, for records sent other
+    // than in answer to a query for them, which counts no hits.
+    pub(crate) fn hides(&self, name: &Name, qtype: DnsType, view: Option<&str>) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.refuses(name, qtype, view))
+    }
+
+    // Hit counters in the `stats` layout, optionally starting over.
+    pub(crate) fn report(&self, reset: bool) -> String {
+        let mut report = String::new();
+        for rule in &self.rules {
+            let hits = match reset {
+                true => rule.hits.swap(0, Ordering::Relaxed),
+                false => rule.hits.load(Ordering::Relaxed),
+            };
+            writeln!(report, "policy.hits.{}={}", rule.spec, hits).unwrap();
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policies(specs: &[&str]) -> Policies {
+        let mut policies = Policies::default();
+        for spec in specs {
+            policies.add(spec).unwrap();
+        }
+        policies
+    }
+
+    #[test]
+    fn test_parse() {
+        for bad in [
+            "example.com",
+            "example.com:A",
+            "example.com:permit=A",
+            "example.com:allow=A/BOGUS",
+            "example.com:deny=ANY,view=",
+            "example.com:deny=ANY,client=10.0.0.1",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_refuses() {
+        let policies = policies(&[
+            "example.com:allow=A/AAAA/TXT",
+            ".:deny=ANY",
+            "in-addr.arpa:deny=PTR,view=external",
+        ]);
+        let refuses = |name: &str, qtype, view| policies.refuses(&name.into(), qtype, view);
+        assert!(!refuses("www.example.com", DnsType::Aaaa, None));
+        assert!(refuses("www.example.com", DnsType::Mx, None));
+        assert!(!refuses("www.example.org", DnsType::Mx, None));
+        assert!(refuses("www.example.org", DnsType::Any, None));
+        assert!(refuses(
+            "1.2.0.192.in-addr.arpa",
+            DnsType::Ptr,
+            Some("external")
+        ));
+        assert!(!refuses(
+            "1.2.0.192.in-addr.arpa",
+            DnsType::Ptr,
+            Some("internal")
+        ));
+        assert!(!refuses("1.2.0.192.in-addr.arpa", DnsType::Ptr, None));
+
+        assert_eq!(
+            policies.report(true),
+            "policy.hits.example.com:allow=A/AAAA/TXT=1\n\
+             policy.hits..:deny=ANY=1\n\
+             policy.hits.in-addr.arpa:deny=PTR,view=external=1\n"
+        );
+        assert!(policies.report(false).ends_with("external=0\n"));
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::eyeballs;
+
+// Older connections are closed instead of reused, so that a server's
+// per-connection limits are never reached and a restarted upstream gets
+// its share of connections back.
+const MAX_AGE: Duration = Duration::from_secs(60);
+// Servers close idle connections after a few seconds (RFC 7766 section
+// 6.2.3); one left longer has likely been closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_IDLE: usize = 4;
+// Connections open to one server at once; more queries wait for one.
+const MAX_OPEN: usize = 32;
+
+// TCP connections to upstream servers, kept open between queries so that
+// each query doesn't pay for a handshake.
+#[derive(Default)]
+pub(crate) struct Pool {
+    servers: Mutex<HashMap<SocketAddr, Server>>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct Server {
+    idle: Vec<Idle>,
+    // Connections handed out and not yet returned.
+    busy: usize,
+    opened: u64,
+    reused: u64,
+}
+
+struct Idle {
+    stream: TcpStream,
+    opened: Instant,
+    used: Instant,
+}
+
+// A connection taken from the pool. It goes back with `release` after a
+// successful exchange; dropped, it is closed.
+pub(crate) struct Connection<'a> {
+    pool: &'a Pool,
+    server: SocketAddr,
+    stream: Option<TcpStream>,
+    opened: Instant,
+    pub(crate) reused: bool,
+}
+
+impl Pool {
+    // An idle connection to the server that is still open, or a new one,
+    // waiting until the timeout while MAX_OPEN are in use.
+    pub(crate) fn take(&self, server: SocketAddr, timeout: Duration) -> io::Result<Connection<'_>> {
+        let deadline = Instant::now() + timeout;
+        let mut servers = self.servers.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let entry = servers.entry(server).or_default();
+            while let Some(idle) = entry.idle.pop() {
+                let fresh = now - idle.opened < MAX_AGE && now - idle.used < IDLE_TIMEOUT;
+                if fresh && alive(&idle.stream) {
+                    entry.busy += 1;
+                    entry.reused += 1;
+                    return Ok(Connection {
+                        pool: self,
+                        server,
+                        stream: Some(idle.stream),
+                        opened: idle.opened,
+                        reused: true,
+                    });
+                }
+            }
+            if entry.busy < MAX_OPEN {
+                entry.busy += 1;
+                entry.opened += 1;
+                break;
+            }
+            let remaining = deadline.saturating_duration_since(now);
+            if remaining.is_zero() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no free upstream connection",
+                ));
+            }
+            servers = self.released.wait_timeout(servers, remaining).unwrap().0;
+        }
+        drop(servers);
+
+        let mut connection = Connection {
+            pool: self,
+            server,
+            stream: None,
+            opened: Instant::now(),
+            reused: false,
+        };
+        let remaining = deadline.saturating_duration_since(connection.opened);
+        connection.stream = Some(eyeballs::connect(&[server], remaining)?);
+        Ok(connection)
+    }
+
+    // One key=value line per counter, as in `ctl stats`.
+    pub(crate) fn report(&self) -> String {
+        let servers = self.servers.lock().unwrap();
+        let mut addrs: Vec<&SocketAddr> = servers.keys().collect();
+        addrs.sort();
+        let mut report = String::new();
+        for addr in addrs {
+            let server = &servers[addr];
+            for (key, value) in [
+                ("opened", server.opened),
+                ("reused", server.reused),
+                ("idle", server.idle.len() as u64),
+            ] {
+                writeln!(report, "upstream.{}.tcp.{}={}", addr, key, value).unwrap();
+            }
+        }
+        report
+    }
+}
+
+impl Connection<'_> {
+    pub(crate) fn stream(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().expect("connected")
+    }
+
+    pub(crate) fn release(mut self) {
+        let Some(stream) = self.stream.take() else {
+            return;
+        };
+        let mut servers = self.pool.servers.lock().unwrap();
+        let entry = servers.entry(self.server).or_default();
+        if entry.idle.len() < MAX_IDLE && self.opened.elapsed() < MAX_AGE {
+            entry.idle.push(Idle {
+                stream,
+                opened: self.opened,
+                used: Instant::now(),
+            });
+        }
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        let mut servers = self.pool.servers.lock().unwrap();
+        if let Some(entry) = servers.get_mut(&self.server) {
+            entry.busy -= 1;
+        }
+        self.pool.released.notify_one();
+    }
+}
+
+// Whether an idle connection is still open: the server has neither closed
+// it nor sent anything unasked.
+fn alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let result = stream.peek(&mut [0; 1]);
+    stream.set_nonblocking(false).is_ok()
+        && matches!(result, Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_reuse() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let pool = Pool::default();
+        let timeout = Duration::from_secs(1);
+
+        let connection = pool.take(server, timeout).unwrap();
+        assert!(!connection.reused);
+        let (accepted, _) = listener.accept().unwrap();
+        connection.release();
+        let connection = pool.take(server, timeout).unwrap();
+        assert!(connection.reused);
+        connection.release();
+
+        // One the server closed while idle isn't handed out again.
+        drop(accepted);
+        std::thread::sleep(Duration::from_millis(50));
+        let connection = pool.take(server, timeout).unwrap();
+        assert!(!connection.reused);
+        // Nor one that wasn't released.
+        drop(connection);
+        assert!(!pool.take(server, timeout).unwrap().reused);
+
+        let report = pool.report();
+        assert!(report.contains(&format!("upstream.{}.tcp.opened=3\n", server)));
+        assert!(report.contains(&format!("upstream.{}.tcp.reused=1\n", server)));
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use rand::Rng;
+
+use crate::crypto;
+
+// `--anonymize-clients truncate[=v4/v6]` or `hash[=key]`: client addresses
+// in logs and metrics are cut down to a network, a /24 and a /56 by
+// default, or replaced by a keyed hash of the same family, so one client's
+// queries can still be told apart from another's without saying who it
+// is. Without a key, each run picks its own, and hashes don't carry over
+// restarts.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) enum Anonymizer {
+    Truncate { v4: u8, v6: u8 },
+    Hash(Vec<u8>),
+}
+
+impl Anonymizer {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once('=') {
+            None if spec == "truncate" => Ok(Anonymizer::Truncate { v4: 24, v6: 56 }),
+            None if spec == "hash" => Ok(Anonymizer::Hash(
+                rand::thread_rng().gen::<[u8; 32]>().to_vec(),
+            )),
+            Some(("truncate", prefixes)) => {
+                let bad = || format!("expected truncate=<v4 bits>/<v6 bits>, got {:?}", prefixes);
+                let (v4, v6) = prefixes.split_once('/').ok_or_else(bad)?;
+                let v4 = v4.parse().ok().filter(|bits| *bits <= 32).ok_or_else(bad)?;
+                let v6 = v6
+                    .parse()
+                    .ok()
+                    .filter(|bits| *bits <= 128)
+                    .ok_or_else(bad)?;
+                Ok(Anonymizer::Truncate { v4, v6 })
+            }
+            Some(("hash", key)) if !key.is_empty() => Ok(Anonymizer::Hash(key.as_bytes().to_vec())),
+            _ => Err(format!("expected truncate or hash, got {:?}", spec)),
+        }
+    }
+
+    pub(crate) fn apply(&self, client: IpAddr) -> IpAddr {
+        match (self, client) {
+            (Anonymizer::Truncate { v4, .. }, IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *v4 as u32).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            (Anonymizer::Truncate { v6, .. }, IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *v6 as u32).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+            (Anonymizer::Hash(key), IpAddr::V4(ip)) => {
+                let digest = crypto::hmac_sha256(key, &ip.octets());
+                IpAddr::V4(Ipv4Addr::new(digest[0], digest[1], digest[2], digest[3]))
+            }
+            (Anonymizer::Hash(key), IpAddr::V6(ip)) => {
+                let digest = crypto::hmac_sha256(key, &ip.octets());
+                IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&digest[..16]).unwrap()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn test_truncate() {
+        let anonymizer = Anonymizer::parse("truncate").unwrap();
+        assert_eq!(anonymizer.apply(ip("192.0.2.77")), ip("192.0.2.0"));
+        assert_eq!(
+            anonymizer.apply(ip("2001:db8:1:2345:6789::1")),
+            ip("2001:db8:1:2300::")
+        );
+        let anonymizer = Anonymizer::parse("truncate=16/0").unwrap();
+        assert_eq!(anonymizer.apply(ip("192.0.2.77")), ip("192.0.0.0"));
+        assert_eq!(anonymizer.apply(ip("2001:db8::1")), ip("::"));
+        assert_eq!(
+            Anonymizer::parse("truncate=32/128")
+                .unwrap()
+                .apply(ip("192.0.2.77")),
+            ip("192.0.2.77")
+        );
+
+        for bad in [
+            "truncate=24",
+            "truncate=33/56",
+            "truncate=24/129",
+            "hash=",
+            "mask",
+        ] {
+            assert!(Anonymizer::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        let anonymizer = Anonymizer::parse("hash=secret").unwrap();
+        let hashed = anonymizer.apply(ip("192.0.2.77"));
+        assert!(hashed.is_ipv4());
+        assert_ne!(hashed, ip("192.0.2.77"));
+        assert_eq!(anonymizer.apply(ip("192.0.2.77")), hashed);
+        assert_ne!(anonymizer.apply(ip("192.0.2.78")), hashed);
+        assert!(anonymizer.apply(ip("2001:db8::1")).is_ipv6());
+        // Another key, another pseudonym.
+        assert_ne!(
+            Anonymizer::parse("hash").unwrap().apply(ip("192.0.2.77")),
+            hashed
+        );
+    }
+}
@@ -0,0 +1,639 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::common::Name;
+use crate::gzip;
+use crate::json::Json;
+
+const DEFAULT_MAX_SIZE: u64 = 10 << 20;
+const DEFAULT_KEEP: usize = 7;
+// With a retention window and no max-age, the log still rotates this
+// often, so that nothing outlives the window by more than this.
+const RETAIN_MAX_AGE: Duration = Duration::from_secs(86400);
+// How often rotated files are checked against the retention window.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "usage: dns-server log tail <file> [-n <count>] [--follow]
+       dns-server log search <file> [--client <ip>] [--name <name>] [--type <type>] [--rcode <rcode>]";
+
+// `--query-log path[,max-size=N[k|m|g]][,max-age=N[s|m|h|d]][,keep=N]
+// [,retain=N[s|m|h|d]]`: every query answered is appended to `path` as a
+// line of JSON. Once the file reaches max-size (10m by default) or max-age,
+// it is compressed to `path.1.gz`, older ones moving up to `path.<keep>.gz`
+// (7 by default). Rotated files whose entries are all older than `retain`
+// are deleted.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct QueryLogConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) max_size: u64,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) keep: usize,
+    pub(crate) retain: Option<Duration>,
+}
+
+impl QueryLogConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let path = parts.next().filter(|path| !path.is_empty());
+        let mut config = QueryLogConfig {
+            path: PathBuf::from(path.ok_or("expected a log file")?),
+            max_size: DEFAULT_MAX_SIZE,
+            max_age: None,
+            keep: DEFAULT_KEEP,
+            retain: None,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("max-size", size)) => {
+                    config.max_size =
+                        parse_scaled(size, &[("k", 1 << 10), ("m", 1 << 20), ("g", 1 << 30)])
+                            .filter(|size| *size > 0)
+                            .ok_or_else(|| format!("bad max-size {:?}", size))?;
+                }
+                Some(("max-age", age)) => {
+                    let age =
+                        parse_duration(age).ok_or_else(|| format!("bad max-age {:?}", age))?;
+                    config.max_age = Some(age);
+                }
+                Some(("retain", window)) => {
+                    let window = parse_duration(window)
+                        .ok_or_else(|| format!("bad retention window {:?}", window))?;
+                    config.retain = Some(window);
+                }
+                Some(("keep", keep)) => {
+                    config.keep = keep
+                        .parse()
+                        .map_err(|_| format!("bad keep count {:?}", keep))?;
+                }
+                _ => return Err(format!("unknown query log option {:?}", part)),
+            }
+        }
+        if let (Some(window), None) = (config.retain, config.max_age) {
+            config.max_age = Some(window.min(RETAIN_MAX_AGE));
+        }
+        Ok(config)
+    }
+
+    // The n-th rotated file, 1 being the newest.
+    fn rotated(&self, n: usize) -> PathBuf {
+        rotated(&self.path, n)
+    }
+}
+
+// A number with an optional unit suffix.
+fn parse_scaled(text: &str, units: &[(&str, u64)]) -> Option<u64> {
+    let (digits, scale) = units
+        .iter()
+        .find_map(|(unit, scale)| Some((text.strip_suffix(unit)?, *scale)))
+        .unwrap_or((text, 1));
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+fn parse_duration(text: &str) -> Option<Duration> {
+    let units = [("s", 1), ("m", 60), ("h", 3600), ("d", 86400)];
+    let seconds = parse_scaled(text, &units).filter(|seconds| *seconds > 0)?;
+    Some(Duration::from_secs(seconds))
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.gz", n));
+    PathBuf::from(name)
+}
+
+// One answered query.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Entry {
+    pub(crate) time: SystemTime,
+    pub(crate) client: IpAddr,
+    pub(crate) name: String,
+    pub(crate) qtype: String,
+    pub(crate) rcode: String,
+    pub(crate) answers: usize,
+    pub(crate) duration: Duration,
+}
+
+impl Entry {
+    fn to_json(&self) -> Json {
+        let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let millis = |d: Duration| (d.as_secs_f64() * 1e6).round() / 1e3;
+        Json::Object(vec![
+            ("time".into(), Json::Number(millis(time) / 1e3)),
+            ("client".into(), Json::String(self.client.to_string())),
+            ("name".into(), Json::String(self.name.clone())),
+            ("type".into(), Json::String(self.qtype.clone())),
+            ("rcode".into(), Json::String(self.rcode.clone())),
+            ("answers".into(), Json::Number(self.answers as f64)),
+            ("ms".into(), Json::Number(millis(self.duration))),
+        ])
+    }
+
+    fn from_json(line: &str) -> Option<Entry> {
+        let json = Json::parse(line).ok()?;
+        let seconds = |key| match json.get(key)? {
+            Json::Number(n) if *n >= 0.0 => Some(Duration::from_secs_f64(*n)),
+            _ => None,
+        };
+        let text = |key| Some(json.get(key)?.as_str()?.to_string());
+        Some(Entry {
+            time: UNIX_EPOCH + seconds("time")?,
+            client: json.get("client")?.as_str()?.parse().ok()?,
+            name: text("name")?,
+            qtype: text("type")?,
+            rcode: text("rcode")?,
+            answers: json.get("answers")?.as_u64()? as usize,
+            duration: seconds("ms")? / 1000,
+        })
+    }
+}
+
+// `2026-10-15T09:30:00.250Z 192.0.2.7 www.example.com A NOERROR 2 answers 1.5ms`
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let since = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since.as_secs();
+        let (year, month, day) = civil_date(seconds / 86400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z {} {} {} {} {} answers {}ms",
+            year,
+            month,
+            day,
+            seconds / 3600 % 24,
+            seconds / 60 % 60,
+            seconds % 60,
+            since.subsec_millis(),
+            self.client,
+            self.name,
+            self.qtype,
+            self.rcode,
+            self.answers,
+            self.duration.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+// The proleptic Gregorian date `days` after 1970-01-01.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counted in 400-year eras from 0000-03-01, as in Howard Hinnant's
+    // `civil_from_days`.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// The log file being appended to. Opened on the first query, so a server
+// without `--query-log` never touches the disk.
+#[derive(Default)]
+pub(crate) struct QueryLog {
+    current: Mutex<Option<Current>>,
+}
+
+struct Current {
+    file: File,
+    size: u64,
+    opened: SystemTime,
+    purged: SystemTime,
+}
+
+impl QueryLog {
+    // Failures are reported and the entry dropped: answering matters more
+    // than logging.
+    pub(crate) fn record(&self, config: &QueryLogConfig, entry: &Entry) {
+        let mut current = self.current.lock().unwrap();
+        if let Err(e) = append(config, &mut current, entry) {
+            eprintln!("Failed to write query log {}: {}", config.path.display(), e);
+            *current = None;
+        }
+    }
+}
+
+fn append(config: &QueryLogConfig, current: &mut Option<Current>, entry: &Entry) -> io::Result<()> {
+    if let Some(open) = current {
+        if entry.time.duration_since(open.purged).unwrap_or_default() >= PURGE_INTERVAL {
+            open.purged = entry.time;
+            purge(config, entry.time)?;
+        }
+        let old = config.max_age.is_some_and(|max_age| {
+            entry.time.duration_since(open.opened).unwrap_or_default() >= max_age
+        });
+        if open.size >= config.max_size || old {
+            *current = None;
+            rotate(config)?;
+        }
+    }
+    let open = match current {
+        Some(open) => open,
+        None => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&config.path)?;
+            let metadata = file.metadata()?;
+            // A file left by an earlier run is as old as its first entry.
+            let opened = metadata.created().unwrap_or(entry.time);
+            purge(config, entry.time)?;
+            current.insert(Current {
+                file,
+                size: metadata.len(),
+                opened,
+                purged: entry.time,
+            })
+        }
+    };
+    // One write per line, so readers never see half of one.
+    let line = format!("{}\n", entry.to_json());
+    open.file.write_all(line.as_bytes())?;
+    open.size += line.len() as u64;
+    Ok(())
+}
+
+// Compresses the log into `path.1.gz`, moving the older ones up and dropping
+// the oldest.
+fn rotate(config: &QueryLogConfig) -> io::Result<()> {
+    let ignore_missing = |result: io::Result<()>| match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+    if config.keep > 0 {
+        ignore_missing(fs::remove_file(config.rotated(config.keep)))?;
+        for n in (1..config.keep).rev() {
+            ignore_missing(fs::rename(config.rotated(n), config.rotated(n + 1)))?;
+        }
+        let compressed = gzip::compress(&fs::read(&config.path)?);
+        // Written aside first, so a crash never leaves half a file.
+        let mut partial = config.path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, compressed)?;
+        fs::rename(&partial, config.rotated(1))?;
+    }
+    fs::remove_file(&config.path)
+}
+
+// Deletes the rotated files last written before the retention window. Older
+// files were written earlier still, so they all go.
+fn purge(config: &QueryLogConfig, now: SystemTime) -> io::Result<()> {
+    let Some(window) = config.retain else {
+        return Ok(());
+    };
+    for n in 1.. {
+        let path = config.rotated(n);
+        let written = match fs::metadata(&path) {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if now.duration_since(written).unwrap_or_default() >= window {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// The log and its rotated files, oldest first.
+fn files(path: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = (1..)
+        .map(|n| rotated(path, n))
+        .take_while(|path| path.exists())
+        .collect();
+    files.reverse();
+    files.push(path.to_path_buf());
+    files
+}
+
+// The entries of one file, skipping lines that aren't; a missing file has
+// none.
+fn read(path: &Path) -> io::Result<(Vec<Entry>, usize)> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e),
+    };
+    let data = if path.extension().is_some_and(|extension| extension == "gz") {
+        gzip::decompress(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+    } else {
+        data
+    };
+    Ok(parse(&String::from_utf8_lossy(&data)))
+}
+
+fn parse(text: &str) -> (Vec<Entry>, usize) {
+    let mut skipped = 0;
+    let entries = text
+        .lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| {
+            let entry = Entry::from_json(line);
+            skipped += usize::from(entry.is_none());
+            entry
+        })
+        .collect();
+    (entries, skipped)
+}
+
+#[derive(Default)]
+struct Filter {
+    client: Option<IpAddr>,
+    name: Option<Name>,
+    qtype: Option<String>,
+    rcode: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        let name = Name::from(entry.name.as_str());
+        self.client.iter().all(|client| *client == entry.client)
+            && self.name.iter().all(|zone| name.is_subdomain_of(zone))
+            && self
+                .qtype
+                .iter()
+                .all(|qtype| qtype.eq_ignore_ascii_case(&entry.qtype))
+            && self
+                .rcode
+                .iter()
+                .all(|rcode| rcode.eq_ignore_ascii_case(&entry.rcode))
+    }
+}
+
+// `dns-server log tail <file>` prints the last entries of a query log,
+// reaching back into rotated files as needed, and with --follow keeps
+// printing new ones. `dns-server log search <file>` prints the entries
+// matching every filter given, oldest first.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let mut args = args.into_iter();
+    let (Some(command), Some(path)) = (args.next(), args.next()) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let path = PathBuf::from(path);
+    let (mut count, mut follow, mut filter) = (10, false, Filter::default());
+    while let Some(arg) = args.next() {
+        let parsed = match (command.as_str(), arg.as_str()) {
+            ("tail", "-n") => args.next().and_then(|v| v.parse().ok()).map(|v| count = v),
+            ("tail", "--follow" | "-f") => {
+                follow = true;
+                Some(())
+            }
+            ("search", "--client") => args
+                .next()
+                .and_then(|v| v.parse().ok())
+                .map(|v| filter.client = Some(v)),
+            ("search", "--name") => args
+                .next()
+                .map(|v| filter.name = Some(Name::from(v.as_str()))),
+            ("search", "--type") => args.next().map(|v| filter.qtype = Some(v)),
+            ("search", "--rcode") => args.next().map(|v| filter.rcode = Some(v)),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+
+    let result = match command.as_str() {
+        "tail" => tail(&path, count, follow),
+        "search" => search(&path, &filter),
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    match result {
+        Ok(0) => 0,
+        Ok(skipped) => {
+            eprintln!("{} unreadable lines skipped", skipped);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            1
+        }
+    }
+}
+
+// Returns the number of unreadable lines skipped.
+fn tail(path: &Path, count: usize, follow: bool) -> io::Result<usize> {
+    let mut last = Vec::new();
+    let mut skipped = 0;
+    for file in files(path).iter().rev() {
+        if last.len() >= count {
+            break;
+        }
+        let (entries, unreadable) = read(file)?;
+        skipped += unreadable;
+        let wanted = count - last.len();
+        last.splice(
+            0..0,
+            entries[entries.len().saturating_sub(wanted)..]
+                .iter()
+                .cloned(),
+        );
+    }
+    for entry in &last {
+        println!("{}", entry);
+    }
+    if follow {
+        let offset = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        return self::follow(path, offset);
+    }
+    Ok(skipped)
+}
+
+// Prints entries as they are appended, starting over when the log is
+// rotated. Returns only on errors.
+fn follow(path: &Path, mut offset: u64) -> io::Result<usize> {
+    loop {
+        thread::sleep(FOLLOW_INTERVAL);
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if file.metadata()?.len() < offset {
+            offset = 0;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        // Only whole lines; the rest is read again next time.
+        let end = data.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        offset += end as u64;
+        for entry in parse(&String::from_utf8_lossy(&data[..end])).0 {
+            println!("{}", entry);
+        }
+    }
+}
+
+fn search(path: &Path, filter: &Filter) -> io::Result<usize> {
+    let mut skipped = 0;
+    for file in files(path) {
+        let (entries, unreadable) = read(&file)?;
+        skipped += unreadable;
+        for entry in entries.iter().filter(|entry| filter.matches(entry)) {
+            println!("{}", entry);
+        }
+    }
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(name: &str, seconds: u64) -> Entry {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_millis(seconds * 1000 + 250),
+            client: "192.0.2.7".parse().unwrap(),
+            name: name.to_string(),
+            qtype: "A".to_string(),
+            rcode: "NOERROR".to_string(),
+            answers: 2,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let config =
+            QueryLogConfig::parse("/var/log/dns.jsonl,max-size=5m,max-age=1d,keep=3,retain=30d")
+                .unwrap();
+        assert_eq!(
+            config,
+            QueryLogConfig {
+                path: "/var/log/dns.jsonl".into(),
+                max_size: 5 << 20,
+                max_age: Some(Duration::from_secs(86400)),
+                keep: 3,
+                retain: Some(Duration::from_secs(30 * 86400)),
+            }
+        );
+        let config = QueryLogConfig::parse("q.log,retain=12h").unwrap();
+        assert_eq!(config.max_age, Some(Duration::from_secs(12 * 3600)));
+        let config = QueryLogConfig::parse("q.log,retain=7d").unwrap();
+        assert_eq!(config.max_age, Some(RETAIN_MAX_AGE));
+        assert_eq!(
+            QueryLogConfig::parse("q.log").unwrap().max_size,
+            DEFAULT_MAX_SIZE
+        );
+        for bad in [
+            "",
+            ",keep=1",
+            "q.log,max-size=0",
+            "q.log,max-age=1w",
+            "q.log,gzip",
+        ] {
+            assert!(QueryLogConfig::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_entry() {
+        let entry = entry("www.example.com", 1_791_970_200);
+        let line = entry.to_json().to_string();
+        assert_eq!(
+            line,
+            "{\"time\":1791970200.25,\"client\":\"192.0.2.7\",\"name\":\"www.example.com\",\"type\":\"A\",\"rcode\":\"NOERROR\",\"answers\":2,\"ms\":1.5}"
+        );
+        assert_eq!(Entry::from_json(&line), Some(entry.clone()));
+        assert_eq!(
+            entry.to_string(),
+            "2026-10-14T09:30:00.250Z 192.0.2.7 www.example.com A NOERROR 2 answers 1.5ms"
+        );
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("dns-server-querylog-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = QueryLogConfig {
+            path: dir.join("queries.jsonl"),
+            max_size: 200,
+            max_age: None,
+            keep: 2,
+            retain: None,
+        };
+        let log = QueryLog::default();
+        // About 120 bytes each, so every second entry rotates.
+        for i in 0..7 {
+            log.record(
+                &config,
+                &entry(&format!("host{}.example.com", i), 1_791_970_200 + i),
+            );
+        }
+        assert!(config.rotated(2).exists());
+        assert!(!config.rotated(3).exists());
+
+        let names = |filter: &Filter| {
+            files(&config.path)
+                .iter()
+                .flat_map(|file| read(file).unwrap().0)
+                .filter(|entry| filter.matches(entry))
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+        // The first two were dropped with the oldest file.
+        assert_eq!(
+            names(&Filter::default()),
+            vec![
+                "host2.example.com",
+                "host3.example.com",
+                "host4.example.com",
+                "host5.example.com",
+                "host6.example.com"
+            ]
+        );
+        let filter = Filter {
+            name: Some("HOST4.example.com".into()),
+            qtype: Some("a".into()),
+            ..Filter::default()
+        };
+        assert_eq!(names(&filter), vec!["host4.example.com"]);
+        let filter = Filter {
+            client: Some("192.0.2.8".parse().unwrap()),
+            ..Filter::default()
+        };
+        assert!(names(&filter).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_purge() {
+        let dir = std::env::temp_dir().join(format!("dns-server-purge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("queries.jsonl");
+        let config = QueryLogConfig::parse(&format!("{},retain=1h", path.display())).unwrap();
+        let now = SystemTime::now();
+        for (n, age) in [(1, 0), (2, 7200), (3, 9000)] {
+            let file = File::create(config.rotated(n)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        purge(&config, now).unwrap();
+        assert!(config.rotated(1).exists());
+        assert!(!config.rotated(2).exists());
+        assert!(!config.rotated(3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
-use crate::common::{DnsClass, DnsType, Name};
+use crate::common::{Compressor, DnsClass, DnsType, Name};
 use crate::error::ParseError;
 
+#[derive(PartialEq, Debug, Clone)]
 pub(crate) struct DnsQuestion {
     pub(crate) qname: Name,
     pub(crate) qtype: DnsType,
@@ -11,37 +12,66 @@ impl TryFrom<&[u8]> for DnsQuestion {
     type Error = ParseError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let qname = Name::try_from(value)?;
+        DnsQuestion::parse(value, 0).map(|(question, _)| question)
+    }
+}
+
+impl DnsQuestion {
+    pub(crate) fn parse(message: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (qname, offset) = Name::parse(message, offset)?;
 
-        let value = &value[qname.len()..];
+        let value = message
+            .get(offset..offset + 4)
+            .ok_or(ParseError::UnexpectedEof)?;
         let qtype: u16 = ((value[0] as u16) << 8) | value[1] as u16;
-        let qtype = DnsType::try_from(qtype)?;
+        let qtype = DnsType::from(qtype);
         let qclass: u16 = ((value[2] as u16) << 8) | value[3] as u16;
         let qclass = DnsClass::try_from(qclass)?;
 
-        Ok(DnsQuestion {
+        let question = DnsQuestion {
             qname,
             qtype,
             qclass,
-        })
+        };
+        Ok((question, offset + 4))
     }
-}
 
-impl DnsQuestion {
+    #[allow(dead_code)]
     pub(crate) fn len(&self) -> usize {
         self.qname.len() + 4
     }
 
-    pub(crate) fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.qname.to_bytes());
-        bytes.push((self.qtype as u16 >> 8) as u8);
-        bytes.push(self.qtype as u8);
-        bytes.push((self.qclass as u16 >> 8) as u8);
-        bytes.push(self.qclass as u8);
-        bytes
+    #[allow(dead_code)]
+    pub(crate) fn write(&self, bytes: &mut Vec<u8>) {
+        self.write_compressed(bytes, &mut Compressor::off());
+    }
+
+    pub(crate) fn write_compressed<'a>(&'a self, bytes: &mut Vec<u8>, names: &mut Compressor<'a>) {
+        self.qname.write_compressed(bytes, names);
+        bytes.extend_from_slice(&(self.qtype.code()).to_be_bytes());
+        bytes.extend_from_slice(&(self.qclass as u16).to_be_bytes());
     }
 }
 
 #[cfg(test)]
-mod test {}
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_question_parse() {
+        let bytes = b"\x07example\x03com\x00\x00\x10\x00\x03";
+        let (question, end) = DnsQuestion::parse(bytes, 0).unwrap();
+        assert_eq!(question.qname.as_str(), "example.com");
+        assert_eq!(question.qtype, DnsType::Txt);
+        assert_eq!(question.qclass, DnsClass::Ch);
+        assert_eq!(end, bytes.len());
+        let mut written = Vec::new();
+        question.write(&mut written);
+        assert_eq!(written, bytes);
+
+        assert!(matches!(
+            DnsQuestion::parse(&bytes[..bytes.len() - 1], 0),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// Which query gives way when one arrives at a full queue.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Overflow {
+    // The new one, so the queries already waiting get answered.
+    Newest,
+    // The one that has waited longest, whose client is the likeliest to
+    // have given up on it.
+    Oldest,
+}
+
+impl Overflow {
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "newest" => Ok(Overflow::Newest),
+            "oldest" => Ok(Overflow::Oldest),
+            _ => Err(format!("expected newest or oldest, got {:?}", text)),
+        }
+    }
+}
+
+// How UDP queries wait between the socket and the threads answering them:
+// `--workers` threads per listener take queries from a queue of at most
+// `--queue-limit`, and `--queue-drop` picks the one dropped when it is
+// full. A flood then costs dropped queries rather than memory, and
+// latency stays within what the queue holds.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct QueueLimits {
+    pub(crate) workers: usize,
+    pub(crate) size: usize,
+    pub(crate) overflow: Overflow,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits {
+            workers: 8,
+            size: 1024,
+            overflow: Overflow::Newest,
+        }
+    }
+}
+
+// Counters over every listener's queue.
+#[derive(Default)]
+pub(crate) struct QueueStats {
+    depth: AtomicUsize,
+    peak: AtomicUsize,
+    taken: AtomicU64,
+    dropped: AtomicU64,
+    // Taken too late to be worth answering.
+    stale: AtomicU64,
+    waited_micros: AtomicU64,
+}
+
+impl QueueStats {
+    pub(crate) fn report(&self) -> String {
+        let taken = self.taken.load(Ordering::Relaxed);
+        let waited = self.waited_micros.load(Ordering::Relaxed);
+        let mut report = String::new();
+        for (key, value) in [
+            ("depth", self.depth.load(Ordering::Relaxed) as u64),
+            ("peak", self.peak.load(Ordering::Relaxed) as u64),
+            ("dropped", self.dropped.load(Ordering::Relaxed)),
+            ("stale", self.stale.load(Ordering::Relaxed)),
+            ("wait.avg_us", waited.checked_div(taken).unwrap_or(0)),
+        ] {
+            writeln!(report, "queue.{}={}", key, value).unwrap();
+        }
+        report
+    }
+}
+
+// A bounded queue of work, each item stamped with when it arrived.
+pub(crate) struct Queue<'a, T> {
+    items: Mutex<Items<T>>,
+    ready: Condvar,
+    limits: QueueLimits,
+    stats: &'a QueueStats,
+}
+
+struct Items<T> {
+    queued: VecDeque<(T, Instant)>,
+    closed: bool,
+}
+
+impl<'a, T> Queue<'a, T> {
+    pub(crate) fn new(limits: QueueLimits, stats: &'a QueueStats) -> Self {
+        Queue {
+            items: Mutex::new(Items {
+                queued: VecDeque::with_capacity(limits.size),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            limits,
+            stats,
+        }
+    }
+
+    // Queues the item, unless the queue is full and it is the one to go.
+    pub(crate) fn push(&self, item: T) -> bool {
+        let mut items = self.items.lock().unwrap();
+        if items.queued.len() >= self.limits.size {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            match self.limits.overflow {
+                Overflow::Newest => return false,
+                Overflow::Oldest => {
+                    items.queued.pop_front();
+                    self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+        items.queued.push_back((item, Instant::now()));
+        let depth = self.stats.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.stats.peak.fetch_max(depth, Ordering::Relaxed);
+        drop(items);
+        self.ready.notify_one();
+        true
+    }
+
+    // The oldest item, waiting for one if there is none, or None once the
+    // queue is closed and empty. Items that have waited longer than
+    // `max_wait` are discarded on the way.
+    pub(crate) fn pop(&self, max_wait: Duration) -> Option<T> {
+        let mut items = self.items.lock().unwrap();
+        loop {
+            let Some((item, queued)) = items.queued.pop_front() else {
+                if items.closed {
+                    return None;
+                }
+                items = self.ready.wait(items).unwrap();
+                continue;
+            };
+            self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+            let waited = queued.elapsed();
+            if waited > max_wait {
+                self.stats.stale.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.stats.taken.fetch_add(1, Ordering::Relaxed);
+            self.stats
+                .waited_micros
+                .fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+            return Some(item);
+        }
+    }
+
+    // Lets the threads waiting on the queue finish once it is empty.
+    pub(crate) fn close(&self) {
+        self.items.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn limits(size: usize, overflow: Overflow) -> QueueLimits {
+        QueueLimits {
+            workers: 1,
+            size,
+            overflow,
+        }
+    }
+
+    const FOREVER: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_overflow() {
+        let stats = QueueStats::default();
+        let queue = Queue::new(limits(2, Overflow::Newest), &stats);
+        assert!(queue.push(1));
+        assert!(queue.push(2));
+        assert!(!queue.push(3));
+        assert_eq!((queue.pop(FOREVER), queue.pop(FOREVER)), (Some(1), Some(2)));
+
+        let queue = Queue::new(limits(2, Overflow::Oldest), &stats);
+        for item in 1..=3 {
+            assert!(queue.push(item));
+        }
+        assert_eq!((queue.pop(FOREVER), queue.pop(FOREVER)), (Some(2), Some(3)));
+        assert_eq!(
+            stats.report().lines().take(3).collect::<Vec<_>>(),
+            vec!["queue.depth=0", "queue.peak=2", "queue.dropped=2"]
+        );
+    }
+
+    #[test]
+    fn test_stale() {
+        let stats = QueueStats::default();
+        let queue = Queue::new(limits(4, Overflow::Newest), &stats);
+        queue.push(1);
+        thread::sleep(Duration::from_millis(20));
+        queue.push(2);
+        assert_eq!(queue.pop(Duration::from_millis(10)), Some(2));
+        assert!(stats.report().contains("queue.stale=1\n"));
+    }
+
+    #[test]
+    fn test_close() {
+        let stats = QueueStats::default();
+        let queue = Queue::new(QueueLimits::default(), &stats);
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                let mut sum = 0;
+                while let Some(item) = queue.pop(FOREVER) {
+                    sum += item;
+                }
+                sum
+            });
+            thread::sleep(Duration::from_millis(10));
+            queue.push(20);
+            queue.push(22);
+            queue.close();
+            assert_eq!(worker.join().unwrap(), 42);
+        });
+    }
+}
@@ -0,0 +1,792 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::edns::Edns;
+use crate::error::ResolveError;
+use crate::header::ResponseCode;
+use crate::hints;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::reputation::{Failure, Reputation};
+use crate::resolver::Resolver;
+use crate::sanitize;
+
+const SERVER_TIMEOUT: Duration = Duration::from_millis(800);
+// Nameservers without glue resolved per referral. Resolving every NS name
+// of a referral is what NXNSAttack-style delegations abuse.
+const MAX_GLUELESS_LOOKUPS: usize = 3;
+
+// Caps on the work one client query may cause, so a zone crafted to make
+// the resolver chase endless referrals, CNAMEs or nameserver names fails
+// with SERVFAIL instead.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) referrals: usize,
+    pub(crate) cname_depth: usize,
+    pub(crate) queries: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            referrals: 30,
+            cname_depth: 8,
+            queries: 64,
+        }
+    }
+}
+
+struct Budget {
+    limits: Limits,
+    deadline: Instant,
+    referrals: usize,
+    cnames: usize,
+    queries: usize,
+}
+
+impl Budget {
+    fn new(limits: Limits, deadline: Instant) -> Self {
+        Budget {
+            limits,
+            deadline,
+            referrals: 0,
+            cnames: 0,
+            queries: 0,
+        }
+    }
+
+    fn spend(count: &mut usize, limit: usize, what: &'static str) -> Result<(), ResolveError> {
+        *count += 1;
+        if *count > limit {
+            return Err(ResolveError::LimitExceeded(what));
+        }
+        Ok(())
+    }
+
+    fn referral(&mut self) -> Result<(), ResolveError> {
+        Budget::spend(&mut self.referrals, self.limits.referrals, "referrals")
+    }
+
+    fn cname(&mut self) -> Result<(), ResolveError> {
+        Budget::spend(&mut self.cnames, self.limits.cname_depth, "CNAME chain")
+    }
+
+    fn query(&mut self) -> Result<(), ResolveError> {
+        Budget::spend(&mut self.queries, self.limits.queries, "upstream queries")
+    }
+}
+
+// One query to one nameserver while resolving, and what came of it.
+pub(crate) struct Step<'a> {
+    // The zone the server was asked as an authority for.
+    pub(crate) zone: &'a Name,
+    pub(crate) server: SocketAddr,
+    pub(crate) name: &'a Name,
+    pub(crate) qtype: DnsType,
+    pub(crate) rtt: Duration,
+    pub(crate) result: Result<&'a DnsPacket, &'a ResolveError>,
+}
+
+// Told of every query as it completes, for `dns-server trace`.
+pub(crate) type Tracer = Arc<dyn Fn(&Step) + Send + Sync>;
+
+// An iterative resolver starting from the root servers.
+pub(crate) struct Recursor {
+    roots: Vec<SocketAddr>,
+    port: u16,
+    limits: Limits,
+    // The UDP payload size advertised to nameservers.
+    udp_size: u16,
+    // Shared by every query, so that broken nameservers are avoided.
+    reputation: Arc<Reputation>,
+    // Whether to ask for DNSSEC records, and so denial of existence proofs.
+    dnssec_ok: bool,
+    tracer: Option<Tracer>,
+}
+
+impl Recursor {
+    pub(crate) fn new(
+        limits: Limits,
+        udp_size: u16,
+        roots: &[IpAddr],
+        reputation: Arc<Reputation>,
+    ) -> Self {
+        let roots = roots.iter().map(|ip| SocketAddr::new(*ip, 53)).collect();
+        Recursor {
+            roots,
+            port: 53,
+            limits,
+            udp_size,
+            reputation,
+            dnssec_ok: false,
+            tracer: None,
+        }
+    }
+
+    pub(crate) fn with_dnssec_ok(mut self, dnssec_ok: bool) -> Self {
+        self.dnssec_ok = dnssec_ok;
+        self
+    }
+
+    pub(crate) fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    // Asks the roots for their own NS records (RFC 8109), returning the
+    // addresses given for them; hints go stale as root servers move.
+    pub(crate) fn prime(&self, deadline: Instant) -> Result<Vec<IpAddr>, ResolveError> {
+        let mut budget = Budget::new(self.limits, deadline);
+        let mut servers = self.roots.clone();
+        let root = Name::from(".");
+        loop {
+            let (response, server) =
+                self.ask_any(&servers, &root, &root, DnsType::Ns, &mut budget)?;
+            let roots = hints::from_priming(&response);
+            if !roots.is_empty() {
+                self.reputation.answered(server.ip());
+                return Ok(roots);
+            }
+            self.reputation.failed(server.ip(), Failure::Lame);
+            servers.retain(|other| *other != server);
+            if servers.is_empty() {
+                return Err(ResolveError::ServFail);
+            }
+        }
+    }
+
+    // The root's DNSKEY records with their signatures, straight from a root
+    // server.
+    pub(crate) fn root_keys(&self, deadline: Instant) -> Result<Vec<DnsAnswer>, ResolveError> {
+        let mut budget = Budget::new(self.limits, deadline);
+        let root = Name::from(".");
+        let (response, server) =
+            self.ask_any(&self.roots, &root, &root, DnsType::Dnskey, &mut budget)?;
+        self.reputation.answered(server.ip());
+        Ok(response
+            .answers
+            .into_iter()
+            .filter(|record| record.name == root)
+            .collect())
+    }
+
+    // Answers the question, following CNAMEs, with the answer section
+    // holding the chain followed by the records of the requested type.
+    pub(crate) fn resolve(
+        &self,
+        question: &DnsQuestion,
+        deadline: Instant,
+    ) -> Result<DnsPacket, ResolveError> {
+        let mut budget = Budget::new(self.limits, deadline);
+        let mut chain = Vec::new();
+        let mut name = question.qname.clone();
+        loop {
+            let mut response = self.resolve_from_roots(&name, question.qtype, &mut budget)?;
+            let mut target = name.clone();
+            while let Some((record, next)) =
+                cname(&response.answers, &target).filter(|_| question.qtype != DnsType::Cname)
+            {
+                budget.cname()?;
+                chain.push(record);
+                target = next;
+            }
+
+            let records: Vec<DnsAnswer> = response
+                .answers
+                .iter()
+                .filter(|answer| {
+                    answer.name.eq_ignore_case(&target)
+                        && (answer.qtype == question.qtype || question.qtype == DnsType::Any)
+                })
+                .cloned()
+                .collect();
+            let answered = !records.is_empty() || response.header.rcode != ResponseCode::NoError;
+            if answered || target.eq_ignore_case(&name) {
+                chain.extend(records);
+                response.answers = chain;
+                response.additionals.clear();
+                return Ok(response);
+            }
+            // The CNAME leads out of what this server answered for.
+            name = target;
+        }
+    }
+
+    fn resolve_from_roots(
+        &self,
+        name: &Name,
+        qtype: DnsType,
+        budget: &mut Budget,
+    ) -> Result<DnsPacket, ResolveError> {
+        let mut zone = Name::from(".");
+        let mut servers = self.roots.clone();
+        loop {
+            let (mut response, server) = self.ask_any(&servers, &zone, name, qtype, budget)?;
+            let question = DnsQuestion {
+                qname: name.clone(),
+                qtype,
+                qclass: DnsClass::In,
+            };
+            if response.header.rcode != ResponseCode::NoError || !response.answers.is_empty() {
+                self.reputation.answered(server.ip());
+                sanitize::scrub(&question, &zone, &mut response);
+                return Ok(response);
+            }
+            let Some((cut, nameservers)) = referral(&response, name, &zone) else {
+                // No data, which only the zone's authority can say, or a
+                // referral that doesn't get closer. The others may know.
+                if !response.header.aa {
+                    self.reputation.failed(server.ip(), Failure::Lame);
+                    if servers.len() > 1 {
+                        servers.retain(|other| *other != server);
+                        continue;
+                    }
+                } else {
+                    self.reputation.answered(server.ip());
+                }
+                sanitize::scrub(&question, &zone, &mut response);
+                return Ok(response);
+            };
+            self.reputation.answered(server.ip());
+            budget.referral()?;
+            sanitize::referral(&zone, &cut, &mut response);
+            servers = self.addresses(&response, &nameservers, budget)?;
+            zone = cut;
+        }
+    }
+
+    // Addresses for the nameservers of a referral: the glue when there is
+    // some, otherwise a few of the names resolved from the roots.
+    fn addresses(
+        &self,
+        referral: &DnsPacket,
+        nameservers: &[Name],
+        budget: &mut Budget,
+    ) -> Result<Vec<SocketAddr>, ResolveError> {
+        let mut addresses: Vec<SocketAddr> = referral
+            .additionals
+            .iter()
+            .filter(|record| nameservers.iter().any(|ns| ns.eq_ignore_case(&record.name)))
+            .filter_map(|record| self.address(record))
+            .collect();
+        for nameserver in nameservers.iter().take(MAX_GLUELESS_LOOKUPS) {
+            if !addresses.is_empty() {
+                break;
+            }
+            match self.resolve_from_roots(nameserver, DnsType::A, budget) {
+                Ok(response) => {
+                    addresses.extend(response.answers.iter().filter_map(|a| self.address(a)))
+                }
+                Err(e @ (ResolveError::Deadline | ResolveError::LimitExceeded(_))) => {
+                    return Err(e)
+                }
+                Err(_) => continue,
+            }
+        }
+        if addresses.is_empty() {
+            return Err(ResolveError::ServFail);
+        }
+        // IPv4 first, since IPv6 may not be routable from here.
+        addresses.sort_by_key(|addr| addr.is_ipv6());
+        Ok(addresses)
+    }
+
+    fn address(&self, record: &DnsAnswer) -> Option<SocketAddr> {
+        match record.rdata {
+            RData::A(ip) => Some(SocketAddr::new(Ipv4Addr::from(ip).into(), self.port)),
+            RData::Aaaa(ip) => Some(SocketAddr::new(Ipv6Addr::from(ip).into(), self.port)),
+            _ => None,
+        }
+    }
+
+    // Tries the servers in a random order until one answers, returning the
+    // response and the server that gave it. Servers backing off after
+    // failures are left out, unless that leaves none.
+    fn ask_any(
+        &self,
+        servers: &[SocketAddr],
+        zone: &Name,
+        name: &Name,
+        qtype: DnsType,
+        budget: &mut Budget,
+    ) -> Result<(DnsPacket, SocketAddr), ResolveError> {
+        let usable: Vec<SocketAddr> = servers
+            .iter()
+            .copied()
+            .filter(|server| self.reputation.usable(server.ip()))
+            .collect();
+        let servers = match usable.is_empty() {
+            true => servers,
+            false => &usable,
+        };
+        let start = rand::thread_rng().gen_range(0..servers.len().max(1));
+        let mut last_error = ResolveError::NoServers;
+        for server in servers[start..].iter().chain(&servers[..start]) {
+            budget.query()?;
+            let mut query = DnsPacket::query(
+                rand::thread_rng().gen(),
+                DnsQuestion {
+                    qname: name.clone(),
+                    qtype,
+                    qclass: DnsClass::In,
+                },
+            );
+            query.header.rd = false;
+            let mut edns = Edns::new(self.udp_size);
+            // Keys are only asked for to be checked, which takes signatures.
+            edns.dnssec_ok = self.dnssec_ok || qtype == DnsType::Dnskey;
+            query.edns = Some(edns);
+            let sent = Instant::now();
+            let result = Resolver::new(vec![*server])
+                .with_attempts(1)
+                .with_timeout(SERVER_TIMEOUT)
+                .with_deadline(budget.deadline)
+                .send(&query, None);
+            if let Some(tracer) = &self.tracer {
+                tracer(&Step {
+                    zone,
+                    server: *server,
+                    name,
+                    qtype,
+                    rtt: sent.elapsed(),
+                    result: result.as_ref(),
+                });
+            }
+            let failure = match result {
+                Ok(response) => match response.header.rcode {
+                    ResponseCode::ServFail => {
+                        last_error = ResolveError::ServFail;
+                        Failure::ServFail
+                    }
+                    ResponseCode::Refused => {
+                        last_error = ResolveError::Rcode(ResponseCode::Refused as u8);
+                        Failure::Lame
+                    }
+                    _ => return Ok((response, *server)),
+                },
+                Err(ResolveError::Deadline) => return Err(ResolveError::Deadline),
+                Err(e) => {
+                    let failure = match e {
+                        ResolveError::Parse(_) => Failure::ServFail,
+                        _ => Failure::Timeout,
+                    };
+                    last_error = e;
+                    failure
+                }
+            };
+            self.reputation.failed(server.ip(), failure);
+        }
+        Err(last_error)
+    }
+}
+
+fn cname(answers: &[DnsAnswer], owner: &Name) -> Option<(DnsAnswer, Name)> {
+    answers.iter().find_map(|answer| match &answer.rdata {
+        RData::Cname(next) if answer.name.eq_ignore_case(owner) => {
+            Some((answer.clone(), next.clone()))
+        }
+        _ => None,
+    })
+}
+
+// The zone cut and nameserver names of a referral towards `name`, as long
+// as it delegates to a zone below the one that was asked.
+fn referral(response: &DnsPacket, name: &Name, zone: &Name) -> Option<(Name, Vec<Name>)> {
+    let mut cut: Option<&Name> = None;
+    let mut nameservers = Vec::new();
+    for record in &response.authorities {
+        let RData::Ns(nameserver) = &record.rdata else {
+            continue;
+        };
+        let owner = &record.name;
+        if !name.is_subdomain_of(owner)
+            || !owner.is_subdomain_of(zone)
+            || owner.eq_ignore_case(zone)
+        {
+            continue;
+        }
+        match cut {
+            Some(cut) if !cut.eq_ignore_case(owner) => continue,
+            _ => cut = Some(owner),
+        }
+        nameservers.push(nameserver.clone());
+    }
+    cut.map(|cut| (cut.clone(), nameservers))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn question(name: &str, qtype: DnsType) -> DnsQuestion {
+        DnsQuestion {
+            qname: name.into(),
+            qtype,
+            qclass: DnsClass::In,
+        }
+    }
+
+    fn record(name: &str, qtype: DnsType, rdata: RData) -> DnsAnswer {
+        DnsAnswer::new(name.into(), qtype, DnsClass::In, 300, rdata)
+    }
+
+    // Serves `answer` on 127.0.0.<host>:port, counting the queries.
+    fn spawn_server(
+        host: u8,
+        port: u16,
+        answer: impl Fn(&DnsQuestion, &mut DnsPacket) + Send + 'static,
+    ) -> (u16, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind((Ipv4Addr::new(127, 0, 0, host), port)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&queries);
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((size, peer)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut response = DnsPacket::try_from(&buf[..size]).unwrap();
+                response.header.flip_qr();
+                let question = response.questions[0].clone();
+                answer(&question, &mut response);
+                socket.send_to(&response.to_bytes(), peer).unwrap();
+            }
+        });
+        (port, queries)
+    }
+
+    fn recursor(port: u16, limits: Limits) -> Recursor {
+        Recursor {
+            roots: vec![SocketAddr::from(([127, 0, 0, 1], port))],
+            port,
+            limits,
+            udp_size: 1232,
+            reputation: Arc::default(),
+            dnssec_ok: false,
+            tracer: None,
+        }
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    // Hosts other than 127.0.0.1 are only local on some systems.
+    fn loopback_hosts() -> bool {
+        UdpSocket::bind("127.0.0.2:0").is_ok()
+    }
+
+    #[test]
+    fn test_follows_referrals_and_cnames() {
+        if !loopback_hosts() {
+            return;
+        }
+        let (port, _) = spawn_server(1, 0, |_, response| {
+            response
+                .authorities
+                .push(record("com", DnsType::Ns, RData::Ns("a.gtld.test".into())));
+            response
+                .additionals
+                .push(record("a.gtld.test", DnsType::A, RData::A([127, 0, 0, 2])));
+        });
+        spawn_server(2, port, |_, response| {
+            response.authorities.push(record(
+                "example.com",
+                DnsType::Ns,
+                RData::Ns("ns1.example.com".into()),
+            ));
+            response.additionals.push(record(
+                "ns1.example.com",
+                DnsType::A,
+                RData::A([127, 0, 0, 3]),
+            ));
+        });
+        spawn_server(3, port, |question, response| {
+            response.header.aa = true;
+            match question.qname.as_str() {
+                "www.example.com" => response.answers.push(record(
+                    "www.example.com",
+                    DnsType::Cname,
+                    RData::Cname("web.example.com".into()),
+                )),
+                "web.example.com" => response.answers.push(record(
+                    "web.example.com",
+                    DnsType::A,
+                    RData::A([192, 0, 2, 80]),
+                )),
+                _ => response.header.rcode = ResponseCode::NxDomain,
+            }
+        });
+
+        let recursor = recursor(port, Limits::default());
+        let response = recursor
+            .resolve(&question("www.example.com", DnsType::A), deadline())
+            .unwrap();
+        let rdata: Vec<&RData> = response.answers.iter().map(|a| &a.rdata).collect();
+        assert_eq!(
+            rdata,
+            vec![
+                &RData::Cname("web.example.com".into()),
+                &RData::A([192, 0, 2, 80])
+            ]
+        );
+
+        let response = recursor
+            .resolve(&question("nope.example.com", DnsType::A), deadline())
+            .unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NxDomain);
+
+        // Every query is traced with the zone it was asked of; the CNAME
+        // target is resolved from the roots again.
+        let steps = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&steps);
+        let recursor = recursor.with_tracer(Arc::new(move |step: &Step| {
+            assert!(step.result.is_ok());
+            seen.lock().unwrap().push(format!(
+                "{}. {} {}.",
+                step.zone,
+                step.server.ip(),
+                step.name
+            ));
+        }));
+        recursor
+            .resolve(&question("www.example.com", DnsType::A), deadline())
+            .unwrap();
+        assert_eq!(
+            *steps.lock().unwrap(),
+            vec![
+                ". 127.0.0.1 www.example.com.",
+                "com. 127.0.0.2 www.example.com.",
+                "example.com. 127.0.0.3 www.example.com.",
+                ". 127.0.0.1 web.example.com.",
+                "com. 127.0.0.2 web.example.com.",
+                "example.com. 127.0.0.3 web.example.com.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignores_out_of_bailiwick_glue() {
+        if !loopback_hosts() {
+            return;
+        }
+        let tld = |qname: &Name| qname.as_str().rsplit('.').next().unwrap().to_string();
+        let (port, _) = spawn_server(1, 0, move |question, response| {
+            let tld = tld(&question.qname);
+            response
+                .authorities
+                .push(record(&tld, DnsType::Ns, RData::Ns("a.gtld.test".into())));
+            response
+                .additionals
+                .push(record("a.gtld.test", DnsType::A, RData::A([127, 0, 0, 2])));
+        });
+        // Serves com and net. Its glue for ns.example.net in a com referral
+        // is out of bailiwick, and points at an impostor.
+        spawn_server(2, port, move |question, response| {
+            let tld = tld(&question.qname);
+            let glue = match tld.as_str() {
+                "com" => [127, 0, 0, 4],
+                _ => [127, 0, 0, 3],
+            };
+            response.authorities.push(record(
+                &format!("example.{}", tld),
+                DnsType::Ns,
+                RData::Ns("ns.example.net".into()),
+            ));
+            response
+                .additionals
+                .push(record("ns.example.net", DnsType::A, RData::A(glue)));
+        });
+        spawn_server(3, port, |question, response| {
+            response.header.aa = true;
+            let rdata = match question.qname.as_str() {
+                "ns.example.net" => RData::A([127, 0, 0, 3]),
+                _ => RData::A([192, 0, 2, 80]),
+            };
+            response
+                .answers
+                .push(record(question.qname.as_str(), DnsType::A, rdata));
+        });
+        let (_, impostor) = spawn_server(4, port, |question, response| {
+            response.header.aa = true;
+            response.answers.push(record(
+                question.qname.as_str(),
+                DnsType::A,
+                RData::A([203, 0, 113, 66]),
+            ));
+        });
+
+        let response = recursor(port, Limits::default())
+            .resolve(&question("www.example.com", DnsType::A), deadline())
+            .unwrap();
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+        assert_eq!(impostor.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_avoids_lame_servers() {
+        if !loopback_hosts() {
+            return;
+        }
+        let (port, _) = spawn_server(1, 0, |_, response| {
+            for (ns, host) in [("ns1.example.com", 3), ("ns2.example.com", 4)] {
+                response
+                    .authorities
+                    .push(record("example.com", DnsType::Ns, RData::Ns(ns.into())));
+                response
+                    .additionals
+                    .push(record(ns, DnsType::A, RData::A([127, 0, 0, host])));
+            }
+        });
+        // Delegated example.com, but knows nothing of it.
+        let (_, lame) = spawn_server(3, port, |_, _| {});
+        spawn_server(4, port, |question, response| {
+            response.header.aa = true;
+            response.answers.push(record(
+                question.qname.as_str(),
+                DnsType::A,
+                RData::A([192, 0, 2, 80]),
+            ));
+        });
+
+        let recursor = recursor(port, Limits::default());
+        for _ in 0..5 {
+            let response = recursor
+                .resolve(&question("www.example.com", DnsType::A), deadline())
+                .unwrap();
+            assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+        }
+        assert!(lame.load(Ordering::SeqCst) <= 1);
+        let report = recursor.reputation.report();
+        assert!(report.contains("nameserver.127.0.0.4.answers=5\n"));
+    }
+
+    #[test]
+    fn test_prime() {
+        let (port, _) = spawn_server(1, 0, |question, response| {
+            assert_eq!(question.qname, Name::from("."));
+            assert_eq!(question.qtype, DnsType::Ns);
+            response.header.aa = true;
+            response
+                .answers
+                .push(record(".", DnsType::Ns, RData::Ns("a.root.test".into())));
+            response
+                .additionals
+                .push(record("a.root.test", DnsType::A, RData::A([127, 0, 0, 9])));
+            response.additionals.push(record(
+                "www.example.com",
+                DnsType::A,
+                RData::A([192, 0, 2, 80]),
+            ));
+        });
+        let roots = recursor(port, Limits::default()).prime(deadline()).unwrap();
+        assert_eq!(roots, vec![IpAddr::from([127, 0, 0, 9])]);
+
+        // A server that doesn't answer for the root.
+        let (port, _) = spawn_server(1, 0, |_, response| response.header.aa = true);
+        assert!(matches!(
+            recursor(port, Limits::default()).prime(deadline()),
+            Err(ResolveError::ServFail)
+        ));
+    }
+
+    #[test]
+    fn test_root_keys() {
+        let (port, _) = spawn_server(1, 0, |question, response| {
+            assert_eq!(question.qtype, DnsType::Dnskey);
+            assert!(response.edns.as_ref().is_some_and(|edns| edns.dnssec_ok));
+            response.header.aa = true;
+            for qtype in [DnsType::Dnskey, DnsType::Rrsig] {
+                response
+                    .answers
+                    .push(record(".", qtype, RData::Unknown(vec![1, 1, 3, 8])));
+            }
+            response
+                .answers
+                .push(record("com", DnsType::Ds, RData::Unknown(vec![0; 4])));
+        });
+        let records = recursor(port, Limits::default())
+            .root_keys(deadline())
+            .unwrap();
+        let types: Vec<DnsType> = records.iter().map(|record| record.qtype).collect();
+        assert_eq!(types, vec![DnsType::Dnskey, DnsType::Rrsig]);
+    }
+
+    #[test]
+    fn test_referral_limit() {
+        // Delegates one label deeper on every query, back to itself.
+        let asked = Arc::new(AtomicUsize::new(0));
+        let depth = Arc::clone(&asked);
+        let (port, _) = spawn_server(1, 0, move |question, response| {
+            let labels: Vec<&str> = question.qname.as_str().split('.').collect();
+            let n = depth.fetch_add(1, Ordering::SeqCst) + 1;
+            let cut = labels[labels.len() - n.min(labels.len())..].join(".");
+            response
+                .authorities
+                .push(record(&cut, DnsType::Ns, RData::Ns("ns.loop.test".into())));
+            response
+                .additionals
+                .push(record("ns.loop.test", DnsType::A, RData::A([127, 0, 0, 1])));
+        });
+        let limits = Limits {
+            referrals: 3,
+            ..Limits::default()
+        };
+        let result = recursor(port, limits)
+            .resolve(&question("a.b.c.d.e.f.g.h.test", DnsType::A), deadline());
+        assert!(matches!(
+            result,
+            Err(ResolveError::LimitExceeded("referrals"))
+        ));
+    }
+
+    #[test]
+    fn test_cname_limit() {
+        // Every name is a CNAME to a longer one.
+        let (port, _) = spawn_server(1, 0, |question, response| {
+            let name = question.qname.as_str();
+            response.answers.push(record(
+                name,
+                DnsType::Cname,
+                RData::Cname(format!("x.{}", name).as_str().into()),
+            ));
+        });
+        let result = recursor(port, Limits::default())
+            .resolve(&question("loop.test", DnsType::A), deadline());
+        assert!(matches!(
+            result,
+            Err(ResolveError::LimitExceeded("CNAME chain"))
+        ));
+    }
+
+    #[test]
+    fn test_glueless_delegation_is_bounded() {
+        // NXNSAttack: a referral to many nameservers without glue, each of
+        // which leads to another such referral.
+        let (port, queries) = spawn_server(1, 0, |question, response| {
+            let zone = question.qname.as_str().to_string();
+            for i in 0..10 {
+                response.authorities.push(record(
+                    &zone,
+                    DnsType::Ns,
+                    RData::Ns(format!("ns{}.{}", i, zone).as_str().into()),
+                ));
+            }
+        });
+        let result = recursor(port, Limits::default())
+            .resolve(&question("victim.test", DnsType::A), deadline());
+        assert!(matches!(
+            result,
+            Err(ResolveError::LimitExceeded(_) | ResolveError::ServFail)
+        ));
+        assert!(queries.load(Ordering::SeqCst) <= Limits::default().queries);
+    }
+}
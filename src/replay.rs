@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::error::ParseError;
+use crate::header::{PacketType, ResponseCode};
+use crate::packet::DnsPacket;
+use crate::pcap::{self, Capture};
+
+const USAGE: &str = "usage: dns-server replay <file.pcap> [--target <addr:port>] [--timeout <ms>]";
+
+#[derive(Default)]
+struct Check {
+    queries: usize,
+    responses: usize,
+    failures: Vec<(usize, ParseError)>,
+}
+
+#[derive(Default)]
+struct Replay {
+    sent: usize,
+    answered: usize,
+    rcodes: BTreeMap<u8, usize>,
+}
+
+// `dns-server replay <file.pcap>` runs every DNS message in a capture
+// through the parser and reports the ones that fail. With --target the
+// queries are also replayed, one at a time, against a server.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let mut path = None;
+    let mut target: Option<SocketAddr> = None;
+    let mut timeout = Duration::from_secs(1);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--target" => args
+                .next()
+                .and_then(|v| v.parse().ok())
+                .map(|v| target = Some(v)),
+            "--timeout" => args
+                .next()
+                .and_then(|v| v.parse().ok())
+                .map(|v| timeout = Duration::from_millis(v)),
+            _ if path.is_none() && !arg.starts_with('-') => {
+                path = Some(arg);
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let summary = match fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| pcap::read(&data).map_err(|e| e.to_string()))
+    {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 2;
+        }
+    };
+
+    let check = check(&summary.captures);
+    for (packet, error) in &check.failures {
+        println!("packet {}: {}", packet, error);
+    }
+    println!(
+        "{} packets, {} DNS messages ({} queries, {} responses), {} parse failures, {} other packets skipped",
+        summary.packets,
+        summary.captures.len(),
+        check.queries,
+        check.responses,
+        check.failures.len(),
+        summary.skipped
+    );
+
+    if let Some(target) = target {
+        match replay(&summary.captures, target, timeout) {
+            Ok(replay) => print!("{}", replay),
+            Err(e) => {
+                eprintln!("replay failed: {}", e);
+                return 1;
+            }
+        }
+    }
+
+    if check.failures.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+fn check(captures: &[Capture]) -> Check {
+    let mut check = Check::default();
+    for capture in captures {
+        match DnsPacket::try_from(capture.message.as_slice()) {
+            Ok(packet) if packet.header.qr == PacketType::Query => check.queries += 1,
+            Ok(_) => check.responses += 1,
+            Err(e) => check.failures.push((capture.packet, e)),
+        }
+    }
+    check
+}
+
+fn replay(captures: &[Capture], target: SocketAddr, timeout: Duration) -> io::Result<Replay> {
+    let socket = UdpSocket::bind(match target {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })?;
+    socket.connect(target)?;
+
+    let mut replay = Replay::default();
+    let mut buf = [0; 4096];
+    for capture in captures {
+        let Ok(query) = DnsPacket::try_from(capture.message.as_slice()) else {
+            continue;
+        };
+        if query.header.qr != PacketType::Query {
+            continue;
+        }
+        socket.send(&capture.message)?;
+        replay.sent += 1;
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+            let Ok(len) = socket.recv(&mut buf) else {
+                break;
+            };
+            // Late answers to earlier queries are skipped.
+            if len >= 12 && buf[..2] == capture.message[..2] {
+                replay.answered += 1;
+                *replay.rcodes.entry(buf[3] & 0x0F).or_default() += 1;
+                break;
+            }
+        }
+    }
+    Ok(replay)
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "replayed {} queries:", self.sent)?;
+        writeln!(f, "  answered:  {}", self.answered)?;
+        writeln!(f, "  timed out: {}", self.sent - self.answered)?;
+        for (rcode, count) in &self.rcodes {
+            let name = ResponseCode::try_from(*rcode)
+                .map(|rcode| rcode.to_string())
+                .unwrap_or_else(|_| format!("RCODE{}", rcode));
+            writeln!(f, "  {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{DnsClass, DnsType};
+    use crate::pcap::test::{pcap, udp_frame};
+    use crate::question::DnsQuestion;
+    use std::thread;
+
+    fn query(id: u16) -> Vec<u8> {
+        DnsPacket::query(
+            id,
+            DnsQuestion {
+                qname: "example.com".into(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            },
+        )
+        .to_bytes()
+    }
+
+    fn captures() -> Vec<Capture> {
+        let mut truncated = query(3);
+        truncated.truncate(20);
+        let mut response = query(2);
+        response[2] |= 0x80;
+        let data = pcap(&[
+            udp_frame(5353, 53, &query(1)),
+            udp_frame(53, 5353, &response),
+            udp_frame(5353, 53, &truncated),
+        ]);
+        pcap::read(&data).unwrap().captures
+    }
+
+    #[test]
+    fn test_check() {
+        let check = check(&captures());
+        assert_eq!(check.queries, 1);
+        assert_eq!(check.responses, 1);
+        assert_eq!(check.failures, vec![(3, ParseError::UnexpectedEof)]);
+    }
+
+    #[test]
+    fn test_replay() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((len, peer)) = server.recv_from(&mut buf) {
+                buf[2] |= 0x80;
+                buf[3] = 3; // NXDOMAIN
+                let _ = server.send_to(&buf[..len], peer);
+            }
+        });
+
+        let replay = replay(&captures(), target, Duration::from_secs(1)).unwrap();
+        assert_eq!(replay.sent, 1);
+        assert_eq!(replay.answered, 1);
+        assert_eq!(replay.rcodes.get(&3), Some(&1));
+        assert!(replay.to_string().contains("NXDOMAIN: 1"));
+    }
+}
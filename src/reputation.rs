@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clock::SharedClock;
+
+const MAX_TRACKED: usize = 10_000;
+// Backoff after the first failure in a row, doubling with each one after,
+// up to the maximum (Unbound's infra-host-ttl).
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(900);
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Failure {
+    // No response, or the connection failed.
+    Timeout,
+    // SERVFAIL, or a response that doesn't parse.
+    ServFail,
+    // REFUSED, or a response that neither answers nor refers onwards: the
+    // server isn't authoritative for the zone it was delegated.
+    Lame,
+}
+
+// How authoritative nameservers have been behaving, so the recursor stops
+// asking ones that keep failing. Each failure in a row puts a server out of
+// use for twice as long as the last; an answer clears that.
+#[derive(Default)]
+pub(crate) struct Reputation {
+    servers: Mutex<HashMap<IpAddr, Record>>,
+    clock: SharedClock,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Record {
+    answers: u64,
+    timeouts: u64,
+    servfails: u64,
+    lame: u64,
+    failures_in_a_row: u32,
+    backoff_until: Option<Instant>,
+}
+
+impl Reputation {
+    #[cfg(test)]
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Reputation {
+            clock,
+            ..Reputation::default()
+        }
+    }
+
+    pub(crate) fn answered(&self, server: IpAddr) {
+        let mut servers = self.servers.lock().unwrap();
+        if let Some(record) = tracked(&mut servers, server) {
+            record.answers += 1;
+            record.failures_in_a_row = 0;
+            record.backoff_until = None;
+        }
+    }
+
+    pub(crate) fn failed(&self, server: IpAddr, failure: Failure) {
+        let mut servers = self.servers.lock().unwrap();
+        let Some(record) = tracked(&mut servers, server) else {
+            return;
+        };
+        match failure {
+            Failure::Timeout => record.timeouts += 1,
+            Failure::ServFail => record.servfails += 1,
+            Failure::Lame => record.lame += 1,
+        }
+        let backoff = BASE_BACKOFF * 2u32.saturating_pow(record.failures_in_a_row);
+        record.failures_in_a_row = record.failures_in_a_row.saturating_add(1);
+        record.backoff_until = Some(self.clock.now() + backoff.min(MAX_BACKOFF));
+    }
+
+    // Whether the server is out of its backoff, if it has one.
+    pub(crate) fn usable(&self, server: IpAddr) -> bool {
+        let servers = self.servers.lock().unwrap();
+        let until = servers.get(&server).and_then(|record| record.backoff_until);
+        !matches!(until, Some(until) if until > self.clock.now())
+    }
+
+    // One key=value line per counter, as in `ctl stats`, with the seconds
+    // of backoff left.
+    pub(crate) fn report(&self) -> String {
+        let servers = self.servers.lock().unwrap();
+        let mut addrs: Vec<&IpAddr> = servers.keys().collect();
+        addrs.sort();
+        let now = self.clock.now();
+        let mut report = String::new();
+        for addr in addrs {
+            let record = &servers[addr];
+            let backoff = record
+                .backoff_until
+                .map(|until| until.saturating_duration_since(now).as_secs())
+                .unwrap_or(0);
+            for (key, value) in [
+                ("answers", record.answers),
+                ("timeouts", record.timeouts),
+                ("servfail", record.servfails),
+                ("lame", record.lame),
+                ("backoff", backoff),
+            ] {
+                writeln!(report, "nameserver.{}.{}={}", addr, key, value).unwrap();
+            }
+        }
+        report
+    }
+}
+
+// The server's record, unless the table is full without it.
+fn tracked(servers: &mut HashMap<IpAddr, Record>, server: IpAddr) -> Option<&mut Record> {
+    if servers.len() >= MAX_TRACKED && !servers.contains_key(&server) {
+        return None;
+    }
+    Some(servers.entry(server).or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_backoff() {
+        let clock = ManualClock::new();
+        let reputation = Reputation::with_clock(clock.shared());
+        let server: IpAddr = "192.0.2.53".parse().unwrap();
+        assert!(reputation.usable(server));
+
+        reputation.failed(server, Failure::Timeout);
+        assert!(!reputation.usable(server));
+        reputation.failed(server, Failure::Lame);
+        reputation.failed(server, Failure::ServFail);
+        // 2s, 4s then 8s.
+        let report = reputation.report();
+        assert!(report.contains("nameserver.192.0.2.53.timeouts=1\n"));
+        assert!(report.contains("nameserver.192.0.2.53.lame=1\n"));
+        assert!(report.contains("nameserver.192.0.2.53.servfail=1\n"));
+        assert!(report.contains("nameserver.192.0.2.53.backoff=8\n"));
+        clock.advance(Duration::from_secs(7));
+        assert!(!reputation.usable(server));
+        clock.advance(Duration::from_secs(1));
+        assert!(reputation.usable(server));
+
+        for _ in 0..20 {
+            reputation.failed(server, Failure::Timeout);
+        }
+        assert!(reputation
+            .report()
+            .contains("nameserver.192.0.2.53.backoff=900\n"));
+
+        reputation.answered(server);
+        assert!(reputation.usable(server));
+        assert!(reputation
+            .report()
+            .contains("nameserver.192.0.2.53.backoff=0\n"));
+    }
+}
@@ -0,0 +1,697 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::answer::RData;
+use crate::common::{DnsClass, DnsType};
+use crate::edns::Edns;
+use crate::error::ResolveError;
+use crate::eyeballs;
+use crate::header::{PacketType, ResponseCode};
+use crate::packet::DnsPacket;
+use crate::pool::Pool;
+use crate::question::DnsQuestion;
+use crate::sig0::Sig0Key;
+use crate::tsig::{self, TsigKey};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_ATTEMPTS: usize = 2;
+const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+const MIN_UDP_PAYLOAD_SIZE: u16 = 512;
+
+/// A stub resolver that sends queries to a fixed list of nameservers.
+///
+/// ```no_run
+/// use dns_starter_rust::Resolver;
+///
+/// let resolver = Resolver::new(vec!["8.8.8.8:53".parse().unwrap()]);
+/// for ip in resolver.lookup_ip("example.com").unwrap() {
+///     println!("{}", ip);
+/// }
+/// ```
+pub struct Resolver {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    attempts: usize,
+    tcp: bool,
+    source: Option<IpAddr>,
+    deadline: Option<Instant>,
+    pool: Option<Arc<Pool>>,
+    sig0: Option<Sig0Key>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+}
+
+impl Resolver {
+    pub fn new(servers: Vec<SocketAddr>) -> Self {
+        Resolver {
+            servers,
+            timeout: DEFAULT_TIMEOUT,
+            attempts: DEFAULT_ATTEMPTS,
+            tcp: false,
+            source: None,
+            deadline: None,
+            pool: None,
+            sig0: None,
+        }
+    }
+
+    /// Uses the `nameserver` entries of `/etc/resolv.conf`.
+    pub fn from_system_conf() -> io::Result<Self> {
+        let conf = fs::read_to_string("/etc/resolv.conf")?;
+        Ok(Resolver::new(parse_resolv_conf(&conf)))
+    }
+
+    pub(crate) fn system_servers() -> Vec<SocketAddr> {
+        fs::read_to_string("/etc/resolv.conf")
+            .map(|conf| parse_resolv_conf(&conf))
+            .unwrap_or_default()
+    }
+
+    /// How long to wait for each individual response.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times to go through the server list before giving up.
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Send every query over TCP instead of trying UDP first.
+    pub fn with_tcp(mut self, tcp: bool) -> Self {
+        self.tcp = tcp;
+        self
+    }
+
+    /// Send UDP queries from this local address, e.g. one on a VPN tunnel.
+    /// TCP connections still use whichever address the OS picks.
+    pub fn with_source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    // Caps the whole lookup, across servers and retries, at this instant.
+    pub(crate) fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // Sends TCP queries over the pool's connections, leaving them open for
+    // the next query, instead of connecting for each.
+    pub(crate) fn with_pool(mut self, pool: &Arc<Pool>) -> Self {
+        self.pool = Some(Arc::clone(pool));
+        self
+    }
+
+    // Signs messages sent without a TSIG key with SIG(0) instead.
+    pub(crate) fn with_sig0(mut self, key: Sig0Key) -> Self {
+        self.sig0 = Some(key);
+        self
+    }
+
+    pub fn lookup_ip(&self, name: &str) -> Result<Vec<IpAddr>, ResolveError> {
+        let mut ips = Vec::new();
+        for record in self.lookup(name, DnsType::A)? {
+            if let RData::A(ip) = record {
+                ips.push(IpAddr::V4(Ipv4Addr::from(ip)));
+            }
+        }
+        for record in self.lookup(name, DnsType::Aaaa)? {
+            if let RData::Aaaa(ip) = record {
+                ips.push(IpAddr::V6(Ipv6Addr::from(ip)));
+            }
+        }
+        Ok(ips)
+    }
+
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, ResolveError> {
+        let mut records = Vec::new();
+        for record in self.lookup(name, DnsType::Srv)? {
+            if let RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } = record
+            {
+                records.push(SrvRecord {
+                    priority,
+                    weight,
+                    port,
+                    target: target.to_string(),
+                });
+            }
+        }
+        records.sort_by_key(|srv| (srv.priority, u16::MAX - srv.weight));
+        Ok(records)
+    }
+
+    /// Each TXT record is returned as the concatenation of its strings.
+    pub fn lookup_txt(&self, name: &str) -> Result<Vec<String>, ResolveError> {
+        let mut records = Vec::new();
+        for record in self.lookup(name, DnsType::Txt)? {
+            if let RData::Txt(strings) = record {
+                records.push(String::from_utf8_lossy(&strings.concat()).into_owned());
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn reverse_lookup(&self, ip: IpAddr) -> Result<Vec<String>, ResolveError> {
+        let mut names = Vec::new();
+        for record in self.lookup(&reverse_name(ip), DnsType::Ptr)? {
+            if let RData::Ptr(name) = record {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    // Returns the records of the requested type from the answer section,
+    // including those reached through a CNAME chain.
+    fn lookup(&self, name: &str, qtype: DnsType) -> Result<Vec<RData>, ResolveError> {
+        let response = self.query(name, qtype, DnsClass::In)?;
+        match response.header.rcode {
+            ResponseCode::NoError => {}
+            ResponseCode::NxDomain => return Err(ResolveError::NxDomain),
+            ResponseCode::ServFail => return Err(ResolveError::ServFail),
+            rcode => return Err(ResolveError::Rcode(rcode as u8)),
+        }
+        Ok(response
+            .answers
+            .into_iter()
+            .filter(|answer| answer.qtype == qtype)
+            .map(|answer| answer.rdata)
+            .collect())
+    }
+
+    pub(crate) fn query(
+        &self,
+        name: &str,
+        qtype: DnsType,
+        qclass: DnsClass,
+    ) -> Result<DnsPacket, ResolveError> {
+        let mut query = DnsPacket::query(
+            rand::thread_rng().gen(),
+            DnsQuestion {
+                qname: name.into(),
+                qtype,
+                qclass,
+            },
+        );
+        query.edns = Some(Edns::new(EDNS_UDP_PAYLOAD_SIZE));
+        self.send(&query, None)
+    }
+
+    // Sends the message to each server in turn, retrying over TCP when a UDP
+    // response comes back truncated. With a key the message is TSIG-signed
+    // and only a response with a valid signature is accepted. Without one,
+    // a SIG(0) key signs it, but responses go unchecked: nothing here knows
+    // the server's public key.
+    pub(crate) fn send(
+        &self,
+        query: &DnsPacket,
+        key: Option<&TsigKey>,
+    ) -> Result<DnsPacket, ResolveError> {
+        if self.servers.is_empty() {
+            return Err(ResolveError::NoServers);
+        }
+
+        // Silence after advertising a large buffer may be a path dropping
+        // the fragments of a large response, so later attempts at a server
+        // that timed out advertise the minimum instead. The answer then
+        // fits, or comes back with TC and is retried over TCP (DNS Flag Day
+        // 2020).
+        let smaller = fallback(query).filter(|_| !self.tcp);
+        let mut timed_out = vec![false; self.servers.len()];
+        let mut last_error = ResolveError::Timeout;
+        for _ in 0..self.attempts {
+            for (server, timed_out) in self.servers.iter().zip(&mut timed_out) {
+                let query = match &smaller {
+                    Some(smaller) if *timed_out => smaller,
+                    _ => query,
+                };
+                match self.exchange(query, key, *server) {
+                    Ok(response) => return Ok(response),
+                    Err(ResolveError::Deadline) => return Err(ResolveError::Deadline),
+                    Err(e) => {
+                        *timed_out |= matches!(e, ResolveError::Timeout);
+                        last_error = e;
+                    }
+                }
+            }
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(ResolveError::Deadline);
+        }
+        Err(last_error)
+    }
+
+    // One query to one server: over UDP unless the response is truncated
+    // or `tcp` is set.
+    fn exchange(
+        &self,
+        query: &DnsPacket,
+        key: Option<&TsigKey>,
+        server: SocketAddr,
+    ) -> Result<DnsPacket, ResolveError> {
+        let timeout = match self.deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => remaining.min(self.timeout),
+                _ => return Err(ResolveError::Deadline),
+            },
+            None => self.timeout,
+        };
+        let (message, mac) = match (key, &self.sig0) {
+            (Some(key), _) => {
+                let (message, mac) = key.sign(&query.to_bytes(), None, tsig::now());
+                (message, Some(mac))
+            }
+            (None, Some(sig0)) => (sig0.sign(&query.to_bytes(), tsig::now()), None),
+            (None, None) => (query.to_bytes(), None),
+        };
+
+        let result = if self.tcp {
+            self.query_tcp(query, &message, server, timeout)
+        } else {
+            self.query_udp(query, &message, server, timeout)
+                .and_then(|(response, raw)| {
+                    if response.header.tc {
+                        self.query_tcp(query, &message, server, timeout)
+                    } else {
+                        Ok((response, raw))
+                    }
+                })
+        };
+        let (response, raw) = result?;
+        if let Some(key) = key {
+            key.verify(&raw, mac.as_deref(), tsig::now())?;
+        }
+        Ok(response)
+    }
+
+    fn query_udp(
+        &self,
+        query: &DnsPacket,
+        message: &[u8],
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> Result<(DnsPacket, Vec<u8>), ResolveError> {
+        let local: SocketAddr = match (self.source, server) {
+            (Some(source), _) => (source, 0).into(),
+            (None, SocketAddr::V4(_)) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            (None, SocketAddr::V6(_)) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.send_to(message, server)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0; 4096];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(ResolveError::Timeout);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let (size, source) = socket.recv_from(&mut buf).map_err(timeout_error)?;
+            // Anything that doesn't match our query is ignored rather than
+            // trusted, so a spoofed or stale packet can't end the wait.
+            if source != server {
+                continue;
+            }
+            let Ok(response) = DnsPacket::try_from(&buf[..size]) else {
+                continue;
+            };
+            if is_response_to(&response, query) {
+                return Ok((response, buf[..size].to_vec()));
+            }
+        }
+    }
+
+    // The server, then a server of the other address family to race it
+    // with, if there is one: the servers share the same data, so whichever
+    // connects first will do.
+    fn race(&self, server: SocketAddr) -> Vec<SocketAddr> {
+        let other = self
+            .servers
+            .iter()
+            .find(|other| other.is_ipv4() != server.is_ipv4());
+        std::iter::once(server).chain(other.copied()).collect()
+    }
+
+    fn query_tcp(
+        &self,
+        query: &DnsPacket,
+        message: &[u8],
+        server: SocketAddr,
+        timeout: Duration,
+    ) -> Result<(DnsPacket, Vec<u8>), ResolveError> {
+        if let Some(pool) = &self.pool {
+            return query_pooled(pool, query, message, server, timeout);
+        }
+        let mut stream = eyeballs::connect(&self.race(server), timeout).map_err(timeout_error)?;
+        exchange_tcp(&mut stream, query, message, timeout)
+    }
+}
+
+// A reused connection may have been closed by the server just as it was
+// taken, which fails at once rather than timing out; the query is then
+// tried once more on a new connection.
+fn query_pooled(
+    pool: &Pool,
+    query: &DnsPacket,
+    message: &[u8],
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<(DnsPacket, Vec<u8>), ResolveError> {
+    loop {
+        let mut connection = pool.take(server, timeout).map_err(timeout_error)?;
+        match exchange_tcp(connection.stream(), query, message, timeout) {
+            Ok(response) => {
+                connection.release();
+                return Ok(response);
+            }
+            Err(ResolveError::Io(_)) if connection.reused => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn exchange_tcp(
+    stream: &mut TcpStream,
+    query: &DnsPacket,
+    message: &[u8],
+    timeout: Duration,
+) -> Result<(DnsPacket, Vec<u8>), ResolveError> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).map_err(timeout_error)?;
+
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).map_err(timeout_error)?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut buf).map_err(timeout_error)?;
+
+    let response = DnsPacket::try_from(buf.as_slice())?;
+    if !is_response_to(&response, query) {
+        return Err(ResolveError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "response does not match the query",
+        )));
+    }
+    Ok((response, buf))
+}
+
+// The query advertising the minimum UDP payload size, if it asked for more.
+fn fallback(query: &DnsPacket) -> Option<DnsPacket> {
+    let edns = query.edns.as_ref()?;
+    if edns.udp_payload_size <= MIN_UDP_PAYLOAD_SIZE {
+        return None;
+    }
+    let mut query = query.clone();
+    if let Some(edns) = &mut query.edns {
+        edns.udp_payload_size = MIN_UDP_PAYLOAD_SIZE;
+    }
+    Some(query)
+}
+
+fn is_response_to(response: &DnsPacket, query: &DnsPacket) -> bool {
+    if response.header.id != query.header.id || response.header.qr != PacketType::Response {
+        return false;
+    }
+    match (response.questions.first(), query.questions.first()) {
+        (Some(r), Some(q)) => {
+            r.qname.as_str().eq_ignore_ascii_case(q.qname.as_str())
+                && r.qtype == q.qtype
+                && r.qclass == q.qclass
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn timeout_error(e: io::Error) -> ResolveError {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ResolveError::Timeout,
+        _ => ResolveError::Io(e),
+    }
+}
+
+pub(crate) fn parse_resolv_conf(conf: &str) -> Vec<SocketAddr> {
+    conf.lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        // Drop IPv6 zone ids such as fe80::1%eth0, which IpAddr can't parse.
+        .filter_map(|addr| addr.split('%').next()?.parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect()
+}
+
+pub(crate) fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::DnsAnswer;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name("192.0.2.10".parse().unwrap()),
+            "10.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name("2001:db8::567:89ab".parse().unwrap()),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_parse_resolv_conf() {
+        let conf = "# generated\nsearch example.com\nnameserver 10.0.0.1\nnameserver fe80::1%eth0\noptions ndots:2\n";
+        assert_eq!(
+            parse_resolv_conf(conf),
+            vec![
+                "10.0.0.1:53".parse().unwrap(),
+                "[fe80::1]:53".parse().unwrap()
+            ]
+        );
+    }
+
+    // Answers every query with a single A record; `truncate` makes the UDP
+    // side set TC so the client has to retry over TCP.
+    fn respond(query: &[u8], truncate: bool) -> Vec<u8> {
+        let mut packet = DnsPacket::try_from(query).unwrap();
+        packet.header.flip_qr();
+        packet.edns = None;
+        if truncate {
+            packet.header.tc = true;
+        } else if packet.questions[0].qtype == DnsType::A {
+            let name = packet.questions[0].qname.clone();
+            packet.add_answer(DnsAnswer::new(
+                name,
+                DnsType::A,
+                DnsClass::In,
+                60,
+                RData::A([192, 0, 2, 1]),
+            ));
+        }
+        packet.to_bytes()
+    }
+
+    fn spawn_server(truncate: bool) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (size, source) = udp.recv_from(&mut buf).unwrap();
+                udp.send_to(&respond(&buf[..size], truncate), source)
+                    .unwrap();
+            }
+        });
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                let mut stream = stream.unwrap();
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut query).unwrap();
+                let response = respond(&query, false);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_lookup_ip_over_udp() {
+        let resolver = Resolver::new(vec![spawn_server(false)]);
+        let ips = resolver.lookup_ip("example.com").unwrap();
+        assert_eq!(ips, vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+    }
+
+    #[test]
+    fn test_truncated_response_retries_over_tcp() {
+        let resolver = Resolver::new(vec![spawn_server(true)]);
+        let ips = resolver.lookup_ip("example.com").unwrap();
+        assert_eq!(ips, vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+    }
+
+    #[test]
+    fn test_pooled_tcp() {
+        // Answers any number of queries on each connection.
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&accepted);
+        thread::spawn(move || {
+            for stream in tcp.incoming() {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let mut stream = stream.unwrap();
+                let mut len = [0; 2];
+                while stream.read_exact(&mut len).is_ok() {
+                    let mut query = vec![0; u16::from_be_bytes(len) as usize];
+                    stream.read_exact(&mut query).unwrap();
+                    let response = respond(&query, false);
+                    stream
+                        .write_all(&(response.len() as u16).to_be_bytes())
+                        .unwrap();
+                    stream.write_all(&response).unwrap();
+                }
+            }
+        });
+        let pool = Arc::new(Pool::default());
+        for _ in 0..3 {
+            let resolver = Resolver::new(vec![addr]).with_tcp(true).with_pool(&pool);
+            assert_eq!(resolver.lookup_ip("example.com").unwrap().len(), 1);
+        }
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // A server that closes after each answer still gets every query.
+        let resolver = Resolver::new(vec![spawn_server(true)]).with_pool(&pool);
+        for _ in 0..3 {
+            assert_eq!(resolver.lookup_ip("example.com").unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        // Bound but never answered.
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let resolver = Resolver::new(vec![silent.local_addr().unwrap()])
+            .with_timeout(Duration::from_millis(50))
+            .with_attempts(2);
+        assert!(matches!(
+            resolver.lookup_ip("example.com"),
+            Err(ResolveError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_payload_size_fallback() {
+        // Drops queries advertising more than 512 bytes, like a path that
+        // loses fragmented responses.
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (size, source) = udp.recv_from(&mut buf).unwrap();
+                let query = DnsPacket::try_from(&buf[..size]).unwrap();
+                if query.edns.unwrap().udp_payload_size <= 512 {
+                    udp.send_to(&respond(&buf[..size], false), source).unwrap();
+                }
+            }
+        });
+        let resolver = Resolver::new(vec![addr]).with_timeout(Duration::from_millis(100));
+        let response = resolver
+            .query("example.com", DnsType::A, DnsClass::In)
+            .unwrap();
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_unknown_record_types() {
+        // Puts a DNAME, a type this server has no name for, ahead of the
+        // address.
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, source) = udp.recv_from(&mut buf).unwrap();
+            let mut response = DnsPacket::try_from(&respond(&buf[..size], false)[..]).unwrap();
+            let dname = DnsAnswer::new(
+                "example.com".into(),
+                DnsType::Unknown(39),
+                DnsClass::In,
+                60,
+                RData::Unknown(b"\x03net\x00".to_vec()),
+            );
+            response.answers.insert(0, dname);
+            udp.send_to(&response.to_bytes(), source).unwrap();
+        });
+        let resolver = Resolver::new(vec![addr]).with_timeout(Duration::from_secs(1));
+        let start = Instant::now();
+        let response = resolver
+            .query("example.com", DnsType::A, DnsClass::In)
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(response.answers[0].qtype, DnsType::Unknown(39));
+        assert_eq!(response.answers[1].rdata, RData::A([192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        let resolver = Resolver::new(vec![silent.local_addr().unwrap()])
+            .with_attempts(5)
+            .with_deadline(start + Duration::from_millis(100));
+        assert!(matches!(
+            resolver.lookup_ip("example.com"),
+            Err(ResolveError::Deadline)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
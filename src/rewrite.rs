@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsType, Name};
+
+// What a `--rewrite` rule does to names at or below its suffix.
+#[derive(PartialEq, Debug, Clone)]
+enum Action {
+    // Look the name up under another suffix, e.g. `*.docker` as
+    // `*.docker.internal`, and answer with the original name.
+    Name(Name),
+    // Point CNAMEs with a target under the suffix at another suffix.
+    CnameTarget(Name),
+    // Keep TTLs within min..=max.
+    Ttl(u32, u32),
+    // Answer AAAA queries with NODATA and drop AAAA records.
+    StripAaaa,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Rule {
+    suffix: Name,
+    action: Action,
+}
+
+// Rules indexed by lowercased suffix, so matching a name costs one lookup
+// per label rather than one comparison per rule.
+#[derive(Default)]
+pub(crate) struct Rewriter {
+    rules: Vec<Rule>,
+    index: HashMap<String, Vec<usize>>,
+}
+
+impl Rule {
+    // `name:from=to`, `cname:from=to`, `ttl:suffix=min-max` or
+    // `strip-aaaa:suffix`.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, rest) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("expected kind:arguments, got {:?}", spec))?;
+        let (suffix, argument) = match rest.split_once('=') {
+            Some((suffix, argument)) => (suffix, Some(argument)),
+            None => (rest, None),
+        };
+        let suffix = Name::from(suffix);
+        if suffix.as_str().is_empty() {
+            return Err(format!("rule {:?} needs a suffix below the root", spec));
+        }
+        let action = match (kind, argument) {
+            ("name", Some(to)) if !to.is_empty() => Action::Name(to.into()),
+            ("cname", Some(to)) if !to.is_empty() => Action::CnameTarget(to.into()),
+            ("ttl", Some(range)) => {
+                let (min, max) = range
+                    .split_once('-')
+                    .and_then(|(min, max)| Some((min.parse().ok()?, max.parse().ok()?)))
+                    .filter(|(min, max)| min <= max && *max <= i32::MAX as u32)
+                    .ok_or_else(|| format!("bad TTL range {:?}, expected min-max", range))?;
+                Action::Ttl(min, max)
+            }
+            ("strip-aaaa", None) => Action::StripAaaa,
+            _ => return Err(format!("bad rewrite rule {:?}", spec)),
+        };
+        Ok(Rule { suffix, action })
+    }
+}
+
+impl Rewriter {
+    pub(crate) fn add(&mut self, spec: &str) -> Result<(), String> {
+        let rule = Rule::parse(spec)?;
+        self.index
+            .entry(rule.suffix.as_str().to_ascii_lowercase())
+            .or_default()
+            .push(self.rules.len());
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Rules for the name and each of its parents, most specific first.
+    fn matching<'a>(&'a self, name: &'a Name) -> impl Iterator<Item = &'a Rule> + 'a {
+        let name = name.as_str();
+        let suffixes = std::iter::once(name).chain(
+            name.match_indices('.')
+                .map(move |(dot, _)| &name[dot + 1..]),
+        );
+        suffixes
+            .filter_map(|suffix| self.index.get(&suffix.to_ascii_lowercase()))
+            .flatten()
+            .map(|&rule| &self.rules[rule])
+    }
+
+    pub(crate) fn strips_aaaa(&self, name: &Name) -> bool {
+        self.matching(name)
+            .any(|rule| rule.action == Action::StripAaaa)
+    }
+
+    // The name to look up instead, with the two suffixes so that answers can
+    // be renamed back.
+    pub(crate) fn rename(&self, name: &Name) -> Option<(Name, Name, Name)> {
+        self.matching(name).find_map(|rule| match &rule.action {
+            Action::Name(to) => Some((
+                replace_suffix(name, &rule.suffix, to)?,
+                rule.suffix.clone(),
+                to.clone(),
+            )),
+            _ => None,
+        })
+    }
+
+    // Applies the rules that act on records: CNAME targets, AAAA stripping
+    // and TTL bounds.
+    pub(crate) fn apply(&self, records: &mut Vec<DnsAnswer>) {
+        records.retain(|record| !(record.qtype == DnsType::Aaaa && self.strips_aaaa(&record.name)));
+        for record in records.iter_mut() {
+            if let RData::Cname(target) = &record.rdata {
+                let renamed = self.matching(target).find_map(|rule| match &rule.action {
+                    Action::CnameTarget(to) => replace_suffix(target, &rule.suffix, to),
+                    _ => None,
+                });
+                if let Some(target) = renamed {
+                    *record = DnsAnswer::new(
+                        record.name.clone(),
+                        record.qtype,
+                        record.qclass,
+                        record.ttl,
+                        RData::Cname(target),
+                    );
+                }
+            }
+            let bounds = self
+                .matching(&record.name)
+                .find_map(|rule| match rule.action {
+                    Action::Ttl(min, max) => Some((min as i32, max as i32)),
+                    _ => None,
+                });
+            if let Some((min, max)) = bounds {
+                record.ttl = record.ttl.clamp(min, max);
+            }
+        }
+    }
+}
+
+// `name` with `from` at its end replaced by `to`, if it lies below `from`.
+pub(crate) fn replace_suffix(name: &Name, from: &Name, to: &Name) -> Option<Name> {
+    if !name.is_subdomain_of(from) || from.as_str().is_empty() {
+        return None;
+    }
+    let prefix = &name.as_str()[..name.as_str().len() - from.as_str().len()];
+    Some(Name::from(format!("{}{}", prefix, to).as_str()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::DnsClass;
+
+    fn rewriter(specs: &[&str]) -> Rewriter {
+        let mut rewriter = Rewriter::default();
+        for spec in specs {
+            rewriter.add(spec).unwrap();
+        }
+        rewriter
+    }
+
+    fn record(name: &str, qtype: DnsType, rdata: RData) -> DnsAnswer {
+        DnsAnswer::new(name.into(), qtype, DnsClass::In, 300, rdata)
+    }
+
+    #[test]
+    fn test_parse() {
+        for bad in [
+            "name",
+            "name:docker",
+            "name:docker=",
+            "name:.=internal",
+            "ttl:example.com=60",
+            "ttl:example.com=600-60",
+            "strip-aaaa:example.com=1",
+            "frobnicate:example.com",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            Rule::parse("ttl:example.com.=60-3600").unwrap(),
+            Rule {
+                suffix: "example.com".into(),
+                action: Action::Ttl(60, 3600)
+            }
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let rewriter = rewriter(&["name:docker=docker.internal", "name:b.docker=elsewhere"]);
+        let (name, from, to) = rewriter.rename(&"web.Docker".into()).unwrap();
+        assert_eq!(name.as_str(), "web.docker.internal");
+        assert_eq!((from.as_str(), to.as_str()), ("docker", "docker.internal"));
+        assert_eq!(
+            rewriter.rename(&"a.b.docker".into()).unwrap().0.as_str(),
+            "a.elsewhere"
+        );
+        assert!(rewriter.rename(&"notdocker".into()).is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let rewriter = rewriter(&[
+            "cname:old.example=new.example",
+            "ttl:example.com=60-3600",
+            "strip-aaaa:v4only.example",
+        ]);
+        assert!(rewriter.strips_aaaa(&"www.v4only.example".into()));
+        assert!(!rewriter.strips_aaaa(&"www.example.com".into()));
+
+        let mut records = vec![
+            record(
+                "www.example.com",
+                DnsType::Cname,
+                RData::Cname("cdn.old.example".into()),
+            ),
+            record("host.v4only.example", DnsType::Aaaa, RData::Aaaa([0; 16])),
+            record("host.v4only.example", DnsType::A, RData::A([192, 0, 2, 1])),
+        ];
+        records[0].ttl = 5;
+        rewriter.apply(&mut records);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].rdata, RData::Cname("cdn.new.example".into()));
+        assert_eq!(records[0].ttl, 60);
+        assert_eq!(records[1].qtype, DnsType::A);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::common::Name;
+
+const GOOGLE: &str = "forcesafesearch.google.com";
+const BING: &str = "strict.bing.com";
+const DUCKDUCKGO: &str = "safe.duckduckgo.com";
+const YOUTUBE_STRICT: &str = "restrict.youtube.com";
+const YOUTUBE_MODERATE: &str = "restrictmoderate.youtube.com";
+
+// For the CNAMEs to the enforcement names.
+pub(crate) const TTL: i32 = 3600;
+
+// The hosts each site answers searches on.
+const BING_HOSTS: [&str; 2] = ["bing.com", "www.bing.com"];
+const DUCKDUCKGO_HOSTS: [&str; 3] = [
+    "duckduckgo.com",
+    "www.duckduckgo.com",
+    "start.duckduckgo.com",
+];
+const YOUTUBE_HOSTS: [&str; 5] = [
+    "www.youtube.com",
+    "m.youtube.com",
+    "youtubei.googleapis.com",
+    "youtube.googleapis.com",
+    "www.youtube-nocookie.com",
+];
+
+// The sites whose SafeSearch or restricted mode is forced, from
+// `--safesearch` or a client group's `safesearch=`: `on` for all of them
+// with YouTube strict, `off` for none, or a `/`-separated list of
+// `google`, `bing`, `duckduckgo`, `youtube` and `youtube-moderate`. Each
+// site honours the setting when its hosts resolve to the enforcement name
+// it publishes, so their queries are answered with a CNAME to it.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub(crate) struct Sites {
+    google: bool,
+    bing: bool,
+    duckduckgo: bool,
+    youtube: Option<&'static str>,
+}
+
+impl Sites {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "on" => {
+                return Ok(Sites {
+                    google: true,
+                    bing: true,
+                    duckduckgo: true,
+                    youtube: Some(YOUTUBE_STRICT),
+                })
+            }
+            "off" => return Ok(Sites::default()),
+            _ => {}
+        }
+        let mut sites = Sites::default();
+        for site in spec.split('/') {
+            let youtube = match site {
+                "google" => {
+                    sites.google = true;
+                    continue;
+                }
+                "bing" => {
+                    sites.bing = true;
+                    continue;
+                }
+                "duckduckgo" => {
+                    sites.duckduckgo = true;
+                    continue;
+                }
+                "youtube" => YOUTUBE_STRICT,
+                "youtube-moderate" => YOUTUBE_MODERATE,
+                _ => return Err(format!("unknown SafeSearch site {:?}", site)),
+            };
+            if sites.youtube.is_some_and(|other| other != youtube) {
+                return Err("youtube and youtube-moderate can't both be given".into());
+            }
+            sites.youtube = Some(youtube);
+        }
+        Ok(sites)
+    }
+
+    // The enforcement name to answer for `name` with, if it is a host of
+    // one of the sites.
+    pub(crate) fn target(&self, name: &Name) -> Option<Name> {
+        let name = name.as_str().to_ascii_lowercase();
+        let target = if self.google && is_google(&name) {
+            GOOGLE
+        } else if self.bing && BING_HOSTS.contains(&name.as_str()) {
+            BING
+        } else if self.duckduckgo && DUCKDUCKGO_HOSTS.contains(&name.as_str()) {
+            DUCKDUCKGO
+        } else {
+            self.youtube
+                .filter(|_| YOUTUBE_HOSTS.contains(&name.as_str()))?
+        };
+        Some(target.into())
+    }
+}
+
+// `google.<tld>`, `google.com.<cc>` or `google.co.<cc>`, or `www.` below
+// one of those: the search pages Google runs in every country.
+fn is_google(name: &str) -> bool {
+    let name = name.strip_prefix("www.").unwrap_or(name);
+    let Some(suffix) = name.strip_prefix("google.") else {
+        return false;
+    };
+    let labels: Vec<&str> = suffix.split('.').collect();
+    match labels[..] {
+        [tld] => !tld.is_empty(),
+        [second, cc] => (second == "com" || second == "co") && !cc.is_empty(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn target(sites: &Sites, name: &str) -> Option<String> {
+        sites.target(&name.into()).map(|name| name.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Sites::parse("off").unwrap(), Sites::default());
+        assert_eq!(
+            Sites::parse("google/bing/duckduckgo/youtube").unwrap(),
+            Sites::parse("on").unwrap()
+        );
+        assert_eq!(
+            Sites::parse("youtube-moderate").unwrap().youtube,
+            Some(YOUTUBE_MODERATE)
+        );
+        for bad in ["", "yahoo", "google/", "youtube/youtube-moderate"] {
+            assert!(Sites::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        let sites = Sites::parse("on").unwrap();
+        for (name, expected) in [
+            ("www.google.com", Some(GOOGLE)),
+            ("Google.CO.uk", Some(GOOGLE)),
+            ("www.google.com.au", Some(GOOGLE)),
+            ("google.de", Some(GOOGLE)),
+            ("mail.google.com", None),
+            ("forcesafesearch.google.com", None),
+            ("google.org.uk", None),
+            ("www.bing.com", Some(BING)),
+            ("strict.bing.com", None),
+            ("start.duckduckgo.com", Some(DUCKDUCKGO)),
+            ("safe.duckduckgo.com", None),
+            ("m.youtube.com", Some(YOUTUBE_STRICT)),
+            ("youtubei.googleapis.com", Some(YOUTUBE_STRICT)),
+            ("restrict.youtube.com", None),
+            ("example.com", None),
+        ] {
+            assert_eq!(target(&sites, name).as_deref(), expected, "{}", name);
+        }
+
+        let sites = Sites::parse("bing/youtube-moderate").unwrap();
+        assert_eq!(target(&sites, "www.google.com"), None);
+        assert_eq!(target(&sites, "bing.com").as_deref(), Some(BING));
+        assert_eq!(
+            target(&sites, "www.youtube.com").as_deref(),
+            Some(YOUTUBE_MODERATE)
+        );
+        assert_eq!(target(&Sites::default(), "www.bing.com"), None);
+    }
+}
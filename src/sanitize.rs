@@ -0,0 +1,253 @@
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsType, Name};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+// Drops the records of an upstream response that don't belong to the
+// question, before they are cached or passed on: answers other than the
+// question name and the CNAME chain from it, authority records for other
+// zones and additional records nothing else refers to. Records outside
+// `zone`, the zone the server was asked as an authority for, go too; a
+// server can only vouch for its own zone. Forwarded responses use the root.
+pub(crate) fn scrub(question: &DnsQuestion, zone: &Name, response: &mut DnsPacket) {
+    let owners = chain(question, zone, &response.answers);
+    let owned = |record: &DnsAnswer| {
+        owners
+            .iter()
+            .any(|owner| owner.eq_ignore_case(&record.name))
+    };
+
+    response.answers.retain(|record| {
+        owned(record)
+            && (record.qtype == question.qtype
+                || question.qtype == DnsType::Any
+                || matches!(record.qtype, DnsType::Cname | DnsType::Rrsig))
+    });
+    response.authorities.retain(|record| {
+        record.name.is_subdomain_of(zone)
+            && (owners.iter().any(|owner| owner.is_subdomain_of(&record.name))
+                // Denial of existence proofs are owned by nearby names.
+                || matches!(
+                    record.qtype,
+                    DnsType::Nsec | DnsType::Nsec3 | DnsType::Rrsig
+                ))
+    });
+    let referenced: Vec<Name> = response
+        .answers
+        .iter()
+        .chain(&response.authorities)
+        .filter_map(|record| target(&record.rdata).cloned())
+        .collect();
+    response.additionals.retain(|record| {
+        record.name.is_subdomain_of(zone)
+            && referenced
+                .iter()
+                .any(|name| name.eq_ignore_case(&record.name))
+    });
+}
+
+// Keeps what a server for `zone` may say when it delegates `cut`: the NS
+// records there, with the DS records and signatures that go with them, and
+// glue for those nameservers from within `zone`. Addresses for names
+// outside it would be trusted for every later query to those nameservers.
+pub(crate) fn referral(zone: &Name, cut: &Name, response: &mut DnsPacket) {
+    response.authorities.retain(|record| {
+        record.name.eq_ignore_case(cut)
+            && matches!(record.qtype, DnsType::Ns | DnsType::Ds | DnsType::Rrsig)
+    });
+    let nameservers: Vec<Name> = response
+        .authorities
+        .iter()
+        .filter_map(|record| match &record.rdata {
+            RData::Ns(nameserver) => Some(nameserver.clone()),
+            _ => None,
+        })
+        .collect();
+    response.additionals.retain(|record| {
+        matches!(record.qtype, DnsType::A | DnsType::Aaaa)
+            && record.name.is_subdomain_of(zone)
+            && nameservers
+                .iter()
+                .any(|nameserver| nameserver.eq_ignore_case(&record.name))
+    });
+}
+
+// The question name and the CNAME targets from it, as long as they stay
+// within the zone; a CNAME leading out of it ends the chain.
+fn chain(question: &DnsQuestion, zone: &Name, answers: &[DnsAnswer]) -> Vec<Name> {
+    let mut owners = vec![question.qname.clone()];
+    if question.qtype == DnsType::Cname || !question.qname.is_subdomain_of(zone) {
+        return owners;
+    }
+    // Each step takes a new CNAME, so loops end.
+    for _ in 0..answers.len() {
+        let last = &owners[owners.len() - 1];
+        let next = answers.iter().find_map(|record| match &record.rdata {
+            RData::Cname(next) if record.name.eq_ignore_case(last) => Some(next),
+            _ => None,
+        });
+        match next {
+            Some(next)
+                if next.is_subdomain_of(zone)
+                    && !owners.iter().any(|owner| owner.eq_ignore_case(next)) =>
+            {
+                owners.push(next.clone())
+            }
+            _ => break,
+        }
+    }
+    owners
+}
+
+// A name whose addresses may be useful alongside the record.
+fn target(rdata: &RData) -> Option<&Name> {
+    match rdata {
+        RData::Ns(name) => Some(name),
+        RData::Mx { exchange, .. } => Some(exchange),
+        RData::Srv { target, .. } => Some(target),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::DnsClass;
+
+    fn record(name: &str, qtype: DnsType, rdata: RData) -> DnsAnswer {
+        DnsAnswer::new(name.into(), qtype, DnsClass::In, 300, rdata)
+    }
+
+    fn a(name: &str) -> DnsAnswer {
+        record(name, DnsType::A, RData::A([192, 0, 2, 1]))
+    }
+
+    fn cname(name: &str, target: &str) -> DnsAnswer {
+        record(name, DnsType::Cname, RData::Cname(target.into()))
+    }
+
+    fn ns(name: &str, target: &str) -> DnsAnswer {
+        record(name, DnsType::Ns, RData::Ns(target.into()))
+    }
+
+    fn names(records: &[DnsAnswer]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| format!("{} {}", record.name, record.qtype))
+            .collect()
+    }
+
+    fn response(qname: &str, qtype: DnsType) -> (DnsQuestion, DnsPacket) {
+        let question = DnsQuestion {
+            qname: qname.into(),
+            qtype,
+            qclass: DnsClass::In,
+        };
+        let response = DnsPacket::query(1, question.clone());
+        (question, response)
+    }
+
+    #[test]
+    fn test_answers() {
+        let (question, mut response) = response("www.example.com", DnsType::A);
+        response.answers = vec![
+            cname("WWW.example.com", "web.example.com"),
+            a("web.example.com"),
+            a("bank.example"),
+            record(
+                "web.example.com",
+                DnsType::Txt,
+                RData::Txt(vec![b"unasked".to_vec()]),
+            ),
+            cname("web.example.com", "loop.example.com"),
+            cname("loop.example.com", "www.example.com"),
+        ];
+        scrub(&question, &Name::from(""), &mut response);
+        assert_eq!(
+            names(&response.answers),
+            vec![
+                "WWW.example.com CNAME",
+                "web.example.com A",
+                "web.example.com CNAME",
+                "loop.example.com CNAME"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_authorities_and_additionals() {
+        let (question, mut response) = response("www.example.com", DnsType::Mx);
+        response.answers = vec![record(
+            "www.example.com",
+            DnsType::Mx,
+            RData::Mx {
+                preference: 10,
+                exchange: "mail.example.com".into(),
+            },
+        )];
+        response.authorities = vec![
+            ns("example.com", "ns1.example.com"),
+            ns("bank.example", "ns.attacker.example"),
+        ];
+        response.additionals = vec![
+            a("mail.example.com"),
+            a("ns1.example.com"),
+            a("ns.attacker.example"),
+            a("www.bank.example"),
+        ];
+        scrub(&question, &Name::from(""), &mut response);
+        assert_eq!(names(&response.authorities), vec!["example.com NS"]);
+        assert_eq!(
+            names(&response.additionals),
+            vec!["mail.example.com A", "ns1.example.com A"]
+        );
+    }
+
+    #[test]
+    fn test_bailiwick() {
+        // Asked as the authority for example.com, which it can't be for
+        // the target of the CNAME or its nameserver's address.
+        let (question, mut response) = response("www.example.com", DnsType::A);
+        response.answers = vec![
+            cname("www.example.com", "cdn.example.net"),
+            a("cdn.example.net"),
+        ];
+        response.authorities = vec![
+            ns("example.com", "ns.example.net"),
+            ns("com", "a.gtld.test"),
+        ];
+        response.additionals = vec![a("ns.example.net")];
+        scrub(&question, &Name::from("example.com"), &mut response);
+        assert_eq!(names(&response.answers), vec!["www.example.com CNAME"]);
+        assert_eq!(names(&response.authorities), vec!["example.com NS"]);
+        assert!(response.additionals.is_empty());
+    }
+
+    #[test]
+    fn test_referral() {
+        // From a com server, delegating example.com.
+        let (_, mut response) = response("www.example.com", DnsType::A);
+        response.authorities = vec![
+            ns("example.com", "ns1.example.com"),
+            ns("example.com", "ns2.example.net"),
+            ns("bank.com", "ns.attacker.example"),
+            record("example.com", DnsType::Ds, RData::Unknown(vec![1, 2, 3])),
+        ];
+        response.additionals = vec![
+            a("ns1.example.com"),
+            a("ns2.example.net"),
+            a("ns.attacker.example"),
+            a("www.example.com"),
+        ];
+        referral(
+            &Name::from("com"),
+            &Name::from("example.com"),
+            &mut response,
+        );
+        assert_eq!(
+            names(&response.authorities),
+            vec!["example.com NS", "example.com NS", "example.com DS"]
+        );
+        assert_eq!(names(&response.additionals), vec!["ns1.example.com A"]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::timezone::TimeZone;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// `--schedule name=window[;window...][,tz=zone]`, when the blocklists and
+// client groups naming it with `schedule=name` are in force. A window is
+// `[days@]HH:MM-HH:MM` with days such as `mon-fri` or `sat/sun`; one
+// ending before it starts runs past midnight, into the day after. Times are
+// in the zone, UTC without one (see TimeZone).
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Schedule {
+    pub(crate) name: String,
+    windows: Vec<Window>,
+    tz: TimeZone,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Window {
+    // Bit 0 is Monday.
+    days: u8,
+    // Seconds since midnight.
+    start: u32,
+    end: u32,
+}
+
+impl Schedule {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let (name, windows) = match parts.next().unwrap_or_default().split_once('=') {
+            Some((name, windows)) if !name.is_empty() && !windows.is_empty() => (name, windows),
+            _ => return Err(format!("expected name=windows, got {:?}", spec)),
+        };
+        let windows = windows
+            .split(';')
+            .map(Window::parse)
+            .collect::<Result<Vec<Window>, String>>()?;
+        let mut tz = TimeZone::UTC;
+        for part in parts {
+            match part.split_once('=') {
+                Some(("tz", zone)) => tz = TimeZone::parse(zone)?,
+                _ => return Err(format!("unknown schedule option {:?}", part)),
+            }
+        }
+        Ok(Schedule {
+            name: name.to_string(),
+            windows,
+            tz,
+        })
+    }
+
+    pub(crate) fn active_at(&self, time: SystemTime) -> bool {
+        let (weekday, secs) = self.tz.local_time(time);
+        let yesterday = (weekday + 6) % 7;
+        self.windows.iter().any(|window| {
+            let on = |day: u32| window.days & (1 << day) != 0;
+            match window.start < window.end {
+                true => on(weekday) && window.start <= secs && secs < window.end,
+                false => {
+                    (on(weekday) && secs >= window.start) || (on(yesterday) && secs < window.end)
+                }
+            }
+        })
+    }
+}
+
+impl Window {
+    fn parse(text: &str) -> Result<Self, String> {
+        let bad = || format!("bad window {:?}", text);
+        let (days, times) = match text.split_once('@') {
+            Some((days, times)) => (parse_days(days).ok_or_else(bad)?, times),
+            None => (0x7f, text),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(bad)?;
+        let (start, end) = (
+            parse_time(start).ok_or_else(bad)?,
+            parse_time(end).ok_or_else(bad)?,
+        );
+        // 24:00 only ends a window.
+        if start == 86400 {
+            return Err(bad());
+        }
+        Ok(Window {
+            days,
+            start,
+            end: end % 86400,
+        })
+    }
+}
+
+// `mon-fri`, `sat/sun` or `mon/wed-fri` as a bitmask. A range may wrap,
+// as `fri-mon`.
+fn parse_days(text: &str) -> Option<u8> {
+    let day = |name: &str| DAYS.iter().position(|day| name.eq_ignore_ascii_case(day));
+    let mut days = 0;
+    for part in text.split('/') {
+        let (from, to) = match part.split_once('-') {
+            Some((from, to)) => (day(from)?, day(to)?),
+            None => (day(part)?, day(part)?),
+        };
+        let mut day = from;
+        loop {
+            days |= 1 << day;
+            if day == to {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Some(days)
+}
+
+// `HH:MM` up to `24:00`, as seconds since midnight.
+fn parse_time(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    if !(1..=2).contains(&hours.len()) || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if minutes > 59 || hours * 60 + minutes > 24 * 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60)
+}
+
+// Schedules forced on or off through `ctl schedule`, until set back to
+// `auto`. Kept until restart.
+#[derive(Default)]
+pub(crate) struct Overrides {
+    forced: Mutex<HashMap<String, bool>>,
+}
+
+impl Overrides {
+    pub(crate) fn set(&self, name: &str, active: Option<bool>) {
+        let mut forced = self.forced.lock().unwrap();
+        match active {
+            Some(active) => {
+                forced.insert(name.to_string(), active);
+            }
+            None => {
+                forced.remove(name);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<bool> {
+        self.forced.lock().unwrap().get(name).copied()
+    }
+}
+
+// Whether something on the named schedule is in force now. Without a
+// schedule it always is; a name that isn't configured never is.
+pub(crate) fn active(schedule: Option<&str>, config: &Config, overrides: &Overrides) -> bool {
+    let Some(name) = schedule else {
+        return true;
+    };
+    overrides.get(name).unwrap_or_else(|| {
+        config
+            .schedules
+            .iter()
+            .any(|schedule| schedule.name == name && schedule.active_at(SystemTime::now()))
+    })
+}
+
+// One line per schedule for `ctl schedules`: whether it is in force, and
+// whether that is forced.
+pub(crate) fn report(config: &Config, overrides: &Overrides) -> String {
+    config
+        .schedules
+        .iter()
+        .map(|schedule| {
+            let mode = match overrides.get(&schedule.name) {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "auto",
+            };
+            format!(
+                "schedule.{}.active={}\nschedule.{}.mode={}\n",
+                schedule.name,
+                active(Some(&schedule.name), config, overrides) as u8,
+                schedule.name,
+                mode
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 2024-01-01 was a Monday.
+    fn monday(hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1704067200 + hour * 3600 + minute * 60)
+    }
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    #[test]
+    fn test_parse() {
+        let schedule =
+            Schedule::parse("work=mon-fri@09:00-17:00;sat/sun@10:00-12:00,tz=+01:00").unwrap();
+        assert_eq!(schedule.name, "work");
+        assert_eq!(schedule.windows.len(), 2);
+        assert_eq!(schedule.windows[0].days, 0b0011111);
+        assert_eq!(schedule.windows[1].days, 0b1100000);
+        assert_eq!(
+            Schedule::parse("x=fri-mon@00:00-24:00").unwrap().windows[0].days,
+            0b1110001
+        );
+        for bad in [
+            "work",
+            "=09:00-17:00",
+            "work=09:00",
+            "work=9-17",
+            "work=09:00-24:01",
+            "work=24:00-01:00",
+            "work=09:60-10:00",
+            "work=someday@09:00-17:00",
+            "work=09:00-17:00,tz=Mars/Olympus",
+            "work=09:00-17:00,every=day",
+        ] {
+            assert!(Schedule::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_active() {
+        let work = Schedule::parse("work=mon-fri@09:00-17:00,tz=+01:00").unwrap();
+        assert!(!work.active_at(monday(7, 59)));
+        assert!(work.active_at(monday(8, 0)));
+        assert!(work.active_at(monday(15, 59)));
+        assert!(!work.active_at(monday(16, 0)));
+        // Saturday.
+        assert!(!work.active_at(monday(12, 0) + DAY * 5));
+
+        // Friday night into Saturday morning, but not Sunday into Monday.
+        let night = Schedule::parse("night=fri@22:00-06:00").unwrap();
+        assert!(night.active_at(monday(23, 0) + DAY * 4));
+        assert!(night.active_at(monday(5, 0) + DAY * 5));
+        assert!(!night.active_at(monday(7, 0) + DAY * 5));
+        assert!(!night.active_at(monday(23, 0) + DAY * 6));
+        assert!(!night.active_at(monday(5, 0)));
+
+        let always = Schedule::parse("always=00:00-24:00").unwrap();
+        assert!(always.active_at(monday(0, 0)));
+        assert!(always.active_at(monday(23, 59)));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let config = Config {
+            schedules: vec![Schedule::parse("work=mon-fri@09:00-17:00").unwrap()],
+            ..Config::default()
+        };
+        let overrides = Overrides::default();
+        assert!(active(None, &config, &overrides));
+        assert!(!active(Some("other"), &config, &overrides));
+        overrides.set("other", Some(true));
+        assert!(active(Some("other"), &config, &overrides));
+        overrides.set("other", None);
+        assert!(!active(Some("other"), &config, &overrides));
+
+        overrides.set("work", Some(false));
+        assert_eq!(
+            report(&config, &overrides),
+            "schedule.work.active=0\nschedule.work.mode=off\n"
+        );
+    }
+}
@@ -0,0 +1,604 @@
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::authority::Zone;
+use crate::clock::SharedClock;
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::DEFAULT_MAX_UDP_SIZE;
+use crate::dig::parse_server;
+use crate::edns::{Edns, EdnsOption};
+use crate::error::ResolveError;
+use crate::handler::State;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::resolver::Resolver;
+use crate::serial;
+use crate::transfer;
+use crate::tsig::TsigKey;
+
+// Between checks until the primary's SOA says otherwise.
+const DEFAULT_RETRY: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// `--secondary zone=primary[:port][,primary=addr[:port]...]
+// [,key=[hmac-sha256:]name:secret][,expired=servfail|refused]`: a zone
+// served from transfers from its primaries. Every primary's SOA is checked
+// each refresh interval, or each retry interval while none answers, and the
+// zone transferred from whichever has the highest serial when that is
+// newer than ours, in serial number arithmetic (RFC 1982). Once no check
+// has succeeded for the SOA's expire time, or the time the primary said was
+// left with EXPIRE (RFC 7314), the data is too stale to answer for: queries
+// get SERVFAIL, or REFUSED with `expired=refused`, until a primary is
+// reached again. Before the first transfer they get SERVFAIL.
+#[derive(Clone)]
+pub(crate) struct SecondaryConfig {
+    pub(crate) zone: Name,
+    pub(crate) primaries: Vec<SocketAddr>,
+    pub(crate) key: Option<TsigKey>,
+    pub(crate) expired: ResponseCode,
+}
+
+impl SecondaryConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let (zone, primary) = parts
+            .next()
+            .and_then(|first| first.split_once('='))
+            .filter(|(zone, primary)| !zone.is_empty() && !primary.is_empty())
+            .ok_or_else(|| format!("expected zone=primary, got {:?}", spec))?;
+        let mut config = SecondaryConfig {
+            zone: Name::from(zone),
+            primaries: vec![parse_server(primary)?],
+            key: None,
+            expired: ResponseCode::ServFail,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("primary", primary)) => config.primaries.push(parse_server(primary)?),
+                Some(("key", key)) => config.key = Some(TsigKey::parse(key)?),
+                Some(("expired", "servfail")) => config.expired = ResponseCode::ServFail,
+                Some(("expired", "refused")) => config.expired = ResponseCode::Refused,
+                _ => return Err(format!("unknown secondary option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Every secondary zone and how fresh its data is.
+#[derive(Default)]
+pub(crate) struct Secondaries {
+    zones: RwLock<Vec<Secondary>>,
+    clock: SharedClock,
+}
+
+struct Secondary {
+    apex: Name,
+    expired: ResponseCode,
+    zone: Option<Arc<Zone>>,
+    serial: Option<u32>,
+    // When the data stops being served, unless a check succeeds first.
+    expires: Option<Instant>,
+    // Checks failed since the last that succeeded.
+    failures: u64,
+    primaries: Vec<Primary>,
+    // Wakes the thread keeping the zone fresh, for a NOTIFY.
+    wake: SyncSender<()>,
+}
+
+// How a primary answered its last check.
+struct Primary {
+    addr: SocketAddr,
+    serial: Option<u32>,
+    // Checks it failed since it last answered.
+    failures: u64,
+}
+
+// How a query for a name in a secondary zone is answered.
+pub(crate) enum Served {
+    // From the zone, which has this many seconds left before it expires.
+    Zone(Arc<Zone>, u32),
+    Unavailable(ResponseCode),
+}
+
+impl Secondaries {
+    #[cfg(test)]
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Secondaries {
+            clock,
+            ..Secondaries::default()
+        }
+    }
+
+    // Returns what a NOTIFY for the zone wakes.
+    pub(crate) fn add(&self, config: &SecondaryConfig) -> Receiver<()> {
+        let (wake, woken) = mpsc::sync_channel(1);
+        self.zones.write().unwrap().push(Secondary {
+            apex: config.zone.clone(),
+            expired: config.expired,
+            zone: None,
+            serial: None,
+            expires: None,
+            failures: 0,
+            primaries: config
+                .primaries
+                .iter()
+                .map(|&addr| Primary {
+                    addr,
+                    serial: None,
+                    failures: 0,
+                })
+                .collect(),
+            wake,
+        });
+        woken
+    }
+
+    // A NOTIFY (RFC 1996) for the zone: its primaries may have a newer
+    // serial, so it is checked now rather than at the next refresh. Only
+    // the zone's primaries are listened to.
+    pub(crate) fn notify(&self, apex: &Name, source: IpAddr) -> bool {
+        let zones = self.zones.read().unwrap();
+        let secondary = zones.iter().find(|secondary| {
+            secondary.apex.eq_ignore_case(apex)
+                && secondary
+                    .primaries
+                    .iter()
+                    .any(|primary| primary.addr.ip().to_canonical() == source)
+        });
+        match secondary {
+            Some(secondary) => {
+                // Full means a check is already due.
+                let _ = secondary.wake.try_send(());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The most specific secondary zone holding the name, with its apex.
+    pub(crate) fn find(&self, qname: &Name) -> Option<(Name, Served)> {
+        let now = self.clock.now();
+        let zones = self.zones.read().unwrap();
+        let secondary = zones
+            .iter()
+            .filter(|secondary| qname.is_subdomain_of(&secondary.apex))
+            .max_by_key(|secondary| secondary.apex.len())?;
+        let served = match (&secondary.zone, secondary.expires) {
+            (Some(zone), Some(expires)) if expires > now => {
+                let left = expires.duration_since(now).as_secs();
+                Served::Zone(Arc::clone(zone), left.min(u32::MAX as u64) as u32)
+            }
+            (Some(_), _) => Served::Unavailable(secondary.expired),
+            (None, _) => Served::Unavailable(ResponseCode::ServFail),
+        };
+        Some((secondary.apex.clone(), served))
+    }
+
+    fn serial(&self, apex: &Name) -> Option<u32> {
+        self.update(apex, |secondary| secondary.serial)
+    }
+
+    fn expired(&self, apex: &Name) -> bool {
+        let now = self.clock.now();
+        self.update(apex, |secondary| {
+            secondary.expires.is_some_and(|expires| expires <= now)
+        })
+    }
+
+    // A check succeeded, with a new transfer of the zone if it had changed.
+    fn refreshed(&self, apex: &Name, zone: Option<Zone>, serial: u32, expire: u32) {
+        let now = self.clock.now();
+        self.update(apex, |secondary| {
+            if let Some(zone) = zone {
+                secondary.zone = Some(Arc::new(zone));
+            }
+            secondary.serial = Some(serial);
+            secondary.expires = Some(now + Duration::from_secs(expire as u64));
+            secondary.failures = 0;
+        })
+    }
+
+    // The serial a primary offered, or None when it couldn't be asked.
+    fn polled(&self, apex: &Name, addr: SocketAddr, serial: Option<u32>) {
+        self.update(apex, |secondary| {
+            let primary = secondary
+                .primaries
+                .iter_mut()
+                .find(|primary| primary.addr == addr);
+            if let Some(primary) = primary {
+                match serial {
+                    Some(_) => primary.failures = 0,
+                    None => primary.failures += 1,
+                }
+                primary.serial = serial;
+            }
+        })
+    }
+
+    fn failed(&self, apex: &Name) {
+        self.update(apex, |secondary| secondary.failures += 1)
+    }
+
+    fn update<T: Default>(&self, apex: &Name, f: impl FnOnce(&mut Secondary) -> T) -> T {
+        let mut zones = self.zones.write().unwrap();
+        let secondary = zones
+            .iter_mut()
+            .find(|secondary| secondary.apex.eq_ignore_case(apex));
+        secondary.map(f).unwrap_or_default()
+    }
+
+    // One key=value line per figure for each zone and each of its
+    // primaries, as in `ctl stats`. A primary's serial is 0 while it's down.
+    pub(crate) fn report(&self) -> String {
+        let now = self.clock.now();
+        let mut report = String::new();
+        for secondary in self.zones.read().unwrap().iter() {
+            let left = secondary.expires.map_or(0, |expires| {
+                expires.saturating_duration_since(now).as_secs()
+            });
+            let expired = secondary.zone.is_some() && left == 0;
+            for (key, value) in [
+                ("serial", secondary.serial.unwrap_or(0) as u64),
+                ("expires_in", left),
+                ("expired", expired as u64),
+                ("failures", secondary.failures),
+            ] {
+                writeln!(report, "secondary.{}.{}={}", secondary.apex, key, value).unwrap();
+            }
+            for primary in &secondary.primaries {
+                for (key, value) in [
+                    ("serial", primary.serial.unwrap_or(0) as u64),
+                    ("failures", primary.failures),
+                ] {
+                    writeln!(
+                        report,
+                        "secondary.{}.primary.{}.{}={}",
+                        secondary.apex, primary.addr, key, value
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        report
+    }
+}
+
+// Keeps the zone fresh in the background, logging when it expires and
+// when it is back.
+pub(crate) fn maintain(config: SecondaryConfig, state: Arc<State>) {
+    let woken = state.secondaries.add(&config);
+    thread::spawn(move || {
+        let secondaries = &state.secondaries;
+        let mut retry = DEFAULT_RETRY;
+        let mut expired = false;
+        loop {
+            let wait = match refresh(&config, secondaries) {
+                Ok(timers) => {
+                    if expired {
+                        println!("Secondary zone {} is being served again", config.zone);
+                        expired = false;
+                    }
+                    retry = timers.retry;
+                    timers.refresh
+                }
+                Err(e) => {
+                    secondaries.failed(&config.zone);
+                    eprintln!("Refreshing secondary zone {} failed: {}", config.zone, e);
+                    if !expired && secondaries.expired(&config.zone) {
+                        eprintln!(
+                            "Secondary zone {} expired, answering {:?} for it",
+                            config.zone, config.expired
+                        );
+                        expired = true;
+                    }
+                    retry
+                }
+            };
+            if woken.recv_timeout(wait).is_ok() {
+                println!("Checking secondary zone {} on NOTIFY", config.zone);
+            }
+        }
+    });
+}
+
+// When to check the primary next, from its SOA.
+struct Timers {
+    refresh: Duration,
+    retry: Duration,
+}
+
+// What a primary's SOA says.
+struct Offer {
+    serial: u32,
+    timers: Timers,
+    expire: u32,
+}
+
+// Asks every primary for the zone's SOA at once, and transfers the zone
+// from the one with the highest serial if that is newer than ours. Fails
+// only when no primary answers, or the transfer fails.
+fn refresh(config: &SecondaryConfig, secondaries: &Secondaries) -> Result<Timers, ResolveError> {
+    let polls: Vec<(SocketAddr, Result<Offer, ResolveError>)> = thread::scope(|scope| {
+        let polling: Vec<_> = config
+            .primaries
+            .iter()
+            .map(|&primary| (primary, scope.spawn(move || poll(config, primary))))
+            .collect();
+        polling
+            .into_iter()
+            .map(|(primary, handle)| (primary, handle.join().unwrap()))
+            .collect()
+    });
+
+    let mut best: Option<(SocketAddr, Offer)> = None;
+    let mut last_error = ResolveError::NoServers;
+    for (primary, result) in polls {
+        let serial = result.as_ref().ok().map(|offer| offer.serial);
+        secondaries.polled(&config.zone, primary, serial);
+        match result {
+            Ok(offer) => {
+                let newer = match &best {
+                    Some((_, best)) => serial::is_newer(offer.serial, best.serial),
+                    None => true,
+                };
+                if newer {
+                    best = Some((primary, offer));
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "Primary {} for secondary zone {} didn't answer: {}",
+                    primary, config.zone, e
+                );
+                last_error = e;
+            }
+        }
+    }
+    let Some((primary, offer)) = best else {
+        return Err(last_error);
+    };
+
+    let (mut serial, mut expire) = (offer.serial, offer.expire);
+    let mut zone = None;
+    match secondaries.serial(&config.zone) {
+        // Primaries behind us, perhaps restored from a backup, still count
+        // as reached, but our copy stays.
+        Some(held) if !serial::is_newer(serial, held) => serial = held,
+        _ => {
+            let transfer = transfer::axfr(primary, &config.zone, config.key.as_ref(), TIMEOUT)
+                .inspect_err(|_| secondaries.polled(&config.zone, primary, None))?;
+            (serial, _, expire) = soa_fields(&transfer.records[0])?;
+            if let Some(remaining) = transfer.expire {
+                expire = remaining;
+            }
+            zone = Some(Zone::from_records(config.zone.clone(), transfer.records));
+        }
+    }
+    secondaries.refreshed(&config.zone, zone, serial, expire);
+    Ok(offer.timers)
+}
+
+// One primary's SOA for the zone, asking for EXPIRE too.
+fn poll(config: &SecondaryConfig, primary: SocketAddr) -> Result<Offer, ResolveError> {
+    let mut query = DnsPacket::query(
+        rand::thread_rng().gen(),
+        DnsQuestion {
+            qname: config.zone.clone(),
+            qtype: DnsType::Soa,
+            qclass: DnsClass::In,
+        },
+    );
+    query.header.rd = false;
+    let mut edns = Edns::new(DEFAULT_MAX_UDP_SIZE);
+    edns.options.push(EdnsOption::Expire(None));
+    query.edns = Some(edns);
+    let resolver = Resolver::new(vec![primary]).with_timeout(TIMEOUT);
+    let response = resolver.send(&query, config.key.as_ref())?;
+    if response.header.rcode != ResponseCode::NoError {
+        return Err(ResolveError::Rcode(response.header.rcode as u8));
+    }
+    let soa = response
+        .answers
+        .iter()
+        .find(|record| record.qtype == DnsType::Soa && record.name.eq_ignore_case(&config.zone))
+        .ok_or_else(|| ResolveError::Transfer("no SOA in the answer".into()))?;
+    let (serial, timers, mut expire) = soa_fields(soa)?;
+    if let Some(remaining) = response.edns.as_ref().and_then(Edns::expire) {
+        expire = remaining;
+    }
+    Ok(Offer {
+        serial,
+        timers,
+        expire,
+    })
+}
+
+fn soa_fields(soa: &DnsAnswer) -> Result<(u32, Timers, u32), ResolveError> {
+    match soa.rdata {
+        RData::Soa {
+            serial,
+            refresh,
+            retry,
+            expire,
+            ..
+        } => {
+            let timers = Timers {
+                refresh: Duration::from_secs(refresh.max(1) as u64),
+                retry: Duration::from_secs(retry.max(1) as u64),
+            };
+            Ok((serial, timers, expire))
+        }
+        _ => Err(ResolveError::Transfer("malformed SOA".into())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::testing::{response, MockUpstream, Reply};
+
+    fn soa(serial: u32) -> DnsAnswer {
+        DnsAnswer::new(
+            "example.com".into(),
+            DnsType::Soa,
+            DnsClass::In,
+            3600,
+            RData::Soa {
+                mname: "ns1.example.com".into(),
+                rname: "hostmaster.example.com".into(),
+                serial,
+                refresh: 7200,
+                retry: 900,
+                expire: 600,
+                minimum: 300,
+            },
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = SecondaryConfig::parse("example.com=192.0.2.1").unwrap();
+        assert_eq!(config.primaries, vec!["192.0.2.1:53".parse().unwrap()]);
+        assert_eq!(config.expired, ResponseCode::ServFail);
+        let config =
+            SecondaryConfig::parse("example.com=192.0.2.1:5353,key=xfr:c2VjcmV0,expired=refused")
+                .unwrap();
+        assert_eq!(config.primaries[0].port(), 5353);
+        assert_eq!(config.key.unwrap().name, Name::from("xfr"));
+        assert_eq!(config.expired, ResponseCode::Refused);
+        let config =
+            SecondaryConfig::parse("example.com=192.0.2.1,primary=[2001:db8::1]:5353").unwrap();
+        assert_eq!(config.primaries[1], "[2001:db8::1]:5353".parse().unwrap());
+        for bad in [
+            "example.com",
+            "=192.0.2.1",
+            "example.com=192.0.2.1,expired=nxdomain",
+            "example.com=192.0.2.1,primary=",
+        ] {
+            assert!(SecondaryConfig::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    // A primary at `serial`, with an EXPIRE of 600 seconds on SOA answers,
+    // whose zone has www at 192.0.2.<serial>.
+    fn primary(serial: u32) -> MockUpstream {
+        MockUpstream::start(move |query, _| {
+            let mut reply = response(query);
+            let edns = reply.edns.as_mut().unwrap();
+            edns.options = vec![EdnsOption::Expire(Some(600))];
+            match query.questions[0].qtype {
+                DnsType::Soa => reply.answers = vec![soa(serial)],
+                _ => {
+                    let www = DnsAnswer::new(
+                        "www.example.com".into(),
+                        DnsType::A,
+                        DnsClass::In,
+                        3600,
+                        RData::A([192, 0, 2, serial as u8]),
+                    );
+                    reply.answers = vec![soa(serial), www, soa(serial)];
+                }
+            }
+            Reply::Send(reply)
+        })
+    }
+
+    fn transfers(primary: &MockUpstream) -> usize {
+        primary
+            .queries()
+            .iter()
+            .filter(|(q, _)| q.qtype == DnsType::Axfr)
+            .count()
+    }
+
+    #[test]
+    fn test_refresh_and_expiry() {
+        let primary = primary(7);
+        let mut config = SecondaryConfig::parse("example.com=127.0.0.1").unwrap();
+        config.primaries = vec![primary.addr];
+        config.expired = ResponseCode::Refused;
+        let clock = ManualClock::new();
+        let secondaries = Secondaries::with_clock(clock.shared());
+        secondaries.add(&config);
+
+        let find = |name: &str| secondaries.find(&name.into()).map(|(_, served)| served);
+        assert!(matches!(
+            find("www.example.com"),
+            Some(Served::Unavailable(ResponseCode::ServFail))
+        ));
+        assert!(find("example.org").is_none());
+
+        let timers = refresh(&config, &secondaries).unwrap();
+        assert_eq!(timers.refresh, Duration::from_secs(7200));
+        let Some(Served::Zone(zone, left)) = find("www.example.com") else {
+            panic!("not served");
+        };
+        assert_eq!((zone.apex.as_str(), left), ("example.com", 600));
+        // An unchanged serial needs no transfer, nor does one behind ours.
+        refresh(&config, &secondaries).unwrap();
+        secondaries.refreshed(&config.zone, None, 8, 600);
+        refresh(&config, &secondaries).unwrap();
+        assert!(secondaries
+            .report()
+            .contains("secondary.example.com.serial=8\n"));
+        assert_eq!(transfers(&primary), 1);
+
+        clock.advance(Duration::from_secs(599));
+        assert!(matches!(find("example.com"), Some(Served::Zone(_, 1))));
+        clock.advance(Duration::from_secs(1));
+        assert!(secondaries.expired(&config.zone));
+        assert!(matches!(
+            find("example.com"),
+            Some(Served::Unavailable(ResponseCode::Refused))
+        ));
+        assert!(secondaries
+            .report()
+            .contains("secondary.example.com.expired=1\n"));
+    }
+
+    #[test]
+    fn test_highest_serial() {
+        let (behind, ahead) = (primary(7), primary(9));
+        let down = MockUpstream::start(|query, _| {
+            let mut reply = response(query);
+            reply.header.rcode = ResponseCode::ServFail;
+            Reply::Send(reply)
+        });
+        let mut config = SecondaryConfig::parse("example.com=127.0.0.1").unwrap();
+        config.primaries = vec![behind.addr, down.addr, ahead.addr];
+        let secondaries = Secondaries::default();
+        secondaries.add(&config);
+
+        // One primary down doesn't stop the others being used.
+        refresh(&config, &secondaries).unwrap();
+        assert_eq!((transfers(&behind), transfers(&ahead)), (0, 1));
+        assert!(matches!(
+            secondaries.find(&"www.example.com".into()),
+            Some((_, Served::Zone(..)))
+        ));
+        let report = secondaries.report();
+        for line in [
+            "secondary.example.com.serial=9\n".to_string(),
+            format!("secondary.example.com.primary.{}.serial=7\n", behind.addr),
+            format!("secondary.example.com.primary.{}.serial=0\n", down.addr),
+            format!("secondary.example.com.primary.{}.failures=1\n", down.addr),
+            format!("secondary.example.com.primary.{}.failures=0\n", ahead.addr),
+        ] {
+            assert!(report.contains(&line), "{} in {}", line, report);
+        }
+
+        // Only the down primary left: the check fails.
+        config.primaries = vec![down.addr];
+        assert!(refresh(&config, &secondaries).is_err());
+    }
+}
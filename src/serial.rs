@@ -0,0 +1,112 @@
+//! SOA serial numbers, which wrap around and so compare in serial number
+//! arithmetic (RFC 1982) rather than as plain integers.
+//!
+//! ```
+//! use dns_starter_rust::serial;
+//!
+//! assert!(serial::is_newer(1, u32::MAX));
+//! assert_eq!(serial::increment(u32::MAX), 0);
+//! assert_eq!(serial::from_date(2024, 1, 31, 2024013105), 2024013106);
+//! ```
+
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::timezone;
+
+/// The largest step one serial can take past another and still be newer.
+pub const MAX_INCREMENT: u32 = (1 << 31) - 1;
+
+/// Orders two serials. Serials exactly half the number space apart have no
+/// defined order, which gives `None`.
+pub fn compare(a: u32, b: u32) -> Option<Ordering> {
+    match a.wrapping_sub(b) {
+        0 => Some(Ordering::Equal),
+        1..=MAX_INCREMENT => Some(Ordering::Greater),
+        0x8000_0000 => None,
+        _ => Some(Ordering::Less),
+    }
+}
+
+/// Whether `a` is a later serial than `b`.
+pub fn is_newer(a: u32, b: u32) -> bool {
+    compare(a, b) == Some(Ordering::Greater)
+}
+
+/// Adds `n` to a serial, wrapping past `u32::MAX`. `n` is capped at
+/// [`MAX_INCREMENT`] so the result is always newer, unless `n` is zero.
+pub fn add(serial: u32, n: u32) -> u32 {
+    serial.wrapping_add(n.min(MAX_INCREMENT))
+}
+
+/// The serial after `serial`.
+pub fn increment(serial: u32) -> u32 {
+    add(serial, 1)
+}
+
+/// A serial in the `YYYYMMDDnn` convention for a change made on the given
+/// day: the day's first, `YYYYMMDD00`, if that is newer than `previous`,
+/// and otherwise the serial after `previous`, so that a hundredth change
+/// in one day, or a clock set back, still moves the serial forward.
+pub fn from_date(year: u32, month: u32, day: u32, previous: u32) -> u32 {
+    let first = year
+        .saturating_mul(1_000_000)
+        .saturating_add(month * 10_000)
+        .saturating_add(day * 100);
+    if is_newer(first, previous) {
+        first
+    } else {
+        increment(previous)
+    }
+}
+
+/// [`from_date`] for today, in UTC.
+pub fn today(previous: u32) -> u32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = timezone::civil(secs as i64);
+    from_date(year as u32, month, day, previous)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(compare(7, 7), Some(Ordering::Equal));
+        assert_eq!(compare(2, 1), Some(Ordering::Greater));
+        assert_eq!(compare(1, 2), Some(Ordering::Less));
+        // Across the wrap, 0 follows the largest serial.
+        assert_eq!(compare(0, u32::MAX), Some(Ordering::Greater));
+        assert_eq!(compare(u32::MAX, 0), Some(Ordering::Less));
+        assert_eq!(compare(MAX_INCREMENT, 0), Some(Ordering::Greater));
+        assert_eq!(compare(1 << 31, 0), None);
+        assert_eq!(compare(0, 1 << 31), None);
+        assert!(is_newer(5, u32::MAX - 5));
+        assert!(!is_newer(1 << 31, 0));
+    }
+
+    #[test]
+    fn test_add() {
+        assert_eq!(increment(41), 42);
+        assert_eq!(increment(u32::MAX), 0);
+        assert_eq!(add(u32::MAX - 1, 3), 1);
+        let far = add(10, u32::MAX);
+        assert_eq!(far, 10 + MAX_INCREMENT);
+        assert!(is_newer(far, 10));
+    }
+
+    #[test]
+    fn test_from_date() {
+        assert_eq!(from_date(2024, 2, 29, 2024010107), 2024022900);
+        assert_eq!(from_date(2024, 2, 29, 2024022900), 2024022901);
+        // Past nn = 99, or behind the zone's serial, counting on.
+        assert_eq!(from_date(2024, 2, 29, 2024022999), 2024023000);
+        assert_eq!(from_date(2024, 2, 29, 2025010100), 2025010101);
+        // A small counting serial jumps to the date.
+        assert_eq!(from_date(2024, 2, 29, 17), 2024022900);
+        assert!(is_newer(today(2024010100), 2024010100));
+    }
+}
@@ -0,0 +1,1256 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::admin::Admin;
+use crate::authority::Zones;
+use crate::blocklist;
+use crate::common::{DnsType, Name};
+use crate::config::{self, Config, Listener, Protocol};
+use crate::consul;
+use crate::control::Control;
+use crate::diagnostics;
+use crate::edns::{Edns, EdnsOption};
+use crate::fault::Injected;
+use crate::handler::{self, State};
+use crate::handoff;
+use crate::header::{DnsHeader, OpCode, PacketType, ResponseCode};
+use crate::healthcheck;
+use crate::hints;
+use crate::kubernetes;
+use crate::packet::DnsPacket;
+use crate::pktinfo;
+use crate::queue::Queue;
+use crate::recursor::Recursor;
+use crate::secondary;
+use crate::stats::Stats;
+use crate::tkey;
+use crate::transfer;
+use crate::trust::{self, TrustAnchors};
+use crate::tsig;
+use crate::update;
+use crate::warm;
+
+// Queries on one TCP connection answered at once; reading more waits.
+const MAX_PIPELINED: usize = 16;
+
+// Everything a listener thread needs, cloned into each one.
+#[derive(Clone)]
+pub(crate) struct Shared {
+    pub(crate) config: Arc<Config>,
+    pub(crate) state: Arc<State>,
+    pub(crate) stats: Arc<Mutex<Stats>>,
+}
+
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let env = match config::env_args(env::vars_os()) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let config = match Config::from_args(env.into_iter().chain(args)) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    if config.aggressive_nsec {
+        eprintln!(
+            "Warning: --aggressive-nsec denies names from NSEC and NSEC3 records \
+             that are not DNSSEC-validated; one forged record denies a whole range"
+        );
+    }
+    let stats = Arc::new(Mutex::new(Stats::new()));
+    if let Some(interval) = config.stats_interval {
+        let stats = Arc::clone(&stats);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            eprint!("{}", stats.lock().unwrap().snapshot());
+        });
+    }
+
+    let zones = match Zones::load(&config) {
+        Ok(zones) => zones,
+        Err(e) => {
+            eprintln!("Failed to load zone {}", e);
+            return 1;
+        }
+    };
+    let blocklists = match blocklist::load_all(&config, &[]) {
+        Ok(blocklists) => blocklists,
+        Err(e) => {
+            eprintln!("Failed to load blocklist {}", e);
+            return 1;
+        }
+    };
+    for list in &blocklists {
+        println!("Blocklist {}: {} domains", list.name, list.len());
+    }
+    let state = Arc::new(State::new(zones));
+    // Before anything binds, so the sockets of the process upgraded from
+    // are used instead.
+    let handoff = match config
+        .handoff
+        .as_ref()
+        .map(|handoff| handoff::take_over(handoff, &state))
+    {
+        Some(Ok(handoff)) => handoff,
+        Some(Err(e)) => {
+            eprintln!("Failed to take over from the running server: {}", e);
+            None
+        }
+        None => None,
+    };
+    if config.recursion {
+        if let Some(path) = &config.root_hints {
+            match hints::load(path) {
+                Ok(roots) => state.roots.set(roots),
+                Err(e) => {
+                    eprintln!("Failed to load root hints {}", e);
+                    return 1;
+                }
+            }
+        }
+        prime(Arc::clone(&config), Arc::clone(&state));
+        if let Some(path) = &config.trust_anchor_file {
+            match TrustAnchors::load(path) {
+                Ok(anchors) => trust::maintain(
+                    path.clone(),
+                    anchors,
+                    Arc::clone(&config),
+                    Arc::clone(&state),
+                ),
+                Err(e) => {
+                    eprintln!("Failed to load trust anchors {}", e);
+                    return 1;
+                }
+            }
+        }
+    }
+    *state.blocklists.write().unwrap() = Arc::new(blocklists);
+    blocklist::update(Arc::clone(&config), Arc::clone(&state));
+    for secondary in &config.secondaries {
+        secondary::maintain(secondary.clone(), Arc::clone(&state));
+    }
+    healthcheck::maintain(Arc::clone(&state));
+    if let Some(consul) = &config.consul {
+        consul::poll(consul.clone(), Arc::clone(&state.catalog));
+    }
+    if let Some(kubernetes) = &config.kubernetes {
+        kubernetes::poll(kubernetes.clone(), Arc::clone(&state.endpoints));
+    }
+
+    if let Some(path) = &config.control_socket {
+        let control = Control::new(Arc::clone(&config), Arc::clone(&state), Arc::clone(&stats));
+        if let Err(e) = control.serve(path) {
+            eprintln!("Failed to listen on {}: {}", path.display(), e);
+        }
+    }
+
+    if let Some(addr) = config.admin_listen {
+        let admin = Admin::new(Arc::clone(&config), Arc::clone(&state), Arc::clone(&stats));
+        if let Err(e) = admin.serve(addr) {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+        }
+    }
+
+    diagnostics::on_sigusr1(Arc::clone(&config), Arc::clone(&state), Arc::clone(&stats));
+
+    let shared = Shared {
+        config: Arc::clone(&config),
+        state,
+        stats,
+    };
+    let threads = match listen(&shared) {
+        Ok(threads) => threads,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    shared.state.health.listening(threads.len());
+    if let Some(handoff) = handoff {
+        match handoff.ready() {
+            Ok(()) => println!("Took over from the running server"),
+            Err(e) => eprintln!("Failed to complete the handoff: {}", e),
+        }
+    }
+    if let Some(handoff) = &config.handoff {
+        if let Err(e) = handoff::serve(handoff.clone(), Arc::clone(&shared.state)) {
+            eprintln!("Failed to listen on {}: {}", handoff.path.display(), e);
+        }
+    }
+    if let Some(warm) = &config.warm {
+        match warm::load(&warm.path) {
+            Ok(questions) => warm::start(questions, Arc::clone(&config), Arc::clone(&shared.state)),
+            Err(e) => eprintln!("Not warming the cache: {}", e),
+        }
+        if let Some(count) = warm.save {
+            warm::persist(warm.clone(), count, Arc::clone(&shared.stats));
+        }
+    }
+    // Listeners only return on fatal socket errors.
+    for (_, thread) in threads {
+        let _ = thread.join();
+    }
+    1
+}
+
+// Refreshes the root server addresses from the roots themselves, in the
+// background so that listening doesn't wait on it. Recursion starts from
+// the hints in the meantime, and keeps them if priming fails.
+fn prime(config: Arc<Config>, state: Arc<State>) {
+    thread::spawn(move || {
+        let recursor = Recursor::new(
+            config.recursion_limits,
+            config.max_udp_size,
+            &state.roots.get(),
+            Arc::clone(&state.reputation),
+        );
+        match recursor.prime(Instant::now() + config.query_budget) {
+            Ok(roots) => {
+                println!("Primed {} root server addresses", roots.len());
+                state.roots.set(roots);
+            }
+            Err(e) => eprintln!("Root priming failed, using hints: {}", e),
+        }
+    });
+}
+
+// Binds every configured listener and serves each socket on its own thread,
+// returning the bound addresses alongside the threads.
+pub(crate) fn listen(shared: &Shared) -> Result<Vec<(SocketAddr, JoinHandle<()>)>, String> {
+    let config = &shared.config;
+    // Wildcard IPv6 sockets are bound first: on dual-stack hosts they also
+    // take the IPv4 wildcard, and `0.0.0.0` on the same port then fails.
+    let mut order: Vec<&Listener> = config.listeners.iter().collect();
+    order.sort_by_key(|listener| !(listener.addr.is_ipv6() && listener.addr.ip().is_unspecified()));
+
+    let mut threads = Vec::new();
+    for listener in order {
+        for &protocol in &listener.protocols {
+            let bound = match protocol {
+                Protocol::Udp => shared.state.sockets.udp(listener.addr).and_then(|socket| {
+                    let addr = socket.local_addr()?;
+                    let (listener, shared) = (listener.clone(), shared.clone());
+                    let thread = thread::spawn(move || {
+                        serve_udp(socket, &listener, &shared);
+                        shared.state.health.listener_stopped();
+                    });
+                    Ok((addr, thread))
+                }),
+                Protocol::Tcp => {
+                    shared
+                        .state
+                        .sockets
+                        .tcp("tcp", listener.addr)
+                        .and_then(|socket| {
+                            let addr = socket.local_addr()?;
+                            let (listener, shared) = (listener.clone(), shared.clone());
+                            let thread = thread::spawn(move || {
+                                serve_tcp(socket, &listener, &shared);
+                                shared.state.health.listener_stopped();
+                            });
+                            Ok((addr, thread))
+                        })
+                }
+            };
+            match bound {
+                Ok(thread) => threads.push(thread),
+                Err(e)
+                    if e.kind() == io::ErrorKind::AddrInUse
+                        && dual_stack(config, listener, protocol) =>
+                {
+                    eprintln!(
+                        "{} {} is served by the dual-stack [::]:{} listener",
+                        protocol,
+                        listener.addr,
+                        listener.addr.port()
+                    );
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "Failed to bind {} {}: {}",
+                        protocol, listener.addr, e
+                    ))
+                }
+            }
+        }
+    }
+    Ok(threads)
+}
+
+fn dual_stack(config: &Config, listener: &Listener, protocol: Protocol) -> bool {
+    listener.addr.is_ipv4()
+        && listener.addr.ip().is_unspecified()
+        && config.listeners.iter().any(|other| {
+            other.addr.is_ipv6()
+                && other.addr.ip().is_unspecified()
+                && other.addr.port() == listener.addr.port()
+                && other.protocols.contains(&protocol)
+        })
+}
+
+fn serve_udp(socket: UdpSocket, listener: &Listener, shared: &Shared) {
+    // On a wildcard address replies have to name the address each query
+    // arrived on as their source.
+    if listener.addr.ip().is_unspecified() {
+        if let Err(e) = pktinfo::enable(&socket) {
+            eprintln!(
+                "Replies on udp {} may come from the wrong address: {}",
+                listener.addr, e
+            );
+        }
+    }
+    // Queries that waited longer than it takes to answer one would be
+    // answered after their clients gave up.
+    let max_wait = shared.config.query_budget;
+    let queue: Queue<(Vec<u8>, SocketAddr, Option<pktinfo::Destination>)> =
+        Queue::new(shared.config.queue, &shared.state.queue);
+    thread::scope(|scope| {
+        for _ in 0..shared.config.queue.workers {
+            scope.spawn(|| {
+                while let Some((received, source, destination)) = queue.pop(max_wait) {
+                    for response in respond(&received, source, listener, Protocol::Udp, shared) {
+                        if let Err(e) =
+                            pktinfo::send(&socket, &response, source, destination.as_ref())
+                        {
+                            let client = shared.config.logged_client(source.ip().to_canonical());
+                            eprintln!("Failed to send response to {}: {}", client, e);
+                        }
+                    }
+                }
+            });
+        }
+        let mut buf = vec![0; shared.config.max_udp_size as usize];
+        loop {
+            match pktinfo::recv(&socket, &mut buf) {
+                Ok((size, source, destination)) => {
+                    queue.push((buf[..size].to_vec(), source, destination));
+                }
+                Err(e) => {
+                    eprintln!("Error receiving data on udp {}: {}", listener.addr, e);
+                    queue.close();
+                    return;
+                }
+            }
+        }
+    })
+}
+
+fn serve_tcp(socket: TcpListener, listener: &Listener, shared: &Shared) {
+    for stream in socket.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Error accepting connection on tcp {}: {}", listener.addr, e);
+                continue;
+            }
+        };
+        let (listener, shared) = (listener.clone(), shared.clone());
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &listener, &shared) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    eprintln!("Closing connection on tcp {}: {}", listener.addr, e);
+                }
+            }
+        });
+    }
+}
+
+// Answers length-prefixed messages (RFC 7766) until the client closes the
+// connection, stays idle too long, reaches the end of its lifetime or is
+// evicted to make room for a newer one. Pipelined queries are answered
+// concurrently, each response written as soon as it is ready; clients match
+// them up by ID.
+fn serve_connection(mut stream: TcpStream, listener: &Listener, shared: &Shared) -> io::Result<()> {
+    let source = stream.peer_addr()?;
+    let limits = shared.config.tcp;
+    let connection = shared
+        .state
+        .connections
+        .open(&stream, limits.max_connections)?;
+    let closes = limits.lifetime.map(|lifetime| Instant::now() + lifetime);
+    let writer = Mutex::new(stream.try_clone()?);
+    let (done, finished) = mpsc::channel();
+    let mut pending = 0;
+    // Queries still being answered hold the connection open after the last
+    // read; the scope waits for their responses.
+    thread::scope(|scope| loop {
+        let idle = match closes {
+            Some(closes) => limits
+                .idle
+                .min(closes.saturating_duration_since(Instant::now())),
+            None => limits.idle,
+        };
+        if idle.is_zero() {
+            return Ok(());
+        }
+        stream.set_read_timeout(Some(idle))?;
+        let mut len = [0; 2];
+        match stream.read_exact(&mut len) {
+            Ok(()) => {}
+            // Idle for too long: an ordinary close, not worth a log line.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        }
+        connection.active();
+        let mut message = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut message)?;
+
+        pending -= finished.try_iter().count();
+        if pending == MAX_PIPELINED {
+            let _ = finished.recv();
+            pending -= 1;
+        }
+        pending += 1;
+        let (writer, done) = (&writer, done.clone());
+        scope.spawn(move || {
+            let responses = respond(&message, source, listener, Protocol::Tcp, shared);
+            if !responses.is_empty() {
+                // A transfer's messages go out together, so pipelined
+                // responses can't come between them.
+                let mut framed = Vec::new();
+                for response in responses {
+                    framed.extend_from_slice(&(response.len() as u16).to_be_bytes());
+                    framed.extend_from_slice(&response);
+                }
+                let mut writer = writer.lock().unwrap();
+                if writer.write_all(&framed).is_err() {
+                    // The reading side sees the connection close too.
+                    let _ = writer.shutdown(Shutdown::Both);
+                }
+            }
+            let _ = done.send(());
+        });
+    })
+}
+
+// An empty FORMERR response to a query that couldn't be parsed past its
+// header.
+fn format_error(query: DnsHeader) -> Vec<u8> {
+    let header = DnsHeader {
+        qr: PacketType::Response,
+        aa: false,
+        tc: false,
+        ra: false,
+        z: 0,
+        rcode: ResponseCode::FormatError,
+        qdcount: 0,
+        ancount: 0,
+        nscount: 0,
+        arcount: 0,
+        ..query
+    };
+    let mut bytes = Vec::with_capacity(12);
+    header.write(&mut bytes);
+    bytes
+}
+
+fn respond(
+    received: &[u8],
+    source: SocketAddr,
+    listener: &Listener,
+    protocol: Protocol,
+    shared: &Shared,
+) -> Vec<Vec<u8>> {
+    let start = Instant::now();
+    // Normalise IPv4 clients reaching a dual-stack socket.
+    let client = source.ip().to_canonical();
+    let logged = shared.config.logged_client(client);
+    match &listener.view {
+        Some(view) => println!(
+            "Received {} bytes from {} (view {})",
+            received.len(),
+            logged,
+            view
+        ),
+        None => println!("Received {} bytes from {}", received.len(), logged),
+    }
+    // Responses are never answered, so two servers can't keep answering
+    // each other. Anything else with a readable header gets FORMERR.
+    let header = match DnsHeader::try_from(received) {
+        Ok(header) if header.qr == PacketType::Query => header,
+        Ok(_) => {
+            eprintln!("Dropping a response from {}", logged);
+            return Vec::new();
+        }
+        Err(e) => {
+            eprintln!("Dropping malformed query from {}: {}", logged, e);
+            return Vec::new();
+        }
+    };
+    let mut packet = match DnsPacket::try_from(received) {
+        Ok(packet) => packet,
+        Err(e) => {
+            eprintln!("Malformed query from {}: {}", logged, e);
+            return vec![format_error(header)];
+        }
+    };
+    // Requests signed with a `--transfer` or `--allow-update` key, or one
+    // negotiated through TKEY, get signed responses; any other signature
+    // gets NOTAUTH.
+    let now = tsig::now();
+    let find = |name: &Name| {
+        let key = shared.config.transfers.key(name);
+        key.or_else(|| shared.config.updates.key(name))
+            .or_else(|| shared.state.tkeys.find(name, now))
+    };
+    let signed = match tkey::verify(received, find, now) {
+        Ok(signed) => signed,
+        Err(e) => {
+            eprintln!("Refusing a request from {}: {}", logged, e);
+            packet.header.flip_qr();
+            packet.header.rcode = ResponseCode::NotAuth;
+            packet.answers.clear();
+            packet.authorities.clear();
+            packet.additionals.clear();
+            packet.edns = None;
+            return vec![packet.to_bytes()];
+        }
+    };
+    packet
+        .additionals
+        .retain(|record| record.qtype != DnsType::Tsig);
+    let advertised = packet.edns.as_ref().map(|edns| edns.udp_payload_size);
+    let keepalive = packet.edns.as_ref().is_some_and(Edns::keepalive_requested);
+    let qtype = packet.questions.first().map(|question| question.qtype);
+    let signer = signed.as_ref().map(|(key, _)| &key.name);
+    let view = listener.view.as_deref();
+    // Zone transfers take TCP; over UDP they get NOTIMP like other meta
+    // types.
+    let mut packets = match (packet.header.opcode, qtype, protocol) {
+        (OpCode::Update, _, _) => {
+            // A key negotiated through TKEY updates what the configured key
+            // it was agreed with may.
+            let negotiated = signer.and_then(|name| shared.state.tkeys.requester(name, now));
+            let signer = negotiated.as_ref().or(signer);
+            vec![update::serve(
+                packet,
+                received,
+                view,
+                signer,
+                &shared.config,
+                &shared.state,
+            )]
+        }
+        (_, Some(DnsType::Tkey), _) => {
+            // Keys are agreed only with those holding a configured one.
+            let configured = |name: &&Name| {
+                shared.config.transfers.key(name).is_some()
+                    || shared.config.updates.key(name).is_some()
+            };
+            let requester = match signer {
+                Some(signer) => Some(signer).filter(configured).cloned(),
+                None => {
+                    let zones = shared.state.zones();
+                    shared.config.updates.sig0_signer(received, &zones, now)
+                }
+            };
+            let tkeys = &shared.state.tkeys;
+            vec![tkey::answer(packet, signer, requester.as_ref(), tkeys, now)]
+        }
+        (_, Some(DnsType::Axfr | DnsType::Ixfr), Protocol::Tcp) => {
+            transfer::serve(packet, view, signer, &shared.config, &shared.state)
+        }
+        _ => vec![handler::handle(
+            packet,
+            client,
+            view,
+            &shared.config,
+            &shared.state,
+        )],
+    };
+    let mut packet = packets.remove(0);
+    // `--fault` rules strike once the query is answered, as a faulty
+    // server or network would.
+    let injected = match packet.questions.first() {
+        Some(question) => shared.config.faults.draw(&question.qname),
+        None => Injected::default(),
+    };
+    let truncate = injected.truncate && protocol == Protocol::Udp;
+    if injected.servfail || truncate {
+        if injected.servfail {
+            packet.header.rcode = ResponseCode::ServFail;
+        }
+        packet.header.tc |= truncate;
+        packet.answers.clear();
+        packet.authorities.clear();
+        packet.additionals.clear();
+        packets.clear();
+    }
+    let question = packet.questions.first();
+    let (name, qtype, rcode) = (
+        question.map(|q| q.qname.to_string()).unwrap_or_default(),
+        question.map(|q| q.qtype.to_string()).unwrap_or_default(),
+        packet.header.rcode,
+    );
+    let compress = shared.config.compression;
+    let (response, saved) = match protocol {
+        Protocol::Udp => {
+            let limit = handler::udp_limit(advertised, &shared.config);
+            handler::fit(packet, limit, compress)
+        }
+        Protocol::Tcp => {
+            // Tells clients that asked how long they may keep the
+            // connection open for further queries.
+            if let (true, Some(edns)) = (keepalive, &mut packet.edns) {
+                let timeout = shared.config.tcp.keepalive();
+                edns.options.push(EdnsOption::TcpKeepalive(Some(timeout)));
+            }
+            packet.encode(compress)
+        }
+    };
+    let mut responses = vec![response];
+    responses.extend(packets.iter().map(|packet| packet.encode(compress).0));
+    // Each message of a transfer is signed chaining on the one before.
+    if let Some((key, tsig)) = &signed {
+        let mut mac = tsig.mac.clone();
+        for (i, response) in responses.iter_mut().enumerate() {
+            let (signed, next) = match i {
+                0 => key.sign(response, Some(&mac), tsig::now()),
+                _ => key.sign_subsequent(response, &mac, tsig::now()),
+            };
+            (*response, mac) = (signed, next);
+        }
+    }
+
+    if injected.drop {
+        println!("Dropping the response to {} (fault injection)", logged);
+        return Vec::new();
+    }
+    thread::sleep(injected.delay);
+
+    let mut stats = shared.stats.lock().unwrap();
+    stats.record(&name, &qtype, logged, rcode, start.elapsed());
+    let size = responses.iter().map(Vec::len).sum();
+    stats.record_size(size, saved);
+    responses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::common::{DnsClass, DnsType};
+    use crate::crypto::base64_encode;
+    use crate::error::ResolveError;
+    use crate::forward::UpstreamGroup;
+    use crate::question::DnsQuestion;
+    use crate::resolver::Resolver;
+    use crate::sig0::Sig0Key;
+    use crate::tsig::TsigKey;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn shared(config: Config) -> Shared {
+        let zones = Zones::load(&config).unwrap();
+        Shared {
+            config: Arc::new(config),
+            state: Arc::new(State::new(zones)),
+            stats: Arc::new(Mutex::new(Stats::new())),
+        }
+    }
+
+    // Listeners serving a zone with www.example.com A 192.0.2.80.
+    fn start(name: &str, specs: &[&str]) -> Vec<SocketAddr> {
+        let zone = std::env::temp_dir().join(format!(
+            "dns-server-test-{}-{}.zone",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(
+            &zone,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\nwww A 192.0.2.80\n",
+        )
+        .unwrap();
+        let config = Config {
+            listeners: specs
+                .iter()
+                .map(|spec| Listener::parse(spec).unwrap())
+                .collect(),
+            zones: vec![(None, zone.clone())],
+            control_socket: None,
+            ..Config::default()
+        };
+        let shared = shared(config);
+        std::fs::remove_file(&zone).unwrap();
+        let threads = listen(&shared).unwrap();
+        threads.into_iter().map(|(addr, _)| addr).collect()
+    }
+
+    #[test]
+    fn test_udp_and_tcp_listeners() {
+        let addrs = start(
+            "listeners",
+            &["127.0.0.1:0,udp", "127.0.0.1:0,tcp,view=internal"],
+        );
+        assert_eq!(addrs.len(), 2);
+
+        let udp = Resolver::new(vec![addrs[0]]).with_timeout(Duration::from_secs(2));
+        let tcp = Resolver::new(vec![addrs[1]])
+            .with_timeout(Duration::from_secs(2))
+            .with_tcp(true);
+        let expected = vec![IpAddr::from([192, 0, 2, 80])];
+        assert_eq!(udp.lookup_ip("www.example.com").unwrap(), expected);
+        assert_eq!(tcp.lookup_ip("www.example.com").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_idle_tcp_close() {
+        let mut config = Config {
+            control_socket: None,
+            ..Config::default()
+        };
+        config.tcp.idle = Duration::from_millis(50);
+        let shared = shared(config);
+        let listener = Listener::parse("127.0.0.1:0,tcp").unwrap();
+        let socket = TcpListener::bind("127.0.0.1:0").unwrap();
+        let connect = || {
+            let client = TcpStream::connect(socket.local_addr().unwrap()).unwrap();
+            (client, socket.accept().unwrap().0)
+        };
+
+        // An idle client is closed quietly; one that stops halfway
+        // through a message isn't.
+        let (_idle, stream) = connect();
+        assert!(serve_connection(stream, &listener, &shared).is_ok());
+        let (mut stalled, stream) = connect();
+        stalled.write_all(&[0, 12, 0x12]).unwrap();
+        assert!(serve_connection(stream, &listener, &shared).is_err());
+    }
+
+    #[test]
+    fn test_tcp_keepalive() {
+        let addrs = start("keepalive", &["127.0.0.1:0,udp", "127.0.0.1:0,tcp"]);
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let mut query = DnsPacket::query(1, question);
+        let mut edns = Edns::new(1232);
+        edns.options.push(EdnsOption::TcpKeepalive(None));
+        query.edns = Some(edns);
+
+        // Only TCP responses carry the option.
+        for (addr, tcp, expected) in [
+            (addrs[1], true, vec![EdnsOption::TcpKeepalive(Some(100))]),
+            (addrs[0], false, vec![]),
+        ] {
+            let resolver = Resolver::new(vec![addr])
+                .with_timeout(Duration::from_secs(2))
+                .with_tcp(tcp);
+            let response = resolver.send(&query, None).unwrap();
+            assert_eq!(response.edns.unwrap().options, expected, "tcp: {}", tcp);
+        }
+    }
+
+    #[test]
+    fn test_negotiated_keys() {
+        let mut config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,udp").unwrap()],
+            control_socket: None,
+            ..Config::default()
+        };
+        config
+            .updates
+            .add("example.com,key=ddns-key:ZHluYW1pYyB1cGRhdGVz")
+            .unwrap();
+        let shared = shared(config);
+        let threads = listen(&shared).unwrap();
+        let resolver = Resolver::new(vec![threads[0].0]).with_timeout(Duration::from_secs(2));
+        let query = DnsPacket::query(
+            1,
+            DnsQuestion {
+                qname: "www.example.com".into(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            },
+        );
+        let key = TsigKey::new("dh.example.com".into(), vec![7; 32]);
+
+        // An unknown key gets an unsigned NOTAUTH, which the resolver
+        // won't take; once negotiated, the response is signed with it.
+        assert!(resolver.send(&query, Some(&key)).is_err());
+        let expires = tsig::now() + 60;
+        let requester = Name::from("ddns-key");
+        shared
+            .state
+            .tkeys
+            .insert(key.clone(), &requester, expires, tsig::now());
+        let response = resolver.send(&query, Some(&key)).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        // Only the response's own TSIG, not the request's as well.
+        let types: Vec<DnsType> = response.additionals.iter().map(|r| r.qtype).collect();
+        assert_eq!(types, vec![DnsType::Tsig]);
+
+        // More keys only for the configured ones, not for anyone or for a
+        // negotiated key.
+        let configured = TsigKey::new("ddns-key".into(), b"dynamic updates".to_vec());
+        let error = |key: Option<&TsigKey>| {
+            let response = resolver.send(&tkey::test::dh_query(), key).unwrap();
+            tkey::test::response_tkey(&response).error
+        };
+        let unsigned = error(None);
+        assert_ne!(unsigned, 0);
+        assert_eq!(error(Some(&key)), unsigned);
+        assert_eq!(error(Some(&configured)), 0);
+        // The new key by the same name replaces the old.
+        assert!(resolver.send(&query, Some(&key)).is_err());
+    }
+
+    #[test]
+    fn test_view_transfers() {
+        let zone = std::env::temp_dir().join(format!(
+            "dns-server-test-transfers-{}.zone",
+            std::process::id()
+        ));
+        let mut text = String::from(
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\nintranet.corp A 10.0.0.1\n",
+        );
+        // Enough records to take several messages.
+        for i in 0..300 {
+            text += &format!("txt{} TXT \"{}\"\n", i, "x".repeat(100));
+        }
+        std::fs::write(&zone, text).unwrap();
+        let mut config = Config {
+            listeners: vec![
+                Listener::parse("127.0.0.1:0,tcp,view=internal").unwrap(),
+                Listener::parse("127.0.0.1:0,tcp,view=external").unwrap(),
+                Listener::parse("127.0.0.1:0,udp,view=external").unwrap(),
+            ],
+            zones: vec![(None, zone.clone())],
+            control_socket: None,
+            ..Config::default()
+        };
+        config
+            .policies
+            .add("corp.example.com:deny=A,view=external")
+            .unwrap();
+        config
+            .transfers
+            .add("example.com,view=internal,key=xfr:aW50ZXJuYWwgc2VjcmV0")
+            .unwrap();
+        config.transfers.add("example.com,view=external").unwrap();
+        let shared = shared(config);
+        std::fs::remove_file(&zone).unwrap();
+        let threads = listen(&shared).unwrap();
+        let (internal, external) = (threads[0].0, threads[1].0);
+        let apex = Name::from("example.com");
+        let timeout = Duration::from_secs(5);
+        let names = |transfer: transfer::Transfer| -> Vec<String> {
+            transfer
+                .records
+                .iter()
+                .map(|r| r.name.to_string())
+                .collect()
+        };
+
+        // The internal view takes the key, and gets every record.
+        let key = TsigKey::new("xfr".into(), b"internal secret".to_vec());
+        assert!(matches!(
+            transfer::axfr(internal, &apex, None, timeout),
+            Err(ResolveError::Rcode(5))
+        ));
+        let records = names(transfer::axfr(internal, &apex, Some(&key), timeout).unwrap());
+        assert_eq!(records.len(), 304);
+        assert!(records.iter().any(|name| name.starts_with("intranet")));
+
+        // The external view doesn't, and doesn't get what its policy hides.
+        let records = names(transfer::axfr(external, &apex, None, timeout).unwrap());
+        assert_eq!(records.len(), 303);
+        assert!(!records.iter().any(|name| name.starts_with("intranet")));
+        let other = Name::from("example.org");
+        assert!(matches!(
+            transfer::axfr(external, &other, None, timeout),
+            Err(ResolveError::Rcode(9))
+        ));
+
+        // Not over UDP.
+        let resolver = Resolver::new(vec![threads[2].0]).with_timeout(timeout);
+        let query = DnsPacket::query(
+            1,
+            DnsQuestion {
+                qname: apex,
+                qtype: DnsType::Axfr,
+                qclass: DnsClass::In,
+            },
+        );
+        let response = resolver.send(&query, None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NotImp);
+    }
+
+    #[test]
+    fn test_dynamic_updates() {
+        let zone = std::env::temp_dir().join(format!(
+            "dns-server-test-updates-{}.zone",
+            std::process::id()
+        ));
+        std::fs::write(
+            &zone,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300 ; serial 1\n\
+             @ NS ns1\nns1 A 192.0.2.1\n",
+        )
+        .unwrap();
+        let mut config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,udp").unwrap()],
+            zones: vec![(None, zone.clone())],
+            control_socket: None,
+            ..Config::default()
+        };
+        config
+            .updates
+            .add("example.com,key=ddns:ZHluYW1pYyB1cGRhdGVz")
+            .unwrap();
+        let shared = shared(config);
+        let threads = listen(&shared).unwrap();
+        let resolver = Resolver::new(vec![threads[0].0]).with_timeout(Duration::from_secs(2));
+        let key = TsigKey::new("ddns".into(), b"dynamic updates".to_vec());
+
+        let mut update = update::Update::new("example.com".into());
+        update.require_name_not_in_use("www.example.com".into());
+        update.add(DnsAnswer {
+            name: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+            ttl: 60,
+            rdata: RData::A([192, 0, 2, 80]),
+        });
+        let query = update.to_packet(1);
+
+        // Only with the key, and only once: the name is in use after.
+        let response = resolver.send(&query, None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        let response = resolver.send(&query, Some(&key)).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        let response = resolver.send(&query, Some(&key)).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::YxDomain);
+        // A key negotiated through TKEY stands for the one it was agreed
+        // with.
+        let expires = tsig::now() + 60;
+        for (name, requester, rcode) in [
+            ("dh.example.com", "ddns", ResponseCode::YxDomain),
+            ("other.example.com", "xfr", ResponseCode::Refused),
+        ] {
+            let negotiated = TsigKey::new(name.into(), vec![7; 32]);
+            let tkeys = &shared.state.tkeys;
+            tkeys.insert(negotiated.clone(), &requester.into(), expires, tsig::now());
+            let response = resolver.send(&query, Some(&negotiated)).unwrap();
+            assert_eq!(response.header.rcode, rcode, "{}", name);
+        }
+
+        let response = resolver
+            .query("www.example.com", DnsType::A, DnsClass::In)
+            .unwrap();
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+        let zones = shared.state.zones();
+        assert_eq!(zones.find(&"example.com".into()).unwrap().serial(), Some(2));
+
+        // And in the zone file, as written apart from the change.
+        assert_eq!(
+            std::fs::read_to_string(&zone).unwrap(),
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 2 7200 900 1209600 300 ; serial 1\n\
+             @ NS ns1\nns1 A 192.0.2.1\n\
+             www.example.com.\t60\tIN\tA\t192.0.2.80\n"
+        );
+        std::fs::remove_file(&zone).unwrap();
+    }
+
+    #[test]
+    fn test_sig0_updates() {
+        let dir = std::env::temp_dir().join(format!("dns-server-test-sig0-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Khost.example.com.+008+00001.private");
+        std::fs::write(&path, crate::sig0::test::private_key_file()).unwrap();
+        let key = Sig0Key::load(&path).unwrap();
+        let zone = dir.join("example.com.zone");
+        std::fs::write(
+            &zone,
+            format!(
+                "$ORIGIN example.com.\n$TTL 300\n\
+                 @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+                 @ NS ns1\nns1 A 192.0.2.1\nhost KEY 512 3 8 {}\n",
+                base64_encode(&key.key.public_key)
+            ),
+        )
+        .unwrap();
+        let mut config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,udp").unwrap()],
+            zones: vec![(None, zone)],
+            control_socket: None,
+            ..Config::default()
+        };
+        config
+            .updates
+            .add("example.com,sig0=host.example.com")
+            .unwrap();
+        let shared = shared(config);
+        let threads = listen(&shared).unwrap();
+        let resolver = || Resolver::new(vec![threads[0].0]).with_timeout(Duration::from_secs(2));
+
+        let mut update = update::Update::new("example.com".into());
+        update.add(DnsAnswer {
+            name: "host.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+            ttl: 60,
+            rdata: RData::A([192, 0, 2, 7]),
+        });
+        let query = update.to_packet(1);
+
+        // Signed by the key the zone publishes, and only then.
+        let response = resolver().send(&query, None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        let signed = resolver().with_sig0(key.clone());
+        let response = signed.send(&query, None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+        let zones = shared.state.zones();
+        let records = zones
+            .find(&"example.com".into())
+            .unwrap()
+            .records_at(&"host.example.com".into(), DnsType::A);
+        assert_eq!(records[0].rdata, RData::A([192, 0, 2, 7]));
+
+        // The key can negotiate TSIG keys too.
+        let tkey = |resolver: Resolver| {
+            let response = resolver.send(&tkey::test::dh_query(), None).unwrap();
+            tkey::test::response_tkey(&response).error
+        };
+        assert_eq!(tkey(resolver().with_sig0(key.clone())), 0);
+
+        // A key of the same name that isn't the one published.
+        let mut other = key;
+        other.key.public_key[5] ^= 1;
+        assert_ne!(tkey(resolver().with_sig0(other.clone())), 0);
+        let response = resolver().with_sig0(other).send(&query, None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NotAuth);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_transfer() {
+        let zone =
+            std::env::temp_dir().join(format!("dns-server-test-ixfr-{}.zone", std::process::id()));
+        std::fs::write(
+            &zone,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\n",
+        )
+        .unwrap();
+        let mut config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,tcp").unwrap()],
+            zones: vec![(None, zone.clone())],
+            control_socket: None,
+            ..Config::default()
+        };
+        config.updates.add("example.com").unwrap();
+        config.transfers.add("example.com").unwrap();
+        let shared = shared(config);
+        let threads = listen(&shared).unwrap();
+        let resolver = Resolver::new(vec![threads[0].0])
+            .with_timeout(Duration::from_secs(2))
+            .with_tcp(true);
+
+        let mut update = update::Update::new("example.com".into());
+        update.delete_rrset("ns1.example.com".into(), DnsType::A);
+        update.add(DnsAnswer {
+            name: "ns1.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+            ttl: 300,
+            rdata: RData::A([192, 0, 2, 2]),
+        });
+        let response = resolver.send(&update.to_packet(1), None).unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::NoError);
+
+        let ixfr = |serial: u32| {
+            let zones = shared.state.zones();
+            let mut soa = zones.find(&"example.com".into()).unwrap().soa().unwrap();
+            if let RData::Soa {
+                serial: ref mut at, ..
+            } = soa.rdata
+            {
+                *at = serial;
+            }
+            let mut query = DnsPacket::query(
+                2,
+                DnsQuestion {
+                    qname: "example.com".into(),
+                    qtype: DnsType::Ixfr,
+                    qclass: DnsClass::In,
+                },
+            );
+            query.authorities.push(soa);
+            let response = resolver.send(&query, None).unwrap();
+            assert_eq!(response.header.rcode, ResponseCode::NoError);
+            response
+                .answers
+                .iter()
+                .map(|record| format!("{} {}", record.qtype, record.rdata))
+                .collect::<Vec<_>>()
+        };
+
+        // From serial 1, just the change between the SOAs.
+        let answers = ixfr(1);
+        assert_eq!(answers.len(), 6);
+        assert!(answers[0].starts_with("SOA") && answers[0].contains(" 2 "));
+        assert!(answers[1].contains(" 1 "));
+        assert_eq!(answers[2], "A 192.0.2.1");
+        assert!(answers[3].contains(" 2 "));
+        assert_eq!(answers[4], "A 192.0.2.2");
+        assert_eq!(answers[5], answers[0]);
+        // Up to date, the SOA alone; from a serial the journal doesn't
+        // reach, the whole zone.
+        assert_eq!(ixfr(2).len(), 1);
+        assert_eq!(ixfr(0).len(), 4);
+        let report = shared.state.journal.report();
+        assert!(report.contains("transfers.incremental=2\n"), "{}", report);
+        assert!(report.contains("transfers.full=1\n"), "{}", report);
+        std::fs::remove_file(&zone).unwrap();
+    }
+
+    #[test]
+    fn test_fault_injection() {
+        let mut config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,udp").unwrap()],
+            control_socket: None,
+            ..Config::default()
+        };
+        for spec in [
+            "servfail,name=broken.example",
+            "drop,name=lost.example",
+            "tc,name=tc.example",
+        ] {
+            config.faults.add(spec).unwrap();
+        }
+        let threads = listen(&shared(config)).unwrap();
+        let query = |name: &str| {
+            let question = DnsQuestion {
+                qname: name.into(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            };
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let query = DnsPacket::query(7, question).to_bytes();
+            socket.send_to(&query, threads[0].0).unwrap();
+            let mut buf = [0; 512];
+            let size = socket.recv(&mut buf).ok()?;
+            Some(DnsPacket::try_from(&buf[..size]).unwrap())
+        };
+
+        let response = query("www.broken.example").unwrap();
+        assert_eq!(response.header.rcode, ResponseCode::ServFail);
+        let response = query("tc.example").unwrap();
+        assert!(response.header.tc);
+        assert_eq!(response.header.rcode, ResponseCode::Refused);
+        assert!(query("lost.example").is_none());
+        assert!(!query("other.example").unwrap().header.tc);
+    }
+
+    #[test]
+    fn test_tcp_pipelining() {
+        // An upstream that takes its time over slow.example.
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spec = format!("up={}", upstream.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                let (size, source) = upstream.recv_from(&mut buf).unwrap();
+                let mut response = DnsPacket::try_from(&buf[..size]).unwrap();
+                response.header.flip_qr();
+                let delay = match response.questions[0].qname.as_str() {
+                    "slow.example" => Duration::from_millis(300),
+                    _ => Duration::ZERO,
+                };
+                let upstream = upstream.try_clone().unwrap();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    upstream.send_to(&response.to_bytes(), source).unwrap();
+                });
+            }
+        });
+        let config = Config {
+            listeners: vec![Listener::parse("127.0.0.1:0,tcp").unwrap()],
+            upstreams: vec![UpstreamGroup::parse(&spec).unwrap()],
+            forward_zones: vec![(".".into(), "up".into())],
+            control_socket: None,
+            ..Config::default()
+        };
+        let threads = listen(&shared(config)).unwrap();
+
+        let mut stream = TcpStream::connect(threads[0].0).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        for (id, name) in [(1, "slow.example"), (2, "fast.example")] {
+            let question = DnsQuestion {
+                qname: name.into(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            };
+            let query = DnsPacket::query(id, question).to_bytes();
+            stream
+                .write_all(&(query.len() as u16).to_be_bytes())
+                .unwrap();
+            stream.write_all(&query).unwrap();
+        }
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            let mut response = vec![0; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut response).unwrap();
+            ids.push(DnsPacket::try_from(response.as_slice()).unwrap().header.id);
+        }
+        // The fast answer didn't wait behind the slow one.
+        assert_eq!(ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn test_ipv6_listener() {
+        // Hosts without IPv6 can't bind the loopback address at all.
+        if UdpSocket::bind("[::1]:0").is_err() {
+            return;
+        }
+        let addrs = start("ipv6", &["[::1]:0,udp"]);
+        let resolver = Resolver::new(addrs).with_timeout(Duration::from_secs(2));
+        assert!(resolver.lookup_ip("www.example.com").is_ok());
+    }
+
+    #[test]
+    fn test_bind_failure() {
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spec = format!("{},udp", taken.local_addr().unwrap());
+        let config = Config {
+            listeners: vec![Listener::parse(&spec).unwrap()],
+            control_socket: None,
+            ..Config::default()
+        };
+        assert!(listen(&shared(config)).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::Path;
+
+use crate::common::{DnsClass, DnsType, Name};
+use crate::crypto::{self, base64_decode};
+use crate::dnssec::{self, Dnskey, RSASHA256};
+use crate::error::TsigError;
+use crate::serial;
+use crate::tsig;
+
+// How far either side of the signing time a signature holds, for clocks
+// that disagree.
+const VALIDITY: u32 = 300;
+// The KEY flags of a key for a host rather than a zone (RFC 2535 section
+// 3.1.2), as `dnssec-keygen -T KEY -n HOST` makes them.
+const HOST_KEY: u16 = 0x0200;
+
+// A private key for SIG(0) transaction signatures (RFC 2931). Whoever checks
+// them only needs the public half, published as a KEY record under the
+// key's name in the zone, so no secret is shared as with TSIG. RSA/SHA-256
+// only.
+#[derive(Clone)]
+pub(crate) struct Sig0Key {
+    pub(crate) name: Name,
+    pub(crate) key: Dnskey,
+    private_exponent: Vec<u8>,
+}
+
+impl Sig0Key {
+    // A BIND private key file, `K<name>.+008+<tag>.private` as written by
+    // `dnssec-keygen -a RSASHA256 -T KEY -n HOST <name>`, which names the key.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix('K'))
+            .and_then(|name| name.split_once(".+"))
+            .map(|(name, _)| Name::from(name))
+            .ok_or_else(|| format!("{} is not named K<name>.+<alg>+<tag>", path.display()))?;
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        Sig0Key::parse(name, &text)
+    }
+
+    fn parse(name: Name, text: &str) -> Result<Self, String> {
+        let field = |wanted: &str| {
+            text.lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(field, _)| field.trim() == wanted)
+                .map(|(_, value)| value.trim())
+                .ok_or_else(|| format!("private key has no {} field", wanted))
+        };
+        let algorithm = field("Algorithm")?;
+        if algorithm.split_whitespace().next() != Some("8") {
+            return Err(format!(
+                "unsupported key algorithm {}, only 8 (RSASHA256) is",
+                algorithm
+            ));
+        }
+        let number = |wanted: &str| {
+            base64_decode(field(wanted)?).ok_or_else(|| format!("{} is not valid base64", wanted))
+        };
+        let exponent = number("PublicExponent")?;
+        let modulus = number("Modulus")?;
+
+        // RFC 3110 section 2: the exponent's length in one byte, or zero and
+        // then two.
+        let mut public_key = match exponent.len() {
+            len @ 1..=255 => vec![len as u8],
+            len => {
+                let len = u16::try_from(len).map_err(|_| "public exponent is too long")?;
+                let mut bytes = vec![0];
+                bytes.extend_from_slice(&len.to_be_bytes());
+                bytes
+            }
+        };
+        public_key.extend(exponent);
+        public_key.extend(modulus);
+        Ok(Sig0Key {
+            name,
+            key: Dnskey {
+                flags: HOST_KEY,
+                protocol: 3,
+                algorithm: RSASHA256,
+                public_key,
+            },
+            private_exponent: number("PrivateExponent")?,
+        })
+    }
+
+    // Appends a SIG(0) record to `message`, signed at `now`. The signature
+    // covers the record's RDATA up to the signature, then the message as it
+    // was (RFC 2931 section 3.1).
+    pub(crate) fn sign(&self, message: &[u8], now: u64) -> Vec<u8> {
+        let now = now as u32;
+        // Type covered, algorithm, labels and original TTL are all zero but
+        // for the algorithm.
+        let mut rdata = vec![0, 0, RSASHA256, 0, 0, 0, 0, 0];
+        rdata.extend_from_slice(&now.wrapping_add(VALIDITY).to_be_bytes());
+        rdata.extend_from_slice(&now.wrapping_sub(VALIDITY).to_be_bytes());
+        rdata.extend_from_slice(&self.key.key_tag().to_be_bytes());
+        rdata.extend_from_slice(&dnssec::canonical(&self.name));
+        let mut data = rdata.clone();
+        data.extend_from_slice(message);
+        let (_, modulus) = self.key.rsa().unwrap_or_default();
+        rdata.extend(crypto::rsa_sha256_sign(
+            &self.private_exponent,
+            modulus,
+            &data,
+        ));
+
+        let mut signed = message.to_vec();
+        signed.push(0);
+        signed.extend_from_slice(&(DnsType::Sig.code()).to_be_bytes());
+        signed.extend_from_slice(&(DnsClass::Any as u16).to_be_bytes());
+        signed.extend_from_slice(&0u32.to_be_bytes());
+        signed.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        signed.extend_from_slice(&rdata);
+        let arcount = u16::from_be_bytes([signed[10], signed[11]]) + 1;
+        signed[10..12].copy_from_slice(&arcount.to_be_bytes());
+        signed
+    }
+}
+
+// Checks the SIG(0) record at the end of `message` against the KEY
+// records `keys` finds for the signer, and returns the signer.
+pub(crate) fn verify(
+    message: &[u8],
+    keys: impl FnOnce(&Name) -> Vec<Dnskey>,
+    now: u64,
+) -> Result<Name, TsigError> {
+    let start = tsig::find_signature(message, DnsType::Sig)?.ok_or(TsigError::Unsigned)?;
+    let rdata = Name::parse(message, start)?.1 + 10;
+    let fixed = message
+        .get(rdata..rdata + 18)
+        .ok_or(crate::error::ParseError::UnexpectedEof)?;
+    let field = |i: usize| u32::from_be_bytes([fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]]);
+    let (algorithm, expiration, inception) = (fixed[2], field(8), field(12));
+    let key_tag = u16::from_be_bytes([fixed[16], fixed[17]]);
+    let (signer, signature) = Name::parse(message, rdata + 18)?;
+
+    let key = keys(&signer)
+        .into_iter()
+        .find(|key| key.algorithm == algorithm && key.key_tag() == key_tag)
+        .ok_or(TsigError::BadKey)?;
+    let (exponent, modulus) = key.rsa().ok_or(TsigError::BadKey)?;
+    let mut data = message[rdata..signature].to_vec();
+    let header = data.len();
+    data.extend_from_slice(&message[..start]);
+    let arcount = u16::from_be_bytes([data[header + 10], data[header + 11]]) - 1;
+    data[header + 10..header + 12].copy_from_slice(&arcount.to_be_bytes());
+    if algorithm != RSASHA256
+        || !crypto::rsa_sha256_verify(exponent, modulus, &data, &message[signature..])
+    {
+        return Err(TsigError::BadSig);
+    }
+    let now = now as u32;
+    if serial::is_newer(inception, now) || serial::is_newer(now, expiration) {
+        return Err(TsigError::BadTime);
+    }
+    Ok(signer)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::crypto::base64_encode;
+    use crate::dnssec::test::{unhex, MODULUS, MODULUS_2, PRIVATE_EXPONENT};
+    use crate::packet::DnsPacket;
+    use crate::question::DnsQuestion;
+
+    // The test key as `dnssec-keygen` would write it.
+    pub(crate) fn private_key_file() -> String {
+        format!(
+            "Private-key-format: v1.3\n\
+             Algorithm: 8 (RSASHA256)\n\
+             Modulus: {}\n\
+             PublicExponent: AQAB\n\
+             PrivateExponent: {}\n",
+            base64_encode(&unhex(MODULUS)),
+            base64_encode(&unhex(PRIVATE_EXPONENT)),
+        )
+    }
+
+    fn message() -> Vec<u8> {
+        let question = DnsQuestion {
+            qname: "example.com".into(),
+            qtype: DnsType::Soa,
+            qclass: DnsClass::In,
+        };
+        DnsPacket::query(42, question).to_bytes()
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = Sig0Key::parse("host.example.com".into(), &private_key_file()).unwrap();
+        assert_eq!(key.key.flags, HOST_KEY);
+        let (exponent, modulus) = key.key.rsa().unwrap();
+        assert_eq!((exponent, modulus), (&[1, 0, 1][..], &unhex(MODULUS)[..]));
+
+        let path = std::env::temp_dir().join(format!(
+            "dns-server-test-{}/Khost.example.com.+008+12345.private",
+            std::process::id()
+        ));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, private_key_file()).unwrap();
+        assert_eq!(
+            Sig0Key::load(&path).unwrap().name.as_str(),
+            "host.example.com"
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let ecdsa = private_key_file().replace("8 (RSASHA256)", "13 (ECDSAP256SHA256)");
+        assert!(Sig0Key::parse("host".into(), &ecdsa).is_err());
+        let missing = private_key_file().replace("PrivateExponent", "Exponent");
+        assert!(Sig0Key::parse("host".into(), &missing).is_err());
+        assert!(Sig0Key::load(Path::new("/nonexistent/host.private")).is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = Sig0Key::parse("Host.Example.com".into(), &private_key_file()).unwrap();
+        let now = 1_700_000_000;
+        let signed = key.sign(&message(), now);
+
+        let packet = DnsPacket::try_from(&signed[..]).unwrap();
+        let sig = packet.additionals.last().unwrap();
+        assert_eq!((sig.qtype, sig.qclass), (DnsType::Sig, DnsClass::Any));
+        assert_eq!(sig.name.as_str(), "");
+
+        let keys = |_: &Name| vec![key.key.clone()];
+        let signer = verify(&signed, keys, now).unwrap();
+        assert_eq!(signer.as_str(), "host.example.com");
+        assert_eq!(verify(&signed, keys, now + 299), Ok(signer));
+        assert_eq!(verify(&signed, keys, now + 301), Err(TsigError::BadTime));
+        assert_eq!(verify(&message(), keys, now), Err(TsigError::Unsigned));
+
+        // Another key, or a message changed after signing.
+        let mut other = key.key.clone();
+        other.public_key = [&[3, 1, 0, 1][..], &unhex(MODULUS_2)].concat();
+        assert_eq!(
+            verify(&signed, |_| vec![other], now),
+            Err(TsigError::BadKey)
+        );
+        let mut tampered = signed.clone();
+        tampered[1] ^= 1;
+        assert_eq!(verify(&tampered, keys, now), Err(TsigError::BadSig));
+    }
+}
@@ -0,0 +1,97 @@
+use std::net::IpAddr;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+
+// Short, so clients let go of the sinkhole soon after it is turned off.
+const TTL: i32 = 60;
+
+// `--sinkhole addr[,addr...][,except=name...]`: every name but the
+// exceptions and the names below them is answered authoritatively with the
+// addresses, as for a captive portal or a lab with no way out.
+#[derive(PartialEq, Debug)]
+pub(crate) struct SinkholeConfig {
+    pub(crate) addresses: Vec<IpAddr>,
+    pub(crate) except: Vec<Name>,
+}
+
+impl SinkholeConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut config = SinkholeConfig {
+            addresses: Vec::new(),
+            except: Vec::new(),
+        };
+        for part in spec.split(',') {
+            match part.split_once('=') {
+                Some(("except", name)) if !name.is_empty() => config.except.push(name.into()),
+                Some(_) => return Err(format!("unknown sinkhole option {:?}", part)),
+                None => {
+                    let addr = part
+                        .parse()
+                        .map_err(|_| format!("bad address {:?}", part))?;
+                    config.addresses.push(addr);
+                }
+            }
+        }
+        if config.addresses.is_empty() {
+            return Err("no sinkhole address".to_string());
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn covers(&self, qname: &Name) -> bool {
+        !self.except.iter().any(|name| qname.is_subdomain_of(name))
+    }
+
+    // The addresses of the question's family; other types get NODATA.
+    pub(crate) fn answer(&self, question: &DnsQuestion, response: &mut DnsPacket) {
+        response.header.aa = true;
+        let addresses = self.addresses.iter().filter_map(|addr| {
+            let (qtype, rdata) = match addr {
+                IpAddr::V4(ip) => (DnsType::A, RData::A(ip.octets())),
+                IpAddr::V6(ip) => (DnsType::Aaaa, RData::Aaaa(ip.octets())),
+            };
+            (question.qtype == qtype || question.qtype == DnsType::Any)
+                .then(|| DnsAnswer::new(question.qname.clone(), qtype, DnsClass::In, TTL, rdata))
+        });
+        response.answers.extend(addresses);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sinkhole() {
+        let config =
+            SinkholeConfig::parse("192.0.2.1,2001:db8::1,except=example.com,except=corp.test")
+                .unwrap();
+        assert!(config.covers(&"anything.test".into()));
+        assert!(!config.covers(&"www.Example.com".into()));
+        assert!(!config.covers(&"corp.test".into()));
+
+        let ask = |qtype| {
+            let question = DnsQuestion {
+                qname: "anything.test".into(),
+                qtype,
+                qclass: DnsClass::In,
+            };
+            let mut response = DnsPacket::query(1, question.clone());
+            config.answer(&question, &mut response);
+            response
+        };
+        let response = ask(DnsType::A);
+        assert!(response.header.aa);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 1]));
+        assert_eq!(ask(DnsType::Any).answers.len(), 2);
+        assert!(ask(DnsType::Mx).answers.is_empty());
+
+        assert!(SinkholeConfig::parse("except=example.com").is_err());
+        assert!(SinkholeConfig::parse("192.0.2.1,ttl=5").is_err());
+        assert!(SinkholeConfig::parse("portal.test").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::header::ResponseCode;
+
+const MAX_TRACKED_KEYS: usize = 10_000; // bound memory use under random-subdomain floods
+const MAX_SAMPLES: usize = 10_000;
+const TOP_N: usize = 10;
+const RECENT: usize = 50;
+
+pub(crate) struct Stats {
+    started: Instant,
+    since: Instant,
+    queries: u64,
+    nxdomain: u64,
+    names: HashMap<String, u64>,
+    clients: HashMap<IpAddr, u64>,
+    samples: Vec<Duration>,
+    next_sample: usize,
+    // Bytes of responses sent, and those name compression saved.
+    responses: u64,
+    response_bytes: u64,
+    compression_saved: u64,
+    // The latest queries, newest last; kept across resets.
+    recent: VecDeque<Recent>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Recent {
+    pub(crate) time: SystemTime,
+    pub(crate) client: IpAddr,
+    pub(crate) name: String,
+    pub(crate) qtype: String,
+    pub(crate) rcode: ResponseCode,
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct StatsSnapshot {
+    pub(crate) uptime: Duration,
+    pub(crate) elapsed: Duration,
+    pub(crate) queries: u64,
+    pub(crate) nxdomain: u64,
+    pub(crate) qps: f64,
+    pub(crate) nxdomain_ratio: f64,
+    pub(crate) p50: Duration,
+    pub(crate) p90: Duration,
+    pub(crate) p99: Duration,
+    pub(crate) top_names: Vec<(String, u64)>,
+    pub(crate) top_clients: Vec<(IpAddr, u64)>,
+    // Average response sizes in bytes, as sent and as they would have been
+    // without compression.
+    pub(crate) response_size: f64,
+    pub(crate) uncompressed_size: f64,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Stats {
+            started: now,
+            since: now,
+            queries: 0,
+            nxdomain: 0,
+            names: HashMap::new(),
+            clients: HashMap::new(),
+            samples: Vec::new(),
+            next_sample: 0,
+            responses: 0,
+            response_bytes: 0,
+            compression_saved: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        name: &str,
+        qtype: &str,
+        client: IpAddr,
+        rcode: ResponseCode,
+        elapsed: Duration,
+    ) {
+        self.queries += 1;
+        if rcode == ResponseCode::NxDomain {
+            self.nxdomain += 1;
+        }
+
+        if let Some(count) = self.names.get_mut(name) {
+            *count += 1;
+        } else if self.names.len() < MAX_TRACKED_KEYS {
+            self.names.insert(name.to_string(), 1);
+        }
+
+        if let Some(count) = self.clients.get_mut(&client) {
+            *count += 1;
+        } else if self.clients.len() < MAX_TRACKED_KEYS {
+            self.clients.insert(client, 1);
+        }
+
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(elapsed);
+        } else {
+            self.samples[self.next_sample] = elapsed;
+        }
+        self.next_sample = (self.next_sample + 1) % MAX_SAMPLES;
+
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(Recent {
+            time: SystemTime::now(),
+            client,
+            name: name.to_string(),
+            qtype: qtype.to_string(),
+            rcode,
+        });
+    }
+
+    pub(crate) fn record_size(&mut self, sent: usize, saved: usize) {
+        self.responses += 1;
+        self.response_bytes += sent as u64;
+        self.compression_saved += saved as u64;
+    }
+
+    pub(crate) fn snapshot(&self) -> StatsSnapshot {
+        let elapsed = self.since.elapsed();
+        let mut samples = self.samples.clone();
+        samples.sort_unstable();
+
+        let qps = if elapsed.as_secs_f64() > 0.0 {
+            self.queries as f64 / elapsed.as_secs_f64()
+        } else {
+            0.0
+        };
+        let nxdomain_ratio = if self.queries > 0 {
+            self.nxdomain as f64 / self.queries as f64
+        } else {
+            0.0
+        };
+
+        let average = |bytes: u64| match self.responses {
+            0 => 0.0,
+            responses => bytes as f64 / responses as f64,
+        };
+
+        StatsSnapshot {
+            uptime: self.started.elapsed(),
+            elapsed,
+            queries: self.queries,
+            nxdomain: self.nxdomain,
+            qps,
+            nxdomain_ratio,
+            p50: percentile(&samples, 50.0),
+            p90: percentile(&samples, 90.0),
+            p99: percentile(&samples, 99.0),
+            top_names: top_n(&self.names, TOP_N),
+            top_clients: top_n(&self.clients, TOP_N),
+            response_size: average(self.response_bytes),
+            uncompressed_size: average(self.response_bytes + self.compression_saved),
+        }
+    }
+
+    pub(crate) fn recent(&self) -> Vec<Recent> {
+        self.recent.iter().cloned().collect()
+    }
+
+    // The n most queried names since the last reset.
+    pub(crate) fn top_names(&self, n: usize) -> Vec<(String, u64)> {
+        top_n(&self.names, n)
+    }
+
+    // Clears the counters but keeps the uptime, like `unbound-control stats`.
+    pub(crate) fn reset(&mut self) {
+        let (started, recent) = (self.started, std::mem::take(&mut self.recent));
+        *self = Stats::new();
+        (self.started, self.recent) = (started, recent);
+    }
+}
+
+// Nearest-rank percentile over an already sorted slice.
+pub(crate) fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn top_n<K: Clone + Ord>(counts: &HashMap<K, u64>, n: usize) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = counts.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+impl fmt::Display for StatsSnapshot {
+    // Same key=value layout as `unbound-control stats`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total.num.queries={}", self.queries)?;
+        writeln!(f, "total.num.nxdomain={}", self.nxdomain)?;
+        writeln!(f, "total.nxdomain.ratio={:.6}", self.nxdomain_ratio)?;
+        writeln!(f, "total.qps={:.6}", self.qps)?;
+        writeln!(
+            f,
+            "total.recursion.time.median={:.6}",
+            self.p50.as_secs_f64()
+        )?;
+        writeln!(f, "total.recursion.time.p90={:.6}", self.p90.as_secs_f64())?;
+        writeln!(f, "total.recursion.time.p99={:.6}", self.p99.as_secs_f64())?;
+        writeln!(f, "response.size.avg={:.2}", self.response_size)?;
+        writeln!(
+            f,
+            "response.size.uncompressed.avg={:.2}",
+            self.uncompressed_size
+        )?;
+        writeln!(
+            f,
+            "response.compression.saved.avg={:.2}",
+            self.uncompressed_size - self.response_size
+        )?;
+        writeln!(f, "time.up={:.6}", self.uptime.as_secs_f64())?;
+        writeln!(f, "time.elapsed={:.6}", self.elapsed.as_secs_f64())?;
+        for (i, (name, count)) in self.top_names.iter().enumerate() {
+            writeln!(f, "top.name.{}={} {}", i + 1, name, count)?;
+        }
+        for (i, (client, count)) in self.top_clients.iter().enumerate() {
+            writeln!(f, "top.client.{}={} {}", i + 1, client, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&samples, 90.0), Duration::from_millis(90));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&samples, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_response_sizes() {
+        let mut stats = Stats::new();
+        stats.record_size(100, 20);
+        stats.record_size(300, 0);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.response_size, 200.0);
+        assert_eq!(snapshot.uncompressed_size, 210.0);
+        assert!(snapshot
+            .to_string()
+            .contains("response.compression.saved.avg=10.00\n"));
+        stats.reset();
+        assert_eq!(stats.snapshot().uncompressed_size, 0.0);
+    }
+
+    #[test]
+    fn test_top_talkers_and_nxdomain() {
+        let mut stats = Stats::new();
+        let a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let ms = Duration::from_millis(1);
+        stats.record("example.com", "A", a, ResponseCode::NoError, ms);
+        stats.record("example.com", "AAAA", a, ResponseCode::NoError, ms);
+        stats.record("missing.com", "A", b, ResponseCode::NxDomain, ms);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.queries, 3);
+        assert_eq!(snapshot.nxdomain, 1);
+        assert_eq!(
+            snapshot.top_names,
+            vec![
+                ("example.com".to_string(), 2),
+                ("missing.com".to_string(), 1)
+            ]
+        );
+        assert_eq!(snapshot.top_clients, vec![(a, 2), (b, 1)]);
+        assert_eq!(snapshot.response_size, 0.0);
+
+        stats.reset();
+        assert_eq!(stats.snapshot().queries, 0);
+        assert!(stats.snapshot().top_names.is_empty());
+        let recent = stats.recent();
+        assert_eq!(recent.len(), 3);
+        assert_eq!(
+            (recent[2].name.as_str(), recent[2].client),
+            ("missing.com", b)
+        );
+
+        for _ in 0..RECENT {
+            stats.record("example.com", "A", a, ResponseCode::NoError, ms);
+        }
+        assert_eq!(stats.recent().len(), RECENT);
+        assert!(stats.recent().iter().all(|recent| recent.client == a));
+    }
+}
@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+
+// What a TEMPLATE record in a zone file answers with, made up for each
+// query, for diagnostics names such as
+//
+//   whoami TEMPLATE address   ; A or AAAA: the client's source address
+//   myip   TEMPLATE txt       ; TXT: the same address as text
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Template {
+    Address,
+    Txt,
+}
+
+impl Template {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Template::Address => "address",
+            Template::Txt => "txt",
+        }
+    }
+
+    // The record answering a query of `qtype` from `client`, if the
+    // template has one of that type; an IPv4 client has no AAAA.
+    pub(crate) fn answer(
+        self,
+        name: &Name,
+        qtype: DnsType,
+        ttl: i32,
+        client: IpAddr,
+    ) -> Option<DnsAnswer> {
+        match (self, qtype, client) {
+            (Template::Address, DnsType::A | DnsType::Any, IpAddr::V4(_))
+            | (Template::Address, DnsType::Aaaa | DnsType::Any, IpAddr::V6(_)) => {
+                Some(DnsAnswer::address(name.clone(), ttl, client))
+            }
+            (Template::Txt, DnsType::Txt | DnsType::Any, _) => Some(DnsAnswer::new(
+                name.clone(),
+                DnsType::Txt,
+                DnsClass::In,
+                ttl,
+                RData::Txt(vec![client.to_string().into_bytes()]),
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Template::Address, Template::Txt]
+            .into_iter()
+            .find(|template| template.name().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_templates() {
+        assert_eq!("ADDRESS".parse(), Ok(Template::Address));
+        assert_eq!("txt".parse(), Ok(Template::Txt));
+        assert_eq!("lua".parse::<Template>(), Err(()));
+
+        let name = Name::from("whoami.example.com");
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let address = |qtype, client| Template::Address.answer(&name, qtype, 0, client);
+        assert_eq!(
+            address(DnsType::A, v4).unwrap().rdata,
+            RData::A([192, 0, 2, 7])
+        );
+        assert_eq!(address(DnsType::Aaaa, v6).unwrap().qtype, DnsType::Aaaa);
+        assert!(address(DnsType::Aaaa, v4).is_none());
+        assert!(address(DnsType::Txt, v4).is_none());
+
+        let txt = Template::Txt.answer(&name, DnsType::Txt, 0, v6).unwrap();
+        assert_eq!(txt.rdata, RData::Txt(vec![b"::1".to_vec()]));
+        assert!(Template::Txt.answer(&name, DnsType::A, 0, v6).is_none());
+    }
+}
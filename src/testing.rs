@@ -0,0 +1,356 @@
+// Test doubles on loopback: a scriptable upstream nameserver, and the whole
+// server running in-process, so that forwarding, caching and failover can
+// be tested without the internet.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::answer::DnsAnswer;
+use crate::authority::Zones;
+use crate::blocklist;
+use crate::common::{DnsClass, DnsType};
+use crate::config::{Config, Protocol};
+use crate::forward::UpstreamGroup;
+use crate::handler::State;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::resolver::Resolver;
+use crate::server::{self, Shared};
+use crate::stats::Stats;
+
+// How often the mock's threads look up from their sockets to see whether
+// it has been dropped.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// What the mock upstream does with one query.
+pub(crate) enum Reply {
+    // This response, as is.
+    Send(DnsPacket),
+    // Nothing, so the query times out.
+    Drop,
+    // Over UDP, the response without records and with TC set, so that the
+    // client asks again over TCP; there, the response in full.
+    Truncate(DnsPacket),
+    // The response under another ID, which a client must not take.
+    WrongId(DnsPacket),
+    // The reply, after a wait that holds up no other query.
+    Delay(Duration, Box<Reply>),
+}
+
+type Script = dyn Fn(&DnsPacket, Protocol) -> Reply + Send + Sync;
+
+// An upstream nameserver answering as its script says, on one loopback
+// port over both UDP and TCP, and remembering what it was asked. It stops
+// when dropped.
+pub(crate) struct MockUpstream {
+    pub(crate) addr: SocketAddr,
+    queries: Arc<Mutex<Vec<(DnsQuestion, Protocol)>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MockUpstream {
+    pub(crate) fn start(
+        script: impl Fn(&DnsPacket, Protocol) -> Reply + Send + Sync + 'static,
+    ) -> Self {
+        let (udp, tcp) = bind_pair();
+        let mock = MockUpstream {
+            addr: udp.local_addr().unwrap(),
+            queries: Arc::default(),
+            stopped: Arc::default(),
+        };
+        let script: Arc<Script> = Arc::new(script);
+
+        let (queries, stopped, run) = (
+            Arc::clone(&mock.queries),
+            Arc::clone(&mock.stopped),
+            Arc::clone(&script),
+        );
+        udp.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while !stopped.load(Ordering::SeqCst) {
+                let Ok((size, peer)) = udp.recv_from(&mut buf) else {
+                    continue;
+                };
+                let Ok(query) = DnsPacket::try_from(&buf[..size]) else {
+                    continue;
+                };
+                remember(&queries, &query, Protocol::Udp);
+                let socket = udp.try_clone().unwrap();
+                send(run(&query, Protocol::Udp), move |reply| {
+                    let _ = socket.send_to(reply, peer);
+                });
+            }
+        });
+
+        let (queries, stopped) = (Arc::clone(&mock.queries), Arc::clone(&mock.stopped));
+        tcp.set_nonblocking(true).unwrap();
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                match tcp.accept() {
+                    Ok((stream, _)) => {
+                        let (queries, script) = (Arc::clone(&queries), Arc::clone(&script));
+                        thread::spawn(move || serve_tcp(stream, &queries, &*script));
+                    }
+                    Err(_) => thread::sleep(POLL_INTERVAL),
+                }
+            }
+        });
+        mock
+    }
+
+    // Answers with the records owned by the name asked of the type asked,
+    // NXDOMAIN for a name owning none of any type.
+    pub(crate) fn serving(records: Vec<DnsAnswer>) -> Self {
+        MockUpstream::start(move |query, _| {
+            let mut reply = response(query);
+            let question = &query.questions[0];
+            let owned: Vec<&DnsAnswer> = records
+                .iter()
+                .filter(|record| record.name.eq_ignore_case(&question.qname))
+                .collect();
+            if owned.is_empty() {
+                reply.header.rcode = ResponseCode::NxDomain;
+            }
+            for record in owned {
+                if record.qtype == question.qtype {
+                    reply.add_answer(record.clone());
+                }
+            }
+            Reply::Send(reply)
+        })
+    }
+
+    // Never answers.
+    pub(crate) fn silent() -> Self {
+        MockUpstream::start(|_, _| Reply::Drop)
+    }
+
+    // The questions asked so far, oldest first, with how they came.
+    pub(crate) fn queries(&self) -> Vec<(DnsQuestion, Protocol)> {
+        self.queries.lock().unwrap().clone()
+    }
+
+    // An upstream group of this server alone.
+    pub(crate) fn group(&self, name: &str) -> UpstreamGroup {
+        UpstreamGroup::parse(&format!("{}={}", name, self.addr)).unwrap()
+    }
+}
+
+impl Drop for MockUpstream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+// The query turned into a response with nothing in it.
+pub(crate) fn response(query: &DnsPacket) -> DnsPacket {
+    let mut response = query.clone();
+    response.header.flip_qr();
+    response
+}
+
+// UDP and TCP sockets on the same free loopback port.
+fn bind_pair() -> (UdpSocket, TcpListener) {
+    loop {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+            return (udp, tcp);
+        }
+    }
+}
+
+fn remember(queries: &Mutex<Vec<(DnsQuestion, Protocol)>>, query: &DnsPacket, protocol: Protocol) {
+    if let Some(question) = query.questions.first() {
+        queries.lock().unwrap().push((question.clone(), protocol));
+    }
+}
+
+// Carries out a reply through `send`, on another thread after a delay.
+fn send(reply: Reply, send_bytes: impl FnOnce(&[u8]) + Send + 'static) {
+    match reply {
+        Reply::Send(response) => send_bytes(&response.to_bytes()),
+        Reply::Drop => {}
+        Reply::Truncate(mut response) => {
+            response.answers.clear();
+            response.authorities.clear();
+            response.additionals.clear();
+            response.header.tc = true;
+            send_bytes(&response.to_bytes())
+        }
+        Reply::WrongId(mut response) => {
+            response.header.id = response.header.id.wrapping_add(1);
+            send_bytes(&response.to_bytes())
+        }
+        Reply::Delay(delay, reply) => {
+            thread::spawn(move || {
+                thread::sleep(delay);
+                send(*reply, send_bytes);
+            });
+        }
+    }
+}
+
+fn serve_tcp(
+    mut stream: TcpStream,
+    queries: &Mutex<Vec<(DnsQuestion, Protocol)>>,
+    script: &Script,
+) {
+    let _ = stream.set_nonblocking(false);
+    let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+    let mut read = || -> io::Result<DnsPacket> {
+        let mut len = [0; 2];
+        stream.read_exact(&mut len)?;
+        let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf)?;
+        DnsPacket::try_from(buf.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    };
+    while let Ok(query) = read() {
+        remember(queries, &query, Protocol::Tcp);
+        let reply = match script(&query, Protocol::Tcp) {
+            // Truncation is for UDP.
+            Reply::Truncate(response) => Reply::Send(response),
+            reply => reply,
+        };
+        let writer = Arc::clone(&writer);
+        send(reply, move |bytes| {
+            let mut framed = (bytes.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(bytes);
+            let _ = writer.lock().unwrap().write_all(&framed);
+        });
+    }
+}
+
+// The server as `dns-server <args>` runs it, listening on a free loopback
+// port over UDP and TCP, without a control socket. Its state and stats are
+// there to be looked at; the listeners run until the tests end.
+pub(crate) struct TestServer {
+    pub(crate) addr: SocketAddr,
+    pub(crate) state: Arc<State>,
+    pub(crate) stats: Arc<Mutex<Stats>>,
+}
+
+impl TestServer {
+    pub(crate) fn start(args: &[&str]) -> Self {
+        loop {
+            // Free a port and take it: the loser of a race for it with
+            // another test tries another.
+            let port = bind_pair().0.local_addr().unwrap().port();
+            let listen = format!("127.0.0.1:{}", port);
+            let flags = ["--listen", &listen, "--control-socket", ""];
+            let args = flags.iter().chain(args).map(|arg| arg.to_string());
+            let config = Config::from_args(args).unwrap();
+            let state = State::new(Zones::load(&config).unwrap());
+            *state.blocklists.write().unwrap() =
+                Arc::new(blocklist::load_all(&config, &[]).unwrap());
+            let shared = Shared {
+                config: Arc::new(config),
+                state: Arc::new(state),
+                stats: Arc::new(Mutex::new(Stats::new())),
+            };
+            if server::listen(&shared).is_ok() {
+                return TestServer {
+                    addr: SocketAddr::from(([127, 0, 0, 1], port)),
+                    state: shared.state,
+                    stats: shared.stats,
+                };
+            }
+        }
+    }
+
+    pub(crate) fn resolver(&self) -> Resolver {
+        Resolver::new(vec![self.addr]).with_timeout(Duration::from_secs(5))
+    }
+
+    pub(crate) fn query(&self, name: &str, qtype: DnsType) -> DnsPacket {
+        self.resolver().query(name, qtype, DnsClass::In).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::RData;
+    use crate::error::ResolveError;
+    use std::time::Instant;
+
+    fn a(name: &str, ip: [u8; 4]) -> DnsAnswer {
+        DnsAnswer::new(name.into(), DnsType::A, DnsClass::In, 300, RData::A(ip))
+    }
+
+    fn questions(mock: &MockUpstream) -> Vec<(String, Protocol)> {
+        mock.queries()
+            .into_iter()
+            .map(|(question, protocol)| (question.qname.as_str().to_string(), protocol))
+            .collect()
+    }
+
+    #[test]
+    fn test_mock_upstream() {
+        let mock = MockUpstream::start(|query, _| {
+            let name = query.questions[0].qname.as_str().to_string();
+            let mut reply = response(query);
+            reply.add_answer(a(&name, [192, 0, 2, 1]));
+            match name.as_str() {
+                "big.example" => Reply::Truncate(reply),
+                "spoofed.example" => Reply::WrongId(reply),
+                "slow.example" => {
+                    Reply::Delay(Duration::from_millis(200), Box::new(Reply::Send(reply)))
+                }
+                _ => Reply::Send(reply),
+            }
+        });
+        let resolver = Resolver::new(vec![mock.addr])
+            .with_attempts(1)
+            .with_timeout(Duration::from_millis(500));
+        let lookup = |name| resolver.query(name, DnsType::A, DnsClass::In);
+
+        assert_eq!(lookup("www.example").unwrap().answers.len(), 1);
+        // Asked again over TCP, which has the records.
+        assert_eq!(lookup("big.example").unwrap().answers.len(), 1);
+        assert!(matches!(
+            lookup("spoofed.example"),
+            Err(ResolveError::Timeout)
+        ));
+        let start = Instant::now();
+        assert_eq!(lookup("slow.example").unwrap().answers.len(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(
+            questions(&mock),
+            vec![
+                ("www.example".into(), Protocol::Udp),
+                ("big.example".into(), Protocol::Udp),
+                ("big.example".into(), Protocol::Tcp),
+                ("spoofed.example".into(), Protocol::Udp),
+                ("slow.example".into(), Protocol::Udp),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forwarding_caching_and_failover() {
+        let (dead, live) = (
+            MockUpstream::silent(),
+            MockUpstream::serving(vec![a("www.example.com", [192, 0, 2, 80])]),
+        );
+        let upstream = format!("wan={},{}", dead.addr, live.addr);
+        let server = TestServer::start(&["--upstream", &upstream, "--forward", ".=wan"]);
+
+        // The dead server's silence costs a timeout before the live one
+        // answers; then the answer comes from the cache.
+        for _ in 0..2 {
+            let response = server.query("www.example.com", DnsType::A);
+            assert_eq!(response.answers[0].rdata, RData::A([192, 0, 2, 80]));
+        }
+        assert_eq!(dead.queries().len(), 1);
+        assert_eq!(live.queries().len(), 1);
+        assert_eq!(server.state.cache.lookups(), (1, 1));
+        assert_eq!(server.stats.lock().unwrap().snapshot().queries, 2);
+    }
+}
@@ -0,0 +1,358 @@
+use std::env;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+const DAY: i64 = 86400;
+
+// Local time for schedules. Zones are POSIX TZ rules, e.g.
+// `CET-1CEST,M3.5.0,M10.5.0/3`; a zone name such as `Europe/Berlin` takes
+// the rule at the end of its zoneinfo file, which is the one in force now.
+// Also `UTC`, fixed offsets like `+05:30` and `local` for the TZ variable or
+// /etc/localtime.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct TimeZone {
+    // Seconds east of UTC.
+    offset: i64,
+    dst: Option<Dst>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Dst {
+    offset: i64,
+    start: Transition,
+    end: Transition,
+}
+
+// `Mm.w.d/time`: day d (0 is Sunday) of week w (5 is the last) of month m,
+// at a local time in seconds.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct Transition {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    time: i64,
+}
+
+impl TimeZone {
+    pub(crate) const UTC: TimeZone = TimeZone {
+        offset: 0,
+        dst: None,
+    };
+
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        match spec {
+            "UTC" | "utc" | "Z" => return Ok(TimeZone::UTC),
+            "local" => return TimeZone::local(),
+            _ => {}
+        }
+        // ISO 8601 offsets, east of UTC positive.
+        if spec.starts_with(['+', '-']) {
+            let offset =
+                parse_offset(&spec[1..]).ok_or_else(|| format!("bad offset {:?}", spec))?;
+            let sign = if spec.starts_with('-') { -1 } else { 1 };
+            return Ok(TimeZone {
+                offset: sign * offset,
+                dst: None,
+            });
+        }
+        match spec.contains('/') && !spec.contains(',') {
+            true => TimeZone::named(spec),
+            false => TimeZone::posix(spec),
+        }
+    }
+
+    fn local() -> Result<Self, String> {
+        match env::var("TZ") {
+            Ok(tz) if !tz.is_empty() => TimeZone::parse(tz.trim_start_matches(':')),
+            _ => TimeZone::from_tzif("/etc/localtime"),
+        }
+    }
+
+    fn named(name: &str) -> Result<Self, String> {
+        if name.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(format!("bad zone name {:?}", name));
+        }
+        TimeZone::from_tzif(&format!("{}/{}", ZONEINFO, name))
+    }
+
+    // Version 2 and later TZif files end with the rule as a POSIX TZ string
+    // between newlines.
+    fn from_tzif(path: &str) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if !data.starts_with(b"TZif") {
+            return Err(format!("{}: not a zoneinfo file", path));
+        }
+        let footer = data
+            .strip_suffix(b"\n")
+            .and_then(|data| data.rsplit(|b| *b == b'\n').next())
+            .and_then(|footer| std::str::from_utf8(footer).ok())
+            .filter(|footer| !footer.is_empty())
+            .ok_or_else(|| format!("{}: no TZ rule", path))?;
+        TimeZone::posix(footer).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // `std offset [dst [offset] [,start[/time],end[/time]]]`, with offsets
+    // west of UTC positive as POSIX has them.
+    fn posix(spec: &str) -> Result<Self, String> {
+        let bad = || format!("bad TZ rule {:?}", spec);
+        let (_, rest) = zone_abbreviation(spec).ok_or_else(bad)?;
+        let (offset, rest) = signed_offset(rest).ok_or_else(bad)?;
+        let offset = -offset;
+        if rest.is_empty() {
+            return Ok(TimeZone { offset, dst: None });
+        }
+        let (_, rest) = zone_abbreviation(rest).ok_or_else(bad)?;
+        let (dst_offset, rest) = match signed_offset(rest) {
+            Some((dst_offset, rest)) => (-dst_offset, rest),
+            None => (offset + 3600, rest),
+        };
+        // Without rules, the US ones POSIX falls back to.
+        let rules = match rest.strip_prefix(',') {
+            Some(rules) => rules,
+            None if rest.is_empty() => "M3.2.0,M11.1.0",
+            None => return Err(bad()),
+        };
+        let (start, end) = rules.split_once(',').ok_or_else(bad)?;
+        Ok(TimeZone {
+            offset,
+            dst: Some(Dst {
+                offset: dst_offset,
+                start: Transition::parse(start).ok_or_else(bad)?,
+                end: Transition::parse(end).ok_or_else(bad)?,
+            }),
+        })
+    }
+
+    // The offset from UTC in seconds at a time.
+    fn offset_at(&self, secs: i64) -> i64 {
+        let Some(dst) = &self.dst else {
+            return self.offset;
+        };
+        let (year, _, _) = civil(secs + self.offset);
+        // Starts are in standard time, ends in daylight time.
+        let start = dst.start.at(year) - self.offset;
+        let end = dst.end.at(year) - dst.offset;
+        let in_dst = match start < end {
+            true => start <= secs && secs < end,
+            // The southern hemisphere, with summer over the new year.
+            false => secs < end || start <= secs,
+        };
+        match in_dst {
+            true => dst.offset,
+            false => self.offset,
+        }
+    }
+
+    // The local weekday (0 is Monday) and seconds since midnight.
+    pub(crate) fn local_time(&self, time: SystemTime) -> (u32, u32) {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+        let local = secs + self.offset_at(secs);
+        let days = local.div_euclid(DAY);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 3).rem_euclid(7) as u32;
+        (weekday, local.rem_euclid(DAY) as u32)
+    }
+}
+
+impl Transition {
+    fn parse(text: &str) -> Option<Self> {
+        let (date, time) = match text.split_once('/') {
+            Some((date, time)) => (
+                date,
+                signed_offset(time).filter(|(_, rest)| rest.is_empty())?.0,
+            ),
+            None => (text, 7200),
+        };
+        let mut fields = date
+            .strip_prefix('M')?
+            .split('.')
+            .map(|n| n.parse::<u32>().ok());
+        let (month, week, weekday) = (fields.next()??, fields.next()??, fields.next()??);
+        if fields.next().is_some()
+            || !(1..=12).contains(&month)
+            || !(1..=5).contains(&week)
+            || weekday > 6
+        {
+            return None;
+        }
+        Some(Transition {
+            month,
+            week,
+            weekday,
+            time,
+        })
+    }
+
+    // The local time of the transition in a year, as seconds since the
+    // epoch.
+    fn at(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        // 1970-01-01 was a Thursday, day 4 counting from Sunday.
+        let first_weekday = (first + 4).rem_euclid(7);
+        let mut day = first
+            + (self.weekday as i64 - first_weekday).rem_euclid(7)
+            + 7 * (self.week as i64 - 1);
+        let next_month = match self.month {
+            12 => days_from_civil(year + 1, 1, 1),
+            month => days_from_civil(year, month + 1, 1),
+        };
+        while day >= next_month {
+            day -= 7;
+        }
+        day * DAY + self.time
+    }
+}
+
+// A zone abbreviation, `CET` or quoted as `<+0530>`, and the rest.
+fn zone_abbreviation(text: &str) -> Option<(&str, &str)> {
+    if let Some(quoted) = text.strip_prefix('<') {
+        let (name, rest) = quoted.split_once('>')?;
+        return Some((name, rest));
+    }
+    let end = text
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    (end >= 3).then(|| text.split_at(end))
+}
+
+// `[+-]hh[:mm[:ss]]` as seconds, and the rest.
+fn signed_offset(text: &str) -> Option<(i64, &str)> {
+    let (sign, text) = match text.as_bytes().first()? {
+        b'-' => (-1, &text[1..]),
+        b'+' => (1, &text[1..]),
+        _ => (1, text),
+    };
+    let end = text
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(text.len());
+    let offset = parse_offset(&text[..end])?;
+    Some((sign * offset, &text[end..]))
+}
+
+// `hh[:mm[:ss]]` as seconds.
+fn parse_offset(text: &str) -> Option<i64> {
+    let mut secs = 0;
+    let mut parts = 0;
+    for (part, scale) in text.split(':').zip([3600, 60, 1]) {
+        if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        secs += part.parse::<i64>().ok()? * scale;
+        parts += 1;
+    }
+    (parts == text.split(':').count()).then_some(secs)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+// algorithm).
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The year, month and day of a time in seconds since the epoch.
+pub(crate) fn civil(secs: i64) -> (i64, u32, u32) {
+    let days = secs.div_euclid(DAY) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    // Seconds since the epoch of a UTC date and time.
+    fn at(year: i64, month: u32, day: u32, hour: i64, minute: i64) -> SystemTime {
+        let secs = days_from_civil(year, month, day) * DAY + hour * 3600 + minute * 60;
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    }
+
+    #[test]
+    fn test_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil(11017 * DAY), (2000, 3, 1));
+        assert_eq!(civil(days_from_civil(2024, 2, 29) * DAY + 1), (2024, 2, 29));
+        assert_eq!(civil(-1), (1969, 12, 31));
+        // 2024-01-01 was a Monday.
+        assert_eq!(
+            TimeZone::UTC.local_time(at(2024, 1, 1, 9, 30)),
+            (0, 9 * 3600 + 1800)
+        );
+    }
+
+    #[test]
+    fn test_posix() {
+        let berlin = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // Winter, then summer after 01:00 UTC on the last Sunday of March
+        // (2024-03-31), then winter after 01:00 UTC on 2024-10-27.
+        assert_eq!(berlin.local_time(at(2024, 1, 15, 12, 0)).1, 13 * 3600);
+        assert_eq!(berlin.local_time(at(2024, 3, 31, 0, 59)).1, 3600 + 59 * 60);
+        assert_eq!(berlin.local_time(at(2024, 3, 31, 1, 0)).1, 3 * 3600);
+        assert_eq!(
+            berlin.local_time(at(2024, 10, 27, 0, 59)).1,
+            2 * 3600 + 59 * 60
+        );
+        assert_eq!(berlin.local_time(at(2024, 10, 27, 1, 0)).1, 2 * 3600);
+
+        // Sydney's summer spans the new year.
+        let sydney = TimeZone::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.local_time(at(2024, 1, 15, 0, 0)).1, 11 * 3600);
+        assert_eq!(sydney.local_time(at(2024, 7, 15, 0, 0)).1, 10 * 3600);
+
+        // A date past midnight moves the weekday on.
+        let tokyo = TimeZone::parse("JST-9").unwrap();
+        assert_eq!(tokyo.local_time(at(2024, 1, 1, 20, 0)), (1, 5 * 3600));
+        let india = TimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!(india.local_time(at(2024, 1, 1, 0, 0)).1, 5 * 3600 + 1800);
+        let new_york = TimeZone::parse("EST5EDT").unwrap();
+        assert_eq!(new_york.local_time(at(2024, 7, 1, 12, 0)).1, 8 * 3600);
+        assert_eq!(new_york.local_time(at(2024, 12, 1, 12, 0)).1, 7 * 3600);
+
+        for bad in [
+            "",
+            "C-1",
+            "CET",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.5.0,M10.5.0",
+            "CET-1x",
+        ] {
+            assert!(TimeZone::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_offsets_and_names() {
+        let zone = TimeZone::parse("-08:00").unwrap();
+        assert_eq!(zone.local_time(at(2024, 1, 1, 6, 0)), (6, 22 * 3600));
+        assert_eq!(TimeZone::parse("+05:30").unwrap().offset, 5 * 3600 + 1800);
+        assert!(TimeZone::parse("+5:x").is_err());
+        assert!(TimeZone::parse("Europe/../etc/passwd").is_err());
+        // Where the system has zoneinfo.
+        if let Ok(berlin) = TimeZone::parse("Europe/Berlin") {
+            assert_eq!(
+                berlin,
+                TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,648 @@
+use std::sync::Mutex;
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::crypto;
+use crate::dnssec::Dnskey;
+use crate::error::TsigError;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::tsig::{self, Tsig, TsigKey, HMAC_SHA256};
+
+// TKEY modes (RFC 2930 section 2.5). Server assignment, GSS-API and
+// resolver assignment aren't offered.
+const DIFFIE_HELLMAN: u16 = 2;
+const DELETE: u16 = 5;
+
+// Errors in the TKEY record, from the TSIG error space (RFC 2930 section
+// 2.6).
+const BADKEY: u16 = 17;
+const BADMODE: u16 = 19;
+const BADNAME: u16 = 20;
+const BADALG: u16 = 21;
+
+// The KEY algorithm of Diffie-Hellman public values (RFC 2539).
+const KEY_DH: u8 = 2;
+// The longest a negotiated key lasts, and how long it lasts when the
+// requester leaves it to us.
+const MAX_LIFETIME: u64 = 86400;
+// The largest prime taken, in bytes: 4096 bits, as far as RFC 3526 goes.
+// Each exchange costs two exponentiations modulo it.
+const MAX_PRIME: usize = 512;
+// Keys held at once for one requester; a new one replaces whichever of
+// theirs expires first.
+const MAX_KEYS: usize = 8;
+
+// The RDATA of a TKEY record (RFC 2930 section 2).
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct Tkey {
+    pub(crate) algorithm: Name,
+    pub(crate) inception: u32,
+    pub(crate) expiration: u32,
+    pub(crate) mode: u16,
+    pub(crate) error: u16,
+    pub(crate) key: Vec<u8>,
+    pub(crate) other: Vec<u8>,
+}
+
+impl Tkey {
+    pub(crate) fn parse(rdata: &[u8]) -> Option<Self> {
+        let (algorithm, offset) = Name::parse(rdata, 0).ok()?;
+        let fixed = rdata.get(offset..offset + 14)?;
+        let field =
+            |i: usize| u32::from_be_bytes([fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]]);
+        let short = |i: usize| u16::from_be_bytes([fixed[i], fixed[i + 1]]);
+        let key_end = offset + 14 + short(12) as usize;
+        let key = rdata.get(offset + 14..key_end)?;
+        let other_len = rdata.get(key_end..key_end + 2)?;
+        let other_len = u16::from_be_bytes([other_len[0], other_len[1]]) as usize;
+        let other = rdata.get(key_end + 2..key_end + 2 + other_len)?;
+        Some(Tkey {
+            algorithm,
+            inception: field(0),
+            expiration: field(4),
+            mode: short(8),
+            error: short(10),
+            key: key.to_vec(),
+            other: other.to_vec(),
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.algorithm.to_bytes();
+        bytes.extend_from_slice(&self.inception.to_be_bytes());
+        bytes.extend_from_slice(&self.expiration.to_be_bytes());
+        bytes.extend_from_slice(&self.mode.to_be_bytes());
+        bytes.extend_from_slice(&self.error.to_be_bytes());
+        bytes.extend_from_slice(&(self.key.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.key);
+        bytes.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.other);
+        bytes
+    }
+}
+
+// A Diffie-Hellman public value as a KEY record carries it (RFC 2539
+// section 2). Only explicit primes: the well-known groups that a prime
+// length of 1 or 2 stands for aren't known here.
+#[derive(PartialEq, Debug)]
+pub(crate) struct DhKey {
+    pub(crate) prime: Vec<u8>,
+    pub(crate) generator: Vec<u8>,
+    pub(crate) public: Vec<u8>,
+}
+
+impl DhKey {
+    pub(crate) fn parse(data: &[u8]) -> Option<Self> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        for _ in 0..3 {
+            let len = data.get(offset..offset + 2)?;
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            fields.push(data.get(offset + 2..offset + 2 + len)?.to_vec());
+            offset += 2 + len;
+        }
+        let [prime, generator, public] = <[Vec<u8>; 3]>::try_from(fields).ok()?;
+        (prime.len() > 2).then_some(DhKey {
+            prime,
+            generator,
+            public,
+        })
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [&self.prime, &self.generator, &self.public] {
+            bytes.extend_from_slice(&(field.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes
+    }
+
+    // Rules out primes too long to work with cheaply, and generators and
+    // public values of 0, 1 and p - 1, which give a shared secret anyone
+    // can guess, or anything past the prime.
+    fn is_valid(&self) -> bool {
+        let prime = strip_zeros(&self.prime);
+        prime.len() <= MAX_PRIME
+            && is_between(&self.generator, prime)
+            && is_between(&self.public, prime)
+    }
+}
+
+// Whether `value` is in 2..p - 1.
+fn is_between(value: &[u8], prime: &[u8]) -> bool {
+    let value = strip_zeros(value);
+    // p - 1: the prime is odd, so only its last byte changes.
+    let mut largest = prime.to_vec();
+    if let Some(last) = largest.last_mut() {
+        *last = last.wrapping_sub(1);
+    }
+    let above_one = value.len() > 1 || value.first().is_some_and(|b| *b > 1);
+    above_one
+        && (value.len() < largest.len() || value.len() == largest.len() && value < &largest[..])
+}
+
+fn strip_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+// A key agreed through TKEY, with the configured key of whoever asked for
+// it.
+struct Negotiated {
+    key: TsigKey,
+    requester: Name,
+    expires: u64,
+}
+
+// TSIG keys agreed through TKEY, each until it expires or whoever
+// negotiated it deletes it.
+#[derive(Default)]
+pub(crate) struct Keys(Mutex<Vec<Negotiated>>);
+
+impl Keys {
+    pub(crate) fn find(&self, name: &Name, now: u64) -> Option<TsigKey> {
+        let mut keys = self.0.lock().unwrap();
+        keys.retain(|held| held.expires > now);
+        keys.iter()
+            .find(|held| held.key.name.eq_ignore_case(name))
+            .map(|held| held.key.clone())
+    }
+
+    // The configured key a negotiated one was agreed with, which it stands
+    // for when authorizing updates.
+    pub(crate) fn requester(&self, name: &Name, now: u64) -> Option<Name> {
+        let keys = self.0.lock().unwrap();
+        keys.iter()
+            .find(|held| held.expires > now && held.key.name.eq_ignore_case(name))
+            .map(|held| held.requester.clone())
+    }
+
+    // Holds the key for `requester`, replacing one of theirs by the same
+    // name. False if someone else holds a key by that name.
+    pub(crate) fn insert(&self, key: TsigKey, requester: &Name, expires: u64, now: u64) -> bool {
+        let mut keys = self.0.lock().unwrap();
+        keys.retain(|held| held.expires > now);
+        let same_name = |held: &Negotiated| held.key.name.eq_ignore_case(&key.name);
+        if keys
+            .iter()
+            .any(|held| same_name(held) && !held.requester.eq_ignore_case(requester))
+        {
+            return false;
+        }
+        keys.retain(|held| !same_name(held));
+        let theirs = |held: &&Negotiated| held.requester.eq_ignore_case(requester);
+        if keys.iter().filter(theirs).count() >= MAX_KEYS {
+            let first = keys
+                .iter()
+                .enumerate()
+                .filter(|(_, held)| theirs(held))
+                .min_by_key(|(_, held)| held.expires)
+                .map(|(i, _)| i);
+            if let Some(i) = first {
+                keys.remove(i);
+            }
+        }
+        keys.push(Negotiated {
+            key,
+            requester: requester.clone(),
+            expires,
+        });
+        true
+    }
+
+    fn remove(&self, name: &Name) -> bool {
+        let mut keys = self.0.lock().unwrap();
+        let before = keys.len();
+        keys.retain(|held| !held.key.name.eq_ignore_case(name));
+        keys.len() < before
+    }
+}
+
+// Checks a TSIG-signed request against the key `find` gives for its name,
+// the negotiated ones among others, returning the key and the request's
+// TSIG for signing the response. Unsigned requests pass with None.
+pub(crate) fn verify(
+    message: &[u8],
+    find: impl Fn(&Name) -> Option<TsigKey>,
+    now: u64,
+) -> Result<Option<(TsigKey, Tsig)>, TsigError> {
+    let Some(name) = tsig::signer(message)? else {
+        return Ok(None);
+    };
+    let key = find(&name).ok_or(TsigError::BadKey)?;
+    let tsig = key.verify(message, None, now)?;
+    Ok(Some((key, tsig)))
+}
+
+// Answers a TKEY query: the requester's TKEY record comes in the
+// additional section, and goes back in the answer section with the outcome
+// in its error field (RFC 2930 section 4). `signer` names the TSIG key the
+// query was signed with, and `requester` the configured TSIG or SIG(0) key
+// if it was signed with one. Agreeing on a key takes a configured one, as
+// each exchange costs two exponentiations; deleting one takes the key
+// itself.
+pub(crate) fn answer(
+    mut packet: DnsPacket,
+    signer: Option<&Name>,
+    requester: Option<&Name>,
+    keys: &Keys,
+    now: u64,
+) -> DnsPacket {
+    packet.header.flip_qr();
+    packet.edns = None;
+    let records = std::mem::take(&mut packet.additionals);
+    let Some((owner, mut tkey)) = records
+        .iter()
+        .filter(|record| record.qtype == DnsType::Tkey)
+        .find_map(|record| match &record.rdata {
+            RData::Unknown(rdata) => Some((record.name.clone(), Tkey::parse(rdata)?)),
+            _ => None,
+        })
+    else {
+        packet.header.rcode = ResponseCode::FormatError;
+        return packet;
+    };
+
+    let mut answers = Vec::new();
+    tkey.error = match tkey.mode {
+        DIFFIE_HELLMAN => match requester {
+            Some(requester) => {
+                match diffie_hellman(&owner, &mut tkey, &records, requester, keys, now) {
+                    Ok(server_key) => {
+                        answers.push(server_key);
+                        0
+                    }
+                    Err(error) => error,
+                }
+            }
+            None => BADKEY,
+        },
+        DELETE if !signer.is_some_and(|signer| signer.eq_ignore_case(&owner)) => BADKEY,
+        DELETE if !keys.remove(&owner) => BADNAME,
+        DELETE => 0,
+        _ => BADMODE,
+    };
+    answers.insert(
+        0,
+        DnsAnswer::new(
+            owner,
+            DnsType::Tkey,
+            DnsClass::Any,
+            0,
+            RData::Unknown(tkey.to_bytes()),
+        ),
+    );
+    packet.answers = answers;
+    packet
+}
+
+// Agrees on a key from the requester's public value in a KEY record and
+// one made up here, which goes back in the KEY record returned, and stores
+// it under the TKEY's name (RFC 2930 section 4.1) for the requester.
+fn diffie_hellman(
+    name: &Name,
+    tkey: &mut Tkey,
+    records: &[DnsAnswer],
+    requester: &Name,
+    keys: &Keys,
+    now: u64,
+) -> Result<DnsAnswer, u16> {
+    if !tkey.algorithm.eq_ignore_case(&HMAC_SHA256.into()) {
+        return Err(BADALG);
+    }
+    let client = records
+        .iter()
+        .filter(|record| record.qtype == DnsType::Key)
+        .filter_map(|record| match &record.rdata {
+            RData::Unknown(rdata) => Dnskey::parse(rdata),
+            _ => None,
+        })
+        .filter(|key| key.algorithm == KEY_DH)
+        .find_map(|key| DhKey::parse(&key.public_key))
+        .filter(DhKey::is_valid)
+        .ok_or(BADKEY)?;
+
+    let mut rng = rand::thread_rng();
+    let mut secret = vec![0; client.prime.len()];
+    rng.fill(&mut secret[..]);
+    let shared = crypto::mod_exp(&client.public, &secret, &client.prime);
+    let nonce: [u8; 16] = rng.gen();
+    let material = keying_material(&tkey.key, &nonce, &shared);
+
+    let lifetime = match (tkey.expiration as u64).saturating_sub(now) {
+        0 => MAX_LIFETIME,
+        requested => requested.min(MAX_LIFETIME),
+    };
+    if !keys.insert(
+        TsigKey::new(name.clone(), material),
+        requester,
+        now + lifetime,
+        now,
+    ) {
+        return Err(BADNAME);
+    }
+    tkey.inception = now as u32;
+    tkey.expiration = (now + lifetime) as u32;
+    tkey.key = nonce.to_vec();
+
+    let public = crypto::mod_exp(&client.generator, &secret, &client.prime);
+    let server = Dnskey {
+        flags: 0,
+        protocol: 3,
+        algorithm: KEY_DH,
+        public_key: DhKey {
+            prime: client.prime,
+            generator: client.generator,
+            public,
+        }
+        .to_bytes(),
+    };
+    Ok(DnsAnswer::new(
+        name.clone(),
+        DnsType::Key,
+        DnsClass::In,
+        0,
+        RData::Unknown(server.to_bytes()),
+    ))
+}
+
+// MD5(query nonce | secret) | MD5(server nonce | secret), XORed over the
+// front of the shared secret or the other way round, whichever is longer.
+pub(crate) fn keying_material(query_nonce: &[u8], server_nonce: &[u8], shared: &[u8]) -> Vec<u8> {
+    let mut digests = crypto::md5(&[query_nonce, shared].concat()).to_vec();
+    digests.extend(crypto::md5(&[server_nonce, shared].concat()));
+    let (mut longer, shorter) = match shared.len() > digests.len() {
+        true => (shared.to_vec(), digests),
+        false => (digests, shared.to_vec()),
+    };
+    for (byte, other) in longer.iter_mut().zip(shorter) {
+        *byte ^= other;
+    }
+    longer
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::question::DnsQuestion;
+
+    // A 64-bit prime, small so tests stay quick, with generator 5.
+    const PRIME: [u8; 8] = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc5];
+    const NOW: u64 = 1_700_000_000;
+    const REQUESTER: &str = "ddns-key";
+
+    fn tkey(mode: u16, key: Vec<u8>) -> Tkey {
+        Tkey {
+            algorithm: HMAC_SHA256.into(),
+            inception: NOW as u32,
+            expiration: (NOW + 3600) as u32,
+            mode,
+            error: 0,
+            key,
+            other: Vec::new(),
+        }
+    }
+
+    fn query(tkey: &Tkey, extra: Vec<DnsAnswer>) -> DnsPacket {
+        let mut packet = DnsPacket::query(
+            7,
+            DnsQuestion {
+                qname: "dh.example.com".into(),
+                qtype: DnsType::Tkey,
+                qclass: DnsClass::Any,
+            },
+        );
+        packet.additionals.push(DnsAnswer::new(
+            "dh.example.com".into(),
+            DnsType::Tkey,
+            DnsClass::Any,
+            0,
+            RData::Unknown(tkey.to_bytes()),
+        ));
+        packet.additionals.extend(extra);
+        packet
+    }
+
+    fn dh_record(public: Vec<u8>) -> DnsAnswer {
+        let key = Dnskey {
+            flags: 0,
+            protocol: 3,
+            algorithm: KEY_DH,
+            public_key: DhKey {
+                prime: PRIME.to_vec(),
+                generator: vec![5],
+                public,
+            }
+            .to_bytes(),
+        };
+        DnsAnswer::new(
+            "client.example.com".into(),
+            DnsType::Key,
+            DnsClass::In,
+            0,
+            RData::Unknown(key.to_bytes()),
+        )
+    }
+
+    // A Diffie-Hellman exchange as a requester would start one.
+    pub(crate) fn dh_query() -> DnsPacket {
+        let public = crypto::mod_exp(&[5], &[7], &PRIME);
+        query(&tkey(DIFFIE_HELLMAN, Vec::new()), vec![dh_record(public)])
+    }
+
+    pub(crate) fn response_tkey(response: &DnsPacket) -> Tkey {
+        match &response.answers[0].rdata {
+            RData::Unknown(rdata) => Tkey::parse(rdata).unwrap(),
+            other => panic!("not a TKEY: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rdata_round_trip() {
+        let tkey = tkey(DIFFIE_HELLMAN, vec![1, 2, 3]);
+        assert_eq!(Tkey::parse(&tkey.to_bytes()), Some(tkey.clone()));
+        assert_eq!(Tkey::parse(&tkey.to_bytes()[..20]), None);
+
+        let dh = DhKey {
+            prime: PRIME.to_vec(),
+            generator: vec![2],
+            public: vec![0x12, 0x34],
+        };
+        assert_eq!(DhKey::parse(&dh.to_bytes()), Some(dh));
+        // A well-known group.
+        assert_eq!(DhKey::parse(&[0, 1, 2, 0, 0, 0, 1, 7]), None);
+    }
+
+    #[test]
+    fn test_public_values() {
+        let key = |public: &[u8]| DhKey {
+            prime: PRIME.to_vec(),
+            generator: vec![5],
+            public: public.to_vec(),
+        };
+        assert!(key(&[2]).is_valid());
+        assert!(key(&[0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc3]).is_valid());
+        for bad in [
+            &[][..],
+            &[0],
+            &[1],
+            &[0, 1],
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc4],
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc5],
+            &[1, 0, 0, 0, 0, 0, 0, 0, 0],
+        ] {
+            assert!(!key(bad).is_valid(), "{:?}", bad);
+        }
+
+        let mut weak = key(&[2]);
+        weak.generator = vec![1];
+        assert!(!weak.is_valid());
+        let mut long = key(&[2]);
+        long.prime = vec![0xff; MAX_PRIME + 1];
+        assert!(!long.is_valid());
+    }
+
+    #[test]
+    fn test_key_limits() {
+        let keys = Keys::default();
+        let (ours, theirs) = (Name::from(REQUESTER), Name::from("other-key"));
+        let key = |name: &str| TsigKey::new(name.into(), vec![1; 32]);
+        for i in 0..MAX_KEYS as u64 + 2 {
+            let name = format!("{}.dh.example.com", i);
+            assert!(keys.insert(key(&name), &ours, NOW + 60 + i, NOW));
+        }
+        // The ones expiring first make way.
+        let held = |i: u64| keys.find(&Name::from(format!("{}.dh.example.com", i).as_str()), NOW);
+        assert!(held(0).is_none() && held(1).is_none());
+        assert!((2..MAX_KEYS as u64 + 2).all(|i| held(i).is_some()));
+
+        // Nobody takes over a name someone else holds.
+        assert!(!keys.insert(key("2.dh.example.com"), &theirs, NOW + 60, NOW));
+        assert!(keys.insert(key("2.dh.example.com"), &ours, NOW + 60, NOW));
+        assert!(keys.insert(key("theirs.example.com"), &theirs, NOW + 60, NOW));
+        assert!(held(9).is_some());
+    }
+
+    #[test]
+    fn test_diffie_hellman() {
+        let keys = Keys::default();
+        let client_secret = [0x0b, 0xad, 0xc0, 0xde, 0x12, 0x34];
+        let client_public = crypto::mod_exp(&[5], &client_secret, &PRIME);
+        let nonce = b"client nonce".to_vec();
+        let query = query(
+            &tkey(DIFFIE_HELLMAN, nonce.clone()),
+            vec![dh_record(client_public)],
+        );
+
+        let requester = Name::from(REQUESTER);
+        let response = answer(query, Some(&requester), Some(&requester), &keys, NOW);
+        let tkey = response_tkey(&response);
+        assert_eq!(tkey.error, 0);
+        assert_eq!(tkey.expiration, (NOW + 3600) as u32);
+        let server = match &response.answers[1].rdata {
+            RData::Unknown(rdata) => DhKey::parse(&Dnskey::parse(rdata).unwrap().public_key),
+            _ => None,
+        }
+        .unwrap();
+
+        // The requester works out the same key from the server's half.
+        let shared = crypto::mod_exp(&server.public, &client_secret, &PRIME);
+        let material = keying_material(&nonce, &tkey.key, &shared);
+        let client_key = TsigKey::new("dh.example.com".into(), material);
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let (signed, mac) = client_key.sign(&DnsPacket::query(9, question).to_bytes(), None, NOW);
+        let (key, tsig) = verify(&signed, |name| keys.find(name, NOW), NOW)
+            .unwrap()
+            .unwrap();
+        assert_eq!((key.name.as_str(), tsig.mac), ("dh.example.com", mac));
+        assert_eq!(keys.requester(&key.name, NOW), Some(requester));
+
+        // The key lapses when it expires.
+        assert_eq!(
+            verify(&signed, |name| keys.find(name, NOW + 3600), NOW + 3600)
+                .map(|signed| signed.is_some()),
+            Err(TsigError::BadKey)
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let keys = Keys::default();
+        let public = crypto::mod_exp(&[5], &[7], &PRIME);
+        let requester = Name::from(REQUESTER);
+        let error = |tkey: Tkey, extra| {
+            let response = answer(query(&tkey, extra), None, Some(&requester), &keys, NOW);
+            response_tkey(&response).error
+        };
+
+        assert_eq!(error(tkey(3, Vec::new()), vec![]), BADMODE);
+        assert_eq!(error(tkey(DIFFIE_HELLMAN, Vec::new()), vec![]), BADKEY);
+        assert_eq!(
+            error(tkey(DIFFIE_HELLMAN, Vec::new()), vec![dh_record(vec![1])]),
+            BADKEY
+        );
+        let mut md5 = tkey(DIFFIE_HELLMAN, Vec::new());
+        md5.algorithm = "hmac-md5.sig-alg.reg.int".into();
+        assert_eq!(error(md5, vec![dh_record(public.clone())]), BADALG);
+
+        // Without a configured key to answer for it, nobody gets one.
+        let query_dh = || {
+            query(
+                &tkey(DIFFIE_HELLMAN, Vec::new()),
+                vec![dh_record(public.clone())],
+            )
+        };
+        let response = answer(query_dh(), None, None, &keys, NOW);
+        assert_eq!(response_tkey(&response).error, BADKEY);
+        assert!(keys.find(&"dh.example.com".into(), NOW).is_none());
+        assert_eq!(
+            error(tkey(DIFFIE_HELLMAN, Vec::new()), vec![dh_record(public)]),
+            0
+        );
+
+        let mut no_tkey = query(&tkey(DELETE, Vec::new()), vec![]);
+        no_tkey.additionals.clear();
+        assert_eq!(
+            answer(no_tkey, None, None, &keys, NOW).header.rcode,
+            ResponseCode::FormatError
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let keys = Keys::default();
+        let name = Name::from("dh.example.com");
+        let requester = Name::from(REQUESTER);
+        keys.insert(
+            TsigKey::new(name.clone(), vec![1; 32]),
+            &requester,
+            NOW + 60,
+            NOW,
+        );
+        let delete = || query(&tkey(DELETE, Vec::new()), vec![]);
+
+        // Only with the key itself.
+        let response = answer(delete(), None, None, &keys, NOW);
+        assert_eq!(response_tkey(&response).error, BADKEY);
+        let other = Name::from("other.example.com");
+        let response = answer(delete(), Some(&other), Some(&other), &keys, NOW);
+        assert_eq!(response_tkey(&response).error, BADKEY);
+        let response = answer(delete(), Some(&requester), Some(&requester), &keys, NOW);
+        assert_eq!(response_tkey(&response).error, BADKEY);
+        assert!(keys.find(&name, NOW).is_some());
+
+        let response = answer(delete(), Some(&name), None, &keys, NOW);
+        assert_eq!(response_tkey(&response).error, 0);
+        assert!(keys.find(&name, NOW).is_none());
+        let response = answer(delete(), Some(&name), None, &keys, NOW);
+        assert_eq!(response_tkey(&response).error, BADNAME);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::Config;
+use crate::hints;
+use crate::json::Json;
+use crate::question::DnsQuestion;
+use crate::recursor::{Recursor, Step};
+use crate::reputation::Reputation;
+
+const USAGE: &str = "usage: dns-server trace <name> [type] [--root-hints <file>]";
+// Longer than a client would wait: a trace is for finding where the time
+// goes.
+pub(crate) const TRACE_BUDGET: Duration = Duration::from_secs(15);
+
+// `dns-server trace <name> [type]`, resolving from the roots the way the
+// server does and printing every query on the way, like `dig +trace`.
+// Exits 1 when the name doesn't resolve.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let (mut name, mut qtype, mut hints_file) = (None, None, None);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root-hints" => match args.next() {
+                Some(path) => hints_file = Some(path),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            _ if name.is_none() => name = Some(arg),
+            _ if qtype.is_none() => match arg.parse::<DnsType>() {
+                Ok(parsed) => qtype = Some(parsed),
+                Err(_) => {
+                    eprintln!("unknown type: {}\n{}", arg, USAGE);
+                    return 2;
+                }
+            },
+            _ => {
+                eprintln!("unexpected argument: {}\n{}", arg, USAGE);
+                return 2;
+            }
+        }
+    }
+    let Some(name) = name else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    let qtype = qtype.unwrap_or(DnsType::A);
+    let roots = match hints_file {
+        Some(path) => match hints::load(Path::new(&path)) {
+            Ok(roots) => roots,
+            Err(e) => {
+                eprintln!("Failed to load root hints {}", e);
+                return 1;
+            }
+        },
+        None => hints::builtin(),
+    };
+
+    println!(
+        "; <<>> dns-server {} <<>> +trace {} {}",
+        env!("CARGO_PKG_VERSION"),
+        name,
+        qtype
+    );
+    println!();
+    let config = Config::default();
+    let recursor = Recursor::new(
+        config.recursion_limits,
+        config.max_udp_size,
+        &roots,
+        Arc::new(Reputation::default()),
+    )
+    .with_tracer(Arc::new(|step: &Step| print!("{}", format_step(step))));
+    let question = DnsQuestion {
+        qname: Name::from(name.as_str()),
+        qtype,
+        qclass: DnsClass::In,
+    };
+    match recursor.resolve(&question, Instant::now() + TRACE_BUDGET) {
+        Ok(response) => {
+            println!(";; ANSWER: {}", response.header.rcode);
+            for record in &response.answers {
+                println!("{}", record);
+            }
+            0
+        }
+        Err(e) => {
+            println!(";; resolution failed: {}", e);
+            1
+        }
+    }
+}
+
+// A step as `dig +trace` shows one: the records that came back, then who
+// sent them and how long it took.
+pub(crate) fn format_step(step: &Step) -> String {
+    let mut out = String::new();
+    let (ip, port, zone, ms) = (
+        step.server.ip(),
+        step.server.port(),
+        step.zone,
+        step.rtt.as_millis(),
+    );
+    match step.result {
+        Ok(response) => {
+            for record in response.answers.iter().chain(&response.authorities) {
+                writeln!(out, "{}", record).unwrap();
+            }
+            writeln!(
+                out,
+                ";; {} for {}. {} from {}#{}({}.) in {} ms",
+                response.header.rcode, step.name, step.qtype, ip, port, zone, ms
+            )
+            .unwrap();
+        }
+        Err(e) => writeln!(
+            out,
+            ";; no answer for {}. {} from {}#{}({}.) after {} ms: {}",
+            step.name, step.qtype, ip, port, zone, ms, e
+        )
+        .unwrap(),
+    }
+    out.push('\n');
+    out
+}
+
+pub(crate) fn step_json(step: &Step) -> Json {
+    let mut fields = vec![
+        (
+            "zone".to_string(),
+            format!("{}.", step.zone).as_str().into(),
+        ),
+        (
+            "server".to_string(),
+            step.server.to_string().as_str().into(),
+        ),
+        (
+            "name".to_string(),
+            format!("{}.", step.name).as_str().into(),
+        ),
+        ("type".to_string(), step.qtype.to_string().as_str().into()),
+        (
+            "rtt_ms".to_string(),
+            Json::Number(step.rtt.as_secs_f64() * 1000.0),
+        ),
+    ];
+    match step.result {
+        Ok(response) => {
+            let records = response
+                .answers
+                .iter()
+                .chain(&response.authorities)
+                .map(|record| record.to_string().as_str().into());
+            fields.push((
+                "rcode".to_string(),
+                response.header.rcode.to_string().as_str().into(),
+            ));
+            fields.push(("records".to_string(), Json::Array(records.collect())));
+        }
+        Err(e) => fields.push(("error".to_string(), e.to_string().as_str().into())),
+    }
+    Json::Object(fields)
+}
+
+// The admin API's trace: the steps and the outcome as one JSON object.
+pub(crate) fn trace_json(recursor: Recursor, question: &DnsQuestion, deadline: Instant) -> Json {
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let traced = Arc::clone(&steps);
+    let recursor = recursor.with_tracer(Arc::new(move |step: &Step| {
+        traced.lock().unwrap().push(step_json(step))
+    }));
+    let result = recursor.resolve(question, deadline);
+
+    let mut fields = vec![
+        (
+            "name".to_string(),
+            format!("{}.", question.qname).as_str().into(),
+        ),
+        (
+            "type".to_string(),
+            question.qtype.to_string().as_str().into(),
+        ),
+        (
+            "steps".to_string(),
+            Json::Array(steps.lock().unwrap().clone()),
+        ),
+    ];
+    match result {
+        Ok(response) => {
+            let answers = response
+                .answers
+                .iter()
+                .map(|record| record.to_string().as_str().into());
+            fields.push((
+                "rcode".to_string(),
+                response.header.rcode.to_string().as_str().into(),
+            ));
+            fields.push(("answers".to_string(), Json::Array(answers.collect())));
+        }
+        Err(e) => fields.push(("error".to_string(), e.to_string().as_str().into())),
+    }
+    Json::Object(fields)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::error::ResolveError;
+    use crate::packet::DnsPacket;
+
+    #[test]
+    fn test_format_step() {
+        let (zone, name) = (Name::from("."), Name::from("example.com"));
+        let mut response = DnsPacket::query(
+            1,
+            DnsQuestion {
+                qname: name.clone(),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            },
+        );
+        response.authorities.push(DnsAnswer::new(
+            "com".into(),
+            DnsType::Ns,
+            DnsClass::In,
+            172800,
+            RData::Ns("a.gtld-servers.net".into()),
+        ));
+        let step = Step {
+            zone: &zone,
+            server: "198.41.0.4:53".parse().unwrap(),
+            name: &name,
+            qtype: DnsType::A,
+            rtt: Duration::from_millis(23),
+            result: Ok(&response),
+        };
+        assert_eq!(
+            format_step(&step),
+            "com.\t172800\tIN\tNS\ta.gtld-servers.net.\n\
+             ;; NOERROR for example.com. A from 198.41.0.4#53(.) in 23 ms\n\n"
+        );
+        assert_eq!(
+            step_json(&step).to_string(),
+            "{\"zone\":\".\",\"server\":\"198.41.0.4:53\",\"name\":\"example.com.\",\
+             \"type\":\"A\",\"rtt_ms\":23,\"rcode\":\"NOERROR\",\
+             \"records\":[\"com.\\t172800\\tIN\\tNS\\ta.gtld-servers.net.\"]}"
+        );
+
+        let error = ResolveError::Timeout;
+        let step = Step {
+            result: Err(&error),
+            ..step
+        };
+        assert_eq!(
+            format_step(&step),
+            format!(
+                ";; no answer for example.com. A from 198.41.0.4#53(.) after 23 ms: {}\n\n",
+                error
+            )
+        );
+        assert_eq!(
+            step_json(&step).get("error"),
+            Some(&error.to_string().as_str().into())
+        );
+    }
+}
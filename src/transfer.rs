@@ -0,0 +1,540 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::{Config, DEFAULT_MAX_UDP_SIZE};
+use crate::dig::parse_server;
+use crate::dnssec::Rrsig;
+use crate::edns::{Edns, EdnsOption};
+use crate::error::{ResolveError, TsigError};
+use crate::handler::State;
+use crate::header::ResponseCode;
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::resolver::timeout_error;
+use crate::secondary::Served;
+use crate::serial;
+use crate::tsig::{self, TsigKey};
+
+const USAGE: &str =
+    "usage: dns-server axfr <zone> @server[:port] [-y [hmac-sha256:]name:secret] [file]";
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(30);
+// Records go out in messages of about this many bytes at most, well under
+// the 64KiB TCP allows so TSIG and compression differences never matter.
+const MESSAGE_SIZE: usize = 16384;
+
+// A transferred zone: its records starting with its SOA, the closing copy
+// of the SOA dropped, and the EXPIRE the primary sent with them (RFC 7314),
+// for the expire timer to start from instead of the SOA's.
+pub(crate) struct Transfer {
+    pub(crate) records: Vec<DnsAnswer>,
+    pub(crate) expire: Option<u32>,
+}
+
+// Full zone transfer (RFC 5936) over TCP.
+pub(crate) fn axfr(
+    server: SocketAddr,
+    zone: &Name,
+    key: Option<&TsigKey>,
+    timeout: Duration,
+) -> Result<Transfer, ResolveError> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout).map_err(timeout_error)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut query = DnsPacket::query(
+        rand::thread_rng().gen(),
+        DnsQuestion {
+            qname: zone.clone(),
+            qtype: DnsType::Axfr,
+            qclass: DnsClass::In,
+        },
+    );
+    query.header.rd = false;
+    let mut edns = Edns::new(DEFAULT_MAX_UDP_SIZE);
+    edns.options.push(EdnsOption::Expire(None));
+    query.edns = Some(edns);
+    let (message, mut mac) = match key {
+        Some(key) => {
+            let (message, mac) = key.sign(&query.to_bytes(), None, tsig::now());
+            (message, Some(mac))
+        }
+        None => (query.to_bytes(), None),
+    };
+    write_message(&mut stream, &message)?;
+
+    let mut records: Vec<DnsAnswer> = Vec::new();
+    // Messages received since the last signed one; TSIG allows gaps.
+    let mut unsigned = Vec::new();
+    let mut first = true;
+    let mut expire = None;
+    loop {
+        let raw = read_message(&mut stream)?;
+        let response = DnsPacket::try_from(raw.as_slice())?;
+        if response.header.id != query.header.id {
+            return Err(ResolveError::Transfer("response ID does not match".into()));
+        }
+        if let (Some(key), Some(prior)) = (key, mac.as_deref()) {
+            let verified = if first {
+                key.verify(&raw, Some(prior), tsig::now())
+            } else {
+                key.verify_subsequent(&unsigned, &raw, prior, tsig::now())
+            };
+            match verified {
+                Ok(tsig) => {
+                    mac = Some(tsig.mac);
+                    unsigned.clear();
+                }
+                Err(TsigError::Unsigned) if !first => unsigned.extend_from_slice(&raw),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if first {
+            expire = response.edns.as_ref().and_then(Edns::expire);
+        }
+        first = false;
+
+        if response.header.rcode != ResponseCode::NoError {
+            return Err(ResolveError::Rcode(response.header.rcode as u8));
+        }
+        if response.answers.is_empty() {
+            return Err(ResolveError::Transfer("empty message".into()));
+        }
+        for record in response.answers {
+            if records.is_empty() && record.qtype != DnsType::Soa {
+                return Err(ResolveError::Transfer("does not start with a SOA".into()));
+            }
+            if record.qtype == DnsType::Soa && !records.is_empty() {
+                if record.rdata != records[0].rdata {
+                    return Err(ResolveError::Transfer("closing SOA differs".into()));
+                }
+                if !unsigned.is_empty() {
+                    return Err(TsigError::Unsigned.into());
+                }
+                return Ok(Transfer { records, expire });
+            }
+            records.push(record);
+        }
+    }
+}
+
+// One `--transfer zone[,view=name][,key=[hmac-sha256:]name:secret]` rule:
+// clients on the view's listeners, or on any listener without a view, may
+// transfer the zone, with requests signed by the key if there is one.
+struct Rule {
+    zone: Name,
+    view: Option<String>,
+    key: Option<TsigKey>,
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let zone = match parts.next() {
+            Some(zone) if !zone.is_empty() => Name::from(zone),
+            _ => return Err(format!("expected a zone in {:?}", spec)),
+        };
+        let (mut view, mut key) = (None, None);
+        for part in parts {
+            match part.split_once('=') {
+                Some(("view", name)) if !name.is_empty() => view = Some(name.to_string()),
+                Some(("key", spec)) => key = Some(TsigKey::parse(spec)?),
+                _ => return Err(format!("unknown transfer option {:?}", part)),
+            }
+        }
+        Ok(Rule { zone, view, key })
+    }
+}
+
+// Who may transfer which zones. Without a rule for a zone nobody may.
+#[derive(Default)]
+pub(crate) struct Transfers {
+    rules: Vec<Rule>,
+}
+
+impl Transfers {
+    pub(crate) fn add(&mut self, spec: &str) -> Result<(), String> {
+        self.rules.push(Rule::parse(spec)?);
+        Ok(())
+    }
+
+    // A key the rules name, for checking the requests signed with it.
+    pub(crate) fn key(&self, name: &Name) -> Option<TsigKey> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.key.as_ref())
+            .find(|key| key.name.eq_ignore_case(name))
+            .cloned()
+    }
+
+    // Whether a request from the view, signed by `signer` if at all, may
+    // transfer the zone.
+    fn allow(&self, zone: &Name, view: Option<&str>, signer: Option<&Name>) -> bool {
+        self.rules.iter().any(|rule| {
+            rule.zone.eq_ignore_case(zone)
+                && (rule.view.is_none() || rule.view.as_deref() == view)
+                && match (&rule.key, signer) {
+                    (None, _) => true,
+                    (Some(key), Some(signer)) => key.name.eq_ignore_case(signer),
+                    (Some(_), None) => false,
+                }
+        })
+    }
+}
+
+// Answers an AXFR, or an IXFR with the changes since the serial the
+// secondary has when the journal still holds them and the whole zone as
+// RFC 1995 section 4 allows when it doesn't, in as many messages as it
+// takes. A view gets only the records its `--policy` rules would answer
+// queries with, so the records kept from one view's queries don't reach it
+// through transfers either; the SOA always goes, to open and close the
+// transfer.
+pub(crate) fn serve(
+    mut packet: DnsPacket,
+    view: Option<&str>,
+    signer: Option<&Name>,
+    config: &Config,
+    state: &State,
+) -> Vec<DnsPacket> {
+    packet.header.flip_qr();
+    packet.header.ra = false;
+    packet.additionals.clear();
+    let expire_requested = packet.edns.as_ref().is_some_and(Edns::expire_requested);
+    packet.edns = packet.edns.take().map(|_| Edns::new(config.max_udp_size));
+    let Some(question) = packet.questions.first().cloned() else {
+        packet.header.rcode = ResponseCode::FormatError;
+        return vec![packet];
+    };
+    let apex = &question.qname;
+    // The secondary's SOA comes in the authority section (RFC 1995 section 3).
+    let known = match question.qtype {
+        DnsType::Ixfr => match packet.authorities.iter().find_map(soa_serial) {
+            Some(serial) => Some(serial),
+            None => {
+                packet.header.rcode = ResponseCode::FormatError;
+                return vec![packet];
+            }
+        },
+        _ => None,
+    };
+    packet.authorities.clear();
+
+    let zones = state.zones();
+    let secondary = state.secondaries.find(apex);
+    let (zone, expire) = match (zones.find(apex), &secondary) {
+        (Some(zone), _) if zone.apex.eq_ignore_case(apex) => (zone, zone.expire()),
+        (_, Some((_, Served::Zone(zone, left)))) if zone.apex.eq_ignore_case(apex) => {
+            (&**zone, Some(*left))
+        }
+        _ => {
+            packet.header.rcode = ResponseCode::NotAuth;
+            return vec![packet];
+        }
+    };
+    if !config.transfers.allow(apex, view, signer)
+        || config.policies.refuses(apex, question.qtype, view)
+    {
+        packet.header.rcode = ResponseCode::Refused;
+        return vec![packet];
+    }
+    let Some(soa) = zone.soa() else {
+        packet.header.rcode = ResponseCode::ServFail;
+        return vec![packet];
+    };
+    packet.header.aa = true;
+    if let (true, Some(expire), Some(edns)) = (expire_requested, expire, &mut packet.edns) {
+        edns.options.push(EdnsOption::Expire(Some(expire)));
+    }
+
+    let hidden = |record: &DnsAnswer| {
+        let qtype = match (record.qtype, &record.rdata) {
+            (DnsType::Rrsig, RData::Unknown(rdata)) => Rrsig::parse(rdata)
+                .map(|rrsig| DnsType::from(rrsig.type_covered))
+                .unwrap_or(DnsType::Rrsig),
+            (qtype, _) => qtype,
+        };
+        config.policies.hides(&record.name, qtype, view)
+    };
+    if let (Some(known), Some(current)) = (known, soa_serial(&soa)) {
+        // Already current, which the SOA alone says.
+        if !serial::is_newer(current, known) {
+            state.journal.served(true);
+            packet.answers.push(soa);
+            return vec![packet];
+        }
+        if let Some(deltas) = state.journal.since(apex, known, current) {
+            state.journal.served(true);
+            let mut records = vec![soa.clone()];
+            for delta in deltas {
+                records.push(delta.from);
+                records.extend(delta.removed.into_iter().filter(|record| !hidden(record)));
+                records.push(delta.to);
+                records.extend(delta.added.into_iter().filter(|record| !hidden(record)));
+            }
+            records.push(soa);
+            return messages(&packet, records.iter());
+        }
+    }
+    state.journal.served(false);
+    let records = zone
+        .records()
+        .iter()
+        .filter(|record| record.qtype != DnsType::Soa && !hidden(record));
+    messages(&packet, std::iter::once(&soa).chain(records).chain([&soa]))
+}
+
+fn soa_serial(record: &DnsAnswer) -> Option<u32> {
+    match record.rdata {
+        RData::Soa { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+// The records as answers to the request, split into messages of about
+// MESSAGE_SIZE.
+fn messages<'a>(
+    packet: &DnsPacket,
+    records: impl Iterator<Item = &'a DnsAnswer>,
+) -> Vec<DnsPacket> {
+    let mut messages = vec![packet.clone()];
+    let mut size = 0;
+    for record in records {
+        let mut bytes = Vec::new();
+        record.write(&mut bytes);
+        if size + bytes.len() > MESSAGE_SIZE && size > 0 {
+            messages.push(DnsPacket {
+                edns: None,
+                ..packet.clone()
+            });
+            size = 0;
+        }
+        size += bytes.len();
+        messages.last_mut().unwrap().answers.push(record.clone());
+    }
+    messages
+}
+
+fn write_message(stream: &mut TcpStream, message: &[u8]) -> Result<(), ResolveError> {
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed).map_err(timeout_error)
+}
+
+fn read_message(stream: &mut TcpStream) -> Result<Vec<u8>, ResolveError> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).map_err(timeout_error)?;
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).map_err(timeout_error)?;
+    Ok(message)
+}
+
+// `dns-server axfr <zone> @server [-y key] [file]`, writing the transferred
+// zone in presentation format to the file or stdout.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let mut zone = None;
+    let mut server = None;
+    let mut key = None;
+    let mut path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let parsed = if arg == "-y" {
+            args.next()
+                .ok_or_else(|| "missing key".to_string())
+                .and_then(|spec| TsigKey::parse(&spec))
+                .map(|parsed| key = Some(parsed))
+        } else if let Some(addr) = arg.strip_prefix('@') {
+            parse_server(addr).map(|addr| server = Some(addr))
+        } else if zone.is_none() {
+            zone = Some(Name::from(arg.as_str()));
+            Ok(())
+        } else if path.is_none() {
+            path = Some(arg);
+            Ok(())
+        } else {
+            Err(format!("unexpected argument: {}", arg))
+        };
+        if let Err(e) = parsed {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    }
+    let (Some(zone), Some(server)) = (zone, server) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let transfer = match axfr(server, &zone, key.as_ref(), TRANSFER_TIMEOUT) {
+        Ok(transfer) => transfer,
+        Err(e) => {
+            eprintln!("; transfer of {} from {} failed: {}", zone, server, e);
+            return 1;
+        }
+    };
+
+    let mut out = format!(
+        "; zone transfer of {}. from {}#{}\n",
+        zone,
+        server.ip(),
+        server.port()
+    );
+    if let Some(expire) = transfer.expire {
+        out.push_str(&format!("; expires in {} seconds\n", expire));
+    }
+    for record in &transfer.records {
+        out.push_str(&format!("{}\n", record));
+    }
+    out.push_str(&format!("; {} records\n", transfer.records.len()));
+
+    let written = match &path {
+        Some(path) => fs::write(path, out),
+        None => std::io::stdout().write_all(out.as_bytes()),
+    };
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("couldn't write zone: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::RData;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn record(name: &str, qtype: DnsType, rdata: RData) -> DnsAnswer {
+        DnsAnswer::new(name.into(), qtype, DnsClass::In, 3600, rdata)
+    }
+
+    fn soa() -> DnsAnswer {
+        record(
+            "example.com",
+            DnsType::Soa,
+            RData::Soa {
+                mname: "ns1.example.com".into(),
+                rname: "hostmaster.example.com".into(),
+                serial: 2024010101,
+                refresh: 7200,
+                retry: 900,
+                expire: 1209600,
+                minimum: 300,
+            },
+        )
+    }
+
+    // Serves the zone as two messages, optionally signing both.
+    fn spawn_primary(key: Option<TsigKey>, rcode: ResponseCode) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = read_message(&mut stream).unwrap();
+            let mut mac = key
+                .as_ref()
+                .map(|key| key.verify(&request, None, tsig::now()).unwrap().mac);
+
+            let messages = [
+                vec![
+                    soa(),
+                    record("www.example.com", DnsType::A, RData::A([192, 0, 2, 1])),
+                ],
+                vec![
+                    record(
+                        "example.com",
+                        DnsType::Ns,
+                        RData::Ns("ns1.example.com".into()),
+                    ),
+                    soa(),
+                ],
+            ];
+            for (i, answers) in messages.into_iter().enumerate() {
+                let mut response = DnsPacket::try_from(request.as_slice()).unwrap();
+                response.header.flip_qr();
+                response.header.rcode = rcode;
+                response.additionals.clear();
+                if let Some(edns) = &mut response.edns {
+                    edns.options = vec![EdnsOption::Expire(Some(86400))];
+                }
+                response.answers = answers;
+                let mut bytes = response.to_bytes();
+                if let (Some(key), Some(prior)) = (&key, &mac) {
+                    let (signed, next) = if i == 0 {
+                        key.sign(&bytes, Some(prior), tsig::now())
+                    } else {
+                        key.sign_subsequent(&bytes, prior, tsig::now())
+                    };
+                    bytes = signed;
+                    mac = Some(next);
+                }
+                write_message(&mut stream, &bytes).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_axfr() {
+        let primary = spawn_primary(None, ResponseCode::NoError);
+        let transfer = axfr(primary, &"example.com".into(), None, Duration::from_secs(5)).unwrap();
+        let types: Vec<DnsType> = transfer.records.iter().map(|r| r.qtype).collect();
+        assert_eq!(types, vec![DnsType::Soa, DnsType::A, DnsType::Ns]);
+        assert_eq!(transfer.expire, Some(86400));
+    }
+
+    #[test]
+    fn test_signed_axfr() {
+        let key = || TsigKey::new("xfr-key".into(), b"transfer secret".to_vec());
+        let primary = spawn_primary(Some(key()), ResponseCode::NoError);
+        let transfer = axfr(
+            primary,
+            &"example.com".into(),
+            Some(&key()),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(transfer.records.len(), 3);
+    }
+
+    #[test]
+    fn test_transfer_rules() {
+        for bad in [
+            "",
+            ",view=internal",
+            "example.com,view=",
+            "example.com,key=xfr",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{:?}", bad);
+        }
+        let mut transfers = Transfers::default();
+        transfers
+            .add("example.com,view=internal,key=xfr:c2VjcmV0")
+            .unwrap();
+        transfers.add("example.org").unwrap();
+        let (zone, key) = (Name::from("Example.COM"), Name::from("xfr"));
+        assert!(transfers.allow(&zone, Some("internal"), Some(&key)));
+        assert!(!transfers.allow(&zone, Some("internal"), None));
+        assert!(!transfers.allow(&zone, None, Some(&key)));
+        assert!(!transfers.allow(&"sub.example.com".into(), Some("internal"), Some(&key)));
+        assert!(transfers.allow(&"example.org".into(), Some("external"), None));
+        assert!(transfers.key(&"XFR".into()).is_some());
+        assert!(transfers.key(&"other".into()).is_none());
+    }
+
+    #[test]
+    fn test_refused_axfr() {
+        let primary = spawn_primary(None, ResponseCode::NotAuth);
+        assert!(matches!(
+            axfr(primary, &"example.com".into(), None, Duration::from_secs(5)),
+            Err(ResolveError::Rcode(9))
+        ));
+    }
+}
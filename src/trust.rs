@@ -0,0 +1,492 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::answer::DnsAnswer;
+use crate::common::{DnsType, Name};
+use crate::config::Config;
+use crate::crypto::{base64_decode, base64_encode};
+use crate::dnssec::{self, Dnskey, SECURE_ENTRY_POINT, ZONE_KEY};
+use crate::handler::State;
+use crate::recursor::Recursor;
+
+// How long a new key must keep being seen before it is trusted, and how
+// long a revoked one is remembered (RFC 5011 section 2.4.1).
+const ADD_HOLD_DOWN: u64 = 30 * 86400;
+const REMOVE_HOLD_DOWN: u64 = 30 * 86400;
+
+// Bounds on the time between refreshes (RFC 5011 section 2.3).
+const MIN_REFRESH: u64 = 3600;
+const MAX_REFRESH: u64 = 15 * 86400;
+
+// The root key signing keys IANA publishes: KSK-2017 (tag 20326) and
+// KSK-2024 (tag 38696), trusted from the start when there's no state yet.
+const ROOT_KEYS: [&str; 2] = [
+    "AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN\
+     7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8\
+     efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLY\
+     A4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=",
+    "AwEAAa96jeuknZlaeSrvyAJj6ZHv28hhOKkx3rLGXVaC6rXTsDc449/cidltpkyGwCJNnOAlFNKF2jBosZBU5eeH\
+     spaQWOmOElZsjICMQMC3aeHbGiShvZsx4wMYSjH8e7Vrhbu6irwCzVBApESjbUdpWWmEnhathWu1jo+siFUiRAAx\
+     m9qyJNg/wOZqqzL/dL/q8PkcRU5oUKEpUge71M3ej2/7CPqpdVwuMoTvoB+ZOT4YeGyxMvHmbrxlFzGOHOijtzN+\
+     u1TQNatX2XBuzZNQ1K+s2CXkPIZo7s6JgZyvaBevYtxPvYLw4z9mR7K2vaF18UYH9Z9GNUUeayffKC73PYc=",
+];
+
+// Where a key is in its life as a trust anchor (RFC 5011 section 4).
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum KeyState {
+    // Seen in a validated key set; trusted once the add hold-down passes.
+    AddPend,
+    Valid,
+    // Trusted, but gone from the key set.
+    Missing,
+    // Revoked by its own signature; never trusted again.
+    Revoked,
+}
+
+impl KeyState {
+    fn as_str(self) -> &'static str {
+        match self {
+            KeyState::AddPend => "ADDPEND",
+            KeyState::Valid => "VALID",
+            KeyState::Missing => "MISSING",
+            KeyState::Revoked => "REVOKED",
+        }
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        [
+            KeyState::AddPend,
+            KeyState::Valid,
+            KeyState::Missing,
+            KeyState::Revoked,
+        ]
+        .into_iter()
+        .find(|state| state.as_str() == text)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Anchor {
+    key: Dnskey,
+    state: KeyState,
+    // When the key entered its state, in seconds since the epoch.
+    since: u64,
+}
+
+// The root's trust anchors, kept current through key rollovers as RFC 5011
+// describes: keys the root signs in with a trusted key are trusted after
+// the add hold-down, and keys that revoke themselves stop being trusted.
+#[derive(PartialEq, Debug)]
+pub(crate) struct TrustAnchors {
+    anchors: Vec<Anchor>,
+}
+
+impl TrustAnchors {
+    pub(crate) fn builtin() -> Self {
+        let anchors = ROOT_KEYS
+            .iter()
+            .map(|key| Anchor {
+                key: Dnskey {
+                    flags: ZONE_KEY | SECURE_ENTRY_POINT,
+                    protocol: 3,
+                    algorithm: dnssec::RSASHA256,
+                    public_key: base64_decode(key).expect("built-in root key"),
+                },
+                state: KeyState::Valid,
+                since: 0,
+            })
+            .collect();
+        TrustAnchors { anchors }
+    }
+
+    // The built-in keys until there is a state file.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                TrustAnchors::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(TrustAnchors::builtin()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    // Written to a temporary file first, so a crash leaves the old state.
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, self.to_text())?;
+        fs::rename(&temporary, path)
+    }
+
+    // One key per line, in DNSKEY presentation format with its state and
+    // since when after a comment marker:
+    //
+    //   . DNSKEY 257 3 8 AwEAAaz/tAm8... ; VALID 1700000000
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut anchors = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let anchor =
+                parse_anchor(line).ok_or_else(|| format!("line {}: bad trust anchor", i + 1))?;
+            anchors.push(anchor);
+        }
+        if anchors.is_empty() {
+            return Err("no trust anchors".to_string());
+        }
+        Ok(TrustAnchors { anchors })
+    }
+
+    fn to_text(&self) -> String {
+        let mut text =
+            String::from("; Root trust anchors, updated as the root's keys roll over.\n");
+        for anchor in &self.anchors {
+            let key = &anchor.key;
+            writeln!(
+                text,
+                ". DNSKEY {} {} {} {} ; {} {}",
+                key.flags,
+                key.protocol,
+                key.algorithm,
+                base64_encode(&key.public_key),
+                anchor.state.as_str(),
+                anchor.since
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    // The keys the root's key set must be signed with.
+    fn trusted(&self) -> impl Iterator<Item = &Dnskey> {
+        self.anchors
+            .iter()
+            .filter(|anchor| matches!(anchor.state, KeyState::Valid | KeyState::Missing))
+            .map(|anchor| &anchor.key)
+    }
+
+    // Moves each key along given the root's DNSKEY records and signatures,
+    // as of `now`. A key set no trusted key has signed changes nothing.
+    pub(crate) fn update(&mut self, records: &[DnsAnswer], now: u64) -> Result<(), String> {
+        let root = Name::from("");
+        let rrset: Vec<DnsAnswer> = records
+            .iter()
+            .filter(|record| record.qtype == DnsType::Dnskey && record.name == root)
+            .cloned()
+            .collect();
+        let keys = dnssec::dnskeys(&rrset);
+        let owned: Vec<DnsAnswer> = records
+            .iter()
+            .filter(|record| record.name == root)
+            .cloned()
+            .collect();
+        let rrsigs: Vec<_> = dnssec::rrsigs(&owned, DnsType::Dnskey)
+            .into_iter()
+            .filter(|rrsig| rrsig.signer == root && rrsig.current(now))
+            .collect();
+        let signers: Vec<&Dnskey> = keys
+            .iter()
+            .filter(|key| rrsigs.iter().any(|rrsig| rrsig.verify(key, &rrset)))
+            .collect();
+        let validated = signers
+            .iter()
+            .any(|signer| self.trusted().any(|anchor| anchor.same_key(signer)));
+        if !validated {
+            return Err("root key set isn't signed by a trusted key".to_string());
+        }
+
+        self.anchors.retain_mut(|anchor| {
+            let seen = keys.iter().find(|key| key.same_key(&anchor.key));
+            let revoked = seen.filter(|key| key.is_revoked() && signers.contains(key));
+            let (state, keep) = match (anchor.state, seen) {
+                (KeyState::Revoked, _) => {
+                    (KeyState::Revoked, now < anchor.since + REMOVE_HOLD_DOWN)
+                }
+                _ if revoked.is_some() => (KeyState::Revoked, true),
+                (KeyState::AddPend, None) => (KeyState::AddPend, false),
+                (KeyState::AddPend, Some(_)) if now >= anchor.since + ADD_HOLD_DOWN => {
+                    (KeyState::Valid, true)
+                }
+                (KeyState::Valid, None) => (KeyState::Missing, true),
+                (KeyState::Missing, Some(key)) if !key.is_revoked() => (KeyState::Valid, true),
+                (state, _) => (state, true),
+            };
+            if let Some(key) = revoked {
+                anchor.key = key.clone();
+            }
+            if state != anchor.state {
+                anchor.state = state;
+                anchor.since = now;
+            }
+            keep
+        });
+        for key in &keys {
+            let new = key.flags & SECURE_ENTRY_POINT != 0
+                && !key.is_revoked()
+                && !self.anchors.iter().any(|anchor| anchor.key.same_key(key));
+            if new {
+                self.anchors.push(Anchor {
+                    key: key.clone(),
+                    state: KeyState::AddPend,
+                    since: now,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_anchor(line: &str) -> Option<Anchor> {
+    let (record, state) = line.split_once(';')?;
+    let fields: Vec<&str> = record.split_whitespace().collect();
+    let [".", "DNSKEY", flags, protocol, algorithm, key @ ..] = fields.as_slice() else {
+        return None;
+    };
+    let [state, since] = state.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    Some(Anchor {
+        key: Dnskey {
+            flags: flags.parse().ok()?,
+            protocol: protocol.parse().ok()?,
+            algorithm: algorithm.parse().ok()?,
+            public_key: base64_decode(&key.concat()).filter(|key| !key.is_empty())?,
+        },
+        state: KeyState::parse(state)?,
+        since: since.parse().ok()?,
+    })
+}
+
+// How long until the key set is next fetched, going by its TTL and how
+// long its signatures have left (RFC 5011 section 2.3).
+fn refresh_after(records: &[DnsAnswer], now: u64) -> Duration {
+    let mut secs = MAX_REFRESH;
+    for rrsig in dnssec::rrsigs(records, DnsType::Dnskey) {
+        let left = rrsig.expiration.wrapping_sub(now as u32) as i32;
+        secs = secs
+            .min(rrsig.original_ttl as u64 / 2)
+            .min(left.max(0) as u64 / 2);
+    }
+    Duration::from_secs(secs.max(MIN_REFRESH))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Fetches the root's keys from the root servers for as long as the server
+// runs, saving the anchors after each successful update.
+pub(crate) fn maintain(
+    path: PathBuf,
+    mut anchors: TrustAnchors,
+    config: Arc<Config>,
+    state: Arc<State>,
+) {
+    thread::spawn(move || loop {
+        let recursor = Recursor::new(
+            config.recursion_limits,
+            config.max_udp_size,
+            &state.roots.get(),
+            Arc::clone(&state.reputation),
+        );
+        let now = now();
+        let result = recursor
+            .root_keys(Instant::now() + config.query_budget)
+            .map_err(|e| e.to_string())
+            .and_then(|records| anchors.update(&records, now).map(|()| records));
+        let wait = match result {
+            Ok(records) => {
+                if let Err(e) = anchors.save(&path) {
+                    eprintln!("Failed to save trust anchors to {}: {}", path.display(), e);
+                }
+                refresh_after(&records, now)
+            }
+            Err(e) => {
+                eprintln!("Failed to refresh root trust anchors: {}", e);
+                Duration::from_secs(MIN_REFRESH)
+            }
+        };
+        thread::sleep(wait);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dnssec::test::{
+        dnskey_record, ksk, sign, MODULUS, MODULUS_2, PRIVATE_EXPONENT, PRIVATE_EXPONENT_2,
+    };
+    use crate::dnssec::REVOKE;
+
+    const DAY: u64 = 86400;
+    const START: u64 = 1_700_000_000;
+
+    // The key set, signed by each of the keys given with private exponents.
+    fn key_set(keys: &[&Dnskey], signers: &[(&Dnskey, &str)], now: u64) -> Vec<DnsAnswer> {
+        let mut records: Vec<DnsAnswer> = keys.iter().map(|key| dnskey_record(".", key)).collect();
+        let signatures: Vec<DnsAnswer> = signers
+            .iter()
+            .map(|(key, private)| sign(&records, ".", key, private, now as u32 - DAY as u32))
+            .collect();
+        records.extend(signatures);
+        records
+    }
+
+    fn states(anchors: &TrustAnchors) -> Vec<(u16, KeyState)> {
+        anchors
+            .anchors
+            .iter()
+            .map(|anchor| (anchor.key.key_tag(), anchor.state))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin() {
+        let anchors = TrustAnchors::builtin();
+        let tags: Vec<u16> = anchors.trusted().map(Dnskey::key_tag).collect();
+        assert_eq!(tags, vec![20326, 38696]);
+        assert_eq!(TrustAnchors::parse(&anchors.to_text()), Ok(anchors));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(TrustAnchors::parse("; nothing\n").is_err());
+        assert!(TrustAnchors::parse(". DNSKEY 257 3 8 AwEAAQ== ; TRUSTED 0\n").is_err());
+        assert!(TrustAnchors::parse(". DNSKEY 257 3 8 AwEAAQ==\n").is_err());
+        let anchors = TrustAnchors::parse(". DNSKEY 257 3 8 AwEA AQ== ; ADDPEND 1700000000\n");
+        assert_eq!(
+            anchors.map(|anchors| anchors.anchors[0].clone()),
+            Ok(Anchor {
+                key: Dnskey {
+                    flags: 257,
+                    protocol: 3,
+                    algorithm: 8,
+                    public_key: vec![3, 1, 0, 1],
+                },
+                state: KeyState::AddPend,
+                since: 1_700_000_000,
+            })
+        );
+    }
+
+    #[test]
+    fn test_rollover() {
+        let old = ksk(MODULUS);
+        let new = ksk(MODULUS_2);
+        let mut revoked = old.clone();
+        revoked.flags |= REVOKE;
+        let mut anchors = TrustAnchors {
+            anchors: vec![Anchor {
+                key: old.clone(),
+                state: KeyState::Valid,
+                since: 0,
+            }],
+        };
+
+        // Signed only by a key that isn't trusted yet.
+        let records = key_set(&[&old, &new], &[(&new, PRIVATE_EXPONENT_2)], START);
+        assert!(anchors.update(&records, START).is_err());
+        assert_eq!(states(&anchors), vec![(old.key_tag(), KeyState::Valid)]);
+
+        // The new key is published and signed in with the old one.
+        let records = key_set(&[&old, &new], &[(&old, PRIVATE_EXPONENT)], START);
+        anchors.update(&records, START).unwrap();
+        assert_eq!(
+            states(&anchors),
+            vec![
+                (old.key_tag(), KeyState::Valid),
+                (new.key_tag(), KeyState::AddPend)
+            ]
+        );
+        let later = START + 29 * DAY;
+        let records = key_set(&[&old, &new], &[(&old, PRIVATE_EXPONENT)], later);
+        anchors.update(&records, later).unwrap();
+        assert_eq!(anchors.anchors[1].state, KeyState::AddPend);
+        let later = START + 30 * DAY;
+        let records = key_set(&[&old, &new], &[(&old, PRIVATE_EXPONENT)], later);
+        anchors.update(&records, later).unwrap();
+        assert_eq!(anchors.anchors[1].state, KeyState::Valid);
+
+        // The old key revokes itself, and the new one signs alone after.
+        let later = START + 60 * DAY;
+        let records = key_set(
+            &[&revoked, &new],
+            &[(&revoked, PRIVATE_EXPONENT), (&new, PRIVATE_EXPONENT_2)],
+            later,
+        );
+        anchors.update(&records, later).unwrap();
+        assert_eq!(
+            states(&anchors),
+            vec![
+                (revoked.key_tag(), KeyState::Revoked),
+                (new.key_tag(), KeyState::Valid)
+            ]
+        );
+        let later = START + 91 * DAY;
+        let records = key_set(&[&new], &[(&new, PRIVATE_EXPONENT_2)], later);
+        anchors.update(&records, later).unwrap();
+        assert_eq!(states(&anchors), vec![(new.key_tag(), KeyState::Valid)]);
+
+        // Survives a restart.
+        let path = std::env::temp_dir().join(format!("dns-server-trust-{}", std::process::id()));
+        anchors.save(&path).unwrap();
+        assert_eq!(TrustAnchors::load(&path), Ok(anchors));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(TrustAnchors::load(&path), Ok(TrustAnchors::builtin()));
+    }
+
+    #[test]
+    fn test_add_pending_key_withdrawn() {
+        let old = ksk(MODULUS);
+        let new = ksk(MODULUS_2);
+        let mut anchors = TrustAnchors {
+            anchors: vec![Anchor {
+                key: old.clone(),
+                state: KeyState::Valid,
+                since: 0,
+            }],
+        };
+        let records = key_set(&[&old, &new], &[(&old, PRIVATE_EXPONENT)], START);
+        anchors.update(&records, START).unwrap();
+        // Gone before the hold-down ends, as a key slipped in by a
+        // compromised signer would be once noticed.
+        let later = START + 10 * DAY;
+        let records = key_set(&[&old], &[(&old, PRIVATE_EXPONENT)], later);
+        anchors.update(&records, later).unwrap();
+        assert_eq!(states(&anchors), vec![(old.key_tag(), KeyState::Valid)]);
+
+        // Starts over if it comes back.
+        let later = START + 31 * DAY;
+        let records = key_set(&[&old, &new], &[(&old, PRIVATE_EXPONENT)], later);
+        anchors.update(&records, later).unwrap();
+        assert_eq!(anchors.anchors[1].state, KeyState::AddPend);
+        assert_eq!(anchors.anchors[1].since, later);
+    }
+
+    #[test]
+    fn test_refresh_after() {
+        let key = ksk(MODULUS);
+        let records = key_set(&[&key], &[(&key, PRIVATE_EXPONENT)], START);
+        // Half the two-day TTL.
+        assert_eq!(refresh_after(&records, START), Duration::from_secs(DAY));
+        // Half the two days the signature has left, at least an hour.
+        assert_eq!(
+            refresh_after(&records, START + 27 * DAY),
+            Duration::from_secs(DAY)
+        );
+        assert_eq!(
+            refresh_after(&records, START + 40 * DAY),
+            Duration::from_secs(MIN_REFRESH)
+        );
+    }
+}
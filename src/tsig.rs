@@ -0,0 +1,408 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::{DnsClass, DnsType, Name};
+use crate::crypto::{base64_decode, hmac_sha256};
+use crate::error::{ParseError, TsigError};
+
+pub(crate) const HMAC_SHA256: &str = "hmac-sha256";
+const FUDGE: u16 = 300;
+
+// TSIG error codes carried in the record (RFC 8945 section 3).
+const BADSIG: u16 = 16;
+const BADKEY: u16 = 17;
+const BADTIME: u16 = 18;
+
+#[derive(Clone)]
+pub(crate) struct TsigKey {
+    pub(crate) name: Name,
+    secret: Vec<u8>,
+}
+
+// The RDATA of a TSIG record (RFC 8945 section 4.2).
+#[derive(PartialEq, Debug)]
+pub(crate) struct Tsig {
+    pub(crate) algorithm: Name,
+    pub(crate) time_signed: u64,
+    pub(crate) fudge: u16,
+    pub(crate) mac: Vec<u8>,
+    pub(crate) original_id: u16,
+    pub(crate) error: u16,
+    pub(crate) other: Vec<u8>,
+}
+
+impl TsigKey {
+    pub(crate) fn new(name: Name, secret: Vec<u8>) -> Self {
+        TsigKey { name, secret }
+    }
+
+    // `[hmac-sha256:]name:base64-secret`, as taken by `nsupdate -y`.
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        let (algorithm, name, secret) = match parts.as_slice() {
+            [name, secret] => (HMAC_SHA256, *name, *secret),
+            [algorithm, name, secret] => (*algorithm, *name, *secret),
+            _ => return Err(format!("invalid key {}", spec)),
+        };
+        if !algorithm.eq_ignore_ascii_case(HMAC_SHA256) {
+            return Err(format!("unsupported TSIG algorithm {}", algorithm));
+        }
+        let secret = base64_decode(secret).ok_or("key secret is not valid base64")?;
+        Ok(TsigKey::new(Name::from(name), secret))
+    }
+
+    // Appends a TSIG record to `message` and returns the signed message
+    // together with its MAC. Responses chain on the request's MAC.
+    pub(crate) fn sign(
+        &self,
+        message: &[u8],
+        request_mac: Option<&[u8]>,
+        time_signed: u64,
+    ) -> (Vec<u8>, Vec<u8>) {
+        self.sign_with(message, request_mac, time_signed, false)
+    }
+
+    // Signs a later message of a multi-message response such as a zone
+    // transfer: the MAC covers the previous MAC, the message and only the
+    // timers (RFC 8945 section 5.3.1).
+    pub(crate) fn sign_subsequent(
+        &self,
+        message: &[u8],
+        prior_mac: &[u8],
+        time_signed: u64,
+    ) -> (Vec<u8>, Vec<u8>) {
+        self.sign_with(message, Some(prior_mac), time_signed, true)
+    }
+
+    // Checks the TSIG record at the end of `message` and returns it.
+    pub(crate) fn verify(
+        &self,
+        message: &[u8],
+        request_mac: Option<&[u8]>,
+        now: u64,
+    ) -> Result<Tsig, TsigError> {
+        self.check(&[], message, request_mac, false, now)
+    }
+
+    // Counterpart of `sign_subsequent`. `unsigned` holds any messages
+    // received since the last signed one, which the MAC covers too.
+    pub(crate) fn verify_subsequent(
+        &self,
+        unsigned: &[u8],
+        message: &[u8],
+        prior_mac: &[u8],
+        now: u64,
+    ) -> Result<Tsig, TsigError> {
+        self.check(unsigned, message, Some(prior_mac), true, now)
+    }
+
+    fn sign_with(
+        &self,
+        message: &[u8],
+        prior_mac: Option<&[u8]>,
+        time_signed: u64,
+        timers_only: bool,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut tsig = Tsig {
+            algorithm: Name::from(HMAC_SHA256),
+            time_signed,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: u16::from_be_bytes([message[0], message[1]]),
+            error: 0,
+            other: Vec::new(),
+        };
+        tsig.mac = self.mac(message, prior_mac, &tsig, timers_only);
+
+        let rdata = tsig.to_bytes();
+        let mut signed = message.to_vec();
+        signed.extend_from_slice(&self.name.to_bytes());
+        signed.extend_from_slice(&(DnsType::Tsig.code()).to_be_bytes());
+        signed.extend_from_slice(&(DnsClass::Any as u16).to_be_bytes());
+        signed.extend_from_slice(&0u32.to_be_bytes());
+        signed.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        signed.extend_from_slice(&rdata);
+        let arcount = u16::from_be_bytes([signed[10], signed[11]]) + 1;
+        signed[10..12].copy_from_slice(&arcount.to_be_bytes());
+        (signed, tsig.mac)
+    }
+
+    fn check(
+        &self,
+        unsigned: &[u8],
+        message: &[u8],
+        prior_mac: Option<&[u8]>,
+        timers_only: bool,
+        now: u64,
+    ) -> Result<Tsig, TsigError> {
+        let start = find_signature(message, DnsType::Tsig)?.ok_or(TsigError::Unsigned)?;
+        let (owner, offset) = Name::parse(message, start)?;
+        let (tsig, _) = Tsig::parse(message, offset + 10)?;
+
+        if !owner.eq_ignore_case(&self.name) || !tsig.algorithm.eq_ignore_case(&HMAC_SHA256.into())
+        {
+            return Err(TsigError::BadKey);
+        }
+        match tsig.error {
+            0 => {}
+            BADSIG => return Err(TsigError::BadSig),
+            BADKEY => return Err(TsigError::BadKey),
+            BADTIME => return Err(TsigError::BadTime),
+            error => return Err(TsigError::Other(error)),
+        }
+
+        // The MAC covers the message as it was before the TSIG was added.
+        let mut data = unsigned.to_vec();
+        let header = data.len();
+        data.extend_from_slice(&message[..start]);
+        data[header..header + 2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let arcount = u16::from_be_bytes([data[header + 10], data[header + 11]]) - 1;
+        data[header + 10..header + 12].copy_from_slice(&arcount.to_be_bytes());
+        if self.mac(&data, prior_mac, &tsig, timers_only) != tsig.mac {
+            return Err(TsigError::BadSig);
+        }
+        if now.abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+            return Err(TsigError::BadTime);
+        }
+        Ok(tsig)
+    }
+
+    fn mac(
+        &self,
+        message: &[u8],
+        prior_mac: Option<&[u8]>,
+        tsig: &Tsig,
+        timers_only: bool,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        if let Some(prior_mac) = prior_mac {
+            data.extend_from_slice(&(prior_mac.len() as u16).to_be_bytes());
+            data.extend_from_slice(prior_mac);
+        }
+        data.extend_from_slice(message);
+        if !timers_only {
+            data.extend_from_slice(&canonical(&self.name));
+            data.extend_from_slice(&(DnsClass::Any as u16).to_be_bytes());
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend_from_slice(&canonical(&tsig.algorithm));
+        }
+        data.extend_from_slice(&tsig.time_signed.to_be_bytes()[2..]);
+        data.extend_from_slice(&tsig.fudge.to_be_bytes());
+        if !timers_only {
+            data.extend_from_slice(&tsig.error.to_be_bytes());
+            data.extend_from_slice(&(tsig.other.len() as u16).to_be_bytes());
+            data.extend_from_slice(&tsig.other);
+        }
+        hmac_sha256(&self.secret, &data).to_vec()
+    }
+}
+
+impl Tsig {
+    fn parse(message: &[u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let (algorithm, offset) = Name::parse(message, offset)?;
+        let fixed = message
+            .get(offset..offset + 10)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let mut time = [0; 8];
+        time[2..].copy_from_slice(&fixed[..6]);
+        let fudge = u16::from_be_bytes([fixed[6], fixed[7]]);
+        let mac_size = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let offset = offset + 10;
+        let mac = message
+            .get(offset..offset + mac_size)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let offset = offset + mac_size;
+        let rest = message
+            .get(offset..offset + 6)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let other_len = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+        let other = message
+            .get(offset + 6..offset + 6 + other_len)
+            .ok_or(ParseError::UnexpectedEof)?;
+
+        let tsig = Tsig {
+            algorithm,
+            time_signed: u64::from_be_bytes(time),
+            fudge,
+            mac: mac.to_vec(),
+            original_id: u16::from_be_bytes([rest[0], rest[1]]),
+            error: u16::from_be_bytes([rest[2], rest[3]]),
+            other: other.to_vec(),
+        };
+        Ok((tsig, offset + 6 + other_len))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.algorithm.to_bytes();
+        bytes.extend_from_slice(&self.time_signed.to_be_bytes()[2..]);
+        bytes.extend_from_slice(&self.fudge.to_be_bytes());
+        bytes.extend_from_slice(&(self.mac.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.mac);
+        bytes.extend_from_slice(&self.original_id.to_be_bytes());
+        bytes.extend_from_slice(&self.error.to_be_bytes());
+        bytes.extend_from_slice(&(self.other.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.other);
+        bytes
+    }
+}
+
+// The name of the key a message is signed with, if it is.
+pub(crate) fn signer(message: &[u8]) -> Result<Option<Name>, ParseError> {
+    match find_signature(message, DnsType::Tsig)? {
+        Some(start) => Ok(Some(Name::parse(message, start)?.0)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn canonical(name: &Name) -> Vec<u8> {
+    Name::from(name.as_str().to_ascii_lowercase().as_str()).to_bytes()
+}
+
+// Offset of the TSIG or SIG(0) record, which must be the last one in the
+// message.
+pub(crate) fn find_signature(message: &[u8], qtype: DnsType) -> Result<Option<usize>, ParseError> {
+    let count = |i: usize| {
+        message
+            .get(i..i + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or(ParseError::UnexpectedEof)
+    };
+    let (qdcount, arcount) = (count(4)?, count(10)?);
+    let records = count(6)? + count(8)? + arcount;
+
+    let mut offset = 12;
+    for _ in 0..qdcount {
+        offset = Name::parse(message, offset)?.1 + 4;
+    }
+    let mut last = None;
+    for _ in 0..records {
+        let start = offset;
+        let (_, after_name) = Name::parse(message, offset)?;
+        let fixed = message
+            .get(after_name..after_name + 10)
+            .ok_or(ParseError::UnexpectedEof)?;
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        offset = after_name + 10 + rdlength;
+        if offset > message.len() {
+            return Err(ParseError::UnexpectedEof);
+        }
+        last = Some((start, u16::from_be_bytes([fixed[0], fixed[1]])));
+    }
+
+    Ok(match last {
+        Some((start, last)) if arcount > 0 && last == qtype.code() => Some(start),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::packet::DnsPacket;
+    use crate::question::DnsQuestion;
+
+    fn key() -> TsigKey {
+        TsigKey::parse("hmac-sha256:update-key:c2VjcmV0LXNlY3JldC1zZWNyZXQ=").unwrap()
+    }
+
+    fn message() -> Vec<u8> {
+        DnsPacket::query(
+            0x4242,
+            DnsQuestion {
+                qname: "example.com".into(),
+                qtype: DnsType::Soa,
+                qclass: DnsClass::In,
+            },
+        )
+        .to_bytes()
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let key = TsigKey::parse("update-key:c2VjcmV0").unwrap();
+        assert_eq!(key.name.as_str(), "update-key");
+        assert_eq!(key.secret, b"secret");
+        assert!(TsigKey::parse("hmac-md5:key:c2VjcmV0").is_err());
+        assert!(TsigKey::parse("key:not base64!").is_err());
+        assert!(TsigKey::parse("c2VjcmV0").is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = key();
+        let (signed, mac) = key.sign(&message(), None, 1_700_000_000);
+        assert_eq!(mac.len(), 32);
+
+        let packet = DnsPacket::try_from(&signed).unwrap();
+        assert_eq!(packet.additionals.len(), 1);
+        assert_eq!(packet.additionals[0].qtype, DnsType::Tsig);
+
+        let tsig = key.verify(&signed, None, 1_700_000_100).unwrap();
+        assert_eq!(tsig.mac, mac);
+        assert_eq!(tsig.original_id, 0x4242);
+
+        // A response is signed over the request MAC as well.
+        let (response, _) = key.sign(&message(), Some(&mac), 1_700_000_001);
+        assert!(key.verify(&response, Some(&mac), 1_700_000_001).is_ok());
+        assert_eq!(
+            key.verify(&response, Some(&[0; 32]), 1_700_000_001),
+            Err(TsigError::BadSig)
+        );
+    }
+
+    #[test]
+    fn test_subsequent_messages() {
+        let key = key();
+        let (first, first_mac) = key.sign(&message(), Some(&[1; 32]), 1_700_000_000);
+        assert!(key.verify(&first, Some(&[1; 32]), 1_700_000_000).is_ok());
+
+        let (second, _) = key.sign_subsequent(&message(), &first_mac, 1_700_000_001);
+        assert!(key
+            .verify_subsequent(&[], &second, &first_mac, 1_700_000_001)
+            .is_ok());
+        // Only the timers are covered, so a full verification fails.
+        assert_eq!(
+            key.verify(&second, Some(&first_mac), 1_700_000_001),
+            Err(TsigError::BadSig)
+        );
+        // As does skipping an unsigned message that came in between.
+        assert_eq!(
+            key.verify_subsequent(&message(), &second, &first_mac, 1_700_000_001),
+            Err(TsigError::BadSig)
+        );
+    }
+
+    #[test]
+    fn test_verify_failures() {
+        let key = key();
+        let (signed, _) = key.sign(&message(), None, 1_700_000_000);
+
+        assert_eq!(
+            key.verify(&message(), None, 1_700_000_000),
+            Err(TsigError::Unsigned)
+        );
+        assert_eq!(
+            key.verify(&signed, None, 1_700_000_301),
+            Err(TsigError::BadTime)
+        );
+
+        let mut tampered = signed.clone();
+        tampered[13] = b'E';
+        assert_eq!(
+            key.verify(&tampered, None, 1_700_000_000),
+            Err(TsigError::BadSig)
+        );
+
+        let other = TsigKey::new("other-key".into(), b"secret".to_vec());
+        assert_eq!(
+            other.verify(&signed, None, 1_700_000_000),
+            Err(TsigError::BadKey)
+        );
+    }
+}
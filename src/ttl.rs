@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rand::Rng;
+
+use crate::common::Name;
+use crate::config::Config;
+use crate::packet::DnsPacket;
+
+const MAX_JITTER: u32 = 50;
+
+// `--force-ttl [zone=]seconds`: records served for names in the zone, or
+// for every name without one, all get the TTL, so that failover tests and
+// demos don't wait out the real ones. The closest enclosing zone wins, and
+// the cache keeps the TTLs records came with.
+pub(crate) fn parse(spec: &str) -> Result<(Name, u32), String> {
+    let (zone, ttl) = match spec.split_once('=') {
+        Some((zone, ttl)) if !zone.is_empty() => (Name::from(zone), ttl),
+        Some(_) => return Err(format!("expected [zone=]seconds, got {:?}", spec)),
+        None => (Name::from(""), spec),
+    };
+    Ok((zone, parse_ttl(ttl)?))
+}
+
+// TTLs are at most 2^31 - 1 (RFC 2181 section 8).
+pub(crate) fn parse_ttl(text: &str) -> Result<u32, String> {
+    text.parse()
+        .ok()
+        .filter(|ttl| *ttl <= i32::MAX as u32)
+        .ok_or_else(|| format!("bad TTL {:?}", text))
+}
+
+// `--ttl-jitter percent[%]`, at most half of a TTL.
+pub(crate) fn parse_jitter(text: &str) -> Result<u32, String> {
+    text.strip_suffix('%')
+        .unwrap_or(text)
+        .parse()
+        .ok()
+        .filter(|percent| *percent <= MAX_JITTER)
+        .ok_or_else(|| format!("{:?} is not a percentage up to {}", text, MAX_JITTER))
+}
+
+// Zones whose TTL `ctl ttl` forced, or stopped forcing with None, until
+// set back to `auto`. Kept until restart.
+#[derive(Default)]
+pub(crate) struct Overrides {
+    zones: Mutex<HashMap<String, Option<u32>>>,
+}
+
+impl Overrides {
+    pub(crate) fn set(&self, zone: &Name, ttl: Option<u32>) {
+        let zone = zone.as_str().to_ascii_lowercase();
+        self.zones.lock().unwrap().insert(zone, ttl);
+    }
+
+    pub(crate) fn clear(&self, zone: &Name) {
+        let zone = zone.as_str().to_ascii_lowercase();
+        self.zones.lock().unwrap().remove(&zone);
+    }
+}
+
+// The TTL forced on answers for the name, if any.
+pub(crate) fn forced(name: &Name, config: &Config, overrides: &Overrides) -> Option<u32> {
+    let name = name.as_str().to_ascii_lowercase();
+    let suffixes = std::iter::once(name.as_str())
+        .chain(name.match_indices('.').map(|(dot, _)| &name[dot + 1..]))
+        .chain((!name.is_empty()).then_some(""));
+    let overrides = overrides.zones.lock().unwrap();
+    suffixes
+        .filter_map(|zone| {
+            // The last flag for a zone wins.
+            overrides.get(zone).copied().or_else(|| {
+                config
+                    .forced_ttls
+                    .iter()
+                    .rev()
+                    .find(|(forced, _)| forced.as_str().eq_ignore_ascii_case(zone))
+                    .map(|(_, ttl)| Some(*ttl))
+            })
+        })
+        .next()
+        .flatten()
+}
+
+pub(crate) fn apply(ttl: u32, response: &mut DnsPacket) {
+    for records in [
+        &mut response.answers,
+        &mut response.authorities,
+        &mut response.additionals,
+    ] {
+        for record in records.iter_mut() {
+            record.ttl = ttl as i32;
+        }
+    }
+}
+
+// Shortens the TTLs of a response served from the cache by one random
+// share of up to `percent`, so that clients which cached a popular record
+// together don't all come back for it together. One share for the whole
+// response keeps an RRset's TTLs equal (RFC 2181 section 5.2), and
+// shortening never has a record outlive its cache entry.
+pub(crate) fn jitter(percent: u32, response: &mut DnsPacket) {
+    if percent == 0 {
+        return;
+    }
+    let share = rand::thread_rng().gen_range(0.0..=percent as f64 / 100.0);
+    for records in [
+        &mut response.answers,
+        &mut response.authorities,
+        &mut response.additionals,
+    ] {
+        for record in records.iter_mut() {
+            record.ttl -= (record.ttl as f64 * share) as i32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::DnsAnswer;
+    use crate::common::{DnsClass, DnsType};
+    use crate::question::DnsQuestion;
+    use std::collections::HashSet;
+    use std::net::IpAddr;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("5").unwrap(), (Name::from(""), 5));
+        assert_eq!(
+            parse("Example.com.=30").unwrap(),
+            (Name::from("Example.com"), 30)
+        );
+        for bad in ["", "=5", "example.com=", "example.com=-1", "2147483648"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        assert_eq!(parse_jitter("10%"), Ok(10));
+        assert_eq!(parse_jitter("0"), Ok(0));
+        for bad in ["", "51", "-5", "ten"] {
+            assert!(parse_jitter(bad).is_err(), "{}", bad);
+        }
+
+        let question = DnsQuestion {
+            qname: "www.example.com".into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        let mut response = DnsPacket::query(1, question);
+        for last in 1..=2 {
+            let addr = IpAddr::from([192, 0, 2, last]);
+            response.add_answer(DnsAnswer::address("www.example.com".into(), 1000, addr));
+        }
+        let mut ttls = HashSet::new();
+        for _ in 0..50 {
+            let mut jittered = response.clone();
+            jitter(20, &mut jittered);
+            let ttl = jittered.answers[0].ttl;
+            assert!((800..=1000).contains(&ttl), "{}", ttl);
+            assert_eq!(jittered.answers[1].ttl, ttl);
+            ttls.insert(ttl);
+        }
+        assert!(ttls.len() > 1);
+        jitter(0, &mut response);
+        assert_eq!(response.answers[0].ttl, 1000);
+    }
+
+    #[test]
+    fn test_forced() {
+        let config = Config {
+            forced_ttls: vec![parse("60").unwrap(), parse("example.com=5").unwrap()],
+            ..Config::default()
+        };
+        let overrides = Overrides::default();
+        let forced = |name: &str| forced(&name.into(), &config, &overrides);
+        assert_eq!(forced("www.Example.com"), Some(5));
+        assert_eq!(forced("example.org"), Some(60));
+        assert_eq!(forced(""), Some(60));
+
+        overrides.set(&"www.example.com".into(), Some(1));
+        overrides.set(&"example.org".into(), None);
+        assert_eq!(forced("a.www.example.com"), Some(1));
+        assert_eq!(forced("mail.example.com"), Some(5));
+        assert_eq!(forced("example.org"), None);
+        overrides.clear(&"example.org".into());
+        assert_eq!(forced("example.org"), Some(60));
+        assert_eq!(forced("example.net"), Some(60));
+    }
+}
@@ -0,0 +1,1008 @@
+use std::fs;
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::authority::{Zone, Zones};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::Config;
+use crate::dnssec::Dnskey;
+use crate::edns::Edns;
+use crate::error::TsigError;
+use crate::handler::State;
+use crate::header::{OpCode, ResponseCode};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::resolver::Resolver;
+use crate::serial;
+use crate::sig0::{self, Sig0Key};
+use crate::tsig::{self, TsigKey};
+use crate::zone::{resolve_name, ZoneFile};
+
+const USAGE: &str =
+    "usage: dns-server update [-y [hmac-sha256:]name:secret | -k keyfile.private] [file]";
+
+// A dynamic update message (RFC 2136). The zone goes in the question
+// section, prerequisites in the answer section and updates in the authority
+// section; the class and TTL of each record say what it means.
+pub(crate) struct Update {
+    zone: Name,
+    prerequisites: Vec<DnsAnswer>,
+    updates: Vec<DnsAnswer>,
+}
+
+impl Update {
+    pub(crate) fn new(zone: Name) -> Self {
+        Update {
+            zone,
+            prerequisites: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    pub(crate) fn require_name_in_use(&mut self, name: Name) {
+        self.prerequisites
+            .push(empty(name, DnsType::Any, DnsClass::Any));
+    }
+
+    pub(crate) fn require_name_not_in_use(&mut self, name: Name) {
+        self.prerequisites
+            .push(empty(name, DnsType::Any, DnsClass::None));
+    }
+
+    pub(crate) fn require_rrset(&mut self, name: Name, qtype: DnsType) {
+        self.prerequisites.push(empty(name, qtype, DnsClass::Any));
+    }
+
+    // The RRset must exist and hold exactly these records.
+    pub(crate) fn require_records(&mut self, mut record: DnsAnswer) {
+        record.ttl = 0;
+        self.prerequisites.push(record);
+    }
+
+    pub(crate) fn require_no_rrset(&mut self, name: Name, qtype: DnsType) {
+        self.prerequisites.push(empty(name, qtype, DnsClass::None));
+    }
+
+    pub(crate) fn add(&mut self, record: DnsAnswer) {
+        self.updates.push(record);
+    }
+
+    pub(crate) fn delete_rrset(&mut self, name: Name, qtype: DnsType) {
+        self.updates.push(empty(name, qtype, DnsClass::Any));
+    }
+
+    pub(crate) fn delete_name(&mut self, name: Name) {
+        self.updates.push(empty(name, DnsType::Any, DnsClass::Any));
+    }
+
+    pub(crate) fn delete_record(&mut self, mut record: DnsAnswer) {
+        record.qclass = DnsClass::None;
+        record.ttl = 0;
+        self.updates.push(record);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.prerequisites.is_empty() && self.updates.is_empty()
+    }
+
+    pub(crate) fn to_packet(&self, id: u16) -> DnsPacket {
+        let mut packet = DnsPacket::query(
+            id,
+            DnsQuestion {
+                qname: self.zone.clone(),
+                qtype: DnsType::Soa,
+                qclass: DnsClass::In,
+            },
+        );
+        packet.header.opcode = OpCode::Update;
+        packet.header.rd = false;
+        packet.answers = self.prerequisites.clone();
+        packet.authorities = self.updates.clone();
+        packet
+    }
+}
+
+fn empty(name: Name, qtype: DnsType, qclass: DnsClass) -> DnsAnswer {
+    DnsAnswer::new(name, qtype, qclass, 0, RData::Unknown(Vec::new()))
+}
+
+struct Session {
+    server: Option<SocketAddr>,
+    key: Option<TsigKey>,
+    sig0: Option<Sig0Key>,
+    update: Option<Update>,
+}
+
+// `dns-server update`, a small nsupdate. Reads commands from a file or
+// stdin: server, zone, key, prereq, update add/delete and send. A blank line
+// sends the pending update too. Updates are signed with TSIG given `-y` or a
+// `key` command, or with SIG(0) given `-k` and a private key file.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let mut session = Session {
+        server: None,
+        key: None,
+        sig0: None,
+        update: None,
+    };
+    let mut path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-y" => match args.next().map(|spec| TsigKey::parse(&spec)) {
+                Some(Ok(key)) => session.key = Some(key),
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    return 2;
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            "-k" => match args.next().map(|path| Sig0Key::load(Path::new(&path))) {
+                Some(Ok(key)) => session.sig0 = Some(key),
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    return 2;
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    return 2;
+                }
+            },
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return 2;
+            }
+        }
+    }
+
+    let script = match &path {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut script = String::new();
+            io::stdin().read_to_string(&mut script).map(|_| script)
+        }
+    };
+    let script = match script {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("couldn't read commands: {}", e);
+            return 2;
+        }
+    };
+
+    for (i, line) in script.lines().enumerate() {
+        if let Err(e) = session.execute(line) {
+            eprintln!("line {}: {}", i + 1, e);
+            return 2;
+        }
+    }
+    match session.send() {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+impl Session {
+    fn execute(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.starts_with([';', '#']) {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] | ["send"] => self.send(),
+            ["server", host] => self.set_server(host, "53"),
+            ["server", host, port] => self.set_server(host, port),
+            ["zone", zone] => {
+                self.update = Some(Update::new(parse_name(zone)?));
+                Ok(())
+            }
+            ["key", name, secret] => {
+                self.key = Some(TsigKey::parse(&format!("{}:{}", name, secret))?);
+                Ok(())
+            }
+            ["prereq", kind, name, rest @ ..] => {
+                let name = parse_name(name)?;
+                let update = self.pending()?;
+                match (*kind, rest) {
+                    ("yxdomain", []) => update.require_name_in_use(name),
+                    ("nxdomain", []) => update.require_name_not_in_use(name),
+                    ("yxrrset", [qtype]) => update.require_rrset(name, parse_type(qtype)?),
+                    ("nxrrset", [qtype]) => update.require_no_rrset(name, parse_type(qtype)?),
+                    ("yxrrset", [_, ..]) => {
+                        let record = format!("{} 0 {}", name, rest.join(" "));
+                        update.require_records(parse_record(&record)?)
+                    }
+                    _ => return Err(format!("bad prerequisite: {}", line)),
+                }
+                Ok(())
+            }
+            ["update", "add", ..] => {
+                let rest = line["update".len()..].trim_start()["add".len()..].trim_start();
+                let record = parse_record(rest)?;
+                self.pending()?.add(record);
+                Ok(())
+            }
+            ["update", "delete" | "del", name, rest @ ..] => {
+                let name = parse_name(name)?;
+                let update = self.pending()?;
+                match rest {
+                    [] => update.delete_name(name),
+                    [qtype] => update.delete_rrset(name, parse_type(qtype)?),
+                    _ => {
+                        // Deleting one record: the zone parser needs a TTL,
+                        // which the message then ignores.
+                        let rest = rest.join(" ");
+                        update.delete_record(parse_record(&format!("{} 0 {}", name, rest))?)
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(format!("unknown command: {}", line)),
+        }
+    }
+
+    fn set_server(&mut self, host: &str, port: &str) -> Result<(), String> {
+        let port: u16 = port.parse().map_err(|_| format!("invalid port {}", port))?;
+        let addr = (host, port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| format!("couldn't resolve server {}", host))?;
+        self.server = Some(addr);
+        Ok(())
+    }
+
+    fn pending(&mut self) -> Result<&mut Update, String> {
+        self.update
+            .as_mut()
+            .ok_or_else(|| "no zone given, use 'zone <name>' first".to_string())
+    }
+
+    // Sends the pending update, if any. The zone stays selected afterwards.
+    fn send(&mut self) -> Result<(), String> {
+        let Some(update) = self.update.as_mut().filter(|u| !u.is_empty()) else {
+            return Ok(());
+        };
+        let server = match self.server {
+            Some(server) => server,
+            None => *Resolver::system_servers()
+                .first()
+                .ok_or("no server given and none in /etc/resolv.conf")?,
+        };
+
+        let query = update.to_packet(rand::thread_rng().gen());
+        let mut resolver = Resolver::new(vec![server]);
+        if let Some(sig0) = &self.sig0 {
+            resolver = resolver.with_sig0(sig0.clone());
+        }
+        let response = resolver
+            .send(&query, self.key.as_ref())
+            .map_err(|e| format!("update failed: {}", e))?;
+        *update = Update::new(update.zone.clone());
+
+        match response.header.rcode {
+            ResponseCode::NoError => Ok(()),
+            rcode => Err(format!("update failed: {}", rcode)),
+        }
+    }
+}
+
+fn parse_name(text: &str) -> Result<Name, String> {
+    resolve_name(text, Some(&Name::from("")))
+}
+
+fn parse_type(text: &str) -> Result<DnsType, String> {
+    text.parse()
+        .map_err(|_| format!("unknown record type {}", text))
+}
+
+// `<name> <ttl> [class] <type> <rdata>`, the same syntax as a zone file.
+fn parse_record(text: &str) -> Result<DnsAnswer, String> {
+    let mut zone = ZoneFile::parse(text, Some(Name::from("")));
+    if let Some(error) = zone.errors.pop() {
+        return Err(error.message);
+    }
+    zone.entries
+        .pop()
+        .map(|entry| entry.record)
+        .ok_or_else(|| format!("missing record: {}", text))
+}
+
+// One `--allow-update zone[,view=name][,key=[hmac-sha256:]name:secret]
+// [,sig0=name]` rule: clients on the view's listeners, or on any listener
+// without a view, may update the zone, with requests signed by the TSIG
+// key or one negotiated with it through TKEY, or by SIG(0) (RFC 2931) with
+// the key published as a KEY record at the name in the zone, if the rule
+// has either.
+struct Rule {
+    zone: Name,
+    view: Option<String>,
+    key: Option<TsigKey>,
+    sig0: Option<Name>,
+}
+
+impl Rule {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let zone = match parts.next() {
+            Some(zone) if !zone.is_empty() => Name::from(zone),
+            _ => return Err(format!("expected a zone in {:?}", spec)),
+        };
+        let (mut view, mut key, mut sig0) = (None, None, None);
+        for part in parts {
+            match part.split_once('=') {
+                Some(("view", name)) if !name.is_empty() => view = Some(name.to_string()),
+                Some(("key", spec)) => key = Some(TsigKey::parse(spec)?),
+                Some(("sig0", name)) if !name.is_empty() => sig0 = Some(Name::from(name)),
+                _ => return Err(format!("unknown update option {:?}", part)),
+            }
+        }
+        Ok(Rule {
+            zone,
+            view,
+            key,
+            sig0,
+        })
+    }
+}
+
+// Who may update which zones. Without a rule for a zone nobody may.
+#[derive(Default)]
+pub(crate) struct Updates {
+    rules: Vec<Rule>,
+}
+
+impl Updates {
+    pub(crate) fn add(&mut self, spec: &str) -> Result<(), String> {
+        self.rules.push(Rule::parse(spec)?);
+        Ok(())
+    }
+
+    // A key the rules name, for checking the requests signed with it.
+    pub(crate) fn key(&self, name: &Name) -> Option<TsigKey> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.key.as_ref())
+            .find(|key| key.name.eq_ignore_case(name))
+            .cloned()
+    }
+
+    // The SIG(0) key a rule names that `message` is signed with, checked
+    // against the KEY records at its name in the zone holding it.
+    pub(crate) fn sig0_signer(&self, message: &[u8], zones: &Zones, now: u64) -> Option<Name> {
+        let signer = sig0::verify(
+            message,
+            |name| {
+                zones
+                    .find(name)
+                    .map_or_else(Vec::new, |zone| keys(zone, name))
+            },
+            now,
+        )
+        .ok()?;
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.sig0.as_ref())
+            .any(|name| name.eq_ignore_case(&signer))
+            .then_some(signer)
+    }
+
+    // Whether a request from the view, signed with the TSIG key `signer` or
+    // the SIG(0) key `sig0` if at all, may update the zone.
+    fn allow(
+        &self,
+        zone: &Name,
+        view: Option<&str>,
+        signer: Option<&Name>,
+        sig0: Option<&Name>,
+    ) -> bool {
+        let signed = |required: Option<&Name>, by: Option<&Name>| {
+            required
+                .zip(by)
+                .is_some_and(|(required, by)| required.eq_ignore_case(by))
+        };
+        self.rules.iter().any(|rule| {
+            rule.zone.eq_ignore_case(zone)
+                && (rule.view.is_none() || rule.view.as_deref() == view)
+                && match (&rule.key, &rule.sig0) {
+                    (None, None) => true,
+                    (key, name) => {
+                        signed(key.as_ref().map(|key| &key.name), signer)
+                            || signed(name.as_ref(), sig0)
+                    }
+                }
+        })
+    }
+}
+
+// Answers a dynamic update (RFC 2136 section 3) to one of the `--zone`
+// zones. The changed zone is written to its file, which keeps its
+// comments and layout, and replaces the loaded one, with its serial
+// increased unless the update set it. An update the file can't take, such
+// as removing a record `$GENERATE` made, fails with SERVFAIL. `message` is
+// the request as received, for checking a SIG(0) signature.
+pub(crate) fn serve(
+    mut packet: DnsPacket,
+    message: &[u8],
+    view: Option<&str>,
+    signer: Option<&Name>,
+    config: &Config,
+    state: &State,
+) -> DnsPacket {
+    packet.header.flip_qr();
+    packet.header.ra = false;
+    packet.edns = packet.edns.take().map(|_| Edns::new(config.max_udp_size));
+    let applied = apply(&packet, message, view, signer, config, state);
+    packet.answers.clear();
+    packet.authorities.clear();
+    packet.additionals.clear();
+    if let Err(rcode) = applied {
+        packet.header.rcode = rcode;
+    }
+    packet
+}
+
+// The prerequisites are in the request's answer section and the updates in
+// its authority section.
+fn apply(
+    packet: &DnsPacket,
+    message: &[u8],
+    view: Option<&str>,
+    signer: Option<&Name>,
+    config: &Config,
+    state: &State,
+) -> Result<(), ResponseCode> {
+    let [zone] = packet.questions.as_slice() else {
+        return Err(ResponseCode::FormatError);
+    };
+    let (prerequisites, updates) = (&packet.answers, &packet.authorities);
+    if zone.qtype != DnsType::Soa {
+        return Err(ResponseCode::FormatError);
+    }
+    // Updates and reloads take turns, so neither undoes the other.
+    let _changing = state.changing_zones.lock().unwrap();
+    let zones = state.zones();
+    let Some(loaded) = zones.find(&zone.qname) else {
+        return Err(ResponseCode::NotAuth);
+    };
+    if !loaded.apex.eq_ignore_case(&zone.qname) {
+        return Err(ResponseCode::NotAuth);
+    }
+    // The KEY records SIG(0) is checked against are the zone's own.
+    let sig0 = match signer {
+        Some(_) => None,
+        None => match sig0::verify(message, |name| keys(loaded, name), tsig::now()) {
+            Ok(name) => Some(name),
+            Err(TsigError::Unsigned) => None,
+            Err(e) => {
+                eprintln!("Refusing an update to {}: {}", loaded.apex, e);
+                return Err(ResponseCode::NotAuth);
+            }
+        },
+    };
+    if !config
+        .updates
+        .allow(&loaded.apex, view, signer, sig0.as_ref())
+    {
+        return Err(ResponseCode::Refused);
+    }
+    check_prerequisites(loaded, prerequisites)?;
+    prescan(loaded, updates)?;
+    if let Some(records) = updated(loaded, updates) {
+        let zone = loaded.updated(records);
+        if let Err(e) = zone.save(loaded) {
+            eprintln!("Not updating zone {}: {}", loaded.apex, e);
+            return Err(ResponseCode::ServFail);
+        }
+        state.journal.record(loaded, &zone);
+        *state.zones.write().unwrap() = Arc::new(zones.replace(zone));
+    }
+    Ok(())
+}
+
+// The KEY records at the name in the zone.
+fn keys(zone: &Zone, name: &Name) -> Vec<Dnskey> {
+    zone.records_at(name, DnsType::Key)
+        .iter()
+        .filter_map(|record| match &record.rdata {
+            RData::Unknown(rdata) => Dnskey::parse(rdata),
+            _ => None,
+        })
+        .collect()
+}
+
+// How prerequisites and deletions say "any value": no RDATA.
+fn is_empty(record: &DnsAnswer) -> bool {
+    record.rdata == RData::Unknown(Vec::new())
+}
+
+// RFC 2136 section 3.2. Records of the zone's class together name RRsets
+// that must exist with exactly those records.
+fn check_prerequisites(zone: &Zone, prerequisites: &[DnsAnswer]) -> Result<(), ResponseCode> {
+    let mut required: Vec<&DnsAnswer> = Vec::new();
+    for record in prerequisites {
+        if record.ttl != 0 {
+            return Err(ResponseCode::FormatError);
+        }
+        if !record.name.is_subdomain_of(&zone.apex) {
+            return Err(ResponseCode::NotZone);
+        }
+        let exists = || match record.qtype {
+            DnsType::Any => zone.in_use(&record.name),
+            qtype => !zone.records_at(&record.name, qtype).is_empty(),
+        };
+        match (record.qclass, record.qtype) {
+            (DnsClass::Any | DnsClass::None, _) if !is_empty(record) => {
+                return Err(ResponseCode::FormatError)
+            }
+            (DnsClass::Any, DnsType::Any) if !exists() => return Err(ResponseCode::NxDomain),
+            (DnsClass::Any, _) if !exists() => return Err(ResponseCode::NxRrset),
+            (DnsClass::None, DnsType::Any) if exists() => return Err(ResponseCode::YxDomain),
+            (DnsClass::None, _) if exists() => return Err(ResponseCode::YxRrset),
+            (DnsClass::Any | DnsClass::None, _) => {}
+            (DnsClass::In, _) => required.push(record),
+            _ => return Err(ResponseCode::FormatError),
+        }
+    }
+    for record in &required {
+        let rrset: Vec<&RData> = required
+            .iter()
+            .filter(|other| other.name.eq_ignore_case(&record.name) && other.qtype == record.qtype)
+            .map(|other| &other.rdata)
+            .collect();
+        let existing = zone.records_at(&record.name, record.qtype);
+        let matches = existing.iter().all(|r| rrset.contains(&&r.rdata))
+            && rrset
+                .iter()
+                .all(|&rdata| existing.iter().any(|r| r.rdata == *rdata));
+        if !matches {
+            return Err(ResponseCode::NxRrset);
+        }
+    }
+    Ok(())
+}
+
+// RFC 2136 section 3.4.1: the whole update is refused before any of it is
+// applied.
+fn prescan(zone: &Zone, updates: &[DnsAnswer]) -> Result<(), ResponseCode> {
+    for record in updates {
+        if !record.name.is_subdomain_of(&zone.apex) {
+            return Err(ResponseCode::NotZone);
+        }
+        let meta = matches!(
+            record.qtype,
+            DnsType::Axfr | DnsType::Ixfr | DnsType::Opt | DnsType::Tkey | DnsType::Tsig
+        );
+        let valid = match record.qclass {
+            DnsClass::In => !meta && record.qtype != DnsType::Any,
+            DnsClass::Any => !meta && record.ttl == 0 && is_empty(record),
+            DnsClass::None => !meta && record.qtype != DnsType::Any && record.ttl == 0,
+            _ => false,
+        };
+        if !valid {
+            return Err(ResponseCode::FormatError);
+        }
+    }
+    Ok(())
+}
+
+// The zone's records once the updates are applied (RFC 2136 section
+// 3.4.2), or None if nothing changed. The apex keeps its SOA and at least
+// one NS, and a name can't hold a CNAME and other data.
+fn updated(zone: &Zone, updates: &[DnsAnswer]) -> Option<Vec<DnsAnswer>> {
+    let mut records = zone.records().to_vec();
+    let (mut changed, mut serial_set) = (false, false);
+    for update in updates {
+        let at_apex = update.name.eq_ignore_case(&zone.apex);
+        let at_name = |record: &DnsAnswer| record.name.eq_ignore_case(&update.name);
+        let before = records.len();
+        match update.qclass {
+            DnsClass::In => {
+                let conflicts = records.iter().filter(|r| at_name(r)).any(|r| {
+                    let cname = (r.qtype == DnsType::Cname, update.qtype == DnsType::Cname);
+                    cname.0 != cname.1 && !matches!(r.qtype, DnsType::Rrsig | DnsType::Nsec)
+                });
+                if conflicts {
+                    continue;
+                }
+                if update.qtype == DnsType::Soa {
+                    let soa = records.iter_mut().find(|r| r.qtype == DnsType::Soa);
+                    if let (true, Some(soa)) = (at_apex, soa) {
+                        if let (RData::Soa { serial: new, .. }, RData::Soa { serial: old, .. }) =
+                            (&update.rdata, &soa.rdata)
+                        {
+                            if serial::is_newer(*new, *old) {
+                                *soa = update.clone();
+                                (changed, serial_set) = (true, true);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                // A name has one CNAME; a new one replaces it.
+                if update.qtype == DnsType::Cname {
+                    records.retain(|r| {
+                        !(at_name(r) && r.qtype == DnsType::Cname && r.rdata != update.rdata)
+                    });
+                }
+                let existing = records
+                    .iter_mut()
+                    .find(|r| at_name(r) && r.qtype == update.qtype && r.rdata == update.rdata);
+                match existing {
+                    Some(existing) if existing.ttl == update.ttl => {}
+                    Some(existing) => {
+                        existing.ttl = update.ttl;
+                        changed = true;
+                    }
+                    None => {
+                        records.push(update.clone());
+                        changed = true;
+                    }
+                }
+            }
+            DnsClass::Any => records.retain(|r| {
+                let kept_at_apex = at_apex && matches!(r.qtype, DnsType::Soa | DnsType::Ns);
+                !at_name(r)
+                    || kept_at_apex
+                    || !(update.qtype == DnsType::Any || update.qtype == r.qtype)
+            }),
+            _ => {
+                let apex_ns = records
+                    .iter()
+                    .filter(|r| r.qtype == DnsType::Ns && r.name.eq_ignore_case(&zone.apex))
+                    .count();
+                let last_ns = at_apex && update.qtype == DnsType::Ns && apex_ns == 1;
+                if update.qtype != DnsType::Soa && !last_ns {
+                    records.retain(|r| {
+                        !(at_name(r) && r.qtype == update.qtype && r.rdata == update.rdata)
+                    });
+                }
+            }
+        }
+        changed |= records.len() != before;
+    }
+    if !changed {
+        return None;
+    }
+    if !serial_set {
+        for record in &mut records {
+            if let RData::Soa { serial, .. } = &mut record.rdata {
+                *serial = serial::increment(*serial);
+            }
+        }
+    }
+    Some(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    fn session() -> Session {
+        Session {
+            server: None,
+            key: None,
+            sig0: None,
+            update: None,
+        }
+    }
+
+    #[test]
+    fn test_update_message() {
+        let mut session = session();
+        for line in [
+            "; ACME DNS-01 challenge",
+            "zone example.com",
+            "prereq nxrrset _acme-challenge.example.com TXT",
+            "update delete old.example.com",
+            "update delete www.example.com A",
+            "update delete www.example.com AAAA 2001:db8::1",
+            "update add _acme-challenge.example.com 60 TXT \"token\"",
+        ] {
+            session.execute(line).unwrap();
+        }
+
+        let bytes = session.update.unwrap().to_packet(7).to_bytes();
+        let packet = DnsPacket::try_from(&bytes).unwrap();
+        assert_eq!(packet.header.opcode, OpCode::Update);
+        assert_eq!(packet.questions[0].qname.as_str(), "example.com");
+        assert_eq!(packet.questions[0].qtype, DnsType::Soa);
+
+        let records = |section: &[DnsAnswer]| -> Vec<String> {
+            section.iter().map(|r| r.to_string()).collect()
+        };
+        assert_eq!(
+            records(&packet.answers),
+            vec!["_acme-challenge.example.com.\t0\tNONE\tTXT\t\\# 0"]
+        );
+        assert_eq!(
+            records(&packet.authorities),
+            vec![
+                "old.example.com.\t0\tANY\tANY\t\\# 0",
+                "www.example.com.\t0\tANY\tA\t\\# 0",
+                "www.example.com.\t0\tNONE\tAAAA\t2001:db8::1",
+                "_acme-challenge.example.com.\t60\tIN\tTXT\t\"token\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        let mut session = session();
+        assert!(session
+            .execute("update add www.example.com 60 A 192.0.2.1")
+            .is_err());
+        session.execute("zone example.com").unwrap();
+        assert!(session
+            .execute("update add www.example.com A 192.0.2.1")
+            .is_err());
+        assert!(session.execute("prereq maybe www.example.com").is_err());
+        assert!(session.execute("frobnicate").is_err());
+        assert!(session.execute("key name not-base64!").is_err());
+    }
+
+    #[test]
+    fn test_signed_update_round_trip() {
+        let secret = b"0123456789abcdef".to_vec();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let server_key = TsigKey::new("ddns-key".into(), secret.clone());
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            let tsig = server_key
+                .verify(&buf[..len], None, crate::tsig::now())
+                .unwrap();
+            let mut response = DnsPacket::try_from(&buf[..len]).unwrap();
+            response.header.flip_qr();
+            response.answers.clear();
+            response.authorities.clear();
+            response.additionals.clear();
+            let (signed, _) =
+                server_key.sign(&response.to_bytes(), Some(&tsig.mac), tsig.time_signed);
+            server.send_to(&signed, peer).unwrap();
+        });
+
+        let mut session = session();
+        session.server = Some(addr);
+        session.key = Some(TsigKey::new("ddns-key".into(), secret));
+        session.execute("zone example.com").unwrap();
+        session
+            .execute("update add host.example.com 300 A 192.0.2.7")
+            .unwrap();
+        assert_eq!(session.execute("send"), Ok(()));
+        handle.join().unwrap();
+        assert!(session.update.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sig0_signed_update() {
+        let path = key_file();
+        let key = Sig0Key::load(&path).unwrap();
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let public = key.key.clone();
+        let handle = thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            let signer = sig0::verify(&buf[..len], |_| vec![public], tsig::now()).unwrap();
+            let mut response = DnsPacket::try_from(&buf[..len]).unwrap();
+            response.header.flip_qr();
+            response.answers.clear();
+            response.authorities.clear();
+            response.additionals.clear();
+            server.send_to(&response.to_bytes(), peer).unwrap();
+            signer
+        });
+
+        let mut session = session();
+        session.server = Some(addr);
+        session.sig0 = Some(key);
+        session.execute("zone example.com").unwrap();
+        session
+            .execute("update add host.example.com 300 A 192.0.2.7")
+            .unwrap();
+        assert_eq!(session.execute("send"), Ok(()));
+        assert_eq!(handle.join().unwrap().as_str(), "host.example.com");
+    }
+
+    const ZONE: &str = "$ORIGIN example.com.\n$TTL 300\n\
+                        @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+                        @ NS ns1\nns1 A 192.0.2.1\n\
+                        www A 192.0.2.80\nwww A 192.0.2.81\nalias CNAME www\n";
+
+    // The update the commands make.
+    fn update(commands: &[&str]) -> Update {
+        let mut session = session();
+        session.execute("zone example.com").unwrap();
+        for command in commands {
+            session.execute(command).unwrap();
+        }
+        session.update.unwrap()
+    }
+
+    fn records(zone: &Zone, name: &str, qtype: DnsType) -> Vec<String> {
+        let records = zone.records_at(&name.into(), qtype);
+        records.iter().map(|r| r.rdata.to_string()).collect()
+    }
+
+    #[test]
+    fn test_prerequisites() {
+        let zone = Zone::parse(ZONE, None).unwrap();
+        let check = |command: &str| {
+            let update = update(&[command]);
+            check_prerequisites(&zone, &update.prerequisites)
+        };
+        assert_eq!(check("prereq yxdomain www.example.com"), Ok(()));
+        assert_eq!(
+            check("prereq yxdomain new.example.com"),
+            Err(ResponseCode::NxDomain)
+        );
+        assert_eq!(check("prereq nxdomain new.example.com"), Ok(()));
+        assert_eq!(
+            check("prereq nxdomain www.example.com"),
+            Err(ResponseCode::YxDomain)
+        );
+        assert_eq!(check("prereq yxrrset www.example.com A"), Ok(()));
+        assert_eq!(
+            check("prereq yxrrset www.example.com MX"),
+            Err(ResponseCode::NxRrset)
+        );
+        assert_eq!(check("prereq nxrrset www.example.com MX"), Ok(()));
+        assert_eq!(
+            check("prereq nxrrset www.example.com A"),
+            Err(ResponseCode::YxRrset)
+        );
+        assert_eq!(
+            check("prereq yxdomain www.example.org"),
+            Err(ResponseCode::NotZone)
+        );
+
+        // An RRset given by value has to match it exactly.
+        let www = |addrs: &[&str]| {
+            let commands: Vec<String> = addrs
+                .iter()
+                .map(|addr| format!("prereq yxrrset www.example.com A {}", addr))
+                .collect();
+            let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+            check_prerequisites(&zone, &update(&commands).prerequisites)
+        };
+        assert_eq!(www(&["192.0.2.81", "192.0.2.80"]), Ok(()));
+        assert_eq!(www(&["192.0.2.80"]), Err(ResponseCode::NxRrset));
+        assert_eq!(
+            www(&["192.0.2.80", "192.0.2.81", "192.0.2.82"]),
+            Err(ResponseCode::NxRrset)
+        );
+    }
+
+    #[test]
+    fn test_updates() {
+        let zone = Zone::parse(ZONE, None).unwrap();
+        let apply = |commands: &[&str]| {
+            let update = update(commands);
+            prescan(&zone, &update.updates)?;
+            let records = updated(&zone, &update.updates);
+            Ok::<_, ResponseCode>(
+                records.map(|records| Zone::from_records(zone.apex.clone(), records)),
+            )
+        };
+
+        let changed = apply(&[
+            "update add _acme-challenge.example.com 60 TXT \"token\"",
+            "update delete www.example.com A 192.0.2.81",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            records(&changed, "_acme-challenge.example.com", DnsType::Txt),
+            vec!["\"token\""]
+        );
+        assert_eq!(
+            records(&changed, "www.example.com", DnsType::A),
+            vec!["192.0.2.80"]
+        );
+        assert_eq!(changed.serial(), Some(2));
+
+        // Deleting a name spares the apex SOA and NS; the last NS stays
+        // even when deleted by value.
+        let changed = apply(&[
+            "update delete example.com",
+            "update delete example.com NS ns1.example.com.",
+            "update delete www.example.com",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(records(&changed, "example.com", DnsType::Ns).len(), 1);
+        assert!(!changed.in_use(&"www.example.com".into()));
+
+        // A CNAME and other data don't mix, and nothing else changes.
+        assert!(apply(&["update add alias.example.com 60 A 192.0.2.1"])
+            .unwrap()
+            .is_none());
+        assert!(apply(&["update add www.example.com 60 CNAME ns1"])
+            .unwrap()
+            .is_none());
+        assert!(apply(&["update add www.example.com 300 A 192.0.2.80"])
+            .unwrap()
+            .is_none());
+
+        let changed = apply(&["update add alias.example.com 60 CNAME ns1.example.com."])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            records(&changed, "alias.example.com", DnsType::Cname),
+            vec!["ns1.example.com."]
+        );
+
+        // A newer SOA replaces the serial rather than adding to it.
+        let changed = apply(&[
+            "update add example.com 300 SOA ns1 hostmaster 10 7200 900 1209600 300",
+            "update add new.example.com 60 A 192.0.2.9",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(changed.serial(), Some(10));
+
+        assert_eq!(
+            apply(&["update add www.example.org 60 A 192.0.2.1"]).err(),
+            Some(ResponseCode::NotZone)
+        );
+        assert_eq!(
+            apply(&["update add www.example.com 60 AXFR \\# 0"]).err(),
+            Some(ResponseCode::FormatError)
+        );
+    }
+
+    #[test]
+    fn test_update_rules() {
+        for bad in [
+            "",
+            "example.com,view=",
+            "example.com,key=ddns",
+            "example.com,who=me",
+            "example.com,sig0=",
+        ] {
+            assert!(Rule::parse(bad).is_err(), "{:?}", bad);
+        }
+        let mut updates = Updates::default();
+        updates.add("example.com,key=ddns-key:c2VjcmV0").unwrap();
+        updates.add("example.org,view=internal").unwrap();
+        updates.add("example.net,sig0=host.example.net").unwrap();
+        let (zone, key) = (Name::from("EXAMPLE.com"), Name::from("ddns-key"));
+        assert!(updates.allow(&zone, None, Some(&key), None));
+        assert!(!updates.allow(&zone, None, None, None));
+        assert!(!updates.allow(&zone, None, Some(&"other".into()), None));
+        // A SIG(0) key of the same name isn't the TSIG key.
+        assert!(!updates.allow(&zone, None, None, Some(&key)));
+        assert!(updates.allow(&"example.org".into(), Some("internal"), None, None));
+        assert!(!updates.allow(&"example.org".into(), Some("external"), None, None));
+        let (net, host) = (Name::from("example.net"), Name::from("Host.example.net"));
+        assert!(updates.allow(&net, None, None, Some(&host)));
+        assert!(!updates.allow(&net, None, Some(&host), None));
+        assert!(!updates.allow(&net, None, None, None));
+        assert!(updates.key(&"DDNS-key".into()).is_some());
+    }
+
+    fn key_file() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("dns-server-update-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Khost.example.com.+008+00001.private");
+        std::fs::write(&path, crate::sig0::test::private_key_file()).unwrap();
+        path
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::{DnsClass, DnsType, Name};
+use crate::config::Config;
+use crate::handler::{self, State};
+use crate::packet::DnsPacket;
+use crate::question::DnsQuestion;
+use crate::stats::Stats;
+
+// Lookups running at once while warming.
+const WORKERS: usize = 8;
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+// `--warm path[,save=N]`: before reporting ready, resolve every name listed
+// in `path` (`name [type]` per line, A and AAAA when no type is given) so
+// that a node joining an anycast pool starts with a warm cache. With
+// `save=N` the N most queried names are written back to `path` every
+// minute, so the next start warms with what this run was asked.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct WarmConfig {
+    pub(crate) path: PathBuf,
+    pub(crate) save: Option<usize>,
+}
+
+impl WarmConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let path = parts.next().filter(|path| !path.is_empty());
+        let mut config = WarmConfig {
+            path: PathBuf::from(path.ok_or("expected a file of names")?),
+            save: None,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("save", count)) => {
+                    let count = count
+                        .parse()
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or_else(|| format!("bad save count {:?}", count))?;
+                    config.save = Some(count);
+                }
+                _ => return Err(format!("unknown warm option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// Progress of the warm-up, for readiness.
+#[derive(Default)]
+pub(crate) struct Progress {
+    pub(crate) total: AtomicUsize,
+    pub(crate) done: AtomicUsize,
+}
+
+impl Progress {
+    pub(crate) fn warming(&self) -> bool {
+        self.done.load(Ordering::SeqCst) < self.total.load(Ordering::SeqCst)
+    }
+}
+
+// The questions in a warm file. A missing file is an empty list, as on the
+// first run with `save`.
+pub(crate) fn load(path: &Path) -> Result<Vec<DnsQuestion>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let mut questions = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let (name, qtypes) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [] => continue,
+            [name] => (name, vec![DnsType::A, DnsType::Aaaa]),
+            [name, qtype] => {
+                let qtype = qtype.parse().map_err(|_| {
+                    format!("{} line {}: bad type {}", path.display(), number + 1, qtype)
+                })?;
+                (name, vec![qtype])
+            }
+            _ => {
+                return Err(format!(
+                    "{} line {}: expected name [type]",
+                    path.display(),
+                    number + 1
+                ))
+            }
+        };
+        for qtype in qtypes {
+            questions.push(DnsQuestion {
+                qname: Name::from(name),
+                qtype,
+                qclass: DnsClass::In,
+            });
+        }
+    }
+    Ok(questions)
+}
+
+// Starts warming in the background. Readiness counts it from here on.
+pub(crate) fn start(questions: Vec<DnsQuestion>, config: Arc<Config>, state: Arc<State>) {
+    state.warm.total.store(questions.len(), Ordering::SeqCst);
+    thread::spawn(move || {
+        let start = Instant::now();
+        let count = questions.len();
+        warm(questions, &config, &state);
+        eprintln!(
+            "Warmed the cache with {} lookups in {:?}",
+            count,
+            start.elapsed()
+        );
+    });
+}
+
+// Resolves the questions as a local client would, which fills the cache.
+fn warm(questions: Vec<DnsQuestion>, config: &Config, state: &State) {
+    let progress = &state.warm;
+    let queue = Mutex::new(questions.into_iter());
+    let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    thread::scope(|scope| {
+        for _ in 0..WORKERS {
+            scope.spawn(|| loop {
+                let Some(question) = queue.lock().unwrap().next() else {
+                    return;
+                };
+                handler::handle(DnsPacket::query(0, question), client, None, config, state);
+                progress.done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+// Writes the most queried names to the warm file every minute.
+pub(crate) fn persist(warm: WarmConfig, count: usize, stats: Arc<Mutex<Stats>>) {
+    thread::spawn(move || loop {
+        thread::sleep(SAVE_INTERVAL);
+        let names = stats.lock().unwrap().top_names(count);
+        if names.is_empty() {
+            continue;
+        }
+        if let Err(e) = save(&warm.path, &names) {
+            eprintln!("Failed to save {}: {}", warm.path.display(), e);
+        }
+    });
+}
+
+// Replaces the file in one rename so a crash never leaves half of it.
+fn save(path: &Path, names: &[(String, u64)]) -> io::Result<()> {
+    let mut text = String::from("# most queried names, written by --warm save=N\n");
+    for (name, _) in names {
+        text.push_str(name);
+        text.push('\n');
+    }
+    let partial = path.with_extension("partial");
+    fs::write(&partial, text)?;
+    fs::rename(&partial, path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::{DnsAnswer, RData};
+    use crate::authority::Zones;
+    use crate::forward::UpstreamGroup;
+    use std::net::UdpSocket;
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dns-server-warm-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            WarmConfig::parse("/var/lib/dns/warm,save=500").unwrap(),
+            WarmConfig {
+                path: "/var/lib/dns/warm".into(),
+                save: Some(500)
+            }
+        );
+        for bad in ["", "warm,save=0", "warm,top=5"] {
+            assert!(WarmConfig::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_load_and_save() {
+        let path = temp("load");
+        fs::write(
+            &path,
+            "# popular\nexample.com\n\nwww.example.com MX # mail\n",
+        )
+        .unwrap();
+        let questions = load(&path).unwrap();
+        let types: Vec<DnsType> = questions.iter().map(|q| q.qtype).collect();
+        assert_eq!(types, vec![DnsType::A, DnsType::Aaaa, DnsType::Mx]);
+        assert_eq!(questions[2].qname.as_str(), "www.example.com");
+
+        save(&path, &[("a.example".into(), 9), ("b.example".into(), 3)]).unwrap();
+        assert_eq!(load(&path).unwrap().len(), 4);
+        fs::write(&path, "example.com BOGUS\n").unwrap();
+        assert!(load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(load(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_warm() {
+        // Answers every query with an A record.
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let spec = format!("wan={}", upstream.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((size, source)) = upstream.recv_from(&mut buf) {
+                let mut response = DnsPacket::try_from(&buf[..size]).unwrap();
+                response.header.flip_qr();
+                let qname = response.questions[0].qname.clone();
+                response.add_answer(DnsAnswer::new(
+                    qname,
+                    DnsType::A,
+                    DnsClass::In,
+                    300,
+                    RData::A([192, 0, 2, 1]),
+                ));
+                upstream.send_to(&response.to_bytes(), source).unwrap();
+            }
+        });
+        let config = Config {
+            upstreams: vec![UpstreamGroup::parse(&spec).unwrap()],
+            forward_zones: vec![(Name::from("."), "wan".into())],
+            ..Config::default()
+        };
+        let state = State::new(Zones::default());
+        let questions: Vec<DnsQuestion> = (0..20)
+            .map(|i| DnsQuestion {
+                qname: Name::from(format!("host{}.example", i).as_str()),
+                qtype: DnsType::A,
+                qclass: DnsClass::In,
+            })
+            .collect();
+        state.warm.total.store(questions.len(), Ordering::SeqCst);
+        assert!(state.warm.warming());
+        warm(questions, &config, &state);
+        assert!(!state.warm.warming());
+        assert_eq!(state.cache.len(), 20);
+    }
+}
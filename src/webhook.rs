@@ -0,0 +1,251 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::answer::DnsAnswer;
+use crate::common::Name;
+use crate::error::HttpError;
+use crate::header::ResponseCode;
+use crate::http::{self, Url};
+use crate::json::Json;
+use crate::question::DnsQuestion;
+use crate::zone::ZoneFile;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_TTL: u64 = 60;
+
+// `--webhook url[,zone=name][,timeout=ms]`: ask an HTTP service for the
+// records of names at or below `zone` (every name by default). Each lookup
+// POSTs
+//   {"qname": "www.example.com", "qtype": "A", "client": "192.0.2.1"}
+// and the service replies with
+//   {"rcode": "NOERROR", "answers": [
+//       {"name": "www.example.com", "type": "A", "ttl": 60, "data": "192.0.2.80"}]}
+// where `data` is in master file format with fully qualified names, and
+// `rcode`, `name` and `ttl` may be left out. `null`, or a reply with
+// neither answers nor rcode, leaves the query to the rest of the chain.
+#[derive(PartialEq, Debug, Clone)]
+pub(crate) struct WebhookConfig {
+    pub(crate) url: Url,
+    pub(crate) zone: Name,
+    pub(crate) timeout: Duration,
+}
+
+impl WebhookConfig {
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let mut parts = spec.split(',');
+        let url = Url::parse(parts.next().unwrap_or_default()).map_err(|e| e.to_string())?;
+        let mut config = WebhookConfig {
+            url,
+            zone: Name::from("."),
+            timeout: DEFAULT_TIMEOUT,
+        };
+        for part in parts {
+            match part.split_once('=') {
+                Some(("zone", zone)) if !zone.is_empty() => config.zone = zone.into(),
+                Some(("timeout", ms)) => {
+                    let ms: u64 = ms
+                        .parse()
+                        .ok()
+                        .filter(|ms| *ms > 0)
+                        .ok_or_else(|| format!("bad timeout {:?}", ms))?;
+                    config.timeout = Duration::from_millis(ms);
+                }
+                _ => return Err(format!("unknown webhook option {:?}", part)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+// What the service said about a name.
+pub(crate) struct Reply {
+    pub(crate) rcode: ResponseCode,
+    pub(crate) answers: Vec<DnsAnswer>,
+}
+
+// Asks the service about a question; None when it has nothing to say.
+pub(crate) fn lookup(
+    config: &WebhookConfig,
+    question: &DnsQuestion,
+    client: IpAddr,
+) -> Result<Option<Reply>, HttpError> {
+    let request = Json::Object(vec![
+        ("qname".into(), question.qname.as_str().into()),
+        ("qtype".into(), question.qtype.to_string().as_str().into()),
+        ("client".into(), client.to_string().as_str().into()),
+    ]);
+    let reply = http::post_json(&config.url, &request, config.timeout)?;
+    if reply == Json::Null {
+        return Ok(None);
+    }
+    let rcode = match reply.get("rcode").map(|rcode| rcode.as_str()) {
+        None => None,
+        Some(Some(rcode)) => {
+            Some(parse_rcode(rcode).ok_or_else(|| bad(format!("rcode {:?}", rcode)))?)
+        }
+        Some(None) => return Err(bad("rcode is not a string".into())),
+    };
+    let answers = reply
+        .get("answers")
+        .map(Json::items)
+        .unwrap_or_default()
+        .iter()
+        .map(|answer| record(answer, &question.qname))
+        .collect::<Result<Vec<_>, _>>()?;
+    if rcode.is_none() && answers.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Reply {
+        rcode: rcode.unwrap_or(ResponseCode::NoError),
+        answers,
+    }))
+}
+
+fn bad(message: String) -> HttpError {
+    HttpError::Json(format!("webhook reply has bad {}", message))
+}
+
+fn parse_rcode(rcode: &str) -> Option<ResponseCode> {
+    match rcode.to_ascii_uppercase().as_str() {
+        "NOERROR" => Some(ResponseCode::NoError),
+        "NXDOMAIN" => Some(ResponseCode::NxDomain),
+        "SERVFAIL" => Some(ResponseCode::ServFail),
+        "REFUSED" => Some(ResponseCode::Refused),
+        _ => None,
+    }
+}
+
+// One answer, read as a master file line so that every type the zone
+// parser knows is supported.
+fn record(answer: &Json, qname: &Name) -> Result<DnsAnswer, HttpError> {
+    let field = |key| answer.get(key).and_then(Json::as_str);
+    let name = field("name").unwrap_or(qname.as_str());
+    let qtype = field("type").ok_or_else(|| bad("answer without a type".into()))?;
+    let data = field("data").ok_or_else(|| bad("answer without data".into()))?;
+    let ttl = match answer.get("ttl") {
+        Some(ttl) => ttl.as_u64().ok_or_else(|| bad("ttl".into()))?,
+        None => DEFAULT_TTL,
+    };
+    if [name, qtype, data].iter().any(|text| text.contains('\n')) {
+        return Err(bad(format!("answer for {}", name)));
+    }
+    let line = format!(
+        "{}. {} IN {} {}",
+        name.trim_end_matches('.'),
+        ttl,
+        qtype,
+        data
+    );
+    let mut zone = ZoneFile::parse(&line, Some(Name::from(".")));
+    match (zone.errors.first(), zone.entries.pop()) {
+        (None, Some(entry)) if zone.entries.is_empty() => Ok(entry.record),
+        (Some(error), _) => Err(bad(format!("answer {:?}: {}", line, error.message))),
+        _ => Err(bad(format!("answer {:?}", line))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::answer::RData;
+    use crate::common::{DnsClass, DnsType};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    // Answers www with an A and a CNAME, gone with NXDOMAIN and anything
+    // else with null, after checking the request.
+    fn fake_webhook() -> WebhookConfig {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/lookup", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let request = Json::parse(std::str::from_utf8(&body).unwrap()).unwrap();
+                assert_eq!(request.get("client"), Some(&"192.0.2.1".into()));
+                let reply = match request.get("qname").and_then(Json::as_str) {
+                    Some("www.example.com") => {
+                        r#"{"answers": [
+                            {"type": "CNAME", "ttl": 30, "data": "web.example.com"},
+                            {"name": "web.example.com", "type": "A", "data": "192.0.2.80"}]}"#
+                    }
+                    Some("gone.example.com") => r#"{"rcode": "NXDOMAIN"}"#,
+                    Some("bad.example.com") => r#"{"answers": [{"type": "A", "data": "x"}]}"#,
+                    _ => "null",
+                };
+                write!(
+                    &stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+            }
+        });
+        WebhookConfig::parse(&url).unwrap()
+    }
+
+    fn ask(config: &WebhookConfig, name: &str) -> Result<Option<Reply>, HttpError> {
+        let question = DnsQuestion {
+            qname: name.into(),
+            qtype: DnsType::A,
+            qclass: DnsClass::In,
+        };
+        lookup(config, &question, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+    }
+
+    #[test]
+    fn test_parse() {
+        let config =
+            WebhookConfig::parse("http://127.0.0.1:9000,zone=dyn.example,timeout=500").unwrap();
+        assert_eq!(config.zone.as_str(), "dyn.example");
+        assert_eq!(config.timeout, Duration::from_millis(500));
+        assert_eq!(
+            WebhookConfig::parse("http://127.0.0.1:9000")
+                .unwrap()
+                .zone
+                .as_str(),
+            ""
+        );
+        assert!(WebhookConfig::parse("http://127.0.0.1:9000,timeout=0").is_err());
+        assert!(WebhookConfig::parse("http://127.0.0.1:9000,ttl=5").is_err());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let config = fake_webhook();
+        let reply = ask(&config, "www.example.com").unwrap().unwrap();
+        assert_eq!(reply.rcode, ResponseCode::NoError);
+        assert_eq!(reply.answers.len(), 2);
+        assert_eq!(reply.answers[0].name.as_str(), "www.example.com");
+        assert_eq!(reply.answers[0].ttl, 30);
+        assert_eq!(
+            reply.answers[0].rdata,
+            RData::Cname("web.example.com".into())
+        );
+        assert_eq!(reply.answers[1].ttl, DEFAULT_TTL as i32);
+        assert_eq!(reply.answers[1].rdata, RData::A([192, 0, 2, 80]));
+
+        let reply = ask(&config, "gone.example.com").unwrap().unwrap();
+        assert_eq!(reply.rcode, ResponseCode::NxDomain);
+        assert!(ask(&config, "other.example.com").unwrap().is_none());
+        assert!(matches!(
+            ask(&config, "bad.example.com"),
+            Err(HttpError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,1255 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+use crate::answer::{DnsAnswer, RData};
+use crate::common::{DnsClass, DnsType, Name};
+use crate::crypto::base64_decode;
+use crate::dnssec::Dnskey;
+
+const MAX_TTL: u32 = i32::MAX as u32; // RFC 2181 section 8
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Records one $GENERATE may produce, enough for a /16 reverse zone.
+const MAX_GENERATED: u64 = 65536;
+// Text this long or longer is split into pieces parsed on their own
+// threads.
+const PARALLEL_PARSE_BYTES: usize = 1 << 20;
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct ZoneError {
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+pub(crate) struct ZoneEntry {
+    pub(crate) line: usize,
+    pub(crate) record: DnsAnswer,
+}
+
+// A master file (RFC 1035 section 5) parsed into records. Parsing carries on
+// past bad lines so that every problem in the file can be reported at once.
+pub(crate) struct ZoneFile {
+    pub(crate) origin: Option<Name>,
+    pub(crate) entries: Vec<ZoneEntry>,
+    pub(crate) errors: Vec<ZoneError>,
+}
+
+struct Token {
+    text: String,
+    quoted: bool,
+    // Where it starts: the line number, and the byte offset into the line.
+    line: usize,
+    column: usize,
+}
+
+// One logical record: a line, or several joined by parentheses.
+struct Line {
+    number: usize,
+    // The last line it takes, past the parentheses.
+    end: usize,
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+#[derive(Clone)]
+struct State {
+    origin: Option<Name>,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<Name>,
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl ZoneFile {
+    // Parses text on its own: `$INCLUDE` is refused, since there is no file
+    // to resolve paths against and the text may come from a client.
+    pub(crate) fn parse(text: &str, origin: Option<Name>) -> ZoneFile {
+        let mut parser = Parser::new(origin, None);
+        parser.parse(text);
+        parser.finish()
+    }
+
+    // Reads a master file, following `$INCLUDE`s relative to the file that
+    // has them. Records and problems from an included file are reported at
+    // the `$INCLUDE` line, with problems naming the file and its own line.
+    pub(crate) fn read(path: &Path, origin: Option<Name>) -> Result<ZoneFile, String> {
+        let error = |e: io::Error| format!("{}: {}", path.display(), e);
+        let path = fs::canonicalize(path).map_err(error)?;
+        let text = fs::read_to_string(&path).map_err(error)?;
+        Ok(ZoneFile::parse_as(&text, path, origin))
+    }
+
+    // Parses text as though it were the file at `path`, for checking an
+    // edit before it is written.
+    pub(crate) fn parse_as(text: &str, path: PathBuf, origin: Option<Name>) -> ZoneFile {
+        let mut parser = Parser::new(origin, Some(vec![path]));
+        parser.parse(text);
+        parser.finish()
+    }
+}
+
+// Edits a master file so it holds none of `removed` and all of `added`,
+// leaving everything else as written: comments, blank lines, the order of
+// records and the directives. A removed record's lines go; one coming
+// back with another TTL, or an SOA with other fields, is rewritten where
+// it stands, only its serial if that is all that changed; the rest of
+// `added` goes at the end. Records from `$INCLUDE` and `$GENERATE` aren't
+// the file's own and can't be removed.
+pub(crate) fn edit(
+    text: &str,
+    origin: Option<Name>,
+    removed: &[&DnsAnswer],
+    added: &[&DnsAnswer],
+) -> Result<String, String> {
+    let (lines, errors) = tokenize(text, 1);
+    if let Some(error) = errors.first() {
+        return Err(error.to_string());
+    }
+    let mut state = State {
+        origin,
+        default_ttl: None,
+        last_ttl: None,
+        last_owner: None,
+    };
+    let mut records = Vec::new();
+    for line in &lines {
+        let directive = match line.tokens.first() {
+            Some(token) if !line.indented => token.text.to_ascii_uppercase(),
+            _ => String::new(),
+        };
+        if directive == "$INCLUDE" || directive == "$GENERATE" {
+            continue;
+        }
+        let parsed = parse_line(&mut state, line);
+        if let Some(record) = parsed.map_err(|e| format!("line {}: {}", line.number, e))? {
+            records.push((line, record));
+        }
+    }
+
+    // Each line of the text, or None once removed.
+    let mut edited: Vec<Option<String>> = text.lines().map(|l| Some(l.to_string())).collect();
+    let mut matched = vec![false; records.len()];
+    let mut gone = vec![false; records.len()];
+    let mut placed = vec![false; added.len()];
+    for &record in removed {
+        let i = (0..records.len())
+            .find(|&i| !matched[i] && records[i].1 == *record)
+            .ok_or_else(|| format!("no line of the file holds {}", record))?;
+        matched[i] = true;
+        let line = records[i].0;
+        let replacement = (0..added.len()).find(|&j| {
+            let new = added[j];
+            !placed[j]
+                && new.name.eq_ignore_case(&record.name)
+                && new.qtype == record.qtype
+                && (new.qtype == DnsType::Soa || new.rdata == record.rdata)
+        });
+        if let Some(j) = replacement {
+            placed[j] = true;
+        }
+        match replacement.map(|j| (j, new_serial(line, record, added[j]))) {
+            Some((_, Some((token, serial)))) => {
+                let text = edited[token.line - 1].as_mut().unwrap();
+                let end = token.column + token.text.len();
+                text.replace_range(token.column..end, &serial.to_string());
+            }
+            Some((j, None)) => {
+                edited[line.number - 1..line.end].fill(None);
+                edited[line.number - 1] = Some(added[j].to_string());
+            }
+            None => {
+                edited[line.number - 1..line.end].fill(None);
+                gone[i] = true;
+            }
+        }
+    }
+
+    // A record without an owner takes the one before it, which may be gone.
+    let mut owner_gone = false;
+    for (i, (line, record)) in records.iter().enumerate() {
+        if !line.indented {
+            owner_gone = gone[i];
+        } else if owner_gone && !gone[i] {
+            let text = edited[line.number - 1].as_mut().unwrap();
+            text.insert_str(0, &format!("{}.", record.name));
+            owner_gone = false;
+        }
+    }
+
+    let mut text: String = edited.into_iter().flatten().map(|l| l + "\n").collect();
+    for (&record, placed) in added.iter().zip(placed) {
+        if !placed {
+            text += &format!("{}\n", record);
+        }
+    }
+    Ok(text)
+}
+
+// The token holding an SOA's serial and what it becomes, if nothing else
+// about the SOA changed.
+fn new_serial<'a>(line: &'a Line, old: &DnsAnswer, new: &DnsAnswer) -> Option<(&'a Token, u32)> {
+    let (RData::Soa { serial: from, .. }, RData::Soa { serial: to, .. }) = (&old.rdata, &new.rdata)
+    else {
+        return None;
+    };
+    let mut unchanged = new.clone();
+    if let RData::Soa { serial, .. } = &mut unchanged.rdata {
+        *serial = *from;
+    }
+    // The serial is the third of seven fields, which end the record.
+    let token = line.tokens.len().checked_sub(5).map(|i| &line.tokens[i])?;
+    let same = unchanged == *old && !token.quoted && token.text == from.to_string();
+    same.then_some((token, *to))
+}
+
+struct Parser {
+    state: State,
+    first_origin: Option<Name>,
+    entries: Vec<ZoneEntry>,
+    errors: Vec<ZoneError>,
+    // The files being read, the innermost last, or None when parsing text
+    // that may not include files.
+    files: Option<Vec<PathBuf>>,
+}
+
+// Part of a text to parse, from a byte offset and line number on, and the
+// state at its start.
+struct Piece {
+    start: usize,
+    line: usize,
+    state: State,
+}
+
+impl Parser {
+    fn new(origin: Option<Name>, files: Option<Vec<PathBuf>>) -> Parser {
+        Parser {
+            first_origin: origin.clone(),
+            state: State {
+                origin,
+                default_ttl: None,
+                last_ttl: None,
+                last_owner: None,
+            },
+            entries: Vec::new(),
+            errors: Vec::new(),
+            files,
+        }
+    }
+
+    // Large texts are parsed a piece per thread, the results put back
+    // together in order, so a single big zone loads as fast as many small
+    // ones.
+    fn parse(&mut self, text: &str) {
+        let threads = thread::available_parallelism().map_or(1, usize::from);
+        self.parse_on(text, threads);
+    }
+
+    fn parse_on(&mut self, text: &str, threads: usize) {
+        let pieces = self.pieces(text, threads);
+        if pieces.len() < 2 {
+            self.parse_lines(text, 1);
+            return;
+        }
+        let files = &self.files;
+        let parsed: Vec<Parser> = thread::scope(|scope| {
+            let parsing: Vec<_> = pieces
+                .iter()
+                .enumerate()
+                .map(|(i, piece)| {
+                    let end = pieces.get(i + 1).map_or(text.len(), |next| next.start);
+                    let text = &text[piece.start..end];
+                    scope.spawn(move || {
+                        let mut parser = Parser {
+                            state: piece.state.clone(),
+                            first_origin: None,
+                            entries: Vec::new(),
+                            errors: Vec::new(),
+                            files: files.clone(),
+                        };
+                        parser.parse_lines(text, piece.line);
+                        parser
+                    })
+                })
+                .collect();
+            parsing
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        for parser in parsed {
+            if self.first_origin.is_none() {
+                self.first_origin = parser.first_origin;
+            }
+            self.entries.extend(parser.entries);
+            self.errors.extend(parser.errors);
+            self.state = parser.state;
+        }
+    }
+
+    // Where the text can be split: at a record with an owner of its own,
+    // outside parentheses, so the only state it needs from the lines before
+    // is what `$ORIGIN` and `$TTL` set, and only once `$TTL` has been given,
+    // since records without a TTL otherwise take the last one given. Text
+    // with an `$INCLUDE` isn't split, since the included file may leave any
+    // state behind.
+    fn pieces(&self, text: &str, threads: usize) -> Vec<Piece> {
+        let mut pieces = vec![Piece {
+            start: 0,
+            line: 1,
+            state: self.state.clone(),
+        }];
+        if threads < 2 || text.len() < PARALLEL_PARSE_BYTES {
+            return pieces;
+        }
+        let size = text.len() / threads;
+        let mut state = self.state.clone();
+        let (mut start, mut depth) = (0, 0);
+        for (i, raw) in text.split_inclusive('\n').enumerate() {
+            let owned = raw.starts_with(|c: char| !c.is_whitespace() && c != ';' && c != '(');
+            if depth == 0 && raw.starts_with('$') {
+                let (lines, _) = tokenize(raw, i + 1);
+                let directive = lines.first().map(|l| l.tokens[0].text.to_ascii_uppercase());
+                match directive.as_deref() {
+                    Some("$ORIGIN" | "$TTL") => {
+                        let _ = parse_line(&mut state, &lines[0]);
+                    }
+                    Some("$GENERATE") => {}
+                    // `$INCLUDE`, or a directive running on past the line.
+                    _ => {
+                        pieces.truncate(1);
+                        return pieces;
+                    }
+                }
+            } else if depth == 0
+                && owned
+                && state.default_ttl.is_some()
+                && start >= pieces[pieces.len() - 1].start + size
+            {
+                pieces.push(Piece {
+                    start,
+                    line: i + 1,
+                    state: state.clone(),
+                });
+            }
+            depth = paren_depth(raw, depth);
+            start += raw.len();
+        }
+        pieces
+    }
+
+    fn parse_lines(&mut self, text: &str, first: usize) {
+        let (lines, errors) = tokenize(text, first);
+        self.errors.extend(errors);
+        for line in lines {
+            let directive = match line.tokens.first() {
+                Some(token) if !line.indented => token.text.to_ascii_uppercase(),
+                _ => String::new(),
+            };
+            let result = match directive.as_str() {
+                "$INCLUDE" => self.include(&line),
+                "$GENERATE" => self.generate(&line),
+                _ => match parse_line(&mut self.state, &line) {
+                    Ok(Some(record)) => {
+                        self.entries.push(ZoneEntry {
+                            line: line.number,
+                            record,
+                        });
+                        Ok(())
+                    }
+                    Ok(None) => {
+                        if self.first_origin.is_none() {
+                            self.first_origin = self.state.origin.clone();
+                        }
+                        Ok(())
+                    }
+                    Err(message) => Err(message),
+                },
+            };
+            if let Err(message) = result {
+                self.errors.push(ZoneError {
+                    line: line.number,
+                    message,
+                });
+            }
+        }
+    }
+
+    fn finish(mut self) -> ZoneFile {
+        self.errors.sort_by_key(|e| e.line);
+        ZoneFile {
+            origin: self.first_origin,
+            entries: self.entries,
+            errors: self.errors,
+        }
+    }
+
+    // `$INCLUDE <file> [<origin>]` (RFC 1035 section 5.1). The included
+    // file starts at the given origin, or the current one, and whatever it
+    // sets doesn't outlast it.
+    fn include(&mut self, line: &Line) -> Result<(), String> {
+        let (file, origin) = match &line.tokens[1..] {
+            [file] => (&file.text, self.state.origin.clone()),
+            [file, origin] => {
+                let origin = resolve_name(&origin.text, self.state.origin.as_ref())?;
+                (&file.text, Some(origin))
+            }
+            [] => return Err("$INCLUDE needs a file name".to_string()),
+            [_, _, extra, ..] => return Err(format!("unexpected '{}' after $INCLUDE", extra.text)),
+        };
+        let files = self
+            .files
+            .as_mut()
+            .ok_or("$INCLUDE is only allowed in zone files")?;
+        if files.len() > MAX_INCLUDE_DEPTH {
+            return Err(format!("{}: $INCLUDEs nested too deeply", file));
+        }
+        let including = files.last().and_then(|path| path.parent());
+        let path = including.unwrap_or(Path::new("")).join(file);
+        let path = fs::canonicalize(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if files.contains(&path) {
+            return Err(format!("{}: $INCLUDE cycle", path.display()));
+        }
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        files.push(path);
+
+        let outer = std::mem::replace(&mut self.state.origin, origin);
+        let (entries, errors) = (self.entries.len(), self.errors.len());
+        self.parse(&text);
+        self.state.origin = outer;
+        let path = self.files.as_mut().and_then(Vec::pop).unwrap_or_default();
+        for entry in &mut self.entries[entries..] {
+            entry.line = line.number;
+        }
+        for error in &mut self.errors[errors..] {
+            error.message = format!("{} line {}: {}", path.display(), error.line, error.message);
+            error.line = line.number;
+        }
+        Ok(())
+    }
+
+    // BIND's `$GENERATE <start>-<stop>[/<step>] <owner> [<ttl>] [<class>]
+    // <type> <rdata>`: a record for each number in the range, with `$` in
+    // the owner and rdata replaced by it, as in
+    // `$GENERATE 1-254 $ PTR host-$.example.com.` for a reverse zone.
+    fn generate(&mut self, line: &Line) -> Result<(), String> {
+        let tokens = &line.tokens[1..];
+        if tokens.len() < 4 {
+            return Err("$GENERATE needs a range, an owner, a type and rdata".to_string());
+        }
+        let (start, stop, step) = parse_range(&tokens[0].text)?;
+        let (owner, rdata) = (&tokens[1], &tokens[tokens.len() - 1]);
+        let mut records = Vec::new();
+        for value in (start..=stop).step_by(step as usize) {
+            let mut generated = vec![Token {
+                text: substitute(&owner.text, value)?,
+                ..*owner
+            }];
+            generated.extend(tokens[2..tokens.len() - 1].iter().map(|token| Token {
+                text: token.text.clone(),
+                ..*token
+            }));
+            generated.push(Token {
+                text: substitute(&rdata.text, value)?,
+                ..*rdata
+            });
+            let generated = Line {
+                number: line.number,
+                end: line.end,
+                indented: false,
+                tokens: generated,
+            };
+            match parse_line(&mut self.state, &generated)? {
+                Some(record) => records.push(record),
+                None => return Err("$GENERATE cannot generate directives".to_string()),
+            }
+        }
+        self.entries
+            .extend(records.into_iter().map(|record| ZoneEntry {
+                line: line.number,
+                record,
+            }));
+        Ok(())
+    }
+}
+
+// The lines of the text, numbered from `first`.
+fn tokenize(text: &str, first: usize) -> (Vec<Line>, Vec<ZoneError>) {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut current: Option<Line> = None;
+    let mut depth = 0;
+
+    for (i, raw) in text.lines().enumerate() {
+        let number = first + i;
+        let line = current.get_or_insert_with(|| Line {
+            number,
+            end: number,
+            indented: raw.starts_with([' ', '\t']),
+            tokens: Vec::new(),
+        });
+
+        let mut chars = raw.char_indices();
+        let mut token: Option<Token> = None;
+        let mut in_quotes = false;
+        let start = |column| Token {
+            text: String::new(),
+            quoted: false,
+            line: number,
+            column,
+        };
+        while let Some((column, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    let text = &mut token.get_or_insert_with(|| start(column)).text;
+                    text.push(c);
+                    if let Some((_, escaped)) = chars.next() {
+                        text.push(escaped);
+                    }
+                }
+                '"' if in_quotes => {
+                    in_quotes = false;
+                    line.tokens.extend(token.take());
+                }
+                _ if in_quotes => token.as_mut().unwrap().text.push(c),
+                '"' => {
+                    line.tokens.extend(token.take());
+                    in_quotes = true;
+                    token = Some(Token {
+                        quoted: true,
+                        ..start(column)
+                    });
+                }
+                ';' => break,
+                '(' | ')' => {
+                    line.tokens.extend(token.take());
+                    if c == '(' {
+                        depth += 1;
+                    } else if depth == 0 {
+                        errors.push(ZoneError {
+                            line: number,
+                            message: "unbalanced ')'".to_string(),
+                        });
+                    } else {
+                        depth -= 1;
+                    }
+                }
+                ' ' | '\t' => line.tokens.extend(token.take()),
+                _ => token.get_or_insert_with(|| start(column)).text.push(c),
+            }
+        }
+        if in_quotes {
+            errors.push(ZoneError {
+                line: number,
+                message: "unterminated quoted string".to_string(),
+            });
+        }
+        line.tokens.extend(token.take());
+
+        line.end = number;
+        if depth == 0 {
+            let line = current.take().unwrap();
+            if !line.tokens.is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+
+    if let Some(line) = current {
+        errors.push(ZoneError {
+            line: line.number,
+            message: "unbalanced '('".to_string(),
+        });
+    }
+    (lines, errors)
+}
+
+// How deep in parentheses the line leaves a record that was `depth` deep,
+// counted as `tokenize` does.
+fn paren_depth(raw: &str, mut depth: usize) -> usize {
+    let mut chars = raw.chars();
+    let mut in_quotes = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            ';' => break,
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn parse_line(state: &mut State, line: &Line) -> Result<Option<DnsAnswer>, String> {
+    let mut tokens = line.tokens.iter().map(|t| t.text.as_str()).peekable();
+
+    if !line.indented {
+        if let Some(directive) = tokens.peek().filter(|t| t.starts_with('$')) {
+            let directive = directive.to_ascii_uppercase();
+            tokens.next();
+            let argument = tokens.next();
+            match (directive.as_str(), argument) {
+                ("$ORIGIN", Some(origin)) => {
+                    state.origin = Some(resolve_name(origin, state.origin.as_ref())?);
+                }
+                ("$TTL", Some(ttl)) => state.default_ttl = Some(parse_ttl(ttl)?),
+                ("$ORIGIN" | "$TTL", None) => {
+                    return Err(format!("{} needs an argument", directive))
+                }
+                _ => return Err(format!("unknown directive {}", directive)),
+            }
+            return match tokens.next() {
+                Some(extra) => Err(format!("unexpected '{}' after {}", extra, directive)),
+                None => Ok(None),
+            };
+        }
+    }
+
+    let owner = if line.indented {
+        state.last_owner.clone().ok_or("no owner name for record")?
+    } else {
+        resolve_name(tokens.next().unwrap(), state.origin.as_ref())?
+    };
+    state.last_owner = Some(owner.clone());
+
+    let mut ttl = None;
+    let mut class = None;
+    let qtype = loop {
+        let token = tokens.next().ok_or("missing record type")?;
+        if ttl.is_none() && token.starts_with(|c: char| c.is_ascii_digit()) {
+            ttl = Some(parse_ttl(token)?);
+        } else if let (None, Ok(parsed)) = (class, DnsClass::from_str(token)) {
+            class = Some(parsed);
+        } else {
+            break DnsType::from_str(token)
+                .map_err(|_| format!("unknown record type {}", token))?;
+        }
+    };
+
+    if ttl.is_some() {
+        state.last_ttl = ttl;
+    }
+    let ttl = ttl
+        .or(state.default_ttl)
+        .or(state.last_ttl)
+        .ok_or("no TTL specified and no $TTL default")?;
+
+    let rdata_tokens: Vec<&Token> = line
+        .tokens
+        .iter()
+        .skip(line.tokens.len() - tokens.count())
+        .collect();
+    let rdata = parse_rdata(qtype, &rdata_tokens, state.origin.as_ref())?;
+
+    Ok(Some(DnsAnswer::new(
+        owner,
+        qtype,
+        class.unwrap_or(DnsClass::In),
+        ttl as i32,
+        rdata,
+    )))
+}
+
+fn parse_rdata(qtype: DnsType, tokens: &[&Token], origin: Option<&Name>) -> Result<RData, String> {
+    if tokens.first().is_some_and(|t| !t.quoted && t.text == "\\#") {
+        return parse_generic(&tokens[1..]);
+    }
+
+    let mut fields = tokens.iter().map(|t| t.text.as_str());
+    let mut next = |what: &str| fields.next().ok_or(format!("missing {}", what));
+    let rdata = match qtype {
+        DnsType::A => {
+            let text = next("address")?;
+            let ip: Ipv4Addr = text
+                .parse()
+                .map_err(|_| format!("invalid IPv4 address {}", text))?;
+            RData::A(ip.octets())
+        }
+        DnsType::Aaaa => {
+            let text = next("address")?;
+            let ip: Ipv6Addr = text
+                .parse()
+                .map_err(|_| format!("invalid IPv6 address {}", text))?;
+            RData::Aaaa(ip.octets())
+        }
+        DnsType::Ns => RData::Ns(resolve_name(next("name server")?, origin)?),
+        DnsType::Cname => RData::Cname(resolve_name(next("target")?, origin)?),
+        DnsType::Ptr => RData::Ptr(resolve_name(next("target")?, origin)?),
+        DnsType::Alias => RData::Alias(resolve_name(next("target")?, origin)?),
+        DnsType::Template => {
+            let name = next("template")?;
+            RData::Template(
+                name.parse()
+                    .map_err(|_| format!("unknown template {}", name))?,
+            )
+        }
+        DnsType::Weighted | DnsType::Failover => RData::Pool {
+            rank: parse_number(next(match qtype {
+                DnsType::Weighted => "weight",
+                _ => "priority",
+            })?)?,
+            port: parse_number(next("port")?)?,
+            target: resolve_name(next("target")?, origin)?,
+        },
+        DnsType::Mx => RData::Mx {
+            preference: parse_number(next("preference")?)?,
+            exchange: resolve_name(next("exchange")?, origin)?,
+        },
+        DnsType::Srv => RData::Srv {
+            priority: parse_number(next("priority")?)?,
+            weight: parse_number(next("weight")?)?,
+            port: parse_number(next("port")?)?,
+            target: resolve_name(next("target")?, origin)?,
+        },
+        DnsType::Soa => RData::Soa {
+            mname: resolve_name(next("primary name server")?, origin)?,
+            rname: resolve_name(next("responsible mailbox")?, origin)?,
+            serial: parse_number(next("serial")?)?,
+            refresh: parse_ttl(next("refresh")?)?,
+            retry: parse_ttl(next("retry")?)?,
+            expire: parse_ttl(next("expire")?)?,
+            minimum: parse_ttl(next("minimum")?)?,
+        },
+        DnsType::Txt => {
+            let strings = tokens
+                .iter()
+                .map(|t| character_string(&t.text))
+                .collect::<Result<Vec<_>, _>>()?;
+            if strings.is_empty() {
+                return Err("missing text".to_string());
+            }
+            return Ok(RData::Txt(strings));
+        }
+        // As `dnssec-keygen -T KEY` writes it, the key in base64 that may
+        // be split over several fields.
+        DnsType::Key => {
+            let flags = parse_number(next("flags")?)?;
+            let protocol = parse_number(next("protocol")?)?;
+            let algorithm = parse_number(next("algorithm")?)?;
+            let text: String = fields.collect();
+            if text.is_empty() {
+                return Err("missing public key".to_string());
+            }
+            let public_key =
+                base64_decode(&text).ok_or_else(|| format!("invalid base64 key {}", text))?;
+            let key = Dnskey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+            };
+            return Ok(RData::Unknown(key.to_bytes()));
+        }
+        other => {
+            return Err(format!(
+                "unsupported record type {} (use the \\# generic syntax)",
+                other
+            ))
+        }
+    };
+
+    match fields.next() {
+        Some(extra) => Err(format!("unexpected '{}' after {} rdata", extra, qtype)),
+        None => Ok(rdata),
+    }
+}
+
+// RFC 3597: `\# <length> <hex>...`
+fn parse_generic(tokens: &[&Token]) -> Result<RData, String> {
+    let (length, hex) = tokens.split_first().ok_or("missing rdata length")?;
+    let length: usize = parse_number(&length.text)?;
+    let hex: String = hex.iter().map(|t| t.text.as_str()).collect();
+    if hex.len() % 2 == 1 {
+        return Err("odd number of hex digits".to_string());
+    }
+    let data = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| format!("invalid hex data {}", hex))?;
+    if data.len() != length {
+        return Err(format!(
+            "rdata length {} does not match {} bytes of data",
+            length,
+            data.len()
+        ));
+    }
+    Ok(RData::Unknown(data))
+}
+
+// `<start>-<stop>[/<step>]`.
+fn parse_range(text: &str) -> Result<(u32, u32, u32), String> {
+    let invalid = || format!("invalid $GENERATE range {}", text);
+    let (range, step) = match text.split_once('/') {
+        Some((range, step)) => (range, step.parse().map_err(|_| invalid())?),
+        None => (text, 1),
+    };
+    let (start, stop) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u32 = start.parse().map_err(|_| invalid())?;
+    let stop: u32 = stop.parse().map_err(|_| invalid())?;
+    if stop < start || step == 0 {
+        return Err(invalid());
+    }
+    if ((stop - start) / step) as u64 + 1 > MAX_GENERATED {
+        return Err(format!(
+            "$GENERATE range {} is over {} records",
+            text, MAX_GENERATED
+        ));
+    }
+    Ok((start, stop, step))
+}
+
+// Replaces `$` with the value, and `${offset[,width[,base]]}` with it
+// offset, zero-padded to the width and written in base d, o, x or X.
+// `\$` is a literal dollar sign.
+fn substitute(template: &str, value: u32) -> Result<String, String> {
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('$') => text.push('$'),
+                Some(escaped) => text.extend([c, escaped]),
+                None => text.push(c),
+            },
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let modifier: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let invalid = || format!("invalid $GENERATE modifier ${{{}}}", modifier);
+                let mut fields = modifier.split(',');
+                let offset: i64 = fields.next().unwrap().parse().map_err(|_| invalid())?;
+                let width: usize = match fields.next() {
+                    Some(width) => width.parse().map_err(|_| invalid())?,
+                    None => 0,
+                };
+                let base = fields.next().unwrap_or("d");
+                if fields.next().is_some() {
+                    return Err(invalid());
+                }
+                let value = u64::try_from(value as i64 + offset)
+                    .map_err(|_| format!("$GENERATE offset {} makes {} negative", offset, value))?;
+                let formatted = match base {
+                    "d" => format!("{:0width$}", value, width = width),
+                    "o" => format!("{:0width$o}", value, width = width),
+                    "x" => format!("{:0width$x}", value, width = width),
+                    "X" => format!("{:0width$X}", value, width = width),
+                    _ => return Err(invalid()),
+                };
+                text.push_str(&formatted);
+            }
+            '$' => text.push_str(&value.to_string()),
+            _ => text.push(c),
+        }
+    }
+    Ok(text)
+}
+
+pub(crate) fn resolve_name(text: &str, origin: Option<&Name>) -> Result<Name, String> {
+    let name = if text == "@" {
+        origin.cloned().ok_or("'@' used without an origin")?
+    } else if text.ends_with('.') {
+        Name::from(text)
+    } else {
+        let origin = origin.ok_or(format!("relative name {} without an origin", text))?;
+        if origin.as_str().is_empty() {
+            Name::from(text)
+        } else {
+            Name::from(format!("{}.{}", text, origin).as_str())
+        }
+    };
+
+    if name.as_str().split('.').any(|label| label.len() > 63) {
+        return Err(format!("label longer than 63 bytes in {}", text));
+    }
+    if name.len() > 255 {
+        return Err(format!("name longer than 255 bytes: {}", text));
+    }
+    Ok(name)
+}
+
+// Plain seconds or BIND-style units, e.g. `3600`, `1h`, `1w2d`.
+pub(crate) fn parse_ttl(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid TTL {}", text);
+    let mut total: u64 = 0;
+    let mut value: Option<u64> = None;
+
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0) * 10 + digit as u64);
+        } else {
+            let unit = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                'd' => 86400,
+                'w' => 604800,
+                _ => return Err(invalid()),
+            };
+            total += value.take().ok_or_else(invalid)? * unit;
+        }
+        if total > MAX_TTL as u64 || value.unwrap_or(0) > MAX_TTL as u64 {
+            return Err(format!("TTL out of range: {}", text));
+        }
+    }
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    let total = total + value.unwrap_or(0);
+    if total > MAX_TTL as u64 {
+        return Err(format!("TTL out of range: {}", text));
+    }
+    Ok(total as u32)
+}
+
+fn parse_number<T: FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number {}", text))
+}
+
+// Unescapes `\X` and `\DDD` sequences.
+fn character_string(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some(d) if d.is_ascii_digit() => {
+                let digits: String = [Some(d), chars.next(), chars.next()]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                let byte = digits
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|_| digits.len() == 3)
+                    .ok_or(format!("invalid escape \\{}", digits))?;
+                bytes.push(byte);
+            }
+            Some(other) => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buf).as_bytes());
+            }
+            None => return Err("dangling escape".to_string()),
+        }
+    }
+    if bytes.len() > 255 {
+        return Err("character string longer than 255 bytes".to_string());
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            2h 15m 2w 5m )
+    IN  NS  ns1
+    IN  MX  10 mail.example.com.
+ns1     IN A 192.0.2.1
+www 300 IN CNAME @
+txt     IN TXT "v=spf1 -all" "a \"quoted\" \059 string"
+_sip._udp SRV 10 5 5060 sip
+unknown IN TYPE16 \# 4 03616263
+dname   IN TYPE39 \# 5 036e657400
+empty      TYPE65280 \# 0
+host    IN KEY 512 3 8 AQ AB
+"#;
+
+    #[test]
+    fn test_parse_zone() {
+        let zone = ZoneFile::parse(ZONE, None);
+        assert_eq!(zone.errors, Vec::new());
+        assert_eq!(zone.origin, Some(Name::from("example.com")));
+        let records: Vec<String> = zone.entries.iter().map(|e| e.record.to_string()).collect();
+        assert_eq!(
+            records,
+            vec![
+                "example.com.\t3600\tIN\tSOA\tns1.example.com. hostmaster.example.com. 2024010101 7200 900 1209600 300",
+                "example.com.\t3600\tIN\tNS\tns1.example.com.",
+                "example.com.\t3600\tIN\tMX\t10 mail.example.com.",
+                "ns1.example.com.\t3600\tIN\tA\t192.0.2.1",
+                "www.example.com.\t300\tIN\tCNAME\texample.com.",
+                "txt.example.com.\t3600\tIN\tTXT\t\"v=spf1 -all\" \"a \\\"quoted\\\" ; string\"",
+                "_sip._udp.example.com.\t3600\tIN\tSRV\t10 5 5060 sip.example.com.",
+                "unknown.example.com.\t3600\tIN\tTXT\t\\# 4 03616263",
+                "dname.example.com.\t3600\tIN\tTYPE39\t\\# 5 036e657400",
+                "empty.example.com.\t3600\tIN\tTYPE65280\t\\# 0",
+                "host.example.com.\t3600\tIN\tKEY\t\\# 7 02000308010001",
+            ]
+        );
+        assert_eq!(zone.entries[0].line, 4);
+        assert_eq!(zone.entries[3].line, 9);
+    }
+
+    #[test]
+    fn test_edit() {
+        let zone = ZoneFile::parse(ZONE, None);
+        let records: Vec<&DnsAnswer> = zone.entries.iter().map(|e| &e.record).collect();
+        let mut soa = records[0].clone();
+        if let RData::Soa { serial, .. } = &mut soa.rdata {
+            *serial += 1;
+        }
+        let mut www = records[4].clone();
+        www.ttl = 60;
+        let new = DnsAnswer::new(
+            "new.example.com".into(),
+            DnsType::A,
+            DnsClass::In,
+            60,
+            RData::A([192, 0, 2, 9]),
+        );
+
+        // Only the serial changes in the SOA, comments and all.
+        let edited = edit(
+            ZONE,
+            None,
+            &[records[0], records[3], records[4]],
+            &[&new, &www, &soa],
+        )
+        .unwrap();
+        let expected = ZONE
+            .replace("2024010101 ; serial", "2024010102 ; serial")
+            .replace("ns1     IN A 192.0.2.1\n", "")
+            .replace(
+                "www 300 IN CNAME @",
+                "www.example.com.\t60\tIN\tCNAME\texample.com.",
+            )
+            + "new.example.com.\t60\tIN\tA\t192.0.2.9\n";
+        assert_eq!(edited, expected);
+
+        // A record that took its owner from a removed one gets it written.
+        let text = "$ORIGIN example.com.\n$TTL 300\n\
+                    www A 192.0.2.80 ; web\n    AAAA 2001:db8::80\n\
+                    $GENERATE 1-2 host-$ A 192.0.2.$\n";
+        let zone = ZoneFile::parse(text, None);
+        let edited = edit(text, None, &[&zone.entries[0].record], &[]).unwrap();
+        assert_eq!(
+            edited,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             www.example.com.    AAAA 2001:db8::80\n\
+             $GENERATE 1-2 host-$ A 192.0.2.$\n"
+        );
+        assert_eq!(
+            edit(text, None, &[&zone.entries[2].record], &[]),
+            Err("no line of the file holds host-1.example.com.\t300\tIN\tA\t192.0.2.1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parallel_parse() {
+        let mut text = String::from(
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n",
+        );
+        for i in 0..40_000 {
+            if i % 10_000 == 0 {
+                text += &format!("$ORIGIN zone{}.example.com.\n", i);
+            }
+            text += &format!(
+                "host{} A 192.0.2.{}\n  TXT ( \"record\" ; {}\n  \"(\" )\n",
+                i,
+                i % 256,
+                i
+            );
+        }
+        text += "bad A 192.0.2.256\n";
+        assert!(text.len() >= PARALLEL_PARSE_BYTES);
+        assert_eq!(Parser::new(None, None).pieces(&text, 4).len(), 4);
+        let including = format!("$INCLUDE hosts.zone\n{}", text);
+        assert_eq!(Parser::new(None, None).pieces(&including, 4).len(), 1);
+
+        // The same as parsing it in one go, lines and all.
+        let mut parallel = Parser::new(None, None);
+        parallel.parse_on(&text, 4);
+        let parallel = parallel.finish();
+        let mut sequential = Parser::new(None, None);
+        sequential.parse_on(&text, 1);
+        let sequential = sequential.finish();
+        assert_eq!(parallel.origin, sequential.origin);
+        assert_eq!(parallel.errors, sequential.errors);
+        assert_eq!(parallel.errors.len(), 1);
+        assert_eq!(parallel.entries.len(), 80_001);
+        for (parallel, sequential) in parallel.entries.iter().zip(&sequential.entries) {
+            assert_eq!(parallel.line, sequential.line);
+            assert_eq!(parallel.record, sequential.record);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors_are_collected() {
+        let text = "$ORIGIN example.com.\n\
+                    a IN A 192.0.2.1\n\
+                    b 60 IN A 300.1.1.1\n\
+                    c IN A 192.0.2.1 extra\n\
+                    d IN BOGUS data\n\
+                    d IN TYPE39 target\n\
+                    e IN TXT \"open\n\
+                    f IN MX 10 ( mail\n";
+        let zone = ZoneFile::parse(text, None);
+        let errors: Vec<String> = zone.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "line 2: no TTL specified and no $TTL default",
+                "line 3: invalid IPv4 address 300.1.1.1",
+                "line 4: unexpected 'extra' after A rdata",
+                "line 5: unknown record type BOGUS",
+                "line 6: unsupported record type TYPE39 (use the \\# generic syntax)",
+                "line 7: unterminated quoted string",
+                "line 8: unbalanced '('",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relative_names_need_origin() {
+        let zone = ZoneFile::parse("www 60 IN A 192.0.2.1\n", None);
+        assert_eq!(
+            zone.errors,
+            vec![ZoneError {
+                line: 1,
+                message: "relative name www without an origin".to_string()
+            }]
+        );
+        let zone = ZoneFile::parse("www 60 IN A 192.0.2.1\n", Some("example.org".into()));
+        assert_eq!(zone.entries[0].record.name, Name::from("www.example.org"));
+    }
+
+    #[test]
+    fn test_generate() {
+        let zone = ZoneFile::parse(
+            "$ORIGIN 2.0.192.in-addr.arpa.\n\
+             $GENERATE 1-5/2 $ 60 PTR host-${0,3}.example.com.\n\
+             $GENERATE 10-11 ${-10,2,x} IN 60 PTR \\$${16,0,X}.example.com.\n",
+            None,
+        );
+        assert_eq!(zone.errors, Vec::new());
+        let records: Vec<String> = zone.entries.iter().map(|e| e.record.to_string()).collect();
+        assert_eq!(
+            records,
+            vec![
+                "1.2.0.192.in-addr.arpa.\t60\tIN\tPTR\thost-001.example.com.",
+                "3.2.0.192.in-addr.arpa.\t60\tIN\tPTR\thost-003.example.com.",
+                "5.2.0.192.in-addr.arpa.\t60\tIN\tPTR\thost-005.example.com.",
+                "00.2.0.192.in-addr.arpa.\t60\tIN\tPTR\t$1A.example.com.",
+                "01.2.0.192.in-addr.arpa.\t60\tIN\tPTR\t$1B.example.com.",
+            ]
+        );
+        assert_eq!(zone.entries[4].line, 3);
+
+        let errors = |text: &str| -> Vec<String> {
+            let zone = ZoneFile::parse(text, Some("example.com".into()));
+            zone.errors.iter().map(|e| e.message.clone()).collect()
+        };
+        assert_eq!(
+            errors("$GENERATE 5-1 $ 60 A 192.0.2.$\n"),
+            vec!["invalid $GENERATE range 5-1"]
+        );
+        assert_eq!(
+            errors("$GENERATE 0-1 $ 60 A 192.0.2.${-1}\n"),
+            vec!["$GENERATE offset -1 makes 0 negative"]
+        );
+        assert_eq!(
+            errors("$GENERATE 0-1 $ 60 A 192.0.2.${0,1,q}\n"),
+            vec!["invalid $GENERATE modifier ${0,1,q}"]
+        );
+        assert_eq!(
+            errors("$GENERATE 250-260 $ 60 A 192.0.2.$\n"),
+            vec!["invalid IPv4 address 192.0.2.256"]
+        );
+        assert_eq!(
+            errors("$GENERATE 0-65536 $ 60 A 192.0.2.1\n"),
+            vec!["$GENERATE range 0-65536 is over 65536 records"]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("dns-server-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("hosts")).unwrap();
+        fs::write(
+            dir.join("example.com.zone"),
+            "$ORIGIN example.com.\n\
+             $TTL 60\n\
+             @ SOA ns1 hostmaster 1 2h 15m 2w 5m\n\
+             $INCLUDE hosts/web.zone web.example.com.\n\
+             after A 192.0.2.9\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("hosts/web.zone"),
+            "@ A 192.0.2.1\n\
+             www A 192.0.2.2\n\
+             $INCLUDE ../example.com.zone\n",
+        )
+        .unwrap();
+
+        let zone = ZoneFile::read(&dir.join("example.com.zone"), None).unwrap();
+        let records: Vec<(usize, String)> = zone
+            .entries
+            .iter()
+            .map(|e| (e.line, e.record.name.to_string()))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (3, "example.com".to_string()),
+                (4, "web.example.com".to_string()),
+                (4, "www.web.example.com".to_string()),
+                (5, "after.example.com".to_string()),
+            ]
+        );
+        let web = fs::canonicalize(dir.join("hosts/web.zone")).unwrap();
+        let main = fs::canonicalize(dir.join("example.com.zone")).unwrap();
+        assert_eq!(
+            zone.errors,
+            vec![ZoneError {
+                line: 4,
+                message: format!(
+                    "{} line 3: {}: $INCLUDE cycle",
+                    web.display(),
+                    main.display()
+                ),
+            }]
+        );
+
+        let zone = ZoneFile::parse("$INCLUDE hosts/web.zone\n", Some("example.com".into()));
+        assert_eq!(
+            zone.errors[0].message,
+            "$INCLUDE is only allowed in zone files"
+        );
+        assert!(ZoneFile::read(&dir.join("missing.zone"), None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("3600"), Ok(3600));
+        assert_eq!(parse_ttl("1h30m"), Ok(5400));
+        assert_eq!(parse_ttl("1W"), Ok(604800));
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("10x").is_err());
+        assert!(parse_ttl("4294967296").is_err());
+    }
+}
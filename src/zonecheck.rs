@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::answer::RData;
+use crate::common::{DnsType, Name};
+use crate::zone::{resolve_name, ZoneFile};
+
+const USAGE: &str = "usage: dns-server check-zone <file> [origin]";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(PartialEq, Debug)]
+pub(crate) struct Finding {
+    pub(crate) line: Option<usize>,
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+// `dns-server check-zone <file> [origin]`, in the spirit of named-checkzone.
+// Exits 1 when the zone has errors; warnings alone don't fail the check.
+pub(crate) fn main(args: Vec<String>) -> i32 {
+    let (path, origin) = match args.as_slice() {
+        [path] => (path, None),
+        [path, origin] => match resolve_name(origin, Some(&Name::from(""))) {
+            Ok(origin) => (path, Some(origin)),
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            return 2;
+        }
+    };
+    let zone = match ZoneFile::read(Path::new(path), origin.clone()) {
+        Ok(zone) => zone,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+
+    let apex = origin.or_else(|| find_apex(&zone));
+    let mut findings: Vec<Finding> = zone
+        .errors
+        .iter()
+        .map(|e| Finding {
+            line: Some(e.line),
+            severity: Severity::Error,
+            message: e.message.clone(),
+        })
+        .collect();
+    match &apex {
+        Some(apex) => findings.extend(check(&zone, apex)),
+        None => findings.push(Finding {
+            line: None,
+            severity: Severity::Error,
+            message: "cannot determine the zone origin, pass it as an argument".to_string(),
+        }),
+    }
+    findings.sort_by_key(|f| f.line);
+
+    for finding in &findings {
+        let severity = match finding.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match finding.line {
+            Some(line) => println!("{}:{}: {}: {}", path, line, severity, finding.message),
+            None => println!("{}: {}: {}", path, severity, finding.message),
+        }
+    }
+
+    if findings.iter().any(|f| f.severity == Severity::Error) {
+        return 1;
+    }
+    let apex = apex.unwrap();
+    let serial = zone
+        .entries
+        .iter()
+        .find_map(|entry| match entry.record.rdata {
+            RData::Soa { serial, .. } => Some(serial),
+            _ => None,
+        });
+    println!(
+        "zone {}/IN: loaded serial {}",
+        apex,
+        serial.unwrap_or_default()
+    );
+    println!("OK");
+    0
+}
+
+pub(crate) fn find_apex(zone: &ZoneFile) -> Option<Name> {
+    zone.entries
+        .iter()
+        .find(|entry| entry.record.qtype == DnsType::Soa)
+        .map(|entry| entry.record.name.clone())
+        .or_else(|| zone.origin.clone())
+}
+
+pub(crate) fn check(zone: &ZoneFile, apex: &Name) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut error = |line: Option<usize>, message: String| {
+        findings.push(Finding {
+            line,
+            severity: Severity::Error,
+            message,
+        })
+    };
+
+    // Types present at each owner, keyed by lowercased name.
+    let mut owners: HashMap<String, Vec<DnsType>> = HashMap::new();
+    for entry in &zone.entries {
+        owners
+            .entry(entry.record.name.as_str().to_ascii_lowercase())
+            .or_default()
+            .push(entry.record.qtype);
+    }
+    let types_at = |name: &Name| owners.get(&name.as_str().to_ascii_lowercase());
+
+    let mut apex_soa = false;
+    for entry in &zone.entries {
+        let record = &entry.record;
+        let line = Some(entry.line);
+
+        if !record.name.is_subdomain_of(apex) {
+            error(
+                line,
+                format!("out-of-zone data: {} is not in {}", record.name, apex),
+            );
+            continue;
+        }
+
+        if record.qtype == DnsType::Soa {
+            if !record.name.eq_ignore_case(apex) {
+                error(
+                    line,
+                    format!("SOA record for {} is not at the zone apex", record.name),
+                );
+            } else if apex_soa {
+                error(line, "multiple SOA records at the zone apex".to_string());
+            }
+            apex_soa |= record.name.eq_ignore_case(apex);
+        }
+
+        if record.qtype == DnsType::Cname {
+            let types = types_at(&record.name).unwrap();
+            if types.iter().filter(|t| **t == DnsType::Cname).count() > 1 {
+                error(line, format!("multiple CNAME records at {}", record.name));
+            } else if types.iter().any(|t| *t != DnsType::Cname) {
+                error(line, format!("CNAME and other data at {}", record.name));
+            }
+        }
+
+        // WEIGHTED and FAILOVER records answer as a CNAME does, and a
+        // TEMPLATE makes up all of its name's answers.
+        if matches!(
+            record.qtype,
+            DnsType::Weighted | DnsType::Failover | DnsType::Template
+        ) {
+            let types = types_at(&record.name).unwrap();
+            if types.iter().any(|t| *t != record.qtype) {
+                error(
+                    line,
+                    format!("{} and other data at {}", record.qtype, record.name),
+                );
+            }
+        }
+
+        // An ALIAS stands in for the name's addresses.
+        if record.qtype == DnsType::Alias {
+            let types = types_at(&record.name).unwrap();
+            if types.iter().filter(|t| **t == DnsType::Alias).count() > 1 {
+                error(line, format!("multiple ALIAS records at {}", record.name));
+            } else if types.contains(&DnsType::A) || types.contains(&DnsType::Aaaa) {
+                error(
+                    line,
+                    format!("ALIAS and address records at {}", record.name),
+                );
+            }
+        }
+
+        if let RData::Ns(target) = &record.rdata {
+            let has_address = types_at(target)
+                .is_some_and(|types| types.contains(&DnsType::A) || types.contains(&DnsType::Aaaa));
+            if target.is_subdomain_of(apex) && !has_address {
+                error(
+                    line,
+                    format!(
+                        "missing glue: no A or AAAA record for name server {}",
+                        target
+                    ),
+                );
+            }
+        }
+    }
+
+    if !apex_soa {
+        error(None, format!("no SOA record at the zone apex {}", apex));
+    }
+    if !types_at(apex).is_some_and(|types| types.contains(&DnsType::Ns)) {
+        error(None, format!("no NS records at the zone apex {}", apex));
+    }
+
+    // All records of an RRset must share a TTL (RFC 2181 section 5.2).
+    let mut first_ttl: HashMap<(String, u16), (i32, usize)> = HashMap::new();
+    for entry in &zone.entries {
+        let record = &entry.record;
+        let key = (
+            record.name.as_str().to_ascii_lowercase(),
+            record.qtype.code(),
+        );
+        let (ttl, line) = *first_ttl.entry(key).or_insert((record.ttl, entry.line));
+        if ttl != record.ttl {
+            findings.push(Finding {
+                line: Some(entry.line),
+                severity: Severity::Warning,
+                message: format!(
+                    "TTL {} differs from {} for {} {} (line {})",
+                    record.ttl, ttl, record.name, record.qtype, line
+                ),
+            });
+        }
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn findings(text: &str) -> Vec<String> {
+        let zone = ZoneFile::parse(text, None);
+        assert_eq!(zone.errors, Vec::new());
+        check(&zone, &find_apex(&zone).unwrap())
+            .iter()
+            .map(|f| match f.line {
+                Some(line) => format!("{}: {}", line, f.message),
+                None => f.message.clone(),
+            })
+            .collect()
+    }
+
+    const HEADER: &str = "$ORIGIN example.com.\n\
+                          $TTL 3600\n\
+                          @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+                          @ NS ns1\n\
+                          ns1 A 192.0.2.1\n";
+
+    #[test]
+    fn test_clean_zone() {
+        assert_eq!(findings(HEADER), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_missing_soa_and_ns() {
+        let zone = "$ORIGIN example.com.\n$TTL 60\nwww A 192.0.2.1\n";
+        assert_eq!(
+            findings(zone),
+            vec![
+                "no SOA record at the zone apex example.com",
+                "no NS records at the zone apex example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zone_violations() {
+        let zone = format!(
+            "{}\
+             www CNAME @\n\
+             www A 192.0.2.2\n\
+             other.net. A 192.0.2.3\n\
+             sub NS ns.sub\n\
+             sub NS ns.elsewhere.net.\n\
+             mail 60 A 192.0.2.4\n\
+             mail 120 A 192.0.2.5\n\
+             sub SOA ns1 hostmaster 1 2 3 4 5\n\
+             api ALIAS lb.example.net.\n\
+             api AAAA 2001:db8::1\n\
+             lb FAILOVER 10 443 a.example.net.\n\
+             lb WEIGHTED 1 443 b.example.net.\n",
+            HEADER
+        );
+        assert_eq!(
+            findings(&zone),
+            vec![
+                "6: CNAME and other data at www.example.com",
+                "8: out-of-zone data: other.net is not in example.com",
+                "9: missing glue: no A or AAAA record for name server ns.sub.example.com",
+                "13: SOA record for sub.example.com is not at the zone apex",
+                "14: ALIAS and address records at api.example.com",
+                "16: FAILOVER and other data at lb.example.com",
+                "17: WEIGHTED and other data at lb.example.com",
+                "12: TTL 120 differs from 60 for mail.example.com A (line 11)",
+            ]
+        );
+    }
+}
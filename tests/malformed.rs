@@ -0,0 +1,347 @@
+// Throws pathological messages at a running `dns-server` and checks that it
+// answers each one as policy says, or ignores it, and keeps serving.
+//
+// Policy: messages too short for a header, and responses, are dropped
+// without a reply; other messages that don't parse get FORMERR, and ones
+// that parse but can't be answered get an error RCODE.
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, UdpSocket};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dns_starter_rust::Resolver;
+
+const NOERROR: u8 = 0;
+const FORMERR: u8 = 1;
+const NOTIMP: u8 = 4;
+
+// How long to wait for a reply that shouldn't come.
+const SILENCE: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+enum Expect {
+    Dropped,
+    Rcode(u8),
+}
+
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn start() -> Server {
+        let zone =
+            std::env::temp_dir().join(format!("dns-server-malformed-{}.zone", std::process::id()));
+        std::fs::write(
+            &zone,
+            "$ORIGIN example.com.\n$TTL 300\n\
+             @ SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+             @ NS ns1\nns1 A 192.0.2.1\nwww A 192.0.2.80\n",
+        )
+        .unwrap();
+        // Another test may take the same free port first; the loser exits
+        // and tries another.
+        let server = loop {
+            let addr = free_port();
+            let child = Command::new(env!("CARGO_BIN_EXE_dns-starter-rust"))
+                .args(["--listen", &addr.to_string(), "--control-socket", ""])
+                .arg("--zone")
+                .arg(&zone)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            let mut server = Server { child, addr };
+            let start = Instant::now();
+            while server.child.try_wait().unwrap().is_none() && !server.answers() {
+                assert!(
+                    start.elapsed() < Duration::from_secs(10),
+                    "server didn't start"
+                );
+                thread::sleep(Duration::from_millis(50));
+            }
+            if server.child.try_wait().unwrap().is_none() {
+                break server;
+            }
+        };
+        std::fs::remove_file(&zone).unwrap();
+        server
+    }
+
+    // Over both UDP and TCP.
+    fn answers(&self) -> bool {
+        let resolver = Resolver::new(vec![self.addr]).with_timeout(Duration::from_millis(500));
+        resolver.lookup_ip("www.example.com").is_ok()
+            && resolver.with_tcp(true).lookup_ip("www.example.com").is_ok()
+    }
+
+    fn assert_up(&mut self, after: &str) {
+        assert!(
+            self.child.try_wait().unwrap().is_none(),
+            "server exited after {}",
+            after
+        );
+        assert!(self.answers(), "server stopped answering after {}", after);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A port free for both UDP and TCP, as far as we can tell.
+fn free_port() -> SocketAddr {
+    loop {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        if std::net::TcpListener::bind(addr).is_ok() {
+            return addr;
+        }
+    }
+}
+
+fn header(id: u16, flags: u16, counts: [u16; 4]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&id.to_be_bytes());
+    bytes.extend_from_slice(&flags.to_be_bytes());
+    for count in counts {
+        bytes.extend_from_slice(&count.to_be_bytes());
+    }
+    bytes
+}
+
+// A query for www.example.com A.
+fn query(id: u16) -> Vec<u8> {
+    let mut bytes = header(id, 0x0100, [1, 0, 0, 0]);
+    bytes.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+    bytes
+}
+
+fn with_question(id: u16, qdcount: u16, question: &[u8]) -> Vec<u8> {
+    let mut bytes = header(id, 0x0100, [qdcount, 0, 0, 0]);
+    bytes.extend_from_slice(question);
+    bytes
+}
+
+fn catalog() -> Vec<(&'static str, Vec<u8>, Expect)> {
+    let mut long_name = Vec::new();
+    for _ in 0..5 {
+        long_name.push(63);
+        long_name.extend_from_slice(&[b'a'; 63]);
+    }
+    long_name.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    let mut two_questions = query(13);
+    two_questions[5] = 2;
+    two_questions.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+
+    let mut bad_opt = query(14);
+    bad_opt[11] = 1;
+    // OPT claiming 16 bytes of options, with none there.
+    bad_opt.extend_from_slice(&[0, 0, 41, 4, 0xd0, 0, 0, 0, 0, 0, 16]);
+
+    let mut truncated_option = query(15);
+    truncated_option[11] = 1;
+    truncated_option.extend_from_slice(&[0, 0, 41, 4, 0xd0, 0, 0, 0, 0, 0, 4, 0, 3, 0, 8]);
+
+    vec![
+        ("an empty datagram", Vec::new(), Expect::Dropped),
+        ("a single byte", vec![0], Expect::Dropped),
+        (
+            "a truncated header",
+            query(1)[..7].to_vec(),
+            Expect::Dropped,
+        ),
+        (
+            "a header alone, claiming a question",
+            header(2, 0x0100, [1, 0, 0, 0]),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a header alone, with no question",
+            header(3, 0x0100, [0, 0, 0, 0]),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "qdcount claiming more questions than sent",
+            with_question(4, 3, &query(0)[12..]),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "ancount claiming records that aren't there",
+            {
+                let mut bytes = query(5);
+                bytes[7] = 200;
+                bytes
+            },
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a name pointing at itself",
+            with_question(6, 1, b"\xc0\x0c\x00\x01\x00\x01"),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "two names pointing at each other",
+            with_question(7, 2, b"\xc0\x12\x00\x01\x00\x01\xc0\x0c\x00\x01\x00\x01"),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a pointer past the end",
+            with_question(8, 1, b"\xc0\xff\x00\x01\x00\x01"),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a 64-byte label length",
+            with_question(9, 1, &[&[64][..], &[b'a'; 64], &[0, 0, 1, 0, 1]].concat()),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a reserved label type",
+            with_question(10, 1, b"\x80www\x00\x00\x01\x00\x01"),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a label running past the end",
+            with_question(11, 1, b"\x3fwww"),
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "a name longer than 255 bytes",
+            with_question(12, 1, &long_name),
+            Expect::Rcode(FORMERR),
+        ),
+        ("two questions", two_questions, Expect::Rcode(FORMERR)),
+        ("an OPT record overrunning", bad_opt, Expect::Rcode(FORMERR)),
+        (
+            "an EDNS option overrunning",
+            truncated_option,
+            Expect::Rcode(FORMERR),
+        ),
+        (
+            "an unknown opcode",
+            {
+                let mut bytes = query(16);
+                bytes[2] |= 3 << 3;
+                bytes
+            },
+            Expect::Rcode(NOTIMP),
+        ),
+        (
+            "an unknown qtype",
+            with_question(17, 1, b"\x03www\x07example\x03com\x00\xff\xfe\x00\x01"),
+            Expect::Rcode(NOERROR),
+        ),
+        (
+            "a response",
+            {
+                let mut bytes = query(18);
+                bytes[2] |= 0x80;
+                bytes
+            },
+            Expect::Dropped,
+        ),
+        (
+            "a malformed response",
+            {
+                let mut bytes = header(19, 0x8100, [1, 0, 0, 0]);
+                bytes.push(0x3f);
+                bytes
+            },
+            Expect::Dropped,
+        ),
+    ]
+}
+
+#[test]
+fn test_malformed_udp() {
+    let mut server = Server::start();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(server.addr).unwrap();
+    socket.set_read_timeout(Some(SILENCE)).unwrap();
+
+    for (case, message, expect) in catalog() {
+        socket.send(&message).unwrap();
+        let mut buf = [0; 4096];
+        let reply = socket.recv(&mut buf).ok().map(|size| &buf[..size]);
+        match (&expect, reply) {
+            (Expect::Dropped, None) => {}
+            (Expect::Rcode(rcode), Some(reply)) => {
+                assert_eq!(reply[..2], message[..2], "{}: wrong id", case);
+                assert_eq!(reply[3] & 0x0f, *rcode, "{}: wrong rcode", case);
+            }
+            (expect, reply) => panic!("{}: expected {:?}, got {:?}", case, expect, reply),
+        }
+        server.assert_up(case);
+    }
+}
+
+#[test]
+fn test_malformed_tcp() {
+    let mut server = Server::start();
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(SILENCE)).unwrap();
+
+    // Each message on the same connection, which has to stay usable.
+    for (case, message, expect) in catalog() {
+        stream
+            .write_all(&(message.len() as u16).to_be_bytes())
+            .unwrap();
+        stream.write_all(&message).unwrap();
+        let mut len = [0; 2];
+        let reply = match stream.read_exact(&mut len) {
+            Ok(()) => {
+                let mut reply = vec![0; u16::from_be_bytes(len) as usize];
+                stream.read_exact(&mut reply).unwrap();
+                Some(reply)
+            }
+            Err(_) => None,
+        };
+        match (&expect, reply) {
+            (Expect::Dropped, None) => {}
+            (Expect::Rcode(rcode), Some(reply)) => {
+                assert_eq!(reply[..2], message[..2], "{}: wrong id", case);
+                assert_eq!(reply[3] & 0x0f, *rcode, "{}: wrong rcode", case);
+            }
+            (expect, reply) => panic!("{}: expected {:?}, got {:?}", case, expect, reply),
+        }
+        server.assert_up(case);
+    }
+    let message = query(99);
+    stream
+        .write_all(&[&(message.len() as u16).to_be_bytes()[..], &message].concat())
+        .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(reply[2..], message[..2]);
+
+    // A length prefix promising more than arrives before the client gives
+    // up: the server closes its side.
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(&[0, 100, 1, 2, 3]).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    server.assert_up("a short TCP message");
+
+    // Half a length prefix.
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(&[0]).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    server.assert_up("half a length prefix");
+}